@@ -10,9 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cell::RefCell;
 use std::fmt;
 use std::fmt::Debug;
 use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{anyhow, Context, Result};
@@ -27,6 +29,20 @@ use util::aio::Iovec;
 use util::byte_code::ByteCode;
 use util::test_helper::is_test_enabled;
 
+/// Region transaction of current thread, topology updates of address spaces are
+/// deferred until the outermost transaction is committed.
+#[derive(Default)]
+struct Transaction {
+    /// Nesting depth of transactions.
+    depth: u32,
+    /// Address spaces whose topology changed in transaction.
+    pending: Vec<AddressSpace>,
+}
+
+thread_local! {
+    static TRANSACTION: RefCell<Transaction> = RefCell::new(Transaction::default());
+}
+
 /// Contains an array of `FlatRange`.
 #[derive(Default, Clone, Debug)]
 pub(crate) struct FlatView(pub(crate) Vec<FlatRange>);
@@ -54,6 +70,8 @@ impl FlatView {
 
                 if fr.owner.region_type() == RegionType::Ram
                     || fr.owner.region_type() == RegionType::RamDevice
+                    || fr.owner.region_type() == RegionType::Rom
+                    || fr.owner.region_type() == RegionType::Iommu
                 {
                     l = std::cmp::min(l, fr_remain);
                 }
@@ -86,6 +104,8 @@ impl FlatView {
                 let fr_remain = fr.addr_range.size - fr_offset;
                 if fr.owner.region_type() == RegionType::Ram
                     || fr.owner.region_type() == RegionType::RamDevice
+                    || fr.owner.region_type() == RegionType::Rom
+                    || fr.owner.region_type() == RegionType::Iommu
                 {
                     l = std::cmp::min(l, fr_remain);
                 }
@@ -114,6 +134,35 @@ pub struct RegionCache {
     pub host_base: u64,
     pub start: u64,
     pub end: u64,
+    /// Topology generation of address space when the cache is built, the cache
+    /// is stale once the generation changes.
+    pub generation: u64,
+}
+
+/// Region caches of all the Ram ranges sorted by guest address, which translates guest
+/// addresses in hot paths without walking the flat view.
+#[derive(Clone, Default)]
+pub struct RegionCacheTable {
+    caches: Vec<RegionCache>,
+    /// Topology generation of address space when the table is built, the table is
+    /// stale once the generation changes.
+    pub generation: u64,
+}
+
+impl RegionCacheTable {
+    /// Return the host address and the remaining length of the range which `addr`
+    /// belongs to, or None if `addr` is not in any Ram range.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest address.
+    pub fn translate(&self, addr: GuestAddress) -> Option<(u64, u64)> {
+        let index = self.caches.partition_point(|c| c.end <= addr.0);
+        self.caches
+            .get(index)
+            .filter(|c| c.start <= addr.0)
+            .map(|c| (c.host_base + addr.0 - c.start, c.end - addr.0))
+    }
 }
 
 type ListenerObj = Arc<Mutex<dyn Listener>>;
@@ -132,6 +181,9 @@ pub struct AddressSpace {
     listeners: Arc<Mutex<Vec<ListenerObj>>>,
     /// The current layout of ioeventfds, which is compared with new ones in topology-update stage.
     ioeventfds: Arc<Mutex<Vec<RegionIoEventFd>>>,
+    /// Generation of topology, it is increased every time `flat_view` is updated, so that
+    /// host address caches built from the old `flat_view` can be revalidated.
+    generation: Arc<AtomicU64>,
 }
 
 impl fmt::Debug for AddressSpace {
@@ -158,6 +210,7 @@ impl AddressSpace {
             flat_view: Arc::new(ArcSwap::new(Arc::new(FlatView::default()))),
             listeners: Arc::new(Mutex::new(Vec::new())),
             ioeventfds: Arc::new(Mutex::new(Vec::new())),
+            generation: Arc::new(AtomicU64::new(0)),
         });
 
         root.set_belonged_address_space(&space);
@@ -170,6 +223,17 @@ impl AddressSpace {
         Ok(space)
     }
 
+    /// Get the generation of topology. Host addresses translated before the generation
+    /// changes may be unmapped, they must be translated again.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Make host address caches built before stale, as the translation is changed.
+    pub(crate) fn invalidate_caches(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
     /// Get the reference of root region of AddressSpace.
     pub fn root(&self) -> &Region {
         &self.root
@@ -442,6 +506,11 @@ impl AddressSpace {
 
         view.find_flatrange(addr).and_then(|range| {
             let offset = addr.offset_from(range.addr_range.base);
+            if let Some(iommu) = range.owner.iommu() {
+                return iommu
+                    .get_host_address(range.offset_in_region + offset, false)
+                    .map(|(host, _)| host);
+            }
             range
                 .owner
                 .get_host_address()
@@ -457,6 +526,18 @@ impl AddressSpace {
     /// Return Error if the `addr` is not mapped.
     /// or return the HVA address and available mem length
     pub fn addr_cache_init(&self, addr: GuestAddress) -> Option<(u64, u64)> {
+        self.get_host_range(addr, false)
+    }
+
+    /// Return the hva and available mem length of the given `GuestAddress`, which is
+    /// accessed in the direction of `is_write`. Return None if the `addr` is not mapped,
+    /// or the translation of vIOMMU doesn't permit the access.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest address.
+    /// * `is_write` - If the host memory is written by the device.
+    pub fn get_host_range(&self, addr: GuestAddress, is_write: bool) -> Option<(u64, u64)> {
         let view = self.flat_view.load();
 
         if let Some(flat_range) = view.find_flatrange(addr) {
@@ -465,6 +546,11 @@ impl AddressSpace {
 
             let region_remain = flat_range.owner.size() - region_offset;
             let fr_remain = flat_range.addr_range.size - fr_offset;
+            if let Some(iommu) = flat_range.owner.iommu() {
+                return iommu
+                    .get_host_address(region_offset, is_write)
+                    .map(|(host, size)| (host, std::cmp::min(size, fr_remain)));
+            }
 
             return flat_range.owner.get_host_address().map(|host| {
                 (
@@ -483,14 +569,20 @@ impl AddressSpace {
     ///
     /// * `addr` - Guest address.
     /// * `count` - Memory needed length
-    pub fn get_address_map(&self, addr: GuestAddress, count: u64) -> Result<Vec<Iovec>> {
+    /// * `is_write` - If the memory is written by the device.
+    pub fn get_address_map(
+        &self,
+        addr: GuestAddress,
+        count: u64,
+        is_write: bool,
+    ) -> Result<Vec<Iovec>> {
         let mut len = count;
         let mut start = addr;
         let mut hva_iovec = Vec::new();
 
         loop {
             let io_vec = self
-                .addr_cache_init(start)
+                .get_host_range(start, is_write)
                 .map(|(hva, fr_len)| Iovec {
                     iov_base: hva,
                     iov_len: std::cmp::min(len, fr_len),
@@ -519,10 +611,10 @@ impl AddressSpace {
         addr: GuestAddress,
         cache: &Option<RegionCache>,
     ) -> Option<(u64, u64)> {
-        if cache.is_none() {
-            return self.addr_cache_init(addr);
-        }
-        let region_cache = cache.unwrap();
+        let region_cache = match cache {
+            Some(c) if c.generation == self.generation() => c,
+            _ => return self.addr_cache_init(addr),
+        };
         if addr.0 >= region_cache.start && addr.0 < region_cache.end {
             Some((
                 region_cache.host_base + addr.0 - region_cache.start,
@@ -533,23 +625,69 @@ impl AddressSpace {
         }
     }
 
+    /// Return the host address according to the given `GuestAddress` from region cache table.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest address.
+    /// * `is_write` - If the memory is written by the device.
+    /// * `table` - The region cache table built by `get_region_cache_table`.
+    pub fn get_host_address_from_table(
+        &self,
+        addr: GuestAddress,
+        is_write: bool,
+        table: &Option<RegionCacheTable>,
+    ) -> Option<(u64, u64)> {
+        table
+            .as_ref()
+            .filter(|t| t.generation == self.generation())
+            .and_then(|t| t.translate(addr))
+            .or_else(|| self.get_host_range(addr, is_write))
+    }
+
     /// Check if the GuestAddress is in one of Ram region.
     ///
     /// # Arguments
     ///
     /// * `addr` - Guest address.
     pub fn address_in_memory(&self, addr: GuestAddress, size: u64) -> bool {
+        self.range_in_memory(addr, size, false)
+    }
+
+    /// Check if the GuestAddress range is in one of Ram region, and can be accessed by
+    /// the device in the direction of `is_write`.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Guest address.
+    /// * `size` - Size of the range.
+    /// * `is_write` - If the range is written by the device.
+    pub fn range_in_memory(&self, addr: GuestAddress, size: u64, is_write: bool) -> bool {
         let view = &self.flat_view.load();
 
         view.find_flatrange(addr).map_or(false, |range| {
-            range.owner.region_type() == RegionType::Ram
-                && size <= range.addr_range.end_addr().offset_from(addr)
+            let fr_offset = addr.offset_from(range.addr_range.base);
+            if size > range.addr_range.size - fr_offset {
+                return false;
+            }
+            match range.owner.iommu() {
+                Some(iommu) => {
+                    iommu.address_in_memory(range.offset_in_region + fr_offset, size, is_write)
+                }
+                None => range.owner.region_type() == RegionType::Ram,
+            }
         })
     }
 
     pub fn get_region_cache(&self, addr: GuestAddress) -> Option<RegionCache> {
+        // Load generation before flat view, so the cache is at worst considered stale.
+        let generation = self.generation();
         let view = &self.flat_view.load();
-        if let Some(range) = view.find_flatrange(addr) {
+        // Translations of Iommu-type region are not continuous, so it is never cached.
+        if let Some(range) = view
+            .find_flatrange(addr)
+            .filter(|range| range.owner.region_type() != RegionType::Iommu)
+        {
             let reg_type = range.owner.region_type();
             let start = range.addr_range.base.0;
             let end = range.addr_range.end_addr().0;
@@ -559,12 +697,37 @@ impl AddressSpace {
                 host_base,
                 start,
                 end,
+                generation,
             };
             return Some(cache);
         }
         None
     }
 
+    /// Build the region cache table of all the Ram ranges in AddressSpace.
+    pub fn get_region_cache_table(&self) -> RegionCacheTable {
+        // Load generation before flat view, so the table is at worst considered stale.
+        let generation = self.generation();
+        let caches = self
+            .flat_view
+            .load()
+            .0
+            .iter()
+            .filter(|fr| fr.owner.region_type() == RegionType::Ram)
+            .filter_map(|fr| {
+                let host_base = fr.owner.get_host_address()? + fr.offset_in_region;
+                Some(RegionCache {
+                    reg_type: RegionType::Ram,
+                    host_base,
+                    start: fr.addr_range.base.0,
+                    end: fr.addr_range.end_addr().0,
+                    generation,
+                })
+            })
+            .collect();
+        RegionCacheTable { caches, generation }
+    }
+
     /// Return the end address of memory according to all Ram regions in AddressSpace.
     pub fn memory_end_address(&self) -> GuestAddress {
         self.flat_view
@@ -709,8 +872,83 @@ impl AddressSpace {
         Ok(obj)
     }
 
-    /// Update the topology of memory.
+    /// Begin a region transaction in current thread. Region changes of all address spaces
+    /// in transaction take effect once, when the outermost transaction is committed.
+    ///
+    /// # Note
+    ///
+    /// Accessing address space in transaction sees the topology before transaction.
+    pub fn begin_transaction() {
+        TRANSACTION.with(|t| t.borrow_mut().depth += 1);
+    }
+
+    /// Commit the region transaction in current thread, the topology of changed address
+    /// spaces is updated if it is the outermost transaction.
+    pub fn commit_transaction() -> Result<()> {
+        let pending = TRANSACTION.with(|t| {
+            let mut t = t.borrow_mut();
+            t.depth = t.depth.saturating_sub(1);
+            if t.depth == 0 {
+                std::mem::take(&mut t.pending)
+            } else {
+                Vec::new()
+            }
+        });
+
+        let mut ret = Ok(());
+        for space in pending {
+            if let Err(e) = space.do_update_topology() {
+                if ret.is_ok() {
+                    ret = Err(e).with_context(|| {
+                        format!(
+                            "Failed to commit transaction of address space {}",
+                            space.name
+                        )
+                    });
+                }
+            }
+        }
+        ret
+    }
+
+    /// Run `f` in a region transaction, the topology is updated once after `f` returns.
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - Function changing regions.
+    pub fn transaction<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+        Self::begin_transaction();
+        let ret = f();
+        let commit = Self::commit_transaction();
+        let value = ret?;
+        commit?;
+        Ok(value)
+    }
+
+    /// Update the topology of memory. It is deferred if current thread is in transaction.
     pub fn update_topology(&self) -> Result<()> {
+        let deferred = TRANSACTION.with(|t| {
+            let mut t = t.borrow_mut();
+            if t.depth == 0 {
+                return false;
+            }
+            if !t
+                .pending
+                .iter()
+                .any(|space| Arc::ptr_eq(&space.flat_view, &self.flat_view))
+            {
+                t.pending.push(self.clone());
+            }
+            true
+        });
+        if deferred {
+            return Ok(());
+        }
+
+        self.do_update_topology()
+    }
+
+    fn do_update_topology(&self) -> Result<()> {
         let old_fv = self.flat_view.load();
 
         let addr_range = AddressRange::new(GuestAddress(0), self.root.size());
@@ -725,6 +963,7 @@ impl AddressSpace {
             .with_context(|| "Failed to update topology (second pass)")?;
 
         self.flat_view.store(Arc::new(new_fv));
+        self.generation.fetch_add(1, Ordering::Release);
         self.update_ioeventfds()
             .with_context(|| "Failed to generate and update ioeventfds")?;
         Ok(())
@@ -939,6 +1178,46 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_transaction() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let listener = Arc::new(Mutex::new(TestListener::default()));
+        space.register_listener(listener.clone()).unwrap();
+
+        let default_ops = RegionOps {
+            read: Arc::new(|_: &mut [u8], _: GuestAddress, _: u64| -> bool { true }),
+            write: Arc::new(|_: &[u8], _: GuestAddress, _: u64| -> bool { true }),
+        };
+        let region_a = Region::init_io_region(1000, default_ops.clone(), "region_a");
+        let region_b = Region::init_io_region(1000, default_ops, "region_b");
+
+        AddressSpace::transaction(|| {
+            root.add_subregion(region_a.clone(), 0)?;
+            root.add_subregion(region_b.clone(), 2000)?;
+            root.delete_subregion(&region_a)?;
+            // Nested transaction doesn't update topology.
+            AddressSpace::transaction(|| root.add_subregion(region_a.clone(), 4000))?;
+            assert!(space.flat_view.load().0.is_empty());
+            Ok(())
+        })
+        .unwrap();
+
+        // Only the final layout is reported to listener.
+        assert_eq!(space.flat_view.load().0.len(), 2);
+        let locked_listener = listener.lock().unwrap();
+        let reqs = locked_listener.reqs.lock().unwrap();
+        assert_eq!(reqs.len(), 2);
+        assert_eq!(reqs[0].1, AddressRange::new(GuestAddress(2000), 1000));
+        assert_eq!(reqs[1].1, AddressRange::new(GuestAddress(4000), 1000));
+        drop(reqs);
+        drop(locked_listener);
+
+        // Topology is updated immediately out of transaction.
+        root.delete_subregion(&region_b).unwrap();
+        assert_eq!(space.flat_view.load().0.len(), 1);
+    }
+
     #[test]
     fn test_update_topology() {
         let root = Region::init_container_region(8000, "root");
@@ -1194,6 +1473,78 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_region_cache_invalidation() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram1.clone(), "region_a");
+        root.add_subregion(region_a.clone(), 0).unwrap();
+
+        let cache = space.get_region_cache(GuestAddress(0));
+        assert_eq!(cache.unwrap().generation, space.generation());
+        assert_eq!(
+            space.get_host_address_from_cache(GuestAddress(100), &cache),
+            Some((ram1.host_address() + 100, 900))
+        );
+
+        // Memory is moved, the cache built before is not used any more.
+        root.delete_subregion(&region_a).unwrap();
+        root.add_subregion(region_a, 2000).unwrap();
+        assert_ne!(cache.unwrap().generation, space.generation());
+        assert!(space
+            .get_host_address_from_cache(GuestAddress(100), &cache)
+            .is_none());
+        assert_eq!(
+            space.get_host_address_from_cache(GuestAddress(2100), &cache),
+            Some((ram1.host_address() + 100, 900))
+        );
+    }
+
+    #[test]
+    fn test_region_cache_table() {
+        let root = Region::init_container_region(8000, "root");
+        let space = AddressSpace::new(root.clone(), "space").unwrap();
+        let ram1 = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1000, None, false, false, false).unwrap(),
+        );
+        let ram2 = Arc::new(
+            HostMemMapping::new(GuestAddress(2000), None, 1000, None, false, false, false).unwrap(),
+        );
+        let region_a = Region::init_ram_region(ram1.clone(), "region_a");
+        let region_b = Region::init_ram_region(ram2.clone(), "region_b");
+        root.add_subregion(region_a.clone(), 0).unwrap();
+        root.add_subregion(region_b, ram2.start_address().raw_value())
+            .unwrap();
+
+        let table = Some(space.get_region_cache_table());
+        assert_eq!(table.as_ref().unwrap().generation, space.generation());
+        assert_eq!(
+            space.get_host_address_from_table(GuestAddress(100), false, &table),
+            Some((ram1.host_address() + 100, 900))
+        );
+        assert_eq!(
+            space.get_host_address_from_table(GuestAddress(2500), false, &table),
+            Some((ram2.host_address() + 500, 500))
+        );
+        assert!(space
+            .get_host_address_from_table(GuestAddress(1500), false, &table)
+            .is_none());
+
+        // Memory is moved, the table built before is not used any more.
+        root.delete_subregion(&region_a).unwrap();
+        root.add_subregion(region_a, 4000).unwrap();
+        assert!(space
+            .get_host_address_from_table(GuestAddress(100), false, &table)
+            .is_none());
+        assert_eq!(
+            space.get_host_address_from_table(GuestAddress(4100), false, &table),
+            Some((ram1.host_address() + 100, 900))
+        );
+    }
+
     #[test]
     fn test_write_and_read_object() {
         let root = Region::init_container_region(8000, "root");
@@ -50,4 +50,6 @@ pub enum AddressSpaceError {
     KvmSlotOverlap { add: (u64, u64), exist: (u64, u64) },
     #[error("Invalid offset: offset 0x{0:X}, data length 0x{1:X}, region size 0x{2:X}")]
     InvalidOffset(u64, u64, u64),
+    #[error("IOMMU translation fault, iova 0x{0:X}, write {1}")]
+    IommuFault(u64, bool),
 }
@@ -10,25 +10,43 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::fs::File;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use log::{error, info};
+use log::{error, info, warn};
 
 use crate::{AddressRange, GuestAddress, Region};
-use machine_manager::config::{HostMemPolicy, MachineMemConfig, MemZoneConfig};
+use machine_manager::config::{HostMemPolicy, MachineMemConfig, MemZoneConfig, SgxEpcObjConfig};
 use util::{
+    seclabel::label_fd,
     syscall::mbind,
     unix::{do_mmap, host_page_size},
 };
 
 const MAX_PREALLOC_THREAD: u8 = 16;
+/// Size of memory populated by a prealloc thread at a time.
+const PREALLOC_CHUNK_SIZE: u64 = 0x1000_0000;
+/// Interval of checking the progress of prealloc.
+const PREALLOC_REPORT_INTERVAL: Duration = Duration::from_millis(100);
+/// Magic number of hugetlbfs, see `linux/magic.h`.
+const HUGETLBFS_MAGIC: libc::__fsword_t = 0x9584_58f6;
 /// Verify existing pages in the mapping.
 const MPOL_MF_STRICT: u32 = 1;
+
+/// Host ranges of all the guest memory mappings, in format of (HVA, size).
+static HOST_MEM_RANGES: Mutex<Vec<(u64, u64)>> = Mutex::new(Vec::new());
+
+/// Get host ranges of the guest memory mappings in format of (HVA, size), which are
+/// used to tell guest memory from the memory of VMM itself.
+pub fn host_mem_ranges() -> Vec<(u64, u64)> {
+    HOST_MEM_RANGES.lock().unwrap().clone()
+}
 /// Move pages owned by this process to conform to mapping.
 const MPOL_MF_MOVE: u32 = 2;
 
@@ -130,6 +148,11 @@ impl FileBackend {
                 .with_context(|| format!("Failed to open file: {}", file_path))?
         };
 
+        // Files which already exist are labeled by their owners.
+        if path.is_dir() || need_unlink {
+            label_fd(file.as_raw_fd())?;
+        }
+
         // Safe because struct `statfs` only contains plain-data-type field,
         // and set to all-zero will not cause any undefined behavior.
         let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
@@ -139,6 +162,18 @@ impl FileBackend {
             fstat.f_bsize
         );
 
+        if fstat.f_type == HUGETLBFS_MAGIC && file_len % fstat.f_bsize as u64 != 0 {
+            if need_unlink {
+                file_unlink(file_path);
+            }
+            bail!(
+                "Memory size 0x{:X} is not aligned to hugepage size 0x{:X} of {}",
+                file_len,
+                fstat.f_bsize,
+                file_path
+            );
+        }
+
         let old_file_len = file.metadata().unwrap().len();
         if old_file_len == 0 {
             if file.set_len(file_len).is_err() {
@@ -161,17 +196,185 @@ impl FileBackend {
             page_size: fstat.f_bsize as u64,
         })
     }
+
+    /// Construct a new read-only FileBackend with an existing file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of file.
+    /// * `file_len` - The size of memory, the file should not be smaller than it.
+    pub fn new_mem_readonly(file_path: &str, file_len: u64) -> Result<FileBackend> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .open(file_path)
+            .with_context(|| format!("Failed to open file: {}", file_path))?;
+        let old_file_len = file
+            .metadata()
+            .with_context(|| format!("Failed to get metadata of file: {}", file_path))?
+            .len();
+        if old_file_len < file_len {
+            bail!(
+                "Read-only backing file {} is smaller than RAM (size is 0x{:X})",
+                file_path,
+                file_len
+            );
+        }
+
+        // SAFETY: struct `statfs` only contains plain-data-type field,
+        // and set to all-zero will not cause any undefined behavior.
+        let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: file is valid and the return value is checked.
+        if unsafe { libc::fstatfs(file.as_raw_fd(), &mut fstat) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to get fs stat of file: {}", file_path));
+        }
+
+        Ok(FileBackend {
+            file: Arc::new(file),
+            offset: 0_u64,
+            page_size: fstat.f_bsize as u64,
+        })
+    }
+
+    /// Construct a new FileBackend with anonymous file created by memfd, which can be
+    /// shared with other processes by fd without creating file on disk.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of memory.
+    /// * `hugetlb_size` - Create memfd on hugetlbfs with the hugepage size if set,
+    ///   0 means the default hugepage size of host.
+    /// * `seal` - Seal the size of memfd, so that it can't be changed by other processes.
+    pub fn new_memfd(size: u64, hugetlb_size: Option<u64>, seal: bool) -> Result<FileBackend> {
+        let mut flags = libc::MFD_CLOEXEC;
+        if let Some(hugetlb_size) = hugetlb_size {
+            flags |= libc::MFD_HUGETLB;
+            if hugetlb_size != 0 {
+                flags |= hugetlb_size.trailing_zeros() << libc::MFD_HUGE_SHIFT;
+            }
+        }
+        if seal {
+            flags |= libc::MFD_ALLOW_SEALING;
+        }
+
+        let anon_mem_name = b"stratovirt_anon_mem\0";
+        // SAFETY: the name is a nul-terminated string and the return value is checked.
+        let anon_fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                anon_mem_name.as_ptr() as *const libc::c_char,
+                flags,
+            )
+        } as RawFd;
+        if anon_fd < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| match hugetlb_size {
+                Some(size) => format!(
+                    "Failed to create hugetlb memfd, hugepage size 0x{:X} may be not supported",
+                    size
+                ),
+                None => "Failed to create memfd".to_string(),
+            });
+        }
+
+        // SAFETY: anon_fd is created above and owned by the file.
+        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
+        label_fd(anon_fd)?;
+        anon_file
+            .set_len(size)
+            .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
+
+        if seal {
+            // SAFETY: anon_file is valid and the return value is checked.
+            let ret = unsafe {
+                libc::fcntl(
+                    anon_file.as_raw_fd(),
+                    libc::F_ADD_SEALS,
+                    libc::F_SEAL_GROW | libc::F_SEAL_SHRINK,
+                )
+            };
+            if ret < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| "Failed to add seals to memfd");
+            }
+        }
+
+        let page_size = if hugetlb_size.is_some() {
+            // SAFETY: struct `statfs` only contains plain-data-type field,
+            // and set to all-zero will not cause any undefined behavior.
+            let mut fstat: libc::statfs = unsafe { std::mem::zeroed() };
+            // SAFETY: anon_file is valid and the return value is checked.
+            if unsafe { libc::fstatfs(anon_file.as_raw_fd(), &mut fstat) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| "Failed to get hugepage size of memfd");
+            }
+            fstat.f_bsize as u64
+        } else {
+            host_page_size()
+        };
+
+        Ok(FileBackend {
+            file: Arc::new(anon_file),
+            offset: 0,
+            page_size,
+        })
+    }
+}
+
+/// Map the file on persistent memory with `MAP_SYNC`, so that the guest writes are
+/// persistent once flushed from cpu cache. Fall back to normal shared mapping if
+/// the file system does not support DAX.
+///
+/// # Arguments
+///
+/// * `f_back` - File backend on persistent memory.
+/// * `size` - Size of memory.
+/// * `dump_guest_core` - Dump guest memory during coredump or not.
+fn do_mmap_pmem(f_back: &FileBackend, size: u64, dump_guest_core: bool) -> Result<u64> {
+    // SAFETY: the file is valid and the return value is checked.
+    let hva = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            size as libc::size_t,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED_VALIDATE | libc::MAP_SYNC,
+            f_back.file.as_raw_fd(),
+            f_back.offset as libc::off_t,
+        )
+    };
+    if hva == libc::MAP_FAILED {
+        warn!(
+            "Failed to map pmem with MAP_SYNC ({:?}), guest writes may be lost on host crash",
+            std::io::Error::last_os_error()
+        );
+        return do_mmap(
+            &Some(f_back.file.as_ref()),
+            size,
+            f_back.offset,
+            false,
+            true,
+            dump_guest_core,
+        );
+    }
+    if !dump_guest_core {
+        // SAFETY: hva and size are valid.
+        unsafe { libc::madvise(hva, size as libc::size_t, libc::MADV_DONTDUMP) };
+    }
+    Ok(hva as u64)
 }
 
-/// Get the max number of threads that can be used to touch pages.
+/// Get the number of threads that can be used to touch pages.
 ///
 /// # Arguments
 ///
-/// * `nr_vcpus` - Number of vcpus.
-fn max_nr_threads(nr_vcpus: u8) -> u8 {
+/// * `nr_threads` - Number of threads set by user, 0 means sized to host CPUs.
+fn max_nr_threads(nr_threads: u8) -> u8 {
+    if nr_threads != 0 {
+        return nr_threads;
+    }
+    // SAFETY: sysconf has no side effect and the return value is checked.
     let nr_host_cpu = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
     if nr_host_cpu > 0 {
-        return min(min(nr_host_cpu as u8, MAX_PREALLOC_THREAD), nr_vcpus);
+        return min(nr_host_cpu, MAX_PREALLOC_THREAD as libc::c_long) as u8;
     }
     // If fails to call `sysconf` function, just use a single thread to touch pages.
     1
@@ -200,39 +403,120 @@ fn touch_pages(start: u64, page_size: u64, nr_pages: u64) {
     }
 }
 
-/// Pre-alloc memory for virtual machine.
+/// Populate pages with `MADV_POPULATE_WRITE`, which reports the failure of allocation
+/// (e.g. no free hugepages) instead of raising SIGBUS. Fall back to touching pages if
+/// it is not supported by host kernel.
+///
+/// # Arguments
+///
+/// * `start` - The start host address of memory segment.
+/// * `page_size` - Size of host page.
+/// * `nr_pages` - Number of pages.
+fn populate_pages(start: u64, page_size: u64, nr_pages: u64) -> Result<()> {
+    if nr_pages == 0 {
+        return Ok(());
+    }
+    let len = page_size * nr_pages;
+    // SAFETY: the memory segment is mapped and the return value is checked.
+    let ret = unsafe {
+        libc::madvise(
+            start as *mut libc::c_void,
+            len as libc::size_t,
+            libc::MADV_POPULATE_WRITE,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EINVAL) {
+        touch_pages(start, page_size, nr_pages);
+        return Ok(());
+    }
+    Err(err).with_context(|| {
+        format!(
+            "Failed to populate memory [0x{:X}, 0x{:X})",
+            start,
+            start + len
+        )
+    })
+}
+
+/// Pre-alloc memory for virtual machine. Memory is split into chunks, which are
+/// populated by multiple threads, and the progress is reported every 10 percent.
 ///
 /// # Arguments
 ///
 /// * `host_addr` - The start host address to pre allocate.
 /// * `size` - Size of memory.
-/// * `nr_vcpus` - Number of vcpus.
-fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
-    let page_size = host_page_size();
-    let threads = max_nr_threads(nr_vcpus);
-    let nr_pages = (size + page_size - 1) / page_size;
-    let pages_per_thread = nr_pages / (threads as u64);
-    let left = nr_pages % (threads as u64);
-    let mut addr = host_addr;
+/// * `nr_threads` - Number of threads, 0 means sized to host CPUs.
+/// * `page_size` - Page size of the memory.
+fn mem_prealloc(host_addr: u64, size: u64, nr_threads: u8, page_size: u64) -> Result<()> {
+    // Block size of some file systems is not the page size of memory.
+    let page_size = if size % page_size == 0 {
+        page_size
+    } else {
+        host_page_size()
+    };
+    let chunk_size = max(PREALLOC_CHUNK_SIZE / page_size, 1) * page_size;
+    let threads = max_nr_threads(nr_threads);
+    let next = Arc::new(AtomicU64::new(0));
+    let done = Arc::new(AtomicU64::new(0));
+    let failed = Arc::new(AtomicBool::new(false));
     let mut threads_join = Vec::new();
-    for i in 0..threads {
-        let touch_nr_pages = if i < (left as u8) {
-            pages_per_thread + 1
-        } else {
-            pages_per_thread
-        };
-        let thread = thread::spawn(move || {
-            touch_pages(addr, page_size, touch_nr_pages);
+    for _ in 0..threads {
+        let next = next.clone();
+        let done = done.clone();
+        let failed = failed.clone();
+        let thread = thread::spawn(move || -> Result<()> {
+            while !failed.load(Ordering::Acquire) {
+                let offset = next.fetch_add(chunk_size, Ordering::AcqRel);
+                if offset >= size {
+                    break;
+                }
+                let len = min(chunk_size, size - offset);
+                if let Err(e) = populate_pages(host_addr + offset, page_size, len / page_size) {
+                    failed.store(true, Ordering::Release);
+                    return Err(e);
+                }
+                done.fetch_add(len, Ordering::AcqRel);
+            }
+            Ok(())
         });
         threads_join.push(thread);
-        addr += touch_nr_pages * page_size;
     }
+
+    let mut reported = 0;
+    while threads_join.iter().any(|t| !t.is_finished()) {
+        thread::sleep(PREALLOC_REPORT_INTERVAL);
+        let percent = done.load(Ordering::Acquire) * 100 / size;
+        if percent >= reported + 10 {
+            reported = percent / 10 * 10;
+            info!(
+                "Preallocated {}% of memory 0x{:X} at 0x{:X} with {} threads",
+                reported, size, host_addr, threads
+            );
+        }
+    }
+
     // join all threads to wait for pre-allocating.
+    let mut result = Ok(());
     while let Some(thread) = threads_join.pop() {
-        if let Err(ref e) = thread.join() {
-            error!("Failed to join thread: {:?}", e);
+        match thread.join() {
+            Ok(Err(e)) => result = Err(e),
+            Err(ref e) => error!("Failed to join thread: {:?}", e),
+            _ => {}
         }
     }
+    result
+}
+
+/// Get the page size of memory, which is the hugepage size if backed by hugetlbfs.
+fn f_back_page_size(f_back: Option<&FileBackend>) -> u64 {
+    match f_back {
+        Some(fb) if fb.page_size != 0 => fb.page_size,
+        _ => host_page_size(),
+    }
 }
 
 /// If the memory is not configured numa, use this
@@ -240,8 +524,7 @@ fn mem_prealloc(host_addr: u64, size: u64, nr_vcpus: u8) {
 /// # Arguments
 ///
 /// * `mem_config` - The config of default memory.
-/// * `thread_num` - The num of mem preallocv threads, typically the number of vCPUs.
-pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Result<Region> {
+pub fn create_default_mem(mem_config: &MachineMemConfig) -> Result<Region> {
     let mut f_back: Option<FileBackend> = None;
 
     if let Some(path) = &mem_config.mem_path {
@@ -250,24 +533,7 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
                 .with_context(|| "Failed to create file that backs memory")?,
         );
     } else if mem_config.mem_share {
-        let anon_mem_name = String::from("stratovirt_anon_mem");
-
-        let anon_fd =
-            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) } as RawFd;
-        if anon_fd < 0 {
-            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create memfd");
-        }
-
-        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
-        anon_file
-            .set_len(mem_config.mem_size)
-            .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
-
-        f_back = Some(FileBackend {
-            file: Arc::new(anon_file),
-            offset: 0,
-            page_size: host_page_size(),
-        });
+        f_back = Some(FileBackend::new_memfd(mem_config.mem_size, None, false)?);
     }
     let block = Arc::new(HostMemMapping::new(
         GuestAddress(0),
@@ -280,7 +546,9 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
     )?);
 
     if mem_config.mem_prealloc {
-        mem_prealloc(block.host_address(), mem_config.mem_size, thread_num);
+        let page_size = f_back_page_size(block.file_backend().as_ref());
+        mem_prealloc(block.host_address(), mem_config.mem_size, 0, page_size)
+            .with_context(|| "Failed to preallocate memory, check free hugepages if used")?;
     }
     let region = Region::init_ram_region(block, "DefaultRam");
 
@@ -291,47 +559,58 @@ pub fn create_default_mem(mem_config: &MachineMemConfig, thread_num: u8) -> Resu
 ///
 /// # Arguments
 ///
-/// * `mem_config` - The config of default memory.
-/// * `thread_num` - The num of mem preallocv threads, typically the number of vCPUs.
-pub fn create_backend_mem(mem_config: &MemZoneConfig, thread_num: u8) -> Result<Region> {
+/// * `mem_config` - The config of memory backend.
+pub fn create_backend_mem(mem_config: &MemZoneConfig) -> Result<Region> {
     let mut f_back: Option<FileBackend> = None;
 
-    if mem_config.memfd {
-        let anon_mem_name = String::from("stratovirt_anon_mem");
-
-        let anon_fd =
-            unsafe { libc::syscall(libc::SYS_memfd_create, anon_mem_name.as_ptr(), 0) } as RawFd;
-        if anon_fd < 0 {
-            return Err(std::io::Error::last_os_error()).with_context(|| "Failed to create memfd");
-        }
-
-        let anon_file = unsafe { File::from_raw_fd(anon_fd) };
-        anon_file
-            .set_len(mem_config.size)
-            .with_context(|| "Failed to set the length of anonymous file that backs memory")?;
-
-        f_back = Some(FileBackend {
-            file: Arc::new(anon_file),
-            offset: 0,
-            page_size: host_page_size(),
-        });
+    if mem_config.memfd || mem_config.hugetlb {
+        f_back = Some(FileBackend::new_memfd(
+            mem_config.size,
+            mem_config.hugetlb.then_some(mem_config.hugetlb_size),
+            mem_config.seal,
+        )?);
     } else if let Some(path) = &mem_config.mem_path {
-        f_back = Some(
+        f_back = Some(if mem_config.readonly {
+            FileBackend::new_mem_readonly(path, mem_config.size)
+                .with_context(|| "Failed to open read-only file that backs memory")?
+        } else {
             FileBackend::new_mem(path, mem_config.size)
-                .with_context(|| "Failed to create file that backs memory")?,
-        );
+                .with_context(|| "Failed to create file that backs memory")?
+        });
     }
+    let host_addr = match f_back.as_ref() {
+        Some(fb) if mem_config.pmem => Some(do_mmap_pmem(
+            fb,
+            mem_config.size,
+            mem_config.dump_guest_core,
+        )?),
+        _ => None,
+    };
     let block = Arc::new(HostMemMapping::new(
         GuestAddress(0),
-        None,
+        host_addr,
         mem_config.size,
         f_back,
         mem_config.dump_guest_core,
         mem_config.share,
-        false,
+        mem_config.readonly,
     )?);
+    // Advise before preallocation, so that touched pages are backed as requested.
+    set_host_memory_advice(&block, mem_config)?;
     if mem_config.prealloc {
-        mem_prealloc(block.host_address(), mem_config.size, thread_num);
+        let page_size = f_back_page_size(block.file_backend().as_ref());
+        mem_prealloc(
+            block.host_address(),
+            mem_config.size,
+            mem_config.prealloc_threads,
+            page_size,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to preallocate memory backend {}, check free hugepages if used",
+                mem_config.id
+            )
+        })?;
     }
     set_host_memory_policy(&block, mem_config)?;
 
@@ -339,6 +618,74 @@ pub fn create_backend_mem(mem_config: &MemZoneConfig, thread_num: u8) -> Result<
     Ok(region)
 }
 
+/// Path of the device which provides SGX EPC pages for guest.
+const SGX_VEPC_PATH: &str = "/dev/sgx_vepc";
+
+/// Create memory region of SGX EPC section, whose pages are allocated from host EPC by
+/// the SGX virtual EPC device.
+///
+/// # Arguments
+///
+/// * `epc_config` - Config of the EPC memory backend.
+pub fn create_sgx_epc_mem(epc_config: &SgxEpcObjConfig) -> Result<Region> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(SGX_VEPC_PATH)
+        .with_context(|| format!("Failed to open {}, check SGX of host", SGX_VEPC_PATH))?;
+    // EPC is not readable by host, so it's never dumped.
+    let block = Arc::new(HostMemMapping::new(
+        GuestAddress(0),
+        None,
+        epc_config.size,
+        Some(FileBackend::new_common(file)),
+        false,
+        true,
+        false,
+    )?);
+    if epc_config.prealloc {
+        // EPC is mapped with VM_PFNMAP, which can't be populated by madvise, so the pages
+        // are touched to be allocated. Host reads and writes of EPC are ignored.
+        let page_size = host_page_size();
+        touch_pages(block.host_address(), page_size, epc_config.size / page_size);
+    }
+    Ok(Region::init_ram_region(block, epc_config.id.as_str()))
+}
+
+/// Set KSM and transparent hugepage advice of host memory backend.
+///
+/// # Arguments
+///
+/// * `mem_mappings` - The host virtual address of mapped memory information.
+/// * `zone` - Memory zone config info.
+fn set_host_memory_advice(mem_mappings: &Arc<HostMemMapping>, zone: &MemZoneConfig) -> Result<()> {
+    let mut advice = Vec::new();
+    if zone.merge {
+        advice.push((libc::MADV_MERGEABLE, "MADV_MERGEABLE"));
+    }
+    match zone.thp {
+        Some(true) => advice.push((libc::MADV_HUGEPAGE, "MADV_HUGEPAGE")),
+        Some(false) => advice.push((libc::MADV_NOHUGEPAGE, "MADV_NOHUGEPAGE")),
+        None => {}
+    }
+
+    for (flag, name) in advice {
+        // SAFETY: The address range is mapped by mem_mappings and valid.
+        let ret = unsafe {
+            libc::madvise(
+                mem_mappings.host_address() as *mut libc::c_void,
+                zone.size as libc::size_t,
+                flag,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to set {} for memory backend {}", name, zone.id));
+        }
+    }
+    Ok(())
+}
+
 /// Set host memory backend numa policy.
 ///
 /// # Arguments
@@ -392,6 +739,8 @@ pub struct HostMemMapping {
     file_back: Option<FileBackend>,
     /// share mem flag
     is_share: bool,
+    /// read only mem flag
+    read_only: bool,
 }
 
 // Send and Sync is not auto-implemented for raw pointer type
@@ -435,6 +784,7 @@ impl HostMemMapping {
             )?
         };
 
+        HOST_MEM_RANGES.lock().unwrap().push((host_addr, size));
         Ok(Self {
             address_range: AddressRange {
                 base: guest_addr,
@@ -443,6 +793,7 @@ impl HostMemMapping {
             host_addr: host_addr as *mut u8,
             file_back,
             is_share,
+            read_only,
         })
     }
 
@@ -471,11 +822,20 @@ impl HostMemMapping {
     pub fn mem_shared(&self) -> bool {
         self.is_share
     }
+
+    pub fn mem_read_only(&self) -> bool {
+        self.read_only
+    }
 }
 
 impl Drop for HostMemMapping {
     /// Release the memory mapping.
     fn drop(&mut self) {
+        let host_addr = self.host_address();
+        HOST_MEM_RANGES
+            .lock()
+            .unwrap()
+            .retain(|(addr, _)| *addr != host_addr);
         unsafe {
             libc::munmap(
                 self.host_addr as *mut libc::c_void,
@@ -589,15 +949,61 @@ mod test {
         std::fs::remove_file(file_path).unwrap();
     }
 
+    #[test]
+    fn test_file_backend_readonly() {
+        let file_path = String::from("back_mem_test_ro");
+        assert!(FileBackend::new_mem_readonly(&file_path, 0x1000).is_err());
+
+        let file = File::create(file_path.clone()).unwrap();
+        file.set_len(0x1000).unwrap();
+        assert!(FileBackend::new_mem_readonly(&file_path, 0x2000).is_err());
+
+        let zone = MemZoneConfig {
+            id: String::from("mem0"),
+            size: 0x1000,
+            mem_path: Some(file_path.clone()),
+            readonly: true,
+            ..Default::default()
+        };
+        let region = create_backend_mem(&zone).unwrap();
+        assert_eq!(region.get_host_read_only(), Some(true));
+        assert!(region
+            .write(&mut [0_u8; 4].as_ref(), GuestAddress(0), 0, 4)
+            .is_err());
+        let mut data = [0xff_u8; 4];
+        region
+            .read(&mut data.as_mut(), GuestAddress(0), 0, 4)
+            .unwrap();
+        assert_eq!(data, [0_u8; 4]);
+
+        std::fs::remove_file(file_path).unwrap();
+    }
+
+    #[test]
+    fn test_file_backend_memfd() {
+        let f_back = FileBackend::new_memfd(0x10_0000, None, false).unwrap();
+        assert_eq!(f_back.file.metadata().unwrap().len(), 0x10_0000);
+        assert!(f_back.file.set_len(0x20_0000).is_ok());
+
+        // The size of sealed memfd can't be changed.
+        let f_back = FileBackend::new_memfd(0x10_0000, None, true).unwrap();
+        assert_eq!(f_back.page_size, host_page_size());
+        assert!(f_back.file.set_len(0x20_0000).is_err());
+        assert!(f_back.file.set_len(0x1000).is_err());
+        assert_eq!(f_back.file.metadata().unwrap().len(), 0x10_0000);
+    }
+
     #[test]
     fn test_memory_prealloc() {
         // Mmap and prealloc with anonymous memory.
         let host_addr = do_mmap(&None, 0x20_0000, 0, false, false, false).unwrap();
-        // Check the thread number equals to minimum value.
+        // Check the thread number set by user is used.
         assert_eq!(max_nr_threads(1), 1);
-        // The max threads limit is 16, or the number of host CPUs, it will never be 20.
-        assert_ne!(max_nr_threads(20), 20);
-        mem_prealloc(host_addr, 0x20_0000, 20);
+        assert_eq!(max_nr_threads(20), 20);
+        // The max threads limit is 16, or the number of host CPUs.
+        assert!(max_nr_threads(0) <= MAX_PREALLOC_THREAD);
+        mem_prealloc(host_addr, 0x20_0000, 20, host_page_size()).unwrap();
+        mem_prealloc(host_addr, 0x20_0000, 0, host_page_size()).unwrap();
 
         // Mmap and prealloc with file backend.
         let file_path = String::from("back_mem_test");
@@ -612,7 +1018,7 @@ mod test {
             false,
         )
         .unwrap();
-        mem_prealloc(host_addr, 0x10_0000, 2);
+        mem_prealloc(host_addr, 0x10_0000, 2, f_back.page_size).unwrap();
         std::fs::remove_file(file_path).unwrap();
     }
 }
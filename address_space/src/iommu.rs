@@ -0,0 +1,405 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::{AddressSpace, AddressSpaceError, GuestAddress, Region};
+
+/// Access permission of IOMMU translation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct IommuPerm {
+    /// Device is allowed to read the memory.
+    pub read: bool,
+    /// Device is allowed to write the memory.
+    pub write: bool,
+}
+
+/// Translation result of vIOMMU, which maps a naturally aligned IOVA range to the
+/// target address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IommuTlbEntry {
+    /// Start IOVA of the range, aligned to `addr_mask + 1`.
+    pub iova: u64,
+    /// Start address of the range in target address space.
+    pub translated_addr: u64,
+    /// Mask of offset within the range, e.g. 0xFFF for 4K page.
+    pub addr_mask: u64,
+    /// Access permission of the range.
+    pub perm: IommuPerm,
+}
+
+impl IommuTlbEntry {
+    fn contains(&self, iova: u64) -> bool {
+        iova & !self.addr_mask == self.iova
+    }
+
+    fn permits(&self, is_write: bool) -> bool {
+        if is_write {
+            self.perm.write
+        } else {
+            self.perm.read
+        }
+    }
+
+    fn overlaps(&self, iova: u64, size: u64) -> bool {
+        let end = iova.saturating_add(size);
+        self.iova < end && iova <= self.iova + self.addr_mask
+    }
+}
+
+/// Translation of vIOMMU, it is implemented by vIOMMU device models according to the
+/// page tables programmed by guest for the device.
+pub trait IommuTranslate: Send + Sync {
+    /// Translate `iova` of the device, return None if it is not mapped.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - IO virtual address accessed by the device.
+    /// * `is_write` - If the access is a write.
+    fn translate(&self, iova: u64, is_write: bool) -> Option<IommuTlbEntry>;
+}
+
+/// IOMMU memory region of one device. DMA of the device is translated by vIOMMU into
+/// the target address space, and the translations are cached in the IOTLB of the device
+/// until vIOMMU invalidates them.
+pub struct IommuRegion {
+    /// Translation of vIOMMU for the device.
+    translator: Arc<dyn IommuTranslate>,
+    /// Address space which IOVA is translated into, usually the system memory.
+    target: Arc<AddressSpace>,
+    /// IOTLB of the device, indexed by start IOVA of entries.
+    iotlb: Mutex<BTreeMap<u64, IommuTlbEntry>>,
+    /// DMA address space of the device, host address caches of it are stale once
+    /// the IOTLB is invalidated.
+    dma_space: RwLock<Weak<AddressSpace>>,
+}
+
+impl IommuRegion {
+    /// Create IOMMU memory region of a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `translator` - Translation of vIOMMU for the device.
+    /// * `target` - Address space which IOVA is translated into.
+    pub fn new(translator: Arc<dyn IommuTranslate>, target: Arc<AddressSpace>) -> Arc<Self> {
+        Arc::new(IommuRegion {
+            translator,
+            target,
+            iotlb: Mutex::new(BTreeMap::new()),
+            dma_space: RwLock::new(Weak::new()),
+        })
+    }
+
+    /// Create the DMA address space of the device, which covers the whole IOVA space with
+    /// this IOMMU memory region. The device accesses it instead of system memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the address space.
+    pub fn create_dma_space(self: &Arc<Self>, name: &str) -> Result<Arc<AddressSpace>> {
+        let root = Region::init_container_region(u64::MAX, name);
+        let space = AddressSpace::new(root.clone(), name)?;
+        root.add_subregion(Region::init_iommu_region(u64::MAX, self.clone(), name), 0)
+            .with_context(|| format!("Failed to add IOMMU region to {}", name))?;
+        *self.dma_space.write().unwrap() = Arc::downgrade(&space);
+
+        Ok(space)
+    }
+
+    /// Translate `iova`, the IOTLB is looked up before asking vIOMMU.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - IO virtual address accessed by the device.
+    /// * `is_write` - If the access is a write.
+    pub fn translate(&self, iova: u64, is_write: bool) -> Result<IommuTlbEntry> {
+        let mut iotlb = self.iotlb.lock().unwrap();
+        if let Some((_, entry)) = iotlb.range(..=iova).next_back() {
+            if entry.contains(iova) && entry.permits(is_write) {
+                return Ok(*entry);
+            }
+        }
+
+        let entry = self
+            .translator
+            .translate(iova, is_write)
+            .filter(|e| e.contains(iova) && e.permits(is_write))
+            .ok_or_else(|| anyhow!(AddressSpaceError::IommuFault(iova, is_write)))?;
+        iotlb.insert(entry.iova, entry);
+
+        Ok(entry)
+    }
+
+    /// Invalidate the IOTLB entries overlapping with the IOVA range, it is called by vIOMMU
+    /// once guest unmaps or changes the translations.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - Start IOVA of the range.
+    /// * `size` - Size of the range.
+    pub fn invalidate(&self, iova: u64, size: u64) {
+        self.iotlb
+            .lock()
+            .unwrap()
+            .retain(|_, entry| !entry.overlaps(iova, size));
+        self.invalidate_host_caches();
+    }
+
+    /// Invalidate all IOTLB entries of the device.
+    pub fn invalidate_all(&self) {
+        self.iotlb.lock().unwrap().clear();
+        self.invalidate_host_caches();
+    }
+
+    fn invalidate_host_caches(&self) {
+        if let Some(space) = self.dma_space.read().unwrap().upgrade() {
+            space.invalidate_caches();
+        }
+    }
+
+    /// Translate `iova` to address in target address space, and return it with the
+    /// remaining length of the translated range.
+    fn translate_range(&self, iova: u64, is_write: bool) -> Result<(u64, u64)> {
+        let entry = self.translate(iova, is_write)?;
+        let offset = iova & entry.addr_mask;
+        Ok((
+            entry.translated_addr + offset,
+            (entry.addr_mask - offset).saturating_add(1),
+        ))
+    }
+
+    /// Read memory of IOVA range to `dst`.
+    pub(crate) fn read(&self, dst: &mut dyn std::io::Write, iova: u64, count: u64) -> Result<()> {
+        let mut iova = iova;
+        let mut len = count;
+        while len > 0 {
+            let (addr, remain) = self.translate_range(iova, false)?;
+            let l = min(len, remain);
+            self.target.read(dst, GuestAddress(addr), l)?;
+            iova += l;
+            len -= l;
+        }
+        Ok(())
+    }
+
+    /// Write data from `src` to memory of IOVA range.
+    pub(crate) fn write(&self, src: &mut dyn std::io::Read, iova: u64, count: u64) -> Result<()> {
+        let mut iova = iova;
+        let mut len = count;
+        while len > 0 {
+            let (addr, remain) = self.translate_range(iova, true)?;
+            let l = min(len, remain);
+            self.target.write(src, GuestAddress(addr), l)?;
+            iova += l;
+            len -= l;
+        }
+        Ok(())
+    }
+
+    /// Return the host address of `iova` and the length of host memory continuous from it.
+    ///
+    /// # Notes
+    ///
+    /// The host memory is accessed directly by device, so the translation must permit
+    /// the access direction of the device.
+    ///
+    /// # Arguments
+    ///
+    /// * `iova` - IO virtual address accessed by the device.
+    /// * `is_write` - If the device writes the memory.
+    pub(crate) fn get_host_address(&self, iova: u64, is_write: bool) -> Option<(u64, u64)> {
+        let (addr, remain) = self.translate_range(iova, is_write).ok()?;
+        self.target
+            .get_host_range(GuestAddress(addr), is_write)
+            .map(|(host, size)| (host, min(size, remain)))
+    }
+
+    /// Check if the IOVA range is translated to memory of target address space, and the
+    /// translation permits the access direction `is_write`.
+    pub(crate) fn address_in_memory(&self, iova: u64, size: u64, is_write: bool) -> bool {
+        let mut iova = iova;
+        let mut len = size;
+        loop {
+            let (addr, remain) = match self.translate_range(iova, is_write) {
+                Ok(r) => r,
+                Err(_) => return false,
+            };
+            let l = min(len, remain);
+            if !self.target.range_in_memory(GuestAddress(addr), l, is_write) {
+                return false;
+            }
+            len -= l;
+            if len == 0 {
+                return true;
+            }
+            iova += l;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::HostMemMapping;
+
+    const PAGE_MASK: u64 = 0xFFF;
+
+    /// Translation of 4K pages programmed by test.
+    #[derive(Default)]
+    struct TestTranslator {
+        pages: Mutex<HashMap<u64, (u64, IommuPerm)>>,
+        walks: AtomicU32,
+    }
+
+    impl TestTranslator {
+        fn map(&self, iova: u64, gpa: u64, perm: IommuPerm) {
+            self.pages.lock().unwrap().insert(iova, (gpa, perm));
+        }
+
+        fn unmap(&self, iova: u64) {
+            self.pages.lock().unwrap().remove(&iova);
+        }
+    }
+
+    impl IommuTranslate for TestTranslator {
+        fn translate(&self, iova: u64, _is_write: bool) -> Option<IommuTlbEntry> {
+            self.walks.fetch_add(1, Ordering::SeqCst);
+            let page = iova & !PAGE_MASK;
+            self.pages
+                .lock()
+                .unwrap()
+                .get(&page)
+                .map(|(gpa, perm)| IommuTlbEntry {
+                    iova: page,
+                    translated_addr: *gpa,
+                    addr_mask: PAGE_MASK,
+                    perm: *perm,
+                })
+        }
+    }
+
+    fn sys_mem_init() -> Arc<AddressSpace> {
+        let root = Region::init_container_region(1 << 20, "sysmem");
+        let sys_mem = AddressSpace::new(root.clone(), "sysmem").unwrap();
+        let ram = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x10000, None, false, false, false).unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(ram, "ram"), 0)
+            .unwrap();
+        sys_mem
+    }
+
+    #[test]
+    fn test_iommu_dma() {
+        let rw = IommuPerm {
+            read: true,
+            write: true,
+        };
+        let sys_mem = sys_mem_init();
+        let translator = Arc::new(TestTranslator::default());
+        let iommu = IommuRegion::new(translator.clone(), sys_mem.clone());
+        let dma_space = iommu.create_dma_space("dma").unwrap();
+
+        // Continuous IOVA pages are mapped to discontinuous guest pages.
+        translator.map(0x10_0000, 0x3000, rw);
+        translator.map(0x10_1000, 0x5000, rw);
+        let data: u64 = 0x1122_3344_5566_7788;
+        dma_space
+            .write_object(&data, GuestAddress(0x10_0ffc))
+            .unwrap();
+        assert_eq!(
+            dma_space
+                .read_object::<u64>(GuestAddress(0x10_0ffc))
+                .unwrap(),
+            data
+        );
+        assert_eq!(
+            sys_mem.read_object::<u32>(GuestAddress(0x3ffc)).unwrap(),
+            0x5566_7788
+        );
+        assert_eq!(
+            sys_mem.read_object::<u32>(GuestAddress(0x5000)).unwrap(),
+            0x1122_3344
+        );
+        assert!(dma_space.address_in_memory(GuestAddress(0x10_0ff0), 0x20));
+        let iovecs = dma_space
+            .get_address_map(GuestAddress(0x10_0ff0), 0x20, true)
+            .unwrap();
+        assert_eq!(iovecs.len(), 2);
+        assert_eq!(
+            iovecs[1].iov_base,
+            sys_mem.get_host_address(GuestAddress(0x5000)).unwrap()
+        );
+
+        // Translations are cached in IOTLB.
+        let walks = translator.walks.load(Ordering::SeqCst);
+        dma_space
+            .read_object::<u64>(GuestAddress(0x10_0ffc))
+            .unwrap();
+        assert_eq!(translator.walks.load(Ordering::SeqCst), walks);
+
+        // Unmapped or read-only IOVA can't be written.
+        assert!(dma_space
+            .write_object(&data, GuestAddress(0x20_0000))
+            .is_err());
+        assert!(!dma_space.address_in_memory(GuestAddress(0x10_1ff0), 0x20));
+        translator.map(
+            0x20_0000,
+            0x6000,
+            IommuPerm {
+                read: true,
+                write: false,
+            },
+        );
+        assert!(dma_space
+            .write_object(&data, GuestAddress(0x20_0000))
+            .is_err());
+        assert!(dma_space
+            .read_object::<u64>(GuestAddress(0x20_0000))
+            .is_ok());
+        assert!(dma_space
+            .get_address_map(GuestAddress(0x20_0000), 0x10, true)
+            .is_err());
+        assert!(dma_space
+            .get_address_map(GuestAddress(0x20_0000), 0x10, false)
+            .is_ok());
+        assert!(!dma_space.range_in_memory(GuestAddress(0x20_0000), 0x10, true));
+        assert!(dma_space.range_in_memory(GuestAddress(0x20_0000), 0x10, false));
+
+        // Stale translation is used until IOTLB is invalidated.
+        translator.unmap(0x10_1000);
+        let generation = dma_space.generation();
+        assert!(dma_space
+            .read_object::<u64>(GuestAddress(0x10_1000))
+            .is_ok());
+        iommu.invalidate(0x10_1000, 0x1000);
+        assert_ne!(dma_space.generation(), generation);
+        assert!(dma_space
+            .read_object::<u64>(GuestAddress(0x10_1000))
+            .is_err());
+        assert!(dma_space
+            .read_object::<u64>(GuestAddress(0x10_0000))
+            .is_ok());
+        iommu.invalidate_all();
+        translator.unmap(0x10_0000);
+        assert!(dma_space
+            .get_host_address(GuestAddress(0x10_0000))
+            .is_none());
+    }
+}
@@ -84,16 +84,21 @@ pub mod error;
 mod address;
 mod address_space;
 mod host_mmap;
+mod iommu;
 mod listener;
 mod region;
 mod state;
 
 pub use anyhow::Result;
 
-pub use crate::address_space::{AddressSpace, RegionCache};
+pub use crate::address_space::{AddressSpace, RegionCache, RegionCacheTable};
 pub use address::{AddressRange, GuestAddress};
 pub use error::AddressSpaceError;
-pub use host_mmap::{create_backend_mem, create_default_mem, FileBackend, HostMemMapping};
+pub use host_mmap::{
+    create_backend_mem, create_default_mem, create_sgx_epc_mem, host_mem_ranges, FileBackend,
+    HostMemMapping,
+};
+pub use iommu::{IommuPerm, IommuRegion, IommuTlbEntry, IommuTranslate};
 #[cfg(target_arch = "x86_64")]
 pub use listener::KvmIoListener;
 pub use listener::KvmMemoryListener;
@@ -118,7 +123,7 @@ type ReadFn = std::sync::Arc<dyn Fn(&mut [u8], GuestAddress, u64) -> bool + Send
 /// * `data` - A u8-type array.
 /// * `base` - Base address.
 /// * `offset` - Offset from base address.
-type WriteFn = std::sync::Arc<dyn Fn(&[u8], GuestAddress, u64) -> bool + Send + Sync>;
+pub type WriteFn = std::sync::Arc<dyn Fn(&[u8], GuestAddress, u64) -> bool + Send + Sync>;
 
 /// Provide Some operations of `Region`, mainly used by Vm's devices.
 #[derive(Clone)]
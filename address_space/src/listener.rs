@@ -226,6 +226,7 @@ impl KvmMemoryListener {
         if flat_range.owner.region_type() != RegionType::Ram
             && flat_range.owner.region_type() != RegionType::RomDevice
             && flat_range.owner.region_type() != RegionType::RamDevice
+            && flat_range.owner.region_type() != RegionType::Rom
         {
             return Ok(());
         }
@@ -246,7 +247,10 @@ impl KvmMemoryListener {
             .with_context(|| "Failed to get available KVM mem slot")?;
 
         let mut flags = 0_u32;
-        if flat_range.owner.get_rom_device_romd().unwrap_or(false) {
+        if flat_range.owner.get_rom_device_romd().unwrap_or(false)
+            || flat_range.owner.get_host_read_only().unwrap_or(false)
+            || flat_range.owner.region_type() == RegionType::Rom
+        {
             flags |= KVM_MEM_READONLY;
         }
         let kvm_region = kvm_userspace_memory_region {
@@ -256,6 +260,23 @@ impl KvmMemoryListener {
             userspace_addr: aligned_hva,
             flags,
         };
+        let kvm_fds = KVM_FDS.load();
+        if kvm_fds.private_memory_enabled() && flat_range.owner.region_type() == RegionType::Ram {
+            kvm_fds
+                .add_mem_slot(kvm_region)
+                .with_context(|| "Failed to add memory slot to kvm")?;
+            return kvm_fds.set_private_memory_region(kvm_region).or_else(|e| {
+                self.delete_slot(aligned_addr.raw_value(), aligned_size)
+                    .with_context(|| "Failed to delete Kvm mem slot")?;
+                Err(e).with_context(|| {
+                    format!(
+                        "KVM register private memory region failed: addr 0x{:X}, size 0x{:X}",
+                        aligned_addr.raw_value(),
+                        aligned_size
+                    )
+                })
+            });
+        }
         unsafe {
             KVM_FDS
                 .load()
@@ -292,6 +313,7 @@ impl KvmMemoryListener {
         if flat_range.owner.region_type() != RegionType::Ram
             && flat_range.owner.region_type() != RegionType::RomDevice
             && flat_range.owner.region_type() != RegionType::RamDevice
+            && flat_range.owner.region_type() != RegionType::Rom
         {
             return Ok(());
         }
@@ -335,6 +357,7 @@ impl KvmMemoryListener {
                     )
                 })?;
         }
+        KVM_FDS.load().remove_private_memory_region(kvm_region.slot);
 
         Ok(())
     }
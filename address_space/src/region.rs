@@ -22,7 +22,7 @@ use log::{debug, warn};
 use crate::address_space::FlatView;
 use crate::{
     AddressRange, AddressSpace, AddressSpaceError, FileBackend, GuestAddress, HostMemMapping,
-    RegionOps,
+    IommuRegion, RegionOps, WriteFn,
 };
 use migration::{migration::Migratable, MigrationManager};
 
@@ -42,6 +42,10 @@ pub enum RegionType {
     RamDevice,
     /// Alias type
     Alias,
+    /// Rom type, read-only Ram whose guest writes are trapped.
+    Rom,
+    /// Iommu type, accesses are translated by vIOMMU into another address space.
+    Iommu,
 }
 
 /// Represents a memory region, used by mem-mapped IO, Ram or Rom.
@@ -57,7 +61,7 @@ pub struct Region {
     size: Arc<AtomicU64>,
     /// Offset in parent Container-type region. It won't be changed once initialized.
     offset: Arc<Mutex<GuestAddress>>,
-    /// If not Ram, RomDevice, RamDevice Region type, `mem_mapping` is None. It won't be changed
+    /// If not Ram, RomDevice, RamDevice, Rom Region type, `mem_mapping` is None. It won't be changed
     /// once initialized.
     mem_mapping: Option<Arc<HostMemMapping>>,
     /// `ops` provides read/write function.
@@ -77,6 +81,10 @@ pub struct Region {
     alias: Option<Arc<Region>>,
     /// Offset in parent Alias-type region.
     alias_offset: u64,
+    /// Handler of guest writes to Rom-type region, writes are discarded if it is None.
+    rom_write: Option<WriteFn>,
+    /// Translation of Iommu-type region.
+    iommu: Option<Arc<IommuRegion>>,
 }
 
 impl fmt::Debug for Region {
@@ -260,6 +268,8 @@ impl Region {
             max_access_size: None,
             alias: None,
             alias_offset: 0_u64,
+            rom_write: None,
+            iommu: None,
         }
     }
 
@@ -329,6 +339,46 @@ impl Region {
         region
     }
 
+    /// Initialize Rom-type region, which is mapped read-only to guest.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_mapping` - Mapped memory of this region.
+    /// * `write` - Handler of guest writes, writes are silently discarded if it is None.
+    pub fn init_rom_region(
+        mem_mapping: Arc<HostMemMapping>,
+        write: Option<WriteFn>,
+        name: &str,
+    ) -> Region {
+        let mut region = Region::init_region_internal(
+            name,
+            mem_mapping.size(),
+            RegionType::Rom,
+            Some(mem_mapping),
+            None,
+        );
+        region.rom_write = write;
+
+        region
+    }
+
+    /// Initialize Iommu-type region, the offset within it is IOVA of device.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of IOVA space.
+    /// * `iommu` - Translation of the region.
+    pub fn init_iommu_region(size: u64, iommu: Arc<IommuRegion>, name: &str) -> Region {
+        let mut region = Region::init_region_internal(name, size, RegionType::Iommu, None, None);
+        region.iommu = Some(iommu);
+        region
+    }
+
+    /// Get the translation of Iommu-type region.
+    pub(crate) fn iommu(&self) -> Option<&Arc<IommuRegion>> {
+        self.iommu.as_ref()
+    }
+
     /// Initialize RamDevice-type region.
     ///
     /// # Arguments
@@ -415,19 +465,46 @@ impl Region {
     }
 
     /// Returns the minimum address managed by the region.
-    /// If this region is not `Ram`, `RamDevice`, `RomDevice` type,
+    /// If this region is not `Ram`, `RamDevice`, `RomDevice`, `Rom` type,
     /// this function will return `None`.
     pub fn start_addr(&self) -> Option<GuestAddress> {
-        if self.region_type != RegionType::Ram
-            && self.region_type != RegionType::RamDevice
-            && self.region_type != RegionType::RomDevice
-        {
+        if !self.is_host_backed() {
             return None;
         }
 
         self.mem_mapping.as_ref().map(|r| r.start_address())
     }
 
+    /// Whether this region is backed by host memory.
+    fn is_host_backed(&self) -> bool {
+        matches!(
+            self.region_type,
+            RegionType::Ram | RegionType::RamDevice | RegionType::RomDevice | RegionType::Rom
+        )
+    }
+
+    /// Load the content of Rom-type region from host side, guest writes can't change it.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Source data.
+    /// * `offset` - Offset in region.
+    /// * `count` - Size of data.
+    pub fn load_rom(&self, src: &mut dyn std::io::Read, offset: u64, count: u64) -> Result<()> {
+        if self.region_type != RegionType::Rom {
+            return Err(anyhow!(AddressSpaceError::RegionType(self.region_type)));
+        }
+        self.check_valid_offset(offset, count)
+            .with_context(|| AddressSpaceError::InvalidOffset(offset, count, self.size()))?;
+        let host_addr = self.mem_mapping.as_ref().unwrap().host_address();
+        // SAFETY: The range is checked to be in the memory mapped for this region.
+        let slice = unsafe {
+            std::slice::from_raw_parts_mut((host_addr + offset) as *mut u8, count as usize)
+        };
+        src.read_exact(slice)
+            .with_context(|| format!("Failed to load content of Rom {}", self.name))
+    }
+
     /// Change mode of RomDevice-type region,
     ///
     /// # Arguments
@@ -468,25 +545,28 @@ impl Region {
     /// Get the host address if this region is backed by host-memory,
     /// Return `None` if it is not a Ram-type region.
     pub fn get_host_address(&self) -> Option<u64> {
-        if self.region_type != RegionType::Ram
-            && self.region_type != RegionType::RamDevice
-            && self.region_type != RegionType::RomDevice
-        {
+        if !self.is_host_backed() {
             return None;
         }
         self.mem_mapping.as_ref().map(|r| r.host_address())
     }
 
     pub fn get_host_share(&self) -> Option<bool> {
-        if self.region_type != RegionType::Ram
-            && self.region_type != RegionType::RamDevice
-            && self.region_type != RegionType::RomDevice
-        {
+        if !self.is_host_backed() {
             return None;
         }
         self.mem_mapping.as_ref().map(|r| r.mem_shared())
     }
 
+    /// Get read-only mode of Ram-type region, which is mapped read-only from a host file.
+    /// Return `None` if it is not a Ram-type region.
+    pub fn get_host_read_only(&self) -> Option<bool> {
+        if self.region_type != RegionType::Ram {
+            return None;
+        }
+        self.mem_mapping.as_ref().map(|r| r.mem_read_only())
+    }
+
     /// Get the file information if this region is backed by host-memory.
     /// Return `None` if it is not a Ram-type region.
     pub fn get_file_backend(&self) -> Option<FileBackend> {
@@ -503,7 +583,7 @@ impl Region {
 
     /// Return all sub-regions of this Region, the returned vector is not empty,
     /// iff this region is a container.
-    pub(crate) fn subregions(&self) -> Vec<Region> {
+    pub fn subregions(&self) -> Vec<Region> {
         self.subregions.read().unwrap().clone()
     }
 
@@ -569,7 +649,7 @@ impl Region {
         count: u64,
     ) -> Result<()> {
         match self.region_type {
-            RegionType::Ram | RegionType::RamDevice => {
+            RegionType::Ram | RegionType::RamDevice | RegionType::Rom => {
                 self.check_valid_offset(offset, count).with_context(|| {
                     AddressSpaceError::InvalidOffset(offset, count, self.size())
                 })?;
@@ -629,6 +709,9 @@ impl Region {
                     "Failed to write slice provided by device to mutable buffer"
                 })?;
             }
+            RegionType::Iommu => {
+                self.iommu.as_ref().unwrap().read(dst, offset, count)?;
+            }
             _ => {
                 return Err(anyhow!(AddressSpaceError::RegionType(self.region_type())));
             }
@@ -669,6 +752,9 @@ impl Region {
 
         match self.region_type {
             RegionType::Ram | RegionType::RamDevice => {
+                if self.get_host_read_only().unwrap_or(false) {
+                    bail!("Failed to write to read-only Ram {}", self.name);
+                }
                 let host_addr = self.mem_mapping.as_ref().unwrap().host_address();
                 // Mark vmm dirty page manually if live migration is active.
                 MigrationManager::mark_dirty_log(host_addr + offset, count);
@@ -679,6 +765,26 @@ impl Region {
                 src.read_exact(slice)
                     .with_context(|| "Failed to write buffer to Ram")?;
             }
+            RegionType::Rom => {
+                let mut slice = vec![0_u8; count as usize];
+                src.read_exact(&mut slice)
+                    .with_context(|| "Failed to write buffer to slice, which will be discarded")?;
+                match self.rom_write.as_ref() {
+                    Some(write_ops) => {
+                        if !write_ops(&slice, base, offset) {
+                            return Err(anyhow!(AddressSpaceError::IoAccess(
+                                base.raw_value(),
+                                offset,
+                                count
+                            )));
+                        }
+                    }
+                    None => debug!(
+                        "Discard write to Rom {}: offset 0x{:X}, length 0x{:X}",
+                        self.name, offset, count
+                    ),
+                }
+            }
             RegionType::RomDevice | RegionType::IO => {
                 if count >= std::usize::MAX as u64 {
                     return Err(anyhow!(AddressSpaceError::Overflow(count)));
@@ -705,6 +811,9 @@ impl Region {
                     )));
                 }
             }
+            RegionType::Iommu => {
+                self.iommu.as_ref().unwrap().write(src, offset, count)?;
+            }
             _ => {
                 return Err(anyhow!(AddressSpaceError::RegionType(self.region_type())));
             }
@@ -895,7 +1004,12 @@ impl Region {
                     })?;
                 }
             }
-            RegionType::Ram | RegionType::IO | RegionType::RomDevice | RegionType::RamDevice => {
+            RegionType::Ram
+            | RegionType::IO
+            | RegionType::RomDevice
+            | RegionType::RamDevice
+            | RegionType::Rom
+            | RegionType::Iommu => {
                 self.render_terminate_region(base, addr_range, flat_view)
                     .with_context(||
                         format!(
@@ -1028,6 +1142,8 @@ impl Region {
             RegionType::IO => String::from("i/o"),
             RegionType::RomDevice => String::from("romd"),
             RegionType::RamDevice => String::from("ramd"),
+            RegionType::Rom => String::from("rom"),
+            RegionType::Iommu => String::from("iommu"),
             _ => String::from("err type"),
         }
     }
@@ -1057,7 +1173,12 @@ impl Region {
                     sub_r.mtree(level + 1);
                 }
             }
-            RegionType::Ram | RegionType::IO | RegionType::RomDevice | RegionType::RamDevice => {
+            RegionType::Ram
+            | RegionType::IO
+            | RegionType::RomDevice
+            | RegionType::RamDevice
+            | RegionType::Rom
+            | RegionType::Iommu => {
                 println!(
                     "{}0x{:X} - 0x{:X}, (Prio {}, {}) : {}",
                     tab,
@@ -1193,6 +1314,62 @@ mod test {
         assert_eq!(&slice, &mut res_slice2);
     }
 
+    #[test]
+    fn test_rom_region() {
+        let mem_mapping = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 1024, None, false, false, false).unwrap(),
+        );
+        let rom_region = Region::init_rom_region(mem_mapping.clone(), None, "rom");
+        let data: [u8; 8] = [0x5a; 8];
+        let mut res_data: [u8; 8] = [0; 8];
+        let count = data.len() as u64;
+
+        assert_eq!(rom_region.region_type(), RegionType::Rom);
+        assert_eq!(
+            rom_region.get_host_address().unwrap(),
+            mem_mapping.host_address()
+        );
+
+        // Guest writes are discarded.
+        assert!(rom_region.load_rom(&mut data.as_ref(), 0, count).is_ok());
+        assert!(rom_region
+            .write(&mut [0_u8; 8].as_ref(), GuestAddress(0), 0, count)
+            .is_ok());
+        assert!(rom_region
+            .read(&mut res_data.as_mut(), GuestAddress(0), 0, count)
+            .is_ok());
+        assert_eq!(data, res_data);
+        assert!(rom_region
+            .load_rom(&mut data.as_ref(), 1020, count)
+            .is_err());
+
+        // Guest writes are trapped into handler.
+        let written = Arc::new(AtomicU64::new(0));
+        let written_clone = written.clone();
+        let write_ops = move |data: &[u8], _addr: GuestAddress, offset: u64| -> bool {
+            written_clone.store(offset + data.len() as u64, Ordering::SeqCst);
+            true
+        };
+        let rom_region = Region::init_rom_region(mem_mapping, Some(Arc::new(write_ops)), "rom");
+        assert!(rom_region
+            .write(&mut [0_u8; 8].as_ref(), GuestAddress(0), 16, count)
+            .is_ok());
+        assert_eq!(written.load(Ordering::SeqCst), 24);
+        assert!(rom_region
+            .read(&mut res_data.as_mut(), GuestAddress(0), 0, count)
+            .is_ok());
+        assert_eq!(data, res_data);
+
+        let ram_region = Region::init_ram_region(
+            Arc::new(
+                HostMemMapping::new(GuestAddress(0), None, 1024, None, false, false, false)
+                    .unwrap(),
+            ),
+            "ram",
+        );
+        assert!(ram_region.load_rom(&mut data.as_ref(), 0, count).is_err());
+    }
+
     #[test]
     fn test_io_region() {
         let test_dev = Arc::new(Mutex::new(TestDevice::default()));
@@ -79,14 +79,23 @@ impl StateTransfer for AddressSpace {
     }
 }
 
-impl MigrationHook for AddressSpace {
-    fn save_memory(&self, fd: &mut dyn Write) -> Result<()> {
+impl AddressSpace {
+    /// Save state of ram regions with padding before memory data.
+    fn save_ram_state(&self, fd: &mut dyn Write) -> Result<()> {
         let ram_state = self.get_state_vec()?;
         fd.write_all(&ram_state)?;
         let padding_buffer =
             [0].repeat(memory_offset() - MIGRATION_HEADER_LENGTH - size_of::<AddressSpaceState>());
         fd.write_all(&padding_buffer)?;
 
+        Ok(())
+    }
+}
+
+impl MigrationHook for AddressSpace {
+    fn save_memory(&self, fd: &mut dyn Write) -> Result<()> {
+        self.save_ram_state(fd)?;
+
         for region in self.root().subregions().iter() {
             if let Some(base_addr) = region.start_addr() {
                 region
@@ -98,6 +107,22 @@ impl MigrationHook for AddressSpace {
         Ok(())
     }
 
+    fn save_memory_state(&self, fd: &mut dyn Write) -> Result<Vec<(u64, u64)>> {
+        self.save_ram_state(fd)?;
+
+        let mut ranges = Vec::new();
+        for region in self.root().subregions().iter() {
+            if region.start_addr().is_some() {
+                let hva = region.get_host_address().ok_or_else(|| {
+                    MigrationError::SaveVmMemoryErr("Region is not backed by host memory".into())
+                })?;
+                ranges.push((hva, region.size()));
+            }
+        }
+
+        Ok(ranges)
+    }
+
     fn restore_memory(&self, memory: Option<&File>, state: &[u8]) -> Result<()> {
         let address_space_state: &AddressSpaceState =
             AddressSpaceState::from_bytes(&state[0..size_of::<AddressSpaceState>()])
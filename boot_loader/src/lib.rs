@@ -50,12 +50,13 @@
 //!         kernel: Some(kernel_file),
 //!         initrd: None,
 //!         kernel_cmdline: String::new(),
-//!         cpu_count: 0,
+//!         apic_ids: Vec::new(),
 //!         gap_range: (0xC000_0000, 0x4000_0000),
 //!         ioapic_addr: 0xFEC0_0000,
 //!         lapic_addr: 0xFEE0_0000,
 //!         prot64_mode: true,
 //!         ident_tss_range: None,
+//!         reserved_ranges: Vec::new(),
 //!     };
 //!
 //!     let layout = load_linux(&bootloader_config, &guest_mem, None).unwrap();
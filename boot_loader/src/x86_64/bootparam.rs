@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp::min;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -203,7 +204,12 @@ impl BootParams {
 
         let high_memory_start = VMLINUX_RAM_START;
         let layout_32bit_gap_end = config.gap_range.0 + config.gap_range.1;
-        let mem_end = sys_mem.memory_end_address().raw_value();
+        // Reserved ranges are mapped as ram region, but they are not guest ram.
+        let mem_end = config
+            .reserved_ranges
+            .iter()
+            .map(|range| range.0)
+            .fold(sys_mem.memory_end_address().raw_value(), min);
         if mem_end < layout_32bit_gap_end {
             self.add_e820_entry(high_memory_start, mem_end - high_memory_start, E820_RAM);
         } else {
@@ -218,6 +224,9 @@ impl BootParams {
                 E820_RAM,
             );
         }
+        for (base, size) in config.reserved_ranges.iter() {
+            self.add_e820_entry(*base, *size, E820_RESERVED);
+        }
     }
 }
 
@@ -254,12 +263,13 @@ mod test {
             kernel: Some(PathBuf::new()),
             initrd: Some(PathBuf::new()),
             kernel_cmdline: String::from("this_is_a_piece_of_test_string"),
-            cpu_count: 2,
+            apic_ids: vec![0, 1],
             gap_range: (0xC000_0000, 0x4000_0000),
             ioapic_addr: 0xFEC0_0000,
             lapic_addr: 0xFEE0_0000,
             prot64_mode: false,
             ident_tss_range: None,
+            reserved_ranges: Vec::new(),
         };
 
         let boot_hdr = RealModeKernelHeader::default();
@@ -284,5 +294,32 @@ mod test {
         assert!(boot_params.e820_table[3].addr == 0x0010_0000);
         assert!(boot_params.e820_table[3].size == 0x0ff0_0000);
         assert!(boot_params.e820_table[3].type_ == 1);
+
+        // Reserved range above ram is not reported as ram.
+        let epc = Arc::new(
+            HostMemMapping::new(
+                GuestAddress(0x1000_0000),
+                None,
+                0x10_0000,
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap(),
+        );
+        root.add_subregion(Region::init_ram_region(epc, "epc"), 0x1000_0000)
+            .unwrap();
+        let config = X86BootLoaderConfig {
+            reserved_ranges: vec![(0x1000_0000, 0x10_0000)],
+            ..config
+        };
+        let mut boot_params = BootParams::new(boot_hdr);
+        boot_params.setup_e820_entries(&config, &space);
+        assert!(boot_params.e820_entries == 5);
+        assert!(boot_params.e820_table[3].size == 0x0ff0_0000);
+        assert!(boot_params.e820_table[4].addr == 0x1000_0000);
+        assert!(boot_params.e820_table[4].size == 0x10_0000);
+        assert!(boot_params.e820_table[4].type_ == E820_RESERVED);
     }
 }
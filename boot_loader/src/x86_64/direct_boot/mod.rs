@@ -261,7 +261,7 @@ pub fn load_linux(
     setup_isa_mptable(
         sys_mem,
         EBDA_START,
-        config.cpu_count,
+        &config.apic_ids,
         config.ioapic_addr,
         config.lapic_addr,
     )?;
@@ -327,12 +327,13 @@ mod test {
             kernel: Some(PathBuf::new()),
             initrd: Some(PathBuf::new()),
             kernel_cmdline: String::from("this_is_a_piece_of_test_string"),
-            cpu_count: 2,
+            apic_ids: vec![0, 1],
             gap_range: (0xC000_0000, 0x4000_0000),
             ioapic_addr: 0xFEC0_0000,
             lapic_addr: 0xFEE0_0000,
             prot64_mode: false,
             ident_tss_range: None,
+            reserved_ranges: Vec::new(),
         };
         let mut boot_hdr = RealModeKernelHeader::new();
         assert!(setup_boot_params(&config, &space, &boot_hdr).is_ok());
@@ -276,7 +276,7 @@ macro_rules! write_entry {
 pub fn setup_isa_mptable(
     sys_mem: &Arc<AddressSpace>,
     start_addr: u64,
-    num_cpus: u8,
+    apic_ids: &[u8],
     ioapic_addr: u32,
     lapic_addr: u32,
 ) -> Result<()> {
@@ -285,11 +285,17 @@ pub fn setup_isa_mptable(
     const MPTABLE_MAX_CPUS: u32 = 254;
     const MPTABLE_IOAPIC_NR: u8 = 16;
 
-    if u32::from(num_cpus) > MPTABLE_MAX_CPUS {
+    let num_cpus = apic_ids.len() as u8;
+    if apic_ids.len() as u32 > MPTABLE_MAX_CPUS {
         return Err(anyhow!(BootLoaderError::MaxCpus(num_cpus)));
     }
 
-    let ioapic_id: u8 = num_cpus + 1;
+    // IO APIC ID follows the max local APIC ID.
+    let ioapic_id = match apic_ids.iter().max() {
+        Some(id) if u32::from(*id) < MPTABLE_MAX_CPUS => id + 1,
+        None => 1,
+        _ => return Err(anyhow!(BootLoaderError::MaxCpus(num_cpus))),
+    };
     let header = start_addr + std::mem::size_of::<FloatingPointer>() as u64;
     sys_mem.write_object(
         &FloatingPointer::new(header as u32),
@@ -298,9 +304,9 @@ pub fn setup_isa_mptable(
 
     let mut offset = header + std::mem::size_of::<ConfigTableHeader>() as u64;
     let mut sum = 0u8;
-    for cpu_id in 0..num_cpus {
+    for (cpu_id, apic_id) in apic_ids.iter().enumerate() {
         write_entry!(
-            ProcessEntry::new(cpu_id, true, cpu_id == 0),
+            ProcessEntry::new(*apic_id, true, cpu_id == 0),
             ProcessEntry,
             sys_mem,
             offset,
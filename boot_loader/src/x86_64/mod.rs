@@ -100,8 +100,8 @@ pub struct X86BootLoaderConfig {
     pub initrd: Option<PathBuf>,
     /// Kernel cmdline parameters.
     pub kernel_cmdline: String,
-    /// VM's CPU count.
-    pub cpu_count: u8,
+    /// Local APIC IDs of VM's CPUs, the first one is the bootstrap processor.
+    pub apic_ids: Vec<u8>,
     /// (gap start, gap size)
     pub gap_range: (u64, u64),
     /// IO APIC base address
@@ -112,6 +112,9 @@ pub struct X86BootLoaderConfig {
     pub ident_tss_range: Option<(u64, u64)>,
     /// Boot from 64-bit protection mode or not.
     pub prot64_mode: bool,
+    /// (base, size) of ranges above guest ram which are reserved in e820 table, such as
+    /// SGX EPC sections.
+    pub reserved_ranges: Vec<(u64, u64)>,
 }
 
 /// The start address for some boot source in guest memory for `x86_64`.
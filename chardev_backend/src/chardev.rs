@@ -11,10 +11,10 @@
 // See the Mulan PSL v2 for more details.
 
 use std::fs::{read_link, File, OpenOptions};
-use std::io::{Stdin, Stdout};
+use std::io::{Stdin, Stdout, Write};
 use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
@@ -23,6 +23,9 @@ use libc::{cfmakeraw, tcgetattr, tcsetattr, termios};
 use log::{error, info};
 use vmm_sys_util::epoll::EventSet;
 
+use crate::logfile::{ChardevLog, LogOutput};
+use crate::mux::MuxChardev;
+use crate::ringbuf::Ringbuf;
 use machine_manager::machine::{PathInfo, PTY_PATH};
 use machine_manager::{
     config::{ChardevConfig, ChardevType},
@@ -32,6 +35,7 @@ use util::file::clear_file;
 use util::loop_context::{
     gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::seclabel::label_path;
 use util::set_termi_raw_mode;
 use util::unix::limit_permission;
 
@@ -48,6 +52,7 @@ pub trait ChardevNotifyDevice: Send {
     fn chardev_notify(&mut self, status: ChardevStatus);
 }
 
+#[derive(Clone, Copy)]
 pub enum ChardevStatus {
     Close,
     Open,
@@ -71,6 +76,19 @@ pub struct Chardev {
     receiver: Option<Arc<Mutex<dyn InputReceiver>>>,
     /// Used to notify device the socket is opened or closed.
     dev: Option<Arc<Mutex<dyn ChardevNotifyDevice>>>,
+    /// Whether the backend is shared by multiple frontends.
+    mux_enabled: bool,
+    /// Multiplexer which the backend is shared through, and index of this frontend in it.
+    mux: Option<(Arc<Mutex<MuxChardev>>, usize)>,
+    /// Ring buffer for ringbuf-type chardev.
+    ringbuf: Option<Arc<Mutex<Ringbuf>>>,
+    /// Path of file which logs the output.
+    logfile: Option<String>,
+    /// Whether to append to the log file instead of truncating it.
+    logappend: bool,
+    /// Log file which all the output is written to, it's shared with the backend for
+    /// frontends of multiplexed chardev.
+    log: Option<Arc<Mutex<ChardevLog>>>,
 }
 
 impl Chardev {
@@ -84,15 +102,41 @@ impl Chardev {
             stream_fd: None,
             receiver: None,
             dev: None,
+            mux_enabled: chardev_cfg.mux,
+            mux: None,
+            ringbuf: None,
+            logfile: chardev_cfg.logfile,
+            logappend: chardev_cfg.logappend,
+            log: None,
         }
     }
 
     pub fn realize(&mut self) -> Result<()> {
+        if self.mux_enabled {
+            // Output of all the frontends is logged by the shared backend.
+            let (mux, index) = MuxChardev::attach(ChardevConfig {
+                id: self.id.clone(),
+                backend: self.backend.clone(),
+                mux: false,
+                logfile: self.logfile.clone(),
+                logappend: self.logappend,
+            })?;
+            let locked_mux = mux.lock().unwrap();
+            self.output = Some(locked_mux.output());
+            self.log = locked_mux.log();
+            drop(locked_mux);
+            self.mux = Some((mux, index));
+            return Ok(());
+        }
+        if let Some(path) = &self.logfile {
+            self.log = Some(Arc::new(Mutex::new(ChardevLog::new(path, self.logappend)?)));
+        }
+
         match &self.backend {
             ChardevType::Stdio => {
                 set_termi_raw_mode().with_context(|| "Failed to set terminal to raw mode")?;
                 self.input = Some(Arc::new(Mutex::new(std::io::stdin())));
-                self.output = Some(Arc::new(Mutex::new(std::io::stdout())));
+                self.set_output(Some(Arc::new(Mutex::new(std::io::stdout()))));
             }
             ChardevType::Pty => {
                 let (master, path) =
@@ -106,7 +150,7 @@ impl Chardev {
                 // Safe because `master_arc` is the only one owner for the file descriptor.
                 let master_arc = unsafe { Arc::new(Mutex::new(File::from_raw_fd(master))) };
                 self.input = Some(master_arc.clone());
-                self.output = Some(master_arc);
+                self.set_output(Some(master_arc));
             }
             ChardevType::Socket {
                 path,
@@ -131,8 +175,12 @@ impl Chardev {
                         path
                     )
                 })?;
+                label_path(path)?;
+                // Output is logged even if no client is connected.
+                self.set_output(None);
             }
             ChardevType::File(path) => {
+                let created = !Path::new(path).exists();
                 let file = Arc::new(Mutex::new(
                     OpenOptions::new()
                         .read(true)
@@ -140,21 +188,69 @@ impl Chardev {
                         .create(true)
                         .open(path)?,
                 ));
-                self.output = Some(file);
+                if created {
+                    label_path(path)?;
+                }
+                self.set_output(Some(file));
+            }
+            ChardevType::Ringbuf(size) => {
+                let ringbuf = Ringbuf::register(&self.id, *size)?;
+                self.set_output(Some(ringbuf.clone()));
+                self.ringbuf = Some(ringbuf);
             }
         };
         Ok(())
     }
 
+    /// Set output of the backend, which is wrapped to write to the log file as well.
+    fn set_output(&mut self, output: Option<Arc<Mutex<dyn CommunicatOutInterface>>>) {
+        self.output = match &self.log {
+            Some(log) => Some(Arc::new(Mutex::new(LogOutput::new(output, log.clone())))),
+            None => output,
+        };
+    }
+
+    pub(crate) fn log(&self) -> Option<Arc<Mutex<ChardevLog>>> {
+        self.log.clone()
+    }
+
+    /// Write `buf` only to the log file, used when the frontend discards the output as
+    /// the backend is not connected.
+    pub fn write_log(&self, buf: &[u8]) {
+        if let Some(log) = &self.log {
+            let mut locked_log = log.lock().unwrap();
+            if let Err(e) = locked_log.write_all(buf).and_then(|_| locked_log.flush()) {
+                error!("Failed to write chardev log: {:?}", e);
+            }
+        }
+    }
+
     pub fn set_receiver<T: 'static + InputReceiver>(&mut self, dev: &Arc<Mutex<T>>) {
+        if let Some((mux, index)) = &self.mux {
+            mux.lock().unwrap().set_receiver(*index, dev.clone());
+        }
+        if let Some(ringbuf) = &self.ringbuf {
+            ringbuf.lock().unwrap().set_receiver(dev.clone());
+        }
         self.receiver = Some(dev.clone());
     }
 
     pub fn set_device(&mut self, dev: Arc<Mutex<dyn ChardevNotifyDevice>>) {
+        if let Some((mux, index)) = &self.mux {
+            mux.lock().unwrap().set_device(*index, dev.clone());
+        }
         self.dev = Some(dev.clone());
     }
 }
 
+impl Drop for Chardev {
+    fn drop(&mut self) {
+        if self.ringbuf.is_some() {
+            Ringbuf::unregister(&self.id);
+        }
+    }
+}
+
 fn set_pty_raw_mode() -> Result<(i32, PathBuf)> {
     let mut master: libc::c_int = 0;
     let master_ptr: *mut libc::c_int = &mut master;
@@ -239,7 +335,10 @@ fn get_notifier_handler(
                 return None;
             }
             let mut buffer = vec![0_u8; buff_size];
-            if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
+            // Input is unlocked before received, as receiver may write to the output which
+            // shares the same lock.
+            let ret = input.lock().unwrap().chr_read_raw(&mut buffer);
+            if let Ok(index) = ret {
                 locked_receiver.receive(&buffer[..index]);
             } else {
                 error!("Failed to read input data");
@@ -254,7 +353,7 @@ fn get_notifier_handler(
             locked_chardev.stream_fd = Some(stream_fd);
             let stream_arc = Arc::new(Mutex::new(stream));
             locked_chardev.input = Some(stream_arc.clone());
-            locked_chardev.output = Some(stream_arc);
+            locked_chardev.set_output(Some(stream_arc));
 
             if let Some(dev) = &locked_chardev.dev {
                 dev.lock().unwrap().chardev_notify(ChardevStatus::Open);
@@ -282,7 +381,8 @@ fn get_notifier_handler(
                         return None;
                     }
                     let mut buffer = vec![0_u8; buff_size];
-                    if let Ok(index) = input.lock().unwrap().chr_read_raw(&mut buffer) {
+                    let ret = input.lock().unwrap().chr_read_raw(&mut buffer);
+                    if let Ok(index) = ret {
                         locked_receiver.receive(&buffer[..index]);
                     } else {
                         error!("Failed to read input data");
@@ -294,7 +394,7 @@ fn get_notifier_handler(
                         dev.lock().unwrap().chardev_notify(ChardevStatus::Close);
                     }
                     locked_chardev.input = None;
-                    locked_chardev.output = None;
+                    locked_chardev.set_output(None);
                     locked_chardev.stream_fd = None;
                     Some(gen_delete_notifiers(&[stream_fd]))
                 } else {
@@ -309,12 +409,16 @@ fn get_notifier_handler(
                 vec![inner_handler],
             )])
         }),
-        ChardevType::File(_) => Rc::new(move |_, _| None),
+        ChardevType::File(_) | ChardevType::Ringbuf(_) => Rc::new(move |_, _| None),
     }
 }
 
 impl EventNotifierHelper for Chardev {
     fn internal_notifiers(chardev: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        if let Some((mux, _)) = chardev.lock().unwrap().mux.clone() {
+            return MuxChardev::notifiers(&mux);
+        }
+
         let mut notifiers = Vec::new();
         let backend = chardev.lock().unwrap().backend.clone();
         let cloned_chardev = chardev.clone();
@@ -349,7 +453,7 @@ impl EventNotifierHelper for Chardev {
                     ));
                 }
             }
-            ChardevType::File(_) => (),
+            ChardevType::File(_) | ChardevType::Ringbuf(_) => (),
         }
         notifiers
     }
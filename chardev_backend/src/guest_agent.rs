@@ -0,0 +1,215 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+use machine_manager::event;
+use machine_manager::qmp::qmp_channel::QmpChannel;
+use machine_manager::qmp::qmp_schema::{self, Any};
+
+/// Name of the virtio serial port which qemu-guest-agent in guest communicates through.
+pub const GUEST_AGENT_PORT_NAME: &str = "org.qemu.guest_agent.0";
+/// Default seconds to wait for the response of guest agent.
+const GUEST_AGENT_DEFAULT_TIMEOUT: u64 = 10;
+/// Sentinel byte which resets the parser of guest agent, and precedes the response of
+/// `guest-sync-delimited`.
+const GUEST_AGENT_SENTINEL: u8 = 0xFF;
+
+/// Socket path of the guest agent channel.
+static GUEST_AGENT_PATH: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+/// Whether a guest agent command is in progress.
+static GUEST_AGENT_BUSY: AtomicBool = AtomicBool::new(false);
+/// Id used to synchronize with guest agent.
+static GUEST_AGENT_SYNC_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Register the server socket of the guest agent channel, which is connected by
+/// `guest-agent-command` as a client.
+///
+/// # Arguments
+///
+/// * `path` - Path of the server socket.
+pub fn register_guest_agent(path: &str) -> Result<()> {
+    let mut agent_path = GUEST_AGENT_PATH.lock().unwrap();
+    if agent_path.is_some() {
+        bail!("Only one {} port is supported", GUEST_AGENT_PORT_NAME);
+    }
+    *agent_path = Some(path.to_string());
+    Ok(())
+}
+
+/// Send a command to guest agent in a separate thread, as the virtio serial port is
+/// served by the main loop which also handles qmp. The result is reported by
+/// `GUEST_AGENT_RESPONSE` event.
+///
+/// # Arguments
+///
+/// * `command` - Name of the guest agent command.
+/// * `arguments` - Arguments of the guest agent command.
+/// * `timeout` - Seconds to wait for the response.
+pub fn guest_agent_command(
+    command: String,
+    arguments: Option<Any>,
+    timeout: Option<u64>,
+) -> Result<()> {
+    let path = GUEST_AGENT_PATH.lock().unwrap().clone().with_context(|| {
+        format!(
+            "No guest agent channel, add a virtserialport named {} on a socket chardev",
+            GUEST_AGENT_PORT_NAME
+        )
+    })?;
+    let timeout = Duration::from_secs(timeout.unwrap_or(GUEST_AGENT_DEFAULT_TIMEOUT));
+    if timeout.is_zero() {
+        bail!("Timeout of guest agent command should be greater than 0");
+    }
+    if GUEST_AGENT_BUSY.swap(true, Ordering::SeqCst) {
+        bail!("Another guest agent command is in progress");
+    }
+
+    let request = match arguments {
+        Some(args) => json!({ "execute": command, "arguments": args }),
+        None => json!({ "execute": command }),
+    };
+    let ret = thread::Builder::new()
+        .name("guest-agent".to_string())
+        .spawn(move || {
+            let result = execute(&path, &request, timeout);
+            GUEST_AGENT_BUSY.store(false, Ordering::SeqCst);
+            let mut resp = qmp_schema::GuestAgentResponse {
+                command,
+                ..Default::default()
+            };
+            match result {
+                Ok(ret) => resp.ret = Some(ret),
+                Err(e) => {
+                    error!("Guest agent command failed: {:?}", e);
+                    resp.error = Some(format!("{:#}", e));
+                }
+            }
+            event!(GuestAgentResponse; resp);
+        });
+    if let Err(e) = ret {
+        GUEST_AGENT_BUSY.store(false, Ordering::SeqCst);
+        bail!("Failed to create guest agent thread: {}", e);
+    }
+    Ok(())
+}
+
+fn send_request(stream: &mut UnixStream, request: &Any) -> Result<()> {
+    let mut data = serde_json::to_vec(request)?;
+    data.push(b'\n');
+    stream
+        .write_all(&data)
+        .with_context(|| "Failed to send to guest agent")
+}
+
+fn read_response(reader: &mut BufReader<UnixStream>) -> Result<Any> {
+    let mut line = String::new();
+    reader.read_line(&mut line).with_context(|| {
+        "No response from guest agent, it may not be running or the channel is in use"
+    })?;
+    if line.is_empty() {
+        bail!("Guest agent channel is closed");
+    }
+    serde_json::from_str(line.trim()).with_context(|| format!("Invalid response {}", line))
+}
+
+/// Synchronize with guest agent to discard stale data, then execute the request.
+fn execute(path: &str, request: &Any, timeout: Duration) -> Result<Any> {
+    let mut stream = UnixStream::connect(path)
+        .with_context(|| format!("Failed to connect to guest agent channel {}", path))?;
+    stream.set_read_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let sync_id = GUEST_AGENT_SYNC_ID.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(&[GUEST_AGENT_SENTINEL])?;
+    send_request(
+        &mut stream,
+        &json!({ "execute": "guest-sync-delimited", "arguments": { "id": sync_id } }),
+    )?;
+    let mut stale = Vec::new();
+    reader
+        .read_until(GUEST_AGENT_SENTINEL, &mut stale)
+        .with_context(|| {
+            "No response from guest agent, it may not be running or the channel is in use"
+        })?;
+    let resp = read_response(&mut reader)?;
+    if resp["return"] != json!(sync_id) {
+        bail!("Failed to synchronize with guest agent: {}", resp);
+    }
+
+    send_request(&mut stream, request)?;
+    let mut resp = read_response(&mut reader)?;
+    if let Some(err) = resp.get("error") {
+        bail!(
+            "{}",
+            err["desc"].as_str().map_or(err.to_string(), str::to_string)
+        );
+    }
+    Ok(resp["return"].take())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_guest_agent_execute() {
+        let path = "/tmp/test_guest_agent_execute.sock";
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).unwrap();
+        // Fake guest agent, which returns the path of guest-exec and fails other commands.
+        let agent = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut sentinel = [0_u8; 1];
+                reader.read_exact(&mut sentinel).unwrap();
+                assert_eq!(sentinel[0], GUEST_AGENT_SENTINEL);
+
+                let req = read_response(&mut reader).unwrap();
+                assert_eq!(req["execute"], "guest-sync-delimited");
+                writer.write_all(b"stale\xff").unwrap();
+                send_request(&mut writer, &json!({ "return": req["arguments"]["id"] })).unwrap();
+
+                let req = read_response(&mut reader).unwrap();
+                let resp = match req["execute"].as_str() {
+                    Some("guest-exec") => json!({ "return": req["arguments"]["path"] }),
+                    _ => json!({ "error": { "class": "GenericError", "desc": "unknown" } }),
+                };
+                send_request(&mut writer, &resp).unwrap();
+            }
+        });
+
+        let timeout = Duration::from_secs(5);
+        let request = json!({ "execute": "guest-exec", "arguments": { "path": "/bin/ls" } });
+        assert_eq!(execute(path, &request, timeout).unwrap(), "/bin/ls");
+        let request = json!({ "execute": "guest-none" });
+        let err = execute(path, &request, timeout).unwrap_err();
+        assert_eq!(err.to_string(), "unknown");
+        agent.join().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -11,3 +11,8 @@
 // See the Mulan PSL v2 for more details.
 
 pub mod chardev;
+pub mod guest_agent;
+pub mod logfile;
+pub mod monitor;
+pub mod mux;
+pub mod ringbuf;
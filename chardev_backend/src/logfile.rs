@@ -0,0 +1,208 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::{rename, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use crate::chardev::CommunicatOutInterface;
+
+/// Max size of chardev log file, it's rotated when exceeded.
+const CHARDEV_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files which are kept, named `<logfile>.1` (the newest) to
+/// `<logfile>.3` (the oldest).
+const CHARDEV_LOG_BACKUPS: u32 = 3;
+
+/// Log file which records the output of chardev.
+pub struct ChardevLog {
+    path: String,
+    file: File,
+    /// Size of the current log file.
+    size: u64,
+    max_size: u64,
+}
+
+impl ChardevLog {
+    /// Open the log file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the log file.
+    /// * `append` - Whether to append to the log file instead of truncating it.
+    pub fn new(path: &str, append: bool) -> Result<Self> {
+        let file = open_log(path, append)?;
+        let size = file.metadata()?.len();
+        Ok(ChardevLog {
+            path: path.to_string(),
+            file,
+            size,
+            max_size: CHARDEV_LOG_MAX_SIZE,
+        })
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        for i in (1..CHARDEV_LOG_BACKUPS).rev() {
+            let from = format!("{}.{}", self.path, i);
+            if let Err(e) = rename(&from, format!("{}.{}", self.path, i + 1)) {
+                if e.kind() != ErrorKind::NotFound {
+                    return Err(e).with_context(|| format!("Failed to rotate {}", from));
+                }
+            }
+        }
+        rename(&self.path, format!("{}.1", self.path))
+            .with_context(|| format!("Failed to rotate {}", self.path))?;
+        self.file = open_log(&self.path, false)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_log(path: &str, append: bool) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("Failed to open chardev log file {}", path))
+}
+
+impl Write for ChardevLog {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size >= self.max_size {
+            // Keep logging to the current file if rotation fails.
+            if let Err(e) = self.rotate() {
+                error!("{:?}", e);
+            }
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Output of chardev which also writes to the log file. The output of backend may be
+/// absent, e.g. no client is connected to the socket, then only the log is written.
+pub struct LogOutput {
+    output: Option<Arc<Mutex<dyn CommunicatOutInterface>>>,
+    log: Arc<Mutex<ChardevLog>>,
+}
+
+impl LogOutput {
+    pub fn new(
+        output: Option<Arc<Mutex<dyn CommunicatOutInterface>>>,
+        log: Arc<Mutex<ChardevLog>>,
+    ) -> Self {
+        LogOutput { output, log }
+    }
+}
+
+impl Write for LogOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // All the data is logged whatever the result of backend, so the backend is written
+        // fully here to avoid logging the same data again.
+        if let Err(e) = self.log.lock().unwrap().write_all(buf) {
+            error!("Failed to write chardev log: {:?}", e);
+        }
+        if let Some(output) = &self.output {
+            output.lock().unwrap().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Err(e) = self.log.lock().unwrap().flush() {
+            error!("Failed to flush chardev log: {:?}", e);
+        }
+        match &self.output {
+            Some(output) => output.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CommunicatOutInterface for LogOutput {}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, remove_file};
+
+    use super::*;
+
+    #[test]
+    fn test_chardev_log_rotate() {
+        let path = "/tmp/test_chardev_log_rotate.log";
+        let log = Arc::new(Mutex::new(ChardevLog::new(path, false).unwrap()));
+        log.lock().unwrap().max_size = 4;
+        let mut output = LogOutput::new(None, log.clone());
+
+        output.write_all(b"boot").unwrap();
+        output.write_all(b"ing").unwrap();
+        assert_eq!(read(path).unwrap(), b"ing");
+        assert_eq!(read(format!("{}.1", path)).unwrap(), b"boot");
+        for data in [b"abcd", b"efgh", b"ijkl"] {
+            output.write_all(data).unwrap();
+        }
+        assert_eq!(read(path).unwrap(), b"ijkl");
+        assert_eq!(read(format!("{}.1", path)).unwrap(), b"efgh");
+        assert_eq!(read(format!("{}.2", path)).unwrap(), b"ingabcd");
+        assert_eq!(read(format!("{}.3", path)).unwrap(), b"boot");
+        drop(output);
+        drop(log);
+
+        // Log is kept if appended.
+        let mut log = ChardevLog::new(path, true).unwrap();
+        log.write_all(b"mn").unwrap();
+        assert_eq!(read(path).unwrap(), b"ijklmn");
+        let mut log = ChardevLog::new(path, false).unwrap();
+        log.write_all(b"op").unwrap();
+        assert_eq!(read(path).unwrap(), b"op");
+
+        remove_file(path).unwrap();
+        for i in 1..=CHARDEV_LOG_BACKUPS {
+            remove_file(format!("{}.{}", path, i)).unwrap();
+        }
+    }
+
+    struct BrokenOutput;
+
+    impl Write for BrokenOutput {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CommunicatOutInterface for BrokenOutput {}
+
+    #[test]
+    fn test_chardev_log_backend_failure() {
+        let path = format!("/tmp/test_chardev_log_backend_{}.log", std::process::id());
+        let log = Arc::new(Mutex::new(ChardevLog::new(&path, false).unwrap()));
+        let mut output = LogOutput::new(Some(Arc::new(Mutex::new(BrokenOutput))), log);
+
+        // Output is logged even if the backend fails to write it.
+        assert!(output.write_all(b"boot").is_err());
+        assert_eq!(read(&path).unwrap(), b"boot");
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use log::error;
+
+use crate::chardev::{Chardev, InputReceiver};
+use machine_manager::config::ChardevConfig;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::MachineExternalInterface;
+use machine_manager::qmp::qmp_audit::QmpPeer;
+use machine_manager::qmp::qmp_socket::{handle_qmp_json, qmp_greeting, qmp_quit};
+use util::loop_context::EventNotifierHelper;
+
+/// Max length of one qmp command.
+const MONITOR_MAX_LINE: usize = 64 * 1024;
+/// Size of input received each time.
+const MONITOR_RECV_SIZE: usize = 1024;
+
+/// Qmp monitor on chardev, which is usually multiplexed with serial console on stdio.
+/// Input is echoed as it's typed on terminal, and one command is executed each line.
+/// Events are not reported and file descriptors can't be passed through it, use qmp
+/// socket for them.
+pub struct ChardevMonitor {
+    chardev: Arc<Mutex<Chardev>>,
+    /// Controller which executes qmp commands.
+    controller: Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>,
+    /// Input which hasn't formed a line.
+    line: Vec<u8>,
+    /// Peer of the commands, for audit log.
+    peer: QmpPeer,
+}
+
+impl ChardevMonitor {
+    /// Create monitor on the chardev and register it to event loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - Config of the chardev.
+    /// * `controller` - The VM which executes qmp commands.
+    pub fn realize(
+        cfg: ChardevConfig,
+        controller: Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>,
+    ) -> Result<()> {
+        let peer = QmpPeer::chardev(&cfg.id);
+        let mut chardev = Chardev::new(cfg);
+        chardev
+            .realize()
+            .with_context(|| "Failed to realize chardev of monitor")?;
+        let chardev = Arc::new(Mutex::new(chardev));
+        let monitor = Arc::new(Mutex::new(ChardevMonitor {
+            chardev: chardev.clone(),
+            controller,
+            line: Vec::new(),
+            peer,
+        }));
+        chardev.lock().unwrap().set_receiver(&monitor);
+        monitor.lock().unwrap().send_line(qmp_greeting().as_bytes());
+        EventLoop::update_event(EventNotifierHelper::internal_notifiers(chardev), None)
+            .with_context(|| "Failed to register monitor to event loop")?;
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(output) = &self.chardev.lock().unwrap().output {
+            let mut locked_output = output.lock().unwrap();
+            if let Err(e) = locked_output.write_all(data) {
+                error!("Failed to write to monitor: {:?}", e);
+            }
+            let _ = locked_output.flush();
+        }
+    }
+
+    fn send_line(&self, msg: &[u8]) {
+        let mut data = msg.to_vec();
+        data.extend_from_slice(b"\r\n");
+        self.write(&data);
+    }
+
+    fn handle_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line).into_owned();
+        self.line.clear();
+        if line.trim().is_empty() {
+            return;
+        }
+        let controller: Arc<Mutex<dyn MachineExternalInterface>> = self.controller.clone();
+        let (resp, quit) = handle_qmp_json(&line, &controller, &self.peer);
+        self.send_line(resp.as_bytes());
+        if quit {
+            qmp_quit();
+        }
+    }
+}
+
+impl InputReceiver for ChardevMonitor {
+    fn receive(&mut self, buffer: &[u8]) {
+        let mut echo = Vec::with_capacity(buffer.len());
+        for &ch in buffer {
+            match ch {
+                b'\r' | b'\n' => {
+                    echo.extend_from_slice(b"\r\n");
+                    self.write(&echo);
+                    echo.clear();
+                    self.handle_line();
+                }
+                // Backspace and delete.
+                0x08 | 0x7F => {
+                    if self.line.pop().is_some() {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                }
+                _ => {
+                    if self.line.len() < MONITOR_MAX_LINE {
+                        self.line.push(ch);
+                        echo.push(ch);
+                    }
+                }
+            }
+        }
+        self.write(&echo);
+    }
+
+    fn remain_size(&mut self) -> usize {
+        MONITOR_RECV_SIZE
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use log::error;
+use once_cell::sync::Lazy;
+
+use crate::chardev::{
+    Chardev, ChardevNotifyDevice, ChardevStatus, CommunicatOutInterface, InputReceiver,
+};
+use crate::logfile::ChardevLog;
+use machine_manager::config::ChardevConfig;
+use machine_manager::event;
+use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_schema};
+use machine_manager::temp_cleaner::TempCleaner;
+use util::loop_context::{EventNotifier, EventNotifierHelper};
+use util::set_termi_canon_mode;
+
+/// Ctrl-a, the escape character of multiplexer commands.
+const MUX_ESCAPE_CHAR: u8 = 0x01;
+
+const MUX_HELP: &[u8] = b"\r\n\
+C-a h    print this help\r\n\
+C-a x    exit emulator\r\n\
+C-a c    switch between console and monitor\r\n\
+C-a C-a  sends C-a\r\n";
+
+/// Multiplexed chardevs, indexed by chardev id.
+static MUX_CHARDEVS: Lazy<Mutex<HashMap<String, Arc<Mutex<MuxChardev>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Default)]
+struct MuxFrontend {
+    receiver: Option<Arc<Mutex<dyn InputReceiver>>>,
+    dev: Option<Arc<Mutex<dyn ChardevNotifyDevice>>>,
+}
+
+/// Multiplexer which shares one chardev backend between multiple frontends, such as
+/// serial console and monitor. Output of all the frontends is written to the backend,
+/// while input is delivered to the focused frontend, which is switched by `C-a c`.
+pub struct MuxChardev {
+    /// The shared backend.
+    backend: Arc<Mutex<Chardev>>,
+    frontends: Vec<MuxFrontend>,
+    /// Index of the frontend which receives input.
+    focus: usize,
+    /// Whether the escape character has been received.
+    got_escape: bool,
+    /// Whether notifiers of the backend have been registered to event loop.
+    notifiers_registered: bool,
+}
+
+impl MuxChardev {
+    /// Attach a frontend to the multiplexed chardev, the backend is realized when the
+    /// first frontend is attached. Returns the multiplexer and index of the frontend.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - Config of the backend.
+    pub fn attach(cfg: ChardevConfig) -> Result<(Arc<Mutex<Self>>, usize)> {
+        let mut muxes = MUX_CHARDEVS.lock().unwrap();
+        let id = cfg.id.clone();
+        if let Some(mux) = muxes.get(&id) {
+            let mut locked_mux = mux.lock().unwrap();
+            locked_mux.frontends.push(MuxFrontend::default());
+            return Ok((mux.clone(), locked_mux.frontends.len() - 1));
+        }
+
+        let mut chardev = Chardev::new(cfg);
+        chardev
+            .realize()
+            .with_context(|| format!("Failed to realize backend of chardev {}", id))?;
+        let chardev = Arc::new(Mutex::new(chardev));
+        let mux = Arc::new(Mutex::new(MuxChardev {
+            backend: chardev.clone(),
+            frontends: vec![MuxFrontend::default()],
+            focus: 0,
+            got_escape: false,
+            notifiers_registered: false,
+        }));
+        let mut locked_chardev = chardev.lock().unwrap();
+        locked_chardev.set_receiver(&mux);
+        locked_chardev.set_device(mux.clone());
+        drop(locked_chardev);
+        muxes.insert(id, mux.clone());
+        Ok((mux, 0))
+    }
+
+    pub fn set_receiver(&mut self, index: usize, receiver: Arc<Mutex<dyn InputReceiver>>) {
+        self.frontends[index].receiver = Some(receiver);
+    }
+
+    pub fn set_device(&mut self, index: usize, dev: Arc<Mutex<dyn ChardevNotifyDevice>>) {
+        self.frontends[index].dev = Some(dev);
+    }
+
+    /// Get output of the frontends, which writes to the backend.
+    pub fn output(&self) -> Arc<Mutex<dyn CommunicatOutInterface>> {
+        Arc::new(Mutex::new(MuxOutput {
+            backend: self.backend.clone(),
+        }))
+    }
+
+    /// Get log file of the backend, which is shared by the frontends.
+    pub fn log(&self) -> Option<Arc<Mutex<ChardevLog>>> {
+        self.backend.lock().unwrap().log()
+    }
+
+    /// Get notifiers of the backend, which are registered only once for all the frontends.
+    pub fn notifiers(mux: &Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let mut locked_mux = mux.lock().unwrap();
+        if locked_mux.notifiers_registered {
+            return Vec::new();
+        }
+        locked_mux.notifiers_registered = true;
+        let backend = locked_mux.backend.clone();
+        drop(locked_mux);
+        EventNotifierHelper::internal_notifiers(backend)
+    }
+
+    fn focused_receiver(&self) -> Option<Arc<Mutex<dyn InputReceiver>>> {
+        self.frontends
+            .get(self.focus)
+            .and_then(|f| f.receiver.clone())
+    }
+
+    fn write_backend(&self, data: &[u8]) {
+        if let Some(output) = &self.backend.lock().unwrap().output {
+            let mut locked_output = output.lock().unwrap();
+            if let Err(e) = locked_output.write_all(data) {
+                error!("Failed to write to mux chardev: {:?}", e);
+            }
+            let _ = locked_output.flush();
+        }
+    }
+
+    /// Deliver input to the focused frontend, input exceeding its buffer is dropped.
+    fn deliver(&self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(receiver) = self.focused_receiver() {
+            let mut locked_receiver = receiver.lock().unwrap();
+            let len = std::cmp::min(locked_receiver.remain_size(), data.len());
+            locked_receiver.receive(&data[..len]);
+        }
+    }
+
+    fn exit(&self) {
+        self.write_backend(b"\r\nStratoVirt: Terminated\r\n");
+        let shutdown_msg = qmp_schema::Shutdown {
+            guest: false,
+            reason: "host-ui".to_string(),
+        };
+        event!(Shutdown; shutdown_msg);
+        TempCleaner::clean();
+        set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
+
+        std::process::exit(0);
+    }
+
+    /// Handle commands in the input, returns the rest which should be delivered to the
+    /// focused frontend.
+    fn handle_input(&mut self, buffer: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(buffer.len());
+        for (i, &ch) in buffer.iter().enumerate() {
+            if !self.got_escape {
+                if ch == MUX_ESCAPE_CHAR {
+                    self.got_escape = true;
+                } else {
+                    data.push(ch);
+                }
+                continue;
+            }
+
+            self.got_escape = false;
+            match ch {
+                MUX_ESCAPE_CHAR => data.push(ch),
+                b'h' | b'?' => self.write_backend(MUX_HELP),
+                b'x' => self.exit(),
+                b'c' => {
+                    // Input after the command goes to the next frontend.
+                    self.deliver(&data);
+                    self.focus = (self.focus + 1) % self.frontends.len();
+                    return self.handle_input(&buffer[i + 1..]);
+                }
+                _ => {}
+            }
+        }
+        data
+    }
+}
+
+impl InputReceiver for MuxChardev {
+    fn receive(&mut self, buffer: &[u8]) {
+        let data = self.handle_input(buffer);
+        self.deliver(&data);
+    }
+
+    fn remain_size(&mut self) -> usize {
+        // Always read at least one byte, so that commands can still be received when
+        // the focused frontend doesn't consume input.
+        let size = self
+            .focused_receiver()
+            .map(|r| r.lock().unwrap().remain_size())
+            .unwrap_or_default();
+        std::cmp::max(size, 1)
+    }
+}
+
+impl ChardevNotifyDevice for MuxChardev {
+    fn chardev_notify(&mut self, status: ChardevStatus) {
+        for dev in self.frontends.iter().filter_map(|f| f.dev.as_ref()) {
+            dev.lock().unwrap().chardev_notify(status);
+        }
+    }
+}
+
+/// Output of the frontends of multiplexed chardev. The backend is locked instead of
+/// the multiplexer, as frontends may be locked by the multiplexer when receiving input.
+struct MuxOutput {
+    backend: Arc<Mutex<Chardev>>,
+}
+
+impl Write for MuxOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &self.backend.lock().unwrap().output {
+            Some(output) => output.lock().unwrap().write(buf),
+            // Backend is not connected, the output is discarded.
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &self.backend.lock().unwrap().output {
+            Some(output) => output.lock().unwrap().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl CommunicatOutInterface for MuxOutput {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_manager::config::ChardevType;
+
+    #[derive(Default)]
+    struct TestReceiver {
+        data: Vec<u8>,
+    }
+
+    impl InputReceiver for TestReceiver {
+        fn receive(&mut self, buffer: &[u8]) {
+            self.data.extend_from_slice(buffer);
+        }
+
+        fn remain_size(&mut self) -> usize {
+            8
+        }
+    }
+
+    #[test]
+    fn test_mux_chardev_input() {
+        let path = "/tmp/test_mux_chardev_input.out".to_string();
+        let logfile = "/tmp/test_mux_chardev_input.log".to_string();
+        let cfg = ChardevConfig {
+            id: "mux_test".to_string(),
+            backend: ChardevType::File(path.clone()),
+            mux: false,
+            logfile: Some(logfile.clone()),
+            logappend: false,
+        };
+        let (mux, index0) = MuxChardev::attach(cfg.clone()).unwrap();
+        let (_, index1) = MuxChardev::attach(cfg).unwrap();
+        assert_eq!((index0, index1), (0, 1));
+
+        let console = Arc::new(Mutex::new(TestReceiver::default()));
+        let monitor = Arc::new(Mutex::new(TestReceiver::default()));
+        let mut locked_mux = mux.lock().unwrap();
+        locked_mux.set_receiver(index0, console.clone());
+        locked_mux.set_receiver(index1, monitor.clone());
+
+        // Input to the focused frontend, C-a C-a sends C-a.
+        locked_mux.receive(b"ab\x01\x01c");
+        assert_eq!(console.lock().unwrap().data, b"ab\x01c");
+        // Input exceeding the buffer of frontend is dropped.
+        console.lock().unwrap().data.clear();
+        locked_mux.receive(b"0123456789");
+        assert_eq!(console.lock().unwrap().data, b"01234567");
+
+        // Switch focus in the middle of input, the escape character may be split.
+        console.lock().unwrap().data.clear();
+        locked_mux.receive(b"12\x01");
+        locked_mux.receive(b"c34");
+        assert_eq!(console.lock().unwrap().data, b"12");
+        assert_eq!(monitor.lock().unwrap().data, b"34");
+        locked_mux.receive(b"\x01c5");
+        assert_eq!(console.lock().unwrap().data, b"125");
+
+        // Help is written to the backend, unknown commands are ignored.
+        locked_mux.receive(b"\x01h\x01z");
+        assert_eq!(console.lock().unwrap().data, b"125");
+        let output = std::fs::read(&path).unwrap();
+        assert_eq!(output, MUX_HELP);
+        // Output of the backend is logged.
+        assert_eq!(std::fs::read(&logfile).unwrap(), MUX_HELP);
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(logfile).unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use once_cell::sync::Lazy;
+
+use crate::chardev::{CommunicatOutInterface, InputReceiver};
+use machine_manager::qmp::qmp_schema::DataFormat;
+
+/// Ringbuf chardevs, indexed by chardev id. The ring buffers are owned by the chardevs,
+/// so that they are freed with the chardevs.
+static RINGBUF_CHARDEVS: Lazy<Mutex<HashMap<String, Weak<Mutex<Ringbuf>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Ring buffer which keeps the latest output of the frontend, so that it can be
+/// fetched by `ringbuf-read` without attaching to the chardev. The oldest data is
+/// overwritten when the buffer is full.
+pub struct Ringbuf {
+    data: VecDeque<u8>,
+    size: usize,
+    /// Frontend which receives the input written by `ringbuf-write`.
+    receiver: Option<Arc<Mutex<dyn InputReceiver>>>,
+}
+
+impl Ringbuf {
+    /// Create a ring buffer for the chardev.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the chardev.
+    /// * `size` - Size of the ring buffer.
+    pub fn register(id: &str, size: u64) -> Result<Arc<Mutex<Self>>> {
+        let mut ringbufs = RINGBUF_CHARDEVS.lock().unwrap();
+        if ringbufs.get(id).map_or(false, |r| r.strong_count() > 0) {
+            bail!("Ringbuf chardev {} has been realized", id);
+        }
+        let ringbuf = Arc::new(Mutex::new(Ringbuf {
+            data: VecDeque::new(),
+            size: size as usize,
+            receiver: None,
+        }));
+        ringbufs.insert(id.to_string(), Arc::downgrade(&ringbuf));
+        Ok(ringbuf)
+    }
+
+    /// Remove the ring buffer of the chardev, it's called when the chardev is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the chardev.
+    pub fn unregister(id: &str) {
+        RINGBUF_CHARDEVS.lock().unwrap().remove(id);
+    }
+
+    pub fn set_receiver(&mut self, receiver: Arc<Mutex<dyn InputReceiver>>) {
+        self.receiver = Some(receiver);
+    }
+
+    fn get(id: &str) -> Result<Arc<Mutex<Self>>> {
+        RINGBUF_CHARDEVS
+            .lock()
+            .unwrap()
+            .get(id)
+            .and_then(Weak::upgrade)
+            .with_context(|| format!("Ringbuf chardev {} not found", id))
+    }
+}
+
+impl Write for Ringbuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = buf.len().saturating_sub(self.size);
+        self.data.extend(&buf[start..]);
+        let overflow = self.data.len().saturating_sub(self.size);
+        self.data.drain(..overflow);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl CommunicatOutInterface for Ringbuf {}
+
+/// Read and remove at most `size` bytes from the ringbuf chardev.
+///
+/// # Arguments
+///
+/// * `id` - Id of the chardev.
+/// * `size` - Max bytes to read.
+/// * `format` - Encoding of the returned data. Invalid UTF-8 sequences are replaced
+///   with U+FFFD in utf8 format.
+pub fn ringbuf_read(id: &str, size: u64, format: DataFormat) -> Result<String> {
+    let ringbuf = Ringbuf::get(id)?;
+    let mut locked_ringbuf = ringbuf.lock().unwrap();
+    let len = std::cmp::min(size, locked_ringbuf.data.len() as u64) as usize;
+    let data: Vec<u8> = locked_ringbuf.data.drain(..len).collect();
+    Ok(match format {
+        DataFormat::Utf8 => String::from_utf8_lossy(&data).into_owned(),
+        DataFormat::Base64 => STANDARD.encode(data),
+    })
+}
+
+/// Write data to the frontend of the ringbuf chardev as input.
+///
+/// # Arguments
+///
+/// * `id` - Id of the chardev.
+/// * `data` - Data to write.
+/// * `format` - Encoding of the data.
+pub fn ringbuf_write(id: &str, data: &str, format: DataFormat) -> Result<()> {
+    let data = match format {
+        DataFormat::Utf8 => data.as_bytes().to_vec(),
+        DataFormat::Base64 => STANDARD
+            .decode(data)
+            .with_context(|| "Invalid base64 data")?,
+    };
+    // Receiver is cloned before used, as it may write to the ring buffer.
+    let receiver = Ringbuf::get(id)?
+        .lock()
+        .unwrap()
+        .receiver
+        .clone()
+        .with_context(|| format!("No frontend is attached to ringbuf chardev {}", id))?;
+
+    let mut locked_receiver = receiver.lock().unwrap();
+    let mut written = 0;
+    while written < data.len() {
+        let len = std::cmp::min(locked_receiver.remain_size(), data.len() - written);
+        if len == 0 {
+            bail!(
+                "Frontend of ringbuf chardev {} is busy, only {} bytes are written",
+                id,
+                written
+            );
+        }
+        locked_receiver.receive(&data[written..written + len]);
+        written += len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct TestReceiver {
+        data: Vec<u8>,
+    }
+
+    impl InputReceiver for TestReceiver {
+        fn receive(&mut self, buffer: &[u8]) {
+            self.data.extend_from_slice(buffer);
+        }
+
+        fn remain_size(&mut self) -> usize {
+            4
+        }
+    }
+
+    #[test]
+    fn test_ringbuf_chardev() {
+        let ringbuf = Ringbuf::register("ringbuf_test", 8).unwrap();
+        assert!(Ringbuf::register("ringbuf_test", 8).is_err());
+        assert!(ringbuf_read("ringbuf_none", 8, DataFormat::Utf8).is_err());
+
+        // The oldest output is overwritten when the buffer is full.
+        let mut locked_ringbuf = ringbuf.lock().unwrap();
+        locked_ringbuf.write_all(b"hello").unwrap();
+        locked_ringbuf.write_all(b" world").unwrap();
+        drop(locked_ringbuf);
+        assert_eq!(
+            ringbuf_read("ringbuf_test", 3, DataFormat::Utf8).unwrap(),
+            "lo "
+        );
+        assert_eq!(
+            ringbuf_read("ringbuf_test", 16, DataFormat::Base64).unwrap(),
+            STANDARD.encode("world")
+        );
+        assert_eq!(
+            ringbuf_read("ringbuf_test", 16, DataFormat::Utf8).unwrap(),
+            ""
+        );
+        ringbuf.lock().unwrap().write_all(b"0123456789").unwrap();
+        assert_eq!(
+            ringbuf_read("ringbuf_test", 16, DataFormat::Utf8).unwrap(),
+            "23456789"
+        );
+
+        // Input is delivered to the frontend.
+        assert!(ringbuf_write("ringbuf_test", "ls", DataFormat::Utf8).is_err());
+        let receiver = Arc::new(Mutex::new(TestReceiver::default()));
+        ringbuf.lock().unwrap().set_receiver(receiver.clone());
+        ringbuf_write("ringbuf_test", "ls -l\r", DataFormat::Utf8).unwrap();
+        ringbuf_write("ringbuf_test", &STANDARD.encode("\x03"), DataFormat::Base64).unwrap();
+        assert_eq!(receiver.lock().unwrap().data, b"ls -l\r\x03");
+        assert!(ringbuf_write("ringbuf_test", "!", DataFormat::Base64).is_err());
+
+        // The id can be reused after the chardev is removed.
+        drop(ringbuf);
+        assert!(ringbuf_read("ringbuf_test", 8, DataFormat::Utf8).is_err());
+        let ringbuf = Ringbuf::register("ringbuf_test", 8).unwrap();
+        Ringbuf::unregister("ringbuf_test");
+        assert!(ringbuf_read("ringbuf_test", 8, DataFormat::Utf8).is_err());
+        drop(ringbuf);
+    }
+}
@@ -17,6 +17,7 @@ use kvm_bindings::{
 use kvm_ioctls::{Cap, Kvm, VcpuFd};
 
 use super::core_regs::Result;
+use super::sve::{SVE_VLS_WORDS, SVE_VQ_BITS};
 use machine_manager::config::{CpuConfig, PmuConfig};
 
 // Capabilities for ARM cpu.
@@ -49,15 +50,29 @@ impl ArmCPUCaps {
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ArmCPUFeatures {
     pub pmu: bool,
+    pub sve: bool,
+    /// Bitmap of SVE vector lengths in quadwords, bit `n` stands for `(n + 1) * 128` bits.
+    /// All the lengths supported by host are used if it's empty.
+    pub sve_vls: [u64; SVE_VLS_WORDS],
+    pub steal_time: bool,
 }
 
 impl From<&CpuConfig> for ArmCPUFeatures {
     fn from(conf: &CpuConfig) -> Self {
+        let mut sve_vls = [0_u64; SVE_VLS_WORDS];
+        for vl in conf.sve.vector_lengths.iter() {
+            let vq = (vl / SVE_VQ_BITS - 1) as usize;
+            sve_vls[vq / 64] |= 1 << (vq % 64);
+        }
+
         Self {
             pmu: match &conf.pmu {
                 PmuConfig::On => true,
                 PmuConfig::Off => false,
             },
+            sve: conf.sve.enabled,
+            sve_vls,
+            steal_time: conf.steal_time,
         }
     }
 }
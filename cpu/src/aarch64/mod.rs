@@ -12,6 +12,7 @@
 
 pub mod caps;
 mod core_regs;
+mod sve;
 
 pub use self::caps::{ArmCPUCaps, ArmCPUFeatures};
 
@@ -21,18 +22,19 @@ use std::{
     sync::{Arc, Mutex},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use kvm_bindings::{
     kvm_device_attr, kvm_mp_state, kvm_regs, kvm_vcpu_events, kvm_vcpu_init, RegList,
     KVM_ARM_VCPU_PMU_V3_CTRL, KVM_ARM_VCPU_PMU_V3_INIT, KVM_ARM_VCPU_PMU_V3_IRQ,
-    KVM_MP_STATE_RUNNABLE, KVM_MP_STATE_STOPPED,
+    KVM_ARM_VCPU_PVTIME_CTRL, KVM_ARM_VCPU_PVTIME_IPA, KVM_MP_STATE_RUNNABLE, KVM_MP_STATE_STOPPED,
 };
 use kvm_ioctls::{DeviceFd, VcpuFd};
 
 use self::caps::CpregListEntry;
 use self::core_regs::{get_core_regs, set_core_regs};
-use crate::CPU;
-use hypervisor::kvm::KVM_FDS;
+use self::sve::{init_sve, SveState};
+use crate::{CpuLifecycleState, CPU};
+use hypervisor::kvm::{sve_supported, KVM_FDS};
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -66,6 +68,10 @@ const KVM_MAX_CPREG_ENTRIES: usize = 500;
 pub const PPI_BASE: u32 = 16;
 pub const PMU_INTR: u32 = 7;
 
+/// Size of the stolen time structure of each vCPU in PV time region.
+/// See: https://developer.arm.com/documentation/den0057/a/
+pub const PVTIME_VCPU_SIZE: u64 = 64;
+
 /// AArch64 CPU booting configure information
 ///
 /// Before jumping into the kernel, primary CPU general-purpose
@@ -119,6 +125,10 @@ pub struct ArmCPUState {
     features: ArmCPUFeatures,
     /// Virtual timer count.
     vtimer_cnt: u64,
+    /// Vcpu SVE registers, valid if SVE is enabled.
+    sve: SveState,
+    /// Guest physical address of stolen time structure, 0 if steal time is disabled.
+    pvtime_ipa: u64,
 }
 
 impl ArmCPUState {
@@ -155,6 +165,8 @@ impl ArmCPUState {
         self.cpreg_len = locked_cpu_state.cpreg_len;
         self.cpreg_list = locked_cpu_state.cpreg_list;
         self.features = locked_cpu_state.features;
+        self.sve = locked_cpu_state.sve;
+        self.pvtime_ipa = locked_cpu_state.pvtime_ipa;
     }
 
     /// Set register value in `ArmCPUState` according to `boot_config`.
@@ -169,11 +181,9 @@ impl ArmCPUState {
         boot_config: &ArmCPUBootConfig,
         vcpu_config: &ArmCPUFeatures,
     ) -> Result<()> {
-        KVM_FDS
-            .load()
-            .vm_fd
-            .as_ref()
-            .unwrap()
+        let kvm_fds = KVM_FDS.load();
+        let vm_fd = kvm_fds.vm_fd.as_ref().unwrap();
+        vm_fd
             .get_preferred_target(&mut self.kvi)
             .with_context(|| "Failed to get kvm vcpu preferred target")?;
 
@@ -190,17 +200,28 @@ impl ArmCPUState {
             self.kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_PMU_V3;
         }
 
+        // Enable SVE from config.
+        if vcpu_config.sve {
+            if !sve_supported(vm_fd) {
+                bail!("SVE is not supported by kvm");
+            }
+            self.kvi.features[0] |= 1 << kvm_bindings::KVM_ARM_VCPU_SVE;
+        }
+
         self.set_core_reg(boot_config);
 
         vcpu_fd
             .vcpu_init(&self.kvi)
             .with_context(|| "Failed to init kvm vcpu")?;
+        self.features = *vcpu_config;
+        if vcpu_config.sve {
+            self.features.sve_vls = init_sve(vcpu_fd, &vcpu_config.sve_vls)
+                .with_context(|| format!("Failed to init SVE for CPU {}", self.apic_id))?;
+        }
         self.mpidr = vcpu_fd
             .get_one_reg(SYS_MPIDR_EL1)
             .with_context(|| "Failed to get mpidr")? as u64;
 
-        self.features = *vcpu_config;
-
         Ok(())
     }
 
@@ -213,14 +234,30 @@ impl ArmCPUState {
         Ok(())
     }
 
+    /// Format the core registers of vcpu for diagnostics.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn dump_regs(&self, vcpu_fd: &VcpuFd) -> Result<String> {
+        let core_regs = get_core_regs(vcpu_fd, self.features.sve)
+            .with_context(|| format!("Failed to get core register for CPU {}", self.apic_id))?;
+        Ok(format!("{:#x?}", core_regs))
+    }
+
     /// Reset register value in `Kvm` with `ArmCPUState`.
     ///
     /// # Arguments
     ///
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     pub fn reset_vcpu(&self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
-        set_core_regs(vcpu_fd, self.core_regs)
+        set_core_regs(vcpu_fd, self.core_regs, self.features.sve)
             .with_context(|| format!("Failed to set core register for CPU {}", self.apic_id))?;
+        if self.features.sve {
+            self.sve
+                .set_regs(vcpu_fd)
+                .with_context(|| format!("Failed to set SVE register for CPU {}", self.apic_id))?;
+        }
         vcpu_fd
             .set_mp_state(self.mp_state)
             .with_context(|| format!("Failed to set mpstate for CPU {}", self.apic_id))?;
@@ -323,13 +360,52 @@ impl CPU {
 
         Ok(())
     }
+
+    /// Check whether kvm supports PV time for ARM CPU.
+    pub fn pvtime_supported(&self) -> bool {
+        let pvtime_attr = kvm_device_attr {
+            group: KVM_ARM_VCPU_PVTIME_CTRL,
+            attr: u64::from(KVM_ARM_VCPU_PVTIME_IPA),
+            addr: 0,
+            flags: 0,
+        };
+        self.fd.has_device_attr(&pvtime_attr).is_ok()
+    }
+
+    /// Init PV time for ARM CPU, kvm reports stolen time of the vCPU to guest
+    /// at `ipa` afterwards. It can only be called once for each vCPU.
+    ///
+    /// # Arguments
+    ///
+    /// * `ipa` - Guest physical address of stolen time structure of the vCPU.
+    pub fn init_pvtime(&self, ipa: u64) -> Result<()> {
+        self.set_pvtime_ipa(ipa)?;
+        self.arch_cpu.lock().unwrap().pvtime_ipa = ipa;
+        Ok(())
+    }
+
+    fn set_pvtime_ipa(&self, ipa: u64) -> Result<()> {
+        let pvtime_attr = kvm_device_attr {
+            group: KVM_ARM_VCPU_PVTIME_CTRL,
+            attr: u64::from(KVM_ARM_VCPU_PVTIME_IPA),
+            addr: &ipa as *const u64 as u64,
+            flags: 0,
+        };
+        self.fd
+            .set_device_attr(&pvtime_attr)
+            .with_context(|| format!("Failed to init PV time for CPU {}", self.id))
+    }
 }
 
 impl StateTransfer for CPU {
     fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
         let mut cpu_state_locked = self.arch_cpu.lock().unwrap();
 
-        cpu_state_locked.core_regs = get_core_regs(&self.fd)?;
+        let sve = cpu_state_locked.features.sve;
+        cpu_state_locked.core_regs = get_core_regs(&self.fd, sve)?;
+        if sve {
+            cpu_state_locked.sve.get_regs(&self.fd)?;
+        }
         if self.caps.mp_state {
             let mut mp_state = self.fd.get_mp_state()?;
             if mp_state.mp_state != KVM_MP_STATE_STOPPED {
@@ -338,6 +414,8 @@ impl StateTransfer for CPU {
             cpu_state_locked.mp_state = mp_state;
         }
 
+        let frozen_clock = !KVM_FDS.load().clock_advance_on_pause()
+            && *self.state.0.lock().unwrap() == CpuLifecycleState::Paused;
         let mut cpreg_list = RegList::new(KVM_MAX_CPREG_ENTRIES)?;
         self.fd.get_reg_list(&mut cpreg_list)?;
         cpu_state_locked.cpreg_len = 0;
@@ -348,6 +426,10 @@ impl StateTransfer for CPU {
             };
             if cpreg_entry.validate() {
                 cpreg_entry.get_cpreg(&self.fd.clone())?;
+                // Virtual counter of paused VM is the one saved when VM is paused.
+                if cpreg_entry.reg_id == SYS_CNTV_CNT_EL0 && frozen_clock {
+                    cpreg_entry.value = u128::from(cpu_state_locked.vtimer_cnt);
+                }
                 cpu_state_locked.cpreg_list[index] = cpreg_entry;
                 cpu_state_locked.cpreg_len += 1;
             }
@@ -366,10 +448,20 @@ impl StateTransfer for CPU {
 
         self.fd.vcpu_init(&cpu_state.kvi)?;
 
+        if cpu_state.features.sve {
+            init_sve(&self.fd, &cpu_state.features.sve_vls)
+                .with_context(|| MigrationError::FromBytesError("Failed to init SVE."))?;
+        }
+
         if cpu_state.features.pmu {
             self.init_pmu()
                 .with_context(|| MigrationError::FromBytesError("Failed to init pmu."))?;
         }
+
+        if cpu_state.pvtime_ipa != 0 {
+            self.set_pvtime_ipa(cpu_state.pvtime_ipa)
+                .with_context(|| MigrationError::FromBytesError("Failed to init PV time."))?;
+        }
         Ok(())
     }
 
@@ -0,0 +1,150 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use kvm_bindings::{
+    KVM_REG_ARM64, KVM_REG_ARM64_SVE, KVM_REG_ARM64_SVE_FFR_BASE, KVM_REG_ARM64_SVE_PREG_BASE,
+    KVM_REG_ARM64_SVE_ZREG_BASE, KVM_REG_SIZE_U2048, KVM_REG_SIZE_U256, KVM_REG_SIZE_U512,
+};
+use kvm_ioctls::VcpuFd;
+
+use hypervisor::kvm::{finalize_sve, get_wide_reg, set_wide_reg};
+
+/// Bits of a vector quadword.
+pub const SVE_VQ_BITS: u64 = 128;
+/// Number of u64 words in the bitmap of SVE vector lengths.
+pub const SVE_VLS_WORDS: usize = 8;
+
+const SVE_NUM_ZREGS: usize = 32;
+const SVE_NUM_PREGS: usize = 16;
+/// Z registers are accessed by kvm as 2048 bits slices.
+const SVE_ZREG_WORDS: usize = 32;
+/// P registers and FFR are accessed by kvm as 256 bits slices.
+const SVE_PREG_WORDS: usize = 4;
+
+// See: https://elixir.bootlin.com/linux/v5.6/source/arch/arm64/include/uapi/asm/kvm.h#L229
+fn sve_vls_reg() -> u64 {
+    KVM_REG_ARM64 | u64::from(KVM_REG_ARM64_SVE) | KVM_REG_SIZE_U512 | 0xffff
+}
+
+fn sve_zreg(n: usize) -> u64 {
+    KVM_REG_ARM64
+        | u64::from(KVM_REG_ARM64_SVE)
+        | u64::from(KVM_REG_ARM64_SVE_ZREG_BASE)
+        | KVM_REG_SIZE_U2048
+        | ((n as u64 & 0x1f) << 5)
+}
+
+fn sve_preg(n: usize) -> u64 {
+    KVM_REG_ARM64
+        | u64::from(KVM_REG_ARM64_SVE)
+        | u64::from(KVM_REG_ARM64_SVE_PREG_BASE)
+        | KVM_REG_SIZE_U256
+        | ((n as u64 & 0xf) << 5)
+}
+
+fn sve_ffr() -> u64 {
+    KVM_REG_ARM64
+        | u64::from(KVM_REG_ARM64_SVE)
+        | u64::from(KVM_REG_ARM64_SVE_FFR_BASE)
+        | KVM_REG_SIZE_U256
+}
+
+/// Configure the vector lengths of SVE and finalize SVE for vCPU, which must be
+/// called after the vCPU is initialized with SVE feature. Returns the vector
+/// lengths which are actually used.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - Vcpu file descriptor in kvm.
+/// * `vls` - Bitmap of vector lengths, host vector lengths are used if it's empty.
+pub fn init_sve(vcpu_fd: &VcpuFd, vls: &[u64; SVE_VLS_WORDS]) -> Result<[u64; SVE_VLS_WORDS]> {
+    let mut host_vls = [0_u64; SVE_VLS_WORDS];
+    get_wide_reg(vcpu_fd, sve_vls_reg(), &mut host_vls)
+        .with_context(|| "Failed to get SVE vector lengths supported by host")?;
+
+    if vls.iter().any(|word| *word != 0) {
+        // Kvm requires that all the lengths supported by host no longer than the
+        // max length are enabled.
+        let max_vq = vls
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, word)| **word != 0)
+            .map(|(idx, word)| idx * 64 + 63 - word.leading_zeros() as usize)
+            .unwrap();
+        if vls
+            .iter()
+            .zip(host_vls.iter())
+            .any(|(vl, host)| vl & !host != 0)
+        {
+            bail!("SVE vector lengths {:?} are not supported by host", vls);
+        }
+        let mut expected_vls = [0_u64; SVE_VLS_WORDS];
+        for vq in 0..=max_vq {
+            expected_vls[vq / 64] |= host_vls[vq / 64] & (1 << (vq % 64));
+        }
+        if *vls != expected_vls {
+            bail!("SVE vector lengths must contain all the lengths supported by host up to the max one");
+        }
+        set_wide_reg(vcpu_fd, sve_vls_reg(), vls)
+            .with_context(|| "Failed to set SVE vector lengths")?;
+    }
+
+    finalize_sve(vcpu_fd)?;
+
+    let mut cur_vls = [0_u64; SVE_VLS_WORDS];
+    get_wide_reg(vcpu_fd, sve_vls_reg(), &mut cur_vls)
+        .with_context(|| "Failed to get SVE vector lengths")?;
+    Ok(cur_vls)
+}
+
+/// SVE registers of vCPU.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct SveState {
+    zregs: [[u64; SVE_ZREG_WORDS]; SVE_NUM_ZREGS],
+    pregs: [[u64; SVE_PREG_WORDS]; SVE_NUM_PREGS],
+    ffr: [u64; SVE_PREG_WORDS],
+}
+
+impl SveState {
+    /// Get SVE registers from kvm.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn get_regs(&mut self, vcpu_fd: &VcpuFd) -> Result<()> {
+        for (n, zreg) in self.zregs.iter_mut().enumerate() {
+            get_wide_reg(vcpu_fd, sve_zreg(n), zreg)?;
+        }
+        for (n, preg) in self.pregs.iter_mut().enumerate() {
+            get_wide_reg(vcpu_fd, sve_preg(n), preg)?;
+        }
+        get_wide_reg(vcpu_fd, sve_ffr(), &mut self.ffr)
+    }
+
+    /// Set SVE registers to kvm.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn set_regs(&self, vcpu_fd: &VcpuFd) -> Result<()> {
+        for (n, zreg) in self.zregs.iter().enumerate() {
+            set_wide_reg(vcpu_fd, sve_zreg(n), zreg)?;
+        }
+        for (n, preg) in self.pregs.iter().enumerate() {
+            set_wide_reg(vcpu_fd, sve_preg(n), preg)?;
+        }
+        set_wide_reg(vcpu_fd, sve_ffr(), &self.ffr)
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::{CpuLifecycleState, CPU};
+
+/// Eventfd written when a vCPU stops for guest debug, such as hitting a breakpoint.
+static DEBUG_STOP_NOTIFIER: Mutex<Option<Arc<EventFd>>> = Mutex::new(None);
+
+/// Set the eventfd to be notified when vCPUs stop for guest debug.
+///
+/// # Arguments
+///
+/// * `notifier` - The eventfd of debugger, `None` means no debugger.
+pub fn set_debug_stop_notifier(notifier: Option<Arc<EventFd>>) {
+    *DEBUG_STOP_NOTIFIER.lock().unwrap() = notifier;
+}
+
+impl CPU {
+    /// Whether this `CPU` has stopped for guest debug since it was resumed last time.
+    pub fn debug_stopped(&self) -> bool {
+        self.debug_stopped.load(Ordering::SeqCst)
+    }
+
+    /// Stop this `CPU` because of a debug exit, the `CPU` keeps paused until
+    /// the debugger resumes it.
+    pub(crate) fn debug_stop(&self) -> Result<()> {
+        let (cpu_state, _) = &*self.state;
+        *cpu_state.lock().unwrap() = CpuLifecycleState::Paused;
+        self.debug_stopped.store(true, Ordering::SeqCst);
+
+        if let Some(notifier) = DEBUG_STOP_NOTIFIER.lock().unwrap().as_ref() {
+            notifier
+                .write(1)
+                .with_context(|| format!("Failed to notify debug stop of vcpu{}", self.id()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+use once_cell::sync::OnceCell;
+
+use crate::CPU;
+use hypervisor::kvm::VcpuStats;
+use machine_manager::qmp::qmp_schema::{ExitAddressInfo, VcpuExitStatsInfo};
+
+/// Max number of PIO and MMIO addresses tracked for each vCPU, exits of the addresses
+/// beyond it are only counted by reason.
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+/// Number of addresses causing most exits reported.
+const TOP_ADDRESSES: usize = 16;
+
+/// Reasons of the exits handled by StratoVirt.
+#[derive(Clone, Copy)]
+pub(crate) enum ExitReason {
+    IoIn,
+    IoOut,
+    MmioRead,
+    MmioWrite,
+    Hlt,
+    Shutdown,
+    #[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+    SystemEvent,
+    Debug,
+    FailEntry,
+    InternalError,
+    DirtyRingFull,
+    MemoryFault,
+    /// KVM_RUN is interrupted by signal.
+    Interrupted,
+    Other,
+}
+
+/// Names of `ExitReason` reported by qmp, in the same order.
+const EXIT_REASON_NAMES: [&str; 14] = [
+    "io-in",
+    "io-out",
+    "mmio-read",
+    "mmio-write",
+    "hlt",
+    "shutdown",
+    "system-event",
+    "debug",
+    "fail-entry",
+    "internal-error",
+    "dirty-ring-full",
+    "memory-fault",
+    "intr",
+    "other",
+];
+
+/// Exit statistics of a vCPU.
+#[derive(Default)]
+pub(crate) struct ExitStats {
+    counters: [AtomicU64; EXIT_REASON_NAMES.len()],
+    /// Number of exits keyed by (is PIO, address).
+    addresses: Mutex<HashMap<(bool, u64), u64>>,
+    /// Statistics of kvm, which is opened when it's queried at first.
+    kvm_stats: OnceCell<Option<VcpuStats>>,
+    /// Values of kvm statistics when they are reset.
+    kvm_baseline: Mutex<HashMap<String, u64>>,
+}
+
+impl ExitStats {
+    pub(crate) fn record(&self, reason: ExitReason) {
+        self.counters[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the exit of PIO or MMIO with the accessed address.
+    pub(crate) fn record_addr(&self, reason: ExitReason, addr: u64) {
+        self.record(reason);
+        let is_pio = matches!(reason, ExitReason::IoIn | ExitReason::IoOut);
+        let mut addresses = self.addresses.lock().unwrap();
+        let tracked = addresses.len();
+        match addresses.get_mut(&(is_pio, addr)) {
+            Some(count) => *count += 1,
+            None if tracked < MAX_TRACKED_ADDRESSES => {
+                addresses.insert((is_pio, addr), 1);
+            }
+            None => (),
+        }
+    }
+
+    fn snapshot(&self, cpu: &CPU, reset: bool) -> VcpuExitStatsInfo {
+        let exits = EXIT_REASON_NAMES
+            .iter()
+            .zip(self.counters.iter())
+            .map(|(name, counter)| {
+                let count = if reset {
+                    counter.swap(0, Ordering::Relaxed)
+                } else {
+                    counter.load(Ordering::Relaxed)
+                };
+                (name.to_string(), count)
+            })
+            .collect();
+
+        let mut addresses: Vec<((bool, u64), u64)> = {
+            let mut locked_addresses = self.addresses.lock().unwrap();
+            if reset {
+                locked_addresses.drain().collect()
+            } else {
+                locked_addresses.iter().map(|(k, v)| (*k, *v)).collect()
+            }
+        };
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let top_addresses = addresses
+            .into_iter()
+            .take(TOP_ADDRESSES)
+            .map(|((is_pio, addr), count)| ExitAddressInfo {
+                space: if is_pio { "pio" } else { "mmio" }.to_string(),
+                addr,
+                count,
+            })
+            .collect();
+
+        VcpuExitStatsInfo {
+            cpu_index: cpu.id(),
+            exits,
+            top_addresses,
+            kvm_stats: self.kvm_stats(cpu, reset),
+        }
+    }
+
+    fn kvm_stats(&self, cpu: &CPU, reset: bool) -> BTreeMap<String, u64> {
+        let stats = self.kvm_stats.get_or_init(|| {
+            VcpuStats::new(cpu.fd())
+                .map_err(|e| {
+                    warn!(
+                        "Statistics of kvm for vcpu{} is unavailable: {:?}",
+                        cpu.id(),
+                        e
+                    )
+                })
+                .ok()
+        });
+        let values = match stats.as_ref().map(|s| s.read()) {
+            Some(Ok(values)) => values,
+            Some(Err(e)) => {
+                warn!("Failed to read kvm statistics of vcpu{}: {:?}", cpu.id(), e);
+                return BTreeMap::new();
+            }
+            None => return BTreeMap::new(),
+        };
+
+        let mut baseline = self.kvm_baseline.lock().unwrap();
+        let result = values
+            .iter()
+            .map(|(name, value)| {
+                let base = baseline.get(name).copied().unwrap_or(0);
+                (name.clone(), value.saturating_sub(base))
+            })
+            .collect();
+        if reset {
+            *baseline = values.into_iter().collect();
+        }
+        result
+    }
+}
+
+/// Query the exit statistics of vCPUs.
+///
+/// # Arguments
+///
+/// * `cpus` - The vCPUs of VM.
+/// * `reset` - Clear the statistics after they are queried.
+pub fn query_exit_stats(cpus: &[Arc<CPU>], reset: bool) -> Vec<VcpuExitStatsInfo> {
+    cpus.iter()
+        .map(|cpu| cpu.exit_stats.snapshot(cpu, reset))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_exit_addresses() {
+        let stats = ExitStats::default();
+        for _ in 0..3 {
+            stats.record_addr(ExitReason::MmioWrite, 0xfe00_0000);
+        }
+        stats.record_addr(ExitReason::IoOut, 0x3f8);
+        stats.record_addr(ExitReason::IoIn, 0x3f8);
+        stats.record(ExitReason::Interrupted);
+
+        assert_eq!(
+            stats.counters[ExitReason::MmioWrite as usize].load(Ordering::Relaxed),
+            3
+        );
+        assert_eq!(
+            stats.counters[ExitReason::Interrupted as usize].load(Ordering::Relaxed),
+            1
+        );
+        let addresses = stats.addresses.lock().unwrap();
+        assert_eq!(addresses.get(&(false, 0xfe00_0000)), Some(&3));
+        assert_eq!(addresses.get(&(true, 0x3f8)), Some(&2));
+        drop(addresses);
+
+        for addr in 0..MAX_TRACKED_ADDRESSES as u64 {
+            stats.record_addr(ExitReason::MmioRead, 0x1000_0000 + addr);
+        }
+        let addresses = stats.addresses.lock().unwrap();
+        assert_eq!(addresses.len(), MAX_TRACKED_ADDRESSES);
+        assert_eq!(addresses.get(&(false, 0xfe00_0000)), Some(&3));
+    }
+}
@@ -27,7 +27,10 @@
 //! - `x86_64`
 //! - `aarch64`
 
+mod debug;
 pub mod error;
+mod exit_stats;
+mod throttle;
 
 #[allow(clippy::upper_case_acronyms)]
 #[cfg(target_arch = "aarch64")]
@@ -49,16 +52,27 @@ pub use aarch64::ArmCPUTopology as CPUTopology;
 pub use aarch64::PMU_INTR;
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::PPI_BASE;
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::PVTIME_VCPU_SIZE;
+pub use debug::set_debug_stop_notifier;
 pub use error::CpuError;
+pub use exit_stats::query_exit_stats;
+pub use throttle::{set_throttle_percentage, throttle_percentage, MAX_THROTTLE_PERCENTAGE};
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::caps::SgxEpcSection;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::caps::X86CPUFeatures as CPUFeatures;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUBootConfig as CPUBootConfig;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUState as ArchCPU;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::X86CPUTopology as CPUTopology;
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::{set_spec_isolation, MAX_APIC_ID};
 
 use std::cell::RefCell;
-use std::sync::atomic::{fence, AtomicBool, Ordering};
+use std::sync::atomic::{fence, AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
 use std::thread;
 use std::time::Duration;
@@ -69,12 +83,22 @@ use libc::{c_int, c_void, siginfo_t};
 use log::{error, info, warn};
 use vmm_sys_util::signal::{register_signal_handler, Killable};
 
+use address_space::{AddressSpace, GuestAddress};
+use exit_stats::{ExitReason, ExitStats};
+use hypervisor::kvm::{get_memory_fault, KVM_FDS};
 use machine_manager::config::ShutdownAction::{ShutdownActionPause, ShutdownActionPoweroff};
 use machine_manager::event;
 use machine_manager::machine::MachineInterface;
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_schema};
+use migration::{migration::Migratable, MigrationManager};
+#[cfg(not(test))]
+use util::boot_time::record_first_vcpu_run;
+use util::seccomp::spawn_class_thread;
+#[cfg(not(test))]
+use util::seccomp::{apply_thread_filter, ThreadClass};
 #[cfg(not(test))]
 use util::test_helper::is_test_enabled;
+use util::vmm_limits::join_vcpu_cgroup;
 #[cfg(target_arch = "x86_64")]
 use x86_64::caps::X86CPUCaps as CPUCaps;
 
@@ -125,7 +149,7 @@ pub trait CPUInterface {
         &self,
         boot: &CPUBootConfig,
         topology: &CPUTopology,
-        #[cfg(target_arch = "aarch64")] features: &CPUFeatures,
+        features: &CPUFeatures,
     ) -> Result<()>;
 
     /// Start `CPU` thread and run virtual CPU in kvm.
@@ -179,14 +203,27 @@ pub struct CPU {
     task: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
     /// The thread tid of this VCPU.
     tid: Arc<Mutex<Option<u64>>>,
+    /// Host CPUs the thread of this VCPU is pinned to.
+    affinity: Arc<Mutex<Option<Vec<u64>>>>,
     /// The VM combined by this VCPU.
     vm: Weak<Mutex<dyn MachineInterface + Send + Sync>>,
+    /// The system memory space, MMIO exits are dispatched to it directly without
+    /// taking the lock of VM.
+    sys_mem: Arc<AddressSpace>,
     /// The capability of VCPU.
     caps: CPUCaps,
     /// The state backup of architecture CPU right before boot.
     boot_state: Arc<Mutex<ArchCPU>>,
     /// Sync the pause state of vCPU in kvm and userspace.
     pause_signal: Arc<AtomicBool>,
+    /// Time in nanoseconds this VCPU is requested to sleep by throttling.
+    throttle_sleep_ns: Arc<AtomicU64>,
+    /// Id of the timer which throttles this VCPU periodically.
+    throttle_timer: Arc<Mutex<Option<u64>>>,
+    /// This VCPU has stopped for guest debug.
+    debug_stopped: Arc<AtomicBool>,
+    /// Statistics of the exits of this VCPU.
+    exit_stats: Arc<ExitStats>,
 }
 
 impl CPU {
@@ -198,11 +235,13 @@ impl CPU {
     /// * `id` - ID of this `CPU`.
     /// * `arch_cpu` - Architecture special `CPU` property.
     /// * `vm` - The virtual machine this `CPU` gets attached to.
+    /// * `sys_mem` - The system memory space of the virtual machine.
     pub fn new(
         vcpu_fd: Arc<VcpuFd>,
         id: u8,
         arch_cpu: Arc<Mutex<ArchCPU>>,
         vm: Arc<Mutex<dyn MachineInterface + Send + Sync>>,
+        sys_mem: Arc<AddressSpace>,
     ) -> Self {
         CPU {
             id,
@@ -211,10 +250,16 @@ impl CPU {
             state: Arc::new((Mutex::new(CpuLifecycleState::Created), Condvar::new())),
             task: Arc::new(Mutex::new(None)),
             tid: Arc::new(Mutex::new(None)),
+            affinity: Arc::new(Mutex::new(None)),
             vm: Arc::downgrade(&vm),
+            sys_mem,
             caps: CPUCaps::init_capabilities(),
             boot_state: Arc::new(Mutex::new(ArchCPU::default())),
             pause_signal: Arc::new(AtomicBool::new(false)),
+            throttle_sleep_ns: Arc::new(AtomicU64::new(0)),
+            throttle_timer: Arc::new(Mutex::new(None)),
+            debug_stopped: Arc::new(AtomicBool::new(false)),
+            exit_stats: Arc::new(ExitStats::default()),
         }
     }
 
@@ -222,11 +267,35 @@ impl CPU {
         self.arch_cpu.lock().unwrap().set(&self.boot_state);
     }
 
+    /// Reset the registers of this `CPU` to boot state, which is used before a parked
+    /// `CPU` is started again. The thread of this `CPU` must not be running.
+    pub fn reset_to_boot_state(&self) -> Result<()> {
+        self.set_to_boot_state();
+        let arch_cpu = self.arch_cpu.lock().unwrap();
+        #[cfg(target_arch = "aarch64")]
+        self.fd
+            .vcpu_init(&arch_cpu.kvi())
+            .with_context(|| format!("Failed to init vcpu{}", self.id))?;
+        arch_cpu.reset_vcpu(
+            &self.fd,
+            #[cfg(target_arch = "x86_64")]
+            &self.caps,
+        )
+    }
+
     /// Get this `CPU`'s ID.
     pub fn id(&self) -> u8 {
         self.id
     }
 
+    /// Get the VM this `CPU` attached to.
+    #[cfg(target_arch = "x86_64")]
+    fn machine(&self) -> Result<Arc<Mutex<dyn MachineInterface + Send + Sync>>> {
+        self.vm
+            .upgrade()
+            .with_context(|| CpuError::NoMachineInterface)
+    }
+
     /// Get this `CPU`'s file descriptor.
     pub fn fd(&self) -> &Arc<VcpuFd> {
         &self.fd
@@ -249,14 +318,65 @@ impl CPU {
         *data = task;
     }
 
+    /// Wait for the thread of this `CPU` to exit, it's called after `destroy`.
+    pub fn join(&self) {
+        self.set_task(None);
+    }
+
     /// Get this `CPU`'s thread id.
     pub fn tid(&self) -> u64 {
         (*self.tid.lock().unwrap()).unwrap_or(0)
     }
 
-    /// Set thread id for `CPU`.
-    fn set_tid(&self) {
-        *self.tid.lock().unwrap() = Some(util::unix::gettid());
+    /// Format the registers of this `CPU` for diagnostics. The registers of a running
+    /// vcpu can't be got by other threads until it exits to user space, so it fails
+    /// unless the vcpu is not running or it's called by the thread of the vcpu.
+    pub fn dump_regs(&self) -> Result<String> {
+        let running = match self.state.0.try_lock() {
+            Ok(state) => *state == CpuLifecycleState::Running,
+            Err(_) => true,
+        };
+        if running && self.tid() != util::unix::gettid() {
+            return Err(anyhow!("vcpu{} is running", self.id));
+        }
+        let arch_cpu = self
+            .arch_cpu
+            .try_lock()
+            .map_err(|_| anyhow!("Architecture state of vcpu{} is locked", self.id))?;
+        arch_cpu.dump_regs(&self.fd)
+    }
+
+    /// Set thread id for `CPU`, and pin the thread to the configured host CPUs.
+    fn set_tid(&self) -> Result<()> {
+        let affinity = self.affinity.lock().unwrap();
+        let tid = util::unix::gettid();
+        *self.tid.lock().unwrap() = Some(tid);
+        if let Some(cpus) = affinity.as_ref() {
+            util::unix::set_thread_affinity(tid, cpus)
+                .with_context(|| format!("Failed to pin vcpu{}", self.id))?;
+        }
+        Ok(())
+    }
+
+    /// Get the host CPUs this `CPU` is pinned to.
+    pub fn affinity(&self) -> Option<Vec<u64>> {
+        self.affinity.lock().unwrap().clone()
+    }
+
+    /// Pin the thread of this `CPU` to host CPUs. If the thread is not running yet,
+    /// the affinity is applied when it starts.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpus` - The host CPUs to run on.
+    pub fn set_affinity(&self, cpus: Vec<u64>) -> Result<()> {
+        let mut affinity = self.affinity.lock().unwrap();
+        if let Some(tid) = *self.tid.lock().unwrap() {
+            util::unix::set_thread_affinity(tid, &cpus)
+                .with_context(|| format!("Failed to pin vcpu{}", self.id))?;
+        }
+        *affinity = Some(cpus);
+        Ok(())
     }
 }
 
@@ -265,9 +385,9 @@ impl CPUInterface for CPU {
         &self,
         boot: &CPUBootConfig,
         topology: &CPUTopology,
-        #[cfg(target_arch = "aarch64")] config: &CPUFeatures,
+        config: &CPUFeatures,
     ) -> Result<()> {
-        trace_cpu_boot_config(boot);
+        util::trace::cpu_boot_config(boot);
         let (cpu_state, _) = &*self.state;
         if *cpu_state.lock().unwrap() != CpuLifecycleState::Created {
             return Err(anyhow!(CpuError::RealizeVcpu(format!(
@@ -279,12 +399,7 @@ impl CPUInterface for CPU {
         self.arch_cpu
             .lock()
             .unwrap()
-            .set_boot_config(
-                &self.fd,
-                boot,
-                #[cfg(target_arch = "aarch64")]
-                config,
-            )
+            .set_boot_config(&self.fd, boot, config)
             .with_context(|| "Failed to realize arch cpu")?;
 
         self.arch_cpu
@@ -298,11 +413,14 @@ impl CPUInterface for CPU {
     }
 
     fn resume(&self) -> Result<()> {
+        // Virtual counter is restored so that guest doesn't see the time elapsed during pause.
         #[cfg(target_arch = "aarch64")]
-        self.arch()
-            .lock()
-            .unwrap()
-            .set_virtual_timer_cnt(self.fd())?;
+        if !KVM_FDS.load().clock_advance_on_pause() {
+            self.arch()
+                .lock()
+                .unwrap()
+                .set_virtual_timer_cnt(self.fd())?;
+        }
 
         let (cpu_state_locked, cvar) = &*self.state;
         let mut cpu_state = cpu_state_locked.lock().unwrap();
@@ -313,6 +431,7 @@ impl CPUInterface for CPU {
 
         *cpu_state = CpuLifecycleState::Running;
         self.pause_signal.store(false, Ordering::SeqCst);
+        self.debug_stopped.store(false, Ordering::SeqCst);
         drop(cpu_state);
         cvar.notify_one();
         Ok(())
@@ -333,18 +452,20 @@ impl CPUInterface for CPU {
 
         let local_cpu = cpu.clone();
         let cpu_thread_worker = CPUThreadWorker::new(cpu);
-        let handle = thread::Builder::new()
-            .name(format!("CPU {}/KVM", local_cpu.id))
-            .spawn(move || {
+        let handle = spawn_class_thread(
+            thread::Builder::new().name(format!("CPU {}/KVM", local_cpu.id)),
+            move || {
                 if let Err(e) = cpu_thread_worker.handle(thread_barrier) {
                     error!(
                         "Some error occurred in cpu{} thread: {:?}",
                         cpu_thread_worker.thread_cpu.id, e
                     );
                 }
-            })
-            .with_context(|| format!("Failed to create thread for CPU {}/KVM", local_cpu.id()))?;
+            },
+        )
+        .with_context(|| format!("Failed to create thread for CPU {}/KVM", local_cpu.id()))?;
         local_cpu.set_task(Some(handle));
+        throttle::start_throttle(&local_cpu);
         Ok(())
     }
 
@@ -382,6 +503,8 @@ impl CPUInterface for CPU {
             *cpu_state.lock().unwrap() = CpuLifecycleState::Paused;
             cvar.notify_one()
         }
+        // The signal may be set by kicks of throttling before.
+        self.pause_signal.store(false, Ordering::SeqCst);
 
         match task.as_ref() {
             Some(thread) => {
@@ -403,10 +526,12 @@ impl CPUInterface for CPU {
         }
 
         #[cfg(target_arch = "aarch64")]
-        self.arch()
-            .lock()
-            .unwrap()
-            .get_virtual_timer_cnt(self.fd())?;
+        if !KVM_FDS.load().clock_advance_on_pause() {
+            self.arch()
+                .lock()
+                .unwrap()
+                .get_virtual_timer_cnt(self.fd())?;
+        }
 
         Ok(())
     }
@@ -416,6 +541,8 @@ impl CPUInterface for CPU {
         let mut cpu_state = cpu_state.lock().unwrap();
         if *cpu_state == CpuLifecycleState::Running {
             *cpu_state = CpuLifecycleState::Stopping;
+            // Wake up the VCPU thread if it's sleeping for throttling.
+            cvar.notify_one();
         } else if *cpu_state == CpuLifecycleState::Stopped
             || *cpu_state == CpuLifecycleState::Paused
         {
@@ -477,47 +604,61 @@ impl CPUInterface for CPU {
     }
 
     fn kvm_vcpu_exec(&self) -> Result<bool> {
-        let vm = self
-            .vm
-            .upgrade()
-            .with_context(|| CpuError::NoMachineInterface)?;
-
         match self.fd.run() {
             Ok(run) => match run {
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoIn(addr, data) => {
+                    self.exit_stats
+                        .record_addr(ExitReason::IoIn, u64::from(addr));
+                    let vm = self.machine()?;
                     vm.lock().unwrap().pio_in(u64::from(addr), data);
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::IoOut(addr, data) => {
+                    self.exit_stats
+                        .record_addr(ExitReason::IoOut, u64::from(addr));
                     #[cfg(feature = "boot_time")]
                     capture_boot_signal(addr as u64, data);
 
+                    let vm = self.machine()?;
                     vm.lock().unwrap().pio_out(u64::from(addr), data);
                 }
-                VcpuExit::MmioRead(addr, data) => {
-                    vm.lock().unwrap().mmio_read(addr, data);
+                // MMIO exits of all vCPUs are served by the lock-free flat view of
+                // system memory, so they don't contend on the lock of VM.
+                VcpuExit::MmioRead(addr, mut data) => {
+                    self.exit_stats.record_addr(ExitReason::MmioRead, addr);
+                    let length = data.len() as u64;
+                    let _ = self.sys_mem.read(&mut data, GuestAddress(addr), length);
                 }
-                VcpuExit::MmioWrite(addr, data) => {
+                VcpuExit::MmioWrite(addr, mut data) => {
+                    self.exit_stats.record_addr(ExitReason::MmioWrite, addr);
                     #[cfg(all(target_arch = "aarch64", feature = "boot_time"))]
                     capture_boot_signal(addr, data);
 
-                    vm.lock().unwrap().mmio_write(addr, data);
+                    let count = data.len() as u64;
+                    let _ = self.sys_mem.write(&mut data, GuestAddress(addr), count);
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Hlt => {
+                    self.exit_stats.record(ExitReason::Hlt);
                     info!("Vcpu{} received KVM_EXIT_HLT signal", self.id());
                     return Err(anyhow!(CpuError::VcpuHltEvent(self.id())));
                 }
                 #[cfg(target_arch = "x86_64")]
                 VcpuExit::Shutdown => {
+                    self.exit_stats.record(ExitReason::Shutdown);
                     info!("Vcpu{} received an KVM_EXIT_SHUTDOWN signal", self.id());
-                    self.guest_shutdown()?;
+                    // Triple fault resets the machine as real hardware does, the vCPU
+                    // can't run anymore and keeps paused until the machine is reset.
+                    let (cpu_state, _) = &*self.state;
+                    *cpu_state.lock().unwrap() = CpuLifecycleState::Paused;
+                    self.guest_reset()?;
 
-                    return Ok(false);
+                    return Ok(true);
                 }
                 #[cfg(target_arch = "aarch64")]
                 VcpuExit::SystemEvent(event, flags) => {
+                    self.exit_stats.record(ExitReason::SystemEvent);
                     if event == kvm_bindings::KVM_SYSTEM_EVENT_SHUTDOWN {
                         info!(
                             "Vcpu{} received an KVM_SYSTEM_EVENT_SHUTDOWN signal",
@@ -544,7 +685,13 @@ impl CPUInterface for CPU {
                     }
                     return Ok(false);
                 }
+                VcpuExit::Debug(_) => {
+                    self.exit_stats.record(ExitReason::Debug);
+                    self.debug_stop()?;
+                    return Ok(true);
+                }
                 VcpuExit::FailEntry(reason, cpuid) => {
+                    self.exit_stats.record(ExitReason::FailEntry);
                     info!(
                         "Vcpu{} received KVM_EXIT_FAIL_ENTRY signal. the vcpu could not be run due to unknown reasons({})",
                         cpuid, reason
@@ -552,10 +699,18 @@ impl CPUInterface for CPU {
                     return Ok(false);
                 }
                 VcpuExit::InternalError => {
+                    self.exit_stats.record(ExitReason::InternalError);
                     info!("Vcpu{} received KVM_EXIT_INTERNAL_ERROR signal", self.id());
                     return Ok(false);
                 }
+                VcpuExit::Unsupported(kvm_bindings::KVM_EXIT_DIRTY_RING_FULL) => {
+                    self.exit_stats.record(ExitReason::DirtyRingFull);
+                    MigrationManager::harvest_dirty_ring(u32::from(self.id())).with_context(
+                        || format!("Failed to harvest dirty ring of vcpu{}", self.id()),
+                    )?;
+                }
                 r => {
+                    self.exit_stats.record(ExitReason::Other);
                     return Err(anyhow!(CpuError::VcpuExitReason(
                         self.id(),
                         format!("{:?}", r)
@@ -566,8 +721,20 @@ impl CPUInterface for CPU {
                 match e.errno() {
                     libc::EAGAIN => {}
                     libc::EINTR => {
+                        self.exit_stats.record(ExitReason::Interrupted);
                         self.fd.set_kvm_immediate_exit(0);
                     }
+                    libc::EFAULT if KVM_FDS.load().private_memory_enabled() => {
+                        self.exit_stats.record(ExitReason::MemoryFault);
+                        let fault = get_memory_fault(&self.fd)
+                            .with_context(|| CpuError::UnhandledKvmExit(self.id()))?;
+                        KVM_FDS
+                            .load()
+                            .convert_memory(fault.gpa, fault.size, fault.private)
+                            .with_context(|| {
+                                format!("Failed to convert memory for vcpu{}", self.id())
+                            })?;
+                    }
                     _ => {
                         return Err(anyhow!(CpuError::UnhandledKvmExit(self.id())));
                     }
@@ -680,6 +847,24 @@ impl CPUThreadWorker {
         }
     }
 
+    /// Sleep for the time requested by throttling, the sleep is interrupted once
+    /// the vcpu is not running.
+    #[cfg(not(test))]
+    fn throttle_sleep(&self) {
+        let sleep_ns = self.thread_cpu.throttle_sleep_ns.swap(0, Ordering::SeqCst);
+        if sleep_ns == 0 {
+            return;
+        }
+        let (cpu_state, cvar) = &*self.thread_cpu.state;
+        let _ = cvar
+            .wait_timeout_while(
+                cpu_state.lock().unwrap(),
+                Duration::from_nanos(sleep_ns),
+                |state| *state == CpuLifecycleState::Running,
+            )
+            .unwrap();
+    }
+
     /// Handle the all events in vcpu thread.
     fn handle(&self, thread_barrier: Arc<Barrier>) -> Result<()> {
         self.init_local_thread_vcpu();
@@ -687,7 +872,12 @@ impl CPUThreadWorker {
             error!("Failed to init cpu{} signal:{:?}", self.thread_cpu.id, e);
         }
 
-        self.thread_cpu.set_tid();
+        if let Err(e) = self.thread_cpu.set_tid() {
+            error!("{:?}", e);
+        }
+        if let Err(e) = join_vcpu_cgroup() {
+            error!("{:?}", e);
+        }
 
         // The vcpu thread is going to run,
         // reset its running environment.
@@ -715,6 +905,13 @@ impl CPUThreadWorker {
                     thread::sleep(Duration::from_millis(5));
                     continue;
                 }
+                apply_thread_filter(ThreadClass::Vcpu).with_context(|| {
+                    format!(
+                        "Failed to apply seccomp filter of vcpu{}",
+                        self.thread_cpu.id
+                    )
+                })?;
+                record_first_vcpu_run();
                 if !self
                     .thread_cpu
                     .kvm_vcpu_exec()
@@ -722,6 +919,7 @@ impl CPUThreadWorker {
                 {
                     break;
                 }
+                self.throttle_sleep();
             }
             #[cfg(test)]
             {
@@ -808,6 +1006,17 @@ impl CpuTopology {
         mask[vcpu_id]
     }
 
+    /// Set online mask for a cpu, which is changed when the cpu is hot-added or
+    /// hot-removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - ID of vcpu.
+    /// * `mask` - Online mask, `1` means online and `0` means offline.
+    pub fn set_mask(&self, vcpu_id: usize, mask: u8) {
+        self.online_mask.lock().unwrap()[vcpu_id] = mask;
+    }
+
     /// Get single cpu topology for vcpu, return this vcpu's `socket-id`,
     /// `core-id` and `thread-id`.
     ///
@@ -823,6 +1032,18 @@ impl CpuTopology {
         (socketid, dieid, clusterid, coreid, threadid)
     }
 
+    /// Get the APIC ID of vcpu, which is also the id of vcpu in kvm.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - ID of vcpu.
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_apic_id(&self, vcpu_id: u8) -> u32 {
+        CPUTopology::new()
+            .set_topology((self.threads, self.cores, self.dies))
+            .apic_id(vcpu_id)
+    }
+
     pub fn get_topo_instance_for_qmp(&self, cpu_index: usize) -> qmp_schema::CpuInstanceProperties {
         let (socketid, _dieid, _clusterid, coreid, threadid) = self.get_topo_item(cpu_index);
         qmp_schema::CpuInstanceProperties {
@@ -838,10 +1059,6 @@ impl CpuTopology {
     }
 }
 
-fn trace_cpu_boot_config(cpu_boot_config: &CPUBootConfig) {
-    util::ftrace!(trace_CPU_boot_config, "{:#?}", cpu_boot_config);
-}
-
 /// Capture the boot signal that trap from guest kernel, and then record
 /// kernel boot timestamp.
 #[cfg(feature = "boot_time")]
@@ -861,7 +1078,8 @@ mod tests {
     use std::time::Duration;
 
     use super::*;
-    use hypervisor::kvm::{KVMFds, KVM_FDS};
+    use address_space::Region;
+    use hypervisor::kvm::KVMFds;
     use machine_manager::machine::{
         KvmVmState, MachineAddressInterface, MachineInterface, MachineLifecycle,
     };
@@ -937,6 +1155,8 @@ mod tests {
         KVM_FDS.store(Arc::new(kvm_fds));
 
         let vm = Arc::new(Mutex::new(TestVm::new()));
+        let sys_mem =
+            AddressSpace::new(Region::init_container_region(u64::MAX, "sysmem"), "sysmem").unwrap();
         let cpu = CPU::new(
             Arc::new(
                 KVM_FDS
@@ -950,6 +1170,7 @@ mod tests {
             0,
             Arc::new(Mutex::new(ArchCPU::default())),
             vm.clone(),
+            sys_mem,
         );
         let (cpu_state, _) = &*cpu.state;
         assert_eq!(*cpu_state.lock().unwrap(), CpuLifecycleState::Created);
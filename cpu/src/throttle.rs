@@ -0,0 +1,122 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::error;
+
+use crate::{CPUInterface, CpuLifecycleState, CPU};
+use machine_manager::event_loop::EventLoop;
+
+/// Max percentage of time that vCPUs are forced to sleep.
+pub const MAX_THROTTLE_PERCENTAGE: u8 = 99;
+/// vCPU runs for one time slice between two forced sleeps.
+const THROTTLE_TIMESLICE_NS: u64 = 10_000_000;
+
+/// Percentage of time that vCPUs are forced to sleep, 0 means no throttling.
+static THROTTLE_PERCENTAGE: AtomicU8 = AtomicU8::new(0);
+
+/// Get the percentage of time that vCPUs are forced to sleep.
+pub fn throttle_percentage() -> u8 {
+    THROTTLE_PERCENTAGE.load(Ordering::SeqCst)
+}
+
+/// Limit the CPU time consumed by vCPUs to (100 - `percentage`)%, vCPUs started
+/// later are throttled too.
+///
+/// # Arguments
+///
+/// * `cpus` - The running vCPUs of VM.
+/// * `percentage` - Percentage of time that vCPUs are forced to sleep, 0 stops throttling.
+pub fn set_throttle_percentage(cpus: &[Arc<CPU>], percentage: u8) -> Result<()> {
+    if percentage > MAX_THROTTLE_PERCENTAGE {
+        bail!(
+            "Throttle percentage {} exceeds the max value {}",
+            percentage,
+            MAX_THROTTLE_PERCENTAGE
+        );
+    }
+    THROTTLE_PERCENTAGE.store(percentage, Ordering::SeqCst);
+    for cpu in cpus {
+        start_throttle(cpu);
+    }
+    Ok(())
+}
+
+/// Get the sleep time in each throttle period.
+fn throttle_sleep_ns(percentage: u8) -> u64 {
+    THROTTLE_TIMESLICE_NS * u64::from(percentage) / u64::from(100 - percentage)
+}
+
+/// Start the throttle timer of vCPU if vCPUs are throttled and the timer is not
+/// started yet.
+pub(crate) fn start_throttle(cpu: &Arc<CPU>) {
+    let percentage = throttle_percentage();
+    if percentage == 0 {
+        return;
+    }
+    let mut timer = cpu.throttle_timer.lock().unwrap();
+    if timer.is_some() {
+        return;
+    }
+    let ctx = match EventLoop::get_ctx(None) {
+        Some(ctx) => ctx,
+        None => return,
+    };
+    let cloned_cpu = cpu.clone();
+    let period = THROTTLE_TIMESLICE_NS + throttle_sleep_ns(percentage);
+    *timer = Some(ctx.timer_add(
+        Box::new(move || throttle_tick(&cloned_cpu)),
+        Duration::from_nanos(period),
+    ));
+}
+
+/// Force the vCPU to sleep once, then arm the timer for the next period.
+fn throttle_tick(cpu: &Arc<CPU>) {
+    *cpu.throttle_timer.lock().unwrap() = None;
+    let percentage = throttle_percentage();
+    if percentage == 0 {
+        return;
+    }
+    let state = *cpu.state.0.lock().unwrap();
+    match state {
+        CpuLifecycleState::Running => {
+            cpu.throttle_sleep_ns
+                .store(throttle_sleep_ns(percentage), Ordering::SeqCst);
+            if let Err(e) = cpu.kick() {
+                error!("Failed to throttle vcpu{}: {:?}", cpu.id(), e);
+            }
+        }
+        CpuLifecycleState::Paused => {}
+        _ => return,
+    }
+    start_throttle(cpu);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle_sleep() {
+        assert_eq!(throttle_sleep_ns(0), 0);
+        assert_eq!(throttle_sleep_ns(50), THROTTLE_TIMESLICE_NS);
+        assert_eq!(throttle_sleep_ns(75), 3 * THROTTLE_TIMESLICE_NS);
+        assert_eq!(throttle_sleep_ns(99), 99 * THROTTLE_TIMESLICE_NS);
+
+        assert!(set_throttle_percentage(&[], 100).is_err());
+        assert_eq!(throttle_percentage(), 0);
+    }
+}
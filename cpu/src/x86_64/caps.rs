@@ -14,6 +14,8 @@ use kvm_bindings::{kvm_msr_entry, Msrs};
 use kvm_ioctls::{Cap, Kvm};
 use vmm_sys_util::fam::Error;
 
+use machine_manager::config::{CpuConfig, MAX_SGX_EPC_SECTIONS};
+
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/msr-index.h#L558
 const MSR_IA32_MISC_ENABLE: ::std::os::raw::c_uint = 0x1a0;
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/arch/x86/include/asm/msr-index.h#L597
@@ -69,3 +71,62 @@ impl X86CPUCaps {
         Msrs::from_entries(&entry_vec)
     }
 }
+
+/// IBRS/IBPB/STIBP speculation control features.
+pub(crate) const SPEC_FEATURE_SPEC_CTRL: u32 = 1 << 0;
+/// Speculative store bypass disable.
+pub(crate) const SPEC_FEATURE_SSBD: u32 = 1 << 1;
+/// VERW clears CPU buffers against MDS.
+pub(crate) const SPEC_FEATURE_MD_CLEAR: u32 = 1 << 2;
+
+/// SGX EPC section of guest, which is enumerated by CPUID leaf 0x12.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SgxEpcSection {
+    /// Guest physical address of the section.
+    pub base: u64,
+    /// Size of the section, 0 means the section is not used.
+    pub size: u64,
+}
+
+/// CPU features of x86 configured by user.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct X86CPUFeatures {
+    /// Fixed TSC frequency in kHz, 0 means host TSC frequency is used.
+    pub tsc_khz: u32,
+    /// Whether invariant TSC is exposed.
+    pub invtsc: bool,
+    /// Whether steal time is reported.
+    pub steal_time: bool,
+    /// SGX EPC sections, SGX is exposed only if any section is used.
+    pub sgx_epc: [SgxEpcSection; MAX_SGX_EPC_SECTIONS],
+    /// Speculation control features which are required to be exposed.
+    pub spec_enabled: u32,
+    /// Speculation control features which are hidden from guest.
+    pub spec_disabled: u32,
+}
+
+impl From<&CpuConfig> for X86CPUFeatures {
+    fn from(conf: &CpuConfig) -> Self {
+        let spec_features = [
+            (conf.spec_ctrl, SPEC_FEATURE_SPEC_CTRL),
+            (conf.ssbd, SPEC_FEATURE_SSBD),
+            (conf.md_clear, SPEC_FEATURE_MD_CLEAR),
+        ];
+        let spec_mask = |enabled: bool| {
+            spec_features
+                .iter()
+                .filter(|(conf, _)| *conf == Some(enabled))
+                .fold(0, |mask, (_, feature)| mask | feature)
+        };
+        Self {
+            tsc_khz: conf.tsc_frequency.map_or(0, |freq| (freq / 1000) as u32),
+            invtsc: conf.invtsc,
+            steal_time: conf.steal_time,
+            sgx_epc: Default::default(),
+            spec_enabled: spec_mask(true),
+            spec_disabled: spec_mask(false),
+        }
+    }
+}
@@ -24,9 +24,14 @@ use kvm_bindings::{
     KVM_MP_STATE_UNINITIALIZED,
 };
 use kvm_ioctls::{Kvm, VcpuFd};
+use log::warn;
 
+use self::caps::{
+    SgxEpcSection, X86CPUFeatures, SPEC_FEATURE_MD_CLEAR, SPEC_FEATURE_SPEC_CTRL, SPEC_FEATURE_SSBD,
+};
 use self::cpuid::host_cpuid;
 use crate::CPU;
+use machine_manager::config::MAX_SGX_EPC_SECTIONS;
 use migration::{
     DeviceStateDesc, FieldDesc, MigrationError, MigrationHook, MigrationManager, StateTransfer,
 };
@@ -36,6 +41,37 @@ use util::byte_code::ByteCode;
 const ECX_EPB_SHIFT: u32 = 3;
 const X86_FEATURE_HYPERVISOR: u32 = 31;
 const X86_FEATURE_TSC_DEADLINE_TIMER: u32 = 24;
+const X86_FEATURE_HTT: u32 = 28;
+const X86_FEATURE_INVTSC: u32 = 8;
+/// SGX bit of CPUID.(EAX=7,ECX=0):EBX.
+const X86_FEATURE_SGX: u32 = 2;
+/// SGX launch control bit of CPUID.(EAX=7,ECX=0):ECX.
+const X86_FEATURE_SGX_LC: u32 = 30;
+/// CPUID leaf of SGX capabilities, sub-leaf 2 and above enumerate EPC sections.
+const CPUID_SGX_LEAF: u32 = 0x12;
+const SGX_EPC_FIRST_SUBLEAF: u32 = 2;
+/// Sub-leaf type of valid EPC section.
+const SGX_EPC_SUBLEAF_TYPE_SECTION: u32 = 1;
+/// EPC section has confidentiality, integrity and replay protection.
+const SGX_EPC_SECTION_PROTECTED: u32 = 1;
+const KVM_FEATURE_STEAL_TIME: u32 = 5;
+/// CPUID bits of speculation control features, in the order of feature, name,
+/// CPUID.(EAX=7,ECX=0):EDX and CPUID.80000008H:EBX.
+const SPEC_FEATURE_CPUID_BITS: &[(u32, &str, u32, u32)] = &[
+    // SPEC_CTRL and STIBP of Intel, IBPB, IBRS and STIBP of AMD.
+    (
+        SPEC_FEATURE_SPEC_CTRL,
+        "spec-ctrl",
+        1 << 26 | 1 << 27,
+        1 << 12 | 1 << 14 | 1 << 15,
+    ),
+    // SSBD of Intel, AMD_SSBD and VIRT_SSBD of AMD.
+    (SPEC_FEATURE_SSBD, "ssbd", 1 << 31, 1 << 24 | 1 << 25),
+    (SPEC_FEATURE_MD_CLEAR, "md-clear", 1 << 10, 0),
+];
+
+/// The max APIC ID of vcpu, 0xff is the broadcast ID of xAPIC.
+pub const MAX_APIC_ID: u8 = 0xfe;
 
 const MSR_LIST: &[u32] = &[
     0x0174,      // MSR_IA32_SYSENTER_CS
@@ -49,6 +85,7 @@ const MSR_LIST: &[u32] = &[
     0x0010,      // MSR_IA32_TSC,
     0x01a0,      // MSR_IA32_MISC_ENABLE,
     0x2ff,       // MSR_MTRRdefType
+    0x4b56_4d03, // MSR_KVM_STEAL_TIME, guest address of steal time structure
 ];
 
 const MSR_IA32_MISC_ENABLE: u32 = 0x01a0;
@@ -101,6 +138,39 @@ impl X86CPUTopology {
         self.dies = toplogy.2;
         self
     }
+
+    /// Get the bit offsets of core id, die id and package id in APIC ID.
+    fn apic_id_offsets(&self) -> (u32, u32, u32) {
+        let core_offset = apic_id_width(u32::from(self.threads));
+        let die_offset = apic_id_width(u32::from(self.cores)) + core_offset;
+        let pkg_offset = apic_id_width(u32::from(self.dies)) + die_offset;
+        (core_offset, die_offset, pkg_offset)
+    }
+
+    /// Get the APIC ID of vcpu, which encodes the socket, die, core and thread
+    /// the vcpu belongs to as the guest decodes it from CPUID.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - Index of vcpu.
+    pub fn apic_id(&self, vcpu_id: u8) -> u32 {
+        let threads = u32::from(self.threads.max(1));
+        let cores = u32::from(self.cores.max(1));
+        let dies = u32::from(self.dies.max(1));
+        let vcpu_id = u32::from(vcpu_id);
+        let (core_offset, die_offset, pkg_offset) = self.apic_id_offsets();
+
+        let thread_id = vcpu_id % threads;
+        let core_id = vcpu_id / threads % cores;
+        let die_id = vcpu_id / (threads * cores) % dies;
+        let pkg_id = vcpu_id / (threads * cores * dies);
+        pkg_id << pkg_offset | die_id << die_offset | core_id << core_offset | thread_id
+    }
+}
+
+/// Number of bits needed to hold the ids of `count` items.
+fn apic_id_width(count: u32) -> u32 {
+    32u32 - count.max(1).saturating_sub(1).leading_zeros()
 }
 
 /// The state of vCPU's register.
@@ -126,6 +196,14 @@ pub struct X86CPUState {
     xsave: kvm_xsave,
     xcrs: kvm_xcrs,
     debugregs: kvm_debugregs,
+    /// Fixed TSC frequency in kHz, 0 means host TSC frequency is used.
+    tsc_khz: u32,
+    invtsc: bool,
+    steal_time: bool,
+    /// SGX EPC sections.
+    sgx_epc: [SgxEpcSection; MAX_SGX_EPC_SECTIONS],
+    spec_enabled: u32,
+    spec_disabled: u32,
 }
 
 impl X86CPUState {
@@ -170,6 +248,12 @@ impl X86CPUState {
         self.xsave = locked_cpu_state.xsave;
         self.xcrs = locked_cpu_state.xcrs;
         self.debugregs = locked_cpu_state.debugregs;
+        self.tsc_khz = locked_cpu_state.tsc_khz;
+        self.invtsc = locked_cpu_state.invtsc;
+        self.steal_time = locked_cpu_state.steal_time;
+        self.sgx_epc = locked_cpu_state.sgx_epc;
+        self.spec_enabled = locked_cpu_state.spec_enabled;
+        self.spec_disabled = locked_cpu_state.spec_disabled;
     }
 
     /// Set register value in `X86CPUState` according to `boot_config`.
@@ -178,11 +262,19 @@ impl X86CPUState {
     ///
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     /// * `boot_config` - Boot message from boot_loader.
+    /// * `features` - CPU features configured by user.
     pub fn set_boot_config(
         &mut self,
         vcpu_fd: &Arc<VcpuFd>,
         boot_config: &X86CPUBootConfig,
+        features: &X86CPUFeatures,
     ) -> Result<()> {
+        self.tsc_khz = features.tsc_khz;
+        self.invtsc = features.invtsc;
+        self.steal_time = features.steal_time;
+        self.sgx_epc = features.sgx_epc;
+        self.spec_enabled = features.spec_enabled;
+        self.spec_disabled = features.spec_disabled;
         self.setup_lapic(vcpu_fd)?;
         self.setup_regs(boot_config);
         self.setup_sregs(vcpu_fd, boot_config)?;
@@ -204,6 +296,21 @@ impl X86CPUState {
         Ok(())
     }
 
+    /// Format the general and special registers of vcpu for diagnostics.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - Vcpu file descriptor in kvm.
+    pub fn dump_regs(&self, vcpu_fd: &VcpuFd) -> Result<String> {
+        let regs = vcpu_fd
+            .get_regs()
+            .with_context(|| format!("Failed to get regs for CPU {}", self.apic_id))?;
+        let sregs = vcpu_fd
+            .get_sregs()
+            .with_context(|| format!("Failed to get sregs for CPU {}", self.apic_id))?;
+        Ok(format!("{:#x?}\n{:#x?}", regs, sregs))
+    }
+
     /// Reset register value with `X86CPUState`.
     ///
     /// # Arguments
@@ -211,6 +318,15 @@ impl X86CPUState {
     /// * `vcpu_fd` - Vcpu file descriptor in kvm.
     /// * `caps` - Vcpu capabilities in kvm.
     pub fn reset_vcpu(&self, vcpu_fd: &Arc<VcpuFd>, caps: &caps::X86CPUCaps) -> Result<()> {
+        // TSC frequency is set before cpuid, as kvm reports it to guest through cpuid.
+        if self.tsc_khz != 0 {
+            vcpu_fd.set_tsc_khz(self.tsc_khz).with_context(|| {
+                format!(
+                    "Failed to set TSC frequency {} kHz for CPU {}, TSC scaling may be unsupported by host",
+                    self.tsc_khz, self.apic_id
+                )
+            })?;
+        }
         self.setup_cpuid(vcpu_fd)
             .with_context(|| format!("Failed to set cpuid for CPU {}", self.apic_id))?;
 
@@ -421,10 +537,56 @@ impl X86CPUState {
         Ok(())
     }
 
+    /// Expose SGX and enumerate EPC sections by CPUID leaf 0x12 if EPC is configured,
+    /// otherwise hide SGX as enclaves can't be created without EPC.
+    fn setup_sgx_cpuid(&self, cpuid: &mut CpuId) -> Result<()> {
+        let sections: Vec<&SgxEpcSection> =
+            self.sgx_epc.iter().filter(|epc| epc.size != 0).collect();
+        let entries = cpuid.as_mut_slice();
+        if sections.is_empty() {
+            for entry in entries.iter_mut() {
+                if entry.function == 7 && entry.index == 0 {
+                    entry.ebx &= !(1u32 << X86_FEATURE_SGX);
+                    entry.ecx &= !(1u32 << X86_FEATURE_SGX_LC);
+                } else if entry.function == CPUID_SGX_LEAF {
+                    entry.eax = 0;
+                    entry.ebx = 0;
+                    entry.ecx = 0;
+                    entry.edx = 0;
+                }
+            }
+            return Ok(());
+        }
+
+        let sgx_supported = entries.iter().any(|entry| {
+            entry.function == 7 && entry.index == 0 && entry.ebx & (1u32 << X86_FEATURE_SGX) != 0
+        }) && entries.iter().any(|entry| entry.function == CPUID_SGX_LEAF);
+        if !sgx_supported {
+            bail!("SGX is not supported by host or KVM");
+        }
+        for (index, section) in sections.iter().enumerate() {
+            cpuid.push(sgx_epc_cpuid_entry(
+                SGX_EPC_FIRST_SUBLEAF + index as u32,
+                Some(section),
+            ))?;
+        }
+        // The invalid sub-leaf terminates the enumeration of EPC sections.
+        cpuid.push(sgx_epc_cpuid_entry(
+            SGX_EPC_FIRST_SUBLEAF + sections.len() as u32,
+            None,
+        ))?;
+        Ok(())
+    }
+
     fn setup_cpuid(&self, vcpu_fd: &Arc<VcpuFd>) -> Result<()> {
-        let core_offset = 32u32 - (self.nr_threads - 1).leading_zeros();
-        let die_offset = (32u32 - (self.nr_cores - 1).leading_zeros()) + core_offset;
-        let pkg_offset = (32u32 - (self.nr_dies - 1).leading_zeros()) + die_offset;
+        let (core_offset, die_offset, pkg_offset) = X86CPUTopology::new()
+            .set_topology((
+                self.nr_threads as u8,
+                self.nr_cores as u8,
+                self.nr_dies as u8,
+            ))
+            .apic_id_offsets();
+        let nr_pkg_cpus = self.nr_dies * self.nr_cores * self.nr_threads;
         let sys_fd = match Kvm::new() {
             Ok(fd) => fd,
             _ => bail!("setup_cpuid: Open /dev/kvm failed"),
@@ -435,7 +597,16 @@ impl X86CPUState {
                 format!("Failed to get supported cpuid for CPU {}/KVM", self.apic_id)
             })?;
         self.adjust_cpuid(&mut cpuid)?;
+        self.setup_sgx_cpuid(&mut cpuid)?;
         let entries = cpuid.as_mut_slice();
+        adjust_spec_cpuid(entries, self.spec_enabled, self.spec_disabled)?;
+        if self.invtsc
+            && !entries.iter().any(|entry| {
+                entry.function == 0x8000_0007 && entry.edx & (1u32 << X86_FEATURE_INVTSC) != 0
+            })
+        {
+            bail!("Invariant TSC is not supported by host");
+        }
 
         for entry in entries.iter_mut() {
             match entry.function {
@@ -443,7 +614,10 @@ impl X86CPUState {
                     if entry.index == 0 {
                         entry.ecx |= 1u32 << X86_FEATURE_HYPERVISOR;
                         entry.ecx |= 1u32 << X86_FEATURE_TSC_DEADLINE_TIMER;
-                        entry.ebx = self.apic_id << 24 | 8 << 8;
+                        entry.ebx = self.apic_id << 24 | (nr_pkg_cpus & 0xff) << 16 | 8 << 8;
+                        if nr_pkg_cpus > 1 {
+                            entry.edx |= 1u32 << X86_FEATURE_HTT;
+                        }
                     }
                 }
                 2 => {
@@ -467,9 +641,17 @@ impl X86CPUState {
                         &mut entry.ecx,
                         &mut entry.edx,
                     );
-                    entry.eax &= !0xfc00_0000;
-                    if entry.eax & 0x0001_ffff != 0 && self.nr_vcpus > 1 {
-                        entry.eax |= (self.nr_vcpus - 1) << 26;
+                    entry.eax &= !0xffff_c000;
+                    if entry.eax & 0x0001_ffff != 0 {
+                        // Bits 31-26: max number of addressable core IDs in package.
+                        entry.eax |= ((1u32 << (pkg_offset - core_offset)) - 1) << 26;
+                        // Bits 25-14: max number of addressable IDs sharing this cache,
+                        // L1 and L2 are shared in core, L3 is shared in die.
+                        let share_offset = match (entry.eax >> 5) & 0x7 {
+                            1 | 2 => core_offset,
+                            _ => die_offset,
+                        };
+                        entry.eax |= ((1u32 << share_offset) - 1) << 14;
                     }
                 }
                 6 => {
@@ -496,7 +678,7 @@ impl X86CPUState {
                         }
                         1 => {
                             entry.eax = pkg_offset;
-                            entry.ebx = self.nr_threads * self.nr_cores;
+                            entry.ebx = nr_pkg_cpus;
                             entry.ecx |= ECX_CORE;
                         }
                         _ => {
@@ -542,6 +724,17 @@ impl X86CPUState {
                         }
                     }
                 }
+                0x4000_0001 => {
+                    // KVM paravirtualized features.
+                    if !self.steal_time {
+                        entry.eax &= !(1u32 << KVM_FEATURE_STEAL_TIME);
+                    }
+                }
+                0x8000_0007 => {
+                    if !self.invtsc {
+                        entry.edx &= !(1u32 << X86_FEATURE_INVTSC);
+                    }
+                }
                 0x8000_0002..=0x8000_0004 => {
                     // Passthrough host cpu model name directly to guest
                     host_cpuid(
@@ -608,6 +801,85 @@ impl StateTransfer for CPU {
 
 impl MigrationHook for CPU {}
 
+/// Hide the disabled speculation control features from guest, and check the enabled ones
+/// are supported by host.
+fn adjust_spec_cpuid(entries: &mut [kvm_cpuid_entry2], enabled: u32, disabled: u32) -> Result<()> {
+    for (feature, name, leaf7_edx, ext8_ebx) in SPEC_FEATURE_CPUID_BITS.iter() {
+        if enabled & feature != 0
+            && !entries.iter().any(|entry| {
+                (entry.function == 7 && entry.index == 0 && entry.edx & leaf7_edx != 0)
+                    || (entry.function == 0x8000_0008 && entry.ebx & ext8_ebx != 0)
+            })
+        {
+            bail!(
+                "Speculation control feature {} is not supported by host",
+                name
+            );
+        }
+        if disabled & feature == 0 {
+            continue;
+        }
+        for entry in entries.iter_mut() {
+            if entry.function == 7 && entry.index == 0 {
+                entry.edx &= !leaf7_edx;
+            } else if entry.function == 0x8000_0008 {
+                entry.ebx &= !ext8_ebx;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Force disable speculative store bypass and indirect branch speculation on host for the
+/// current thread, the threads created later, such as vCPU threads, inherit it.
+pub fn set_spec_isolation() -> Result<()> {
+    for (spec, name) in [
+        (libc::PR_SPEC_STORE_BYPASS, "speculative store bypass"),
+        (libc::PR_SPEC_INDIRECT_BRANCH, "indirect branch speculation"),
+    ] {
+        // SAFETY: prctl only changes the speculation control of current thread.
+        let ret = unsafe {
+            libc::prctl(
+                libc::PR_SET_SPECULATION_CTRL,
+                spec,
+                libc::PR_SPEC_FORCE_DISABLE,
+                0,
+                0,
+            )
+        };
+        if ret == 0 {
+            continue;
+        }
+        let err = std::io::Error::last_os_error();
+        // ENXIO means host is not affected or the mitigation is not controlled per task.
+        if err.raw_os_error() == Some(libc::ENXIO) {
+            warn!("Failed to disable {} per task: {}", name, err);
+            continue;
+        }
+        return Err(err).with_context(|| format!("Failed to disable {}", name));
+    }
+    Ok(())
+}
+
+/// Sub-leaf of CPUID leaf 0x12 which enumerates an EPC section, `None` means the invalid
+/// sub-leaf.
+fn sgx_epc_cpuid_entry(index: u32, section: Option<&SgxEpcSection>) -> kvm_cpuid_entry2 {
+    let mut entry = kvm_cpuid_entry2 {
+        function: CPUID_SGX_LEAF,
+        index,
+        flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+        ..Default::default()
+    };
+    if let Some(epc) = section {
+        // Bits 31:12 of EAX/ECX and bits 19:0 of EBX/EDX are the base and size.
+        entry.eax = (epc.base as u32 & 0xffff_f000) | SGX_EPC_SUBLEAF_TYPE_SECTION;
+        entry.ebx = (epc.base >> 32) as u32 & 0xf_ffff;
+        entry.ecx = (epc.size as u32 & 0xffff_f000) | SGX_EPC_SECTION_PROTECTED;
+        entry.edx = (epc.size >> 32) as u32 & 0xf_ffff;
+    }
+    entry
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -615,6 +887,77 @@ mod test {
     use kvm_bindings::kvm_segment;
     use std::sync::Arc;
 
+    #[test]
+    fn test_x86_64_apic_id() {
+        // 2 sockets * 3 cores * 2 threads, core id takes 2 bits.
+        let topology = X86CPUTopology::new().set_topology((2, 3, 1));
+        assert_eq!(topology.apic_id_offsets(), (1, 3, 3));
+        let apic_ids: Vec<u32> = (0..12).map(|id| topology.apic_id(id)).collect();
+        assert_eq!(apic_ids, vec![0, 1, 2, 3, 4, 5, 8, 9, 10, 11, 12, 13]);
+
+        // 2 dies * 2 cores * 1 thread.
+        let topology = X86CPUTopology::new().set_topology((1, 2, 2));
+        assert_eq!(topology.apic_id_offsets(), (0, 1, 2));
+        assert_eq!(topology.apic_id(3), 3);
+        assert_eq!(topology.apic_id(5), 5);
+
+        // Power-of-two topology keeps APIC ID the same as vcpu index.
+        let topology = X86CPUTopology::new().set_topology((2, 4, 1));
+        assert!((0..32).all(|id| topology.apic_id(id) == u32::from(id)));
+    }
+
+    #[test]
+    fn test_sgx_epc_cpuid_entry() {
+        let epc = SgxEpcSection {
+            base: 0x1_8000_0000,
+            size: 0x400_0000,
+        };
+        let entry = sgx_epc_cpuid_entry(2, Some(&epc));
+        assert_eq!(entry.function, 0x12);
+        assert_eq!(entry.index, 2);
+        assert_eq!(entry.flags, KVM_CPUID_FLAG_SIGNIFCANT_INDEX);
+        assert_eq!(entry.eax, 0x8000_0001);
+        assert_eq!(entry.ebx, 0x1);
+        assert_eq!(entry.ecx, 0x400_0001);
+        assert_eq!(entry.edx, 0);
+
+        let entry = sgx_epc_cpuid_entry(3, None);
+        assert_eq!((entry.eax, entry.ebx, entry.ecx, entry.edx), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_adjust_spec_cpuid() {
+        let host_entries = [
+            kvm_cpuid_entry2 {
+                function: 7,
+                edx: 1 << 10 | 1 << 26 | 1 << 27 | 1 << 31,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x8000_0008,
+                ebx: 1 << 12 | 1 << 14 | 1 << 15 | 1 << 24,
+                ..Default::default()
+            },
+        ];
+
+        let mut entries = host_entries;
+        adjust_spec_cpuid(&mut entries, 0, 0).unwrap();
+        assert_eq!(entries[0].edx, host_entries[0].edx);
+        assert_eq!(entries[1].ebx, host_entries[1].ebx);
+
+        adjust_spec_cpuid(
+            &mut entries,
+            SPEC_FEATURE_MD_CLEAR,
+            SPEC_FEATURE_SPEC_CTRL | SPEC_FEATURE_SSBD,
+        )
+        .unwrap();
+        assert_eq!(entries[0].edx, 1 << 10);
+        assert_eq!(entries[1].ebx, 0);
+
+        // Features which are hidden can't be required.
+        assert!(adjust_spec_cpuid(&mut entries, SPEC_FEATURE_SSBD, 0).is_err());
+    }
+
     #[test]
     fn test_x86_64_cpu() {
         let kvm_fds = KVMFds::new();
@@ -676,7 +1019,9 @@ mod test {
         let vcpu = Arc::new(vm_fd.create_vcpu(0).unwrap());
         let mut x86_cpu = X86CPUState::new(0, 1);
         // test `set_boot_config` function
-        assert!(x86_cpu.set_boot_config(&vcpu, &cpu_config).is_ok());
+        assert!(x86_cpu
+            .set_boot_config(&vcpu, &cpu_config, &X86CPUFeatures::default())
+            .is_ok());
 
         // test setup special registers
         let cpu_caps = caps::X86CPUCaps::init_capabilities();
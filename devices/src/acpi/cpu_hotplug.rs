@@ -0,0 +1,492 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AcpiError, AmlAcquire, AmlAddressSpaceType, AmlAnd, AmlArg, AmlBuilder, AmlCallWithArgs1,
+    AmlDevice, AmlEisaId, AmlEqual, AmlField, AmlFieldAccessType, AmlFieldLockRule, AmlFieldUnit,
+    AmlFieldUpdateRule, AmlIf, AmlInteger, AmlLocal, AmlMethod, AmlMutex, AmlName, AmlNameDecl,
+    AmlNotify, AmlOpRegion, AmlRelease, AmlReturn, AmlScopeBuilder, AmlStore, AmlString, AmlZero,
+};
+#[cfg(target_arch = "x86_64")]
+use acpi::{AmlBuffer, AmlCreateDWordField};
+use address_space::GuestAddress;
+use util::num_ops::{read_data_u32, write_data_u32};
+
+/// Size of the register region of CPU hotplug controller.
+pub const CPU_HOTPLUG_REG_SIZE: u64 = 0x8;
+
+/// Read: the selected CPU. Write: select the CPU.
+const REG_SELECTOR: u64 = 0x0;
+/// Read: status of the selected CPU. Write: clear the events or eject the CPU.
+const REG_STATUS: u64 = 0x4;
+
+const STATUS_ENABLED: u32 = 0x1;
+const STATUS_INSERT: u32 = 0x2;
+const STATUS_REMOVE: u32 = 0x4;
+/// Written by guest to eject the CPU after it is offline.
+const STATUS_EJECT: u32 = 0x8;
+
+/// Notify values of ACPI device object.
+const NOTIFY_DEVICE_CHECK: u64 = 0x1;
+const NOTIFY_EJECT_REQUEST: u64 = 0x3;
+
+const AML_CPU_HOTPLUG_DEV: &str = "CPUS";
+const AML_CPU_LOCK: &str = "CPLK";
+const AML_CPU_REG: &str = "CPHR";
+const AML_CPU_SELECTOR: &str = "CSEL";
+const AML_CPU_STATUS: &str = "CSTS";
+/// Method to scan CPUs and notify guest of inserted or removing CPUs.
+pub const AML_CPU_SCAN_METHOD: &str = "\\_SB.CPUS.CSCN";
+
+/// State of a possible CPU in CPU hotplug controller.
+#[derive(Clone, Debug, Default)]
+struct CpuSlot {
+    /// Id of the hot-added CPU device, `None` for the CPU present at boot.
+    id: Option<String>,
+    enabled: bool,
+    /// Whether the guest has not been notified of the inserted CPU.
+    inserting: bool,
+    /// Whether the guest has been requested to remove the CPU.
+    removing: bool,
+}
+
+/// CPU hotplug controller, which reports possible CPUs to guest through ACPI.
+#[derive(Clone)]
+pub struct CpuHotplug {
+    base: SysBusDevBase,
+    slots: Vec<CpuSlot>,
+    /// CPU selected by guest.
+    selector: usize,
+    /// CPUs ejected by guest, which are waiting to be parked.
+    ejected: Vec<u8>,
+    /// Notify the machine that CPUs are ejected.
+    eject_req: Arc<EventFd>,
+    /// Local APIC ID of each CPU.
+    #[cfg(target_arch = "x86_64")]
+    apic_ids: Vec<u8>,
+}
+
+impl CpuHotplug {
+    /// Create CPU hotplug controller.
+    ///
+    /// # Arguments
+    ///
+    /// * `nr_cpus` - Number of CPUs present at boot.
+    /// * `max_cpus` - Number of possible CPUs.
+    /// * `eject_req` - Eventfd written when guest ejects a CPU.
+    pub fn new(nr_cpus: u8, max_cpus: u8, eject_req: Arc<EventFd>) -> Self {
+        let mut slots = vec![CpuSlot::default(); max_cpus as usize];
+        slots
+            .iter_mut()
+            .take(nr_cpus as usize)
+            .for_each(|s| s.enabled = true);
+        Self {
+            base: SysBusDevBase::default(),
+            slots,
+            selector: 0,
+            ejected: Vec::new(),
+            eject_req,
+            #[cfg(target_arch = "x86_64")]
+            apic_ids: (0..max_cpus).collect(),
+        }
+    }
+
+    /// Set the local APIC IDs of CPUs, which default to the CPU index.
+    ///
+    /// # Arguments
+    ///
+    /// * `apic_ids` - APIC ID of each possible CPU.
+    #[cfg(target_arch = "x86_64")]
+    pub fn with_apic_ids(mut self, apic_ids: Vec<u8>) -> Self {
+        self.apic_ids = apic_ids;
+        self
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<CpuHotplug>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "CpuHotplug")?;
+        Ok(dev)
+    }
+
+    /// Find the CPU hot-added with device `id`.
+    pub fn find_cpu(&self, id: &str) -> Option<u8> {
+        self.slots
+            .iter()
+            .position(|s| s.enabled && s.id.as_deref() == Some(id))
+            .map(|cpu_id| cpu_id as u8)
+    }
+
+    /// Plug a CPU, guest should be notified of it afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu_id` - Id of the CPU.
+    /// * `id` - Id of the CPU device.
+    pub fn plug(&mut self, cpu_id: u8, id: &str) -> Result<()> {
+        if self.slots.iter().any(|s| s.id.as_deref() == Some(id)) {
+            bail!("Device id {} existed", id);
+        }
+        let slot = self
+            .slots
+            .get_mut(cpu_id as usize)
+            .with_context(|| format!("CPU {} is out of max cpus", cpu_id))?;
+        if slot.enabled {
+            bail!("CPU {} is already present", cpu_id);
+        }
+        *slot = CpuSlot {
+            id: Some(id.to_string()),
+            enabled: true,
+            inserting: true,
+            removing: false,
+        };
+        Ok(())
+    }
+
+    /// Remove a hot-added CPU which guest has not been notified of, it is used when
+    /// hot-adding the CPU fails.
+    pub fn unplug(&mut self, cpu_id: u8) {
+        if let Some(slot) = self.slots.get_mut(cpu_id as usize) {
+            *slot = CpuSlot::default();
+        }
+    }
+
+    /// Request guest to remove a hot-added CPU, the CPU is ejected by guest after
+    /// it is offline.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu_id` - Id of the CPU.
+    pub fn request_unplug(&mut self, cpu_id: u8) -> Result<()> {
+        let slot = self
+            .slots
+            .get_mut(cpu_id as usize)
+            .filter(|s| s.enabled)
+            .with_context(|| format!("CPU {} is not present", cpu_id))?;
+        if slot.id.is_none() {
+            bail!("CPU {} present at boot can't be removed", cpu_id);
+        }
+        if slot.removing {
+            bail!("CPU {} is being removed", cpu_id);
+        }
+        slot.removing = true;
+        Ok(())
+    }
+
+    /// Take the CPUs ejected by guest, with the id of the CPU devices.
+    pub fn take_ejected(&mut self) -> Vec<(u8, String)> {
+        std::mem::take(&mut self.ejected)
+            .into_iter()
+            .map(|cpu_id| {
+                let slot = &mut self.slots[cpu_id as usize];
+                (cpu_id, slot.id.take().unwrap_or_default())
+            })
+            .collect()
+    }
+
+    fn eject(&mut self) {
+        let selector = self.selector;
+        match self.slots.get_mut(selector) {
+            Some(slot) if slot.enabled && slot.removing => {
+                slot.enabled = false;
+                slot.removing = false;
+                slot.inserting = false;
+            }
+            _ => return,
+        }
+        self.ejected.push(selector as u8);
+        if let Err(e) = self.eject_req.write(1) {
+            error!("Failed to notify ejection of CPU {}: {:?}", selector, e);
+        }
+    }
+}
+
+impl Device for CpuHotplug {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for CpuHotplug {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let value = match offset {
+            REG_SELECTOR => self.selector as u32,
+            REG_STATUS => match self.slots.get(self.selector) {
+                Some(slot) if slot.enabled => {
+                    let mut status = STATUS_ENABLED;
+                    if slot.inserting {
+                        status |= STATUS_INSERT;
+                    }
+                    if slot.removing {
+                        status |= STATUS_REMOVE;
+                    }
+                    status
+                }
+                _ => 0,
+            },
+            _ => return false,
+        };
+        write_data_u32(data, value)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut value = 0;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        match offset {
+            REG_SELECTOR => self.selector = value as usize,
+            REG_STATUS => {
+                if let Some(slot) = self.slots.get_mut(self.selector) {
+                    if value & STATUS_INSERT != 0 {
+                        slot.inserting = false;
+                    }
+                }
+                if value & STATUS_EJECT != 0 {
+                    self.eject();
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // Guest scans all the CPUs when booting.
+        self.slots.iter_mut().for_each(|s| s.inserting = false);
+        self.selector = 0;
+        Ok(())
+    }
+}
+
+/// Select the CPU in `Arg0` with the lock held, run `ops`, and release the lock.
+fn aml_cpu_method<F: FnOnce(&mut AmlMethod)>(name: &str, ops: F) -> AmlMethod {
+    let mut method = AmlMethod::new(name, 1, true);
+    method.append_child(AmlAcquire::new(AmlName(AML_CPU_LOCK.to_string()), 0xffff));
+    method.append_child(AmlStore::new(
+        AmlArg(0),
+        AmlName(AML_CPU_SELECTOR.to_string()),
+    ));
+    ops(&mut method);
+    method.append_child(AmlRelease::new(AmlName(AML_CPU_LOCK.to_string())));
+    method
+}
+
+/// Test event `status` of the selected CPU, notify the CPU device and clear the event.
+fn aml_cpu_event(cpu_id: usize, status: u32, notify: u64) -> AmlIf {
+    let mut if_scope = AmlIf::new(AmlEqual::new(
+        AmlAnd::new(
+            AmlName(AML_CPU_STATUS.to_string()),
+            AmlInteger(status as u64),
+            AmlLocal(0),
+        ),
+        AmlInteger(status as u64),
+    ));
+    if_scope.append_child(AmlNotify::new(
+        AmlName(format!("C{:03}", cpu_id)),
+        AmlInteger(notify),
+    ));
+    if_scope.append_child(AmlStore::new(
+        AmlInteger(status as u64),
+        AmlName(AML_CPU_STATUS.to_string()),
+    ));
+    if_scope
+}
+
+impl AmlBuilder for CpuHotplug {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new(AML_CPU_HOTPLUG_DEV);
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0010".to_string())));
+        acpi_dev.append_child(AmlNameDecl::new("_CID", AmlEisaId::new("PNP0A05")));
+        acpi_dev.append_child(AmlMutex::new(AML_CPU_LOCK, 0));
+        acpi_dev.append_child(AmlOpRegion::new(
+            AML_CPU_REG,
+            AmlAddressSpaceType::SystemMemory,
+            self.base.res.region_base,
+            self.base.res.region_size,
+        ));
+
+        let mut field = AmlField::new(
+            AML_CPU_REG,
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        field.append_child(AmlFieldUnit::new(Some(AML_CPU_SELECTOR), 32));
+        field.append_child(AmlFieldUnit::new(Some(AML_CPU_STATUS), 32));
+        acpi_dev.append_child(field);
+
+        let mut method = aml_cpu_method("CSTA", |method| {
+            method.append_child(AmlStore::new(AmlZero, AmlLocal(0)));
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(
+                    AmlName(AML_CPU_STATUS.to_string()),
+                    AmlInteger(STATUS_ENABLED as u64),
+                    AmlLocal(1),
+                ),
+                AmlInteger(STATUS_ENABLED as u64),
+            ));
+            if_scope.append_child(AmlStore::new(AmlInteger(0xf), AmlLocal(0)));
+            method.append_child(if_scope);
+        });
+        method.append_child(AmlReturn::with_value(AmlLocal(0)));
+        acpi_dev.append_child(method);
+
+        let method = aml_cpu_method("CEJ0", |method| {
+            method.append_child(AmlStore::new(
+                AmlInteger(STATUS_EJECT as u64),
+                AmlName(AML_CPU_STATUS.to_string()),
+            ));
+        });
+        acpi_dev.append_child(method);
+
+        // Scan all the CPUs, notify guest of the inserted and removing CPUs.
+        let mut method = AmlMethod::new("CSCN", 0, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_CPU_LOCK.to_string()), 0xffff));
+        for cpu_id in 0..self.slots.len() {
+            method.append_child(AmlStore::new(
+                AmlInteger(cpu_id as u64),
+                AmlName(AML_CPU_SELECTOR.to_string()),
+            ));
+            method.append_child(aml_cpu_event(cpu_id, STATUS_INSERT, NOTIFY_DEVICE_CHECK));
+            method.append_child(aml_cpu_event(cpu_id, STATUS_REMOVE, NOTIFY_EJECT_REQUEST));
+        }
+        method.append_child(AmlRelease::new(AmlName(AML_CPU_LOCK.to_string())));
+        acpi_dev.append_child(method);
+
+        for cpu_id in 0..self.slots.len() {
+            let mut cpu = AmlDevice::new(&format!("C{:03}", cpu_id));
+            cpu.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
+            cpu.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id as u64)));
+
+            let mut method = AmlMethod::new("_STA", 0, false);
+            method.append_child(AmlReturn::with_value(AmlCallWithArgs1::new(
+                "CSTA",
+                AmlInteger(cpu_id as u64),
+            )));
+            cpu.append_child(method);
+
+            let mut method = AmlMethod::new("_EJ0", 1, false);
+            method.append_child(AmlCallWithArgs1::new("CEJ0", AmlInteger(cpu_id as u64)));
+            cpu.append_child(method);
+
+            // Local APIC structure of the CPU, whose flags is the enabled bit of status.
+            #[cfg(target_arch = "x86_64")]
+            {
+                let mut method = AmlMethod::new("_MAT", 0, false);
+                method.append_child(AmlNameDecl::new(
+                    "LAPI",
+                    AmlBuffer(vec![0, 8, cpu_id as u8, self.apic_ids[cpu_id], 0, 0, 0, 0]),
+                ));
+                method.append_child(AmlCreateDWordField::new(
+                    AmlName("LAPI".to_string()),
+                    AmlInteger(4),
+                    "FLGS",
+                ));
+                method.append_child(AmlAnd::new(
+                    AmlCallWithArgs1::new("CSTA", AmlInteger(cpu_id as u64)),
+                    AmlInteger(STATUS_ENABLED as u64),
+                    AmlName("FLGS".to_string()),
+                ));
+                method.append_child(AmlReturn::with_value(AmlName("LAPI".to_string())));
+                cpu.append_child(method);
+            }
+            acpi_dev.append_child(cpu);
+        }
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_hotplug_regs() {
+        let eject_req = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+        let mut ctrl = CpuHotplug::new(1, 3, eject_req.clone());
+        let base = GuestAddress(0);
+        let mut data = [0_u8; 4];
+        let mut status = |ctrl: &mut CpuHotplug, cpu_id: u32| {
+            assert!(ctrl.write(&cpu_id.to_le_bytes(), base, REG_SELECTOR));
+            assert!(ctrl.read(&mut data, base, REG_STATUS));
+            u32::from_le_bytes(data)
+        };
+
+        assert_eq!(status(&mut ctrl, 0), STATUS_ENABLED);
+        assert_eq!(status(&mut ctrl, 2), 0);
+        // CPU present at boot can't be removed.
+        assert!(ctrl.request_unplug(0).is_err());
+
+        ctrl.plug(2, "cpu2").unwrap();
+        assert!(ctrl.plug(2, "cpu3").is_err());
+        assert!(ctrl.plug(1, "cpu2").is_err());
+        assert!(ctrl.plug(3, "cpu3").is_err());
+        assert_eq!(ctrl.find_cpu("cpu2"), Some(2));
+        assert_eq!(status(&mut ctrl, 2), STATUS_ENABLED | STATUS_INSERT);
+        assert!(ctrl.write(&STATUS_INSERT.to_le_bytes(), base, REG_STATUS));
+        assert_eq!(status(&mut ctrl, 2), STATUS_ENABLED);
+
+        // Guest can't eject CPU which is not requested to be removed.
+        assert!(ctrl.write(&STATUS_EJECT.to_le_bytes(), base, REG_STATUS));
+        assert!(ctrl.take_ejected().is_empty());
+
+        assert!(ctrl.request_unplug(1).is_err());
+        ctrl.request_unplug(2).unwrap();
+        assert!(ctrl.request_unplug(2).is_err());
+        assert_eq!(status(&mut ctrl, 2), STATUS_ENABLED | STATUS_REMOVE);
+        assert!(ctrl.write(&STATUS_EJECT.to_le_bytes(), base, REG_STATUS));
+        assert_eq!(status(&mut ctrl, 2), 0);
+        assert_eq!(eject_req.read().unwrap(), 1);
+        assert_eq!(ctrl.take_ejected(), vec![(2, "cpu2".to_string())]);
+        assert_eq!(ctrl.find_cpu("cpu2"), None);
+
+        // The CPU can be hot-added again.
+        ctrl.plug(2, "cpu2").unwrap();
+        ctrl.unplug(2);
+        assert_eq!(status(&mut ctrl, 2), 0);
+        assert_eq!(ctrl.find_cpu("cpu2"), None);
+        ctrl.plug(1, "cpu2").unwrap();
+    }
+}
@@ -20,6 +20,8 @@ use log::error;
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
+use crate::acpi::cpu_hotplug::AML_CPU_SCAN_METHOD;
+use crate::acpi::memory_hotplug::AML_MEM_SCAN_METHOD;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::{
@@ -27,8 +29,9 @@ use acpi::{
     AmlEqual, AmlExtendedInterrupt, AmlField, AmlFieldAccessType, AmlFieldLockRule, AmlFieldUnit,
     AmlFieldUpdateRule, AmlIf, AmlIntShare, AmlInteger, AmlLocal, AmlMethod, AmlName, AmlNameDecl,
     AmlNotify, AmlOpRegion, AmlResTemplate, AmlResourceUsage, AmlScopeBuilder, AmlStore, AmlString,
-    INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT,
 };
+#[cfg(target_arch = "aarch64")]
+use acpi::{INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT};
 use address_space::GuestAddress;
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -43,6 +46,8 @@ pub enum AcpiEvent {
     AcadSt = 2,
     BatteryInf = 4,
     BatterySt = 8,
+    MemHotplug = 16,
+    CpuHotplug = 32,
 }
 
 const AML_GED_EVT_REG: &str = "EREG";
@@ -53,6 +58,10 @@ pub struct Ged {
     base: SysBusDevBase,
     notification_type: Arc<AtomicU32>,
     battery_present: bool,
+    /// Whether power button is present, which is notified of power down event.
+    power_button: bool,
+    mem_hotplug: bool,
+    cpu_hotplug: bool,
 }
 
 impl Default for Ged {
@@ -61,15 +70,28 @@ impl Default for Ged {
             base: SysBusDevBase::default(),
             notification_type: Arc::new(AtomicU32::new(AcpiEvent::Nothing as u32)),
             battery_present: false,
+            power_button: false,
+            mem_hotplug: false,
+            cpu_hotplug: false,
         }
     }
 }
 
 impl Ged {
+    /// Enable memory hotplug event, which makes guest scan DIMM slots.
+    pub fn enable_mem_hotplug(&mut self) {
+        self.mem_hotplug = true;
+    }
+
+    /// Enable CPU hotplug event, which makes guest scan possible CPUs.
+    pub fn enable_cpu_hotplug(&mut self) {
+        self.cpu_hotplug = true;
+    }
+
     pub fn realize(
         mut self,
         sysbus: &mut SysBus,
-        power_button: Arc<EventFd>,
+        power_button: Option<Arc<EventFd>>,
         battery_present: bool,
         region_base: u64,
         region_size: u64,
@@ -78,13 +100,16 @@ impl Ged {
         self.set_sys_resource(sysbus, region_base, region_size)
             .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
         self.battery_present = battery_present;
+        self.power_button = power_button.is_some();
 
         let dev = Arc::new(Mutex::new(self));
         sysbus.attach_device(&dev, region_base, region_size, "Ged")?;
 
-        let ged = dev.lock().unwrap();
-        ged.register_acpi_powerdown_event(power_button)
-            .with_context(|| "Failed to register ACPI powerdown event.")?;
+        if let Some(power_button) = power_button {
+            let ged = dev.lock().unwrap();
+            ged.register_acpi_powerdown_event(power_button)
+                .with_context(|| "Failed to register ACPI powerdown event.")?;
+        }
         Ok(dev.clone())
     }
 
@@ -178,7 +203,10 @@ impl AmlBuilder for Ged {
         let mut res = AmlResTemplate::new();
 
         // SPI start at interrupt number 32 on aarch64 platform.
+        #[cfg(target_arch = "aarch64")]
         let irq_base = INTERRUPT_PPIS_COUNT + INTERRUPT_SGIS_COUNT;
+        #[cfg(target_arch = "x86_64")]
+        let irq_base = 0;
         res.append_child(AmlExtendedInterrupt::new(
             AmlResourceUsage::Consumer,
             AmlEdgeLevel::Edge,
@@ -223,6 +251,9 @@ impl AmlBuilder for Ged {
             let dev = event.1;
             let notify = event.2;
 
+            if !self.power_button && evt == AcpiEvent::PowerDown as u64 {
+                continue;
+            }
             if !self.battery_present
                 && (evt > AcpiEvent::PowerDown as u64 && evt <= AcpiEvent::BatterySt as u64)
             {
@@ -237,6 +268,26 @@ impl AmlBuilder for Ged {
             method.append_child(if_scope);
         }
 
+        if self.mem_hotplug {
+            let evt = AcpiEvent::MemHotplug as u64;
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(AmlLocal(0), AmlInteger(evt), AmlLocal(1)),
+                AmlInteger(evt),
+            ));
+            if_scope.append_child(AmlName(AML_MEM_SCAN_METHOD.to_string()));
+            method.append_child(if_scope);
+        }
+
+        if self.cpu_hotplug {
+            let evt = AcpiEvent::CpuHotplug as u64;
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(AmlLocal(0), AmlInteger(evt), AmlLocal(1)),
+                AmlInteger(evt),
+            ));
+            if_scope.append_child(AmlName(AML_CPU_SCAN_METHOD.to_string()));
+            method.append_child(if_scope);
+        }
+
         acpi_dev.append_child(method);
 
         acpi_dev.aml_bytes()
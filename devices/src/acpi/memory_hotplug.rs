@@ -0,0 +1,542 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AcpiError, AmlAcquire, AmlAdd, AmlAddressSpaceDecode, AmlAddressSpaceType, AmlAnd, AmlArg,
+    AmlBuilder, AmlCacheable, AmlCallWithArgs1, AmlCreateQWordField, AmlDevice, AmlEisaId,
+    AmlEqual, AmlField, AmlFieldAccessType, AmlFieldLockRule, AmlFieldUnit, AmlFieldUpdateRule,
+    AmlIf, AmlInteger, AmlLocal, AmlMethod, AmlMutex, AmlName, AmlNameDecl, AmlNotify, AmlOpRegion,
+    AmlOr, AmlQWordDesc, AmlReadAndWrite, AmlRelease, AmlResTemplate, AmlReturn, AmlScopeBuilder,
+    AmlShiftLeft, AmlStore, AmlString, AmlSubtract, AmlZero,
+};
+use address_space::GuestAddress;
+use util::num_ops::{read_data_u32, round_up, write_data_u32};
+
+/// Size of the register region of memory hotplug controller.
+pub const MEM_HOTPLUG_REG_SIZE: u64 = 0x18;
+
+/// Read: low 32 bits of DIMM address. Write: select the slot.
+const REG_ADDR_LO: u64 = 0x0;
+const REG_ADDR_HI: u64 = 0x4;
+const REG_SIZE_LO: u64 = 0x8;
+const REG_SIZE_HI: u64 = 0xc;
+const REG_NODE: u64 = 0x10;
+/// Read: status of the selected slot. Write: clear the events.
+const REG_STATUS: u64 = 0x14;
+
+const STATUS_ENABLED: u32 = 0x1;
+const STATUS_INSERT: u32 = 0x2;
+
+const AML_MEM_HOTPLUG_DEV: &str = "MHPC";
+const AML_MEM_LOCK: &str = "MLCK";
+const AML_MEM_REG: &str = "MHPR";
+const AML_MEM_ADDR_LO: &str = "MRBL";
+const AML_MEM_ADDR_HI: &str = "MRBH";
+const AML_MEM_SIZE_LO: &str = "MRLL";
+const AML_MEM_SIZE_HI: &str = "MRLH";
+const AML_MEM_NODE: &str = "MPX";
+const AML_MEM_STATUS: &str = "MSTS";
+const AML_MEM_SELECTOR: &str = "MSEL";
+/// Method to scan slots and notify guest of inserted DIMMs.
+pub const AML_MEM_SCAN_METHOD: &str = "\\_SB.MHPC.MSCN";
+
+/// DIMM plugged in a slot of memory hotplug controller.
+#[derive(Clone, Debug)]
+pub struct DimmSlot {
+    pub id: String,
+    /// Id of the memory backend.
+    pub memdev: String,
+    pub addr: u64,
+    pub size: u64,
+    pub node: u32,
+    /// Whether the guest has not been notified of the DIMM.
+    inserting: bool,
+}
+
+/// Memory hotplug controller, which reports DIMMs to guest through ACPI.
+#[derive(Clone)]
+pub struct MemHotplug {
+    base: SysBusDevBase,
+    /// Guest physical address and size of the hotplug memory window.
+    window: (u64, u64),
+    slots: Vec<Option<DimmSlot>>,
+    /// Slot selected by guest.
+    selector: usize,
+}
+
+impl MemHotplug {
+    pub fn new(window: (u64, u64), nr_slots: u32) -> Self {
+        Self {
+            base: SysBusDevBase::default(),
+            window,
+            slots: vec![None; nr_slots as usize],
+            selector: 0,
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<MemHotplug>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "MemHotplug")?;
+        Ok(dev)
+    }
+
+    /// Get guest physical address and size of the hotplug memory window.
+    pub fn window(&self) -> (u64, u64) {
+        self.window
+    }
+
+    /// Get the plugged DIMMs.
+    pub fn dimms(&self) -> Vec<&DimmSlot> {
+        self.slots.iter().flatten().collect()
+    }
+
+    /// Find a free slot and guest address for a DIMM.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size of the DIMM.
+    /// * `addr` - Guest address required by user.
+    /// * `align` - Alignment of the guest address.
+    pub fn alloc(&self, size: u64, addr: Option<u64>, align: u64) -> Result<(usize, u64)> {
+        let slot = self
+            .slots
+            .iter()
+            .position(|s| s.is_none())
+            .with_context(|| "No free DIMM slot")?;
+
+        let mut used: Vec<(u64, u64)> = self.dimms().iter().map(|d| (d.addr, d.size)).collect();
+        used.sort_unstable();
+        let (start, end) = (self.window.0, self.window.0 + self.window.1);
+        let overlap = |addr: u64| used.iter().find(|(a, s)| addr < a + s && *a < addr + size);
+
+        if let Some(addr) = addr {
+            if addr % align != 0 {
+                bail!("DIMM address 0x{:x} is not aligned to 0x{:x}", addr, align);
+            }
+            if addr < start || addr.checked_add(size).map_or(true, |e| e > end) {
+                bail!(
+                    "DIMM [0x{:x}, 0x{:x}) is out of hotplug memory window [0x{:x}, 0x{:x})",
+                    addr,
+                    addr.wrapping_add(size),
+                    start,
+                    end
+                );
+            }
+            if let Some((a, s)) = overlap(addr) {
+                bail!(
+                    "DIMM at 0x{:x} overlaps with DIMM [0x{:x}, 0x{:x})",
+                    addr,
+                    a,
+                    a + s
+                );
+            }
+            return Ok((slot, addr));
+        }
+
+        let mut addr = start;
+        while addr + size <= end {
+            match overlap(addr) {
+                Some((a, s)) => addr = round_up(a + s, align).unwrap_or(end),
+                None => return Ok((slot, addr)),
+            }
+        }
+        bail!(
+            "No space for DIMM of size 0x{:x} in hotplug memory window",
+            size
+        );
+    }
+
+    /// Plug a DIMM into `slot`, which is returned by `alloc`.
+    ///
+    /// # Arguments
+    ///
+    /// * `slot` - The free slot.
+    /// * `dimm` - The DIMM.
+    /// * `hotplug` - Whether guest should be notified of the DIMM.
+    pub fn plug(&mut self, slot: usize, mut dimm: DimmSlot, hotplug: bool) {
+        dimm.inserting = hotplug;
+        self.slots[slot] = Some(dimm);
+    }
+
+    fn selected(&self) -> Option<&DimmSlot> {
+        self.slots.get(self.selector).and_then(|s| s.as_ref())
+    }
+}
+
+impl DimmSlot {
+    pub fn new(id: String, memdev: String, addr: u64, size: u64, node: u32) -> Self {
+        Self {
+            id,
+            memdev,
+            addr,
+            size,
+            node,
+            inserting: false,
+        }
+    }
+}
+
+impl Device for MemHotplug {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for MemHotplug {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let value = match self.selected() {
+            None => 0,
+            Some(dimm) => match offset {
+                REG_ADDR_LO => dimm.addr as u32,
+                REG_ADDR_HI => (dimm.addr >> 32) as u32,
+                REG_SIZE_LO => dimm.size as u32,
+                REG_SIZE_HI => (dimm.size >> 32) as u32,
+                REG_NODE => dimm.node,
+                REG_STATUS => {
+                    let mut status = STATUS_ENABLED;
+                    if dimm.inserting {
+                        status |= STATUS_INSERT;
+                    }
+                    status
+                }
+                _ => return false,
+            },
+        };
+        write_data_u32(data, value)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let mut value = 0;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        match offset {
+            REG_ADDR_LO => self.selector = value as usize,
+            REG_STATUS => {
+                if let Some(Some(dimm)) = self.slots.get_mut(self.selector) {
+                    if value & STATUS_INSERT != 0 {
+                        dimm.inserting = false;
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // Guest scans all the slots when booting.
+        self.slots
+            .iter_mut()
+            .flatten()
+            .for_each(|d| d.inserting = false);
+        self.selector = 0;
+        Ok(())
+    }
+}
+
+/// Select the slot in `Arg0` with the lock held, run `ops`, and return `Local0`.
+fn aml_slot_method<F: FnOnce(&mut AmlMethod)>(name: &str, ops: F) -> AmlMethod {
+    let mut method = AmlMethod::new(name, 1, true);
+    method.append_child(AmlAcquire::new(AmlName(AML_MEM_LOCK.to_string()), 0xffff));
+    method.append_child(AmlStore::new(
+        AmlArg(0),
+        AmlName(AML_MEM_SELECTOR.to_string()),
+    ));
+    ops(&mut method);
+    method.append_child(AmlRelease::new(AmlName(AML_MEM_LOCK.to_string())));
+    method.append_child(AmlReturn::with_value(AmlLocal(0)));
+    method
+}
+
+/// Combine the 32-bit fields `hi` and `lo` into the 64-bit `dst`.
+fn aml_store_qword(method: &mut AmlMethod, hi: &str, lo: &str, dst: &str) {
+    method.append_child(AmlShiftLeft::new(
+        AmlName(hi.to_string()),
+        AmlInteger(32),
+        AmlLocal(1),
+    ));
+    method.append_child(AmlOr::new(
+        AmlLocal(1),
+        AmlName(lo.to_string()),
+        AmlName(dst.to_string()),
+    ));
+}
+
+impl AmlBuilder for MemHotplug {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new(AML_MEM_HOTPLUG_DEV);
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlEisaId::new("PNP0A06")));
+        acpi_dev.append_child(AmlNameDecl::new(
+            "_UID",
+            AmlString("DIMM devices".to_string()),
+        ));
+        acpi_dev.append_child(AmlMutex::new(AML_MEM_LOCK, 0));
+        acpi_dev.append_child(AmlOpRegion::new(
+            AML_MEM_REG,
+            AmlAddressSpaceType::SystemMemory,
+            self.base.res.region_base,
+            self.base.res.region_size,
+        ));
+
+        let mut field = AmlField::new(
+            AML_MEM_REG,
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        for name in [
+            AML_MEM_ADDR_LO,
+            AML_MEM_ADDR_HI,
+            AML_MEM_SIZE_LO,
+            AML_MEM_SIZE_HI,
+            AML_MEM_NODE,
+            AML_MEM_STATUS,
+        ] {
+            field.append_child(AmlFieldUnit::new(Some(name), 32));
+        }
+        acpi_dev.append_child(field);
+        let mut field = AmlField::new(
+            AML_MEM_REG,
+            AmlFieldAccessType::DWord,
+            AmlFieldLockRule::NoLock,
+            AmlFieldUpdateRule::Preserve,
+        );
+        field.append_child(AmlFieldUnit::new(Some(AML_MEM_SELECTOR), 32));
+        acpi_dev.append_child(field);
+
+        let method = aml_slot_method("MSTA", |method| {
+            method.append_child(AmlStore::new(AmlZero, AmlLocal(0)));
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(
+                    AmlName(AML_MEM_STATUS.to_string()),
+                    AmlInteger(STATUS_ENABLED as u64),
+                    AmlLocal(1),
+                ),
+                AmlInteger(STATUS_ENABLED as u64),
+            ));
+            if_scope.append_child(AmlStore::new(AmlInteger(0xf), AmlLocal(0)));
+            method.append_child(if_scope);
+        });
+        acpi_dev.append_child(method);
+
+        let method = aml_slot_method("MPXM", |method| {
+            method.append_child(AmlStore::new(
+                AmlName(AML_MEM_NODE.to_string()),
+                AmlLocal(0),
+            ));
+        });
+        acpi_dev.append_child(method);
+
+        let method = aml_slot_method("MCRS", |method| {
+            let mut res = AmlResTemplate::new();
+            res.append_child(AmlQWordDesc::new_memory(
+                AmlAddressSpaceDecode::Positive,
+                AmlCacheable::Cacheable,
+                AmlReadAndWrite::ReadWrite,
+                0,
+                0,
+                0,
+                0,
+                0,
+            ));
+            method.append_child(AmlNameDecl::new("MR64", res));
+            method.append_child(AmlCreateQWordField::new(
+                AmlName("MR64".to_string()),
+                AmlInteger(14),
+                "MINL",
+            ));
+            method.append_child(AmlCreateQWordField::new(
+                AmlName("MR64".to_string()),
+                AmlInteger(22),
+                "MAXL",
+            ));
+            method.append_child(AmlCreateQWordField::new(
+                AmlName("MR64".to_string()),
+                AmlInteger(38),
+                "LENL",
+            ));
+            aml_store_qword(method, AML_MEM_ADDR_HI, AML_MEM_ADDR_LO, "MINL");
+            aml_store_qword(method, AML_MEM_SIZE_HI, AML_MEM_SIZE_LO, "LENL");
+            method.append_child(AmlAdd::new(
+                AmlName("MINL".to_string()),
+                AmlName("LENL".to_string()),
+                AmlLocal(2),
+            ));
+            method.append_child(AmlSubtract::new(
+                AmlLocal(2),
+                AmlInteger(1),
+                AmlName("MAXL".to_string()),
+            ));
+            method.append_child(AmlStore::new(AmlName("MR64".to_string()), AmlLocal(0)));
+        });
+        acpi_dev.append_child(method);
+
+        // Scan all the slots, notify guest of the inserted DIMMs.
+        let mut method = AmlMethod::new("MSCN", 0, true);
+        method.append_child(AmlAcquire::new(AmlName(AML_MEM_LOCK.to_string()), 0xffff));
+        for slot in 0..self.slots.len() {
+            method.append_child(AmlStore::new(
+                AmlInteger(slot as u64),
+                AmlName(AML_MEM_SELECTOR.to_string()),
+            ));
+            let mut if_scope = AmlIf::new(AmlEqual::new(
+                AmlAnd::new(
+                    AmlName(AML_MEM_STATUS.to_string()),
+                    AmlInteger(STATUS_INSERT as u64),
+                    AmlLocal(0),
+                ),
+                AmlInteger(STATUS_INSERT as u64),
+            ));
+            if_scope.append_child(AmlNotify::new(
+                AmlName(format!("M{:03X}", slot)),
+                AmlInteger(1),
+            ));
+            if_scope.append_child(AmlStore::new(
+                AmlInteger(STATUS_INSERT as u64),
+                AmlName(AML_MEM_STATUS.to_string()),
+            ));
+            method.append_child(if_scope);
+        }
+        method.append_child(AmlRelease::new(AmlName(AML_MEM_LOCK.to_string())));
+        acpi_dev.append_child(method);
+
+        for slot in 0..self.slots.len() {
+            let mut dimm = AmlDevice::new(&format!("M{:03X}", slot));
+            dimm.append_child(AmlNameDecl::new("_HID", AmlEisaId::new("PNP0C80")));
+            dimm.append_child(AmlNameDecl::new("_UID", AmlInteger(slot as u64)));
+            for (name, call) in [("_CRS", "MCRS"), ("_STA", "MSTA"), ("_PXM", "MPXM")] {
+                let mut method = AmlMethod::new(name, 0, false);
+                method.append_child(AmlReturn::with_value(AmlCallWithArgs1::new(
+                    call,
+                    AmlInteger(slot as u64),
+                )));
+                dimm.append_child(method);
+            }
+            acpi_dev.append_child(dimm);
+        }
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALIGN: u64 = 128 << 20;
+
+    #[test]
+    fn test_mem_hotplug_alloc() {
+        let mut ctrl = MemHotplug::new((4 << 30, 4 << 30), 3);
+        let (slot, addr) = ctrl.alloc(1 << 30, None, ALIGN).unwrap();
+        assert_eq!((slot, addr), (0, 4 << 30));
+        ctrl.plug(
+            slot,
+            DimmSlot::new("d0".into(), "m0".into(), addr, 1 << 30, 0),
+            true,
+        );
+
+        // Overlap with the plugged DIMM.
+        assert!(ctrl.alloc(1 << 30, Some((4 << 30) + ALIGN), ALIGN).is_err());
+        // Misaligned address.
+        assert!(ctrl
+            .alloc(1 << 30, Some((6 << 30) | 0x1000), ALIGN)
+            .is_err());
+        // Out of the window.
+        assert!(ctrl.alloc(1 << 30, Some(7 << 30 | ALIGN), ALIGN).is_err());
+        assert!(ctrl.alloc(1 << 30, Some(3 << 30), ALIGN).is_err());
+
+        let (slot, addr) = ctrl.alloc(1 << 30, Some(6 << 30), ALIGN).unwrap();
+        assert_eq!((slot, addr), (1, 6 << 30));
+        ctrl.plug(
+            slot,
+            DimmSlot::new("d1".into(), "m1".into(), addr, 1 << 30, 0),
+            true,
+        );
+
+        // The hole between the two DIMMs.
+        assert_eq!(ctrl.alloc(1 << 30, None, ALIGN).unwrap(), (2, 5 << 30));
+        assert!(ctrl.alloc(2 << 30, None, ALIGN).is_err());
+
+        ctrl.plug(
+            2,
+            DimmSlot::new("d2".into(), "m2".into(), 5 << 30, ALIGN, 0),
+            true,
+        );
+        assert!(ctrl.alloc(ALIGN, None, ALIGN).is_err());
+        assert_eq!(ctrl.dimms().len(), 3);
+    }
+
+    #[test]
+    fn test_mem_hotplug_regs() {
+        let mut ctrl = MemHotplug::new((0x1_0000_0000, 4 << 30), 2);
+        ctrl.plug(
+            1,
+            DimmSlot::new("d0".into(), "m0".into(), 0x1_4000_0000, 0x1_0000_0000, 1),
+            true,
+        );
+        let base = GuestAddress(0);
+        let mut data = [0_u8; 4];
+        let read = |ctrl: &mut MemHotplug, offset: u64, data: &mut [u8; 4]| {
+            assert!(ctrl.read(data, base, offset));
+            u32::from_le_bytes(*data)
+        };
+
+        // Slot 0 is empty.
+        assert_eq!(read(&mut ctrl, REG_STATUS, &mut data), 0);
+
+        assert!(ctrl.write(&1_u32.to_le_bytes(), base, REG_ADDR_LO));
+        assert_eq!(read(&mut ctrl, REG_ADDR_LO, &mut data), 0x4000_0000);
+        assert_eq!(read(&mut ctrl, REG_ADDR_HI, &mut data), 0x1);
+        assert_eq!(read(&mut ctrl, REG_SIZE_LO, &mut data), 0);
+        assert_eq!(read(&mut ctrl, REG_SIZE_HI, &mut data), 0x1);
+        assert_eq!(read(&mut ctrl, REG_NODE, &mut data), 1);
+        assert_eq!(
+            read(&mut ctrl, REG_STATUS, &mut data),
+            STATUS_ENABLED | STATUS_INSERT
+        );
+
+        assert!(ctrl.write(&STATUS_INSERT.to_le_bytes(), base, REG_STATUS));
+        assert_eq!(read(&mut ctrl, REG_STATUS, &mut data), STATUS_ENABLED);
+    }
+}
@@ -10,5 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod cpu_hotplug;
 pub mod ged;
+pub mod memory_hotplug;
 pub mod power;
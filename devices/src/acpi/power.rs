@@ -14,7 +14,7 @@ use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::info;
 
 use crate::acpi::ged::{AcpiEvent, Ged};
@@ -59,6 +59,13 @@ const REG_IDX_BAT_PVOLT: usize = 7;
 const ACPI_BATTERY_STATE_DISCHARGING: u32 = 0x1;
 const ACPI_BATTERY_STATE_CHARGING: u32 = 0x2;
 
+/// Capacity of the emulated battery, unit: mWh.
+const EMU_BAT_CAPACITY: u32 = 50000;
+/// Voltage of the emulated battery, unit: mV.
+const EMU_BAT_VOLTAGE: u32 = 12000;
+/// Charging or discharging rate of the emulated battery, unit: mW.
+const EMU_BAT_RATE: u32 = 10000;
+
 const ACAD_SYSFS_DIR: &str = "/sys/class/power_supply/Mains";
 const BAT_SYSFS_DIR: &str = "/sys/class/power_supply/Battery";
 
@@ -77,6 +84,8 @@ pub struct PowerDev {
     regs: Vec<u32>,
     state: PowerDevState,
     ged: Arc<Mutex<Ged>>,
+    /// Whether the power status is set by QMP instead of mirroring the host.
+    emulated: bool,
 }
 
 impl PowerDev {
@@ -90,6 +99,7 @@ impl PowerDev {
                 last_bat_lvl: 0xffffffff,
             },
             ged: ged_dev,
+            emulated: false,
         }
     }
 
@@ -172,6 +182,83 @@ impl PowerDev {
     fn send_power_event(&self, evt: AcpiEvent) {
         self.ged.lock().unwrap().inject_acpi_event(evt);
     }
+
+    /// Set the status of the emulated AC adapter and battery. Once it is called, the
+    /// status of host is no longer mirrored. The status which is not given is kept,
+    /// and it starts with AC adapter online and battery fully charged.
+    ///
+    /// # Arguments
+    ///
+    /// * `ac_online` - Whether AC adapter is plugged.
+    /// * `battery_level` - Charge level of battery in percentage.
+    pub fn set_emulated_status(
+        &mut self,
+        ac_online: Option<bool>,
+        battery_level: Option<u8>,
+    ) -> Result<()> {
+        if let Some(level) = battery_level {
+            if level > 100 {
+                bail!("Invalid battery level {}, should be in [0, 100]", level);
+            }
+        }
+
+        if !self.emulated {
+            self.emulated = true;
+            self.regs[REG_IDX_ACAD_ON] = 1;
+            self.regs[REG_IDX_BAT_DCAP] = EMU_BAT_CAPACITY;
+            self.regs[REG_IDX_BAT_FCAP] = EMU_BAT_CAPACITY;
+            self.regs[REG_IDX_BAT_DVOLT] = EMU_BAT_VOLTAGE;
+            self.regs[REG_IDX_BAT_RCAP] = EMU_BAT_CAPACITY;
+            self.regs[REG_IDX_BAT_PVOLT] = EMU_BAT_VOLTAGE;
+            self.send_power_event(AcpiEvent::BatteryInf);
+        }
+        if let Some(online) = ac_online {
+            self.regs[REG_IDX_ACAD_ON] = online as u32;
+        }
+        if let Some(level) = battery_level {
+            self.regs[REG_IDX_BAT_RCAP] = EMU_BAT_CAPACITY / 100 * level as u32;
+        }
+
+        let online = self.regs[REG_IDX_ACAD_ON] == 1;
+        self.regs[REG_IDX_BAT_STATE] = if !online {
+            ACPI_BATTERY_STATE_DISCHARGING
+        } else if self.regs[REG_IDX_BAT_RCAP] < EMU_BAT_CAPACITY {
+            ACPI_BATTERY_STATE_CHARGING
+        } else {
+            0
+        };
+        self.regs[REG_IDX_BAT_PRATE] = if self.regs[REG_IDX_BAT_STATE] == 0 {
+            0
+        } else {
+            EMU_BAT_RATE
+        };
+
+        if self.state.last_acad_st != self.regs[REG_IDX_ACAD_ON] {
+            self.send_power_event(AcpiEvent::AcadSt);
+            self.state.last_acad_st = self.regs[REG_IDX_ACAD_ON];
+        }
+        if self.state.last_bat_st != self.regs[REG_IDX_BAT_STATE]
+            || self.state.last_bat_lvl != self.regs[REG_IDX_BAT_RCAP]
+        {
+            self.send_power_event(AcpiEvent::BatterySt);
+            self.state.last_bat_st = self.regs[REG_IDX_BAT_STATE];
+            self.state.last_bat_lvl = self.regs[REG_IDX_BAT_RCAP];
+        }
+        Ok(())
+    }
+
+    /// Get the status of AC adapter and battery, returns whether AC adapter is
+    /// plugged, the charge level of battery in percentage and whether it's charging.
+    pub fn get_status(&self) -> (bool, u8, bool) {
+        let level = (self.regs[REG_IDX_BAT_RCAP] as u64 * 100)
+            .checked_div(self.regs[REG_IDX_BAT_FCAP] as u64)
+            .map_or(0, |level| std::cmp::min(level, 100) as u8);
+        (
+            self.regs[REG_IDX_ACAD_ON] == 1,
+            level,
+            self.regs[REG_IDX_BAT_STATE] == ACPI_BATTERY_STATE_CHARGING,
+        )
+    }
 }
 
 impl PowerDev {
@@ -180,7 +267,7 @@ impl PowerDev {
         sysbus: &mut SysBus,
         region_base: u64,
         region_size: u64,
-    ) -> Result<()> {
+    ) -> Result<Arc<Mutex<PowerDev>>> {
         self.set_sys_resource(sysbus, region_base, region_size)
             .with_context(|| AcpiError::Alignment(region_size.try_into().unwrap()))?;
 
@@ -201,7 +288,7 @@ impl PowerDev {
             let mut pdev = dev.lock().unwrap();
             pdev.power_load_static_status();
         }
-        Ok(())
+        Ok(dev)
     }
 }
 
@@ -407,6 +494,10 @@ fn power_status_update(dev: &Arc<Mutex<PowerDev>>) {
     });
 
     let mut pdev = dev.lock().unwrap();
+    // Stop mirroring the host once the status is set by QMP.
+    if pdev.emulated {
+        return;
+    }
 
     if pdev.power_status_read().is_ok() {
         let step2notify: u32 = pdev.regs[REG_IDX_BAT_FCAP] / 100;
@@ -429,3 +520,64 @@ fn power_status_update(dev: &Arc<Mutex<PowerDev>>) {
         pdev.power_load_static_status();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_emulated_status() {
+        let ged = Arc::new(Mutex::new(Ged::default()));
+        let mut pdev = PowerDev::new(ged.clone());
+        let base = GuestAddress(0);
+        let mut data = [0_u8; 4];
+        let mut events = || {
+            assert!(ged.lock().unwrap().read(&mut data, base, 0));
+            u32::from_le_bytes(data)
+        };
+        let reg = |pdev: &mut PowerDev, idx: usize| {
+            let mut data = [0_u8; 4];
+            assert!(pdev.read(&mut data, base, idx as u64 * 4));
+            u32::from_le_bytes(data)
+        };
+
+        assert!(pdev.set_emulated_status(None, Some(101)).is_err());
+        assert_eq!(events(), AcpiEvent::Nothing as u32);
+
+        // Starts with AC adapter online and battery fully charged.
+        pdev.set_emulated_status(None, None).unwrap();
+        assert_eq!(
+            events(),
+            AcpiEvent::BatteryInf as u32 | AcpiEvent::BatterySt as u32
+        );
+        assert_eq!(pdev.get_status(), (true, 100, false));
+        assert_eq!(reg(&mut pdev, REG_IDX_BAT_DCAP), EMU_BAT_CAPACITY);
+        assert_eq!(reg(&mut pdev, REG_IDX_BAT_STATE), 0);
+        assert_eq!(reg(&mut pdev, REG_IDX_BAT_PRATE), 0);
+
+        pdev.set_emulated_status(Some(false), Some(30)).unwrap();
+        assert_eq!(
+            events(),
+            AcpiEvent::AcadSt as u32 | AcpiEvent::BatterySt as u32
+        );
+        assert_eq!(pdev.get_status(), (false, 30, false));
+        assert_eq!(reg(&mut pdev, REG_IDX_ACAD_ON), 0);
+        assert_eq!(
+            reg(&mut pdev, REG_IDX_BAT_STATE),
+            ACPI_BATTERY_STATE_DISCHARGING
+        );
+        assert_eq!(reg(&mut pdev, REG_IDX_BAT_RCAP), EMU_BAT_CAPACITY * 3 / 10);
+        assert_eq!(reg(&mut pdev, REG_IDX_BAT_PRATE), EMU_BAT_RATE);
+
+        pdev.set_emulated_status(Some(true), None).unwrap();
+        assert_eq!(
+            events(),
+            AcpiEvent::AcadSt as u32 | AcpiEvent::BatterySt as u32
+        );
+        assert_eq!(pdev.get_status(), (true, 30, true));
+
+        // Nothing changes.
+        pdev.set_emulated_status(Some(true), Some(30)).unwrap();
+        assert_eq!(events(), AcpiEvent::Nothing as u32);
+    }
+}
@@ -435,6 +435,15 @@ impl FwCfgCommon {
         }
     }
 
+    // Get the data of a FwCfgEntry
+    fn get_entry_data(&self, key: FwCfgEntryType) -> Vec<u8> {
+        let key = (key as u16) & FW_CFG_ENTRY_MASK;
+        self.entries
+            .get(key as usize)
+            .map(|e| e.data.clone())
+            .unwrap_or_default()
+    }
+
     fn add_file_callback(
         &mut self,
         filename: &str,
@@ -1213,6 +1222,15 @@ pub trait FwCfgOps {
             .add_entry(key, None, None, bytes, false)
     }
 
+    /// Get raw data bytes of an entry, it's empty if the entry is not added.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - FwCfgEntryType
+    fn get_entry_data(&mut self, key: FwCfgEntryType) -> Vec<u8> {
+        self.fw_cfg_common().get_entry_data(key)
+    }
+
     /// Add a file entry to FwCfg device, with select callback function and
     /// write callback function.
     ///
@@ -1400,6 +1418,13 @@ mod test {
             fwcfg_common.entries[FwCfgEntryType::CmdlineData as usize].data,
             cmdline.as_bytes().to_vec()
         );
+        assert_eq!(
+            fwcfg_common.get_entry_data(FwCfgEntryType::CmdlineData),
+            cmdline.as_bytes().to_vec()
+        );
+        assert!(fwcfg_common
+            .get_entry_data(FwCfgEntryType::InitrdData)
+            .is_empty());
 
         let boot_order = Vec::<u8>::new();
         fwcfg_common
@@ -0,0 +1,682 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{error, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AmlBuilder, AmlDevice, AmlEisaId, AmlInteger, AmlMemory32Fixed, AmlNameDecl, AmlReadAndWrite,
+    AmlResTemplate, AmlScopeBuilder,
+};
+use address_space::GuestAddress;
+use machine_manager::event_loop::EventLoop;
+use migration::{
+    snapshot::HPET_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
+    MigrationManager, StateTransfer,
+};
+use migration_derive::{ByteCode, Desc};
+use util::byte_code::ByteCode;
+
+/// Number of comparators, the minimum number required by the specification.
+const HPET_NUM_TIMERS: usize = 3;
+/// Main counter runs at 100MHz, the period is in femtoseconds.
+const HPET_CLK_PERIOD_FS: u64 = 10_000_000;
+const HPET_CLK_PERIOD_NS: u64 = 10;
+const HPET_VENDOR_ID: u64 = 0x8086;
+
+/// General capabilities and ID register: revision 1, 64-bit main counter.
+pub const HPET_CAPABILITIES: u64 = (HPET_CLK_PERIOD_FS << 32)
+    | (HPET_VENDOR_ID << 16)
+    | HPET_CAP_COUNT_SIZE
+    | (((HPET_NUM_TIMERS - 1) as u64) << 8)
+    | 0x01;
+const HPET_CAP_COUNT_SIZE: u64 = 1 << 13;
+
+// Offsets of general registers.
+const HPET_ID: u64 = 0x000;
+const HPET_CFG: u64 = 0x010;
+const HPET_STATUS: u64 = 0x020;
+const HPET_COUNTER: u64 = 0x0F0;
+const HPET_TIMER_BASE: u64 = 0x100;
+const HPET_TIMER_SIZE: u64 = 0x20;
+
+// Offsets of registers in each timer block.
+const HPET_TN_CFG: u64 = 0x00;
+const HPET_TN_CMP: u64 = 0x08;
+
+const HPET_CFG_ENABLE: u64 = 1 << 0;
+
+const HPET_TN_TYPE_LEVEL: u64 = 1 << 1;
+const HPET_TN_ENABLE: u64 = 1 << 2;
+const HPET_TN_PERIODIC: u64 = 1 << 3;
+const HPET_TN_PERIODIC_CAP: u64 = 1 << 4;
+const HPET_TN_SIZE_CAP: u64 = 1 << 5;
+const HPET_TN_SETVAL: u64 = 1 << 6;
+const HPET_TN_32BIT: u64 = 1 << 8;
+const HPET_TN_INT_ROUTE_SHIFT: u64 = 9;
+const HPET_TN_INT_ROUTE_MASK: u64 = 0x1F << HPET_TN_INT_ROUTE_SHIFT;
+const HPET_TN_CFG_WRITE_MASK: u64 = HPET_TN_TYPE_LEVEL
+    | HPET_TN_ENABLE
+    | HPET_TN_PERIODIC
+    | HPET_TN_SETVAL
+    | HPET_TN_32BIT
+    | HPET_TN_INT_ROUTE_MASK;
+
+/// State of one comparator.
+#[derive(Clone, Copy, Default)]
+struct HpetTimer {
+    config: u64,
+    /// Value of the comparator.
+    cmp: u64,
+    /// Period in ticks, used in periodic mode.
+    period: u64,
+    /// Timer of event loop which is armed for the comparator.
+    timer_id: Option<u64>,
+    /// Bumped each time the timer is re-armed, so that the stale callback which has
+    /// been popped by event loop is ignored.
+    generation: u64,
+}
+
+impl HpetTimer {
+    fn new() -> Self {
+        Self {
+            config: HPET_TN_PERIODIC_CAP | HPET_TN_SIZE_CAP,
+            cmp: u64::MAX,
+            ..Default::default()
+        }
+    }
+
+    fn is_32bit(&self) -> bool {
+        self.config & HPET_TN_32BIT != 0
+    }
+
+    fn is_periodic(&self) -> bool {
+        self.config & HPET_TN_PERIODIC != 0
+    }
+
+    fn width_mask(&self) -> u64 {
+        if self.is_32bit() {
+            u64::from(u32::MAX)
+        } else {
+            u64::MAX
+        }
+    }
+
+    /// Ticks from `counter` to the comparator, a comparator which has been passed
+    /// fires at once.
+    fn ticks_to_fire(&self, counter: u64) -> u64 {
+        if self.is_32bit() {
+            let diff = (self.cmp as u32).wrapping_sub(counter as u32);
+            if diff as i32 > 0 {
+                u64::from(diff)
+            } else {
+                0
+            }
+        } else {
+            let diff = self.cmp.wrapping_sub(counter);
+            if diff as i64 > 0 {
+                diff
+            } else {
+                0
+            }
+        }
+    }
+}
+
+/// Migration state of HPET, the event loop timers of comparators are armed again
+/// after restored.
+#[repr(C)]
+#[derive(Copy, Clone, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+struct HpetState {
+    config: u64,
+    isr: u64,
+    /// Value of main counter when the state is saved.
+    counter: u64,
+    timer_config: [u64; HPET_NUM_TIMERS],
+    timer_cmp: [u64; HPET_NUM_TIMERS],
+    timer_period: [u64; HPET_NUM_TIMERS],
+}
+
+/// High Precision Event Timer, which is found by guest through ACPI HPET table.
+/// Legacy replacement route and FSB interrupt delivery are not supported, all the
+/// comparators share the IRQ allocated from system bus.
+pub struct Hpet {
+    base: SysBusDevBase,
+    /// General configuration register.
+    config: u64,
+    /// General interrupt status register, only used by level-triggered comparators.
+    isr: u64,
+    /// Value of main counter when it is halted, or when it is started at `clock_base`.
+    counter: u64,
+    /// Virtual clock when main counter is started.
+    clock_base: Duration,
+    timers: [HpetTimer; HPET_NUM_TIMERS],
+    /// Self reference, used by the timer callbacks.
+    hpet: Weak<Mutex<Hpet>>,
+}
+
+impl Hpet {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            base: SysBusDevBase {
+                dev_type: SysBusDevType::Others,
+                interrupt_evt: Some(Arc::new(EventFd::new(libc::EFD_NONBLOCK)?)),
+                ..Default::default()
+            },
+            config: 0,
+            isr: 0,
+            counter: 0,
+            clock_base: Duration::ZERO,
+            timers: [HpetTimer::new(); HPET_NUM_TIMERS],
+            hpet: Weak::new(),
+        })
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<Arc<Mutex<Self>>> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to set system resource of HPET")?;
+
+        let dev = Arc::new(Mutex::new(self));
+        dev.lock().unwrap().hpet = Arc::downgrade(&dev);
+        sysbus.attach_device(&dev, region_base, region_size, "HPET")?;
+        MigrationManager::register_device_instance(
+            HpetState::descriptor(),
+            dev.clone(),
+            HPET_SNAPSHOT_ID,
+        );
+        Ok(dev)
+    }
+
+    fn clock() -> Duration {
+        EventLoop::get_ctx(None).unwrap().get_virtual_clock()
+    }
+
+    fn enabled(&self) -> bool {
+        self.config & HPET_CFG_ENABLE != 0
+    }
+
+    fn get_counter(&self) -> u64 {
+        if !self.enabled() {
+            return self.counter;
+        }
+        let elapsed = Self::clock().saturating_sub(self.clock_base).as_nanos() as u64;
+        self.counter.wrapping_add(elapsed / HPET_CLK_PERIOD_NS)
+    }
+
+    fn set_counter(&mut self, counter: u64) {
+        self.counter = counter;
+        self.clock_base = Self::clock();
+    }
+
+    fn disarm_timer(&mut self, index: usize) {
+        let timer = &mut self.timers[index];
+        timer.generation = timer.generation.wrapping_add(1);
+        if let Some(id) = timer.timer_id.take() {
+            EventLoop::get_ctx(None).unwrap().timer_del(id);
+        }
+    }
+
+    /// Arm the event loop timer for comparator `index` if it's enabled.
+    fn arm_timer(&mut self, index: usize) {
+        self.disarm_timer(index);
+        if !self.enabled() || self.timers[index].config & HPET_TN_ENABLE == 0 {
+            return;
+        }
+
+        let ticks = self.timers[index].ticks_to_fire(self.get_counter());
+        let generation = self.timers[index].generation;
+        let hpet = self.hpet.clone();
+        let func = Box::new(move || {
+            if let Some(hpet) = hpet.upgrade() {
+                hpet.lock().unwrap().timer_expired(index, generation);
+            }
+        });
+        self.timers[index].timer_id = Some(
+            EventLoop::get_ctx(None)
+                .unwrap()
+                .timer_add(func, Duration::from_nanos(ticks * HPET_CLK_PERIOD_NS)),
+        );
+    }
+
+    fn timer_expired(&mut self, index: usize, generation: u64) {
+        let timer = &mut self.timers[index];
+        if timer.generation != generation || timer.timer_id.take().is_none() {
+            return;
+        }
+
+        // Virtual clock stops when VM is paused, wait for the rest ticks.
+        let counter = self.get_counter();
+        let timer = &mut self.timers[index];
+        if timer.ticks_to_fire(counter) > 0 {
+            self.arm_timer(index);
+            return;
+        }
+
+        if timer.is_periodic() && timer.period != 0 {
+            // Move the comparator to the first period after current counter.
+            let mask = timer.width_mask();
+            let passed = counter.wrapping_sub(timer.cmp) & mask;
+            let periods = passed / timer.period + 1;
+            timer.cmp = timer.cmp.wrapping_add(periods.wrapping_mul(timer.period)) & mask;
+            self.arm_timer(index);
+        }
+        self.update_irq(index);
+    }
+
+    fn update_irq(&mut self, index: usize) {
+        let config = self.timers[index].config;
+        let route = (config & HPET_TN_INT_ROUTE_MASK) >> HPET_TN_INT_ROUTE_SHIFT;
+        if config & HPET_TN_TYPE_LEVEL != 0 {
+            // The interrupt is asserted until guest clears the status bit.
+            if self.isr & (1 << index) != 0 {
+                return;
+            }
+            self.isr |= 1 << index;
+        }
+        if config & HPET_TN_ENABLE == 0 || route != self.base.res.irq as u64 {
+            return;
+        }
+        if let Some(evt) = self.interrupt_evt() {
+            if let Err(e) = evt.write(1) {
+                error!("HPET: failed to write interrupt eventfd ({:?}).", e);
+            }
+        }
+    }
+
+    fn write_config(&mut self, value: u64) {
+        let old = self.config;
+        self.config = value & HPET_CFG_ENABLE;
+        if (old ^ self.config) & HPET_CFG_ENABLE == 0 {
+            return;
+        }
+        if self.enabled() {
+            self.clock_base = Self::clock();
+        } else {
+            self.counter = self.counter.wrapping_add(
+                Self::clock().saturating_sub(self.clock_base).as_nanos() as u64
+                    / HPET_CLK_PERIOD_NS,
+            );
+        }
+        for i in 0..HPET_NUM_TIMERS {
+            self.arm_timer(i);
+        }
+    }
+
+    fn write_timer_config(&mut self, index: usize, value: u64) {
+        let timer = &mut self.timers[index];
+        let old = timer.config;
+        timer.config = (old & !HPET_TN_CFG_WRITE_MASK) | (value & HPET_TN_CFG_WRITE_MASK);
+        if timer.is_32bit() {
+            timer.cmp &= u64::from(u32::MAX);
+            timer.period &= u64::from(u32::MAX);
+        }
+        if timer.config & HPET_TN_TYPE_LEVEL == 0 {
+            self.isr &= !(1 << index);
+        }
+        if (old ^ timer.config) & (HPET_TN_ENABLE | HPET_TN_PERIODIC | HPET_TN_32BIT) != 0 {
+            self.arm_timer(index);
+        }
+    }
+
+    fn write_timer_cmp(&mut self, index: usize, value: u64) {
+        let timer = &mut self.timers[index];
+        let value = value & timer.width_mask();
+        // In periodic mode, comparator is written directly only with SETVAL set,
+        // otherwise the value written is the period.
+        if !timer.is_periodic() || timer.config & HPET_TN_SETVAL != 0 {
+            timer.cmp = value;
+        }
+        timer.period = value;
+        timer.config &= !HPET_TN_SETVAL;
+        self.arm_timer(index);
+    }
+
+    fn read_reg(&self, offset: u64) -> u64 {
+        match offset {
+            HPET_ID => HPET_CAPABILITIES,
+            HPET_CFG => self.config,
+            HPET_STATUS => self.isr,
+            HPET_COUNTER => self.get_counter(),
+            HPET_TIMER_BASE.. => {
+                let index = ((offset - HPET_TIMER_BASE) / HPET_TIMER_SIZE) as usize;
+                let Some(timer) = self.timers.get(index) else {
+                    return 0;
+                };
+                match (offset - HPET_TIMER_BASE) % HPET_TIMER_SIZE {
+                    // All comparators can be routed to the IRQ of HPET only, which is
+                    // not assigned before the device is realized.
+                    HPET_TN_CFG => {
+                        let route =
+                            u64::try_from(self.base.res.irq).map_or(0, |irq| 1 << (irq + 32));
+                        timer.config | route
+                    }
+                    HPET_TN_CMP => timer.cmp,
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, offset: u64, value: u64) {
+        match offset {
+            HPET_CFG => self.write_config(value),
+            HPET_STATUS => self.isr &= !value,
+            HPET_COUNTER => {
+                if self.enabled() {
+                    warn!("HPET: main counter is written while it's running");
+                }
+                self.set_counter(value);
+                for i in 0..HPET_NUM_TIMERS {
+                    self.arm_timer(i);
+                }
+            }
+            HPET_TIMER_BASE.. => {
+                let index = ((offset - HPET_TIMER_BASE) / HPET_TIMER_SIZE) as usize;
+                if index >= HPET_NUM_TIMERS {
+                    return;
+                }
+                match (offset - HPET_TIMER_BASE) % HPET_TIMER_SIZE {
+                    HPET_TN_CFG => self.write_timer_config(index, value),
+                    HPET_TN_CMP => self.write_timer_cmp(index, value),
+                    // FSB interrupt delivery is not supported.
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Device for Hpet {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for Hpet {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        // Registers are 64-bit, which can also be accessed as two 32-bit halves.
+        let shift = (offset & 0x4) * 8;
+        let value = self.read_reg(offset & !0x7) >> shift;
+        match data.len() {
+            4 if offset & 0x3 == 0 => data.copy_from_slice(&(value as u32).to_le_bytes()),
+            8 if offset & 0x7 == 0 => data.copy_from_slice(&value.to_le_bytes()),
+            _ => {
+                error!(
+                    "HPET: invalid read, offset 0x{:x}, size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        let reg = offset & !0x7;
+        let value = match data.len() {
+            4 if offset & 0x3 == 0 => {
+                let shift = (offset & 0x4) * 8;
+                let old = self.read_reg(reg);
+                let new = u64::from(u32::from_le_bytes(data.try_into().unwrap()));
+                (old & !(u64::from(u32::MAX) << shift)) | (new << shift)
+            }
+            8 if offset & 0x7 == 0 => u64::from_le_bytes(data.try_into().unwrap()),
+            _ => {
+                error!(
+                    "HPET: invalid write, offset 0x{:x}, size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+        };
+        self.write_reg(reg, value);
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        for i in 0..HPET_NUM_TIMERS {
+            self.disarm_timer(i);
+            let generation = self.timers[i].generation;
+            self.timers[i] = HpetTimer {
+                generation,
+                ..HpetTimer::new()
+            };
+        }
+        self.config = 0;
+        self.isr = 0;
+        self.counter = 0;
+        Ok(())
+    }
+}
+
+impl AmlBuilder for Hpet {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("HPET");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlEisaId::new("PNP0103")));
+        acpi_dev.append_child(AmlNameDecl::new("_UID", AmlInteger(0)));
+
+        let mut res = AmlResTemplate::new();
+        res.append_child(AmlMemory32Fixed::new(
+            AmlReadAndWrite::ReadOnly,
+            self.base.res.region_base as u32,
+            self.base.res.region_size as u32,
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_CRS", res));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+impl StateTransfer for Hpet {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        let mut state = HpetState {
+            config: self.config,
+            isr: self.isr,
+            counter: self.get_counter(),
+            timer_config: [0; HPET_NUM_TIMERS],
+            timer_cmp: [0; HPET_NUM_TIMERS],
+            timer_period: [0; HPET_NUM_TIMERS],
+        };
+        for (i, timer) in self.timers.iter().enumerate() {
+            state.timer_config[i] = timer.config;
+            state.timer_cmp[i] = timer.cmp;
+            state.timer_period[i] = timer.period;
+        }
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state =
+            HpetState::from_bytes(state).with_context(|| MigrationError::FromBytesError("HPET"))?;
+        for i in 0..HPET_NUM_TIMERS {
+            self.disarm_timer(i);
+            let timer = &mut self.timers[i];
+            timer.config = state.timer_config[i];
+            timer.cmp = state.timer_cmp[i];
+            timer.period = state.timer_period[i];
+        }
+        self.config = state.config;
+        self.isr = state.isr;
+        self.set_counter(state.counter);
+
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&HpetState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Hpet {
+    fn resume(&mut self) -> migration::Result<()> {
+        for i in 0..HPET_NUM_TIMERS {
+            self.arm_timer(i);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u64(hpet: &mut Hpet, offset: u64) -> u64 {
+        let mut data = [0_u8; 8];
+        assert!(hpet.read(&mut data, GuestAddress(0), offset));
+        u64::from_le_bytes(data)
+    }
+
+    fn write_u32(hpet: &mut Hpet, offset: u64, value: u32) {
+        assert!(hpet.write(&value.to_le_bytes(), GuestAddress(0), offset));
+    }
+
+    #[test]
+    fn test_hpet_registers() {
+        EventLoop::object_init(&None).unwrap();
+        let mut hpet = Hpet::new().unwrap();
+        hpet.base.res.irq = 8;
+
+        let id = read_u64(&mut hpet, HPET_ID);
+        assert_eq!(id >> 32, HPET_CLK_PERIOD_FS);
+        assert_eq!((id >> 8) & 0x1F, (HPET_NUM_TIMERS - 1) as u64);
+        let mut data = [0_u8; 4];
+        assert!(hpet.read(&mut data, GuestAddress(0), HPET_ID + 4));
+        assert_eq!(u64::from(u32::from_le_bytes(data)), HPET_CLK_PERIOD_FS);
+        // Unaligned access.
+        assert!(!hpet.read(&mut data, GuestAddress(0), HPET_ID + 2));
+
+        let tn_cfg = HPET_TIMER_BASE + HPET_TIMER_SIZE + HPET_TN_CFG;
+        let cfg = read_u64(&mut hpet, tn_cfg);
+        assert_eq!(cfg >> 32, 1 << 8);
+        assert_ne!(cfg & HPET_TN_PERIODIC_CAP, 0);
+
+        // Capabilities of timer are read only.
+        write_u32(&mut hpet, tn_cfg, 0xFFFF_FFFF);
+        let cfg = read_u64(&mut hpet, tn_cfg);
+        assert_eq!(cfg & 0xFFFF_FFFF, HPET_TN_CFG_WRITE_MASK | 0x30);
+        // Comparator of 32-bit mode, SETVAL is cleared after comparator is written.
+        write_u32(&mut hpet, tn_cfg - HPET_TN_CFG + HPET_TN_CMP + 4, 0x1234);
+        assert_eq!(
+            read_u64(&mut hpet, tn_cfg - HPET_TN_CFG + HPET_TN_CMP),
+            0xFFFF_FFFF
+        );
+        assert_eq!(read_u64(&mut hpet, tn_cfg) & HPET_TN_SETVAL, 0);
+
+        // Main counter can be set when it's halted.
+        write_u32(&mut hpet, HPET_COUNTER, 0x5678);
+        write_u32(&mut hpet, HPET_COUNTER + 4, 0x1);
+        assert_eq!(read_u64(&mut hpet, HPET_COUNTER), 0x1_0000_5678);
+
+        write_u32(&mut hpet, HPET_CFG, 0x3);
+        assert_eq!(read_u64(&mut hpet, HPET_CFG), HPET_CFG_ENABLE);
+        write_u32(&mut hpet, HPET_CFG, 0);
+
+        hpet.reset().unwrap();
+        assert_eq!(read_u64(&mut hpet, HPET_COUNTER), 0);
+        assert_eq!(read_u64(&mut hpet, tn_cfg) & 0xFFFF_FFFF, 0x30);
+    }
+
+    #[test]
+    fn test_hpet_timer_expired() {
+        EventLoop::object_init(&None).unwrap();
+        let mut hpet = Hpet::new().unwrap();
+        hpet.base.res.irq = 8;
+        let evt = hpet.interrupt_evt().unwrap();
+
+        // Level-triggered comparator 0 routed to IRQ 8, the comparator has been passed.
+        let cfg = HPET_TN_TYPE_LEVEL | HPET_TN_ENABLE | (8 << HPET_TN_INT_ROUTE_SHIFT);
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CFG, cfg as u32);
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CMP, 0);
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CMP + 4, 0);
+        write_u32(&mut hpet, HPET_CFG, HPET_CFG_ENABLE as u32);
+        let generation = hpet.timers[0].generation;
+        assert!(hpet.timers[0].timer_id.is_some());
+
+        hpet.timer_expired(0, generation);
+        assert_eq!(read_u64(&mut hpet, HPET_STATUS), 1);
+        assert_eq!(evt.read().unwrap(), 1);
+        assert!(hpet.timers[0].timer_id.is_none());
+
+        // Stale callback is ignored.
+        write_u32(&mut hpet, HPET_STATUS, 1);
+        hpet.timer_expired(0, generation);
+        assert_eq!(read_u64(&mut hpet, HPET_STATUS), 0);
+
+        write_u32(&mut hpet, HPET_CFG, 0);
+        assert!(hpet.timers[0].timer_id.is_none());
+    }
+
+    #[test]
+    fn test_hpet_state_transfer() {
+        EventLoop::object_init(&None).unwrap();
+        let mut hpet = Hpet::new().unwrap();
+        let cfg = HPET_TN_ENABLE | HPET_TN_PERIODIC | HPET_TN_32BIT | HPET_TN_SETVAL;
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CFG, cfg as u32);
+        // Comparator is set with SETVAL, and then the period is set.
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CMP, 0x1000);
+        write_u32(&mut hpet, HPET_TIMER_BASE + HPET_TN_CMP, 0x100);
+        write_u32(&mut hpet, HPET_COUNTER, 0x800);
+        let state = hpet.get_state_vec().unwrap();
+
+        let mut dst = Hpet::new().unwrap();
+        dst.set_state_mut(&state).unwrap();
+        assert_eq!(read_u64(&mut dst, HPET_COUNTER), 0x800);
+        assert_eq!(read_u64(&mut dst, HPET_TIMER_BASE + HPET_TN_CMP), 0x1000);
+        assert_eq!(dst.timers[0].period, 0x100);
+        assert_eq!(
+            read_u64(&mut dst, HPET_TIMER_BASE + HPET_TN_CFG) & 0xFFFF_FFFF,
+            read_u64(&mut hpet, HPET_TIMER_BASE + HPET_TN_CFG) & 0xFFFF_FFFF
+        );
+
+        // Timers are armed when resumed if main counter is enabled.
+        write_u32(&mut hpet, HPET_CFG, HPET_CFG_ENABLE as u32);
+        dst.set_state_mut(&hpet.get_state_vec().unwrap()).unwrap();
+        assert!(dst.timers[0].timer_id.is_none());
+        dst.resume().unwrap();
+        assert!(dst.timers[0].timer_id.is_some());
+        write_u32(&mut hpet, HPET_CFG, 0);
+        write_u32(&mut dst, HPET_CFG, 0);
+    }
+}
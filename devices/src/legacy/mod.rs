@@ -19,6 +19,7 @@
 //! This module offers support for:
 //! 1. Pl031 device, Arm PrimeCell Real Time Clock.
 //! 2. Serial device, Serial UART.
+//! 3. HPET device, High Precision Event Timer.
 //!
 //! ## Platform Support
 //!
@@ -28,6 +29,8 @@
 pub mod error;
 
 mod fwcfg;
+#[cfg(target_arch = "x86_64")]
+mod hpet;
 mod pflash;
 #[cfg(target_arch = "aarch64")]
 mod pl011;
@@ -37,6 +40,7 @@ mod pl031;
 mod ramfb;
 #[cfg(target_arch = "x86_64")]
 mod rtc;
+mod rtc_clock;
 mod serial;
 
 pub use anyhow::Result;
@@ -48,7 +52,9 @@ pub use error::LegacyError;
 pub use fwcfg::FwCfgIO;
 #[cfg(target_arch = "aarch64")]
 pub use fwcfg::FwCfgMem;
-pub use fwcfg::{FwCfgEntryType, FwCfgOps};
+pub use fwcfg::{FwCfgEntryType, FwCfgOps, FwCfgWriteCallback};
+#[cfg(target_arch = "x86_64")]
+pub use hpet::{Hpet, HPET_CAPABILITIES};
 pub use pflash::PFlash;
 #[cfg(target_arch = "aarch64")]
 pub use pl011::PL011;
@@ -466,6 +466,9 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
+            logfile: None,
+            logappend: false,
         };
         let mut pl011_dev = PL011::new(SerialConfig {
             chardev: chardev_cfg,
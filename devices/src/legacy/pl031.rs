@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
@@ -19,10 +19,12 @@ use log::error;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::error::LegacyError;
+use super::rtc_clock::RtcClockSource;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::AmlBuilder;
 use address_space::GuestAddress;
+use machine_manager::config::RtcConfig;
 use migration::{
     snapshot::PL031_SNAPSHOT_ID, DeviceStateDesc, FieldDesc, MigrationError, MigrationHook,
     MigrationManager, StateTransfer,
@@ -75,26 +77,35 @@ pub struct PL031 {
     state: PL031State,
     /// The duplicate of Load register value.
     tick_offset: u32,
-    /// Record the real time.
-    base_time: Instant,
+    /// Clock value when the tick offset is set.
+    base_time: Duration,
+    /// Clock which RTC time advances with.
+    clock: RtcClockSource,
 }
 
 impl Default for PL031 {
     fn default() -> Self {
+        Self::new(&RtcConfig::default())
+    }
+}
+
+impl PL031 {
+    /// Construct function of PL031 device.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Start time and clock of RTC.
+    pub fn new(config: &RtcConfig) -> Self {
+        let clock = RtcClockSource::new(config.clock);
         Self {
             base: SysBusDevBase::new(SysBusDevType::Rtc),
             state: PL031State::default(),
-            // since 1970-01-01 00:00:00,it never cause overflow.
-            tick_offset: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("time wrong")
-                .as_secs() as u32,
-            base_time: Instant::now(),
+            tick_offset: config.start_time() as u32,
+            base_time: clock.now(),
+            clock,
         }
     }
-}
 
-impl PL031 {
     pub fn realize(
         mut self,
         sysbus: &mut SysBus,
@@ -119,7 +130,8 @@ impl PL031 {
 
     /// Get current clock value.
     fn get_current_value(&self) -> u32 {
-        (self.base_time.elapsed().as_secs() as u128 + self.tick_offset as u128) as u32
+        let elapsed = self.clock.now().saturating_sub(self.base_time);
+        (elapsed.as_secs() as u128 + self.tick_offset as u128) as u32
     }
 
     fn inject_interrupt(&self) {
@@ -190,7 +202,7 @@ impl SysBusDevOps for PL031 {
             RTC_LR => {
                 self.state.lr = value;
                 self.tick_offset = value;
-                self.base_time = Instant::now();
+                self.base_time = self.clock.now();
             }
             RTC_IMSC => {
                 self.state.imsr = value & 1;
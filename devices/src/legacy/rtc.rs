@@ -11,12 +11,13 @@
 // See the Mulan PSL v2 for more details.
 
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
 use anyhow::Result;
 use log::{debug, error, warn};
 use vmm_sys_util::eventfd::EventFd;
 
+use super::rtc_clock::RtcClockSource;
 use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
 use crate::{Device, DeviceBase};
 use acpi::{
@@ -24,6 +25,7 @@ use acpi::{
     AmlResTemplate, AmlScopeBuilder,
 };
 use address_space::GuestAddress;
+use machine_manager::config::RtcConfig;
 use util::time::{mktime64, NANOSECONDS_PER_SECOND};
 
 /// IO port of RTC device to select Register to read/write.
@@ -111,13 +113,20 @@ pub struct RTC {
     gap_start: u64,
     /// The tick offset.
     tick_offset: u64,
-    /// Record the real time.
-    base_time: Instant,
+    /// Clock value when the tick offset is set.
+    base_time: Duration,
+    /// Clock which RTC time advances with.
+    clock: RtcClockSource,
 }
 
 impl RTC {
     /// Construct function of RTC device.
-    pub fn new() -> Result<RTC> {
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Start time and clock of RTC.
+    pub fn new(config: &RtcConfig) -> Result<RTC> {
+        let clock = RtcClockSource::new(config.clock);
         let mut rtc = RTC {
             base: SysBusDevBase {
                 base: DeviceBase::default(),
@@ -133,12 +142,9 @@ impl RTC {
             cur_index: 0_u8,
             mem_size: 0,
             gap_start: 0,
-            // Since 1970-01-01 00:00:00, it never cause overflow.
-            tick_offset: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("time wrong")
-                .as_secs(),
-            base_time: Instant::now(),
+            tick_offset: config.start_time(),
+            base_time: clock.now(),
+            clock,
         };
 
         let tm = rtc_time_to_tm(rtc.get_current_value());
@@ -285,7 +291,12 @@ impl RTC {
 
     /// Get current clock value.
     fn get_current_value(&self) -> i64 {
-        (self.base_time.elapsed().as_secs() as i128 + self.tick_offset as i128) as i64
+        (self.elapsed().as_secs() as i128 + self.tick_offset as i128) as i64
+    }
+
+    /// Get the time elapsed since the tick offset is set.
+    fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.base_time)
     }
 
     fn set_rtc_cmos(&mut self, tm: libc::tm) {
@@ -349,11 +360,11 @@ impl RTC {
 
         self.tick_offset = mktime64(year, mon, day, hour, min, sec);
 
-        self.base_time = Instant::now();
+        self.base_time = self.clock.now();
     }
 
     fn update_in_progress(&self) -> bool {
-        self.base_time.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
+        self.elapsed().subsec_nanos() >= (NANOSECONDS_PER_SECOND - UIP_HOLD_LENGTH) as u32
     }
 }
 
@@ -436,6 +447,7 @@ mod test {
 
     use super::*;
     use address_space::GuestAddress;
+    use machine_manager::config::{RtcBase, RtcClock};
 
     const WIGGLE: u8 = 2;
 
@@ -455,7 +467,8 @@ mod test {
 
     #[test]
     fn test_set_year_20xx() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc time: 2013-11-13 02:04:56
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x20);
         cmos_write(&mut rtc, RTC_YEAR, 0x13);
@@ -489,7 +502,8 @@ mod test {
 
     #[test]
     fn test_set_year_1970() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc time (min): 1970-01-01 00:00:00
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x19);
         cmos_write(&mut rtc, RTC_YEAR, 0x70);
@@ -510,9 +524,30 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_rtc_base_datetime() -> Result<()> {
+        // Start rtc from 2013-11-13 02:04:56.
+        let config = RtcConfig {
+            base: RtcBase::Datetime(mktime64(2013, 11, 13, 2, 4, 56)),
+            clock: RtcClock::Host,
+        };
+        let mut rtc = RTC::new(&config).with_context(|| "Failed to create RTC device")?;
+
+        assert!((cmos_read(&mut rtc, RTC_SECONDS) - 0x56) <= WIGGLE);
+        assert_eq!(cmos_read(&mut rtc, RTC_MINUTES), 0x04);
+        assert_eq!(cmos_read(&mut rtc, RTC_HOURS), 0x02);
+        assert_eq!(cmos_read(&mut rtc, RTC_DAY_OF_MONTH), 0x13);
+        assert_eq!(cmos_read(&mut rtc, RTC_MONTH), 0x11);
+        assert_eq!(cmos_read(&mut rtc, RTC_YEAR), 0x13);
+        assert_eq!(cmos_read(&mut rtc, RTC_CENTURY_BCD), 0x20);
+
+        Ok(())
+    }
+
     #[test]
     fn test_invalid_rtc_time() -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+        let mut rtc =
+            RTC::new(&RtcConfig::default()).with_context(|| "Failed to create RTC device")?;
         // Set rtc year: 1969
         cmos_write(&mut rtc, RTC_CENTURY_BCD, 0x19);
         cmos_write(&mut rtc, RTC_YEAR, 0x69);
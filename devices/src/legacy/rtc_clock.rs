@@ -0,0 +1,41 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::time::{Duration, Instant};
+
+use machine_manager::config::RtcClock;
+use machine_manager::event_loop::EventLoop;
+
+/// Clock which the time of RTC devices advances with.
+pub struct RtcClockSource {
+    clock: RtcClock,
+    /// The time when RTC device is created, used by host clock.
+    start: Instant,
+}
+
+impl RtcClockSource {
+    pub fn new(clock: RtcClock) -> Self {
+        Self {
+            clock,
+            start: Instant::now(),
+        }
+    }
+
+    /// Get the current value of the clock. Only the difference between two values makes
+    /// sense. VM clock doesn't advance when VM is paused.
+    pub fn now(&self) -> Duration {
+        match self.clock {
+            RtcClock::Host => self.start.elapsed(),
+            RtcClock::Vm => EventLoop::get_ctx(None).unwrap().get_virtual_clock(),
+        }
+    }
+}
@@ -480,6 +480,9 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
+            logfile: None,
+            logappend: false,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg.clone(),
@@ -535,6 +538,9 @@ mod test {
         let chardev_cfg = ChardevConfig {
             id: "chardev".to_string(),
             backend: ChardevType::Stdio,
+            mux: false,
+            logfile: None,
+            logappend: false,
         };
         let mut usart = Serial::new(SerialConfig {
             chardev: chardev_cfg,
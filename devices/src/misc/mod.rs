@@ -10,8 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+pub mod pvpanic;
 #[cfg(feature = "scream")]
 pub mod scream;
+pub mod tpm;
+pub mod vmcoreinfo;
 
 #[cfg(feature = "scream")]
 mod ivshmem;
@@ -0,0 +1,410 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Arc, Mutex, Weak,
+};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::pci::{
+    config::{
+        PciConfig, RegionType, DEVICE_ID, PCI_CONFIG_SPACE_SIZE, PCI_SUBDEVICE_ID_QEMU,
+        PCI_VENDOR_ID_REDHAT, PCI_VENDOR_ID_REDHAT_QUMRANET, REVISION_ID, SUBSYSTEM_ID,
+        SUBSYSTEM_VENDOR_ID, SUB_CLASS_CODE, VENDOR_ID,
+    },
+    le_write_u16, PciBus, PciDevBase, PciDevOps,
+};
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{AmlBuilder, AmlDevice, AmlNameDecl, AmlResTemplate, AmlScopeBuilder, AmlString};
+#[cfg(target_arch = "x86_64")]
+use acpi::{AmlIoDecode, AmlIoResource};
+#[cfg(target_arch = "aarch64")]
+use acpi::{AmlMemory32Fixed, AmlReadAndWrite};
+use address_space::{GuestAddress, Region, RegionOps};
+use machine_manager::config::{PanicAction, PvPanicConfig, PVPANIC_CRASH_LOADED, PVPANIC_PANICKED};
+use machine_manager::event;
+use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_schema};
+
+const PCI_DEVICE_ID_REDHAT_PVPANIC: u16 = 0x0011;
+const PCI_REVISION_ID_PVPANIC: u8 = 1;
+const PCI_CLASS_SYSTEM_OTHER: u16 = 0x0880;
+const PCI_BAR_MAX_PVPANIC: u8 = 1;
+/// The register of pvpanic is one byte, the bar is as large as the minimum one.
+const PVPANIC_PCI_BAR_SIZE: u64 = 0x10;
+
+/// Requests of VM lifecycle which are sent by pvpanic device for the panic action.
+#[derive(Clone)]
+pub struct PvPanicReqs {
+    pub pause_req: Arc<EventFd>,
+    pub shutdown_req: Arc<EventFd>,
+}
+
+/// State shared by all types of pvpanic device.
+struct PvPanicState {
+    /// Events that the device reports to guest as supported.
+    supported_features: u32,
+    /// Action taken when guest kernel panics.
+    action: PanicAction,
+    reqs: PvPanicReqs,
+}
+
+impl PvPanicState {
+    fn new(config: &PvPanicConfig, action: PanicAction, reqs: PvPanicReqs) -> Self {
+        Self {
+            supported_features: config.supported_features,
+            action,
+            reqs,
+        }
+    }
+
+    fn read(&self, data: &mut [u8], offset: u64) -> bool {
+        if offset != 0 || data.is_empty() {
+            error!(
+                "pvpanic: invalid read, offset {}, size {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        data.fill(0);
+        data[0] = self.supported_features as u8;
+        true
+    }
+
+    fn write(&self, data: &[u8], offset: u64) -> bool {
+        if offset != 0 || data.is_empty() {
+            error!(
+                "pvpanic: invalid write, offset {}, size {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        let event = u32::from(data[0]) & self.supported_features;
+        if event & PVPANIC_PANICKED != 0 {
+            self.handle_panicked();
+        } else if event & PVPANIC_CRASH_LOADED != 0 {
+            info!("pvpanic: guest has loaded crash kernel");
+            if QmpChannel::is_connected() {
+                let crashloaded_msg = qmp_schema::GuestCrashloaded {
+                    action: "run".to_string(),
+                };
+                event!(GuestCrashloaded; crashloaded_msg);
+            }
+        }
+        true
+    }
+
+    fn handle_panicked(&self) {
+        let action = match self.action {
+            PanicAction::None => "run",
+            PanicAction::Pause => "pause",
+            PanicAction::Shutdown => "poweroff",
+            PanicAction::Coredump => "coredump",
+        };
+        error!("pvpanic: guest kernel panicked, action is {}", action);
+        if QmpChannel::is_connected() {
+            let panicked_msg = qmp_schema::GuestPanicked {
+                action: action.to_string(),
+            };
+            event!(GuestPanicked; panicked_msg);
+        }
+
+        match self.action {
+            PanicAction::None => {}
+            PanicAction::Pause => {
+                if let Err(e) = self.reqs.pause_req.write(1) {
+                    error!("pvpanic: failed to send pause request, {:?}", e);
+                }
+            }
+            PanicAction::Shutdown => {
+                if let Err(e) = self.reqs.shutdown_req.write(1) {
+                    error!("pvpanic: failed to send shutdown request, {:?}", e);
+                }
+            }
+            PanicAction::Coredump => std::process::abort(),
+        }
+    }
+}
+
+/// Pvpanic device on system bus, whose register is an I/O port on x86_64 and
+/// a MMIO region on aarch64.
+pub struct PvPanic {
+    base: SysBusDevBase,
+    state: PvPanicState,
+}
+
+impl PvPanic {
+    pub fn new(config: &PvPanicConfig, action: PanicAction, reqs: PvPanicReqs) -> Self {
+        let mut base = SysBusDevBase::new(SysBusDevType::PvPanic);
+        base.base = DeviceBase::new(config.id.clone(), false);
+        Self {
+            base,
+            state: PvPanicState::new(config, action, reqs),
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<()> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to set system resource of pvpanic")?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "PvPanic")
+    }
+}
+
+impl Device for PvPanic {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for PvPanic {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        self.state.read(data, offset)
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        self.state.write(data, offset)
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+}
+
+impl AmlBuilder for PvPanic {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("PEVT");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlString("QEMU0001".to_string())));
+
+        let mut res = AmlResTemplate::new();
+        #[cfg(target_arch = "x86_64")]
+        res.append_child(AmlIoResource::new(
+            AmlIoDecode::Decode16,
+            self.base.res.region_base as u16,
+            self.base.res.region_base as u16,
+            0x01,
+            self.base.res.region_size as u8,
+        ));
+        #[cfg(target_arch = "aarch64")]
+        res.append_child(AmlMemory32Fixed::new(
+            AmlReadAndWrite::ReadWrite,
+            self.base.res.region_base as u32,
+            self.base.res.region_size as u32,
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_CRS", res));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+/// Pvpanic device on PCI bus.
+pub struct PvPanicPci {
+    base: PciDevBase,
+    dev_id: Arc<AtomicU16>,
+    state: Arc<PvPanicState>,
+}
+
+impl PvPanicPci {
+    pub fn new(
+        config: &PvPanicConfig,
+        action: PanicAction,
+        reqs: PvPanicReqs,
+        devfn: u8,
+        parent_bus: Weak<Mutex<PciBus>>,
+    ) -> Self {
+        Self {
+            base: PciDevBase {
+                base: DeviceBase::new(config.id.clone(), false),
+                config: PciConfig::new(PCI_CONFIG_SPACE_SIZE, PCI_BAR_MAX_PVPANIC),
+                devfn,
+                parent_bus,
+            },
+            dev_id: Arc::new(AtomicU16::new(0)),
+            state: Arc::new(PvPanicState::new(config, action, reqs)),
+        }
+    }
+
+    fn register_bars(&mut self) -> Result<()> {
+        let cloned_state = self.state.clone();
+        let reg_read = move |data: &mut [u8], _: GuestAddress, offset: u64| -> bool {
+            cloned_state.read(data, offset)
+        };
+        let cloned_state = self.state.clone();
+        let reg_write = move |data: &[u8], _: GuestAddress, offset: u64| -> bool {
+            cloned_state.write(data, offset)
+        };
+        let reg_region_ops = RegionOps {
+            read: Arc::new(reg_read),
+            write: Arc::new(reg_write),
+        };
+
+        // bar0: mmio register
+        self.base.config.register_bar(
+            0,
+            Region::init_io_region(PVPANIC_PCI_BAR_SIZE, reg_region_ops, "PvPanicIo"),
+            RegionType::Mem32Bit,
+            false,
+            PVPANIC_PCI_BAR_SIZE,
+        )
+    }
+}
+
+impl Device for PvPanicPci {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl PciDevOps for PvPanicPci {
+    fn pci_base(&self) -> &PciDevBase {
+        &self.base
+    }
+
+    fn pci_base_mut(&mut self) -> &mut PciDevBase {
+        &mut self.base
+    }
+
+    fn realize(mut self) -> Result<()> {
+        self.init_write_mask(false)?;
+        self.init_write_clear_mask(false)?;
+        le_write_u16(
+            &mut self.base.config.config,
+            VENDOR_ID as usize,
+            PCI_VENDOR_ID_REDHAT,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            DEVICE_ID as usize,
+            PCI_DEVICE_ID_REDHAT_PVPANIC,
+        )?;
+        self.base.config.config[REVISION_ID] = PCI_REVISION_ID_PVPANIC;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUB_CLASS_CODE as usize,
+            PCI_CLASS_SYSTEM_OTHER,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUBSYSTEM_VENDOR_ID,
+            PCI_VENDOR_ID_REDHAT_QUMRANET,
+        )?;
+        le_write_u16(
+            &mut self.base.config.config,
+            SUBSYSTEM_ID,
+            PCI_SUBDEVICE_ID_QEMU,
+        )?;
+
+        self.register_bars()?;
+
+        // Attach to the PCI bus.
+        let pci_bus = self.base.parent_bus.upgrade().unwrap();
+        let mut locked_pci_bus = pci_bus.lock().unwrap();
+        let pci_device = locked_pci_bus.devices.get(&self.base.devfn);
+        match pci_device {
+            Some(device) => bail!(
+                "Devfn {:?} has been used by {:?}",
+                &self.base.devfn,
+                device.lock().unwrap().name()
+            ),
+            None => locked_pci_bus
+                .devices
+                .insert(self.base.devfn, Arc::new(Mutex::new(self))),
+        };
+        Ok(())
+    }
+
+    fn write_config(&mut self, offset: usize, data: &[u8]) {
+        let parent_bus = self.base.parent_bus.upgrade().unwrap();
+        let locked_parent_bus = parent_bus.lock().unwrap();
+
+        self.base.config.write(
+            offset,
+            data,
+            self.dev_id.load(Ordering::Acquire),
+            #[cfg(target_arch = "x86_64")]
+            Some(&locked_parent_bus.io_region),
+            Some(&locked_parent_bus.mem_region),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_state(action: PanicAction) -> PvPanicState {
+        QmpChannel::object_init();
+        let reqs = PvPanicReqs {
+            pause_req: Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+            shutdown_req: Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap()),
+        };
+        PvPanicState::new(&PvPanicConfig::default(), action, reqs)
+    }
+
+    #[test]
+    fn test_pvpanic_read() {
+        let state = create_state(PanicAction::None);
+        let mut data = [0xff_u8; 1];
+        assert!(state.read(&mut data, 0));
+        assert_eq!(u32::from(data[0]), PVPANIC_PANICKED | PVPANIC_CRASH_LOADED);
+        assert!(!state.read(&mut data, 1));
+    }
+
+    #[test]
+    fn test_pvpanic_action() {
+        let state = create_state(PanicAction::Pause);
+        assert!(state.write(&[PVPANIC_CRASH_LOADED as u8], 0));
+        assert!(state.reqs.pause_req.read().is_err());
+        assert!(state.write(&[PVPANIC_PANICKED as u8], 0));
+        assert_eq!(state.reqs.pause_req.read().unwrap(), 1);
+        assert!(state.reqs.shutdown_req.read().is_err());
+
+        let state = create_state(PanicAction::Shutdown);
+        assert!(state.write(&[PVPANIC_PANICKED as u8], 0));
+        assert_eq!(state.reqs.shutdown_req.read().unwrap(), 1);
+        assert!(state.reqs.pause_req.read().is_err());
+
+        let state = create_state(PanicAction::None);
+        assert!(state.write(&[PVPANIC_PANICKED as u8], 0));
+        assert!(state.reqs.pause_req.read().is_err());
+        assert!(state.reqs.shutdown_req.read().is_err());
+        assert!(!state.write(&[PVPANIC_PANICKED as u8], 1));
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use anyhow::{bail, Context, Result};
+use byteorder::{BigEndian, ByteOrder};
+use libc::{c_void, iovec};
+
+use util::unix::UnixSock;
+
+// Commands of the swtpm control channel, see swtpm-ioctls(4).
+const CMD_GET_CAPABILITY: u32 = 1;
+const CMD_INIT: u32 = 2;
+const CMD_GET_TPMESTABLISHED: u32 = 4;
+const CMD_SET_LOCALITY: u32 = 5;
+const CMD_STOP: u32 = 14;
+const CMD_SET_DATAFD: u32 = 16;
+const CMD_SET_BUFFERSIZE: u32 = 17;
+
+// Capabilities of swtpm.
+const PTM_CAP_INIT: u64 = 1 << 0;
+const PTM_CAP_GET_TPMESTABLISHED: u64 = 1 << 2;
+const PTM_CAP_SET_LOCALITY: u64 = 1 << 3;
+const PTM_CAP_STOP: u64 = 1 << 10;
+const PTM_CAP_SET_DATAFD: u64 = 1 << 12;
+const PTM_CAP_SET_BUFFERSIZE: u64 = 1 << 13;
+const PTM_CAP_REQUIRED: u64 = PTM_CAP_INIT
+    | PTM_CAP_GET_TPMESTABLISHED
+    | PTM_CAP_SET_LOCALITY
+    | PTM_CAP_STOP
+    | PTM_CAP_SET_DATAFD
+    | PTM_CAP_SET_BUFFERSIZE;
+
+/// Delete the volatile state of TPM when it is initialized.
+const PTM_INIT_FLAG_DELETE_VOLATILE: u32 = 1 << 0;
+
+/// Size of the header of TPM response: tag(u16), size(u32) and response code(u32).
+pub const TPM_RESP_HDR_SIZE: usize = 10;
+
+/// Backend which proxies TPM commands to an external swtpm process. The control
+/// channel is the unix socket given by user, and the TPM commands are transferred
+/// through a socket pair whose peer is handed over to swtpm.
+pub struct TpmEmulator {
+    /// Control channel of swtpm.
+    ctrl: UnixSock,
+    /// Data channel of swtpm.
+    data: Option<UnixStream>,
+}
+
+impl TpmEmulator {
+    pub fn new(sock_path: &str) -> Self {
+        Self {
+            ctrl: UnixSock::new(sock_path),
+            data: None,
+        }
+    }
+
+    /// Connect to swtpm, check its capabilities and set up the data channel.
+    pub fn connect(&mut self) -> Result<()> {
+        self.ctrl.connect()?;
+
+        let resp = self.ctrl_cmd(CMD_GET_CAPABILITY, &[], 8, &[])?;
+        let caps = BigEndian::read_u64(&resp);
+        if caps & PTM_CAP_REQUIRED != PTM_CAP_REQUIRED {
+            bail!(
+                "swtpm lacks required capabilities 0x{:x}",
+                !caps & PTM_CAP_REQUIRED
+            );
+        }
+
+        let (data, peer) = UnixStream::pair().with_context(|| "Failed to create socket pair")?;
+        self.ctrl_result_cmd(CMD_SET_DATAFD, &[], &[peer.as_raw_fd()])
+            .with_context(|| "Failed to set data channel of swtpm")?;
+        self.data = Some(data);
+        Ok(())
+    }
+
+    /// Start up TPM with the expected buffer size, returns the buffer size which
+    /// swtpm actually uses.
+    pub fn startup(&mut self, buffer_size: u32) -> Result<u32> {
+        // The buffer size can only be changed when TPM is stopped.
+        self.ctrl_result_cmd(CMD_STOP, &[], &[])
+            .with_context(|| "Failed to stop TPM")?;
+        let resp = self
+            .ctrl_cmd(CMD_SET_BUFFERSIZE, &buffer_size.to_be_bytes(), 16, &[])
+            .with_context(|| "Failed to set buffer size of TPM")?;
+        check_result(&resp)?;
+        let actual_size = BigEndian::read_u32(&resp[4..8]);
+
+        self.ctrl_result_cmd(CMD_INIT, &PTM_INIT_FLAG_DELETE_VOLATILE.to_be_bytes(), &[])
+            .with_context(|| "Failed to init TPM")?;
+        // Only locality 0 is supported.
+        self.ctrl_result_cmd(CMD_SET_LOCALITY, &[0], &[])
+            .with_context(|| "Failed to set locality of TPM")?;
+        Ok(actual_size)
+    }
+
+    /// Get the flag which shows whether TPM has been established.
+    pub fn get_established_flag(&mut self) -> Result<bool> {
+        let resp = self.ctrl_cmd(CMD_GET_TPMESTABLISHED, &[], 8, &[])?;
+        check_result(&resp)?;
+        Ok(resp[4] != 0)
+    }
+
+    /// Send TPM command in `buf` to swtpm, and the response is written back to `buf`.
+    ///
+    /// # Arguments
+    ///
+    /// * `buf` - Buffer holding the TPM command and the response.
+    /// * `cmd_len` - Length of the TPM command.
+    pub fn deliver_request(&mut self, buf: &mut [u8], cmd_len: usize) -> Result<()> {
+        let data = self
+            .data
+            .as_mut()
+            .with_context(|| "Data channel of swtpm is not set")?;
+        data.write_all(&buf[..cmd_len])
+            .with_context(|| "Failed to send TPM command")?;
+
+        let mut hdr = [0_u8; TPM_RESP_HDR_SIZE];
+        data.read_exact(&mut hdr)
+            .with_context(|| "Failed to receive TPM response header")?;
+        let resp_len = BigEndian::read_u32(&hdr[2..6]) as usize;
+        if resp_len < TPM_RESP_HDR_SIZE || resp_len > buf.len() {
+            bail!("Invalid TPM response size {}", resp_len);
+        }
+        buf[..TPM_RESP_HDR_SIZE].copy_from_slice(&hdr);
+        data.read_exact(&mut buf[TPM_RESP_HDR_SIZE..resp_len])
+            .with_context(|| "Failed to receive TPM response")
+    }
+
+    /// Send command to the control channel, and receive the response whose length
+    /// is `resp_len`.
+    fn ctrl_cmd(&self, cmd: u32, req: &[u8], resp_len: usize, fds: &[RawFd]) -> Result<Vec<u8>> {
+        let mut msg = cmd.to_be_bytes().to_vec();
+        msg.extend_from_slice(req);
+        let mut iovecs = [iovec {
+            iov_base: msg.as_mut_ptr() as *mut c_void,
+            iov_len: msg.len(),
+        }];
+        let sent = self
+            .ctrl
+            .send_msg(&mut iovecs, fds)
+            .with_context(|| format!("Failed to send command {} to swtpm", cmd))?;
+        if sent != msg.len() {
+            bail!("Incomplete command {} sent to swtpm", cmd);
+        }
+
+        let mut resp = vec![0_u8; resp_len];
+        let mut iovecs = [iovec {
+            iov_base: resp.as_mut_ptr() as *mut c_void,
+            iov_len: resp.len(),
+        }];
+        let (received, _) = self
+            .ctrl
+            .recv_msg(&mut iovecs, &mut [])
+            .with_context(|| format!("Failed to receive response of command {}", cmd))?;
+        if received != resp_len {
+            bail!("Incomplete response of command {} from swtpm", cmd);
+        }
+        Ok(resp)
+    }
+
+    /// Send command whose response only contains the result code.
+    fn ctrl_result_cmd(&self, cmd: u32, req: &[u8], fds: &[RawFd]) -> Result<()> {
+        let resp = self.ctrl_cmd(cmd, req, 4, fds)?;
+        check_result(&resp)
+    }
+}
+
+fn check_result(resp: &[u8]) -> Result<()> {
+    let result = BigEndian::read_u32(&resp[0..4]);
+    if result != 0 {
+        bail!("swtpm returns error 0x{:x}", result);
+    }
+    Ok(())
+}
@@ -0,0 +1,448 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod emulator;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use log::error;
+
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysRes};
+use crate::{Device, DeviceBase};
+use acpi::{
+    AmlBuilder, AmlDevice, AmlInteger, AmlMemory32Fixed, AmlNameDecl, AmlReadAndWrite,
+    AmlResTemplate, AmlScopeBuilder, AmlString,
+};
+use address_space::GuestAddress;
+use emulator::{TpmEmulator, TPM_RESP_HDR_SIZE};
+use machine_manager::config::TpmConfig;
+use util::num_ops::read_data_u32;
+
+/// Size of the MMIO region of TPM CRB device, which only contains locality 0.
+pub const TPM_CRB_ADDR_SIZE: u64 = 0x1000;
+/// Offset of the control area in the MMIO region, which is reported by ACPI TPM2 table.
+pub const TPM_CRB_CTRL_AREA_OFFSET: u64 = CRB_CTRL_REQ;
+
+// Registers of CRB interface, see TCG PC Client Platform TPM Profile Specification.
+const CRB_LOC_STATE: u64 = 0x00;
+const CRB_LOC_CTRL: u64 = 0x08;
+const CRB_LOC_STS: u64 = 0x0C;
+const CRB_INTF_ID: u64 = 0x30;
+const CRB_INTF_ID2: u64 = 0x34;
+const CRB_CTRL_REQ: u64 = 0x40;
+const CRB_CTRL_STS: u64 = 0x44;
+const CRB_CTRL_START: u64 = 0x4C;
+const CRB_CTRL_CMD_SIZE: u64 = 0x58;
+const CRB_CTRL_CMD_LADDR: u64 = 0x5C;
+const CRB_CTRL_CMD_HADDR: u64 = 0x60;
+const CRB_CTRL_RSP_SIZE: u64 = 0x64;
+const CRB_CTRL_RSP_ADDR: u64 = 0x68;
+const CRB_DATA_BUFFER: u64 = 0x80;
+/// Size of the command and response buffer.
+const CRB_BUFFER_SIZE: u64 = TPM_CRB_ADDR_SIZE - CRB_DATA_BUFFER;
+
+const CRB_LOC_STATE_TPM_ESTABLISHED: u32 = 1 << 0;
+const CRB_LOC_STATE_LOC_ASSIGNED: u32 = 1 << 1;
+const CRB_LOC_STATE_REG_VALID_STS: u32 = 1 << 7;
+const CRB_LOC_CTRL_REQUEST_ACCESS: u32 = 1 << 0;
+const CRB_LOC_CTRL_RELINQUISH: u32 = 1 << 1;
+const CRB_LOC_STS_GRANTED: u32 = 1 << 0;
+const CRB_CTRL_REQ_CMD_READY: u32 = 1 << 0;
+const CRB_CTRL_REQ_GO_IDLE: u32 = 1 << 1;
+const CRB_CTRL_STS_TPM_STS: u32 = 1 << 0;
+const CRB_CTRL_STS_TPM_IDLE: u32 = 1 << 1;
+const CRB_CTRL_START_INVOKE: u32 = 1 << 0;
+
+/// Interface type and version are CRB, data transfer size is 64 bytes and only
+/// CRB interface can be selected.
+const CRB_INTF_ID_VALUE: u32 = 0x1 | (0x1 << 4) | (0x3 << 11) | (0x1 << 14) | (0x1 << 17);
+/// Vendor ID of IBM and device ID 1, which are the same as swtpm reports.
+const CRB_INTF_ID2_VALUE: u32 = 0x1014 | (0x1 << 16);
+
+/// TPM_ST_NO_SESSIONS tag and TPM_RC_FAILURE code for the response of failed command.
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_RC_FAILURE: u32 = 0x101;
+
+/// TPM 2.0 device with CRB (Command Response Buffer) interface, which proxies the
+/// commands to swtpm.
+pub struct TpmCrb {
+    base: SysBusDevBase,
+    /// Registers of CRB interface, locates before the data buffer.
+    regs: [u8; CRB_DATA_BUFFER as usize],
+    /// Buffer holding the TPM command and response.
+    buffer: Vec<u8>,
+    /// Size of buffer that swtpm supports.
+    be_buffer_size: usize,
+    backend: TpmEmulator,
+}
+
+impl TpmCrb {
+    pub fn new(config: &TpmConfig) -> Self {
+        Self {
+            base: SysBusDevBase {
+                base: DeviceBase::new(config.id.clone(), false),
+                ..Default::default()
+            },
+            regs: [0; CRB_DATA_BUFFER as usize],
+            buffer: vec![0; CRB_BUFFER_SIZE as usize],
+            be_buffer_size: CRB_BUFFER_SIZE as usize,
+            backend: TpmEmulator::new(&config.sock_path),
+        }
+    }
+
+    pub fn realize(
+        mut self,
+        sysbus: &mut SysBus,
+        region_base: u64,
+        region_size: u64,
+    ) -> Result<()> {
+        self.set_sys_resource(sysbus, region_base, region_size)
+            .with_context(|| "Failed to set system resource of TPM")?;
+        self.backend
+            .connect()
+            .with_context(|| "Failed to connect to swtpm")?;
+        self.reset()?;
+
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_device(&dev, region_base, region_size, "TpmCrb")
+    }
+
+    fn get_reg(&self, offset: u64) -> u32 {
+        let offset = offset as usize;
+        LittleEndian::read_u32(&self.regs[offset..offset + 4])
+    }
+
+    fn set_reg(&mut self, offset: u64, value: u32) {
+        let offset = offset as usize;
+        LittleEndian::write_u32(&mut self.regs[offset..offset + 4], value);
+    }
+
+    fn update_reg(&mut self, offset: u64, set: u32, clear: u32) {
+        let value = (self.get_reg(offset) & !clear) | set;
+        self.set_reg(offset, value);
+    }
+
+    fn locality_assigned(&self) -> bool {
+        self.get_reg(CRB_LOC_STATE) & CRB_LOC_STATE_LOC_ASSIGNED != 0
+    }
+
+    fn tpm_idle(&self) -> bool {
+        self.get_reg(CRB_CTRL_STS) & CRB_CTRL_STS_TPM_IDLE != 0
+    }
+
+    /// Execute the command in data buffer. The vCPU waits until swtpm finishes it.
+    fn execute_command(&mut self) {
+        let cmd_len = (BigEndian::read_u32(&self.buffer[2..6]) as usize).min(self.be_buffer_size);
+        let buf = &mut self.buffer[..self.be_buffer_size];
+        if let Err(e) = self.backend.deliver_request(buf, cmd_len) {
+            error!("Failed to execute TPM command: {:?}", e);
+            BigEndian::write_u16(&mut buf[0..2], TPM_ST_NO_SESSIONS);
+            BigEndian::write_u32(&mut buf[2..6], TPM_RESP_HDR_SIZE as u32);
+            BigEndian::write_u32(&mut buf[6..10], TPM_RC_FAILURE);
+            self.update_reg(CRB_CTRL_STS, CRB_CTRL_STS_TPM_STS, 0);
+        }
+    }
+
+    fn write_reg(&mut self, offset: u64, value: u32) {
+        match offset {
+            CRB_LOC_CTRL => {
+                if value & CRB_LOC_CTRL_REQUEST_ACCESS != 0 {
+                    self.update_reg(CRB_LOC_STATE, CRB_LOC_STATE_LOC_ASSIGNED, 0);
+                    self.set_reg(CRB_LOC_STS, CRB_LOC_STS_GRANTED);
+                } else if value & CRB_LOC_CTRL_RELINQUISH != 0 {
+                    self.update_reg(CRB_LOC_STATE, 0, CRB_LOC_STATE_LOC_ASSIGNED);
+                    self.set_reg(CRB_LOC_STS, 0);
+                }
+            }
+            CRB_CTRL_REQ => {
+                if value == CRB_CTRL_REQ_CMD_READY {
+                    self.update_reg(CRB_CTRL_STS, 0, CRB_CTRL_STS_TPM_IDLE);
+                } else if value == CRB_CTRL_REQ_GO_IDLE {
+                    self.update_reg(CRB_CTRL_STS, CRB_CTRL_STS_TPM_IDLE, 0);
+                }
+            }
+            CRB_CTRL_START
+                if value & CRB_CTRL_START_INVOKE != 0
+                    && self.locality_assigned()
+                    && !self.tpm_idle() =>
+            {
+                self.execute_command();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Device for TpmCrb {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for TpmCrb {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, data: &mut [u8], _base: GuestAddress, offset: u64) -> bool {
+        let (src, start) = if offset < CRB_DATA_BUFFER {
+            (&self.regs[..], offset as usize)
+        } else {
+            (&self.buffer[..], (offset - CRB_DATA_BUFFER) as usize)
+        };
+        if start + data.len() > src.len() {
+            error!(
+                "Invalid TPM CRB read: offset 0x{:x}, size {}",
+                offset,
+                data.len()
+            );
+            return false;
+        }
+        data.copy_from_slice(&src[start..start + data.len()]);
+        true
+    }
+
+    fn write(&mut self, data: &[u8], _base: GuestAddress, offset: u64) -> bool {
+        if offset >= CRB_DATA_BUFFER {
+            let start = (offset - CRB_DATA_BUFFER) as usize;
+            if start + data.len() > self.buffer.len() {
+                error!(
+                    "Invalid TPM CRB write: offset 0x{:x}, size {}",
+                    offset,
+                    data.len()
+                );
+                return false;
+            }
+            self.buffer[start..start + data.len()].copy_from_slice(data);
+            return true;
+        }
+
+        let mut value = 0;
+        if !read_data_u32(data, &mut value) {
+            return false;
+        }
+        self.write_reg(offset, value);
+        true
+    }
+
+    fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
+        Some(&mut self.base.res)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let buffer_size = self
+            .backend
+            .startup(CRB_BUFFER_SIZE as u32)
+            .with_context(|| "Failed to start up swtpm")?;
+        self.be_buffer_size = (buffer_size as usize).min(self.buffer.len());
+        let established = self.backend.get_established_flag()?;
+
+        let buffer_addr = self.base.res.region_base + CRB_DATA_BUFFER;
+        self.regs.fill(0);
+        self.buffer.fill(0);
+        let mut loc_state = CRB_LOC_STATE_REG_VALID_STS;
+        if established {
+            loc_state |= CRB_LOC_STATE_TPM_ESTABLISHED;
+        }
+        self.set_reg(CRB_LOC_STATE, loc_state);
+        self.set_reg(CRB_INTF_ID, CRB_INTF_ID_VALUE);
+        self.set_reg(CRB_INTF_ID2, CRB_INTF_ID2_VALUE);
+        self.set_reg(CRB_CTRL_STS, CRB_CTRL_STS_TPM_IDLE);
+        self.set_reg(CRB_CTRL_CMD_SIZE, self.be_buffer_size as u32);
+        self.set_reg(CRB_CTRL_CMD_LADDR, buffer_addr as u32);
+        self.set_reg(CRB_CTRL_CMD_HADDR, (buffer_addr >> 32) as u32);
+        self.set_reg(CRB_CTRL_RSP_SIZE, self.be_buffer_size as u32);
+        let rsp_addr = CRB_CTRL_RSP_ADDR as usize;
+        LittleEndian::write_u64(&mut self.regs[rsp_addr..rsp_addr + 8], buffer_addr);
+        Ok(())
+    }
+}
+
+impl AmlBuilder for TpmCrb {
+    fn aml_bytes(&self) -> Vec<u8> {
+        let mut acpi_dev = AmlDevice::new("TPM");
+        acpi_dev.append_child(AmlNameDecl::new("_HID", AmlString("MSFT0101".to_string())));
+        acpi_dev.append_child(AmlNameDecl::new(
+            "_STR",
+            AmlString("TPM 2.0 Device".to_string()),
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_STA", AmlInteger(0xF)));
+
+        let mut res = AmlResTemplate::new();
+        res.append_child(AmlMemory32Fixed::new(
+            AmlReadAndWrite::ReadWrite,
+            self.base.res.region_base as u32,
+            self.base.res.region_size as u32,
+        ));
+        acpi_dev.append_child(AmlNameDecl::new("_CRS", res));
+
+        acpi_dev.aml_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use libc::{c_void, iovec};
+
+    use super::*;
+    use util::unix::UnixSock;
+
+    const MOCK_BUFFER_SIZE: u32 = 0x800;
+
+    fn recv_exact(sock: &UnixSock, buf: &mut [u8], fds: &mut [i32]) -> bool {
+        if buf.is_empty() {
+            return true;
+        }
+        let mut iovecs = [iovec {
+            iov_base: buf.as_mut_ptr() as *mut c_void,
+            iov_len: buf.len(),
+        }];
+        matches!(sock.recv_msg(&mut iovecs, fds), Ok((len, _)) if len == buf.len())
+    }
+
+    /// Answer TPM commands with a fixed response, which carries two bytes payload.
+    fn mock_data_channel(mut data: UnixStream) {
+        let mut hdr = [0_u8; TPM_RESP_HDR_SIZE];
+        while data.read_exact(&mut hdr).is_ok() {
+            let mut body = vec![0_u8; BigEndian::read_u32(&hdr[2..6]) as usize - hdr.len()];
+            data.read_exact(&mut body).unwrap();
+            data.write_all(&[0x80, 0x01, 0, 0, 0, 0x0c, 0, 0, 0, 0, 0xab, 0xcd])
+                .unwrap();
+        }
+    }
+
+    /// Answer the commands of control channel like swtpm does.
+    fn mock_swtpm(sock_path: &str) -> thread::JoinHandle<()> {
+        let mut sock = UnixSock::new(sock_path);
+        sock.bind(true).unwrap();
+        thread::spawn(move || {
+            sock.accept().unwrap();
+            let mut cmd = [0_u8; 4];
+            let mut fds = [-1_i32; 1];
+            while recv_exact(&sock, &mut cmd, &mut fds) {
+                let (req_len, mut resp) = match BigEndian::read_u32(&cmd) {
+                    1 => (0, u64::MAX.to_be_bytes().to_vec()),
+                    2 => (4, vec![0; 4]),
+                    4 => (0, vec![0; 8]),
+                    5 => (1, vec![0; 4]),
+                    16 => {
+                        // SAFETY: the fd is received from the control channel just now.
+                        let data = unsafe { UnixStream::from_raw_fd(fds[0]) };
+                        thread::spawn(move || mock_data_channel(data));
+                        (0, vec![0; 4])
+                    }
+                    17 => {
+                        let mut resp = vec![0; 16];
+                        BigEndian::write_u32(&mut resp[4..8], MOCK_BUFFER_SIZE);
+                        (4, resp)
+                    }
+                    _ => (0, vec![0; 4]),
+                };
+                let mut req = vec![0_u8; req_len];
+                assert!(recv_exact(&sock, &mut req, &mut []));
+                let mut iovecs = [iovec {
+                    iov_base: resp.as_mut_ptr() as *mut c_void,
+                    iov_len: resp.len(),
+                }];
+                sock.send_msg(&mut iovecs, &[]).unwrap();
+            }
+        })
+    }
+
+    fn read_reg(tpm: &mut TpmCrb, offset: u64) -> u32 {
+        let mut data = [0_u8; 4];
+        assert!(tpm.read(&mut data, GuestAddress(0), offset));
+        LittleEndian::read_u32(&data)
+    }
+
+    fn write_reg(tpm: &mut TpmCrb, offset: u64, value: u32) {
+        assert!(tpm.write(&value.to_le_bytes(), GuestAddress(0), offset));
+    }
+
+    #[test]
+    fn test_tpm_crb_command() {
+        let sock_path = "/tmp/test_tpm_crb.sock";
+        let swtpm = mock_swtpm(sock_path);
+        let config = TpmConfig {
+            id: "tpm0".to_string(),
+            sock_path: sock_path.to_string(),
+        };
+        let mut tpm = TpmCrb::new(&config);
+        tpm.backend.connect().unwrap();
+        tpm.reset().unwrap();
+
+        assert_eq!(
+            read_reg(&mut tpm, CRB_LOC_STATE),
+            CRB_LOC_STATE_REG_VALID_STS
+        );
+        assert_eq!(read_reg(&mut tpm, CRB_INTF_ID), CRB_INTF_ID_VALUE);
+        assert_eq!(read_reg(&mut tpm, CRB_CTRL_STS), CRB_CTRL_STS_TPM_IDLE);
+        assert_eq!(read_reg(&mut tpm, CRB_CTRL_CMD_SIZE), MOCK_BUFFER_SIZE);
+        assert_eq!(
+            read_reg(&mut tpm, CRB_CTRL_CMD_LADDR),
+            CRB_DATA_BUFFER as u32
+        );
+
+        // TPM2_GetRandom for 2 bytes.
+        let cmd = [0x80, 0x01, 0, 0, 0, 0x0c, 0, 0, 0x01, 0x7b, 0, 0x02];
+        assert!(tpm.write(&cmd, GuestAddress(0), CRB_DATA_BUFFER));
+        // Command is not executed before locality is assigned and TPM is ready.
+        write_reg(&mut tpm, CRB_CTRL_START, CRB_CTRL_START_INVOKE);
+        let mut resp = [0_u8; 12];
+        assert!(tpm.read(&mut resp, GuestAddress(0), CRB_DATA_BUFFER));
+        assert_eq!(resp, cmd);
+
+        write_reg(&mut tpm, CRB_LOC_CTRL, CRB_LOC_CTRL_REQUEST_ACCESS);
+        assert_eq!(read_reg(&mut tpm, CRB_LOC_STS), CRB_LOC_STS_GRANTED);
+        assert_ne!(
+            read_reg(&mut tpm, CRB_LOC_STATE) & CRB_LOC_STATE_LOC_ASSIGNED,
+            0
+        );
+        write_reg(&mut tpm, CRB_CTRL_REQ, CRB_CTRL_REQ_CMD_READY);
+        assert_eq!(read_reg(&mut tpm, CRB_CTRL_STS), 0);
+
+        write_reg(&mut tpm, CRB_CTRL_START, CRB_CTRL_START_INVOKE);
+        assert_eq!(read_reg(&mut tpm, CRB_CTRL_START), 0);
+        assert!(tpm.read(&mut resp, GuestAddress(0), CRB_DATA_BUFFER));
+        assert_eq!(resp[6..], [0, 0, 0, 0, 0xab, 0xcd]);
+
+        write_reg(&mut tpm, CRB_CTRL_REQ, CRB_CTRL_REQ_GO_IDLE);
+        write_reg(&mut tpm, CRB_LOC_CTRL, CRB_LOC_CTRL_RELINQUISH);
+        assert_eq!(
+            read_reg(&mut tpm, CRB_LOC_STATE),
+            CRB_LOC_STATE_REG_VALID_STS
+        );
+
+        // Out of the MMIO region.
+        let mut data = [0_u8; 8];
+        assert!(!tpm.read(&mut data, GuestAddress(0), TPM_CRB_ADDR_SIZE - 4));
+
+        drop(tpm);
+        swtpm.join().unwrap();
+        std::fs::remove_file(sock_path).unwrap();
+    }
+}
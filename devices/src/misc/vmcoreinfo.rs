@@ -0,0 +1,203 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use log::{error, warn};
+
+use crate::legacy::{FwCfgOps, FwCfgWriteCallback};
+use crate::sysbus::{SysBus, SysBusDevBase, SysBusDevOps, SysBusDevType};
+use crate::{Device, DeviceBase};
+use acpi::AmlBuilder;
+use address_space::GuestAddress;
+
+/// Name of the fw_cfg file through which guest registers its vmcoreinfo note.
+const VMCOREINFO_FW_CFG_FILE: &str = "etc/vmcoreinfo";
+/// Size of the fw_cfg file: host_format(u16), guest_format(u16), size(u32), paddr(u64).
+const VMCOREINFO_FW_CFG_SIZE: usize = 16;
+
+const VMCOREINFO_FORMAT_NONE: u16 = 0;
+const VMCOREINFO_FORMAT_ELF: u16 = 1;
+
+/// Location of the vmcoreinfo ELF note in guest memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VmCoreInfoNote {
+    /// Guest physical address of the note.
+    pub paddr: u64,
+    /// Size of the note.
+    pub size: u32,
+}
+
+#[derive(Default)]
+struct VmCoreInfoState {
+    note: Option<VmCoreInfoNote>,
+}
+
+impl FwCfgWriteCallback for VmCoreInfoState {
+    fn write_callback(&mut self, data: Vec<u8>, start: u64, len: usize) {
+        if start != 0 || len != VMCOREINFO_FW_CFG_SIZE || data.len() < VMCOREINFO_FW_CFG_SIZE {
+            error!(
+                "Invalid write to vmcoreinfo, offset {} length {}",
+                start, len
+            );
+            return;
+        }
+
+        let guest_format = LittleEndian::read_u16(&data[2..4]);
+        self.note = match guest_format {
+            VMCOREINFO_FORMAT_NONE => None,
+            VMCOREINFO_FORMAT_ELF => Some(VmCoreInfoNote {
+                paddr: LittleEndian::read_u64(&data[8..16]),
+                size: LittleEndian::read_u32(&data[4..8]),
+            }),
+            _ => {
+                warn!("Unsupported vmcoreinfo format {}", guest_format);
+                None
+            }
+        };
+    }
+}
+
+/// vmcoreinfo device, through which guest kernel registers the location of its
+/// vmcoreinfo note. The note is put into the dump of guest memory, so that crash
+/// analysis tools can find kernel symbols with it.
+pub struct VmCoreInfo {
+    base: SysBusDevBase,
+    state: Arc<Mutex<VmCoreInfoState>>,
+}
+
+impl VmCoreInfo {
+    pub fn new() -> Self {
+        Self {
+            base: SysBusDevBase::new(SysBusDevType::Others),
+            state: Arc::new(Mutex::new(VmCoreInfoState::default())),
+        }
+    }
+
+    /// Add the fw_cfg file which guest writes the note location to.
+    pub fn setup(&self, fwcfg: &Arc<Mutex<dyn FwCfgOps>>) -> Result<()> {
+        let mut data = vec![0_u8; VMCOREINFO_FW_CFG_SIZE];
+        LittleEndian::write_u16(&mut data[0..2], VMCOREINFO_FORMAT_ELF);
+        fwcfg
+            .lock()
+            .unwrap()
+            .add_file_callback_entry(
+                VMCOREINFO_FW_CFG_FILE,
+                data,
+                None,
+                Some(self.state.clone()),
+                true,
+            )
+            .with_context(|| "Failed to add vmcoreinfo fw_cfg file")
+    }
+
+    pub fn realize(self, sysbus: &mut SysBus) -> Result<Arc<Mutex<Self>>> {
+        let dev = Arc::new(Mutex::new(self));
+        sysbus.attach_dynamic_device(&dev)?;
+        Ok(dev)
+    }
+
+    /// Get the location of the note registered by guest.
+    pub fn get_note(&self) -> Option<VmCoreInfoNote> {
+        self.state.lock().unwrap().note
+    }
+}
+
+impl Default for VmCoreInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for VmCoreInfo {
+    fn device_base(&self) -> &DeviceBase {
+        &self.base.base
+    }
+
+    fn device_base_mut(&mut self) -> &mut DeviceBase {
+        &mut self.base.base
+    }
+}
+
+impl SysBusDevOps for VmCoreInfo {
+    fn sysbusdev_base(&self) -> &SysBusDevBase {
+        &self.base
+    }
+
+    fn sysbusdev_base_mut(&mut self) -> &mut SysBusDevBase {
+        &mut self.base
+    }
+
+    fn read(&mut self, _data: &mut [u8], _base: GuestAddress, _offset: u64) -> bool {
+        false
+    }
+
+    fn write(&mut self, _data: &[u8], _base: GuestAddress, _offset: u64) -> bool {
+        false
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // The note is registered again by the new kernel.
+        self.state.lock().unwrap().note = None;
+        Ok(())
+    }
+}
+
+impl AmlBuilder for VmCoreInfo {
+    fn aml_bytes(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vmcoreinfo_write() {
+        let mut vmcoreinfo = VmCoreInfo::new();
+        let mut data = vec![0_u8; VMCOREINFO_FW_CFG_SIZE];
+        LittleEndian::write_u16(&mut data[2..4], VMCOREINFO_FORMAT_ELF);
+        LittleEndian::write_u32(&mut data[4..8], 0x1000);
+        LittleEndian::write_u64(&mut data[8..16], 0x1_2345_6000);
+
+        // Partial write is ignored.
+        let state = vmcoreinfo.state.clone();
+        state.lock().unwrap().write_callback(data.clone(), 0, 8);
+        assert_eq!(vmcoreinfo.get_note(), None);
+
+        state
+            .lock()
+            .unwrap()
+            .write_callback(data.clone(), 0, VMCOREINFO_FW_CFG_SIZE);
+        assert_eq!(
+            vmcoreinfo.get_note(),
+            Some(VmCoreInfoNote {
+                paddr: 0x1_2345_6000,
+                size: 0x1000,
+            })
+        );
+
+        vmcoreinfo.reset().unwrap();
+        assert_eq!(vmcoreinfo.get_note(), None);
+
+        // Unknown format.
+        LittleEndian::write_u16(&mut data[2..4], 2);
+        state
+            .lock()
+            .unwrap()
+            .write_callback(data, 0, VMCOREINFO_FW_CFG_SIZE);
+        assert_eq!(vmcoreinfo.get_note(), None);
+    }
+}
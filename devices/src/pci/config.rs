@@ -22,7 +22,7 @@ use crate::pci::{
     le_read_u16, le_read_u32, le_read_u64, le_write_u16, le_write_u32, le_write_u64,
     pci_ext_cap_next, PciBus, PciError, BDF_FUNC_SHIFT,
 };
-use address_space::Region;
+use address_space::{AddressSpace, Region};
 use util::num_ops::ranges_overlap;
 
 /// Size in bytes of the configuration space of legacy PCI device.
@@ -147,6 +147,10 @@ const BAR_NUM_MAX_FOR_ENDPOINT: u8 = 6;
 const BAR_NUM_MAX_FOR_BRIDGE: u8 = 2;
 /// mmio bar's minimum size shall be 4KB
 pub const MINIMUM_BAR_SIZE_FOR_MMIO: usize = 0x1000;
+/// Bit of expansion ROM BAR which enables the address decode of ROM.
+pub const ROM_ADDRESS_ENABLE: u32 = 0x01;
+/// Mask of the base address in expansion ROM BAR.
+pub const ROM_ADDRESS_MASK: u32 = 0xffff_f800;
 /// pio bar's minimum size shall be 4B
 const MINIMUM_BAR_SIZE_FOR_PIO: usize = 0x4;
 
@@ -397,6 +401,8 @@ pub struct PciConfig {
     pub pci_express_cap_offset: u16,
     /// INTx information.
     pub intx: Option<Arc<Mutex<Intx>>>,
+    /// Index of the expansion ROM in `bars`.
+    pub rom_bar_id: Option<usize>,
 }
 
 impl PciConfig {
@@ -430,6 +436,7 @@ impl PciConfig {
             msix: None,
             pci_express_cap_offset: PCI_CONFIG_HEAD_END as u16,
             intx: None,
+            rom_bar_id: None,
         }
     }
 
@@ -722,6 +729,13 @@ impl PciConfig {
     /// * `id` - Index of the BAR.
     pub fn get_bar_address(&self, id: usize) -> u64 {
         let command = le_read_u16(&self.config, COMMAND as usize).unwrap();
+        if self.rom_bar_id == Some(id) {
+            let rom_val = le_read_u32(&self.config, ROM_ADDRESS_ENDPOINT).unwrap();
+            if command & COMMAND_MEMORY_SPACE == 0 || rom_val & ROM_ADDRESS_ENABLE == 0 {
+                return BAR_SPACE_UNMAPPED;
+            }
+            return (rom_val & ROM_ADDRESS_MASK) as u64;
+        }
         let offset: usize = BAR_0 as usize + id * REG_SIZE;
         if self.config[offset] & BAR_IO_SPACE > 0 {
             if command & COMMAND_IO_SPACE == 0 {
@@ -794,12 +808,56 @@ impl PciConfig {
         Ok(())
     }
 
+    /// Register the expansion ROM of endpoint in PciConfig::bars, the ROM is mapped
+    /// when both memory space and the ROM address decode are enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `region` - Rom region which holds the content of expansion ROM.
+    /// * `size` - Size of the expansion ROM.
+    pub fn register_rom_bar(&mut self, region: Region, size: u64) -> Result<()> {
+        if self.config[HEADER_TYPE as usize] & HEADER_TYPE_BRIDGE != 0 || self.rom_bar_id.is_some()
+        {
+            return Err(anyhow!(PciError::InvalidConf(
+                "Expansion ROM".to_string(),
+                "duplicated or bridge".to_string(),
+            )));
+        }
+        if !size.is_power_of_two()
+            || size < MINIMUM_BAR_SIZE_FOR_MMIO as u64
+            || size > u64::from(ROM_ADDRESS_MASK)
+        {
+            return Err(anyhow!(PciError::InvalidConf(
+                "Expansion ROM size".to_string(),
+                size.to_string(),
+            )));
+        }
+
+        let write_mask = !(size - 1) as u32 | ROM_ADDRESS_ENABLE;
+        le_write_u32(&mut self.write_mask, ROM_ADDRESS_ENDPOINT, write_mask)?;
+        le_write_u32(&mut self.config, ROM_ADDRESS_ENDPOINT, 0)?;
+        self.bars.push(Bar {
+            region_type: RegionType::Mem32Bit,
+            address: BAR_SPACE_UNMAPPED,
+            size,
+            region: Some(region),
+            parent_io_region: None,
+            parent_mem_region: None,
+        });
+        self.rom_bar_id = Some(self.bars.len() - 1);
+        Ok(())
+    }
+
     /// Unregister region in PciConfig::bars.
     ///
     /// # Arguments
     ///
     /// * `bus` - The bus which region registered.
     pub fn unregister_bars(&mut self, bus: &Arc<Mutex<PciBus>>) -> Result<()> {
+        AddressSpace::transaction(|| self.do_unregister_bars(bus))
+    }
+
+    fn do_unregister_bars(&mut self, bus: &Arc<Mutex<PciBus>>) -> Result<()> {
         let locked_bus = bus.lock().unwrap();
         for bar in self.bars.iter_mut() {
             if bar.address == BAR_SPACE_UNMAPPED || bar.size == 0 {
@@ -857,6 +915,21 @@ impl PciConfig {
         &mut self,
         #[cfg(target_arch = "x86_64")] io_region: Option<&Region>,
         mem_region: Option<&Region>,
+    ) -> Result<()> {
+        // Remapping all BARs results in one topology update.
+        AddressSpace::transaction(|| {
+            self.do_update_bar_mapping(
+                #[cfg(target_arch = "x86_64")]
+                io_region,
+                mem_region,
+            )
+        })
+    }
+
+    fn do_update_bar_mapping(
+        &mut self,
+        #[cfg(target_arch = "x86_64")] io_region: Option<&Region>,
+        mem_region: Option<&Region>,
     ) -> Result<()> {
         for id in 0..self.bars.len() {
             if self.bars[id].size == 0 {
@@ -1298,6 +1371,57 @@ mod tests {
         assert_eq!(pci_config.get_bar_address(2), MEM_BASE_ADDR_MASK);
     }
 
+    #[test]
+    fn test_register_rom_bar() {
+        let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let write_ops = move |_data: &[u8], _addr: GuestAddress, _offset: u64| -> bool { true };
+        let region_ops = RegionOps {
+            read: Arc::new(read_ops),
+            write: Arc::new(write_ops),
+        };
+        let region = Region::init_io_region(0x10000, region_ops, "rom");
+        let mut pci_config = PciConfig::new(PCI_CONFIG_SPACE_SIZE, 6);
+        pci_config.init_common_write_mask().unwrap();
+
+        // ROM size must be power of 2 and not smaller than 4KB.
+        assert!(pci_config.register_rom_bar(region.clone(), 0x800).is_err());
+        assert!(pci_config.register_rom_bar(region.clone(), 0x3000).is_err());
+        assert!(pci_config.register_rom_bar(region.clone(), 0x10000).is_ok());
+        assert!(pci_config.register_rom_bar(region, 0x10000).is_err());
+        let rom_id = pci_config.rom_bar_id.unwrap();
+        assert_eq!(rom_id, 6);
+
+        // Guest probes the size of ROM.
+        pci_config.write(
+            ROM_ADDRESS_ENDPOINT,
+            &ROM_ADDRESS_MASK.to_le_bytes(),
+            0,
+            #[cfg(target_arch = "x86_64")]
+            None,
+            None,
+        );
+        let mut buf = [0_u8; 4];
+        pci_config.read(ROM_ADDRESS_ENDPOINT, &mut buf);
+        assert_eq!(u32::from_le_bytes(buf), 0xffff_0000);
+
+        le_write_u32(&mut pci_config.config, ROM_ADDRESS_ENDPOINT, 0xfe00_0000).unwrap();
+        le_write_u16(
+            &mut pci_config.config,
+            COMMAND as usize,
+            COMMAND_MEMORY_SPACE,
+        )
+        .unwrap();
+        // ROM address decode is disabled.
+        assert_eq!(pci_config.get_bar_address(rom_id), BAR_SPACE_UNMAPPED);
+        le_write_u32(
+            &mut pci_config.config,
+            ROM_ADDRESS_ENDPOINT,
+            0xfe00_0000 | ROM_ADDRESS_ENABLE,
+        )
+        .unwrap();
+        assert_eq!(pci_config.get_bar_address(rom_id), 0xfe00_0000);
+    }
+
     #[test]
     fn test_update_bar_mapping() {
         let read_ops = move |_data: &mut [u8], _addr: GuestAddress, _offset: u64| -> bool { true };
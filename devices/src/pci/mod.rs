@@ -17,6 +17,7 @@ pub mod error;
 pub mod hotplug;
 pub mod intx;
 pub mod msix;
+pub mod rom;
 
 mod bus;
 mod host;
@@ -0,0 +1,103 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::cmp::max;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use crate::pci::config::{PciConfig, MINIMUM_BAR_SIZE_FOR_MMIO};
+use address_space::{GuestAddress, HostMemMapping, Region};
+
+/// Signature at the beginning of PCI option ROM image.
+const ROM_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+/// Get the size of expansion ROM BAR which holds the option ROM image.
+fn rom_bar_size(image_size: u64) -> u64 {
+    max(
+        image_size.next_power_of_two(),
+        MINIMUM_BAR_SIZE_FOR_MMIO as u64,
+    )
+}
+
+/// Load option ROM image, such as iPXE for network boot, and expose it to guest
+/// firmware through the expansion ROM BAR.
+///
+/// # Arguments
+///
+/// * `romfile` - Path of the option ROM image.
+/// * `config` - Configuration space of the pci device.
+/// * `name` - Name of the pci device.
+pub fn init_rom(romfile: &str, config: &mut PciConfig, name: &str) -> Result<()> {
+    let image =
+        std::fs::read(romfile).with_context(|| format!("Failed to read romfile {}", romfile))?;
+    if !image.starts_with(&ROM_SIGNATURE) {
+        bail!("Romfile {} is not a valid PCI option ROM", romfile);
+    }
+
+    let size = rom_bar_size(image.len() as u64);
+    let mem_mapping = Arc::new(HostMemMapping::new(
+        GuestAddress(0),
+        None,
+        size,
+        None,
+        false,
+        false,
+        false,
+    )?);
+    let region = Region::init_rom_region(mem_mapping, None, &format!("{}-rom", name));
+    region.load_rom(&mut image.as_slice(), 0, image.len() as u64)?;
+
+    config
+        .register_rom_bar(region, size)
+        .with_context(|| format!("Failed to register expansion ROM of {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::*;
+    use crate::pci::config::{PCIE_CONFIG_SPACE_SIZE, ROM_ADDRESS_ENABLE};
+
+    #[test]
+    fn test_init_rom() {
+        assert_eq!(rom_bar_size(0x200), 0x1000);
+        assert_eq!(rom_bar_size(0x1000), 0x1000);
+        assert_eq!(rom_bar_size(0x3_4000), 0x4_0000);
+
+        let romfile = "/tmp/test_init_rom.rom";
+        let mut config = PciConfig::new(PCIE_CONFIG_SPACE_SIZE, 6);
+        File::create(romfile)
+            .unwrap()
+            .write_all(&[0x12, 0x34, 0x56])
+            .unwrap();
+        assert!(init_rom(romfile, &mut config, "net0").is_err());
+        assert!(config.rom_bar_id.is_none());
+
+        File::create(romfile)
+            .unwrap()
+            .write_all(&[0x55, 0xaa, 0x56])
+            .unwrap();
+        assert!(init_rom(romfile, &mut config, "net0").is_ok());
+        let rom_bar = &config.bars[config.rom_bar_id.unwrap()];
+        assert_eq!(rom_bar.size, 0x1000);
+        assert_eq!(
+            config.write_mask[0x30..0x34],
+            (0xffff_f000_u32 | ROM_ADDRESS_ENABLE).to_le_bytes()
+        );
+
+        assert!(init_rom("/tmp/test_init_rom_none.rom", &mut config, "net0").is_err());
+        std::fs::remove_file(romfile).unwrap();
+    }
+}
@@ -148,7 +148,7 @@ impl SysBus {
                         )
                     })?;
             }
-            SysBusDevType::Rtc if cfg!(target_arch = "x86_64") => {
+            SysBusDevType::Rtc | SysBusDevType::PvPanic if cfg!(target_arch = "x86_64") => {
                 #[cfg(target_arch = "x86_64")]
                 self.sys_io
                     .root()
@@ -214,6 +214,7 @@ pub enum SysBusDevType {
     Flash,
     #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
     Ramfb,
+    PvPanic,
     Others,
 }
 
@@ -1904,9 +1904,11 @@ impl XhciDevice {
                     trb.parameter
                 };
 
-                let mut hvas = self
-                    .mem_space
-                    .get_address_map(GuestAddress(dma_addr), chunk as u64)?;
+                let mut hvas = self.mem_space.get_address_map(
+                    GuestAddress(dma_addr),
+                    chunk as u64,
+                    locked_xfer.in_xfer,
+                )?;
                 vec.append(&mut hvas);
             }
         }
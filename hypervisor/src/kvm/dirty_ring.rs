@@ -0,0 +1,187 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use kvm_bindings::{kvm_dirty_gfn, kvm_enable_cap};
+use kvm_ioctls::{VcpuFd, VmFd};
+use vmm_sys_util::ioctl::{ioctl, ioctl_with_ref, ioctl_with_val};
+
+use super::interrupt::KVM_CHECK_EXTENSION;
+use super::{KVM_ENABLE_CAP, KVM_RESET_DIRTY_RINGS};
+use util::unix::host_page_size;
+
+const KVM_CAP_DIRTY_LOG_RING: u32 = 192;
+const KVM_CAP_DIRTY_LOG_RING_ACQ_REL: u32 = 223;
+/// Page offset of dirty ring in the mmap area of vCPU fd.
+const KVM_DIRTY_LOG_PAGE_OFFSET: u64 = 64;
+/// The entry is dirty and published by kvm.
+const KVM_DIRTY_GFN_F_DIRTY: u32 = 1;
+/// The entry is collected by userspace and can be reset by kvm.
+const KVM_DIRTY_GFN_F_RESET: u32 = 2;
+
+/// Enable dirty ring of the VM, it must be called before any vCPU is created.
+///
+/// # Arguments
+///
+/// * `vm_fd` - The file descriptor of VM.
+/// * `entries` - Number of entries of each vCPU's dirty ring.
+pub(crate) fn enable_dirty_ring(vm_fd: &VmFd, entries: u32) -> Result<()> {
+    if !entries.is_power_of_two() {
+        bail!("Dirty ring size {} is not power of 2", entries);
+    }
+    let bytes = u64::from(entries) * size_of::<kvm_dirty_gfn>() as u64;
+
+    // Aarch64 only supports the ring with acquire/release ordering.
+    let mut max_bytes = 0;
+    let mut cap = 0;
+    for c in [KVM_CAP_DIRTY_LOG_RING_ACQ_REL, KVM_CAP_DIRTY_LOG_RING] {
+        // SAFETY: vm_fd is valid and KVM_CHECK_EXTENSION doesn't touch memory.
+        let ret = unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), u64::from(c)) };
+        if ret > 0 {
+            max_bytes = ret as u64;
+            cap = c;
+            break;
+        }
+    }
+    if cap == 0 {
+        bail!("Dirty ring is not supported by kvm");
+    }
+    if bytes > max_bytes {
+        bail!(
+            "Dirty ring size {} exceeds the max {} entries of kvm",
+            entries,
+            max_bytes / size_of::<kvm_dirty_gfn>() as u64
+        );
+    }
+
+    let mut enable_cap = kvm_enable_cap {
+        cap,
+        ..Default::default()
+    };
+    enable_cap.args[0] = bytes;
+    // SAFETY: vm_fd is valid and enable_cap is a valid kvm_enable_cap structure.
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_ENABLE_CAP(), &enable_cap) };
+    if ret < 0 {
+        bail!(
+            "Failed to enable dirty ring, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Reset the collected entries of all dirty rings, so that the pages are write-protected again.
+pub(crate) fn reset_dirty_rings(vm_fd: &VmFd) -> Result<()> {
+    // SAFETY: vm_fd is valid and KVM_RESET_DIRTY_RINGS doesn't touch memory.
+    let ret = unsafe { ioctl(vm_fd, KVM_RESET_DIRTY_RINGS()) };
+    if ret < 0 {
+        bail!(
+            "Failed to reset dirty rings, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Dirty ring of one vCPU, which is shared with kvm. Kvm pushes the guest pages written
+/// by the vCPU, and they are collected by userspace from `fetch_index`.
+pub struct DirtyRing {
+    /// Host address of the mapped ring.
+    gfns: *mut kvm_dirty_gfn,
+    /// Number of entries, it is power of 2.
+    entries: u32,
+    /// Index of the next entry to collect.
+    fetch_index: Mutex<u32>,
+}
+
+// SAFETY: The ring is only accessed by atomic flags and under the lock of `fetch_index`.
+unsafe impl Send for DirtyRing {}
+// SAFETY: Same as above.
+unsafe impl Sync for DirtyRing {}
+
+impl DirtyRing {
+    /// Map the dirty ring of vCPU.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_fd` - The file descriptor of vCPU.
+    /// * `entries` - Number of entries of the ring.
+    pub(crate) fn new(vcpu_fd: &VcpuFd, entries: u32) -> Result<Self> {
+        let size = entries as usize * size_of::<kvm_dirty_gfn>();
+        // SAFETY: vcpu_fd is valid, and the ring is mapped at the offset defined by kvm.
+        let gfns = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                vcpu_fd.as_raw_fd(),
+                (KVM_DIRTY_LOG_PAGE_OFFSET * host_page_size()) as libc::off_t,
+            )
+        };
+        if gfns == libc::MAP_FAILED {
+            bail!(
+                "Failed to mmap dirty ring, error is {}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        Ok(DirtyRing {
+            gfns: gfns as *mut kvm_dirty_gfn,
+            entries,
+            fetch_index: Mutex::new(0),
+        })
+    }
+
+    /// Collect the dirty entries of the ring, returns the number of collected entries.
+    ///
+    /// # Arguments
+    ///
+    /// * `mark` - Called with the memory slot and page offset in slot of each dirty entry.
+    pub fn harvest(&self, mark: &mut dyn FnMut(u32, u64)) -> u32 {
+        let mut fetch_index = self.fetch_index.lock().unwrap();
+        let mut count = 0;
+        while count < self.entries {
+            // SAFETY: The index is in range of the ring mapped in `new`.
+            let gfn = unsafe { self.gfns.add((*fetch_index & (self.entries - 1)) as usize) };
+            // SAFETY: `flags` is the first u32 field of the entry, which is shared with kvm.
+            let flags = unsafe { &*(std::ptr::addr_of_mut!((*gfn).flags) as *const AtomicU32) };
+            if flags.load(Ordering::Acquire) & KVM_DIRTY_GFN_F_DIRTY == 0 {
+                break;
+            }
+            // SAFETY: The dirty entry is published by kvm and won't be changed until reset.
+            let (slot, offset) = unsafe { ((*gfn).slot, (*gfn).offset) };
+            mark(slot, offset);
+            flags.store(KVM_DIRTY_GFN_F_RESET, Ordering::Release);
+            *fetch_index = fetch_index.wrapping_add(1);
+            count += 1;
+        }
+        count
+    }
+}
+
+impl Drop for DirtyRing {
+    fn drop(&mut self) {
+        // SAFETY: The ring is mapped in `new` with the same size.
+        unsafe {
+            libc::munmap(
+                self.gfns as *mut libc::c_void,
+                self.entries as usize * size_of::<kvm_dirty_gfn>(),
+            )
+        };
+    }
+}
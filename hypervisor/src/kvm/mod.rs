@@ -10,19 +10,40 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+mod dirty_ring;
 mod interrupt;
+mod private_mem;
+#[cfg(target_arch = "x86_64")]
+mod sev;
+mod stats;
+#[cfg(target_arch = "aarch64")]
+mod sve;
 
+pub use dirty_ring::DirtyRing;
 pub use interrupt::MsiVector;
+pub use private_mem::{get_memory_fault, ConfidentialGuest, MemoryFault, KVM_EXIT_MEMORY_FAULT};
+#[cfg(target_arch = "x86_64")]
+pub use sev::{
+    check_snp_policy, sev_hashes_table_location, sev_kernel_hashes_table, SevSnpGuest, SevSnpInfo,
+    SevState, KVM_X86_SNP_VM, SEV_MNONCE_LEN, SNP_HOST_DATA_LEN, SNP_POLICY_DEBUG,
+    SNP_POLICY_DEFAULT,
+};
+pub use stats::VcpuStats;
+#[cfg(target_arch = "aarch64")]
+pub use sve::{finalize_sve, get_wide_reg, set_wide_reg, sve_supported};
 
 use std::collections::HashMap;
+use std::fs::File;
 use std::mem::{align_of, size_of};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Context, Result};
 use arc_swap::ArcSwap;
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
 use kvm_bindings::*;
-use kvm_ioctls::{Kvm, VmFd};
+use kvm_ioctls::{Kvm, VcpuFd, VmFd};
 use log::error;
 use once_cell::sync::Lazy;
 use vmm_sys_util::{
@@ -30,6 +51,7 @@ use vmm_sys_util::{
 };
 
 use interrupt::{IrqRoute, IrqRouteEntry, IrqRouteTable};
+use private_mem::{kvm_create_guest_memfd, kvm_memory_attributes, kvm_userspace_memory_region2};
 
 // See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/asm-generic/kvm.h
 pub const KVM_SET_DEVICE_ATTR: u32 = 0x4018_aee1;
@@ -66,6 +88,14 @@ ioctl_iow_nr!(KVM_SET_MSRS, KVMIO, 0x89, kvm_msrs);
 #[cfg(target_arch = "x86_64")]
 ioctl_iow_nr!(KVM_SET_VCPU_EVENTS, KVMIO, 0xa0, kvm_vcpu_events);
 #[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_CLOCK, KVMIO, 0x7b, kvm_clock_data);
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_SET_TSC_KHZ, KVMIO, 0xa2);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_GUEST_DEBUG, KVMIO, 0x9b, kvm_guest_debug);
+#[cfg(target_arch = "x86_64")]
+ioctl_iowr_nr!(KVM_TRANSLATE, KVMIO, 0x85, kvm_translation);
+#[cfg(target_arch = "x86_64")]
 ioctl_ior_nr!(KVM_GET_PIT2, KVMIO, 0x9f, kvm_pit_state2);
 ioctl_ior_nr!(KVM_GET_CLOCK, KVMIO, 0x7c, kvm_clock_data);
 ioctl_iowr_nr!(KVM_GET_IRQCHIP, KVMIO, 0x62, kvm_irqchip);
@@ -93,8 +123,43 @@ ioctl_iow_nr!(KVM_GET_DEVICE_ATTR, KVMIO, 0xe2, kvm_device_attr);
 ioctl_iowr_nr!(KVM_GET_REG_LIST, KVMIO, 0xb0, kvm_reg_list);
 #[cfg(target_arch = "aarch64")]
 ioctl_iow_nr!(KVM_ARM_VCPU_INIT, KVMIO, 0xae, kvm_vcpu_init);
+#[cfg(target_arch = "aarch64")]
+ioctl_iow_nr!(KVM_ARM_VCPU_FINALIZE, KVMIO, 0xc2, std::os::raw::c_int);
 ioctl_iow_nr!(KVM_GET_DIRTY_LOG, KVMIO, 0x42, kvm_dirty_log);
 ioctl_iow_nr!(KVM_IRQ_LINE, KVMIO, 0x61, kvm_irq_level);
+ioctl_iow_nr!(KVM_ENABLE_CAP, KVMIO, 0xa3, kvm_enable_cap);
+ioctl_io_nr!(KVM_RESET_DIRTY_RINGS, KVMIO, 0xc7);
+ioctl_io_nr!(KVM_GET_STATS_FD, KVMIO, 0xce);
+ioctl_iowr_nr!(KVM_CREATE_GUEST_MEMFD, KVMIO, 0xd4, kvm_create_guest_memfd);
+ioctl_iow_nr!(
+    KVM_SET_USER_MEMORY_REGION2,
+    KVMIO,
+    0x49,
+    kvm_userspace_memory_region2
+);
+ioctl_iow_nr!(
+    KVM_SET_MEMORY_ATTRIBUTES,
+    KVMIO,
+    0xd2,
+    kvm_memory_attributes
+);
+#[cfg(target_arch = "x86_64")]
+ioctl_iowr_nr!(KVM_MEMORY_ENCRYPT_OP, KVMIO, 0xba, std::os::raw::c_ulong);
+
+/// Type of the VM created by `KVMFds::new`, 0 is the default type.
+static KVM_VM_TYPE: AtomicU64 = AtomicU64::new(0);
+
+/// Memory slot whose private memory is backed by guest_memfd.
+struct PrivateSlot {
+    /// The guest_memfd backing private memory of the slot.
+    gmem: File,
+    /// Guest physical address of the slot.
+    guest_addr: u64,
+    /// Host virtual address of the shared memory of the slot.
+    host_addr: u64,
+    /// Size of the slot.
+    size: u64,
+}
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Default)]
@@ -103,13 +168,28 @@ pub struct KVMFds {
     pub vm_fd: Option<VmFd>,
     pub irq_route_table: Mutex<IrqRouteTable>,
     pub mem_slots: Arc<Mutex<HashMap<u32, MemorySlot>>>,
+    /// Number of entries of each vCPU's dirty ring, 0 means dirty ring is disabled.
+    pub dirty_ring_size: AtomicU32,
+    /// Dirty rings of vCPUs, indexed by vCPU id.
+    pub dirty_rings: Mutex<HashMap<u32, Arc<DirtyRing>>>,
+    /// Launch flow of confidential guest, None means the guest is not confidential.
+    confidential_guest: Mutex<Option<Arc<dyn ConfidentialGuest>>>,
+    /// Whether ram is registered to kvm with private memory.
+    private_memory: AtomicBool,
+    /// Private memory slots, indexed by slot id.
+    private_slots: Mutex<HashMap<u32, PrivateSlot>>,
+    /// Whether guest clock keeps running while VM is paused.
+    clock_advance_on_pause: AtomicBool,
+    /// Kvm clock saved when VM is paused, which is restored when VM is resumed.
+    #[cfg(target_arch = "x86_64")]
+    paused_clock: Mutex<Option<kvm_clock_data>>,
 }
 
 impl KVMFds {
     pub fn new() -> Self {
         match Kvm::new() {
             Ok(fd) => {
-                let vm_fd = match fd.create_vm() {
+                let vm_fd = match fd.create_vm_with_type(KVM_VM_TYPE.load(Ordering::SeqCst)) {
                     Ok(vm_fd) => vm_fd,
                     Err(e) => {
                         error!("Failed to create VM in KVM: {:?}", e);
@@ -122,6 +202,14 @@ impl KVMFds {
                     vm_fd: Some(vm_fd),
                     irq_route_table,
                     mem_slots: Arc::new(Mutex::new(HashMap::new())),
+                    dirty_ring_size: AtomicU32::new(0),
+                    dirty_rings: Mutex::new(HashMap::new()),
+                    confidential_guest: Mutex::new(None),
+                    private_memory: AtomicBool::new(false),
+                    private_slots: Mutex::new(HashMap::new()),
+                    clock_advance_on_pause: AtomicBool::new(false),
+                    #[cfg(target_arch = "x86_64")]
+                    paused_clock: Mutex::new(None),
                 }
             }
             Err(e) => {
@@ -189,6 +277,9 @@ impl KVMFds {
 
     /// Start dirty page tracking in kvm.
     pub fn start_dirty_log(&self) -> Result<()> {
+        if self.private_memory_enabled() {
+            bail!("Dirty page tracking is not supported for private memory");
+        }
         for (_, region) in self.mem_slots.lock().unwrap().iter_mut() {
             region.flags = KVM_MEM_LOG_DIRTY_PAGES;
             // Safe because region from `KVMFds` is reliable.
@@ -248,6 +339,266 @@ impl KVMFds {
         Ok(res)
     }
 
+    /// Enable dirty ring instead of dirty bitmap to track dirty pages, it must be called
+    /// before any vCPU is created.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - Number of entries of each vCPU's dirty ring, 0 means disabled.
+    pub fn enable_dirty_ring(&self, entries: u32) -> Result<()> {
+        if entries == 0 {
+            return Ok(());
+        }
+        dirty_ring::enable_dirty_ring(self.vm_fd.as_ref().unwrap(), entries)?;
+        self.dirty_ring_size.store(entries, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Whether dirty pages are tracked by dirty ring.
+    pub fn dirty_ring_enabled(&self) -> bool {
+        self.dirty_ring_size.load(Ordering::SeqCst) != 0
+    }
+
+    /// Map the dirty ring of a new vCPU if dirty ring is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - The id of vCPU.
+    /// * `vcpu_fd` - The file descriptor of vCPU.
+    pub fn register_dirty_ring(&self, vcpu_id: u32, vcpu_fd: &VcpuFd) -> Result<()> {
+        let entries = self.dirty_ring_size.load(Ordering::SeqCst);
+        if entries == 0 {
+            return Ok(());
+        }
+        let ring = DirtyRing::new(vcpu_fd, entries)
+            .with_context(|| format!("Failed to map dirty ring of vcpu {}", vcpu_id))?;
+        self.dirty_rings
+            .lock()
+            .unwrap()
+            .insert(vcpu_id, Arc::new(ring));
+        Ok(())
+    }
+
+    /// Get the dirty rings of all vCPUs.
+    pub fn get_dirty_rings(&self) -> Vec<Arc<DirtyRing>> {
+        self.dirty_rings.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Get the dirty ring of vCPU.
+    pub fn get_dirty_ring(&self, vcpu_id: u32) -> Option<Arc<DirtyRing>> {
+        self.dirty_rings.lock().unwrap().get(&vcpu_id).cloned()
+    }
+
+    /// Reset collected entries of dirty rings, which re-protects the pages in kvm.
+    pub fn reset_dirty_rings(&self) -> Result<()> {
+        dirty_ring::reset_dirty_rings(self.vm_fd.as_ref().unwrap())
+    }
+
+    /// Set whether guest clock keeps running while VM is paused.
+    ///
+    /// # Arguments
+    ///
+    /// * `advance` - Guest clock keeps running while VM is paused or not.
+    pub fn set_clock_advance_on_pause(&self, advance: bool) {
+        self.clock_advance_on_pause.store(advance, Ordering::SeqCst);
+    }
+
+    /// Whether guest clock keeps running while VM is paused.
+    pub fn clock_advance_on_pause(&self) -> bool {
+        self.clock_advance_on_pause.load(Ordering::SeqCst)
+    }
+
+    /// Save kvm clock when VM is paused, so that guest doesn't see the time
+    /// elapsed during pause after VM is resumed.
+    #[cfg(target_arch = "x86_64")]
+    pub fn pause_clock(&self) -> Result<()> {
+        if self.clock_advance_on_pause() {
+            return Ok(());
+        }
+        let clock = self
+            .vm_fd
+            .as_ref()
+            .unwrap()
+            .get_clock()
+            .with_context(|| "Failed to get kvm clock")?;
+        *self.paused_clock.lock().unwrap() = Some(clock);
+        Ok(())
+    }
+
+    /// Restore kvm clock saved by `pause_clock` when VM is resumed.
+    #[cfg(target_arch = "x86_64")]
+    pub fn resume_clock(&self) -> Result<()> {
+        if let Some(mut clock) = self.paused_clock.lock().unwrap().take() {
+            // Flags returned by KVM_GET_CLOCK are not accepted by KVM_SET_CLOCK.
+            clock.flags = 0;
+            self.vm_fd
+                .as_ref()
+                .unwrap()
+                .set_clock(&clock)
+                .with_context(|| "Failed to set kvm clock")?;
+        }
+        Ok(())
+    }
+
+    /// Get kvm clock of guest, which is the one saved by `pause_clock` if VM is paused.
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_clock(&self) -> Result<kvm_clock_data> {
+        if let Some(clock) = *self.paused_clock.lock().unwrap() {
+            return Ok(clock);
+        }
+        self.vm_fd
+            .as_ref()
+            .unwrap()
+            .get_clock()
+            .with_context(|| "Failed to get kvm clock")
+    }
+
+    /// Set the launch flow of confidential guest, it must be called before
+    /// `init_confidential_guest`.
+    pub fn set_confidential_guest(&self, guest: Arc<dyn ConfidentialGuest>) {
+        *self.confidential_guest.lock().unwrap() = Some(guest);
+    }
+
+    /// Initialize confidential guest and enable private memory if the guest is
+    /// confidential. It must be called before any ram is registered to kvm.
+    pub fn init_confidential_guest(&self) -> Result<()> {
+        let guest = match self.confidential_guest.lock().unwrap().clone() {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        private_mem::check_private_memory(vm_fd)?;
+        guest
+            .init(vm_fd)
+            .with_context(|| "Failed to init confidential guest")?;
+        self.private_memory.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Encrypt and measure the initial private memory, then finish the launch of
+    /// confidential guest. It must be called after guest memory is loaded and before
+    /// any vCPU runs.
+    pub fn launch_confidential_guest(&self) -> Result<()> {
+        let guest = match self.confidential_guest.lock().unwrap().clone() {
+            Some(g) => g,
+            None => return Ok(()),
+        };
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        for slot in self.private_slots.lock().unwrap().values() {
+            guest
+                .launch_update(vm_fd, slot.guest_addr, slot.host_addr, slot.size)
+                .with_context(|| {
+                    format!(
+                        "Failed to update launch memory 0x{:X}, size 0x{:X}",
+                        slot.guest_addr, slot.size
+                    )
+                })?;
+        }
+        guest
+            .launch_finish(vm_fd)
+            .with_context(|| "Failed to finish launch of confidential guest")
+    }
+
+    /// Whether ram is registered to kvm with private memory.
+    pub fn private_memory_enabled(&self) -> bool {
+        self.private_memory.load(Ordering::SeqCst)
+    }
+
+    /// Register ram memory slot to kvm, whose private memory is backed by a new
+    /// guest_memfd. The memory is private after registration.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_slot` - The memory slot, whose `userspace_addr` is used as shared memory.
+    pub fn set_private_memory_region(&self, mem_slot: MemorySlot) -> Result<()> {
+        let vm_fd = self.vm_fd.as_ref().unwrap();
+        let gmem = private_mem::create_guest_memfd(vm_fd, mem_slot.memory_size)?;
+        private_mem::set_user_memory_region2(
+            vm_fd,
+            mem_slot.slot,
+            mem_slot.guest_phys_addr,
+            mem_slot.memory_size,
+            mem_slot.userspace_addr,
+            &gmem,
+        )?;
+        private_mem::set_memory_attributes(
+            vm_fd,
+            mem_slot.guest_phys_addr,
+            mem_slot.memory_size,
+            true,
+        )?;
+        self.private_slots.lock().unwrap().insert(
+            mem_slot.slot,
+            PrivateSlot {
+                gmem,
+                guest_addr: mem_slot.guest_phys_addr,
+                host_addr: mem_slot.userspace_addr,
+                size: mem_slot.memory_size,
+            },
+        );
+        Ok(())
+    }
+
+    /// Release the guest_memfd of private memory slot, it is called after the slot is
+    /// unregistered from kvm.
+    pub fn remove_private_memory_region(&self, slot: u32) {
+        self.private_slots.lock().unwrap().remove(&slot);
+    }
+
+    /// Convert guest memory between shared and private, and discard the backing of the
+    /// memory in the old state.
+    ///
+    /// # Arguments
+    ///
+    /// * `gpa` - Guest physical address of the memory.
+    /// * `size` - Size of the memory.
+    /// * `private` - Convert the memory to private or shared.
+    pub fn convert_memory(&self, gpa: u64, size: u64, private: bool) -> Result<()> {
+        let locked_slots = self.private_slots.lock().unwrap();
+        let slot = locked_slots
+            .values()
+            .find(|s| gpa >= s.guest_addr && gpa + size <= s.guest_addr + s.size)
+            .with_context(|| {
+                format!(
+                    "Memory 0x{:X}, size 0x{:X} to convert is not private memory",
+                    gpa, size
+                )
+            })?;
+        private_mem::set_memory_attributes(self.vm_fd.as_ref().unwrap(), gpa, size, private)?;
+
+        let offset = gpa - slot.guest_addr;
+        let ret = if private {
+            // SAFETY: The shared memory is mapped by the slot and host doesn't access it
+            // after the memory is converted to private.
+            unsafe {
+                libc::madvise(
+                    (slot.host_addr + offset) as *mut libc::c_void,
+                    size as libc::size_t,
+                    libc::MADV_DONTNEED,
+                )
+            }
+        } else {
+            // SAFETY: The guest_memfd is owned by the slot.
+            unsafe {
+                libc::fallocate(
+                    slot.gmem.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    size as libc::off_t,
+                )
+            }
+        };
+        if ret < 0 {
+            error!(
+                "Failed to discard memory 0x{:X}, size 0x{:X} after conversion, error is {}",
+                gpa,
+                size,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
     /// Add ram memory region to `KVMFds` structure.
     pub fn add_mem_slot(&self, mem_slot: MemorySlot) -> Result<()> {
         if mem_slot.flags & KVM_MEM_READONLY != 0 {
@@ -274,4 +625,18 @@ impl KVMFds {
     }
 }
 
+/// Set the type of VM, such as confidential VM. It must be called before `KVM_FDS`
+/// is used for the first time.
+///
+/// # Arguments
+///
+/// * `vm_type` - Type of the VM which is passed to `KVM_CREATE_VM`.
+pub fn set_kvm_vm_type(vm_type: u64) -> Result<()> {
+    if Lazy::get(&KVM_FDS).is_some() {
+        bail!("VM is already created, its type can't be changed");
+    }
+    KVM_VM_TYPE.store(vm_type, Ordering::SeqCst);
+    Ok(())
+}
+
 pub static KVM_FDS: Lazy<ArcSwap<KVMFds>> = Lazy::new(|| ArcSwap::from(Arc::new(KVMFds::new())));
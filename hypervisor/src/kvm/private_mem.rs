@@ -0,0 +1,272 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+use anyhow::{bail, Result};
+use kvm_bindings::kvm_run;
+use kvm_ioctls::{VcpuFd, VmFd};
+use vmm_sys_util::ioctl::{ioctl_with_ref, ioctl_with_val};
+
+use super::interrupt::KVM_CHECK_EXTENSION;
+use super::{KVM_CREATE_GUEST_MEMFD, KVM_SET_MEMORY_ATTRIBUTES, KVM_SET_USER_MEMORY_REGION2};
+use util::unix::host_page_size;
+
+const KVM_CAP_USER_MEMORY2: u32 = 231;
+const KVM_CAP_MEMORY_ATTRIBUTES: u32 = 233;
+const KVM_CAP_GUEST_MEMFD: u32 = 234;
+/// The memory slot is backed by guest_memfd for private memory.
+const KVM_MEM_GUEST_MEMFD: u32 = 1 << 2;
+/// The memory is private to guest.
+const KVM_MEMORY_ATTRIBUTE_PRIVATE: u64 = 1 << 3;
+/// Exit reason of accessing memory whose attributes mismatch the access of guest.
+pub const KVM_EXIT_MEMORY_FAULT: u32 = 39;
+/// Guest accesses the memory as private.
+const KVM_MEMORY_EXIT_FLAG_PRIVATE: u64 = 1 << 3;
+
+#[repr(C)]
+#[derive(Default)]
+pub struct kvm_create_guest_memfd {
+    pub size: u64,
+    pub flags: u64,
+    pub reserved: [u64; 6],
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct kvm_userspace_memory_region2 {
+    pub slot: u32,
+    pub flags: u32,
+    pub guest_phys_addr: u64,
+    pub memory_size: u64,
+    pub userspace_addr: u64,
+    pub guest_memfd_offset: u64,
+    pub guest_memfd: u32,
+    pub pad1: u32,
+    pub pad2: [u64; 14],
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct kvm_memory_attributes {
+    pub address: u64,
+    pub size: u64,
+    pub attributes: u64,
+    pub flags: u64,
+}
+
+/// Launch flow of confidential guest, which is implemented per architecture,
+/// such as SEV, TDX and CCA.
+pub trait ConfidentialGuest: Send + Sync {
+    /// Initialize the confidential context of VM, it is called before guest memory
+    /// is registered to kvm.
+    fn init(&self, vm_fd: &VmFd) -> Result<()>;
+
+    /// Encrypt and measure the initial content of private guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `gpa` - Guest physical address of the memory.
+    /// * `hva` - Host virtual address of the memory.
+    /// * `size` - Size of the memory.
+    fn launch_update(&self, vm_fd: &VmFd, gpa: u64, hva: u64, size: u64) -> Result<()>;
+
+    /// Finish the launch, host can't update guest private memory afterwards.
+    fn launch_finish(&self, vm_fd: &VmFd) -> Result<()>;
+}
+
+/// Guest memory access mismatching the private attribute of memory, which is a request
+/// of converting the memory between shared and private.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryFault {
+    /// Guest physical address of the memory.
+    pub gpa: u64,
+    /// Size of the memory.
+    pub size: u64,
+    /// Whether guest accesses the memory as private.
+    pub private: bool,
+}
+
+/// Check that kvm supports private memory backed by guest_memfd for the VM.
+pub(crate) fn check_private_memory(vm_fd: &VmFd) -> Result<()> {
+    for cap in [KVM_CAP_USER_MEMORY2, KVM_CAP_GUEST_MEMFD] {
+        // SAFETY: vm_fd is valid and KVM_CHECK_EXTENSION doesn't touch memory.
+        if unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), u64::from(cap)) } <= 0 {
+            bail!(
+                "Capability {} of private memory is not supported by kvm",
+                cap
+            );
+        }
+    }
+    // SAFETY: Same as above.
+    let attrs = unsafe {
+        ioctl_with_val(
+            vm_fd,
+            KVM_CHECK_EXTENSION(),
+            u64::from(KVM_CAP_MEMORY_ATTRIBUTES),
+        )
+    };
+    if attrs < 0 || attrs as u64 & KVM_MEMORY_ATTRIBUTE_PRIVATE == 0 {
+        bail!("Private memory attribute is not supported by kvm");
+    }
+    Ok(())
+}
+
+/// Create guest_memfd which backs private memory of guest.
+///
+/// # Arguments
+///
+/// * `vm_fd` - The file descriptor of VM.
+/// * `size` - Size of the private memory.
+pub(crate) fn create_guest_memfd(vm_fd: &VmFd, size: u64) -> Result<File> {
+    let gmem = kvm_create_guest_memfd {
+        size,
+        ..Default::default()
+    };
+    // SAFETY: vm_fd is valid and gmem is a valid kvm_create_guest_memfd structure.
+    let fd = unsafe { ioctl_with_ref(vm_fd, KVM_CREATE_GUEST_MEMFD(), &gmem) };
+    if fd < 0 {
+        bail!(
+            "Failed to create guest_memfd, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: fd is a new file descriptor returned by kvm and owned by File.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Register memory slot whose private memory is backed by guest_memfd.
+pub(crate) fn set_user_memory_region2(
+    vm_fd: &VmFd,
+    slot: u32,
+    gpa: u64,
+    size: u64,
+    hva: u64,
+    gmem: &File,
+) -> Result<()> {
+    let region = kvm_userspace_memory_region2 {
+        slot,
+        flags: KVM_MEM_GUEST_MEMFD,
+        guest_phys_addr: gpa,
+        memory_size: size,
+        userspace_addr: hva,
+        guest_memfd_offset: 0,
+        guest_memfd: gmem.as_raw_fd() as u32,
+        ..Default::default()
+    };
+    // SAFETY: vm_fd is valid and region is a valid kvm_userspace_memory_region2 structure.
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_SET_USER_MEMORY_REGION2(), &region) };
+    if ret < 0 {
+        bail!(
+            "Failed to set private memory region, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Set guest memory to be private or shared.
+pub(crate) fn set_memory_attributes(
+    vm_fd: &VmFd,
+    gpa: u64,
+    size: u64,
+    private: bool,
+) -> Result<()> {
+    let attrs = kvm_memory_attributes {
+        address: gpa,
+        size,
+        attributes: if private {
+            KVM_MEMORY_ATTRIBUTE_PRIVATE
+        } else {
+            0
+        },
+        flags: 0,
+    };
+    // SAFETY: vm_fd is valid and attrs is a valid kvm_memory_attributes structure.
+    let ret = unsafe { ioctl_with_ref(vm_fd, KVM_SET_MEMORY_ATTRIBUTES(), &attrs) };
+    if ret < 0 {
+        bail!(
+            "Failed to set memory attributes of 0x{:X}, size 0x{:X}, error is {}",
+            gpa,
+            size,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Get the memory fault of vCPU which exits with `KVM_EXIT_MEMORY_FAULT`.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - The file descriptor of vCPU.
+pub fn get_memory_fault(vcpu_fd: &VcpuFd) -> Result<MemoryFault> {
+    // Conversion happens rarely, so the run structure shared with kvm is mapped for
+    // each fault instead of being kept.
+    let size = host_page_size() as usize;
+    // SAFETY: vcpu_fd is valid and the run structure is at offset 0 of its mmap area.
+    let run = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            vcpu_fd.as_raw_fd(),
+            0,
+        )
+    };
+    if run == libc::MAP_FAILED {
+        bail!(
+            "Failed to mmap kvm run structure, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: The mapping is a valid kvm_run structure, and the exit data of memory
+    // fault is three u64 values of flags, gpa and size at the start of the union.
+    let (exit_reason, flags, gpa, fault_size) = unsafe {
+        let run = &*(run as *const kvm_run);
+        let data = std::ptr::addr_of!(run.__bindgen_anon_1) as *const u64;
+        (
+            run.exit_reason,
+            data.read_unaligned(),
+            data.add(1).read_unaligned(),
+            data.add(2).read_unaligned(),
+        )
+    };
+    // SAFETY: The mapping is created above with the same size.
+    unsafe { libc::munmap(run, size) };
+
+    if exit_reason != KVM_EXIT_MEMORY_FAULT {
+        bail!("Unexpected exit reason {} of memory fault", exit_reason);
+    }
+    Ok(MemoryFault {
+        gpa,
+        size: fault_size,
+        private: flags & KVM_MEMORY_EXIT_FLAG_PRIVATE != 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn test_private_mem_struct_layout() {
+        // Layouts must match the definitions in linux/kvm.h.
+        assert_eq!(size_of::<kvm_create_guest_memfd>(), 64);
+        assert_eq!(size_of::<kvm_userspace_memory_region2>(), 160);
+        assert_eq!(size_of::<kvm_memory_attributes>(), 32);
+    }
+}
@@ -0,0 +1,533 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use kvm_ioctls::VmFd;
+use log::warn;
+use ring::digest::{digest, SHA256};
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_iowr_nr};
+
+use super::{ConfidentialGuest, KVM_MEMORY_ENCRYPT_OP};
+
+/// VM type of SEV-SNP guest, which is used to create VM.
+pub const KVM_X86_SNP_VM: u64 = 4;
+
+const KVM_SEV_GET_ATTESTATION_REPORT: u32 = 21;
+const KVM_SEV_INIT2: u32 = 22;
+const KVM_SEV_SNP_LAUNCH_START: u32 = 100;
+const KVM_SEV_SNP_LAUNCH_UPDATE: u32 = 101;
+const KVM_SEV_SNP_LAUNCH_FINISH: u32 = 102;
+/// Command of SEV firmware issued by /dev/sev.
+const SNP_PLATFORM_STATUS: u32 = 9;
+
+/// Page type of the initial guest memory which is encrypted and measured.
+const SNP_PAGE_TYPE_NORMAL: u8 = 1;
+const SNP_PAGE_SIZE: u64 = 4096;
+
+/// Bit 17 of guest policy is reserved and must be one.
+const SNP_POLICY_RESERVED_MBO: u64 = 1 << 17;
+/// Bits of guest policy which are defined by SEV-SNP firmware ABI.
+const SNP_POLICY_MASK: u64 = 0x3f_ffff;
+/// Bit 19 of guest policy allows debugging the guest.
+pub const SNP_POLICY_DEBUG: u64 = 1 << 19;
+/// Default guest policy, SMT is allowed.
+pub const SNP_POLICY_DEFAULT: u64 = 0x3_0000;
+
+/// Length of host data which is included in attestation report.
+pub const SNP_HOST_DATA_LEN: usize = 32;
+/// Length of the nonce which is included in attestation report requested by host.
+pub const SEV_MNONCE_LEN: usize = 16;
+
+/// GUID of the footer of OVMF GUIDed table, which is at the end of the firmware.
+const OVMF_TABLE_FOOTER_GUID: &str = "96b582de-1fb2-45f7-baea-a366c55a082d";
+/// GUID of the OVMF table entry which holds the base and size of the hashes table.
+const SEV_HASH_TABLE_RV_GUID: &str = "7255371f-3a3b-4b04-927b-1da6efa8d454";
+/// Offset of the footer GUID from the end of the firmware.
+const OVMF_TABLE_FOOTER_OFFSET: usize = 32 + 16;
+const SEV_HASH_TABLE_HEADER_GUID: &str = "9438d606-4f22-4cc9-b479-a793d411fd21";
+const SEV_KERNEL_ENTRY_GUID: &str = "4de79437-abd2-427f-b835-d5b172d2045b";
+const SEV_INITRD_ENTRY_GUID: &str = "44baf731-3a2f-4bd7-9af1-41e29169781d";
+const SEV_CMDLINE_ENTRY_GUID: &str = "97d02dd8-bd20-4c94-aa78-e7714d36ab2a";
+/// Length of the entry of hashes table, which is GUID, length and SHA-256 hash.
+const SEV_HASH_ENTRY_LEN: usize = 16 + 2 + 32;
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_cmd {
+    id: u32,
+    pad0: u32,
+    data: u64,
+    error: u32,
+    sev_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_init {
+    vmsa_features: u64,
+    flags: u32,
+    ghcb_version: u16,
+    pad1: u16,
+    pad2: [u32; 8],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_snp_launch_start {
+    policy: u64,
+    gosvw: [u8; 16],
+    flags: u16,
+    pad0: [u8; 6],
+    pad1: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_snp_launch_update {
+    gfn_start: u64,
+    uaddr: u64,
+    len: u64,
+    type_: u8,
+    pad0: u8,
+    flags: u16,
+    pad1: u32,
+    pad2: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_snp_launch_finish {
+    id_block_uaddr: u64,
+    id_auth_uaddr: u64,
+    id_block_en: u8,
+    auth_key_en: u8,
+    vcek_disabled: u8,
+    host_data: [u8; SNP_HOST_DATA_LEN],
+    pad0: [u8; 3],
+    flags: u16,
+    pad1: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct kvm_sev_attestation_report {
+    mnonce: [u8; SEV_MNONCE_LEN],
+    uaddr: u64,
+    len: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct sev_issue_cmd {
+    cmd: u32,
+    data: u64,
+    error: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Default)]
+struct sev_user_data_snp_status {
+    api_major: u8,
+    api_minor: u8,
+    state: u8,
+    rmp_initialized: u8,
+    build_id: u32,
+    features: u32,
+    guest_count: u32,
+    current_tcb_version: u64,
+    reported_tcb_version: u64,
+}
+
+ioctl_iowr_nr!(SEV_ISSUE_CMD, b'S' as u32, 0x0, sev_issue_cmd);
+
+/// State of SEV-SNP guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SevState {
+    /// The guest isn't launched yet.
+    Uninit,
+    /// Initial guest memory is being encrypted and measured.
+    LaunchUpdate,
+    /// The launch is finished and the guest runs.
+    Running,
+}
+
+/// Information of SEV-SNP guest and the platform, which is used to verify the
+/// attestation report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SevSnpInfo {
+    pub state: SevState,
+    pub policy: u64,
+    pub host_data: [u8; SNP_HOST_DATA_LEN],
+    pub api_major: u8,
+    pub api_minor: u8,
+    pub build_id: u32,
+    /// TCB version which is reported in attestation report.
+    pub tcb_version: u64,
+}
+
+/// Launch flow of AMD SEV-SNP guest. The initial content of private memory, such as
+/// firmware, kernel, initrd and boot parameters, is encrypted and measured, and host
+/// data is bound to the guest, both of which are included in attestation report.
+pub struct SevSnpGuest {
+    /// The SEV firmware device, usually /dev/sev.
+    sev_device: File,
+    policy: u64,
+    host_data: [u8; SNP_HOST_DATA_LEN],
+    status: sev_user_data_snp_status,
+    state: Mutex<SevState>,
+}
+
+/// Check the guest policy of SEV-SNP.
+///
+/// # Arguments
+///
+/// * `policy` - The guest policy.
+pub fn check_snp_policy(policy: u64) -> Result<()> {
+    if policy & !SNP_POLICY_MASK != 0 {
+        bail!("Undefined bits are set in SEV-SNP policy 0x{:X}", policy);
+    }
+    if policy & SNP_POLICY_RESERVED_MBO == 0 {
+        bail!("Bit 17 of SEV-SNP policy 0x{:X} must be set", policy);
+    }
+    Ok(())
+}
+
+impl SevSnpGuest {
+    /// Create SEV-SNP guest, it should be done before chroot and seccomp.
+    ///
+    /// # Arguments
+    ///
+    /// * `sev_device` - Path of the SEV firmware device.
+    /// * `policy` - Guest policy which is enforced by SEV firmware.
+    /// * `host_data` - Data provided by host which is included in attestation report.
+    pub fn new(sev_device: &str, policy: u64, host_data: [u8; SNP_HOST_DATA_LEN]) -> Result<Self> {
+        check_snp_policy(policy)?;
+        let sev_device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(sev_device)
+            .with_context(|| format!("Failed to open SEV device {}", sev_device))?;
+        let mut guest = SevSnpGuest {
+            sev_device,
+            policy,
+            host_data,
+            status: sev_user_data_snp_status::default(),
+            state: Mutex::new(SevState::Uninit),
+        };
+        // Platform status is only used for querying, launch doesn't depend on it.
+        if let Err(e) = guest.platform_status() {
+            warn!("Failed to get SEV-SNP platform status: {:?}", e);
+        }
+        Ok(guest)
+    }
+
+    fn platform_status(&mut self) -> Result<()> {
+        let mut status = sev_user_data_snp_status::default();
+        let mut cmd = sev_issue_cmd {
+            cmd: SNP_PLATFORM_STATUS,
+            data: &mut status as *mut sev_user_data_snp_status as u64,
+            error: 0,
+        };
+        // SAFETY: sev_device is valid and cmd points to a valid status buffer.
+        let ret = unsafe { ioctl_with_mut_ref(&self.sev_device, SEV_ISSUE_CMD(), &mut cmd) };
+        if ret < 0 {
+            bail!(
+                "Failed to get SNP platform status, error is {}, firmware error is {}",
+                std::io::Error::last_os_error(),
+                cmd.error
+            );
+        }
+        self.status = status;
+        Ok(())
+    }
+
+    fn sev_ioctl<T>(&self, vm_fd: &VmFd, id: u32, data: &mut T) -> Result<()> {
+        let mut cmd = kvm_sev_cmd {
+            id,
+            data: data as *mut T as u64,
+            sev_fd: self.sev_device.as_raw_fd() as u32,
+            ..Default::default()
+        };
+        // SAFETY: vm_fd is valid and cmd points to the valid data of the command.
+        let ret = unsafe { ioctl_with_mut_ref(vm_fd, KVM_MEMORY_ENCRYPT_OP(), &mut cmd) };
+        if ret < 0 {
+            bail!(
+                "SEV command {} failed, error is {}, firmware error is {}",
+                id,
+                std::io::Error::last_os_error(),
+                cmd.error
+            );
+        }
+        Ok(())
+    }
+
+    /// Get information of the guest and platform.
+    pub fn info(&self) -> SevSnpInfo {
+        let status = &self.status;
+        SevSnpInfo {
+            state: *self.state.lock().unwrap(),
+            policy: self.policy,
+            host_data: self.host_data,
+            api_major: status.api_major,
+            api_minor: status.api_minor,
+            build_id: status.build_id,
+            tcb_version: status.reported_tcb_version,
+        }
+    }
+
+    /// Get the attestation report of the guest from SEV firmware, which contains the
+    /// launch measurement calculated by firmware.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_fd` - Fd of the VM.
+    /// * `mnonce` - Nonce provided by the verifier, which is included in the report.
+    pub fn attestation_report(
+        &self,
+        vm_fd: &VmFd,
+        mnonce: &[u8; SEV_MNONCE_LEN],
+    ) -> Result<Vec<u8>> {
+        if *self.state.lock().unwrap() != SevState::Running {
+            bail!("Launch of SEV-SNP guest is not finished");
+        }
+        // Firmware returns the length of report if the buffer is empty.
+        let mut report = kvm_sev_attestation_report {
+            mnonce: *mnonce,
+            ..Default::default()
+        };
+        let _ = self.sev_ioctl(vm_fd, KVM_SEV_GET_ATTESTATION_REPORT, &mut report);
+        if report.len == 0 {
+            bail!("Failed to get the length of attestation report");
+        }
+        let mut data = vec![0_u8; report.len as usize];
+        report.uaddr = data.as_mut_ptr() as u64;
+        self.sev_ioctl(vm_fd, KVM_SEV_GET_ATTESTATION_REPORT, &mut report)
+            .with_context(|| "Failed to get attestation report")?;
+        data.truncate(report.len as usize);
+        Ok(data)
+    }
+}
+
+/// Convert GUID string to bytes in the mixed-endian layout used by firmware.
+fn guid_bytes(guid: &str) -> [u8; 16] {
+    let hex: String = guid.chars().filter(|c| *c != '-').collect();
+    let mut bytes = [0_u8; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    }
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+/// Find the location of SEV hashes table in OVMF firmware, returns its guest physical
+/// address and size. It's found in the GUIDed table at the end of the firmware.
+///
+/// # Arguments
+///
+/// * `firmware` - Content of the firmware.
+pub fn sev_hashes_table_location(firmware: &[u8]) -> Option<(u64, usize)> {
+    let footer = firmware.len().checked_sub(OVMF_TABLE_FOOTER_OFFSET)?;
+    if footer < 2 || firmware[footer..footer + 16] != guid_bytes(OVMF_TABLE_FOOTER_GUID) {
+        return None;
+    }
+    let table_len = u16::from_le_bytes([firmware[footer - 2], firmware[footer - 1]]) as usize;
+    if table_len < 18 {
+        return None;
+    }
+    let table_start = (footer + 16).checked_sub(table_len)?;
+    let table = &firmware[table_start..footer - 2];
+
+    // Entries are parsed backwards, each ends with its length and GUID.
+    let mut end = table.len();
+    while end >= 18 {
+        let guid = &table[end - 16..end];
+        let len = u16::from_le_bytes([table[end - 18], table[end - 17]]) as usize;
+        if len < 18 || len > end {
+            break;
+        }
+        if guid == guid_bytes(SEV_HASH_TABLE_RV_GUID) && len >= 18 + 8 {
+            let data = &table[end - len..end - 18];
+            let base = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let size = u32::from_le_bytes(data[4..8].try_into().unwrap());
+            return Some((u64::from(base), size as usize));
+        }
+        end -= len;
+    }
+    None
+}
+
+/// Build SEV hashes table of kernel, initrd and cmdline passed to OVMF by fw_cfg. The
+/// table is placed in the initial guest memory and measured at launch, and OVMF refuses
+/// to boot the kernel whose hashes don't match. It's padded to 16 bytes.
+///
+/// # Arguments
+///
+/// * `kernel` - Kernel passed to firmware, which is setup data followed by kernel data.
+/// * `initrd` - Initrd passed to firmware, empty if there is none.
+/// * `cmdline` - Kernel cmdline passed to firmware, including the terminating NUL.
+pub fn sev_kernel_hashes_table(kernel: &[u8], initrd: &[u8], cmdline: &[u8]) -> Vec<u8> {
+    let table_len = 16 + 2 + SEV_HASH_ENTRY_LEN * 3;
+    let mut table = Vec::with_capacity((table_len + 15) & !15);
+    table.extend_from_slice(&guid_bytes(SEV_HASH_TABLE_HEADER_GUID));
+    table.extend_from_slice(&(table_len as u16).to_le_bytes());
+    for (guid, data) in [
+        (SEV_CMDLINE_ENTRY_GUID, cmdline),
+        (SEV_INITRD_ENTRY_GUID, initrd),
+        (SEV_KERNEL_ENTRY_GUID, kernel),
+    ] {
+        table.extend_from_slice(&guid_bytes(guid));
+        table.extend_from_slice(&(SEV_HASH_ENTRY_LEN as u16).to_le_bytes());
+        table.extend_from_slice(digest(&SHA256, data).as_ref());
+    }
+    table.resize((table_len + 15) & !15, 0);
+    table
+}
+
+impl ConfidentialGuest for SevSnpGuest {
+    fn init(&self, vm_fd: &VmFd) -> Result<()> {
+        let mut init = kvm_sev_init::default();
+        self.sev_ioctl(vm_fd, KVM_SEV_INIT2, &mut init)
+            .with_context(|| "Failed to init SEV-SNP guest")?;
+        let mut start = kvm_sev_snp_launch_start {
+            policy: self.policy,
+            ..Default::default()
+        };
+        self.sev_ioctl(vm_fd, KVM_SEV_SNP_LAUNCH_START, &mut start)
+            .with_context(|| "Failed to start launch of SEV-SNP guest")?;
+        *self.state.lock().unwrap() = SevState::LaunchUpdate;
+        Ok(())
+    }
+
+    /// Only pages with content are encrypted and measured by firmware, such as firmware,
+    /// kernel, initrd and the kernel hashes table. Zero pages are left unvalidated, and
+    /// are accepted by guest when used.
+    fn launch_update(&self, vm_fd: &VmFd, gpa: u64, hva: u64, size: u64) -> Result<()> {
+        let page_count = size / SNP_PAGE_SIZE;
+        let mut index = 0;
+        while index < page_count {
+            // SAFETY: The memory of the slot is mapped at hva with the size.
+            let page = |i: u64| unsafe {
+                std::slice::from_raw_parts(
+                    (hva + i * SNP_PAGE_SIZE) as *const u8,
+                    SNP_PAGE_SIZE as usize,
+                )
+            };
+            if page(index).iter().all(|b| *b == 0) {
+                index += 1;
+                continue;
+            }
+            // Pages with content are updated together.
+            let start = index;
+            while index < page_count && page(index).iter().any(|b| *b != 0) {
+                index += 1;
+            }
+
+            let mut update = kvm_sev_snp_launch_update {
+                gfn_start: (gpa + start * SNP_PAGE_SIZE) / SNP_PAGE_SIZE,
+                uaddr: hva + start * SNP_PAGE_SIZE,
+                len: (index - start) * SNP_PAGE_SIZE,
+                type_: SNP_PAGE_TYPE_NORMAL,
+                ..Default::default()
+            };
+            // Kvm updates the fields for the remaining pages if it's interrupted.
+            while update.len != 0 {
+                self.sev_ioctl(vm_fd, KVM_SEV_SNP_LAUNCH_UPDATE, &mut update)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn launch_finish(&self, vm_fd: &VmFd) -> Result<()> {
+        let mut finish = kvm_sev_snp_launch_finish {
+            host_data: self.host_data,
+            ..Default::default()
+        };
+        self.sev_ioctl(vm_fd, KVM_SEV_SNP_LAUNCH_FINISH, &mut finish)
+            .with_context(|| "Failed to finish launch of SEV-SNP guest")?;
+        *self.state.lock().unwrap() = SevState::Running;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn test_sev_struct_layout() {
+        // Layouts must match the definitions in linux/kvm.h and linux/psp-sev.h.
+        assert_eq!(size_of::<kvm_sev_cmd>(), 24);
+        assert_eq!(size_of::<kvm_sev_init>(), 48);
+        assert_eq!(size_of::<kvm_sev_snp_launch_start>(), 64);
+        assert_eq!(size_of::<kvm_sev_snp_launch_update>(), 64);
+        assert_eq!(size_of::<kvm_sev_snp_launch_finish>(), 88);
+        assert_eq!(size_of::<kvm_sev_attestation_report>(), 32);
+        assert_eq!(size_of::<sev_issue_cmd>(), 24);
+        assert_eq!(size_of::<sev_user_data_snp_status>(), 32);
+    }
+
+    #[test]
+    fn test_snp_policy() {
+        assert!(check_snp_policy(SNP_POLICY_DEFAULT).is_ok());
+        assert!(check_snp_policy(SNP_POLICY_DEFAULT | SNP_POLICY_DEBUG).is_ok());
+        assert!(check_snp_policy(0x1_0000).is_err());
+        assert!(check_snp_policy(SNP_POLICY_DEFAULT | 1 << 40).is_err());
+
+        assert!(SevSnpGuest::new("/no/such/sev", SNP_POLICY_DEFAULT, [0; 32]).is_err());
+        assert!(SevSnpGuest::new("/dev/null", 0, [0; 32]).is_err());
+    }
+
+    #[test]
+    fn test_sev_kernel_hashes() {
+        assert_eq!(
+            guid_bytes(SEV_HASH_TABLE_HEADER_GUID),
+            [
+                0x06, 0xd6, 0x38, 0x94, 0x22, 0x4f, 0xc9, 0x4c, 0xb4, 0x79, 0xa7, 0x93, 0xd4, 0x11,
+                0xfd, 0x21
+            ]
+        );
+
+        // The GUIDed table has the entry of hashes table and the footer.
+        let mut firmware = vec![0_u8; 0x1000];
+        let footer = firmware.len() - OVMF_TABLE_FOOTER_OFFSET;
+        let mut table = Vec::new();
+        table.extend_from_slice(&0x80_c000_u32.to_le_bytes());
+        table.extend_from_slice(&0x400_u32.to_le_bytes());
+        table.extend_from_slice(&26_u16.to_le_bytes());
+        table.extend_from_slice(&guid_bytes(SEV_HASH_TABLE_RV_GUID));
+        table.extend_from_slice(&44_u16.to_le_bytes());
+        firmware[footer - table.len()..footer].copy_from_slice(&table);
+        assert_eq!(sev_hashes_table_location(&firmware), None);
+        firmware[footer..footer + 16].copy_from_slice(&guid_bytes(OVMF_TABLE_FOOTER_GUID));
+        assert_eq!(
+            sev_hashes_table_location(&firmware),
+            Some((0x80_c000, 0x400))
+        );
+        assert_eq!(sev_hashes_table_location(&firmware[..16]), None);
+
+        let table = sev_kernel_hashes_table(b"kernel", b"", b"console=ttyS0\0");
+        assert_eq!(table.len(), 176);
+        assert_eq!(u16::from_le_bytes([table[16], table[17]]), 168);
+        assert_eq!(&table[18..34], &guid_bytes(SEV_CMDLINE_ENTRY_GUID));
+        assert_eq!(&table[118..134], &guid_bytes(SEV_KERNEL_ENTRY_GUID));
+        assert_eq!(&table[136..168], digest(&SHA256, b"kernel").as_ref());
+    }
+}
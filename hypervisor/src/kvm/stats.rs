@@ -0,0 +1,166 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::FromRawFd;
+
+use anyhow::{bail, Context, Result};
+use kvm_ioctls::VcpuFd;
+use vmm_sys_util::ioctl::ioctl;
+
+use super::KVM_GET_STATS_FD;
+
+// See: https://elixir.bootlin.com/linux/v5.14/source/include/uapi/linux/kvm.h
+const KVM_STATS_TYPE_MASK: u32 = 0xf;
+const KVM_STATS_TYPE_CUMULATIVE: u32 = 0;
+/// Size of `struct kvm_stats_header`.
+const STATS_HEADER_SIZE: usize = 24;
+/// Size of `struct kvm_stats_desc` without the name.
+const STATS_DESC_SIZE: usize = 16;
+
+/// Descriptor of a statistic, whose value is a u64 at `offset` of data block.
+#[derive(Debug, PartialEq, Eq)]
+struct StatsDesc {
+    name: String,
+    offset: u64,
+}
+
+/// The binary statistics of vCPU provided by kvm, which count the events handled
+/// in kernel, e.g. the exits for EPT violation.
+pub struct VcpuStats {
+    file: File,
+    data_offset: u64,
+    data_size: usize,
+    descs: Vec<StatsDesc>,
+}
+
+impl VcpuStats {
+    /// Open the statistics of vCPU, which is supported since Linux 5.14.
+    pub fn new(vcpu_fd: &VcpuFd) -> Result<Self> {
+        // SAFETY: vcpu_fd is valid and the ioctl has no argument.
+        let fd = unsafe { ioctl(vcpu_fd, KVM_GET_STATS_FD()) };
+        if fd < 0 {
+            bail!(
+                "Failed to get statistics fd of vcpu, error is {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        // SAFETY: fd is newly created and owned by the file.
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        let mut header = [0_u8; STATS_HEADER_SIZE];
+        file.read_exact_at(&mut header, 0)
+            .with_context(|| "Failed to read header of vcpu statistics")?;
+        let field =
+            |index: usize| u32::from_ne_bytes(header[index * 4..index * 4 + 4].try_into().unwrap());
+        let (name_size, num_desc, desc_offset, data_offset) =
+            (field(1), field(2), field(4), field(5));
+
+        let mut buf = vec![0_u8; (STATS_DESC_SIZE + name_size as usize) * num_desc as usize];
+        file.read_exact_at(&mut buf, u64::from(desc_offset))
+            .with_context(|| "Failed to read descriptors of vcpu statistics")?;
+        let descs = parse_stats_descs(&buf, name_size as usize);
+        let data_size = descs
+            .iter()
+            .map(|d| d.offset as usize + 8)
+            .max()
+            .unwrap_or(0);
+
+        Ok(VcpuStats {
+            file,
+            data_offset: u64::from(data_offset),
+            data_size,
+            descs,
+        })
+    }
+
+    /// Read the cumulative statistics, in format of (name, value).
+    pub fn read(&self) -> Result<Vec<(String, u64)>> {
+        let mut data = vec![0_u8; self.data_size];
+        self.file
+            .read_exact_at(&mut data, self.data_offset)
+            .with_context(|| "Failed to read vcpu statistics")?;
+        Ok(self
+            .descs
+            .iter()
+            .map(|desc| {
+                let offset = desc.offset as usize;
+                let value = u64::from_ne_bytes(data[offset..offset + 8].try_into().unwrap());
+                (desc.name.clone(), value)
+            })
+            .collect())
+    }
+}
+
+/// Parse the descriptors of cumulative statistics with single value, the others such as
+/// histograms are skipped.
+fn parse_stats_descs(buf: &[u8], name_size: usize) -> Vec<StatsDesc> {
+    buf.chunks_exact(STATS_DESC_SIZE + name_size)
+        .filter_map(|desc| {
+            let flags = u32::from_ne_bytes(desc[0..4].try_into().unwrap());
+            let size = u16::from_ne_bytes(desc[6..8].try_into().unwrap());
+            let offset = u32::from_ne_bytes(desc[8..12].try_into().unwrap());
+            if flags & KVM_STATS_TYPE_MASK != KVM_STATS_TYPE_CUMULATIVE || size != 1 {
+                return None;
+            }
+            let name = &desc[STATS_DESC_SIZE..];
+            let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+            Some(StatsDesc {
+                name: String::from_utf8_lossy(&name[..len]).to_string(),
+                offset: u64::from(offset),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats_desc(flags: u32, size: u16, offset: u32, name: &str) -> Vec<u8> {
+        let mut desc = Vec::new();
+        desc.extend_from_slice(&flags.to_ne_bytes());
+        desc.extend_from_slice(&0_i16.to_ne_bytes());
+        desc.extend_from_slice(&size.to_ne_bytes());
+        desc.extend_from_slice(&offset.to_ne_bytes());
+        desc.extend_from_slice(&0_u32.to_ne_bytes());
+        let mut name = name.as_bytes().to_vec();
+        name.resize(16, 0);
+        desc.extend_from_slice(&name);
+        desc
+    }
+
+    #[test]
+    fn test_parse_stats_descs() {
+        let mut buf = stats_desc(0, 1, 0, "exits");
+        // Instant statistic.
+        buf.extend(stats_desc(1, 1, 8, "guest_mode"));
+        // Linear histogram.
+        buf.extend(stats_desc(3, 32, 16, "halt_poll_hist"));
+        buf.extend(stats_desc(0, 1, 272, "pf_taken"));
+
+        assert_eq!(
+            parse_stats_descs(&buf, 16),
+            vec![
+                StatsDesc {
+                    name: "exits".to_string(),
+                    offset: 0
+                },
+                StatsDesc {
+                    name: "pf_taken".to_string(),
+                    offset: 272
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::mem::size_of;
+
+use anyhow::{bail, Result};
+use kvm_bindings::{
+    kvm_one_reg, KVM_ARM_VCPU_SVE, KVM_CAP_ARM_SVE, KVM_REG_SIZE_MASK, KVM_REG_SIZE_SHIFT,
+};
+use kvm_ioctls::{VcpuFd, VmFd};
+use vmm_sys_util::ioctl::{ioctl_with_ref, ioctl_with_val};
+
+use super::interrupt::KVM_CHECK_EXTENSION;
+use super::{KVM_ARM_VCPU_FINALIZE, KVM_GET_ONE_REG, KVM_SET_ONE_REG};
+
+/// Check whether kvm supports SVE for guests.
+///
+/// # Arguments
+///
+/// * `vm_fd` - The file descriptor of VM.
+pub fn sve_supported(vm_fd: &VmFd) -> bool {
+    // SAFETY: vm_fd is valid and KVM_CHECK_EXTENSION doesn't touch memory.
+    unsafe { ioctl_with_val(vm_fd, KVM_CHECK_EXTENSION(), u64::from(KVM_CAP_ARM_SVE)) > 0 }
+}
+
+/// Finalize the SVE configuration of vCPU, vector lengths can't be changed and
+/// SVE registers can be accessed afterwards.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - The file descriptor of vCPU.
+pub fn finalize_sve(vcpu_fd: &VcpuFd) -> Result<()> {
+    let feature = KVM_ARM_VCPU_SVE as i32;
+    // SAFETY: vcpu_fd is valid and feature is a valid c_int.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_ARM_VCPU_FINALIZE(), &feature) };
+    if ret < 0 {
+        bail!(
+            "Failed to finalize SVE of vCPU, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+fn check_reg_size(reg_id: u64, len: usize) -> Result<()> {
+    let size = 1_usize << ((reg_id & KVM_REG_SIZE_MASK) >> KVM_REG_SIZE_SHIFT);
+    if len * size_of::<u64>() != size {
+        bail!(
+            "Buffer of {} bytes mismatches the size {} of register 0x{:X}",
+            len * size_of::<u64>(),
+            size,
+            reg_id
+        );
+    }
+    Ok(())
+}
+
+/// Get the value of vCPU register which is wider than 128 bits, such as SVE registers.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - The file descriptor of vCPU.
+/// * `reg_id` - The id of register.
+/// * `data` - Buffer whose size is the same as the register.
+pub fn get_wide_reg(vcpu_fd: &VcpuFd, reg_id: u64, data: &mut [u64]) -> Result<()> {
+    check_reg_size(reg_id, data.len())?;
+    let reg = kvm_one_reg {
+        id: reg_id,
+        addr: data.as_mut_ptr() as u64,
+    };
+    // SAFETY: vcpu_fd is valid and the buffer pointed by reg is as large as the register.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_GET_ONE_REG(), &reg) };
+    if ret < 0 {
+        bail!(
+            "Failed to get register 0x{:X}, error is {}",
+            reg_id,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Set the value of vCPU register which is wider than 128 bits, such as SVE registers.
+///
+/// # Arguments
+///
+/// * `vcpu_fd` - The file descriptor of vCPU.
+/// * `reg_id` - The id of register.
+/// * `data` - Value of the register.
+pub fn set_wide_reg(vcpu_fd: &VcpuFd, reg_id: u64, data: &[u64]) -> Result<()> {
+    check_reg_size(reg_id, data.len())?;
+    let reg = kvm_one_reg {
+        id: reg_id,
+        addr: data.as_ptr() as u64,
+    };
+    // SAFETY: vcpu_fd is valid and the buffer pointed by reg is as large as the register.
+    let ret = unsafe { ioctl_with_ref(vcpu_fd, KVM_SET_ONE_REG(), &reg) };
+    if ret < 0 {
+        bail!(
+            "Failed to set register 0x{:X}, error is {}",
+            reg_id,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
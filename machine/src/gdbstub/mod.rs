@@ -0,0 +1,607 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Gdbstub implements the server side of gdb remote serial protocol, so that
+//! guest kernel can be debugged by gdb through `target remote <ip>:<port>`.
+//!
+//! The whole VM is paused when gdb takes control, and vCPUs are presented as
+//! threads whose id is vCPU index plus one.
+
+mod packet;
+mod x86_64;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use self::packet::{
+    hex_decode, hex_encode, make_packet, parse_hex, GdbInput, PacketParser, MAX_PACKET_SIZE,
+};
+use self::x86_64::{
+    read_registers, register_range, set_guest_debug, translate_gva, write_registers, HwBreakpoint,
+    MAX_HW_BREAKPOINTS, SW_BREAKPOINT_INSN,
+};
+use address_space::{AddressSpace, GuestAddress};
+use cpu::{CPUInterface, CPU};
+use machine_manager::config::GdbConfig;
+use machine_manager::event_loop::EventLoop;
+use machine_manager::machine::{KvmVmState, MachineLifecycle};
+use util::loop_context::{
+    gen_delete_notifiers, read_fd, EventNotifier, NotifierCallback, NotifierOperation,
+};
+
+/// Signal number reported to gdb when vCPU stops for debug exit.
+const SIGTRAP: u8 = 5;
+/// Signal number reported to gdb when the guest is interrupted by gdb.
+const SIGINT: u8 = 2;
+/// Error numbers replied to gdb.
+const EFAULT: u8 = 14;
+const EINVAL: u8 = 22;
+
+/// Software breakpoint, which replaces guest instruction with breakpoint instruction.
+struct SwBreakpoint {
+    /// Guest physical address of breakpoint.
+    gpa: u64,
+    /// Original instruction at the breakpoint.
+    insn: Vec<u8>,
+}
+
+struct GdbStub {
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    vm_state: Arc<(Mutex<KvmVmState>, Condvar)>,
+    cpus: Vec<Arc<CPU>>,
+    sys_mem: Arc<AddressSpace>,
+    listener: TcpListener,
+    /// Connection with gdb, only one gdb can be connected at a time. It's
+    /// kept after gdb detaches, and closed when next gdb connects.
+    stream: Option<File>,
+    attached: bool,
+    parser: PacketParser,
+    /// Index of vCPU whose registers and memory are accessed by gdb.
+    cur_cpu: usize,
+    /// Whether the current vCPU is single-stepping while the VM keeps paused.
+    stepping: bool,
+    /// Software breakpoints indexed by guest virtual address.
+    sw_bps: BTreeMap<u64, SwBreakpoint>,
+    hw_bps: Vec<HwBreakpoint>,
+}
+
+impl GdbStub {
+    fn vm_running(&self) -> bool {
+        *self.vm_state.0.lock().unwrap() == KvmVmState::Running
+    }
+
+    fn pause_vm(&mut self) {
+        if self.stepping {
+            // The VM is paused already, except the single-stepping vCPU.
+            self.stepping = false;
+            if let Err(e) = self.cpus[self.cur_cpu].pause() {
+                error!("Gdbstub failed to pause vcpu{}: {:?}", self.cur_cpu, e);
+            }
+        } else if self.vm_running() && !self.vm.lock().unwrap().pause() {
+            error!("Gdbstub failed to pause VM");
+        }
+    }
+
+    fn resume_vm(&self) {
+        if *self.vm_state.0.lock().unwrap() == KvmVmState::Paused {
+            if !self.vm.lock().unwrap().resume() {
+                error!("Gdbstub failed to resume VM");
+            }
+            return;
+        }
+        // vCPUs may stop for debug exits while the VM is running.
+        for cpu in self.cpus.iter().filter(|cpu| cpu.debug_stopped()) {
+            if let Err(e) = cpu.resume() {
+                error!("Gdbstub failed to resume vcpu{}: {:?}", cpu.id(), e);
+            }
+        }
+    }
+
+    fn accept(&mut self) -> Result<RawFd> {
+        let (stream, addr) = self
+            .listener
+            .accept()
+            .with_context(|| "Failed to accept gdb connection")?;
+        info!("Gdb connected from {}", addr);
+        // Stream is accessed by plain read/write, so that no socket syscalls are needed.
+        let stream = File::from(OwnedFd::from(stream));
+        let fd = stream.as_raw_fd();
+        self.stream = Some(stream);
+        self.attached = true;
+        self.parser = PacketParser::new();
+        self.cur_cpu = 0;
+        self.pause_vm();
+        Ok(fd)
+    }
+
+    /// Remove all breakpoints and let the VM run freely.
+    fn detach(&mut self) {
+        info!("Gdb detached");
+        self.attached = false;
+        if let Err(e) = self.remove_all_breakpoints() {
+            error!("Gdbstub failed to remove breakpoints: {:?}", e);
+        }
+        self.stepping = false;
+        if let Err(e) = self.update_guest_debug() {
+            error!("{:?}", e);
+        }
+        self.resume_vm();
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(stream) = self.stream.as_mut() {
+            stream
+                .write_all(data)
+                .with_context(|| "Failed to send data to gdb")?;
+        }
+        Ok(())
+    }
+
+    fn send_packet(&mut self, data: &[u8]) -> Result<()> {
+        self.send(&make_packet(data))
+    }
+
+    fn send_stop_reply(&mut self, signal: u8) -> Result<()> {
+        let reply = format!("T{:02x}thread:{:x};", signal, self.cur_cpu + 1);
+        self.send_packet(reply.as_bytes())
+    }
+
+    fn handle_input(&mut self) -> Result<bool> {
+        let mut buf = [0_u8; 4096];
+        let len = match self.stream.as_mut() {
+            Some(stream) => stream
+                .read(&mut buf)
+                .with_context(|| "Failed to read from gdb")?,
+            None => return Ok(false),
+        };
+        if len == 0 {
+            return Ok(false);
+        }
+
+        for byte in &buf[..len] {
+            match self.parser.feed(*byte) {
+                Some(GdbInput::Packet(data)) => {
+                    self.send(b"+")?;
+                    if !self.handle_packet(&data)? {
+                        return Ok(false);
+                    }
+                }
+                Some(GdbInput::BadPacket) => self.send(b"-")?,
+                Some(GdbInput::Interrupt) => {
+                    self.pause_vm();
+                    self.send_stop_reply(SIGINT)?;
+                }
+                None => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Handle the vCPU stopped for debug exit.
+    fn handle_debug_stop(&mut self) -> Result<()> {
+        if !self.attached {
+            self.resume_vm();
+            return Ok(());
+        }
+        if self.stepping {
+            self.stepping = false;
+        } else {
+            self.pause_vm();
+        }
+        if let Some(index) = self.cpus.iter().position(|cpu| cpu.debug_stopped()) {
+            self.cur_cpu = index;
+        }
+        self.send_stop_reply(SIGTRAP)
+    }
+
+    /// Handle packet from gdb, returns false if gdb detaches.
+    fn handle_packet(&mut self, data: &[u8]) -> Result<bool> {
+        let (cmd, args) = match data.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return Ok(true),
+        };
+        let reply = match cmd {
+            b'?' => return self.send_stop_reply(SIGTRAP).map(|_| true),
+            b'c' => {
+                self.continue_vm(false)?;
+                return Ok(true);
+            }
+            b's' => {
+                self.continue_vm(true)?;
+                return Ok(true);
+            }
+            b'D' => {
+                self.send_packet(b"OK")?;
+                return Ok(false);
+            }
+            b'k' => return Ok(false),
+            b'g' => self.read_registers(),
+            b'G' => self.write_registers(args),
+            b'p' => self.read_register(args),
+            b'P' => self.write_register(args),
+            b'm' => self.read_memory(args),
+            b'M' => self.write_memory(args),
+            b'Z' => self.insert_breakpoint(args),
+            b'z' => self.remove_breakpoint(args),
+            b'H' => self.set_thread(args),
+            b'T' => self.check_thread(args),
+            b'q' => self.query(args),
+            // Unsupported packets are replied with empty response.
+            _ => Ok(Vec::new()),
+        };
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => {
+                warn!("Gdbstub failed to handle packet {}: {:?}", cmd as char, e);
+                let errno = match cmd {
+                    b'm' | b'M' | b'Z' | b'z' => EFAULT,
+                    _ => EINVAL,
+                };
+                format!("E{:02x}", errno).into_bytes()
+            }
+        };
+        self.send_packet(&reply)?;
+        Ok(true)
+    }
+
+    fn continue_vm(&mut self, single_step: bool) -> Result<()> {
+        self.stepping = single_step;
+        self.update_guest_debug()?;
+        if single_step {
+            // Only the current vCPU runs, others keep paused.
+            self.cpus[self.cur_cpu].resume()?;
+        } else {
+            self.resume_vm();
+        }
+        Ok(())
+    }
+
+    fn update_guest_debug(&self) -> Result<()> {
+        for (index, cpu) in self.cpus.iter().enumerate() {
+            set_guest_debug(
+                cpu,
+                !self.sw_bps.is_empty(),
+                &self.hw_bps,
+                self.stepping && index == self.cur_cpu,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn read_registers(&self) -> Result<Vec<u8>> {
+        let regs = read_registers(&self.cpus[self.cur_cpu])?;
+        Ok(hex_encode(&regs).into_bytes())
+    }
+
+    fn write_registers(&self, args: &[u8]) -> Result<Vec<u8>> {
+        write_registers(&self.cpus[self.cur_cpu], &hex_decode(args)?)?;
+        Ok(b"OK".to_vec())
+    }
+
+    fn read_register(&self, args: &[u8]) -> Result<Vec<u8>> {
+        let range = register_range(parse_hex(args)? as usize)
+            .ok_or_else(|| anyhow!("Invalid register number"))?;
+        let regs = read_registers(&self.cpus[self.cur_cpu])?;
+        Ok(hex_encode(&regs[range]).into_bytes())
+    }
+
+    fn write_register(&self, args: &[u8]) -> Result<Vec<u8>> {
+        let (reg_num, value) = split_args(args, b'=')?;
+        let range = register_range(parse_hex(reg_num)? as usize)
+            .ok_or_else(|| anyhow!("Invalid register number"))?;
+        let value = hex_decode(value)?;
+        if value.len() != range.len() {
+            bail!("Invalid register value size {}", value.len());
+        }
+        let cpu = &self.cpus[self.cur_cpu];
+        let mut regs = read_registers(cpu)?;
+        regs[range].copy_from_slice(&value);
+        write_registers(cpu, &regs)?;
+        Ok(b"OK".to_vec())
+    }
+
+    /// Access guest memory by virtual address, which is split by pages as
+    /// they may not be continuous in guest physical memory.
+    fn access_memory(
+        &self,
+        gva: u64,
+        len: usize,
+        mut f: impl FnMut(u64, u64, usize) -> Result<()>,
+    ) -> Result<()> {
+        const PAGE_SIZE: u64 = 0x1000;
+        let mut done = 0;
+        while done < len {
+            let addr = gva + done as u64;
+            let size = std::cmp::min((PAGE_SIZE - addr % PAGE_SIZE) as usize, len - done);
+            let gpa = translate_gva(&self.cpus[self.cur_cpu], addr)?;
+            f(gpa, size as u64, done)?;
+            done += size;
+        }
+        Ok(())
+    }
+
+    fn read_memory(&self, args: &[u8]) -> Result<Vec<u8>> {
+        let (addr, len) = split_args(args, b',')?;
+        let len = std::cmp::min(parse_hex(len)? as usize, MAX_PACKET_SIZE / 2);
+        let mut data = vec![0_u8; len];
+        self.access_memory(parse_hex(addr)?, len, |gpa, size, offset| {
+            let mut buf = &mut data[offset..];
+            self.sys_mem.read(&mut buf, GuestAddress(gpa), size)
+        })?;
+        Ok(hex_encode(&data).into_bytes())
+    }
+
+    fn write_memory(&self, args: &[u8]) -> Result<Vec<u8>> {
+        let (addr_len, value) = split_args(args, b':')?;
+        let (addr, len) = split_args(addr_len, b',')?;
+        let data = hex_decode(value)?;
+        if data.len() as u64 != parse_hex(len)? {
+            bail!("Memory data size {} mismatches the length", data.len());
+        }
+        self.access_memory(parse_hex(addr)?, data.len(), |gpa, size, offset| {
+            self.sys_mem
+                .write(&mut &data[offset..], GuestAddress(gpa), size)
+        })?;
+        Ok(b"OK".to_vec())
+    }
+
+    /// Parse arguments of `Z` and `z` packets, which is in format of `type,addr,kind`.
+    fn parse_breakpoint(args: &[u8]) -> Result<(u8, u64, u64)> {
+        let (bp_type, addr_kind) = split_args(args, b',')?;
+        let (addr, kind) = split_args(addr_kind, b',')?;
+        // Conditions and commands following kind are ignored.
+        let kind = kind.split(|b| *b == b';').next().unwrap_or_default();
+        Ok((
+            parse_hex(bp_type)? as u8,
+            parse_hex(addr)?,
+            parse_hex(kind)?,
+        ))
+    }
+
+    fn insert_breakpoint(&mut self, args: &[u8]) -> Result<Vec<u8>> {
+        let (bp_type, addr, kind) = Self::parse_breakpoint(args)?;
+        if bp_type == 0 {
+            if !self.sw_bps.contains_key(&addr) {
+                let gpa = translate_gva(&self.cpus[self.cur_cpu], addr)?;
+                let mut insn = vec![0_u8; SW_BREAKPOINT_INSN.len()];
+                self.sys_mem.read(
+                    &mut insn.as_mut_slice(),
+                    GuestAddress(gpa),
+                    SW_BREAKPOINT_INSN.len() as u64,
+                )?;
+                self.sys_mem.write(
+                    &mut SW_BREAKPOINT_INSN.as_slice(),
+                    GuestAddress(gpa),
+                    SW_BREAKPOINT_INSN.len() as u64,
+                )?;
+                self.sw_bps.insert(addr, SwBreakpoint { gpa, insn });
+            }
+        } else {
+            let bp = match HwBreakpoint::new(bp_type, addr, kind) {
+                Ok(bp) => bp,
+                // Empty reply tells gdb the breakpoint type is not supported.
+                Err(_) => return Ok(Vec::new()),
+            };
+            if !self.hw_bps.contains(&bp) {
+                if self.hw_bps.len() >= MAX_HW_BREAKPOINTS {
+                    bail!("No hardware breakpoint left");
+                }
+                self.hw_bps.push(bp);
+            }
+        }
+        self.update_guest_debug()?;
+        Ok(b"OK".to_vec())
+    }
+
+    fn remove_breakpoint(&mut self, args: &[u8]) -> Result<Vec<u8>> {
+        let (bp_type, addr, kind) = Self::parse_breakpoint(args)?;
+        if bp_type == 0 {
+            if let Some(bp) = self.sw_bps.remove(&addr) {
+                self.restore_insn(&bp)?;
+            }
+        } else {
+            let bp = match HwBreakpoint::new(bp_type, addr, kind) {
+                Ok(bp) => bp,
+                Err(_) => return Ok(Vec::new()),
+            };
+            self.hw_bps.retain(|b| *b != bp);
+        }
+        self.update_guest_debug()?;
+        Ok(b"OK".to_vec())
+    }
+
+    fn restore_insn(&self, bp: &SwBreakpoint) -> Result<()> {
+        self.sys_mem.write(
+            &mut bp.insn.as_slice(),
+            GuestAddress(bp.gpa),
+            bp.insn.len() as u64,
+        )
+    }
+
+    fn remove_all_breakpoints(&mut self) -> Result<()> {
+        self.hw_bps.clear();
+        let sw_bps = std::mem::take(&mut self.sw_bps);
+        for bp in sw_bps.values() {
+            self.restore_insn(bp)?;
+        }
+        Ok(())
+    }
+
+    /// Parse thread id in packets, returns the vCPU index, `None` means any thread.
+    fn parse_thread(&self, id: &[u8]) -> Result<Option<usize>> {
+        if id == b"-1" || id == b"0" {
+            return Ok(None);
+        }
+        let id = parse_hex(id)? as usize;
+        if id == 0 || id > self.cpus.len() {
+            bail!("Invalid thread id {}", id);
+        }
+        Ok(Some(id - 1))
+    }
+
+    fn set_thread(&mut self, args: &[u8]) -> Result<Vec<u8>> {
+        // Both `Hg` and `Hc` select the current vCPU.
+        if args.is_empty() {
+            bail!("Missing thread id");
+        }
+        if let Some(index) = self.parse_thread(&args[1..])? {
+            self.cur_cpu = index;
+        }
+        Ok(b"OK".to_vec())
+    }
+
+    fn check_thread(&self, args: &[u8]) -> Result<Vec<u8>> {
+        self.parse_thread(args)?;
+        Ok(b"OK".to_vec())
+    }
+
+    fn query(&self, args: &[u8]) -> Result<Vec<u8>> {
+        let name = args.split(|b| *b == b':').next().unwrap_or_default();
+        let reply = match name {
+            b"Supported" => format!("PacketSize={:x}", MAX_PACKET_SIZE),
+            b"Attached" => "1".to_string(),
+            b"C" => format!("QC{:x}", self.cur_cpu + 1),
+            b"fThreadInfo" => {
+                let ids: Vec<String> = (1..=self.cpus.len())
+                    .map(|id| format!("{:x}", id))
+                    .collect();
+                format!("m{}", ids.join(","))
+            }
+            b"sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        };
+        Ok(reply.into_bytes())
+    }
+}
+
+fn split_args(args: &[u8], delimiter: u8) -> Result<(&[u8], &[u8])> {
+    match args.iter().position(|b| *b == delimiter) {
+        Some(pos) => Ok((&args[..pos], &args[pos + 1..])),
+        None => bail!("Missing delimiter {} in packet", delimiter as char),
+    }
+}
+
+fn create_stream_notifier(stub: &Arc<Mutex<GdbStub>>, stream_fd: RawFd) -> EventNotifier {
+    let cloned_stub = stub.clone();
+    let handler: Rc<NotifierCallback> = Rc::new(move |event, _| {
+        let mut locked_stub = cloned_stub.lock().unwrap();
+        let connected = if event & EventSet::IN == EventSet::IN {
+            locked_stub.handle_input().unwrap_or_else(|e| {
+                error!("{:?}", e);
+                false
+            })
+        } else {
+            true
+        };
+        if !connected || event & EventSet::HANG_UP == EventSet::HANG_UP {
+            locked_stub.detach();
+            return Some(gen_delete_notifiers(&[stream_fd]));
+        }
+        None
+    });
+    EventNotifier::new(
+        NotifierOperation::AddShared,
+        stream_fd,
+        Some(stub.lock().unwrap().listener.as_raw_fd()),
+        EventSet::IN | EventSet::HANG_UP,
+        vec![handler],
+    )
+}
+
+/// Start gdbstub which listens on the tcp address in config, the VM runs
+/// freely until gdb connects.
+///
+/// # Arguments
+///
+/// * `config` - Config of gdbstub.
+/// * `vm` - The VM to be debugged.
+/// * `vm_state` - Lifecycle state of VM.
+/// * `cpus` - vCPUs of VM.
+/// * `sys_mem` - System memory of VM.
+pub fn start_gdbstub(
+    config: &GdbConfig,
+    vm: Arc<Mutex<dyn MachineLifecycle + Send + Sync>>,
+    vm_state: Arc<(Mutex<KvmVmState>, Condvar)>,
+    cpus: Vec<Arc<CPU>>,
+    sys_mem: Arc<AddressSpace>,
+) -> Result<()> {
+    let listener = TcpListener::bind(&config.addr)
+        .with_context(|| format!("Failed to bind gdbstub on {}", config.addr))?;
+    let listener_fd = listener.as_raw_fd();
+    let stop_evt =
+        Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| "Failed to create eventfd")?);
+    let stop_fd = stop_evt.as_raw_fd();
+    let stub = Arc::new(Mutex::new(GdbStub {
+        vm,
+        vm_state,
+        cpus,
+        sys_mem,
+        listener,
+        stream: None,
+        attached: false,
+        parser: PacketParser::new(),
+        cur_cpu: 0,
+        stepping: false,
+        sw_bps: BTreeMap::new(),
+        hw_bps: Vec::new(),
+    }));
+
+    let cloned_stub = stub.clone();
+    let accept_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+        let stream_fd = match cloned_stub.lock().unwrap().accept() {
+            Ok(fd) => fd,
+            Err(e) => {
+                error!("{:?}", e);
+                return None;
+            }
+        };
+        Some(vec![create_stream_notifier(&cloned_stub, stream_fd)])
+    });
+    let cloned_stub = stub.clone();
+    let stop_handler: Rc<NotifierCallback> = Rc::new(move |_, fd| {
+        read_fd(fd);
+        if let Err(e) = cloned_stub.lock().unwrap().handle_debug_stop() {
+            error!("Gdbstub failed to handle debug stop: {:?}", e);
+        }
+        None
+    });
+    let notifiers = vec![
+        EventNotifier::new(
+            NotifierOperation::AddShared,
+            listener_fd,
+            None,
+            EventSet::IN,
+            vec![accept_handler],
+        ),
+        EventNotifier::new(
+            NotifierOperation::AddShared,
+            stop_fd,
+            None,
+            EventSet::IN,
+            vec![stop_handler],
+        ),
+    ];
+    EventLoop::update_event(notifiers, None).with_context(|| "Failed to register gdbstub")?;
+    cpu::set_debug_stop_notifier(Some(stop_evt));
+    info!("Gdbstub is listening on {}", config.addr);
+    Ok(())
+}
@@ -0,0 +1,206 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+
+/// Max size of packet data accepted from gdb.
+pub const MAX_PACKET_SIZE: usize = 0x4000;
+/// Interrupt request sent by gdb out of packets.
+const INTERRUPT_BYTE: u8 = 0x03;
+
+/// Input from gdb.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GdbInput {
+    /// Packet data whose checksum is right.
+    Packet(Vec<u8>),
+    /// Packet whose checksum is wrong, gdb should send it again.
+    BadPacket,
+    /// User interrupts the running guest.
+    Interrupt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ParseState {
+    /// Waiting for the start of packet.
+    Idle,
+    /// Receiving packet data.
+    Data,
+    /// Receiving the first hex digit of checksum.
+    Checksum1,
+    /// Receiving the second hex digit of checksum.
+    Checksum2,
+}
+
+/// Parser of gdb remote serial protocol, which splits the byte stream into packets.
+pub struct PacketParser {
+    state: ParseState,
+    data: Vec<u8>,
+    checksum: u8,
+}
+
+impl PacketParser {
+    pub fn new() -> Self {
+        PacketParser {
+            state: ParseState::Idle,
+            data: Vec::new(),
+            checksum: 0,
+        }
+    }
+
+    /// Feed one byte received from gdb, returns the input if it's completed.
+    pub fn feed(&mut self, byte: u8) -> Option<GdbInput> {
+        match self.state {
+            ParseState::Idle => match byte {
+                b'$' => {
+                    self.data.clear();
+                    self.state = ParseState::Data;
+                }
+                INTERRUPT_BYTE => return Some(GdbInput::Interrupt),
+                // Acks of gdb are ignored, replies are never resent.
+                _ => {}
+            },
+            ParseState::Data => match byte {
+                b'#' => self.state = ParseState::Checksum1,
+                _ if self.data.len() >= MAX_PACKET_SIZE => {
+                    self.state = ParseState::Idle;
+                    return Some(GdbInput::BadPacket);
+                }
+                _ => self.data.push(byte),
+            },
+            ParseState::Checksum1 => {
+                self.checksum = hex_value(byte).unwrap_or(0xff) << 4;
+                self.state = ParseState::Checksum2;
+            }
+            ParseState::Checksum2 => {
+                self.state = ParseState::Idle;
+                let checksum = self.checksum | hex_value(byte).unwrap_or(0xff);
+                if checksum != checksum_of(&self.data) {
+                    return Some(GdbInput::BadPacket);
+                }
+                return Some(GdbInput::Packet(unescape(&self.data)));
+            }
+        }
+        None
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Binary data in packets is escaped by `}` followed by the byte xor 0x20.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut escaped = false;
+    for b in data {
+        if escaped {
+            out.push(b ^ 0x20);
+            escaped = false;
+        } else if *b == b'}' {
+            escaped = true;
+        } else {
+            out.push(*b);
+        }
+    }
+    out
+}
+
+/// Frame the reply data into a packet to be sent to gdb.
+pub fn make_packet(data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(data.len() + 4);
+    packet.push(b'$');
+    for b in data {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            packet.push(b'}');
+            packet.push(b ^ 0x20);
+        } else {
+            packet.push(*b);
+        }
+    }
+    let checksum = checksum_of(&packet[1..]);
+    packet.extend(format!("#{:02x}", checksum).as_bytes());
+    packet
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|v| v as u8)
+}
+
+/// Encode bytes to hex string.
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hex string to bytes.
+pub fn hex_decode(hex: &[u8]) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        bail!("Odd length of hex string");
+    }
+    hex.chunks(2)
+        .map(|pair| match (hex_value(pair[0]), hex_value(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => bail!("Invalid hex string"),
+        })
+        .collect()
+}
+
+/// Parse hex number in packets, such as address and length.
+pub fn parse_hex(hex: &[u8]) -> Result<u64> {
+    let s = std::str::from_utf8(hex).with_context(|| "Invalid hex number")?;
+    u64::from_str_radix(s, 16).with_context(|| format!("Invalid hex number {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed_all(parser: &mut PacketParser, bytes: &[u8]) -> Vec<GdbInput> {
+        bytes.iter().filter_map(|b| parser.feed(*b)).collect()
+    }
+
+    #[test]
+    fn test_packet_parse() {
+        let mut parser = PacketParser::new();
+        assert_eq!(
+            feed_all(&mut parser, b"+$g#67"),
+            vec![GdbInput::Packet(b"g".to_vec())]
+        );
+        assert_eq!(
+            feed_all(&mut parser, b"$m1000,4#00\x03"),
+            vec![GdbInput::BadPacket, GdbInput::Interrupt]
+        );
+        assert_eq!(
+            feed_all(&mut parser, &make_packet(b"X0,1:}")),
+            vec![GdbInput::Packet(b"X0,1:}".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_packet_make() {
+        assert_eq!(make_packet(b"OK"), b"$OK#9a".to_vec());
+        assert_eq!(make_packet(b""), b"$#00".to_vec());
+        assert_eq!(make_packet(b"#"), b"$}\x03#80".to_vec());
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(&[0x00, 0x5a, 0xff]), "005aff");
+        assert_eq!(hex_decode(b"005aFF").unwrap(), vec![0x00, 0x5a, 0xff]);
+        assert!(hex_decode(b"5").is_err());
+        assert!(hex_decode(b"zz").is_err());
+        assert_eq!(
+            parse_hex(b"ffffffff81000000").unwrap(),
+            0xffff_ffff_8100_0000
+        );
+        assert!(parse_hex(b"").is_err());
+    }
+}
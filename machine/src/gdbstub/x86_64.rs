@@ -0,0 +1,233 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use kvm_bindings::{
+    kvm_guest_debug, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_GUESTDBG_USE_HW_BP,
+    KVM_GUESTDBG_USE_SW_BP,
+};
+
+use cpu::CPU;
+
+/// Instruction `int3` which is written to guest memory for software breakpoints.
+pub const SW_BREAKPOINT_INSN: [u8; 1] = [0xcc];
+/// Number of debug address registers, DR0 - DR3.
+pub const MAX_HW_BREAKPOINTS: usize = 4;
+
+/// Offset of `eflags` in gdb register layout, it's following rax - r15 and rip.
+const EFLAGS_OFFSET: usize = 17 * 8;
+/// Size of registers in gdb `amd64` core layout: 16 general purpose registers, rip,
+/// eflags and 6 segment registers.
+const REGS_SIZE: usize = EFLAGS_OFFSET + 7 * 4;
+/// Always set bits of DR7, including GE.
+const DR7_FIXED_BITS: u64 = 0x600;
+
+/// Type of hardware breakpoint, which is the `RW` field in DR7.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwBreakpointType {
+    Execute = 0,
+    Write = 1,
+    Access = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HwBreakpoint {
+    pub addr: u64,
+    pub len: u64,
+    pub bp_type: HwBreakpointType,
+}
+
+impl HwBreakpoint {
+    /// Create hardware breakpoint from the type and kind in `Z` packet.
+    pub fn new(z_type: u8, addr: u64, len: u64) -> Result<Self> {
+        let bp_type = match z_type {
+            1 => HwBreakpointType::Execute,
+            2 => HwBreakpointType::Write,
+            4 => HwBreakpointType::Access,
+            _ => bail!("Unsupported hardware breakpoint type {}", z_type),
+        };
+        if bp_type == HwBreakpointType::Execute {
+            return Ok(HwBreakpoint {
+                addr,
+                len: 1,
+                bp_type,
+            });
+        }
+        if !matches!(len, 1 | 2 | 4 | 8) || addr % len != 0 {
+            bail!("Invalid watchpoint at {:#x} with length {}", addr, len);
+        }
+        Ok(HwBreakpoint { addr, len, bp_type })
+    }
+
+    /// Get the `LEN` field of DR7.
+    fn dr7_len(&self) -> u64 {
+        match self.len {
+            2 => 1,
+            4 => 3,
+            8 => 2,
+            _ => 0,
+        }
+    }
+}
+
+/// Encode DR7 which enables the hardware breakpoints in DR0 - DR3 locally.
+fn dr7_value(hw_bps: &[HwBreakpoint]) -> u64 {
+    hw_bps
+        .iter()
+        .enumerate()
+        .fold(DR7_FIXED_BITS, |dr7, (i, bp)| {
+            dr7 | 1 << (i * 2) | (bp.bp_type as u64) << (16 + i * 4) | bp.dr7_len() << (18 + i * 4)
+        })
+}
+
+/// Set guest debug of vCPU, guest debug is disabled if no breakpoint is set and
+/// it's not single-stepping.
+pub fn set_guest_debug(
+    cpu: &CPU,
+    sw_bp: bool,
+    hw_bps: &[HwBreakpoint],
+    single_step: bool,
+) -> Result<()> {
+    let mut debug = kvm_guest_debug::default();
+    if sw_bp {
+        debug.control |= KVM_GUESTDBG_USE_SW_BP;
+    }
+    if !hw_bps.is_empty() {
+        debug.control |= KVM_GUESTDBG_USE_HW_BP;
+        for (i, bp) in hw_bps.iter().enumerate() {
+            debug.arch.debugreg[i] = bp.addr;
+        }
+        debug.arch.debugreg[7] = dr7_value(hw_bps);
+    }
+    if single_step {
+        debug.control |= KVM_GUESTDBG_SINGLESTEP;
+    }
+    if debug.control != 0 {
+        debug.control |= KVM_GUESTDBG_ENABLE;
+    }
+
+    cpu.fd()
+        .set_guest_debug(&debug)
+        .with_context(|| format!("Failed to set guest debug of vcpu{}", cpu.id()))
+}
+
+/// Translate guest virtual address to guest physical address by page table of vCPU.
+pub fn translate_gva(cpu: &CPU, gva: u64) -> Result<u64> {
+    let translation = cpu
+        .fd()
+        .translate_gva(gva)
+        .with_context(|| format!("Failed to translate address {:#x}", gva))?;
+    if translation.valid == 0 {
+        bail!("Address {:#x} is not mapped", gva);
+    }
+    Ok(translation.physical_address)
+}
+
+/// Read registers of vCPU in gdb `amd64` core layout.
+pub fn read_registers(cpu: &CPU) -> Result<Vec<u8>> {
+    let regs = cpu.fd().get_regs().with_context(|| "Failed to get regs")?;
+    let sregs = cpu
+        .fd()
+        .get_sregs()
+        .with_context(|| "Failed to get sregs")?;
+
+    let mut data = Vec::with_capacity(REGS_SIZE);
+    for reg in [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ] {
+        data.extend_from_slice(&reg.to_le_bytes());
+    }
+    data.extend_from_slice(&(regs.rflags as u32).to_le_bytes());
+    for seg in [sregs.cs, sregs.ss, sregs.ds, sregs.es, sregs.fs, sregs.gs] {
+        data.extend_from_slice(&u32::from(seg.selector).to_le_bytes());
+    }
+    Ok(data)
+}
+
+/// Write registers of vCPU in gdb `amd64` core layout, segment registers are
+/// read-only because their hidden parts can't be set by gdb.
+pub fn write_registers(cpu: &CPU, data: &[u8]) -> Result<()> {
+    if data.len() < EFLAGS_OFFSET + 4 {
+        bail!("Registers data is too short: {}", data.len());
+    }
+    let reg = |i: usize| u64::from_le_bytes(data[i * 8..(i + 1) * 8].try_into().unwrap());
+
+    let mut regs = cpu.fd().get_regs().with_context(|| "Failed to get regs")?;
+    regs.rax = reg(0);
+    regs.rbx = reg(1);
+    regs.rcx = reg(2);
+    regs.rdx = reg(3);
+    regs.rsi = reg(4);
+    regs.rdi = reg(5);
+    regs.rbp = reg(6);
+    regs.rsp = reg(7);
+    regs.r8 = reg(8);
+    regs.r9 = reg(9);
+    regs.r10 = reg(10);
+    regs.r11 = reg(11);
+    regs.r12 = reg(12);
+    regs.r13 = reg(13);
+    regs.r14 = reg(14);
+    regs.r15 = reg(15);
+    regs.rip = reg(16);
+    regs.rflags = u64::from(u32::from_le_bytes(
+        data[EFLAGS_OFFSET..EFLAGS_OFFSET + 4].try_into().unwrap(),
+    ));
+    cpu.fd()
+        .set_regs(&regs)
+        .with_context(|| "Failed to set regs")
+}
+
+/// Get the offset and size of register numbered `reg_num` in gdb register layout.
+pub fn register_range(reg_num: usize) -> Option<std::ops::Range<usize>> {
+    match reg_num {
+        0..=16 => Some(reg_num * 8..(reg_num + 1) * 8),
+        17..=23 => {
+            let start = EFLAGS_OFFSET + (reg_num - 17) * 4;
+            Some(start..start + 4)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hw_breakpoint() {
+        let bp = HwBreakpoint::new(1, 0x1001, 0).unwrap();
+        assert_eq!(bp.len, 1);
+        assert!(HwBreakpoint::new(2, 0x1001, 4).is_err());
+        assert!(HwBreakpoint::new(2, 0x1000, 3).is_err());
+        assert!(HwBreakpoint::new(3, 0x1000, 4).is_err());
+
+        let watch = HwBreakpoint::new(4, 0x2000, 8).unwrap();
+        assert_eq!(dr7_value(&[]), DR7_FIXED_BITS);
+        // DR0: local enable, execute. DR1: local enable, read/write, 8 bytes.
+        assert_eq!(
+            dr7_value(&[bp, watch]),
+            0x600 | 0x1 | 0x4 | 0x3 << 20 | 0x2 << 22
+        );
+    }
+
+    #[test]
+    fn test_register_range() {
+        assert_eq!(register_range(0), Some(0..8));
+        assert_eq!(register_range(16), Some(128..136));
+        assert_eq!(register_range(17), Some(136..140));
+        assert_eq!(register_range(23), Some(160..164));
+        assert_eq!(register_range(23).unwrap().end, REGS_SIZE);
+        assert_eq!(register_range(24), None);
+    }
+}
@@ -13,6 +13,9 @@
 pub mod error;
 pub mod standard_vm;
 
+#[cfg(target_arch = "x86_64")]
+mod gdbstub;
+mod mem_pressure;
 mod micro_vm;
 #[cfg(target_arch = "x86_64")]
 mod vm_state;
@@ -20,6 +23,7 @@ mod vm_state;
 pub use anyhow::Result;
 
 pub use crate::error::MachineError;
+pub use mem_pressure::start_mem_pressure_monitor;
 pub use micro_vm::LightMachine;
 pub use standard_vm::StdMachine;
 
@@ -27,6 +31,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::fs::{remove_file, File};
 use std::net::TcpListener;
 use std::ops::Deref;
+use std::os::unix::io::RawFd;
 use std::os::unix::net::UnixListener;
 use std::path::Path;
 use std::sync::{Arc, Barrier, Condvar, Mutex, Weak};
@@ -41,12 +46,16 @@ use vmm_sys_util::eventfd::EventFd;
 #[cfg(target_arch = "x86_64")]
 use address_space::KvmIoListener;
 use address_space::{
-    create_backend_mem, create_default_mem, AddressSpace, KvmMemoryListener, Region,
+    create_backend_mem, create_default_mem, host_mem_ranges, AddressSpace, KvmMemoryListener,
+    Region,
 };
-#[cfg(target_arch = "aarch64")]
-use cpu::CPUFeatures;
-use cpu::{ArchCPU, CPUBootConfig, CPUInterface, CPUTopology, CPU};
+use chardev_backend::guest_agent::{register_guest_agent, GUEST_AGENT_PORT_NAME};
+#[cfg(target_arch = "x86_64")]
+use cpu::MAX_APIC_ID;
+use cpu::{ArchCPU, CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology, CPU};
+use devices::acpi::memory_hotplug::{DimmSlot, MemHotplug};
 use devices::legacy::FwCfgOps;
+use devices::misc::pvpanic::{PvPanicPci, PvPanicReqs};
 #[cfg(feature = "scream")]
 use devices::misc::scream::Scream;
 #[cfg(feature = "demo_device")]
@@ -76,18 +85,25 @@ use machine_manager::config::parse_usb_host;
 #[cfg(feature = "scream")]
 use machine_manager::config::scream::parse_scream;
 use machine_manager::config::{
-    complete_numa_node, get_multi_function, get_pci_bdf, parse_balloon, parse_blk, parse_device_id,
-    parse_fs, parse_net, parse_numa_distance, parse_numa_mem, parse_rng_dev, parse_root_port,
+    check_numa_cpu_topology, complete_numa_node, get_multi_function, get_pci_bdf,
+    get_virtio_pci_msix, parse_balloon, parse_blk, parse_device_id, parse_dimm, parse_fs,
+    parse_net, parse_numa_distance, parse_numa_mem, parse_pvpanic, parse_rng_dev, parse_root_port,
     parse_scsi_controller, parse_scsi_device, parse_vfio, parse_vhost_user_blk,
-    parse_virtio_serial, parse_virtserialport, parse_vsock, BootIndexInfo, DriveFile, Incoming,
-    MachineMemConfig, MigrateMode, NumaConfig, NumaDistance, NumaNode, NumaNodes, PFlashConfig,
-    PciBdf, SerialConfig, VfioConfig, VmConfig, FAST_UNPLUG_ON, MAX_VIRTIO_QUEUE,
+    parse_vhost_user_scsi, parse_vhost_vdpa, parse_virtio_serial, parse_virtserialport,
+    parse_vsock, BootIndexInfo, ChardevType, DriveFile, Incoming, MachineMemConfig, MigrateMode,
+    NumaConfig, NumaDistance, NumaNode, NumaNodes, PFlashConfig, PciBdf, RtcConfig, SandboxConfig,
+    SandboxProfile, SerialConfig, VfioConfig, VirtioPciMsixConfig, VmConfig, DIMM_ALIGN,
+    FAST_UNPLUG_ON, MAX_VIRTIO_QUEUE,
 };
 use machine_manager::config::{
     parse_usb_keyboard, parse_usb_storage, parse_usb_tablet, parse_xhci,
 };
 use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::{KvmVmState, MachineInterface};
+use machine_manager::qmp::qmp_audit::qmp_audit_allow_list;
+use machine_manager::qmp::qmp_response::Response;
+use machine_manager::qmp::qmp_schema::{self, ThreadStackInfo, VmmMemoryInfo};
+use migration::transport::{fd_stream, ExecMode, ExecStream};
 use migration::MigrationManager;
 use smbios::smbios_table::{build_smbios_ep30, SmbiosTable};
 use smbios::{SMBIOS_ANCHOR_FILE, SMBIOS_TABLE_FILE};
@@ -97,7 +113,12 @@ use ui::console::{get_run_stage, VmRunningStage};
 use util::file::{clear_file, lock_file, unlock_file};
 use util::{
     arg_parser,
-    seccomp::{BpfRule, SeccompOpt, SyscallFilter},
+    boot_time::{BootStage, BootStageTimer},
+    crash_dump::register_crash_dump_section,
+    seccomp::{
+        register_thread_filters, syscall_num, BpfRule, SeccompOpt, SyscallFilter, ThreadClass,
+    },
+    seclabel::{label_path, seclabel_allow_list},
 };
 use vfio::{VfioDevice, VfioPciDevice};
 #[cfg(feature = "virtio_gpu")]
@@ -106,8 +127,9 @@ use virtio::{
     balloon_allow_list, find_port_by_nr, get_max_nr, vhost, Balloon, Block, BlockState, Rng,
     RngState,
     ScsiCntlr::{scsi_cntlr_create_scsi_bus, ScsiCntlr},
-    Serial, SerialPort, VhostKern, VhostUser, VirtioDevice, VirtioMmioDevice, VirtioMmioState,
-    VirtioNetState, VirtioPciDevice, VirtioSerialState, VIRTIO_TYPE_CONSOLE,
+    Serial, SerialPort, VhostKern, VhostUser, VhostVdpa, VirtioDevice, VirtioMmioDevice,
+    VirtioMmioState, VirtioNetState, VirtioPciDevice, VirtioSerialState, VIRTIO_TYPE_BLOCK,
+    VIRTIO_TYPE_CONSOLE, VIRTIO_TYPE_NET,
 };
 
 pub trait MachineOps {
@@ -140,7 +162,6 @@ pub trait MachineOps {
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig>;
 
-    #[cfg(target_arch = "aarch64")]
     fn load_cpu_features(&self, vmcfg: &VmConfig) -> Result<CPUFeatures> {
         Ok((&vmcfg.machine_config.cpu_config).into())
     }
@@ -152,12 +173,12 @@ pub trait MachineOps {
     /// * `mem_size` - memory size of VM.
     fn init_machine_ram(&self, sys_mem: &Arc<AddressSpace>, mem_size: u64) -> Result<()>;
 
-    fn create_machine_ram(&self, mem_config: &MachineMemConfig, thread_num: u8) -> Result<()> {
+    fn create_machine_ram(&self, mem_config: &MachineMemConfig) -> Result<()> {
         let root = self.get_vm_ram();
         let numa_nodes = self.get_numa_nodes();
 
         if numa_nodes.is_none() || mem_config.mem_zones.is_none() {
-            let default_mem = create_default_mem(mem_config, thread_num)?;
+            let default_mem = create_default_mem(mem_config)?;
             root.add_subregion_not_update(default_mem, 0_u64)?;
             return Ok(());
         }
@@ -166,7 +187,7 @@ pub trait MachineOps {
         for (_, node) in numa_nodes.as_ref().unwrap().iter().enumerate() {
             for zone in zones.iter() {
                 if zone.id.eq(&node.1.mem_dev) {
-                    let ram = create_backend_mem(zone, thread_num)?;
+                    let ram = create_backend_mem(zone)?;
                     root.add_subregion_not_update(ram, offset)?;
                     offset += zone.size;
                     break;
@@ -176,6 +197,23 @@ pub trait MachineOps {
         Ok(())
     }
 
+    /// Check whether all guest RAM is shared, which is required by vhost-user backends
+    /// to map guest memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_config` - Memory setting.
+    fn is_machine_ram_shared(&self, mem_config: &MachineMemConfig) -> bool {
+        match (self.get_numa_nodes(), mem_config.mem_zones.as_ref()) {
+            (Some(numa_nodes), Some(zones)) => numa_nodes.values().all(|node| {
+                zones
+                    .iter()
+                    .any(|zone| zone.id == node.mem_dev && zone.share)
+            }),
+            _ => mem_config.mem_share,
+        }
+    }
+
     /// Init I/O & memory address space and mmap guest memory.
     ///
     /// # Arguments
@@ -188,15 +226,15 @@ pub trait MachineOps {
         mem_config: &MachineMemConfig,
         #[cfg(target_arch = "x86_64")] sys_io: &Arc<AddressSpace>,
         sys_mem: &Arc<AddressSpace>,
-        nr_cpus: u8,
     ) -> Result<()> {
+        let _timer = BootStageTimer::new(BootStage::MemorySetup, None);
         // KVM_CREATE_VM system call is invoked when KVM_FDS is used for the first time. The system
         // call registers some notifier functions in the KVM, which are frequently triggered when
         // doing memory prealloc.To avoid affecting memory prealloc performance, create_host_mmaps
         // needs to be invoked first.
         let migrate_info = self.get_migrate_info();
         if migrate_info.0 != MigrateMode::File {
-            self.create_machine_ram(mem_config, nr_cpus)?;
+            self.create_machine_ram(mem_config)?;
         }
 
         sys_mem
@@ -223,59 +261,127 @@ pub trait MachineOps {
     /// # Arguments
     ///
     /// * `vm` - `MachineInterface` to obtain functions cpu can use.
-    /// * `nr_cpus` - The number of vcpus.
+    /// * `sys_mem` - System memory space which MMIO exits of vcpus are dispatched to.
+    /// * `nr_cpus` - The number of vcpus present at boot.
+    /// * `max_cpus` - The number of possible vcpus, vcpus after `nr_cpus` are returned
+    ///   unregistered for hot-adding.
+    /// * `vcpu_affinity` - Host CPUs which the thread of each vcpu is pinned to.
     /// * `boot_cfg` - Boot message generated by reading boot source to guest memory.
+    /// * `vcpu_cfg` - CPU features configured by user.
+    #[allow(clippy::too_many_arguments)]
     fn init_vcpu(
         vm: Arc<Mutex<dyn MachineInterface + Send + Sync>>,
+        sys_mem: Arc<AddressSpace>,
         nr_cpus: u8,
+        max_cpus: u8,
+        vcpu_affinity: &HashMap<u8, Vec<u64>>,
         topology: &CPUTopology,
         boot_cfg: &Option<CPUBootConfig>,
-        #[cfg(target_arch = "aarch64")] vcpu_cfg: &Option<CPUFeatures>,
+        vcpu_cfg: &Option<CPUFeatures>,
     ) -> Result<Vec<Arc<CPU>>>
     where
         Self: Sized,
     {
         let mut cpus = Vec::<Arc<CPU>>::new();
 
-        for vcpu_id in 0..nr_cpus {
+        for vcpu_id in 0..max_cpus {
+            // The id of x86 vcpu in kvm is its APIC ID, which encodes the topology.
+            #[cfg(target_arch = "x86_64")]
+            let kvm_vcpu_id = topology.apic_id(vcpu_id);
+            #[cfg(target_arch = "x86_64")]
+            if kvm_vcpu_id > u32::from(MAX_APIC_ID) {
+                bail!(
+                    "APIC ID {} of vcpu {} exceeds {}, please reduce the number of vcpus",
+                    kvm_vcpu_id,
+                    vcpu_id,
+                    MAX_APIC_ID
+                );
+            }
+            #[cfg(target_arch = "aarch64")]
+            let kvm_vcpu_id = u32::from(vcpu_id);
             let vcpu_fd = KVM_FDS
                 .load()
                 .vm_fd
                 .as_ref()
                 .unwrap()
-                .create_vcpu(vcpu_id as u64)
+                .create_vcpu(u64::from(kvm_vcpu_id))
                 .with_context(|| "Create vcpu failed")?;
+            KVM_FDS
+                .load()
+                .register_dirty_ring(u32::from(vcpu_id), &vcpu_fd)?;
             #[cfg(target_arch = "aarch64")]
-            let arch_cpu = ArchCPU::new(u32::from(vcpu_id));
+            let arch_cpu = ArchCPU::new(kvm_vcpu_id);
             #[cfg(target_arch = "x86_64")]
-            let arch_cpu = ArchCPU::new(u32::from(vcpu_id), u32::from(nr_cpus));
+            let arch_cpu = ArchCPU::new(kvm_vcpu_id, u32::from(max_cpus));
 
             let cpu = Arc::new(CPU::new(
                 Arc::new(vcpu_fd),
                 vcpu_id,
                 Arc::new(Mutex::new(arch_cpu)),
                 vm.clone(),
+                sys_mem.clone(),
             ));
+            if let Some(host_cpus) = vcpu_affinity.get(&vcpu_id) {
+                cpu.set_affinity(host_cpus.clone())?;
+            }
             cpus.push(cpu.clone());
 
-            MigrationManager::register_cpu_instance(cpu::ArchCPU::descriptor(), cpu, vcpu_id);
+            // Spare vCPUs are parked until they are hot-added.
+            if vcpu_id < nr_cpus {
+                MigrationManager::register_cpu_instance(cpu::ArchCPU::descriptor(), cpu, vcpu_id);
+            }
         }
 
+        let dump_cpus = cpus.clone();
+        register_crash_dump_section(
+            "vcpu registers",
+            Box::new(move || {
+                dump_cpus
+                    .iter()
+                    .map(|cpu| match cpu.dump_regs() {
+                        Ok(regs) => format!("vcpu{}:\n{}", cpu.id(), regs),
+                        Err(e) => format!("vcpu{}: unreachable, {:?}", cpu.id(), e),
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            }),
+        );
+
         if let Some(boot_config) = boot_cfg {
-            for (cpu_index, cpu) in cpus.iter().enumerate() {
-                cpu.realize(
-                    boot_config,
-                    topology,
-                    #[cfg(target_arch = "aarch64")]
-                    &vcpu_cfg.unwrap_or_default(),
-                )
-                .with_context(|| {
-                    format!(
-                        "Failed to realize arch cpu register/features for CPU {}/KVM",
-                        cpu_index
-                    )
-                })?;
-            }
+            // Setting registers and features of a vcpu only issues ioctls on its own fd, so the
+            // vcpus are realized in parallel to shorten the boot time of VMs with many vcpus.
+            // The vcpu fds above are still created one by one, as KVM serializes vcpu creation
+            // on the VM, and devices are all realized before the vcpus start.
+            let features = &vcpu_cfg.unwrap_or_default();
+            let threads = std::thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(cpus.len())
+                .max(1);
+            let chunk_size = cpus.len().div_ceil(threads);
+            std::thread::scope(|scope| -> Result<()> {
+                let handles = cpus
+                    .chunks(chunk_size.max(1))
+                    .map(|chunk| {
+                        scope.spawn(move || {
+                            chunk.iter().try_for_each(|cpu| {
+                                cpu.realize(boot_config, topology, features)
+                                    .with_context(|| {
+                                        format!(
+                                            "Failed to realize arch cpu register/features for CPU {}/KVM",
+                                            cpu.id()
+                                        )
+                                    })
+                            })
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for handle in handles {
+                    handle
+                        .join()
+                        .map_err(|_| anyhow!("Thread realizing vcpus panicked"))??;
+                }
+                Ok(())
+            })?;
         }
 
         Ok(cpus)
@@ -289,7 +395,15 @@ pub trait MachineOps {
     fn init_interrupt_controller(&mut self, vcpu_count: u64) -> Result<()>;
 
     /// Add RTC device.
-    fn add_rtc_device(&mut self, #[cfg(target_arch = "x86_64")] mem_size: u64) -> Result<()>;
+    ///
+    /// # Arguments
+    ///
+    /// * `rtc_config` - Start time and clock of RTC.
+    fn add_rtc_device(
+        &mut self,
+        rtc_config: &RtcConfig,
+        #[cfg(target_arch = "x86_64")] mem_size: u64,
+    ) -> Result<()>;
 
     /// Add Generic event device.
     #[cfg(target_arch = "aarch64")]
@@ -372,6 +486,16 @@ pub trait MachineOps {
 
     fn get_numa_nodes(&self) -> &Option<NumaNodes>;
 
+    /// Get the memory hotplug controller, which exists if DIMM slots are configured.
+    fn get_mem_hotplug(&self) -> Option<Arc<Mutex<MemHotplug>>> {
+        None
+    }
+
+    /// Notify guest to scan DIMM slots after hot-adding a DIMM.
+    fn notify_mem_hotplug(&self) -> Result<()> {
+        bail!("Memory hot-add is not supported!");
+    }
+
     /// Get migration mode and path from VM config. There are four modes in total:
     /// Tcp, Unix, File and Unknown.
     fn get_migrate_info(&self) -> Incoming;
@@ -525,13 +649,24 @@ pub trait MachineOps {
             bail!("Repetitive virtio serial port nr {}.", serialport_cfg.nr,);
         }
 
+        // Guest agent on server socket can be accessed by qmp command `guest-agent-command`.
+        let guest_agent_path = match &serialport_cfg.chardev.backend {
+            ChardevType::Socket {
+                path, server: true, ..
+            } if serialport_cfg.name == GUEST_AGENT_PORT_NAME => Some(path.clone()),
+            _ => None,
+        };
+
         let mut serial_port = SerialPort::new(serialport_cfg);
         let port = Arc::new(Mutex::new(serial_port.clone()));
         serial_port.realize()?;
+        if let Some(path) = guest_agent_path {
+            register_guest_agent(&path)?;
+        }
         if !is_console {
             serial_port.chardev.lock().unwrap().set_device(port.clone());
         }
-        serial.ports.lock().unwrap().push(port);
+        serial.add_port(port);
 
         Ok(())
     }
@@ -586,7 +721,7 @@ pub trait MachineOps {
         let id_clone = dev_cfg.id.clone();
         let sys_mem = self.get_sys_mem().clone();
 
-        if !vm_config.machine_config.mem_config.mem_share {
+        if !self.is_machine_ram_shared(&vm_config.machine_config.mem_config) {
             bail!("When configuring the vhost-user-fs-device or vhost-user-fs-pci device, the memory must be shared.");
         }
 
@@ -682,16 +817,22 @@ pub trait MachineOps {
         // SAFETY: unwrap is safe because stand machine always make sure it not return null.
         let boot_order_vec = self.get_boot_order_list().unwrap();
         let mut locked_boot_order_vec = boot_order_vec.lock().unwrap().clone();
-        if locked_boot_order_vec.is_empty() {
-            return Ok(());
-        }
+        let boot_strict = self.get_vm_config().lock().unwrap().boot_source.boot_strict;
         locked_boot_order_vec.sort_by(|x, y| x.boot_index.cmp(&y.boot_index));
         let mut fwcfg_boot_order_string = String::new();
         for item in &locked_boot_order_vec {
             fwcfg_boot_order_string.push_str(&item.dev_path);
             fwcfg_boot_order_string.push('\n');
         }
-        fwcfg_boot_order_string.push('\0');
+        // Firmware stops trying the devices without bootindex after "HALT".
+        if boot_strict {
+            fwcfg_boot_order_string.push_str("HALT\n");
+        }
+        // Keep the bootorder file empty if no boot order is set, the bootindex
+        // of devices may have been unplugged since last time.
+        if !fwcfg_boot_order_string.is_empty() {
+            fwcfg_boot_order_string.push('\0');
+        }
 
         let fwcfg = self.get_fwcfg_dev();
         if fwcfg.is_none() {
@@ -776,8 +917,17 @@ pub trait MachineOps {
             device_cfg.clone(),
             self.get_drive_files(),
         )));
+        let msix = get_virtio_pci_msix(cfg_args)?;
         let pci_dev = self
-            .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)
+            .add_virtio_pci_device(
+                &device_cfg.id,
+                &bdf,
+                device.clone(),
+                multi_func,
+                false,
+                None,
+                Some(&msix),
+            )
             .with_context(|| "Failed to add virtio pci device")?;
         if let Some(bootindex) = device_cfg.boot_index {
             // Eg: OpenFirmware device path(virtio-blk disk):
@@ -815,7 +965,15 @@ pub trait MachineOps {
         scsi_cntlr_create_scsi_bus(&bus_name, &device)?;
 
         let pci_dev = self
-            .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, false)
+            .add_virtio_pci_device(
+                &device_cfg.id,
+                &bdf,
+                device.clone(),
+                multi_func,
+                false,
+                None,
+                None,
+            )
             .with_context(|| "Failed to add virtio scsi controller")?;
         self.reset_bus(&device_cfg.id)?;
         device.lock().unwrap().config.boot_prefix = pci_dev.lock().unwrap().get_dev_path();
@@ -892,19 +1050,36 @@ pub trait MachineOps {
         let bdf = get_pci_bdf(cfg_args)?;
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_net(vm_config, cfg_args)?;
+        let msix = get_virtio_pci_msix(cfg_args)?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
         let mut need_irqfd = false;
         let device: Arc<Mutex<dyn VirtioDevice>> = if device_cfg.vhost_type.is_some() {
             need_irqfd = true;
             if device_cfg.vhost_type == Some(String::from("vhost-kernel")) {
-                Arc::new(Mutex::new(VhostKern::Net::new(
+                let device = Arc::new(Mutex::new(VhostKern::Net::new(
                     &device_cfg,
                     self.get_sys_mem(),
-                )))
+                )));
+                MigrationManager::register_device_instance(
+                    VhostKern::VhostNetState::descriptor(),
+                    device.clone(),
+                    &device_cfg.id,
+                );
+                device
             } else {
-                Arc::new(Mutex::new(VhostUser::Net::new(
+                let device = Arc::new(Mutex::new(VhostUser::Net::new(
                     &device_cfg,
                     self.get_sys_mem(),
-                )))
+                )));
+                MigrationManager::register_device_instance(
+                    VhostUser::VhostUserNetState::descriptor(),
+                    device.clone(),
+                    &device_cfg.id,
+                );
+                device
             }
         } else {
             let device = Arc::new(Mutex::new(virtio::Net::new(device_cfg.clone())));
@@ -915,7 +1090,20 @@ pub trait MachineOps {
             );
             device
         };
-        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, need_irqfd)?;
+        let pci_dev = self.add_virtio_pci_device(
+            &device_cfg.id,
+            &bdf,
+            device,
+            multi_func,
+            need_irqfd,
+            device_cfg.romfile.as_deref(),
+            Some(&msix),
+        )?;
+        if let Some(bootindex) = device_cfg.boot_index {
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &device_cfg.id);
+            }
+        }
         self.reset_bus(&device_cfg.id)?;
         Ok(())
     }
@@ -934,7 +1122,15 @@ pub trait MachineOps {
             self.get_sys_mem(),
         )));
         let pci_dev = self
-            .add_virtio_pci_device(&device_cfg.id, &bdf, device.clone(), multi_func, true)
+            .add_virtio_pci_device(
+                &device_cfg.id,
+                &bdf,
+                device.clone(),
+                multi_func,
+                true,
+                None,
+                None,
+            )
             .with_context(|| {
                 format!(
                     "Failed to add virtio pci device, device id: {}",
@@ -950,6 +1146,50 @@ pub trait MachineOps {
         Ok(())
     }
 
+    fn add_vhost_user_scsi_pci(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let queues_auto = Some(VirtioPciDevice::virtio_pci_auto_queues_num(
+            0,
+            vm_config.machine_config.nr_cpus,
+            MAX_VIRTIO_QUEUE,
+        ));
+        let device_cfg = parse_vhost_user_scsi(vm_config, cfg_args, queues_auto)?;
+        let device: Arc<Mutex<dyn VirtioDevice>> = Arc::new(Mutex::new(VhostUser::Scsi::new(
+            &device_cfg,
+            self.get_sys_mem(),
+        )));
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, true, None, None)
+            .with_context(|| {
+                format!(
+                    "Failed to add virtio pci device, device id: {}",
+                    &device_cfg.id
+                )
+            })?;
+        self.reset_bus(&device_cfg.id)?;
+        Ok(())
+    }
+
+    fn add_vhost_vdpa_pci(&mut self, cfg_args: &str, device_type: u32) -> Result<()> {
+        let bdf = get_pci_bdf(cfg_args)?;
+        let multi_func = get_multi_function(cfg_args)?;
+        let device_cfg = parse_vhost_vdpa(cfg_args)?;
+        let device: Arc<Mutex<dyn VirtioDevice>> = Arc::new(Mutex::new(VhostVdpa::Device::new(
+            &device_cfg,
+            device_type,
+            self.get_sys_mem(),
+        )?));
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, true, None, None)
+            .with_context(|| {
+                format!(
+                    "Failed to add vhost-vdpa pci device, device id: {}",
+                    &device_cfg.id
+                )
+            })?;
+        self.reset_bus(&device_cfg.id)?;
+        Ok(())
+    }
+
     fn add_vhost_user_blk_device(
         &mut self,
         vm_config: &mut VmConfig,
@@ -1015,7 +1255,7 @@ pub trait MachineOps {
         let multi_func = get_multi_function(cfg_args)?;
         let device_cfg = parse_gpu(cfg_args)?;
         let device = Arc::new(Mutex::new(Gpu::new(device_cfg.clone())));
-        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, false)?;
+        self.add_virtio_pci_device(&device_cfg.id, &bdf, device, multi_func, false, None, None)?;
         Ok(())
     }
 
@@ -1053,6 +1293,7 @@ pub trait MachineOps {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_virtio_pci_device(
         &mut self,
         id: &str,
@@ -1060,6 +1301,8 @@ pub trait MachineOps {
         device: Arc<Mutex<dyn VirtioDevice>>,
         multi_func: bool,
         need_irqfd: bool,
+        romfile: Option<&str>,
+        msix: Option<&VirtioPciMsixConfig>,
     ) -> Result<Arc<Mutex<dyn PciDevOps>>> {
         let (devfn, parent_bus) = self.get_devfn_and_parent_bus(bdf)?;
         let sys_mem = self.get_sys_mem();
@@ -1074,6 +1317,12 @@ pub trait MachineOps {
         if need_irqfd {
             pcidev.enable_need_irqfd();
         }
+        if let Some(romfile) = romfile {
+            pcidev.set_romfile(romfile);
+        }
+        if let Some(msix) = msix {
+            pcidev.set_msix_config(msix);
+        }
         let clone_pcidev = Arc::new(Mutex::new(pcidev.clone()));
         pcidev
             .realize()
@@ -1160,6 +1409,9 @@ pub trait MachineOps {
                         mem_dev: numa_config.mem_dev.clone(),
                         ..Default::default()
                     };
+                    for dist in numa_config.distances.unwrap_or_default() {
+                        numa_node.distances.insert(dist.destination, dist.distance);
+                    }
 
                     numa_node.size = vm_config
                         .object
@@ -1205,6 +1457,7 @@ pub trait MachineOps {
             vm_config.machine_config.nr_cpus,
             vm_config.machine_config.mem_config.mem_size,
         )?;
+        check_numa_cpu_topology(&numa_nodes, vm_config.machine_config.nr_threads)?;
 
         Ok(Some(numa_nodes))
     }
@@ -1440,6 +1693,7 @@ pub trait MachineOps {
     /// * `vm_config` - VM Configuration.
     fn add_devices(&mut self, vm_config: &mut VmConfig) -> Result<()> {
         self.add_rtc_device(
+            &vm_config.rtc,
             #[cfg(target_arch = "x86_64")]
             vm_config.machine_config.mem_config.mem_size,
         )
@@ -1456,6 +1710,7 @@ pub trait MachineOps {
         }
 
         if let Some(pflashs) = cloned_vm_config.pflashs.as_ref() {
+            let _timer = BootStageTimer::new(BootStage::FirmwareLoad, None);
             self.add_pflash_device(pflashs)
                 .with_context(|| MachineError::AddDevErr("pflash".to_string()))?;
         }
@@ -1466,6 +1721,7 @@ pub trait MachineOps {
             let id = parse_device_id(cfg_args)?;
             self.check_device_id_existed(&id)
                 .with_context(|| format!("Failed to check device id: config {}", cfg_args))?;
+            let _timer = BootStageTimer::new(BootStage::DeviceRealize, Some(id.as_str()));
             match dev.0.as_str() {
                 "virtio-blk-device" => {
                     self.add_virtio_mmio_block(vm_config, cfg_args)?;
@@ -1491,6 +1747,9 @@ pub trait MachineOps {
                 "pcie-root-port" => {
                     self.add_pci_root_port(cfg_args)?;
                 }
+                "pc-dimm" => {
+                    self.add_pc_dimm(vm_config, cfg_args, false)?;
+                }
                 "vhost-vsock-pci" | "vhost-vsock-device" => {
                     self.add_virtio_vsock(cfg_args)?;
                 }
@@ -1518,6 +1777,15 @@ pub trait MachineOps {
                 "vhost-user-blk-pci" => {
                     self.add_vhost_user_blk_pci(vm_config, cfg_args)?;
                 }
+                "vhost-user-scsi-pci" => {
+                    self.add_vhost_user_scsi_pci(vm_config, cfg_args)?;
+                }
+                "vhost-vdpa-net-pci" => {
+                    self.add_vhost_vdpa_pci(cfg_args, VIRTIO_TYPE_NET)?;
+                }
+                "vhost-vdpa-blk-pci" => {
+                    self.add_vhost_vdpa_pci(cfg_args, VIRTIO_TYPE_BLOCK)?;
+                }
                 "vhost-user-fs-pci" | "vhost-user-fs-device" => {
                     self.add_virtio_fs(vm_config, cfg_args)?;
                 }
@@ -1557,6 +1825,18 @@ pub trait MachineOps {
                 "ivshmem-scream" => {
                     self.add_ivshmem_scream(vm_config, cfg_args)?;
                 }
+                "pvpanic" => {
+                    self.add_pvpanic(vm_config, cfg_args)?;
+                }
+                "pvpanic-pci" => {
+                    self.add_pvpanic_pci(vm_config, cfg_args)?;
+                }
+                "tpm-crb" => {
+                    self.add_tpm_crb(vm_config, cfg_args)?;
+                }
+                "vmcoreinfo" => {
+                    self.add_vmcoreinfo(cfg_args)?;
+                }
                 _ => {
                     bail!("Unsupported device: {:?}", dev.0.as_str());
                 }
@@ -1570,10 +1850,129 @@ pub trait MachineOps {
         bail!("Pflash device is not supported!");
     }
 
+    /// Add pc-dimm device, which is plugged into a free DIMM slot.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    /// * `hotplug` - Whether the DIMM is hot-added when VM is running.
+    fn add_pc_dimm(
+        &mut self,
+        vm_config: &mut VmConfig,
+        cfg_args: &str,
+        hotplug: bool,
+    ) -> Result<()> {
+        let mem_hotplug = self
+            .get_mem_hotplug()
+            .with_context(|| "No DIMM slot, please set \'slots\' and \'maxmem\' of memory")?;
+        let dimm_cfg = parse_dimm(vm_config, cfg_args)?;
+        match self.get_numa_nodes() {
+            Some(numa_nodes) => {
+                if !numa_nodes.contains_key(&dimm_cfg.node) {
+                    bail!("Numa node id is not found {}", dimm_cfg.node);
+                }
+                if numa_nodes
+                    .values()
+                    .any(|node| node.mem_dev == dimm_cfg.mem_zone.id)
+                {
+                    bail!(
+                        "Memory backend {} is used by NUMA node",
+                        dimm_cfg.mem_zone.id
+                    );
+                }
+            }
+            None if dimm_cfg.node != 0 => bail!("Numa node id is not found {}", dimm_cfg.node),
+            None => {}
+        }
+
+        let mut locked_hotplug = mem_hotplug.lock().unwrap();
+        if locked_hotplug.dimms().iter().any(|d| d.id == dimm_cfg.id) {
+            bail!("Device id {} existed", dimm_cfg.id);
+        }
+        let size = dimm_cfg.mem_zone.size;
+        let (slot, addr) = locked_hotplug.alloc(size, dimm_cfg.addr, DIMM_ALIGN)?;
+        let ram = create_backend_mem(&dimm_cfg.mem_zone)?;
+        self.get_sys_mem()
+            .root()
+            .add_subregion(ram, addr)
+            .with_context(|| format!("Failed to map DIMM {} at 0x{:x}", dimm_cfg.id, addr))?;
+        locked_hotplug.plug(
+            slot,
+            DimmSlot::new(dimm_cfg.id, dimm_cfg.mem_zone.id, addr, size, dimm_cfg.node),
+            hotplug,
+        );
+        drop(locked_hotplug);
+
+        if hotplug {
+            self.notify_mem_hotplug()?;
+        }
+        Ok(())
+    }
+
     fn add_ramfb(&mut self, _cfg_args: &str) -> Result<()> {
         bail!("ramfb device is not supported!");
     }
 
+    /// Get the requests of VM lifecycle which are sent by pvpanic device.
+    fn get_pvpanic_reqs(&self) -> Result<PvPanicReqs> {
+        bail!("pvpanic device is not supported!");
+    }
+
+    /// Add pvpanic device on system bus.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    fn add_pvpanic(&mut self, _vm_config: &VmConfig, _cfg_args: &str) -> Result<()> {
+        bail!("pvpanic device is not supported!");
+    }
+
+    /// Add TPM device with CRB interface, which is backed by swtpm.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    fn add_tpm_crb(&mut self, _vm_config: &mut VmConfig, _cfg_args: &str) -> Result<()> {
+        bail!("TPM device is not supported!");
+    }
+
+    /// Add vmcoreinfo device, through which guest kernel registers its vmcoreinfo note.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg_args` - Device configuration args.
+    fn add_vmcoreinfo(&mut self, _cfg_args: &str) -> Result<()> {
+        bail!("vmcoreinfo device is not supported!");
+    }
+
+    /// Add pvpanic device on PCI bus.
+    ///
+    /// # Arguments
+    ///
+    /// * `vm_config` - VM configuration.
+    /// * `cfg_args` - Device configuration args.
+    fn add_pvpanic_pci(&mut self, vm_config: &VmConfig, cfg_args: &str) -> Result<()> {
+        let reqs = self.get_pvpanic_reqs()?;
+        let bdf = get_pci_bdf(cfg_args)?;
+        let (devfn, parent_bus) = self.get_devfn_and_parent_bus(&bdf)?;
+        let config =
+            parse_pvpanic(cfg_args).with_context(|| "Failed to parse cmdline for pvpanic")?;
+
+        let pvpanic = PvPanicPci::new(
+            &config,
+            vm_config.machine_config.panic_action,
+            reqs,
+            devfn,
+            parent_bus,
+        );
+        pvpanic
+            .realize()
+            .with_context(|| "Failed to realize pvpanic device")
+    }
+
     fn display_init(&mut self, _vm_config: &mut VmConfig) -> Result<()> {
         bail!("Display is not supported.");
     }
@@ -1595,26 +1994,103 @@ pub trait MachineOps {
     /// Return the syscall whitelist for seccomp.
     fn syscall_whitelist(&self) -> Vec<BpfRule>;
 
-    /// Register seccomp rules in syscall whitelist to seccomp.
-    fn register_seccomp(&self, balloon_enable: bool) -> Result<()> {
-        let mut seccomp_filter = SyscallFilter::new(SeccompOpt::Trap);
+    /// Return the syscall whitelist with the rules of enabled features.
+    fn builtin_syscall_whitelist(&self, balloon_enable: bool) -> Vec<BpfRule> {
         let mut bpf_rules = self.syscall_whitelist();
         if balloon_enable {
             balloon_allow_list(&mut bpf_rules);
         }
+        seclabel_allow_list(&mut bpf_rules);
+        qmp_audit_allow_list(&mut bpf_rules);
 
         if let Ok(cov_enable) = std::env::var("STRATOVIRT_COV") {
             if cov_enable.eq("on") {
                 coverage_allow_list(&mut bpf_rules);
             }
         }
+        bpf_rules
+    }
 
-        for bpf_rule in &mut bpf_rules {
-            seccomp_filter.push(bpf_rule);
+    /// Register seccomp rules in syscall whitelist to seccomp.
+    ///
+    /// All threads share the built-in whitelist if no policy is specified. Otherwise the
+    /// union of profiles is set for the whole process, and each thread class stacks its
+    /// own profile on it.
+    fn register_seccomp(&self, balloon_enable: bool, sandbox: &SandboxConfig) -> Result<()> {
+        let opt = SeccompOpt::from(sandbox.mode);
+        let builtin = self.builtin_syscall_whitelist(balloon_enable);
+        let policy = match &sandbox.policy {
+            Some(policy) => policy,
+            None => {
+                let mut seccomp_filter = SyscallFilter::new(opt);
+                for mut bpf_rule in builtin {
+                    seccomp_filter.push(&mut bpf_rule);
+                }
+                seccomp_filter
+                    .realize()
+                    .with_context(|| "Failed to init seccomp filter.")?;
+                return Ok(());
+            }
+        };
+
+        let profiles = [
+            (ThreadClass::Main, &policy.main),
+            (ThreadClass::Vcpu, &policy.vcpu),
+            (ThreadClass::IoThread, &policy.iothread),
+            (ThreadClass::Migration, &policy.migration),
+        ];
+        let build_filter = |profile: &SandboxProfile| {
+            let mut seccomp_filter = SyscallFilter::new(opt);
+            let denied: Vec<i64> = profile.deny.iter().filter_map(|n| syscall_num(n)).collect();
+            let allowed = profile.allow.iter().filter_map(|n| syscall_num(n));
+            for mut bpf_rule in builtin
+                .iter()
+                .filter(|rule| !denied.contains(&rule.syscall_num()))
+                .cloned()
+                .chain(allowed.map(BpfRule::new))
+            {
+                seccomp_filter.push(&mut bpf_rule);
+            }
+            seccomp_filter
+        };
+
+        // Threads stack their profiles by seccomp(2), which must be allowed. Syscalls
+        // are denied for the whole process only if all the profiles deny them.
+        let seccomp = "seccomp".to_string();
+        let mut union = SandboxProfile {
+            allow: vec![seccomp.clone()],
+            deny: policy.main.deny.clone(),
+        };
+        for (_, profile) in profiles {
+            union.allow.extend(profile.allow.iter().cloned());
+            union.deny.retain(|name| profile.deny.contains(name));
         }
-        seccomp_filter
+        union.deny.retain(|name| !union.allow.contains(name));
+        build_filter(&union)
             .realize()
             .with_context(|| "Failed to init seccomp filter.")?;
+
+        // Class threads are spawned by the launcher created here, so they don't
+        // inherit the profile of main thread.
+        register_thread_filters(
+            profiles
+                .iter()
+                .filter(|(class, _)| *class != ThreadClass::Main)
+                .map(|(class, profile)| (*class, build_filter(profile)))
+                .collect(),
+        )?;
+
+        // This is called in main thread, which applies its profile at once. Other
+        // threads created later by it inherit the profile, and stack theirs on it.
+        let mut main_profile = policy.main.clone();
+        main_profile.allow.push(seccomp.clone());
+        main_profile.deny.retain(|name| *name != seccomp);
+        build_filter(&main_profile)
+            .realize_thread()
+            .with_context(|| "Failed to init seccomp filter of main thread.")?;
+        // Wake up iothreads to apply their profiles, vcpus apply theirs when exiting
+        // from guest.
+        EventLoop::kick_iothreads();
         Ok(())
     }
 
@@ -1757,6 +2233,9 @@ pub trait MachineOps {
             }
         }
 
+        #[cfg(target_arch = "x86_64")]
+        KVM_FDS.load().pause_clock()?;
+
         #[cfg(target_arch = "aarch64")]
         // SAFETY: ARM architecture must have interrupt controllers in user mode.
         irq_chip.as_ref().unwrap().stop();
@@ -1775,6 +2254,9 @@ pub trait MachineOps {
     fn vm_resume(&self, cpus: &[Arc<CPU>], vm_state: &mut KvmVmState) -> Result<()> {
         EventLoop::get_ctx(None).unwrap().enable_clock();
 
+        #[cfg(target_arch = "x86_64")]
+        KVM_FDS.load().resume_clock()?;
+
         self.active_drive_files()?;
 
         for (cpu_index, cpu) in cpus.iter().enumerate() {
@@ -1886,6 +2368,53 @@ pub fn vm_run(
     Ok(())
 }
 
+/// Register the device list of VM as a section of crash dump.
+pub fn register_crash_dump_devices(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) {
+    let vm_config = vm.lock().unwrap().get_vm_config();
+    register_crash_dump_section(
+        "devices",
+        Box::new(move || match vm_config.try_lock() {
+            Ok(config) => config
+                .devices
+                .iter()
+                .map(|(_, device)| device.clone())
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Err(_) => "Device list is unreachable as it's locked".to_string(),
+        }),
+    );
+}
+
+/// Get the breakdown of resident memory of VMM for `query-vmm-memory`.
+pub fn query_vmm_memory() -> Response {
+    let usage = match util::vmm_memory::vmm_memory_usage(&host_mem_ranges()) {
+        Ok(usage) => usage,
+        Err(e) => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            );
+        }
+    };
+    let info = VmmMemoryInfo {
+        total_rss: usage.total,
+        guest_ram_rss: usage.guest_ram,
+        heap_rss: usage.heap,
+        stack_rss: usage.stacks,
+        other_rss: usage.other,
+        threads: usage
+            .thread_stacks
+            .into_iter()
+            .map(|stack| ThreadStackInfo {
+                tid: stack.tid,
+                name: stack.name,
+                stack_rss: stack.rss,
+            })
+            .collect(),
+    };
+    Response::create_response(serde_json::to_value(info).unwrap(), None)
+}
+
 /// Start incoming migration from destination.
 fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Result<()> {
     let (mode, path) = vm.lock().unwrap().get_migrate_info();
@@ -1901,6 +2430,7 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
         MigrateMode::Unix => {
             clear_file(path.clone())?;
             let listener = UnixListener::bind(&path)?;
+            label_path(&path)?;
             let (mut sock, _) = listener.accept()?;
             remove_file(&path)?;
 
@@ -1912,6 +2442,7 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
                 .with_context(|| "Failed to start VM.")?;
             MigrationManager::finish_migration(&mut sock)
                 .with_context(|| "Failed to finish migraton.")?;
+            migration::start_colo_secondary(sock)?;
         }
         MigrateMode::Tcp => {
             let listener = TcpListener::bind(&path)?;
@@ -1925,6 +2456,41 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
                 .with_context(|| "Failed to start VM.")?;
             MigrationManager::finish_migration(&mut sock)
                 .with_context(|| "Failed to finish migraton.")?;
+            migration::start_colo_secondary(sock)?;
+        }
+        MigrateMode::Exec | MigrateMode::ExecOneway => {
+            let exec_mode = if mode == MigrateMode::Exec {
+                ExecMode::Duplex
+            } else {
+                ExecMode::OneWayRecv
+            };
+            let mut stream = ExecStream::spawn(&path, exec_mode)?;
+
+            MigrationManager::recv_migration(&mut stream)
+                .with_context(|| "Failed to receive migration with exec mode")?;
+            vm.lock()
+                .unwrap()
+                .run(false)
+                .with_context(|| "Failed to start VM.")?;
+            MigrationManager::finish_migration(&mut stream)
+                .with_context(|| "Failed to finish migraton.")?;
+            migration::start_colo_secondary(stream)?;
+        }
+        MigrateMode::Fd => {
+            let fd = path
+                .parse::<RawFd>()
+                .with_context(|| format!("Invalid incoming fd {}", path))?;
+            let mut stream = fd_stream(fd)?;
+
+            MigrationManager::recv_migration(&mut stream)
+                .with_context(|| "Failed to receive migration with fd mode")?;
+            vm.lock()
+                .unwrap()
+                .run(false)
+                .with_context(|| "Failed to start VM.")?;
+            MigrationManager::finish_migration(&mut stream)
+                .with_context(|| "Failed to finish migraton.")?;
+            migration::start_colo_secondary(stream)?;
         }
         MigrateMode::Unknown => {
             bail!("Unknown migration mode");
@@ -1941,6 +2507,42 @@ fn start_incoming_migration(vm: &Arc<Mutex<dyn MachineOps + Send + Sync>>) -> Re
     Ok(())
 }
 
+/// Merge the device tree overlays given by `-dtb-overlay` into the generated device tree.
+///
+/// # Arguments
+///
+/// * `fdt_vec` - The generated flattened device tree blob.
+/// * `overlays` - Paths of the device tree overlay blobs, applied in order.
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn apply_dtb_overlays(
+    fdt_vec: Vec<u8>,
+    overlays: &[std::path::PathBuf],
+) -> Result<Vec<u8>> {
+    if overlays.is_empty() {
+        return Ok(fdt_vec);
+    }
+
+    let mut fdt = util::device_tree::Fdt::from_blob(&fdt_vec)?;
+    for path in overlays {
+        let blob = std::fs::read(path)
+            .with_context(|| format!("Failed to read dtb overlay {:?}", path))?;
+        let overlay = util::device_tree::Fdt::from_blob(&blob)
+            .with_context(|| format!("Failed to parse dtb overlay {:?}", path))?;
+        fdt.apply_overlay(&overlay)
+            .with_context(|| format!("Failed to apply dtb overlay {:?}", path))?;
+    }
+
+    let fdt_vec = fdt.to_blob()?;
+    if fdt_vec.len() > util::device_tree::FDT_MAX_SIZE as usize {
+        bail!(
+            "Device tree size {:#x} exceeds the max size {:#x} after applying overlays",
+            fdt_vec.len(),
+            util::device_tree::FDT_MAX_SIZE
+        );
+    }
+    Ok(fdt_vec)
+}
+
 fn coverage_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     syscall_allow_list.extend(vec![
         BpfRule::new(libc::SYS_fcntl),
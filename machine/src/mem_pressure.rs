@@ -0,0 +1,173 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Monitor which reports high dirty rate of guest memory and high memory pressure of host.
+//!
+//! The dirty rate is sampled by logging dirty pages for a short period in each interval,
+//! and it's skipped while the dirty log is used by migration or COLO. The memory pressure
+//! is the avg10 of the pressure stall information of memory.
+
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use log::{error, info};
+
+use machine_manager::config::MemPressureConfig;
+use machine_manager::event;
+use machine_manager::qmp::qmp_channel::QmpChannel;
+use machine_manager::qmp::qmp_schema::{DirtyRateHigh, MemoryPressure};
+use machine_manager::signal_handler::get_signal;
+use migration::MigrationManager;
+
+/// Period to log dirty pages for sampling the dirty rate.
+const DIRTY_RATE_SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Start the thread which samples the dirty rate and memory pressure every interval.
+///
+/// # Arguments
+///
+/// * `config` - Config of memory pressure monitor.
+pub fn start_mem_pressure_monitor(config: &MemPressureConfig) -> Result<()> {
+    let monitor = MemPressureMonitor {
+        config: config.clone(),
+    };
+    if monitor.psi_enabled() {
+        read_psi(&config.psi_file)?;
+    }
+    let interval = Duration::from_secs(config.interval);
+    thread::Builder::new()
+        .name("mem-pressure".to_string())
+        .spawn(move || {
+            let mut next = Instant::now() + interval;
+            while get_signal() == 0 {
+                thread::sleep(next.saturating_duration_since(Instant::now()));
+                next += interval;
+                monitor.check();
+            }
+        })
+        .with_context(|| "Failed to spawn memory pressure monitor thread")?;
+    info!("Memory pressure monitor started, interval {:?}", interval);
+    Ok(())
+}
+
+struct MemPressureMonitor {
+    config: MemPressureConfig,
+}
+
+impl MemPressureMonitor {
+    fn psi_enabled(&self) -> bool {
+        self.config.psi_some.is_some() || self.config.psi_full.is_some()
+    }
+
+    fn check(&self) {
+        if self.psi_enabled() {
+            match read_psi(&self.config.psi_file) {
+                Ok(psi) => self.check_psi(psi),
+                Err(e) => error!("{:?}", e),
+            }
+        }
+        if let Some(threshold) = self.config.dirty_rate {
+            match MigrationManager::sample_dirty_rate(DIRTY_RATE_SAMPLE_PERIOD) {
+                Ok(Some(rate)) => self.check_dirty_rate(rate >> 20, threshold),
+                Ok(None) => {}
+                Err(e) => error!("Failed to sample dirty rate: {:?}", e),
+            }
+        }
+    }
+
+    fn check_psi(&self, psi: MemoryPressure) {
+        let above = |threshold: Option<f64>, avg10: f64| {
+            threshold
+                .map(|threshold| avg10 >= threshold)
+                .unwrap_or(false)
+        };
+        if above(self.config.psi_some, psi.some_avg10)
+            || above(self.config.psi_full, psi.full_avg10)
+        {
+            info!(
+                "Memory pressure is high, some avg10 {:.2}, full avg10 {:.2}",
+                psi.some_avg10, psi.full_avg10
+            );
+            event!(MemoryPressure; psi);
+        }
+    }
+
+    fn check_dirty_rate(&self, dirty_rate: u64, threshold: u64) {
+        if dirty_rate >= threshold {
+            info!("Dirty rate of guest memory is high, {} MiB/s", dirty_rate);
+            let dirty_rate_high = DirtyRateHigh {
+                dirty_rate,
+                threshold,
+            };
+            event!(DirtyRateHigh; dirty_rate_high);
+        }
+    }
+}
+
+/// Read the avg10 of `some` and `full` from the file of memory pressure stall information.
+fn read_psi(path: &str) -> Result<MemoryPressure> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read memory pressure from {}", path))?;
+    parse_psi(&content).with_context(|| format!("Invalid memory pressure in {}", path))
+}
+
+/// Parse the pressure stall information in format of
+/// `<some|full> avg10=<percent> avg60=<percent> avg300=<percent> total=<us>`.
+/// The `full` line is missing in old kernels, and it's regarded as 0.
+fn parse_psi(content: &str) -> Result<MemoryPressure> {
+    let mut some = None;
+    let mut full = None;
+    for line in content.lines() {
+        let mut items = line.split_whitespace();
+        let kind = items.next();
+        let avg10 = items
+            .find_map(|item| item.strip_prefix("avg10="))
+            .map(|avg10| avg10.parse::<f64>())
+            .transpose()?;
+        match kind {
+            Some("some") => some = avg10,
+            Some("full") => full = avg10,
+            _ => {}
+        }
+    }
+    match some {
+        Some(some_avg10) => Ok(MemoryPressure {
+            some_avg10,
+            full_avg10: full.unwrap_or(0.0),
+        }),
+        None => bail!("avg10 of some is not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_psi() {
+        let psi = parse_psi(
+            "some avg10=23.10 avg60=8.00 avg300=1.53 total=2791245\n\
+             full avg10=4.50 avg60=1.00 avg300=0.20 total=519387\n",
+        )
+        .unwrap();
+        assert_eq!(psi.some_avg10, 23.1);
+        assert_eq!(psi.full_avg10, 4.5);
+
+        let psi = parse_psi("some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        assert_eq!(psi.full_avg10, 0.0);
+
+        assert!(parse_psi("").is_err());
+        assert!(parse_psi("some avg10=x avg60=0.00 avg300=0.00 total=0\n").is_err());
+    }
+}
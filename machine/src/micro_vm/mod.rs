@@ -39,7 +39,8 @@ use std::collections::HashMap;
 use std::fmt;
 use std::fmt::Debug;
 use std::ops::Deref;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
 use std::sync::{Arc, Condvar, Mutex};
 use std::vec::Vec;
 
@@ -47,18 +48,26 @@ use anyhow::{anyhow, bail, Context, Result};
 #[cfg(target_arch = "x86_64")]
 use kvm_bindings::{kvm_pit_config, KVM_PIT_SPEAKER_DUMMY};
 use log::{error, info};
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
 
 use super::Result as MachineResult;
 use super::{error::MachineError, MachineOps};
+#[cfg(target_arch = "aarch64")]
+use crate::apply_dtb_overlays;
 #[cfg(target_arch = "x86_64")]
-use crate::vm_state;
+use crate::{gdbstub, vm_state};
 use address_space::{AddressSpace, GuestAddress, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
+use chardev_backend::guest_agent::guest_agent_command;
+use chardev_backend::ringbuf::{ringbuf_read, ringbuf_write};
+#[cfg(target_arch = "x86_64")]
+use cpu::set_spec_isolation;
 #[cfg(target_arch = "aarch64")]
 use cpu::CPUFeatures;
 #[cfg(target_arch = "aarch64")]
 use cpu::PMU_INTR;
-use cpu::{CPUBootConfig, CPUTopology, CpuLifecycleState, CpuTopology, CPU};
+use cpu::{CPUBootConfig, CPUInterface, CPUTopology, CpuTopology, CPU};
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::PL031;
 #[cfg(target_arch = "x86_64")]
@@ -73,8 +82,8 @@ use devices::{ICGICConfig, ICGICv2Config, ICGICv3Config, InterruptController, GI
 use hypervisor::kvm::KVM_FDS;
 use machine_manager::config::{
     parse_blk, parse_incoming_uri, parse_net, BlkDevConfig, BootSource, ConfigCheck, DiskFormat,
-    DriveFile, Incoming, MigrateMode, NetworkInterfaceConfig, NumaNodes, SerialConfig, VmConfig,
-    DEFAULT_VIRTQUEUE_SIZE,
+    DriveFile, Incoming, MigrateMode, NetworkInterfaceConfig, NumaNodes, RtcConfig, SerialConfig,
+    VmConfig, DEFAULT_NOTIFY_BATCH, DEFAULT_VIRTQUEUE_SIZE,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -89,11 +98,13 @@ use mem_layout::{LayoutEntryType, MEM_LAYOUT};
 use migration::{MigrationManager, MigrationStatus};
 use syscall::syscall_whitelist;
 use util::aio::WriteZeroesState;
+use util::boot_time::{BootStage, BootStageTimer};
 #[cfg(target_arch = "aarch64")]
 use util::device_tree::{self, CompileFDT, FdtBuilder};
-use util::{
-    loop_context::EventLoopManager, num_ops::str_to_usize, seccomp::BpfRule, set_termi_canon_mode,
+use util::loop_context::{
+    read_fd, EventLoopManager, EventNotifier, NotifierCallback, NotifierOperation,
 };
+use util::{num_ops::str_to_usize, seccomp::BpfRule, set_termi_canon_mode, trace};
 use virtio::{
     create_tap, qmp_balloon, qmp_query_balloon, Block, BlockState, Net, VhostKern, VhostUser,
     VirtioDevice, VirtioMmioDevice, VirtioMmioState, VirtioNetState,
@@ -190,6 +201,8 @@ pub struct LightMachine {
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
     // All backend memory region tree.
     machine_ram: Arc<Region>,
+    // Reset request, handle VM `Reset` event.
+    reset_req: Arc<EventFd>,
 }
 
 impl LightMachine {
@@ -249,9 +262,96 @@ impl LightMachine {
             numa_nodes: None,
             drive_files: Arc::new(Mutex::new(vm_config.init_drive_files()?)),
             machine_ram: Arc::new(Region::init_container_region(u64::max_value(), "pc.ram")),
+            reset_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("reset_req".to_string()))?,
+            ),
         })
     }
 
+    fn register_reset_event(&self, vm: Arc<Mutex<Self>>) -> MachineResult<()> {
+        let reset_req_fd = self.reset_req.as_raw_fd();
+        let reset_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            read_fd(reset_req_fd);
+            if let Err(e) = LightMachine::handle_reset_request(&vm) {
+                error!("Fail to reboot micro VM, {:?}", e);
+            }
+
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            reset_req_fd,
+            None,
+            EventSet::IN,
+            vec![reset_req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .with_context(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+
+    /// Reboot the VM in the process: vCPUs are set back to boot state, the kernel is
+    /// reloaded into guest memory and all the devices are reset.
+    fn handle_reset_request(vm: &Arc<Mutex<Self>>) -> MachineResult<()> {
+        let mut locked_vm = vm.lock().unwrap();
+
+        for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
+            cpu.pause()
+                .with_context(|| format!("Failed to pause vcpu{}", cpu_index))?;
+
+            cpu.set_to_boot_state();
+            #[cfg(target_arch = "aarch64")]
+            cpu.fd()
+                .vcpu_init(&cpu.arch().lock().unwrap().kvi())
+                .with_context(|| "Failed to init vcpu fd")?;
+        }
+
+        // Guest may have overwritten the kernel image, load it again as firmware does.
+        #[cfg(target_arch = "x86_64")]
+        locked_vm.load_boot_source(None)?;
+        #[cfg(target_arch = "aarch64")]
+        {
+            let boot_config = locked_vm.load_boot_source(None)?;
+            let mut fdt_helper = FdtBuilder::new();
+            locked_vm
+                .generate_fdt_node(&mut fdt_helper)
+                .with_context(|| MachineError::GenFdtErr)?;
+            let fdt_vec = apply_dtb_overlays(
+                fdt_helper.finish()?,
+                &locked_vm.boot_source.lock().unwrap().dtb_overlays,
+            )?;
+            locked_vm
+                .sys_mem
+                .write(
+                    &mut fdt_vec.as_slice(),
+                    GuestAddress(boot_config.fdt_addr),
+                    fdt_vec.len() as u64,
+                )
+                .with_context(|| MachineError::WrtFdtErr(boot_config.fdt_addr, fdt_vec.len()))?;
+        }
+
+        locked_vm
+            .reset_all_devices()
+            .with_context(|| "Fail to reset all devices")?;
+        #[cfg(target_arch = "aarch64")]
+        locked_vm.irq_chip.as_ref().unwrap().reset()?;
+
+        if QmpChannel::is_connected() {
+            let reset_msg = qmp_schema::Reset { guest: true };
+            event!(Reset; reset_msg);
+        }
+
+        for (cpu_index, cpu) in locked_vm.cpus.iter().enumerate() {
+            cpu.reset()
+                .with_context(|| format!("Failed to reset vcpu{}", cpu_index))?;
+            cpu.resume()
+                .with_context(|| format!("Failed to resume vcpu{}", cpu_index))?;
+        }
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     fn arch_init() -> MachineResult<()> {
         let kvm_fds = KVM_FDS.load();
@@ -384,7 +484,7 @@ impl LightMachine {
             dev_config,
         };
 
-        trace_mmio_replaceable_config(&config);
+        trace::mmio_replaceable_config(&config);
         configs_lock.push(config);
         Ok(())
     }
@@ -593,6 +693,7 @@ impl MachineOps for LightMachine {
         &self,
         fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
     ) -> MachineResult<CPUBootConfig> {
+        let _timer = BootStageTimer::new(BootStage::FirmwareLoad, None);
         let boot_source = self.boot_source.lock().unwrap();
         let initrd = boot_source.initrd.as_ref().map(|b| b.initrd_file.clone());
 
@@ -603,12 +704,15 @@ impl MachineOps for LightMachine {
             kernel: boot_source.kernel_file.clone(),
             initrd,
             kernel_cmdline: boot_source.kernel_cmdline.to_string(),
-            cpu_count: self.cpu_topo.nrcpus,
+            apic_ids: (0..self.cpu_topo.nrcpus)
+                .map(|cpu_id| self.cpu_topo.get_apic_id(cpu_id) as u8)
+                .collect(),
             gap_range: (gap_start, gap_end - gap_start),
             ioapic_addr: MEM_LAYOUT[LayoutEntryType::IoApic as usize].0 as u32,
             lapic_addr: MEM_LAYOUT[LayoutEntryType::LocalApic as usize].0 as u32,
             ident_tss_range: None,
             prot64_mode: true,
+            reserved_ranges: Vec::new(),
         };
         let layout = load_linux(&bootloader_config, &self.sys_mem, fwcfg)
             .with_context(|| MachineError::LoadKernErr)?;
@@ -634,6 +738,7 @@ impl MachineOps for LightMachine {
         &self,
         fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>,
     ) -> MachineResult<CPUBootConfig> {
+        let _timer = BootStageTimer::new(BootStage::FirmwareLoad, None);
         let mut boot_source = self.boot_source.lock().unwrap();
         let initrd = boot_source.initrd.as_ref().map(|b| b.initrd_file.clone());
 
@@ -707,9 +812,9 @@ impl MachineOps for LightMachine {
     }
 
     #[cfg(target_arch = "aarch64")]
-    fn add_rtc_device(&mut self) -> MachineResult<()> {
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig) -> MachineResult<()> {
         PL031::realize(
-            PL031::default(),
+            PL031::new(rtc_config),
             &mut self.sysbus,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].0,
             MEM_LAYOUT[LayoutEntryType::Rtc as usize].1,
@@ -719,7 +824,7 @@ impl MachineOps for LightMachine {
     }
 
     #[cfg(target_arch = "x86_64")]
-    fn add_rtc_device(&mut self, _mem_size: u64) -> MachineResult<()> {
+    fn add_rtc_device(&mut self, _rtc_config: &RtcConfig, _mem_size: u64) -> MachineResult<()> {
         Ok(())
     }
 
@@ -760,12 +865,26 @@ impl MachineOps for LightMachine {
         if device_cfg.vhost_type.is_some() {
             let device = if device_cfg.vhost_type == Some(String::from("vhost-kernel")) {
                 let net = Arc::new(Mutex::new(VhostKern::Net::new(&device_cfg, &self.sys_mem)));
+                MigrationManager::register_device_instance(
+                    VhostKern::VhostNetState::descriptor(),
+                    net.clone(),
+                    &device_cfg.id,
+                );
                 VirtioMmioDevice::new(&self.sys_mem, net)
             } else {
                 let net = Arc::new(Mutex::new(VhostUser::Net::new(&device_cfg, &self.sys_mem)));
+                MigrationManager::register_device_instance(
+                    VhostUser::VhostUserNetState::descriptor(),
+                    net.clone(),
+                    &device_cfg.id,
+                );
                 VirtioMmioDevice::new(&self.sys_mem, net)
             };
-            self.realize_virtio_mmio_device(device)?;
+            MigrationManager::register_transport_instance(
+                VirtioMmioState::descriptor(),
+                self.realize_virtio_mmio_device(device)?,
+                &device_cfg.id,
+            );
         } else {
             let index = MMIO_REPLACEABLE_BLK_NR + self.replaceable_info.net_count;
             if index >= MMIO_REPLACEABLE_BLK_NR + MMIO_REPLACEABLE_NET_NR {
@@ -810,23 +929,33 @@ impl MachineOps for LightMachine {
         let mut locked_vm = vm.lock().unwrap();
 
         // trace for lightmachine
-        trace_sysbus(&locked_vm.sysbus);
-        trace_vm_state(&locked_vm.vm_state);
+        trace::sysbus(&locked_vm.sysbus);
+        trace::vm_state(&locked_vm.vm_state);
 
         let topology = CPUTopology::new().set_topology((
             vm_config.machine_config.nr_threads,
             vm_config.machine_config.nr_cores,
             vm_config.machine_config.nr_dies,
         ));
-        trace_cpu_topo(&topology);
+        trace::cpu_topo(&topology);
         locked_vm.numa_nodes = locked_vm.add_numa_nodes(vm_config)?;
+        KVM_FDS
+            .load()
+            .init_confidential_guest()
+            .with_context(|| "Failed to init confidential guest")?;
         locked_vm.init_memory(
             &vm_config.machine_config.mem_config,
             #[cfg(target_arch = "x86_64")]
             &locked_vm.sys_io,
             &locked_vm.sys_mem,
-            vm_config.machine_config.nr_cpus,
         )?;
+        KVM_FDS
+            .load()
+            .enable_dirty_ring(vm_config.machine_config.dirty_ring_size)
+            .with_context(|| "Failed to enable kvm dirty ring")?;
+        KVM_FDS
+            .load()
+            .set_clock_advance_on_pause(vm_config.machine_config.clock_advance_on_pause);
 
         let migrate_info = locked_vm.get_migrate_info();
 
@@ -840,20 +969,31 @@ impl MachineOps for LightMachine {
                 .create_replaceable_devices()
                 .with_context(|| "Failed to create replaceable devices.")?;
             locked_vm.add_devices(vm_config)?;
-            trace_replaceable_info(&locked_vm.replaceable_info);
+            trace::replaceable_info(&locked_vm.replaceable_info);
 
-            let boot_config = if migrate_info.0 == MigrateMode::Unknown {
-                Some(locked_vm.load_boot_source(None)?)
+            let (boot_config, cpu_config) = if migrate_info.0 == MigrateMode::Unknown {
+                (
+                    Some(locked_vm.load_boot_source(None)?),
+                    Some(locked_vm.load_cpu_features(vm_config)?),
+                )
             } else {
-                None
+                (None, None)
             };
 
+            if vm_config.machine_config.cpu_config.spec_isolation {
+                set_spec_isolation().with_context(|| "Failed to isolate speculation of vCPUs")?;
+            }
             // vCPUs init
+            let sys_mem = locked_vm.sys_mem.clone();
             locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
                 vm.clone(),
+                sys_mem,
+                vm_config.machine_config.nr_cpus,
                 vm_config.machine_config.nr_cpus,
+                &vm_config.machine_config.vcpu_affinity,
                 &topology,
                 &boot_config,
+                &cpu_config,
             )?);
         }
 
@@ -869,9 +1009,13 @@ impl MachineOps for LightMachine {
             };
 
             // vCPUs init,and apply CPU features (for aarch64)
+            let sys_mem = locked_vm.sys_mem.clone();
             locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
                 vm.clone(),
+                sys_mem,
+                vm_config.machine_config.nr_cpus,
                 vm_config.machine_config.nr_cpus,
+                &vm_config.machine_config.vcpu_affinity,
                 &topology,
                 &boot_config,
                 &cpu_config,
@@ -886,14 +1030,15 @@ impl MachineOps for LightMachine {
                 .create_replaceable_devices()
                 .with_context(|| "Failed to create replaceable devices.")?;
             locked_vm.add_devices(vm_config)?;
-            trace_replaceable_info(&locked_vm.replaceable_info);
+            trace::replaceable_info(&locked_vm.replaceable_info);
 
             if let Some(boot_cfg) = boot_config {
                 let mut fdt_helper = FdtBuilder::new();
                 locked_vm
                     .generate_fdt_node(&mut fdt_helper)
                     .with_context(|| MachineError::GenFdtErr)?;
-                let fdt_vec = fdt_helper.finish()?;
+                let fdt_vec =
+                    apply_dtb_overlays(fdt_helper.finish()?, &vm_config.boot_source.dtb_overlays)?;
                 locked_vm
                     .sys_mem
                     .write(
@@ -905,6 +1050,15 @@ impl MachineOps for LightMachine {
             }
         }
 
+        locked_vm
+            .register_reset_event(vm.clone())
+            .with_context(|| "Fail to register reset event")?;
+
+        KVM_FDS
+            .load()
+            .launch_confidential_guest()
+            .with_context(|| "Failed to launch confidential guest")?;
+
         MigrationManager::register_vm_instance(vm.clone());
         #[cfg(target_arch = "x86_64")]
         MigrationManager::register_kvm_instance(
@@ -915,6 +1069,17 @@ impl MachineOps for LightMachine {
             bail!("Failed to set migration status {}", e);
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if let Some(gdb_config) = &vm_config.gdb {
+            gdbstub::start_gdbstub(
+                gdb_config,
+                vm.clone(),
+                locked_vm.vm_state.clone(),
+                locked_vm.cpus.clone(),
+                locked_vm.sys_mem.clone(),
+            )?;
+        }
+
         Ok(())
     }
 
@@ -959,13 +1124,11 @@ impl MachineLifecycle for LightMachine {
     }
 
     fn reset(&mut self) -> bool {
-        // For micro vm, the reboot command is equivalent to the shutdown command.
-        for cpu in self.cpus.iter() {
-            let (cpu_state, _) = cpu.state();
-            *cpu_state.lock().unwrap() = CpuLifecycleState::Stopped;
+        if self.reset_req.write(1).is_err() {
+            error!("Micro vm write reset request failed");
+            return false;
         }
-
-        self.destroy()
+        true
     }
 
     fn notify_lifecycle(&self, old: KvmVmState, new: KvmVmState) -> bool {
@@ -1080,6 +1243,60 @@ impl DeviceInterface for LightMachine {
         Response::create_response(cpu_vec.into(), None)
     }
 
+    fn set_vcpu_affinity(&self, vcpu: u8, cpus: Vec<u64>) -> Response {
+        let cpu = match self.cpus.iter().find(|cpu| cpu.id() == vcpu) {
+            Some(cpu) => cpu,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("vCPU {} not found", vcpu)),
+                    None,
+                );
+            }
+        };
+        match cpu.set_affinity(cpus) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn vcpu_throttle(&self, percentage: u8) -> Response {
+        match cpu::set_throttle_percentage(&self.cpus, percentage) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_vcpu_exit_stats(&self, args: qmp_schema::QueryVcpuExitStatsArgument) -> Response {
+        let stats = cpu::query_exit_stats(&self.cpus, args.reset);
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
+    fn query_virtqueue_stats(&self, args: qmp_schema::QueryVirtqueueStatsArgument) -> Response {
+        let stats = virtio::query_queue_stats(args.device.as_deref(), args.reset);
+        if stats.is_empty() {
+            if let Some(device) = args.device {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                        "No virtqueue statistics for device {}",
+                        device
+                    )),
+                    None,
+                );
+            }
+        }
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
+    fn query_vmm_memory(&self) -> Response {
+        crate::query_vmm_memory()
+    }
+
     fn query_hotpluggable_cpus(&self) -> Response {
         let mut hotplug_vec: Vec<serde_json::Value> = Vec::new();
         #[cfg(target_arch = "x86_64")]
@@ -1138,6 +1355,46 @@ impl DeviceInterface for LightMachine {
         )
     }
 
+    fn ringbuf_read(&self, device: String, size: u64, format: qmp_schema::DataFormat) -> Response {
+        match ringbuf_read(&device, size, format) {
+            Ok(data) => Response::create_response(serde_json::to_value(data).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_write(
+        &self,
+        device: String,
+        data: String,
+        format: qmp_schema::DataFormat,
+    ) -> Response {
+        match ringbuf_write(&device, &data, format) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn guest_agent_command(
+        &self,
+        command: String,
+        arguments: Option<qmp_schema::Any>,
+        timeout: Option<u64>,
+    ) -> Response {
+        match guest_agent_command(command, arguments, timeout) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn query_mem(&self) -> Response {
         self.mem_show();
         Response::create_empty_response()
@@ -1220,6 +1477,7 @@ impl DeviceInterface for LightMachine {
             direct,
             serial_num: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
             iops: None,
             queues: 1,
             boot_index: None,
@@ -1232,6 +1490,8 @@ impl DeviceInterface for LightMachine {
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            poll_us: 0,
+            max_inflight: 0,
         };
         if let Err(e) = config.check() {
             error!("{:?}", e);
@@ -1279,10 +1539,16 @@ impl DeviceInterface for LightMachine {
             vhost_type: None,
             vhost_fds: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
             queues: 2,
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            boot_index: None,
+            romfile: None,
+            io_uring: args.io_uring.unwrap_or_default(),
+            poll_us: 0,
+            notify_batch: DEFAULT_NOTIFY_BATCH,
         };
 
         if let Some(fds) = args.fds {
@@ -1402,16 +1668,32 @@ impl DeviceInterface for LightMachine {
 
 impl MigrateInterface for LightMachine {
     fn migrate(&self, uri: String) -> Response {
+        #[cfg(target_arch = "x86_64")]
+        if let Err(e) = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .cpu_config
+            .check_migratable()
+        {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
         match parse_incoming_uri(&uri) {
             Ok((MigrateMode::File, path)) => migration::snapshot(path),
-            Ok((MigrateMode::Unix, _)) | Ok((MigrateMode::Tcp, _)) => {
-                Response::create_error_response(
-                    qmp_schema::QmpErrorClass::GenericError(
-                        "MicroVM does not support migration".to_string(),
-                    ),
-                    None,
-                )
-            }
+            Ok((MigrateMode::Unix, _))
+            | Ok((MigrateMode::Tcp, _))
+            | Ok((MigrateMode::Exec, _))
+            | Ok((MigrateMode::ExecOneway, _))
+            | Ok((MigrateMode::Fd, _)) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "MicroVM does not support migration".to_string(),
+                ),
+                None,
+            ),
             _ => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
                 None,
@@ -1728,24 +2010,3 @@ impl device_tree::CompileFDT for LightMachine {
         Ok(())
     }
 }
-
-/// Trace descriptions for some devices at stratovirt startup.
-fn trace_cpu_topo(cpu_topo: &CPUTopology) {
-    util::ftrace!(trace_cpu_topo, "{:#?}", cpu_topo);
-}
-
-fn trace_sysbus(sysbus: &SysBus) {
-    util::ftrace!(trace_sysbus, "{:?}", sysbus);
-}
-
-fn trace_replaceable_info(replaceable_info: &MmioReplaceableInfo) {
-    util::ftrace!(trace_replaceable_info, "{:?}", replaceable_info);
-}
-
-fn trace_vm_state(vm_state: &Arc<(Mutex<KvmVmState>, Condvar)>) {
-    util::ftrace!(trace_vm_state, "{:#?}", vm_state);
-}
-
-fn trace_mmio_replaceable_config(config: &MmioReplaceableConfig) {
-    util::ftrace!(trace_mmio_replaceable_config, "{:#?}", config);
-}
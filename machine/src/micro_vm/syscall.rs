@@ -12,7 +12,9 @@
 
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
-use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
+use util::tap::{
+    TUNGETFEATURES, TUNGETIFF, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ,
+};
 use virtio::VhostKern::*;
 
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/futex.h
@@ -42,10 +44,10 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 53 syscalls
-/// * x86_64-unknown-musl: 52 syscalls
-/// * aarch64-unknown-gnu: 51 syscalls
-/// * aarch64-unknown-musl: 51 syscalls
+/// * x86_64-unknown-gnu: 54 syscalls
+/// * x86_64-unknown-musl: 53 syscalls
+/// * aarch64-unknown-gnu: 52 syscalls
+/// * aarch64-unknown-musl: 52 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -99,6 +101,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_tgkill),
         BpfRule::new(libc::SYS_gettid),
+        BpfRule::new(libc::SYS_sched_setaffinity),
+        BpfRule::new(libc::SYS_set_mempolicy),
         BpfRule::new(libc::SYS_getpid),
         BpfRule::new(libc::SYS_fstat),
         BpfRule::new(libc::SYS_pread64),
@@ -129,6 +133,18 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_mprotect),
         BpfRule::new(libc::SYS_ppoll),
         BpfRule::new(libc::SYS_connect),
+        BpfRule::new(libc::SYS_sendto),
+        BpfRule::new(libc::SYS_getdents64),
+        BpfRule::new(libc::SYS_nanosleep),
+        BpfRule::new(libc::SYS_clock_nanosleep),
+        #[cfg(any(target_env = "musl", target_arch = "aarch64"))]
+        BpfRule::new(libc::SYS_clone),
+        #[cfg(all(target_env = "gnu", target_arch = "x86_64"))]
+        BpfRule::new(libc::SYS_clone3),
+        #[cfg(target_env = "gnu")]
+        BpfRule::new(libc::SYS_set_robust_list),
+        BpfRule::new(libc::SYS_prctl),
+        BpfRule::new(libc::SYS_setsockopt),
         madvise_rule(),
     ]
 }
@@ -155,14 +171,25 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_SET_FEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_SET_MEM_TABLE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_NET_SET_BACKEND() as u32)
+        .add_constraint(
+            SeccompCmpOpt::Eq,
+            1,
+            VHOST_SET_VRING_BUSYLOOP_TIMEOUT() as u32,
+        )
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETFEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETIFF() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETIFF() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_API_VERSION() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MP_STATE() as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_STATS_FD() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_RESET_DIRTY_RINGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_CREATE_GUEST_MEMFD() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_USER_MEMORY_REGION2() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MEMORY_ATTRIBUTES() as u32);
     ioctl_arch_allow_list(bpf_rule)
 }
 
@@ -171,6 +198,7 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
     bpf_rule
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_PIT2() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_CLOCK() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CLOCK() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_IRQCHIP() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REGS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SREGS() as u32)
@@ -180,6 +208,20 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_XCRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MSRS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SUPPORTED_CPUID() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CPUID2() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_TSC_KHZ() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MP_STATE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_SREGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_REGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_XSAVE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_XCRS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_DEBUGREGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_LAPIC() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MSRS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_VCPU_EVENTS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GUEST_DEBUG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_TRANSLATE() as u32)
 }
 
 #[cfg(target_arch = "aarch64")]
@@ -188,6 +230,8 @@ fn ioctl_arch_allow_list(bpf_rule: BpfRule) -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_ONE_REG() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DEVICE_ATTR() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REG_LIST() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_ARM_VCPU_INIT() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_ONE_REG() as u32)
 }
 
 fn madvise_rule() -> BpfRule {
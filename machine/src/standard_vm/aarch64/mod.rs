@@ -25,8 +25,8 @@ use kvm_bindings::{KVM_ARM_IRQ_TYPE_SHIFT, KVM_ARM_IRQ_TYPE_SPI};
 use log::{error, info, warn};
 use vmm_sys_util::eventfd::EventFd;
 
-use super::{AcpiBuilder, Result as StdResult, StdMachineOps};
-use crate::MachineOps;
+use super::{add_fwcfg_user_files, AcpiBuilder, Result as StdResult, StdMachineOps};
+use crate::{apply_dtb_overlays, MachineOps};
 use acpi::{
     processor_append_priv_res, AcpiGicCpu, AcpiGicDistributor, AcpiGicIts, AcpiGicRedistributor,
     AcpiSratGiccAffinity, AcpiSratMemoryAffinity, AcpiTable, AmlBuilder, AmlDevice, AmlInteger,
@@ -39,18 +39,23 @@ use acpi::{
     ARCH_GIC_MAINT_IRQ, ID_MAPPING_ENTRY_SIZE, INTERRUPT_PPIS_COUNT, INTERRUPT_SGIS_COUNT,
     ROOT_COMPLEX_ENTRY_SIZE,
 };
-use address_space::{AddressSpace, GuestAddress, Region};
+use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
 use cpu::{
     CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology, CpuTopology, CPU, PMU_INTR, PPI_BASE,
+    PVTIME_VCPU_SIZE,
 };
-use devices::acpi::ged::{acpi_dsdt_add_power_button, Ged};
+use devices::acpi::cpu_hotplug::{CpuHotplug, CPU_HOTPLUG_REG_SIZE};
+use devices::acpi::ged::{acpi_dsdt_add_power_button, AcpiEvent, Ged};
+use devices::acpi::memory_hotplug::MemHotplug;
 use devices::acpi::power::PowerDev;
 #[cfg(feature = "ramfb")]
 use devices::legacy::Ramfb;
 use devices::legacy::{
     FwCfgEntryType, FwCfgMem, FwCfgOps, LegacyError as DevErrorKind, PFlash, PL011, PL031,
 };
+use devices::misc::pvpanic::{PvPanic, PvPanicReqs};
+use devices::misc::vmcoreinfo::VmCoreInfo;
 use devices::pci::{InterruptHandler, PciDevOps, PciHost, PciIntxState};
 use devices::sysbus::{SysBus, SysBusDevType, SysRes};
 use devices::{ICGICConfig, ICGICv3Config, InterruptController, GIC_IRQ_INTERNAL, GIC_IRQ_MAX};
@@ -61,8 +66,9 @@ use machine_manager::config::ShutdownAction;
 #[cfg(feature = "gtk")]
 use machine_manager::config::UiContext;
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    parse_incoming_uri, parse_pvpanic, parse_vmcoreinfo, BootIndexInfo, BootSource, DriveFile,
+    Incoming, MachineMemConfig, MigrateMode, NumaNode, NumaNodes, PFlashConfig, RtcConfig,
+    SerialConfig, VmConfig, G,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -78,9 +84,11 @@ use syscall::syscall_whitelist;
 use ui::gtk::gtk_display_init;
 #[cfg(feature = "vnc")]
 use ui::vnc::vnc_init;
+use util::boot_time::{BootStage, BootStageTimer};
 use util::byte_code::ByteCode;
 use util::device_tree::{self, CompileFDT, FdtBuilder};
 use util::loop_context::EventLoopManager;
+use util::num_ops::round_up;
 use util::seccomp::BpfRule;
 use util::set_termi_canon_mode;
 
@@ -95,6 +103,10 @@ pub enum LayoutEntryType {
     FwCfg,
     Ged,
     PowerDev,
+    MemHotplug,
+    CpuHotplug,
+    PvPanic,
+    PvTime,
     Mmio,
     PcieMmio,
     PciePio,
@@ -115,6 +127,10 @@ pub const MEM_LAYOUT: &[(u64, u64)] = &[
     (0x0902_0000, 0x0000_0018),    // FwCfg
     (0x0908_0000, 0x0000_0004),    // Ged
     (0x0909_0000, 0x0000_1000),    // PowerDev
+    (0x090A_0000, 0x0000_0018),    // MemHotplug
+    (0x090B_0000, 0x0000_0008),    // CpuHotplug
+    (0x090C_0000, 0x0000_0002),    // PvPanic
+    (0x090D_0000, 0x0001_0000),    // PvTime
     (0x0A00_0000, 0x0000_0200),    // Mmio
     (0x1000_0000, 0x2EFF_0000),    // PcieMmio
     (0x3EFF_0000, 0x0001_0000),    // PciePio
@@ -142,6 +158,8 @@ pub struct StdMachine {
     cpu_topo: CpuTopology,
     /// `vCPU` devices.
     cpus: Vec<Arc<CPU>>,
+    /// Parked `vCPU` devices, which can be hot-added.
+    parked_cpus: Vec<Arc<CPU>>,
     cpu_features: CPUFeatures,
     // Interrupt controller device.
     irq_chip: Option<Arc<InterruptController>>,
@@ -179,6 +197,16 @@ pub struct StdMachine {
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
     /// machine all backend memory region tree
     machine_ram: Arc<Region>,
+    /// Generic event device.
+    ged: Option<Arc<Mutex<Ged>>>,
+    /// Memory hotplug controller.
+    mem_hotplug: Option<Arc<Mutex<MemHotplug>>>,
+    /// CPU hotplug controller.
+    cpu_hotplug: Option<Arc<Mutex<CpuHotplug>>>,
+    /// Emulated AC adapter and battery.
+    power_dev: Option<Arc<Mutex<PowerDev>>>,
+    /// vmcoreinfo device.
+    vmcoreinfo: Option<Arc<Mutex<VmCoreInfo>>>,
 }
 
 impl StdMachine {
@@ -212,6 +240,7 @@ impl StdMachine {
         Ok(StdMachine {
             cpu_topo,
             cpus: Vec::new(),
+            parked_cpus: Vec::new(),
             cpu_features: (&vm_config.machine_config.cpu_config).into(),
             irq_chip: None,
             sys_mem: sys_mem.clone(),
@@ -256,6 +285,11 @@ impl StdMachine {
                 u64::max_value(),
                 "MachineRam",
             )),
+            ged: None,
+            mem_hotplug: None,
+            cpu_hotplug: None,
+            power_dev: None,
+            vmcoreinfo: None,
         })
     }
 
@@ -307,6 +341,59 @@ impl StdMachine {
         Ok(())
     }
 
+    fn add_mem_hotplug_device(&mut self, mem_config: &MachineMemConfig) -> Result<()> {
+        if mem_config.slots == 0 {
+            return Ok(());
+        }
+
+        let ram_end = MEM_LAYOUT[LayoutEntryType::Mem as usize].0 + mem_config.mem_size;
+        // SAFETY: memory size has been checked, it won't overflow.
+        let base = round_up(ram_end, G).unwrap();
+        let size = mem_config.max_mem - mem_config.mem_size;
+        if base + size
+            > MEM_LAYOUT[LayoutEntryType::Mem as usize].0
+                + MEM_LAYOUT[LayoutEntryType::Mem as usize].1
+        {
+            bail!("Max memory size 0x{:x} is too large", mem_config.max_mem);
+        }
+
+        let mem_hotplug = MemHotplug::new((base, size), mem_config.slots)
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::MemHotplug as usize].0,
+                MEM_LAYOUT[LayoutEntryType::MemHotplug as usize].1,
+            )
+            .with_context(|| "Failed to realize memory hotplug controller")?;
+        self.mem_hotplug = Some(mem_hotplug);
+        Ok(())
+    }
+
+    fn add_cpu_hotplug_device(&mut self, vm: Arc<Mutex<StdMachine>>) -> Result<()> {
+        if self.cpu_topo.max_cpus <= self.cpu_topo.nrcpus {
+            return Ok(());
+        }
+
+        let eject_req = Arc::new(
+            EventFd::new(libc::EFD_NONBLOCK)
+                .with_context(|| MachineError::InitEventFdErr("cpu_eject_req".to_string()))?,
+        );
+        let cpu_hotplug = CpuHotplug::new(
+            self.cpu_topo.nrcpus,
+            self.cpu_topo.max_cpus,
+            eject_req.clone(),
+        )
+        .realize(
+            &mut self.sysbus,
+            MEM_LAYOUT[LayoutEntryType::CpuHotplug as usize].0,
+            CPU_HOTPLUG_REG_SIZE,
+        )
+        .with_context(|| "Failed to realize CPU hotplug controller")?;
+        self.register_cpu_eject_event(eject_req, vm)
+            .with_context(|| "Fail to register CPU eject event")?;
+        self.cpu_hotplug = Some(cpu_hotplug);
+        Ok(())
+    }
+
     fn build_pptt_cores(&self, pptt: &mut AcpiTable, cluster_offset: u32, uid: &mut u32) {
         for core in 0..self.cpu_topo.cores {
             let mut priv_resources = vec![0; 3];
@@ -370,13 +457,54 @@ impl StdMachine {
     fn cpu_post_init(&self, vcpu_cfg: &Option<CPUFeatures>) -> Result<()> {
         let features = vcpu_cfg.unwrap_or_default();
         if features.pmu {
-            for cpu in self.cpus.iter() {
+            for cpu in self.cpus.iter().chain(self.parked_cpus.iter()) {
                 cpu.init_pmu()?;
             }
         }
         Ok(())
     }
 
+    /// Allocate PV time region for steal time, and assign the stolen time structure
+    /// of each vCPU in it. Stolen time structures of registered vCPUs are assigned by
+    /// their migrated states on destination.
+    fn init_pvtime(&self, migrate: MigrateMode) -> Result<()> {
+        if !self.cpu_features.steal_time {
+            return Ok(());
+        }
+        if !self.cpus[0].pvtime_supported() {
+            warn!("Steal time is not supported by kvm");
+            return Ok(());
+        }
+
+        let (base, size) = MEM_LAYOUT[LayoutEntryType::PvTime as usize];
+        // Memory regions are restored from snapshot file.
+        if migrate != MigrateMode::File {
+            let host_mmap = Arc::new(HostMemMapping::new(
+                GuestAddress(base),
+                None,
+                size,
+                None,
+                false,
+                false,
+                false,
+            )?);
+            self.sys_mem
+                .root()
+                .add_subregion(Region::init_ram_region(host_mmap, "PvTime"), base)
+                .with_context(|| "Failed to add PV time region")?;
+        }
+
+        let cpus: Vec<&Arc<CPU>> = if migrate == MigrateMode::Unknown {
+            self.cpus.iter().chain(self.parked_cpus.iter()).collect()
+        } else {
+            self.parked_cpus.iter().collect()
+        };
+        for cpu in cpus {
+            cpu.init_pvtime(base + u64::from(cpu.id()) * PVTIME_VCPU_SIZE)?;
+        }
+        Ok(())
+    }
+
     pub fn mem_show(&self) {
         self.sys_mem.memspace_show();
         let machine_ram = self.get_vm_ram();
@@ -410,9 +538,15 @@ impl StdMachineOps for StdMachine {
     }
 
     fn add_fwcfg_device(&mut self, nr_cpus: u8) -> StdResult<Option<Arc<Mutex<dyn FwCfgOps>>>> {
-        if self.vm_config.lock().unwrap().pflashs.is_none() {
+        let vm_config = self.vm_config.lock().unwrap();
+        if vm_config.pflashs.is_none() {
+            if !vm_config.fw_cfg.is_empty() {
+                bail!("fw_cfg file requires UEFI to boot, please add pflash devices");
+            }
             return Ok(None);
         }
+        let fw_cfg_files = vm_config.fw_cfg.clone();
+        drop(vm_config);
 
         let mut fwcfg = FwCfgMem::new(self.sys_mem.clone());
         fwcfg
@@ -439,6 +573,7 @@ impl StdMachineOps for StdMachine {
         fwcfg
             .add_file_entry("bios-geometry", bios_geometry)
             .with_context(|| DevErrorKind::AddEntryErr("bios-geometry".to_string()))?;
+        add_fwcfg_user_files(&mut fwcfg, &fw_cfg_files)?;
 
         let fwcfg_dev = FwCfgMem::realize(
             fwcfg,
@@ -460,9 +595,35 @@ impl StdMachineOps for StdMachine {
         &self.cpus
     }
 
+    fn get_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>> {
+        &mut self.cpus
+    }
+
+    fn get_parked_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>> {
+        &mut self.parked_cpus
+    }
+
+    fn get_cpu_hotplug(&self) -> Option<Arc<Mutex<CpuHotplug>>> {
+        self.cpu_hotplug.clone()
+    }
+
+    fn notify_cpu_hotplug(&self) -> StdResult<()> {
+        let ged = self.ged.as_ref().with_context(|| "Ged is not realized")?;
+        ged.lock().unwrap().inject_acpi_event(AcpiEvent::CpuHotplug);
+        Ok(())
+    }
+
     fn get_guest_numa(&self) -> &Option<NumaNodes> {
         &self.numa_nodes
     }
+
+    fn get_power_dev(&self) -> Option<Arc<Mutex<PowerDev>>> {
+        self.power_dev.clone()
+    }
+
+    fn get_vmcoreinfo(&self) -> Option<Arc<Mutex<VmCoreInfo>>> {
+        self.vmcoreinfo.clone()
+    }
 }
 
 impl MachineOps for StdMachine {
@@ -531,6 +692,7 @@ impl MachineOps for StdMachine {
     }
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig> {
+        let _timer = BootStageTimer::new(BootStage::FirmwareLoad, None);
         let mut boot_source = self.boot_source.lock().unwrap();
         let initrd = boot_source.initrd.as_ref().map(|b| b.initrd_file.clone());
 
@@ -552,8 +714,8 @@ impl MachineOps for StdMachine {
         })
     }
 
-    fn add_rtc_device(&mut self) -> Result<()> {
-        let rtc = PL031::default();
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig) -> Result<()> {
+        let rtc = PL031::new(rtc_config);
         PL031::realize(
             rtc,
             &mut self.sysbus,
@@ -566,24 +728,33 @@ impl MachineOps for StdMachine {
 
     fn add_ged_device(&mut self) -> Result<()> {
         let battery_present = self.vm_config.lock().unwrap().machine_config.battery;
-        let ged = Ged::default();
+        let mut ged = Ged::default();
+        if self.mem_hotplug.is_some() {
+            ged.enable_mem_hotplug();
+        }
+        if self.cpu_hotplug.is_some() {
+            ged.enable_cpu_hotplug();
+        }
         let ged_dev = ged
             .realize(
                 &mut self.sysbus,
-                self.power_button.clone(),
+                Some(self.power_button.clone()),
                 battery_present,
                 MEM_LAYOUT[LayoutEntryType::Ged as usize].0,
                 MEM_LAYOUT[LayoutEntryType::Ged as usize].1,
             )
             .with_context(|| "Failed to realize Ged")?;
+        self.ged = Some(ged_dev.clone());
         if battery_present {
             let pdev = PowerDev::new(ged_dev);
-            pdev.realize(
-                &mut self.sysbus,
-                MEM_LAYOUT[LayoutEntryType::PowerDev as usize].0,
-                MEM_LAYOUT[LayoutEntryType::PowerDev as usize].1,
-            )
-            .with_context(|| "Failed to realize PowerDev")?;
+            let pdev = pdev
+                .realize(
+                    &mut self.sysbus,
+                    MEM_LAYOUT[LayoutEntryType::PowerDev as usize].0,
+                    MEM_LAYOUT[LayoutEntryType::PowerDev as usize].1,
+                )
+                .with_context(|| "Failed to realize PowerDev")?;
+            self.power_dev = Some(pdev);
         }
         Ok(())
     }
@@ -604,6 +775,30 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_pvpanic_reqs(&self) -> Result<PvPanicReqs> {
+        Ok(PvPanicReqs {
+            pause_req: self.pause_req.clone(),
+            shutdown_req: self.shutdown_req.clone(),
+        })
+    }
+
+    fn add_pvpanic(&mut self, vm_config: &VmConfig, cfg_args: &str) -> Result<()> {
+        let config =
+            parse_pvpanic(cfg_args).with_context(|| "Failed to parse cmdline for pvpanic")?;
+        let pvpanic = PvPanic::new(
+            &config,
+            vm_config.machine_config.panic_action,
+            self.get_pvpanic_reqs()?,
+        );
+        pvpanic
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::PvPanic as usize].0,
+                MEM_LAYOUT[LayoutEntryType::PvPanic as usize].1,
+            )
+            .with_context(|| "Failed to realize pvpanic device")
+    }
+
     fn syscall_whitelist(&self) -> Vec<BpfRule> {
         syscall_whitelist()
     }
@@ -632,11 +827,18 @@ impl MachineOps for StdMachine {
             .with_context(|| "Fail to register resume event")?;
 
         locked_vm.numa_nodes = locked_vm.add_numa_nodes(vm_config)?;
-        locked_vm.init_memory(
-            &vm_config.machine_config.mem_config,
-            &locked_vm.sys_mem,
-            nr_cpus,
-        )?;
+        KVM_FDS
+            .load()
+            .init_confidential_guest()
+            .with_context(|| "Failed to init confidential guest")?;
+        locked_vm.init_memory(&vm_config.machine_config.mem_config, &locked_vm.sys_mem)?;
+        KVM_FDS
+            .load()
+            .enable_dirty_ring(vm_config.machine_config.dirty_ring_size)
+            .with_context(|| "Failed to enable kvm dirty ring")?;
+        KVM_FDS
+            .load()
+            .set_clock_advance_on_pause(vm_config.machine_config.clock_advance_on_pause);
 
         locked_vm
             .init_pci_host()
@@ -655,19 +857,29 @@ impl MachineOps for StdMachine {
             None
         };
 
-        locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
+        let sys_mem = locked_vm.sys_mem.clone();
+        let max_cpus = vm_config.machine_config.max_cpus;
+        let mut cpus = <Self as MachineOps>::init_vcpu(
             vm.clone(),
+            sys_mem,
             nr_cpus,
+            max_cpus,
+            &vm_config.machine_config.vcpu_affinity,
             &CPUTopology::new(),
             &boot_config,
             &cpu_config,
-        )?);
+        )?;
+        locked_vm.parked_cpus = cpus.split_off(nr_cpus as usize);
+        locked_vm.cpus.extend(cpus);
 
-        // Interrupt Controller Chip init
-        locked_vm.init_interrupt_controller(u64::from(nr_cpus))?;
+        // Interrupt Controller Chip init, all the possible vCPUs are created before it.
+        locked_vm.init_interrupt_controller(u64::from(max_cpus))?;
 
         locked_vm.cpu_post_init(&cpu_config)?;
+        locked_vm.init_pvtime(migrate.0)?;
 
+        locked_vm.add_mem_hotplug_device(&vm_config.machine_config.mem_config)?;
+        locked_vm.add_cpu_hotplug_device(vm.clone())?;
         locked_vm
             .add_devices(vm_config)
             .with_context(|| "Failed to add devices")?;
@@ -677,7 +889,8 @@ impl MachineOps for StdMachine {
             locked_vm
                 .generate_fdt_node(&mut fdt_helper)
                 .with_context(|| MachineError::GenFdtErr)?;
-            let fdt_vec = fdt_helper.finish()?;
+            let fdt_vec =
+                apply_dtb_overlays(fdt_helper.finish()?, &vm_config.boot_source.dtb_overlays)?;
             locked_vm.dtb_vec = fdt_vec.clone();
             locked_vm
                 .sys_mem
@@ -716,6 +929,11 @@ impl MachineOps for StdMachine {
             locked_vm.shutdown_req.clone(),
         );
 
+        KVM_FDS
+            .load()
+            .launch_confidential_guest()
+            .with_context(|| "Failed to launch confidential guest")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         if let Err(e) = MigrationManager::set_status(MigrationStatus::Setup) {
@@ -726,19 +944,18 @@ impl MachineOps for StdMachine {
 
     fn add_pflash_device(&mut self, configs: &[PFlashConfig]) -> Result<()> {
         use super::error::StandardVmError as StdErrorKind;
-        let mut configs_vec = configs.to_vec();
-        configs_vec.sort_by_key(|c| c.unit);
         let sector_len: u32 = 1024 * 256;
         let mut flash_base: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].0;
         let flash_size: u64 = MEM_LAYOUT[LayoutEntryType::Flash as usize].1 / 2;
         for i in 0..=1 {
-            let (fd, read_only) = if i < configs_vec.len() {
-                let path = &configs_vec[i].path_on_host;
-                let read_only = configs_vec[i].read_only;
-                let fd = self.fetch_drive_file(path)?;
-                (Some(fd), read_only)
-            } else {
-                (None, false)
+            // Unit 0 holds firmware code and unit 1 holds variables, each one is mapped
+            // to its own half of flash region even if the other one is absent.
+            let (fd, read_only) = match configs.iter().find(|c| c.unit == i) {
+                Some(config) => (
+                    Some(self.fetch_drive_file(&config.path_on_host)?),
+                    config.read_only,
+                ),
+                None => (None, false),
             };
 
             let pflash = PFlash::new(flash_size, &fd, sector_len, 4, 2, read_only)
@@ -792,6 +1009,23 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn add_vmcoreinfo(&mut self, cfg_args: &str) -> Result<()> {
+        if self.vmcoreinfo.is_some() {
+            bail!("Only one vmcoreinfo device is supported");
+        }
+        parse_vmcoreinfo(cfg_args)?;
+        let fwcfg_dev = self.get_fwcfg_dev().with_context(|| {
+            "vmcoreinfo device must be used UEFI to boot, please add pflash devices"
+        })?;
+        let vmcoreinfo = VmCoreInfo::new();
+        vmcoreinfo.setup(&fwcfg_dev)?;
+        let vmcoreinfo = vmcoreinfo
+            .realize(&mut self.sysbus)
+            .with_context(|| "Failed to realize vmcoreinfo device")?;
+        self.vmcoreinfo = Some(vmcoreinfo);
+        Ok(())
+    }
+
     fn run(&self, paused: bool) -> Result<()> {
         self.vm_start(paused, &self.cpus, &mut self.vm_state.0.lock().unwrap())
     }
@@ -832,6 +1066,16 @@ impl MachineOps for StdMachine {
         &self.numa_nodes
     }
 
+    fn get_mem_hotplug(&self) -> Option<Arc<Mutex<MemHotplug>>> {
+        self.mem_hotplug.clone()
+    }
+
+    fn notify_mem_hotplug(&self) -> Result<()> {
+        let ged = self.ged.as_ref().with_context(|| "Ged is not realized")?;
+        ged.lock().unwrap().inject_acpi_event(AcpiEvent::MemHotplug);
+        Ok(())
+    }
+
     fn get_fwcfg_dev(&mut self) -> Option<Arc<Mutex<dyn FwCfgOps>>> {
         if let Some(fwcfg_dev) = &self.fwcfg_dev {
             return Some(fwcfg_dev.clone());
@@ -1055,14 +1299,16 @@ impl AcpiBuilder for StdMachine {
     ) -> super::Result<u64> {
         let mut dsdt = AcpiTable::new(*b"DSDT", 2, *b"STRATO", *b"VIRTDSDT", 1);
 
-        // 1. CPU info.
-        let cpus_count = self.cpus.len() as u64;
+        // 1. CPU info, which is built by CPU hotplug controller if it exists.
         let mut sb_scope = AmlScope::new("\\_SB");
-        for cpu_id in 0..cpus_count {
-            let mut dev = AmlDevice::new(format!("C{:03}", cpu_id).as_str());
-            dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
-            dev.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id)));
-            sb_scope.append_child(dev);
+        if self.cpu_hotplug.is_none() {
+            let cpus_count = self.cpus.len() as u64;
+            for cpu_id in 0..cpus_count {
+                let mut dev = AmlDevice::new(format!("C{:03}", cpu_id).as_str());
+                dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
+                dev.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id)));
+                sb_scope.append_child(dev);
+            }
         }
 
         // 2. Create pci host bridge node.
@@ -1096,17 +1342,24 @@ impl AcpiBuilder for StdMachine {
         gic_dist.gic_version = 3;
         madt.append_child(&gic_dist.aml_bytes());
 
-        // 2. GIC CPU.
-        let cpus_count = self.cpus.len() as u64;
-        for cpu_index in 0..cpus_count {
-            let mpidr = self.cpus[cpu_index as usize].arch().lock().unwrap().mpidr();
+        // 2. GIC CPU, parked vCPUs are online capable so that they can be hot-added.
+        let mut cpus: Vec<&Arc<CPU>> = self.cpus.iter().chain(self.parked_cpus.iter()).collect();
+        cpus.sort_by_key(|cpu| cpu.id());
+        for cpu in cpus {
+            let cpu_index = cpu.id();
+            let mpidr = cpu.arch().lock().unwrap().mpidr();
             let mpidr_mask: u64 = 0x007f_ffff;
             let mut gic_cpu = AcpiGicCpu::default();
             gic_cpu.type_id = ACPI_MADT_GENERIC_CPU_INTERFACE;
             gic_cpu.length = 80;
             gic_cpu.cpu_interface_num = cpu_index as u32;
             gic_cpu.processor_uid = cpu_index as u32;
-            gic_cpu.flags = 5;
+            gic_cpu.flags = if self.cpu_topo.get_mask(cpu_index as usize) == 1 {
+                5
+            } else {
+                // Flags: online capable.
+                0xc
+            };
             gic_cpu.mpidr = mpidr & mpidr_mask;
             gic_cpu.vgic_interrupt = ARCH_GIC_MAINT_IRQ + INTERRUPT_PPIS_COUNT;
             gic_cpu.perf_interrupt = PMU_INTR + PPI_BASE;
@@ -1194,6 +1447,11 @@ impl AcpiBuilder for StdMachine {
             self.build_srat_cpu(*id, node, &mut srat);
             next_base = self.build_srat_mem(next_base, *id, node, &mut srat);
         }
+        if let Some(mem_hotplug) = &self.mem_hotplug {
+            // SAFETY: the SRAT table is created only when numa node configured.
+            let last_node = *self.numa_nodes.as_ref().unwrap().keys().last().unwrap();
+            self.build_srat_hotplug_mem(mem_hotplug, last_node, &mut srat);
+        }
 
         let srat_begin = StdMachine::add_table_to_loader(acpi_data, loader, &srat)
             .with_context(|| "Fail to add SRAT table to loader")?;
@@ -1313,6 +1571,9 @@ impl MigrateInterface for StdMachine {
             Ok((MigrateMode::File, path)) => migration::snapshot(path),
             Ok((MigrateMode::Unix, path)) => migration::migration_unix_mode(path),
             Ok((MigrateMode::Tcp, path)) => migration::migration_tcp_mode(path),
+            Ok((MigrateMode::Exec, cmd)) => migration::migration_exec_mode(cmd, false),
+            Ok((MigrateMode::ExecOneway, cmd)) => migration::migration_exec_mode(cmd, true),
+            Ok((MigrateMode::Fd, fd_name)) => migration::migration_fd_mode(fd_name),
             _ => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
                 None,
@@ -1327,6 +1588,25 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn colo_lost_heartbeat(&self) -> Response {
+        migration::colo_lost_heartbeat()
+    }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::set_migrate_parameters(args)
+    }
+
+    fn migrate_set_capabilities(
+        &self,
+        capabilities: Vec<qmp_schema::MigrateCapabilities>,
+    ) -> Response {
+        migration::set_migrate_capabilities(capabilities)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -1536,6 +1816,22 @@ fn generate_rtc_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<
     Ok(())
 }
 
+/// Function that helps to generate pvpanic device node in device-tree.
+///
+/// # Arguments
+///
+/// * `fdt` - Flatted device-tree blob where pvpanic node will be filled into.
+/// * `res` - Device resource info of pvpanic device.
+fn generate_pvpanic_device_node(fdt: &mut FdtBuilder, res: &SysRes) -> util::Result<()> {
+    let node = format!("pvpanic@{:x}", res.region_base);
+    let pvpanic_node_dep = fdt.begin_node(&node)?;
+    fdt.set_property_string("compatible", "qemu,pvpanic-mmio")?;
+    fdt.set_property_array_u64("reg", &[res.region_base, res.region_size])?;
+    fdt.end_node(pvpanic_node_dep)?;
+
+    Ok(())
+}
+
 fn generate_pmu_node(fdt: &mut FdtBuilder) -> util::Result<()> {
     let node = "pmu";
     let pmu_node_dep = fdt.begin_node(node)?;
@@ -1577,29 +1873,44 @@ impl CompileFDTHelper for StdMachine {
         fdt.set_property_u32("#address-cells", 0x02)?;
         fdt.set_property_u32("#size-cells", 0x0)?;
 
-        // Generate CPU topology
+        // Generate CPU topology, only the CPUs present at boot have nodes, so the
+        // topology nodes without any present CPU are skipped.
+        let nr_cpus = u32::from(self.cpu_topo.nrcpus);
+        let threads = u32::from(self.cpu_topo.threads);
+        let cores = u32::from(self.cpu_topo.cores);
+        let clusters = u32::from(self.cpu_topo.clusters);
         let cpu_map_node_dep = fdt.begin_node("cpu-map")?;
-        for socket in 0..self.cpu_topo.sockets {
+        for socket in 0..u32::from(self.cpu_topo.sockets) {
+            let socket_base = threads * cores * clusters * socket;
+            if socket_base >= nr_cpus {
+                break;
+            }
             let sock_name = format!("cluster{}", socket);
             let sock_node_dep = fdt.begin_node(&sock_name)?;
-            for cluster in 0..self.cpu_topo.clusters {
+            for cluster in 0..clusters {
+                let cluster_base = socket_base + threads * cores * cluster;
+                if cluster_base >= nr_cpus {
+                    break;
+                }
                 let clster = format!("cluster{}", cluster);
                 let cluster_node_dep = fdt.begin_node(&clster)?;
 
-                for core in 0..self.cpu_topo.cores {
+                for core in 0..cores {
+                    let core_base = cluster_base + threads * core;
+                    if core_base >= nr_cpus {
+                        break;
+                    }
                     let core_name = format!("core{}", core);
                     let core_node_dep = fdt.begin_node(&core_name)?;
 
-                    for thread in 0..self.cpu_topo.threads {
+                    for thread in 0..threads {
+                        let vcpuid = core_base + thread;
+                        if vcpuid >= nr_cpus {
+                            break;
+                        }
                         let thread_name = format!("thread{}", thread);
                         let thread_node_dep = fdt.begin_node(&thread_name)?;
-                        let vcpuid = self.cpu_topo.threads * self.cpu_topo.cores * cluster
-                            + self.cpu_topo.threads * core
-                            + thread;
-                        fdt.set_property_u32(
-                            "cpu",
-                            u32::from(vcpuid) + device_tree::CPU_PHANDLE_START,
-                        )?;
+                        fdt.set_property_u32("cpu", vcpuid + device_tree::CPU_PHANDLE_START)?;
                         fdt.end_node(thread_node_dep)?;
                     }
                     fdt.end_node(core_node_dep)?;
@@ -1729,6 +2040,9 @@ impl CompileFDTHelper for StdMachine {
                     // SAFETY: Legacy devices guarantee is not empty.
                     generate_fwcfg_device_node(fdt, &locked_dev.sysbusdev_base().res)?;
                 }
+                SysBusDevType::PvPanic => {
+                    generate_pvpanic_device_node(fdt, &locked_dev.sysbusdev_base().res)?;
+                }
                 _ => (),
             }
         }
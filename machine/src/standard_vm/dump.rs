@@ -0,0 +1,289 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::size_of;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+use address_space::{AddressSpace, GuestAddress, RegionType};
+use devices::misc::vmcoreinfo::VmCoreInfoNote;
+use util::byte_code::ByteCode;
+use util::num_ops::round_up;
+
+const ELFMAG: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+
+const ET_CORE: u16 = 4;
+#[cfg(target_arch = "x86_64")]
+const EM_MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const EM_MACHINE: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RWX: u32 = 0x7;
+
+/// Upper limit of the vmcoreinfo note, the note of Linux is less than one page.
+const VMCOREINFO_MAX_SIZE: u32 = 1 << 20;
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64Header {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+impl ByteCode for Elf64Header {}
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64ProgHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+impl ByteCode for Elf64ProgHeader {}
+
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Elf64NoteHeader {
+    namesz: u32,
+    descsz: u32,
+    type_: u32,
+}
+
+impl ByteCode for Elf64NoteHeader {}
+
+/// Get the guest RAM mapped in the root of system memory, as (start address, size).
+fn guest_ram_ranges(sys_mem: &Arc<AddressSpace>) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = sys_mem
+        .root()
+        .subregions()
+        .iter()
+        .filter(|r| matches!(r.region_type(), RegionType::Ram | RegionType::Alias))
+        .filter_map(|r| r.start_addr().map(|addr| (addr.raw_value(), r.size())))
+        .filter(|(_, size)| *size != 0)
+        .collect();
+    ranges.sort_unstable();
+    ranges
+}
+
+/// Read the vmcoreinfo ELF note registered by guest kernel. The note is checked
+/// against the registered size, as its content is controlled by guest.
+fn read_vmcoreinfo_note(sys_mem: &Arc<AddressSpace>, note: VmCoreInfoNote) -> Result<Vec<u8>> {
+    let hdr_size = size_of::<Elf64NoteHeader>() as u32;
+    if note.size < hdr_size || note.size > VMCOREINFO_MAX_SIZE {
+        bail!("Invalid size {} of vmcoreinfo note", note.size);
+    }
+    let mut data = vec![0_u8; note.size as usize];
+    sys_mem
+        .read(
+            &mut data.as_mut_slice(),
+            GuestAddress(note.paddr),
+            u64::from(note.size),
+        )
+        .with_context(|| format!("Failed to read vmcoreinfo note at 0x{:x}", note.paddr))?;
+
+    let hdr = Elf64NoteHeader::from_bytes(&data[..hdr_size as usize]).unwrap();
+    let real_size = round_up(u64::from(hdr.namesz), 4)
+        .zip(round_up(u64::from(hdr.descsz), 4))
+        .map(|(name, desc)| u64::from(hdr_size) + name + desc)
+        .filter(|size| *size <= u64::from(note.size))
+        .with_context(|| "Vmcoreinfo note exceeds the registered size")?;
+    data.truncate(real_size as usize);
+    Ok(data)
+}
+
+/// Dump guest memory to an ELF core file, which can be analyzed by crash or drgn
+/// with the vmcoreinfo note. The VM should be paused during the dump.
+///
+/// # Arguments
+///
+/// * `sys_mem` - System memory of VM.
+/// * `vmcoreinfo` - The vmcoreinfo note registered by guest kernel.
+/// * `path` - Path of the dump file.
+pub(crate) fn dump_guest_memory(
+    sys_mem: &Arc<AddressSpace>,
+    vmcoreinfo: Option<VmCoreInfoNote>,
+    path: &str,
+) -> Result<()> {
+    let note = match vmcoreinfo.map(|n| read_vmcoreinfo_note(sys_mem, n)) {
+        Some(Ok(note)) => Some(note),
+        Some(Err(e)) => {
+            warn!("Vmcoreinfo note is ignored in dump: {:?}", e);
+            None
+        }
+        None => None,
+    };
+    let ranges = guest_ram_ranges(sys_mem);
+
+    let phnum = ranges.len() + usize::from(note.is_some());
+    let mut offset = (size_of::<Elf64Header>() + phnum * size_of::<Elf64ProgHeader>()) as u64;
+    let mut ident = [0_u8; 16];
+    ident[..4].copy_from_slice(&ELFMAG);
+    ident[4] = ELFCLASS64;
+    ident[5] = ELFDATA2LSB;
+    ident[6] = EV_CURRENT;
+    let ehdr = Elf64Header {
+        e_ident: ident,
+        e_type: ET_CORE,
+        e_machine: EM_MACHINE,
+        e_version: u32::from(EV_CURRENT),
+        e_phoff: size_of::<Elf64Header>() as u64,
+        e_ehsize: size_of::<Elf64Header>() as u16,
+        e_phentsize: size_of::<Elf64ProgHeader>() as u16,
+        e_phnum: phnum as u16,
+        ..Default::default()
+    };
+
+    let mut phdrs = Vec::with_capacity(phnum);
+    if let Some(note) = &note {
+        phdrs.push(Elf64ProgHeader {
+            p_type: PT_NOTE,
+            p_offset: offset,
+            p_filesz: note.len() as u64,
+            p_memsz: note.len() as u64,
+            ..Default::default()
+        });
+        offset += note.len() as u64;
+    }
+    for (start, size) in ranges.iter() {
+        phdrs.push(Elf64ProgHeader {
+            p_type: PT_LOAD,
+            p_flags: PF_RWX,
+            p_offset: offset,
+            p_paddr: *start,
+            p_filesz: *size,
+            p_memsz: *size,
+            ..Default::default()
+        });
+        offset += size;
+    }
+
+    let file = File::create(path).with_context(|| format!("Failed to create {}", path))?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(ehdr.as_bytes())?;
+    for phdr in phdrs.iter() {
+        writer.write_all(phdr.as_bytes())?;
+    }
+    if let Some(note) = &note {
+        writer.write_all(note)?;
+    }
+    for (start, size) in ranges.iter() {
+        sys_mem
+            .read(&mut writer, GuestAddress(*start), *size)
+            .with_context(|| format!("Failed to dump guest memory at 0x{:x}", start))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write {}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use address_space::{HostMemMapping, Region};
+
+    #[test]
+    fn test_dump_guest_memory() {
+        let root = Region::init_container_region(1 << 36, "root");
+        let sys_mem = AddressSpace::new(root, "sys_mem").unwrap();
+        let host_mmap = Arc::new(
+            HostMemMapping::new(GuestAddress(0), None, 0x2000, None, false, false, false).unwrap(),
+        );
+        sys_mem
+            .root()
+            .add_subregion(Region::init_ram_region(host_mmap, "ram"), 0)
+            .unwrap();
+
+        // Note "VMCOREINFO" with a 6-byte description, 32 bytes in total.
+        let mut note = Elf64NoteHeader {
+            namesz: 11,
+            descsz: 6,
+            type_: 0,
+        }
+        .as_bytes()
+        .to_vec();
+        note.extend_from_slice(b"VMCOREINFO\0\0OSREL=\0\0");
+        sys_mem
+            .write(
+                &mut note.as_slice(),
+                GuestAddress(0x1000),
+                note.len() as u64,
+            )
+            .unwrap();
+
+        let path = "/tmp/test_dump_guest_memory.elf";
+        let vmcoreinfo = VmCoreInfoNote {
+            paddr: 0x1000,
+            size: 0x100,
+        };
+        dump_guest_memory(&sys_mem, Some(vmcoreinfo), path).unwrap();
+
+        let mut dump = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut dump).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let ehdr = Elf64Header::from_bytes(&dump[..64]).unwrap();
+        assert_eq!(ehdr.e_ident[..4], ELFMAG);
+        assert_eq!({ ehdr.e_type }, ET_CORE);
+        assert_eq!({ ehdr.e_phnum }, 2);
+        let note_phdr = Elf64ProgHeader::from_bytes(&dump[64..120]).unwrap();
+        assert_eq!({ note_phdr.p_type }, PT_NOTE);
+        assert_eq!({ note_phdr.p_filesz }, 32);
+        let load_phdr = Elf64ProgHeader::from_bytes(&dump[120..176]).unwrap();
+        assert_eq!({ load_phdr.p_type }, PT_LOAD);
+        assert_eq!({ load_phdr.p_paddr }, 0);
+        assert_eq!({ load_phdr.p_filesz }, 0x2000);
+        assert_eq!(dump.len(), 176 + 32 + 0x2000);
+        assert_eq!(dump[176..208], note[..32]);
+        assert_eq!(dump[208 + 0x1000..208 + 0x1000 + 32], note[..32]);
+
+        // Note exceeding the registered size is ignored.
+        let vmcoreinfo = VmCoreInfoNote {
+            paddr: 0x1000,
+            size: 0x10,
+        };
+        dump_guest_memory(&sys_mem, Some(vmcoreinfo), path).unwrap();
+        let mut dump = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut dump).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let ehdr = Elf64Header::from_bytes(&dump[..64]).unwrap();
+        assert_eq!({ ehdr.e_phnum }, 1);
+    }
+}
@@ -12,6 +12,7 @@
 
 #[cfg(target_arch = "aarch64")]
 pub mod aarch64;
+mod dump;
 pub mod error;
 
 #[cfg(target_arch = "x86_64")]
@@ -31,9 +32,12 @@ use std::os::unix::io::RawFd;
 use std::os::unix::prelude::AsRawFd;
 use std::rc::Rc;
 use std::string::String;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::Duration;
 
 use anyhow::{bail, Context};
+#[cfg(target_arch = "x86_64")]
+use base64::{engine::general_purpose::STANDARD, Engine};
 use log::error;
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
@@ -47,36 +51,56 @@ use aarch64::{LayoutEntryType, MEM_LAYOUT};
 #[cfg(target_arch = "x86_64")]
 use acpi::AcpiGenericAddress;
 use acpi::{
-    AcpiRsdp, AcpiTable, AmlBuilder, TableLoader, ACPI_RSDP_FILE, ACPI_TABLE_FILE,
-    ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
+    AcpiRsdp, AcpiSratMemoryAffinity, AcpiTable, AmlBuilder, TableLoader, ACPI_RSDP_FILE,
+    ACPI_TABLE_FILE, ACPI_TABLE_LOADER_FILE, TABLE_CHECKSUM_OFFSET,
 };
 use address_space::{
     AddressRange, FileBackend, GuestAddress, HostMemMapping, Region, RegionIoEventFd, RegionOps,
 };
 use block_backend::{qcow2::QCOW2_LIST, BlockStatus};
-use cpu::{CpuTopology, CPU};
+use chardev_backend::guest_agent::guest_agent_command;
+use chardev_backend::ringbuf::{ringbuf_read, ringbuf_write};
+use cpu::{CPUInterface, CpuLifecycleState, CpuTopology, CPU};
+use devices::acpi::cpu_hotplug::CpuHotplug;
+use devices::acpi::memory_hotplug::MemHotplug;
+use devices::acpi::power::PowerDev;
 use devices::legacy::FwCfgOps;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::HPET_CAPABILITIES;
+#[cfg(target_arch = "x86_64")]
+use devices::misc::tpm::TPM_CRB_CTRL_AREA_OFFSET;
+use devices::misc::vmcoreinfo::VmCoreInfo;
 use devices::pci::hotplug::{handle_plug, handle_unplug_pci_request};
 use devices::pci::PciBus;
+#[cfg(target_arch = "x86_64")]
+use hypervisor::kvm::{SevSnpGuest, SevState, KVM_FDS, SEV_MNONCE_LEN};
 #[cfg(feature = "usb_camera")]
 use machine_manager::config::get_cameradev_config;
 use machine_manager::config::{
-    get_chardev_config, get_netdev_config, get_pci_df, memory_unit_conversion, BlkDevConfig,
-    ChardevType, ConfigCheck, DiskFormat, DriveConfig, ExBool, NetworkInterfaceConfig, NumaNode,
-    NumaNodes, PciBdf, ScsiCntlrConfig, VmConfig, DEFAULT_VIRTQUEUE_SIZE, M, MAX_VIRTIO_QUEUE,
+    get_chardev_config, get_netdev_config, get_pci_df, memory_unit_conversion,
+    split_queue_iothreads, BlkDevConfig, ChardevType, ConfigCheck, DiskFormat, DriveConfig, ExBool,
+    FwCfgConfig, NetworkInterfaceConfig, NumaNode, NumaNodes, PciBdf, ScsiCntlrConfig, VmConfig,
+    DEFAULT_NOTIFY_BATCH, DEFAULT_VIRTQUEUE_SIZE, M, MAX_VIRTIO_QUEUE,
 };
+use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
 use machine_manager::machine::MachineLifecycle;
 use machine_manager::machine::{DeviceInterface, KvmVmState};
 use machine_manager::qmp::qmp_schema::{BlockDevAddArgument, UpdateRegionArgument};
 use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_response::Response, qmp_schema};
 use migration::MigrationManager;
-use ui::input::{key_event, point_event};
+use ui::input::{
+    get_pointer_state, key_event, point_event, qcode_to_keycode, ABS_MAX, INPUT_BUTTON_LEFT,
+    INPUT_BUTTON_MIDDLE, INPUT_BUTTON_RIGHT, INPUT_BUTTON_WHEEL_DOWN, INPUT_BUTTON_WHEEL_LEFT,
+    INPUT_BUTTON_WHEEL_RIGHT, INPUT_BUTTON_WHEEL_UP,
+};
 #[cfg(feature = "vnc")]
 use ui::vnc::qmp_query_vnc;
 use util::aio::{AioEngine, WriteZeroesState};
 use util::byte_code::ByteCode;
 use util::loop_context::{read_fd, EventNotifier, NotifierCallback, NotifierOperation};
+#[cfg(feature = "virtio_gpu")]
+use virtio::resize_gpu_output;
 use virtio::{
     qmp_balloon, qmp_query_balloon, Block, BlockState,
     ScsiCntlr::{scsi_cntlr_create_scsi_bus, ScsiCntlr},
@@ -160,6 +184,20 @@ trait StdMachineOps: AcpiBuilder {
             xsdt_entries.push(slit_addr);
         }
 
+        #[cfg(target_arch = "x86_64")]
+        if self.has_tpm() {
+            let tpm2_addr = Self::build_tpm2_table(&acpi_tables, &mut loader)
+                .with_context(|| "Failed to build ACPI TPM2 table")?;
+            xsdt_entries.push(tpm2_addr);
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        if self.has_hpet() {
+            let hpet_addr = Self::build_hpet_table(&acpi_tables, &mut loader)
+                .with_context(|| "Failed to build ACPI HPET table")?;
+            xsdt_entries.push(hpet_addr);
+        }
+
         #[cfg(target_arch = "aarch64")]
         {
             let pptt_addr = self
@@ -196,8 +234,41 @@ trait StdMachineOps: AcpiBuilder {
 
     fn get_cpus(&self) -> &Vec<Arc<CPU>>;
 
+    fn get_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>>;
+
+    /// Get the parked vCPUs, which are created at boot and can be hot-added.
+    fn get_parked_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>>;
+
+    /// Get the CPU hotplug controller, which exists if `maxcpus` is larger than `cpus`.
+    fn get_cpu_hotplug(&self) -> Option<Arc<Mutex<CpuHotplug>>>;
+
+    /// Notify guest to scan possible CPUs after hot-adding or hot-removing a vCPU.
+    fn notify_cpu_hotplug(&self) -> MachineResult<()>;
+
     fn get_guest_numa(&self) -> &Option<NumaNodes>;
 
+    /// Get the emulated AC adapter and battery, which exists if `-battery` is set.
+    fn get_power_dev(&self) -> Option<Arc<Mutex<PowerDev>>>;
+
+    /// Get the vmcoreinfo device, which exists if `-device vmcoreinfo` is set.
+    fn get_vmcoreinfo(&self) -> Option<Arc<Mutex<VmCoreInfo>>>;
+
+    /// Check whether TPM device is configured, which is described by ACPI TPM2 table.
+    fn has_tpm(&self) -> bool {
+        false
+    }
+
+    /// Check whether HPET is emulated, which is described by ACPI HPET table.
+    fn has_hpet(&self) -> bool {
+        false
+    }
+
+    /// Get the SEV-SNP guest, which exists if `confidential-guest-support` is set.
+    #[cfg(target_arch = "x86_64")]
+    fn get_sev_guest(&self) -> Option<Arc<SevSnpGuest>> {
+        None
+    }
+
     /// Register event notifier for reset of standard machine.
     ///
     /// # Arguments
@@ -230,6 +301,38 @@ trait StdMachineOps: AcpiBuilder {
         Ok(())
     }
 
+    /// Register event notifier for CPU ejection of standard machine.
+    ///
+    /// # Arguments
+    ///
+    /// * `eject_req` - Eventfd of the CPU eject request.
+    /// * `clone_vm` - Reference of the StdMachine.
+    fn register_cpu_eject_event(
+        &self,
+        eject_req: Arc<EventFd>,
+        clone_vm: Arc<Mutex<StdMachine>>,
+    ) -> MachineResult<()> {
+        let eject_req_fd = eject_req.as_raw_fd();
+        let eject_req_handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            read_fd(eject_req_fd);
+            if let Err(e) = clone_vm.lock().unwrap().handle_cpu_eject() {
+                error!("Fail to eject vcpu, {:?}", e);
+            }
+
+            None
+        });
+        let notifier = EventNotifier::new(
+            NotifierOperation::AddShared,
+            eject_req_fd,
+            None,
+            EventSet::IN,
+            vec![eject_req_handler],
+        );
+        EventLoop::update_event(vec![notifier], None)
+            .with_context(|| "Failed to register event notifier.")?;
+        Ok(())
+    }
+
     fn register_pause_event(
         &self,
         pause_req: Arc<EventFd>,
@@ -311,6 +414,26 @@ trait StdMachineOps: AcpiBuilder {
     }
 }
 
+/// Add the files given by `-fw_cfg` to fw_cfg device, which are read-only for guest.
+///
+/// # Arguments
+///
+/// * `fwcfg` - FwCfg device.
+/// * `configs` - Configs of the fw_cfg files.
+fn add_fwcfg_user_files(fwcfg: &mut dyn FwCfgOps, configs: &[FwCfgConfig]) -> Result<()> {
+    for config in configs {
+        let data = match &config.file {
+            Some(file) => std::fs::read(file)
+                .with_context(|| format!("Failed to read fw_cfg file {}", file))?,
+            None => config.string.clone().unwrap_or_default().into_bytes(),
+        };
+        fwcfg
+            .add_file_callback_entry(&config.name, data, None, None, false)
+            .with_context(|| format!("Failed to add fw_cfg file {}", config.name))?;
+    }
+    Ok(())
+}
+
 /// Trait that helps to build ACPI tables.
 /// Standard machine struct should at least implement `build_dsdt_table`, `build_madt_table`
 /// and `build_mcfg_table` function.
@@ -649,6 +772,64 @@ trait AcpiBuilder {
         Ok(facs_begin as u64)
     }
 
+    /// Build ACPI TPM2 table, returns the offset of ACPI TPM2 table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    #[cfg(target_arch = "x86_64")]
+    fn build_tpm2_table(acpi_data: &Arc<Mutex<Vec<u8>>>, loader: &mut TableLoader) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let mut tpm2 = AcpiTable::new(*b"TPM2", 4, *b"STRATO", *b"VIRTTPM2", 1);
+        // Platform Class: client.
+        tpm2.append_child(0_u16.as_bytes());
+        // Reserved
+        tpm2.append_child(0_u16.as_bytes());
+        // Address of CRB Control Area
+        let ctrl_area_addr = MEM_LAYOUT[LayoutEntryType::Tpm as usize].0 + TPM_CRB_CTRL_AREA_OFFSET;
+        tpm2.append_child(ctrl_area_addr.as_bytes());
+        // Start Method: Command Response Buffer.
+        tpm2.append_child(7_u32.as_bytes());
+        // Start Method Specific Parameters
+        tpm2.append_child(&[0_u8; 12]);
+
+        let tpm2_begin = Self::add_table_to_loader(acpi_data, loader, &tpm2)
+            .with_context(|| "Fail to add TPM2 table to loader")?;
+        Ok(tpm2_begin)
+    }
+
+    /// Build ACPI HPET table, returns the offset of ACPI HPET table in `acpi_data`.
+    ///
+    /// # Arguments
+    ///
+    /// `acpi_data` - Bytes streams that ACPI tables converts to.
+    /// `loader` - ACPI table loader.
+    #[cfg(target_arch = "x86_64")]
+    fn build_hpet_table(acpi_data: &Arc<Mutex<Vec<u8>>>, loader: &mut TableLoader) -> Result<u64>
+    where
+        Self: Sized,
+    {
+        let mut hpet = AcpiTable::new(*b"HPET", 1, *b"STRATO", *b"VIRTHPET", 1);
+        // Event Timer Block ID, the lower 32 bits of capabilities register.
+        hpet.append_child((HPET_CAPABILITIES as u32).as_bytes());
+        // Base Address: system memory, register bit width, offset and access size.
+        hpet.append_child(&[0_u8, 0, 0, 0]);
+        hpet.append_child(MEM_LAYOUT[LayoutEntryType::Hpet as usize].0.as_bytes());
+        // HPET Number
+        hpet.append_child(&[0_u8]);
+        // Main Counter Minimum Clock Tick in Periodic Mode
+        hpet.append_child(0x80_u16.as_bytes());
+        // Page Protection: no guarantee.
+        hpet.append_child(&[0_u8]);
+
+        let hpet_begin = Self::add_table_to_loader(acpi_data, loader, &hpet)
+            .with_context(|| "Fail to add HPET table to loader")?;
+        Ok(hpet_begin)
+    }
+
     /// Build ACPI SRAT CPU table.
     ///  # Arguments
     ///
@@ -672,6 +853,34 @@ trait AcpiBuilder {
         srat: &mut AcpiTable,
     ) -> u64;
 
+    /// Build ACPI SRAT memory table of hotplug memory window.
+    ///  # Arguments
+    ///
+    /// `mem_hotplug` - The memory hotplug controller.
+    /// `proximity_domain` - The proximity domain.
+    /// `srat` - The SRAT table.
+    fn build_srat_hotplug_mem(
+        &self,
+        mem_hotplug: &Arc<Mutex<MemHotplug>>,
+        proximity_domain: u32,
+        srat: &mut AcpiTable,
+    ) {
+        let (base_addr, range_length) = mem_hotplug.lock().unwrap().window();
+        srat.append_child(
+            &AcpiSratMemoryAffinity {
+                type_id: 1,
+                length: size_of::<AcpiSratMemoryAffinity>() as u8,
+                proximity_domain,
+                base_addr,
+                range_length,
+                // Enabled and hot pluggable.
+                flags: 3,
+                ..Default::default()
+            }
+            .aml_bytes(),
+        );
+    }
+
     /// Build ACPI SRAT table, returns the offset of ACPI SRAT table in `acpi_data`.
     ///
     /// # Arguments
@@ -699,13 +908,14 @@ trait AcpiBuilder {
         let mut slit = AcpiTable::new(*b"SLIT", 1, *b"STRATO", *b"VIRTSLIT", 1);
         slit.append_child((numa_nodes.len() as u64).as_bytes());
 
-        let existing_nodes: Vec<u32> = numa_nodes.keys().cloned().collect();
-        for (id, node) in numa_nodes.iter().enumerate() {
-            let distances = &node.1.distances;
-            for i in existing_nodes.iter() {
-                let dist: u8 = if id as u32 == *i {
+        for (id, node) in numa_nodes.iter() {
+            for (i, peer) in numa_nodes.iter() {
+                // Distance is symmetric if only one direction is configured.
+                let dist: u8 = if id == i {
                     10
-                } else if let Some(distance) = distances.get(i) {
+                } else if let Some(distance) = node.distances.get(i) {
+                    *distance
+                } else if let Some(distance) = peer.distances.get(id) {
                     *distance
                 } else {
                     20
@@ -829,13 +1039,15 @@ impl StdMachine {
         let mut locked_vmconfig = vm_config.lock().unwrap();
         let nr_cpus = locked_vmconfig.machine_config.nr_cpus;
         let blk = if let Some(conf) = locked_vmconfig.drives.get(drive) {
+            let (iothread, queue_iothreads) = split_queue_iothreads(args.iothread.clone());
             let dev = BlkDevConfig {
                 id: args.id.clone(),
                 path_on_host: conf.path_on_host.clone(),
                 read_only: conf.read_only,
                 direct: conf.direct,
                 serial_num: args.serial_num.clone(),
-                iothread: args.iothread.clone(),
+                iothread,
+                queue_iothreads,
                 iops: conf.iops,
                 queues: args.queues.unwrap_or_else(|| {
                     VirtioPciDevice::virtio_pci_auto_queues_num(0, nr_cpus, MAX_VIRTIO_QUEUE)
@@ -850,6 +1062,8 @@ impl StdMachine {
                 format: conf.format,
                 l2_cache_size: conf.l2_cache_size,
                 refcount_cache_size: conf.refcount_cache_size,
+                poll_us: args.poll_us.unwrap_or_default(),
+                max_inflight: args.max_inflight.unwrap_or_default(),
             };
             dev.check()?;
             dev
@@ -867,7 +1081,15 @@ impl StdMachine {
         let blk_id = blk.id.clone();
         let blk = Arc::new(Mutex::new(Block::new(blk, self.get_drive_files())));
         let pci_dev = self
-            .add_virtio_pci_device(&args.id, pci_bdf, blk.clone(), multifunction, false)
+            .add_virtio_pci_device(
+                &args.id,
+                pci_bdf,
+                blk.clone(),
+                multifunction,
+                false,
+                None,
+                None,
+            )
             .with_context(|| "Failed to add virtio pci block device")?;
 
         if let Some(bootindex) = args.boot_index {
@@ -896,6 +1118,7 @@ impl StdMachine {
             }) as u32,
             boot_prefix: None,
             queue_size,
+            ..Default::default()
         };
         dev_cfg.check()?;
 
@@ -905,7 +1128,15 @@ impl StdMachine {
         scsi_cntlr_create_scsi_bus(&bus_name, &device)?;
 
         let virtio_pci_dev = self
-            .add_virtio_pci_device(&args.id, pci_bdf, device.clone(), multifunction, false)
+            .add_virtio_pci_device(
+                &args.id,
+                pci_bdf,
+                device.clone(),
+                multifunction,
+                false,
+                None,
+                None,
+            )
             .with_context(|| "Failed to add virtio scsi controller")?;
         device.lock().unwrap().config.boot_prefix = virtio_pci_dev.lock().unwrap().get_dev_path();
 
@@ -942,7 +1173,7 @@ impl StdMachine {
         drop(locked_vmconfig);
 
         let blk = Arc::new(Mutex::new(VhostUser::Block::new(&dev, self.get_sys_mem())));
-        self.add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction, true)
+        self.add_virtio_pci_device(&args.id, pci_bdf, blk, multifunction, true, None, None)
             .with_context(|| "Failed to add vhost user blk pci device")?;
 
         Ok(())
@@ -993,6 +1224,7 @@ impl StdMachine {
                     .get_socket_path(&locked_vmconfig, (&chardev).to_string())
                     .with_context(|| "Failed to get socket path")?;
             }
+            let (iothread, queue_iothreads) = split_queue_iothreads(args.iothread.clone());
             let dev = NetworkInterfaceConfig {
                 id: args.id.clone(),
                 host_dev_name: conf.ifname.clone(),
@@ -1000,11 +1232,17 @@ impl StdMachine {
                 tap_fds: conf.tap_fds.clone(),
                 vhost_type: conf.vhost_type.clone(),
                 vhost_fds: conf.vhost_fds.clone(),
-                iothread: args.iothread.clone(),
+                iothread,
+                queue_iothreads,
                 queues: conf.queues,
                 mq: conf.queues > 2,
                 socket_path,
                 queue_size,
+                boot_index: args.boot_index,
+                romfile: args.romfile.clone(),
+                io_uring: conf.io_uring,
+                poll_us: conf.poll_us,
+                notify_batch: args.notify_batch.unwrap_or(DEFAULT_NOTIFY_BATCH),
             };
             dev.check()?;
             dev
@@ -1014,21 +1252,63 @@ impl StdMachine {
         locked_vmconfig.add_net_device_config(args);
         drop(locked_vmconfig);
 
-        if dev.vhost_type.is_some() {
+        if let Some(bootindex) = args.boot_index {
+            self.check_bootindex(bootindex)
+                .with_context(|| "Fail to add virtio pci net device for invalid bootindex")?;
+        }
+
+        let pci_dev = if dev.vhost_type.is_some() {
             let net: Arc<Mutex<dyn VirtioDevice>> =
                 if dev.vhost_type == Some(String::from("vhost-kernel")) {
-                    Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())))
+                    let net = Arc::new(Mutex::new(VhostKern::Net::new(&dev, self.get_sys_mem())));
+                    MigrationManager::register_device_instance(
+                        VhostKern::VhostNetState::descriptor(),
+                        net.clone(),
+                        &dev.id,
+                    );
+                    net
                 } else {
-                    Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())))
+                    let net = Arc::new(Mutex::new(VhostUser::Net::new(&dev, self.get_sys_mem())));
+                    MigrationManager::register_device_instance(
+                        VhostUser::VhostUserNetState::descriptor(),
+                        net.clone(),
+                        &dev.id,
+                    );
+                    net
                 };
-            self.add_virtio_pci_device(&args.id, pci_bdf, net, multifunction, true)
-                .with_context(|| "Failed to add vhost-kernel/vhost-user net device")?;
+            self.add_virtio_pci_device(
+                &args.id,
+                pci_bdf,
+                net,
+                multifunction,
+                true,
+                dev.romfile.as_deref(),
+                None,
+            )
+            .with_context(|| "Failed to add vhost-kernel/vhost-user net device")?
         } else {
             let net_id = dev.id.clone();
+            let romfile = dev.romfile.clone();
             let net = Arc::new(Mutex::new(virtio::Net::new(dev)));
-            self.add_virtio_pci_device(&args.id, pci_bdf, net.clone(), multifunction, false)
+            let pci_dev = self
+                .add_virtio_pci_device(
+                    &args.id,
+                    pci_bdf,
+                    net.clone(),
+                    multifunction,
+                    false,
+                    romfile.as_deref(),
+                    None,
+                )
                 .with_context(|| "Failed to add virtio net device")?;
             MigrationManager::register_device_instance(VirtioNetState::descriptor(), net, &net_id);
+            pci_dev
+        };
+
+        if let Some(bootindex) = args.boot_index {
+            if let Some(dev_path) = pci_dev.lock().unwrap().get_dev_path() {
+                self.add_bootindex_devices(bootindex, &dev_path, &args.id);
+            }
         }
 
         Ok(())
@@ -1090,6 +1370,122 @@ impl StdMachine {
         Ok(())
     }
 
+    fn plug_virtio_serial_port(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let is_console = args.driver == "virtconsole";
+        let chardev = args.chardev.as_ref().with_context(|| "Chardev not set")?;
+        let vm_config = self.get_vm_config();
+        let mut locked_vmconfig = vm_config.lock().unwrap();
+        let serial_id = locked_vmconfig
+            .virtio_serial
+            .as_ref()
+            .map(|serial| serial.id.clone())
+            .with_context(|| "No virtio serial device specified")?;
+        if let Some(bus) = &args.bus {
+            if *bus != serial_id {
+                bail!("Virtio serial device {} not found", bus);
+            }
+        }
+
+        let mut cfg_args = format!("{},id={},chardev={}", args.driver, args.id, chardev);
+        if let Some(nr) = args.nr {
+            cfg_args = format!("{},nr={}", cfg_args, nr);
+        }
+        self.add_virtio_serial_port(&mut locked_vmconfig, &cfg_args, is_console)
+    }
+
+    fn plug_pc_dimm(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        #[cfg(target_arch = "x86_64")]
+        bail!("Memory hot-add of {} is not supported on x86_64", args.id);
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            let memdev = args.memdev.as_ref().with_context(|| "Memdev not set")?;
+            let mut cfg_args = format!("pc-dimm,id={},memdev={}", args.id, memdev);
+            if let Some(addr) = &args.addr {
+                cfg_args = format!("{},addr={}", cfg_args, addr);
+            }
+            if let Some(node) = args.node {
+                cfg_args = format!("{},node={}", cfg_args, node);
+            }
+
+            let vm_config = self.get_vm_config();
+            let mut locked_vmconfig = vm_config.lock().unwrap();
+            self.add_pc_dimm(&mut locked_vmconfig, &cfg_args, true)
+        }
+    }
+
+    fn plug_cpu(&mut self, args: &qmp_schema::DeviceAddArgument) -> Result<()> {
+        let cpu_hotplug = self
+            .get_cpu_hotplug()
+            .with_context(|| "CPU hot-add is not supported, maxcpus is equal to cpus")?;
+        let cpu_id = args.cpu_id.with_context(|| "cpu-id not set")?;
+        // Hot-added vCPU is started directly, and the virtual timer of vCPU on aarch64
+        // is only saved when VM is paused, so VM must be running.
+        if *self.get_vm_state().deref().0.lock().unwrap() != KvmVmState::Running {
+            bail!("CPU can only be hot-added when VM is running");
+        }
+        let index = self
+            .get_parked_cpus_mut()
+            .iter()
+            .position(|cpu| cpu.id() == cpu_id)
+            .with_context(|| format!("CPU {} is not hotpluggable", cpu_id))?;
+
+        cpu_hotplug.lock().unwrap().plug(cpu_id, &args.id)?;
+        let cpu = self.get_parked_cpus_mut().remove(index);
+        if let Err(e) = cpu
+            .reset_to_boot_state()
+            .and_then(|_| CPU::start(cpu.clone(), Arc::new(Barrier::new(1)), false))
+        {
+            cpu_hotplug.lock().unwrap().unplug(cpu_id);
+            self.get_parked_cpus_mut().push(cpu);
+            return Err(e);
+        }
+
+        let cpus = self.get_cpus_mut();
+        let pos = cpus.partition_point(|c| c.id() < cpu_id);
+        cpus.insert(pos, cpu.clone());
+        self.get_cpu_topo().set_mask(cpu_id as usize, 1);
+        MigrationManager::register_cpu_instance(cpu::ArchCPU::descriptor(), cpu, cpu_id);
+        self.notify_cpu_hotplug()
+    }
+
+    /// Park the vCPUs ejected by guest, which can be hot-added again.
+    fn handle_cpu_eject(&mut self) -> Result<()> {
+        let cpu_hotplug = self
+            .get_cpu_hotplug()
+            .with_context(|| "CPU hotplug controller is not realized")?;
+        let ejected = cpu_hotplug.lock().unwrap().take_ejected();
+        for (cpu_id, dev_id) in ejected {
+            let cpus = self.get_cpus_mut();
+            let index = match cpus.iter().position(|cpu| cpu.id() == cpu_id) {
+                Some(index) => index,
+                None => continue,
+            };
+            let cpu = cpus[index].clone();
+            // Paused vcpu is resumed, so that its thread can exit after destroyed.
+            if *cpu.state().0.lock().unwrap() == CpuLifecycleState::Paused {
+                cpu.resume()
+                    .with_context(|| format!("Failed to resume vcpu{}", cpu_id))?;
+            }
+            cpu.destroy()
+                .with_context(|| format!("Failed to destroy vcpu{}", cpu_id))?;
+            cpu.join();
+            self.get_cpus_mut().remove(index);
+            self.get_parked_cpus_mut().push(cpu);
+            self.get_cpu_topo().set_mask(cpu_id as usize, 0);
+            MigrationManager::unregister_cpu_instance(cpu::ArchCPU::descriptor(), cpu_id);
+
+            if QmpChannel::is_connected() {
+                let cpu_del_event = qmp_schema::DeviceDeleted {
+                    device: Some(dev_id.clone()),
+                    path: format!("/machine/peripheral/{}", dev_id),
+                };
+                event!(DeviceDeleted; cpu_del_event);
+            }
+        }
+        Ok(())
+    }
+
     fn handle_unplug_usb_request(&mut self, id: String) -> Result<()> {
         let vm_config = self.get_vm_config();
         let mut locked_vmconfig = vm_config.lock().unwrap();
@@ -1138,6 +1534,29 @@ impl StdMachine {
             shutdown_req,
         );
     }
+
+    fn do_dump_guest_memory(&self, paging: bool, protocol: &str) -> Result<()> {
+        if paging {
+            bail!("Dump with paging is not supported");
+        }
+        let path = protocol
+            .strip_prefix("file:")
+            .with_context(|| format!("Unsupported dump protocol {:?}", protocol))?;
+
+        // Guest memory must not be changed during the dump.
+        let running = *self.get_vm_state().deref().0.lock().unwrap() == KvmVmState::Running;
+        if running && !self.pause() {
+            bail!("Failed to pause VM for dump");
+        }
+        let note = self
+            .get_vmcoreinfo()
+            .and_then(|dev| dev.lock().unwrap().get_note());
+        let ret = dump::dump_guest_memory(&self.sys_mem, note, path);
+        if running && !self.resume() {
+            bail!("Failed to resume VM after dump");
+        }
+        ret
+    }
 }
 
 impl DeviceInterface for StdMachine {
@@ -1164,10 +1583,10 @@ impl DeviceInterface for StdMachine {
     fn query_cpus(&self) -> Response {
         let mut cpu_vec: Vec<serde_json::Value> = Vec::new();
         let cpu_topo = self.get_cpu_topo();
-        let cpus = self.get_cpus();
-        for cpu_index in 0..cpu_topo.max_cpus {
+        for cpu in self.get_cpus().iter() {
+            let cpu_index = cpu.id();
             if cpu_topo.get_mask(cpu_index as usize) == 1 {
-                let thread_id = cpus[cpu_index as usize].tid();
+                let thread_id = cpu.tid();
                 let cpu_instance = cpu_topo.get_topo_instance_for_qmp(cpu_index as usize);
                 let cpu_common = qmp_schema::CpuInfoCommon {
                     current: true,
@@ -1201,7 +1620,29 @@ impl DeviceInterface for StdMachine {
     }
 
     fn query_hotpluggable_cpus(&self) -> Response {
-        Response::create_empty_response()
+        let mut hotplug_vec: Vec<serde_json::Value> = Vec::new();
+        #[cfg(target_arch = "x86_64")]
+        let cpu_type = String::from("host-x86-cpu");
+        #[cfg(target_arch = "aarch64")]
+        let cpu_type = String::from("host-aarch64-cpu");
+
+        let cpu_topo = self.get_cpu_topo();
+        for cpu_index in 0..cpu_topo.max_cpus {
+            let cpu_instance = cpu_topo.get_topo_instance_for_qmp(cpu_index as usize);
+            let qom_path = if cpu_topo.get_mask(cpu_index as usize) == 1 {
+                Some(String::from("/machine/unattached/device[") + &cpu_index.to_string() + "]")
+            } else {
+                None
+            };
+            let hotpluggable_cpu = qmp_schema::HotpluggableCPU {
+                type_: cpu_type.clone(),
+                vcpus_count: 1,
+                props: cpu_instance,
+                qom_path,
+            };
+            hotplug_vec.push(serde_json::to_value(hotpluggable_cpu).unwrap());
+        }
+        Response::create_response(hotplug_vec.into(), None)
     }
 
     fn balloon(&self, value: u64) -> Response {
@@ -1229,6 +1670,172 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn set_power_supply(&self, ac_online: Option<bool>, battery_level: Option<u8>) -> Response {
+        let pdev = match self.get_power_dev() {
+            Some(pdev) => pdev,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotActive(
+                        "No battery device has been activated".to_string(),
+                    ),
+                    None,
+                );
+            }
+        };
+        let mut locked_pdev = pdev.lock().unwrap();
+        match locked_pdev.set_emulated_status(ac_online, battery_level) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn query_power_supply(&self) -> Response {
+        if let Some(pdev) = self.get_power_dev() {
+            let (ac_online, battery_level, charging) = pdev.lock().unwrap().get_status();
+            let ret = qmp_schema::PowerSupplyInfo {
+                ac_online,
+                battery_level,
+                charging,
+            };
+            return Response::create_response(serde_json::to_value(ret).unwrap(), None);
+        }
+        Response::create_error_response(
+            qmp_schema::QmpErrorClass::DeviceNotActive(
+                "No battery device has been activated".to_string(),
+            ),
+            None,
+        )
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn query_sev(&self) -> Response {
+        let guest = match self.get_sev_guest() {
+            Some(guest) => guest,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError("SEV is not enabled".to_string()),
+                    None,
+                )
+            }
+        };
+        let info = guest.info();
+        let state = match info.state {
+            SevState::Uninit => "uninit",
+            SevState::LaunchUpdate => "launch-update",
+            SevState::Running => "running",
+        };
+        let ret = qmp_schema::SevInfo {
+            enabled: true,
+            sev_type: "SEV-SNP".to_string(),
+            state: state.to_string(),
+            policy: info.policy,
+            host_data: info
+                .host_data
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            api_major: info.api_major,
+            api_minor: info.api_minor,
+            build_id: info.build_id,
+            tcb_version: info.tcb_version,
+        };
+        Response::create_response(serde_json::to_value(ret).unwrap(), None)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn query_sev_attestation_report(&self, mnonce: String) -> Response {
+        let report = self
+            .get_sev_guest()
+            .with_context(|| "SEV is not enabled")
+            .and_then(|guest| {
+                let nonce = STANDARD
+                    .decode(&mnonce)
+                    .with_context(|| "Invalid base64 mnonce")?;
+                if nonce.len() != SEV_MNONCE_LEN {
+                    bail!("The length of mnonce must be {} bytes", SEV_MNONCE_LEN);
+                }
+                let mut buf = [0_u8; SEV_MNONCE_LEN];
+                buf.copy_from_slice(&nonce);
+                guest.attestation_report(KVM_FDS.load().vm_fd.as_ref().unwrap(), &buf)
+            });
+        match report {
+            Ok(data) => {
+                let ret = qmp_schema::SevAttestationReport {
+                    data: STANDARD.encode(data),
+                };
+                Response::create_response(serde_json::to_value(ret).unwrap(), None)
+            }
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn dump_guest_memory(&self, paging: bool, protocol: String) -> Response {
+        match self.do_dump_guest_memory(paging, &protocol) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    #[cfg(feature = "virtio_gpu")]
+    fn display_resize(&self, output: u32, width: u32, height: u32) -> Response {
+        match resize_gpu_output(output, width, height) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_read(&self, device: String, size: u64, format: qmp_schema::DataFormat) -> Response {
+        match ringbuf_read(&device, size, format) {
+            Ok(data) => Response::create_response(serde_json::to_value(data).unwrap(), None),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn ringbuf_write(
+        &self,
+        device: String,
+        data: String,
+        format: qmp_schema::DataFormat,
+    ) -> Response {
+        match ringbuf_write(&device, &data, format) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn guest_agent_command(
+        &self,
+        command: String,
+        arguments: Option<qmp_schema::Any>,
+        timeout: Option<u64>,
+    ) -> Response {
+        match guest_agent_command(command, arguments, timeout) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
     fn query_mem(&self) -> Response {
         self.mem_show();
         Response::create_empty_response()
@@ -1247,6 +1854,10 @@ impl DeviceInterface for StdMachine {
         )
     }
 
+    fn query_migrate_capabilities(&self) -> Response {
+        migration::query_migrate_capabilities()
+    }
+
     fn device_add(&mut self, args: Box<qmp_schema::DeviceAddArgument>) -> Response {
         if let Err(e) = self.check_device_id_existed(&args.id) {
             return Response::create_error_response(
@@ -1255,6 +1866,41 @@ impl DeviceInterface for StdMachine {
             );
         }
 
+        if args.driver == "host-x86-cpu" || args.driver == "host-aarch64-cpu" {
+            if let Err(e) = self.plug_cpu(args.as_ref()) {
+                error!("{:?}", e);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+            return Response::create_empty_response();
+        }
+
+        // DIMM is not a PCI device, its addr is the guest physical address.
+        if args.driver == "pc-dimm" {
+            if let Err(e) = self.plug_pc_dimm(args.as_ref()) {
+                error!("{:?}", e);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+            return Response::create_empty_response();
+        }
+
+        // Virtio serial port is attached to virtio serial device instead of PCI bus.
+        if args.driver == "virtconsole" || args.driver == "virtserialport" {
+            if let Err(e) = self.plug_virtio_serial_port(args.as_ref()) {
+                error!("{:?}", e);
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                    None,
+                );
+            }
+            return Response::create_empty_response();
+        }
+
         // Use args.bus.clone() and args.addr.clone() because args borrowed in the following
         // process.
         let pci_bdf = match get_device_bdf(args.bus.clone(), args.addr.clone()) {
@@ -1365,6 +2011,22 @@ impl DeviceInterface for StdMachine {
     }
 
     fn device_del(&mut self, device_id: String) -> Response {
+        if let Some(cpu_hotplug) = self.get_cpu_hotplug() {
+            let mut locked_hotplug = cpu_hotplug.lock().unwrap();
+            if let Some(cpu_id) = locked_hotplug.find_cpu(&device_id) {
+                // The vCPU is parked after guest ejects it.
+                let result = locked_hotplug.request_unplug(cpu_id);
+                drop(locked_hotplug);
+                return match result.and_then(|_| self.notify_cpu_hotplug()) {
+                    Ok(()) => Response::create_empty_response(),
+                    Err(e) => Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    ),
+                };
+            }
+        }
+
         let pci_host = match self.get_pci_host() {
             Ok(host) => host,
             Err(e) => {
@@ -1774,6 +2436,80 @@ impl DeviceInterface for StdMachine {
         }
     }
 
+    fn input_send_event(&self, events: Vec<qmp_schema::InputEvent>) -> Response {
+        match send_input_events(events) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn send_key(&self, keys: Vec<qmp_schema::KeyValue>, hold_time: Option<u64>) -> Response {
+        match send_keys(keys, hold_time) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    fn set_vcpu_affinity(&self, vcpu: u8, cpus: Vec<u64>) -> Response {
+        let cpu = match self.get_cpus().iter().find(|cpu| cpu.id() == vcpu) {
+            Some(cpu) => cpu,
+            None => {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::GenericError(format!("vCPU {} not found", vcpu)),
+                    None,
+                );
+            }
+        };
+        match cpu.set_affinity(cpus) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn vcpu_throttle(&self, percentage: u8) -> Response {
+        match cpu::set_throttle_percentage(self.get_cpus(), percentage) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn query_vcpu_exit_stats(&self, args: qmp_schema::QueryVcpuExitStatsArgument) -> Response {
+        let stats = cpu::query_exit_stats(self.get_cpus(), args.reset);
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
+    fn query_virtqueue_stats(&self, args: qmp_schema::QueryVirtqueueStatsArgument) -> Response {
+        let stats = virtio::query_queue_stats(args.device.as_deref(), args.reset);
+        if stats.is_empty() {
+            if let Some(device) = args.device {
+                return Response::create_error_response(
+                    qmp_schema::QmpErrorClass::DeviceNotFound(format!(
+                        "No virtqueue statistics for device {}",
+                        device
+                    )),
+                    None,
+                );
+            }
+        }
+        Response::create_response(serde_json::to_value(stats).unwrap(), None)
+    }
+
+    fn query_vmm_memory(&self) -> Response {
+        crate::query_vmm_memory()
+    }
+
     fn human_monitor_command(&self, args: qmp_schema::HumanMonitorCmdArgument) -> Response {
         let cmd_args: Vec<&str> = args.command_line.split(' ').collect();
         match cmd_args[0] {
@@ -2032,6 +2768,116 @@ fn parse_blockdev(args: &BlockDevAddArgument) -> Result<DriveConfig> {
     Ok(config)
 }
 
+/// Max keycode of PC keyboard, extended keys have the bit 0x80 set.
+const MAX_KEYCODE: u16 = 0xff;
+/// Default milliseconds to hold the keys sent by `send-key`.
+const DEFAULT_KEY_HOLD_TIME: u64 = 100;
+
+fn get_keycode(key: &qmp_schema::KeyValue) -> Result<u16> {
+    match key {
+        qmp_schema::KeyValue::Number(keycode) => {
+            if *keycode > MAX_KEYCODE {
+                bail!("Invalid keycode {}", keycode);
+            }
+            Ok(*keycode)
+        }
+        qmp_schema::KeyValue::Qcode(qcode) => {
+            qcode_to_keycode(qcode).with_context(|| format!("Unknown qcode {}", qcode))
+        }
+    }
+}
+
+/// Send input events to the active keyboard and pointer. Events are checked before
+/// sent, so that none of them is sent if any is invalid.
+fn send_input_events(events: Vec<qmp_schema::InputEvent>) -> Result<()> {
+    for event in events.iter() {
+        match event {
+            qmp_schema::InputEvent::Key(evt) => {
+                get_keycode(&evt.key)?;
+            }
+            qmp_schema::InputEvent::Abs(evt) => {
+                if !(0..=ABS_MAX as i64).contains(&evt.value) {
+                    bail!(
+                        "Invalid absolute value {}, should be in range [0, {}]",
+                        evt.value,
+                        ABS_MAX
+                    );
+                }
+            }
+            qmp_schema::InputEvent::Rel(_) => {
+                bail!("Relative pointer event is not supported, use abs event instead");
+            }
+            qmp_schema::InputEvent::Btn(_) => {}
+        }
+    }
+
+    // Pointer events only carry the changes, and moves are sent with the next button
+    // change or at the end.
+    let mut pointer = get_pointer_state();
+    let mut moved = false;
+    for event in events {
+        match event {
+            qmp_schema::InputEvent::Key(evt) => key_event(get_keycode(&evt.key)?, evt.down)?,
+            qmp_schema::InputEvent::Btn(evt) => {
+                let button = match evt.button {
+                    qmp_schema::InputButton::Left => INPUT_BUTTON_LEFT,
+                    qmp_schema::InputButton::Middle => INPUT_BUTTON_MIDDLE,
+                    qmp_schema::InputButton::Right => INPUT_BUTTON_RIGHT,
+                    qmp_schema::InputButton::WheelUp => INPUT_BUTTON_WHEEL_UP,
+                    qmp_schema::InputButton::WheelDown => INPUT_BUTTON_WHEEL_DOWN,
+                    qmp_schema::InputButton::WheelLeft => INPUT_BUTTON_WHEEL_LEFT,
+                    qmp_schema::InputButton::WheelRight => INPUT_BUTTON_WHEEL_RIGHT,
+                };
+                if evt.down {
+                    pointer.button |= button;
+                } else {
+                    pointer.button &= !button;
+                }
+                point_event(pointer.button, pointer.x, pointer.y)?;
+                moved = false;
+            }
+            qmp_schema::InputEvent::Abs(evt) => {
+                match evt.axis {
+                    qmp_schema::InputAxis::X => pointer.x = evt.value as u32,
+                    qmp_schema::InputAxis::Y => pointer.y = evt.value as u32,
+                }
+                moved = true;
+            }
+            qmp_schema::InputEvent::Rel(_) => {}
+        }
+    }
+    if moved {
+        point_event(pointer.button, pointer.x, pointer.y)?;
+    }
+    Ok(())
+}
+
+/// Press the keys in order, and release them in reverse order by timer of main loop
+/// after `hold_time` milliseconds.
+fn send_keys(keys: Vec<qmp_schema::KeyValue>, hold_time: Option<u64>) -> Result<()> {
+    let keycodes = keys.iter().map(get_keycode).collect::<Result<Vec<u16>>>()?;
+    if keycodes.is_empty() {
+        bail!("No key to send");
+    }
+    let ctx = EventLoop::get_ctx(None).with_context(|| "Main loop is not initialized")?;
+
+    for &keycode in keycodes.iter() {
+        key_event(keycode, true)?;
+    }
+    let release = Box::new(move || {
+        for &keycode in keycodes.iter().rev() {
+            if let Err(e) = key_event(keycode, false) {
+                error!("Failed to release key {}: {:?}", keycode, e);
+            }
+        }
+    });
+    ctx.timer_add(
+        release,
+        Duration::from_millis(hold_time.unwrap_or(DEFAULT_KEY_HOLD_TIME)),
+    );
+    Ok(())
+}
+
 fn send_input_event(key: String, value: String) -> Result<()> {
     match key.as_str() {
         "keyboard" => {
@@ -19,6 +19,7 @@ use std::collections::HashMap;
 use std::io::{Seek, SeekFrom};
 use std::mem::size_of;
 use std::ops::Deref;
+use std::os::unix::fs::FileExt;
 use std::sync::{Arc, Condvar, Mutex};
 
 use anyhow::{bail, Context, Result};
@@ -28,29 +29,44 @@ use vmm_sys_util::eventfd::EventFd;
 
 use self::ich9_lpc::SLEEP_CTRL_OFFSET;
 use super::error::StandardVmError;
-use super::{AcpiBuilder, StdMachineOps};
+use super::{add_fwcfg_user_files, AcpiBuilder, StdMachineOps};
 use crate::error::MachineError;
-use crate::{vm_state, MachineOps};
+use crate::{gdbstub, vm_state, MachineOps};
 use acpi::{
     AcpiIoApic, AcpiLocalApic, AcpiSratMemoryAffinity, AcpiSratProcessorAffinity, AcpiTable,
-    AmlBuilder, AmlDevice, AmlInteger, AmlNameDecl, AmlPackage, AmlScope, AmlScopeBuilder,
+    AmlAddressSpaceDecode, AmlBuilder, AmlCacheable, AmlDevice, AmlInteger, AmlNameDecl,
+    AmlPackage, AmlQWordDesc, AmlReadAndWrite, AmlResTemplate, AmlScope, AmlScopeBuilder,
     AmlString, TableLoader, IOAPIC_BASE_ADDR, LAPIC_BASE_ADDR,
 };
-use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+use address_space::{create_sgx_epc_mem, AddressSpace, GuestAddress, HostMemMapping, Region};
 use boot_loader::{load_linux, BootLoaderConfig};
-use cpu::{CPUBootConfig, CPUInterface, CPUTopology, CpuTopology, CPU};
+use cpu::{
+    set_spec_isolation, CPUBootConfig, CPUFeatures, CPUInterface, CPUTopology, CpuTopology,
+    SgxEpcSection, CPU,
+};
+use devices::acpi::cpu_hotplug::{CpuHotplug, CPU_HOTPLUG_REG_SIZE};
+use devices::acpi::ged::{AcpiEvent, Ged};
+use devices::acpi::memory_hotplug::MemHotplug;
+use devices::acpi::power::PowerDev;
 use devices::legacy::{
-    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, PFlash, Serial, RTC,
-    SERIAL_ADDR,
+    error::LegacyError as DevErrorKind, FwCfgEntryType, FwCfgIO, FwCfgOps, Hpet, PFlash, Serial,
+    RTC, SERIAL_ADDR,
 };
+use devices::misc::pvpanic::{PvPanic, PvPanicReqs};
+use devices::misc::tpm::TpmCrb;
+use devices::misc::vmcoreinfo::VmCoreInfo;
 use devices::pci::{PciDevOps, PciHost};
 use devices::sysbus::SysBus;
-use hypervisor::kvm::KVM_FDS;
+use hypervisor::kvm::{
+    set_kvm_vm_type, sev_hashes_table_location, sev_kernel_hashes_table, SevSnpGuest, KVM_FDS,
+    KVM_X86_SNP_VM,
+};
 #[cfg(feature = "gtk")]
 use machine_manager::config::UiContext;
 use machine_manager::config::{
-    parse_incoming_uri, BootIndexInfo, BootSource, DriveFile, Incoming, MigrateMode, NumaNode,
-    NumaNodes, PFlashConfig, SerialConfig, VmConfig,
+    parse_incoming_uri, parse_pvpanic, parse_tpm_crb, parse_vmcoreinfo, BootIndexInfo, BootSource,
+    DriveFile, Incoming, MachineMemConfig, MigrateMode, NumaNode, NumaNodes, PFlashConfig,
+    RtcConfig, SerialConfig, VmConfig, G,
 };
 use machine_manager::event;
 use machine_manager::event_loop::EventLoop;
@@ -67,12 +83,18 @@ use ui::gtk::gtk_display_init;
 #[cfg(feature = "vnc")]
 use ui::vnc::vnc_init;
 use util::{
-    byte_code::ByteCode, loop_context::EventLoopManager, seccomp::BpfRule, set_termi_canon_mode,
+    boot_time::{BootStage, BootStageTimer},
+    byte_code::ByteCode,
+    loop_context::EventLoopManager,
+    num_ops::round_up,
+    seccomp::BpfRule,
+    set_termi_canon_mode,
 };
 
 const VENDOR_ID_INTEL: u16 = 0x8086;
 const HOLE_640K_START: u64 = 0x000A_0000;
 const HOLE_640K_END: u64 = 0x0010_0000;
+const PVPANIC_IOPORT: u64 = 0x505;
 
 /// The type of memory layout entry on x86_64
 #[repr(usize)]
@@ -81,7 +103,13 @@ pub enum LayoutEntryType {
     PcieEcam,
     PcieMmio,
     Mmio,
+    MemHotplug,
+    CpuHotplug,
+    Ged,
+    PowerDev,
     IoApic,
+    Hpet,
+    Tpm,
     LocalApic,
     IdentTss,
     MemAbove4g,
@@ -93,7 +121,13 @@ pub const MEM_LAYOUT: &[(u64, u64)] = &[
     (0xB000_0000, 0x1000_0000),      // PcieEcam
     (0xC000_0000, 0x3000_0000),      // PcieMmio
     (0xF010_0000, 0x200),            // Mmio
+    (0xFEBF_F000, 0x18),             // MemHotplug
+    (0xFEBF_F018, 0x8),              // CpuHotplug
+    (0xFEBF_F020, 0x4),              // Ged
+    (0xFEBF_F040, 0x20),             // PowerDev
     (0xFEC0_0000, 0x10_0000),        // IoApic
+    (0xFED0_0000, 0x400),            // Hpet
+    (0xFED4_0000, 0x1000),           // Tpm
     (0xFEE0_0000, 0x10_0000),        // LocalApic
     (0xFEF0_C000, 0x4000),           // Identity map address and TSS
     (0x1_0000_0000, 0x80_0000_0000), // MemAbove4g
@@ -120,6 +154,8 @@ pub struct StdMachine {
     cpu_topo: CpuTopology,
     /// `vCPU` devices.
     cpus: Vec<Arc<CPU>>,
+    /// Parked `vCPU` devices, which can be hot-added.
+    parked_cpus: Vec<Arc<CPU>>,
     /// IO address space.
     sys_io: Arc<AddressSpace>,
     /// Memory address space.
@@ -136,6 +172,8 @@ pub struct StdMachine {
     reset_req: Arc<EventFd>,
     /// Shutdown_req, handle VM 'ShutDown' event.
     shutdown_req: Arc<EventFd>,
+    /// Pause request, handle VM `Pause` event.
+    pause_req: Arc<EventFd>,
     /// All configuration information of virtual machine.
     vm_config: Arc<Mutex<VmConfig>>,
     /// List of guest NUMA nodes information.
@@ -148,10 +186,29 @@ pub struct StdMachine {
     drive_files: Arc<Mutex<HashMap<String, DriveFile>>>,
     /// All backend memory region tree
     machine_ram: Arc<Region>,
+    /// Memory hotplug controller.
+    mem_hotplug: Option<Arc<Mutex<MemHotplug>>>,
+    /// Generic event device, which notifies guest of memory and CPU hotplug.
+    ged: Option<Arc<Mutex<Ged>>>,
+    /// CPU hotplug controller.
+    cpu_hotplug: Option<Arc<Mutex<CpuHotplug>>>,
+    /// Whether TPM CRB device is configured.
+    tpm_present: bool,
+    /// Emulated AC adapter and battery.
+    power_dev: Option<Arc<Mutex<PowerDev>>>,
+    /// vmcoreinfo device.
+    vmcoreinfo: Option<Arc<Mutex<VmCoreInfo>>>,
+    /// Whether HPET is emulated.
+    hpet_present: bool,
+    /// Launch flow of SEV-SNP guest.
+    sev_guest: Option<Arc<SevSnpGuest>>,
+    /// SGX EPC sections and the guest NUMA nodes which they belong to.
+    sgx_epc: Vec<(SgxEpcSection, u32)>,
 }
 
 impl StdMachine {
     pub fn new(vm_config: &VmConfig) -> Result<Self> {
+        let sev_guest = StdMachine::create_sev_guest(vm_config)?;
         let cpu_topo = CpuTopology::new(
             vm_config.machine_config.nr_cpus,
             vm_config.machine_config.nr_sockets,
@@ -186,6 +243,7 @@ impl StdMachine {
         Ok(StdMachine {
             cpu_topo,
             cpus: Vec::new(),
+            parked_cpus: Vec::new(),
             sys_io: sys_io.clone(),
             sys_mem: sys_mem.clone(),
             sysbus,
@@ -207,6 +265,10 @@ impl StdMachine {
                     MachineError::InitEventFdErr("shutdown request".to_string())
                 })?,
             ),
+            pause_req: Arc::new(
+                EventFd::new(libc::EFD_NONBLOCK)
+                    .with_context(|| MachineError::InitEventFdErr("pause request".to_string()))?,
+            ),
             vm_config: Arc::new(Mutex::new(vm_config.clone())),
             numa_nodes: None,
             boot_order_list: Arc::new(Mutex::new(Vec::new())),
@@ -216,9 +278,84 @@ impl StdMachine {
                 u64::max_value(),
                 "MachineRam",
             )),
+            mem_hotplug: None,
+            ged: None,
+            cpu_hotplug: None,
+            tpm_present: false,
+            power_dev: None,
+            vmcoreinfo: None,
+            hpet_present: false,
+            sev_guest,
+            sgx_epc: Vec::new(),
         })
     }
 
+    /// Create SEV-SNP guest if it's configured, the VM is created with SNP type.
+    fn create_sev_guest(vm_config: &VmConfig) -> Result<Option<Arc<SevSnpGuest>>> {
+        let cfg = match vm_config.get_sev_snp()? {
+            Some(cfg) => cfg,
+            None => return Ok(None),
+        };
+        let guest = Arc::new(
+            SevSnpGuest::new(&cfg.sev_device, cfg.policy, cfg.host_data)
+                .with_context(|| format!("Failed to create SEV-SNP guest {}", cfg.id))?,
+        );
+        set_kvm_vm_type(KVM_X86_SNP_VM)?;
+        let kvm_fds = KVM_FDS.load();
+        if kvm_fds.vm_fd.is_none() {
+            bail!("Failed to create SEV-SNP VM, check whether SEV-SNP is enabled in kvm");
+        }
+        kvm_fds.set_confidential_guest(guest.clone());
+        Ok(Some(guest))
+    }
+
+    /// Fill the SEV hashes table of firmware with hashes of kernel, initrd and cmdline
+    /// passed by fw_cfg, so that they are covered by the launch measurement.
+    fn add_sev_kernel_hashes(
+        &self,
+        vm_config: &VmConfig,
+        fwcfg: &Arc<Mutex<dyn FwCfgOps>>,
+    ) -> Result<()> {
+        match vm_config.get_sev_snp()? {
+            Some(cfg) if cfg.kernel_hashes => (),
+            _ => return Ok(()),
+        }
+        let pflash = vm_config
+            .pflashs
+            .as_ref()
+            .and_then(|p| p.iter().find(|c| c.unit == 0))
+            .with_context(|| "Kernel hashes of SEV-SNP guest require OVMF firmware")?;
+        let fd = self.fetch_drive_file(&pflash.path_on_host)?;
+        let mut firmware = vec![0_u8; fd.metadata()?.len() as usize];
+        fd.read_exact_at(&mut firmware, 0)
+            .with_context(|| format!("Failed to read firmware {}", pflash.path_on_host))?;
+        let (base, size) = sev_hashes_table_location(&firmware)
+            .with_context(|| "SEV hashes table is not found in firmware")?;
+
+        let mut locked_fwcfg = fwcfg.lock().unwrap();
+        let mut kernel = locked_fwcfg.get_entry_data(FwCfgEntryType::SetupData);
+        kernel.extend(locked_fwcfg.get_entry_data(FwCfgEntryType::KernelData));
+        let table = sev_kernel_hashes_table(
+            &kernel,
+            &locked_fwcfg.get_entry_data(FwCfgEntryType::InitrdData),
+            &locked_fwcfg.get_entry_data(FwCfgEntryType::CmdlineData),
+        );
+        if table.len() > size {
+            bail!(
+                "SEV hashes table size 0x{:X} is smaller than 0x{:X}",
+                size,
+                table.len()
+            );
+        }
+        self.sys_mem
+            .write(
+                &mut table.as_slice(),
+                GuestAddress(base),
+                table.len() as u64,
+            )
+            .with_context(|| "Failed to write SEV hashes table")
+    }
+
     pub fn handle_reset_request(vm: &Arc<Mutex<Self>>) -> Result<()> {
         let mut locked_vm = vm.lock().unwrap();
 
@@ -292,6 +429,164 @@ impl StdMachine {
         Ok(())
     }
 
+    fn add_mem_hotplug_device(&mut self, mem_config: &MachineMemConfig) -> Result<()> {
+        if mem_config.slots == 0 {
+            return Ok(());
+        }
+
+        let below4g_size = MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].1;
+        let above4g_start = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        let ram_end = above4g_start + mem_config.mem_size.saturating_sub(below4g_size);
+        // SAFETY: memory size has been checked, it won't overflow.
+        let base = round_up(ram_end, G).unwrap();
+        let size = mem_config.max_mem - mem_config.mem_size;
+        if base + size > above4g_start + MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].1 {
+            bail!("Max memory size 0x{:x} is too large", mem_config.max_mem);
+        }
+
+        let mem_hotplug = MemHotplug::new((base, size), mem_config.slots)
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::MemHotplug as usize].0,
+                MEM_LAYOUT[LayoutEntryType::MemHotplug as usize].1,
+            )
+            .with_context(|| "Failed to realize memory hotplug controller")?;
+        self.mem_hotplug = Some(mem_hotplug);
+        Ok(())
+    }
+
+    /// Map SGX EPC sections to guest, they are placed contiguously above the memory
+    /// hotplug window.
+    fn add_sgx_epc(&mut self, vm_config: &VmConfig) -> Result<()> {
+        let sections = vm_config.get_sgx_epc()?;
+        if sections.is_empty() {
+            return Ok(());
+        }
+
+        let mem_config = &vm_config.machine_config.mem_config;
+        let below4g_size = MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].1;
+        let above4g_start = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
+        let above4g_end = above4g_start + MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].1;
+        let ram_end = above4g_start + mem_config.mem_size.saturating_sub(below4g_size);
+        // SAFETY: memory size has been checked, it won't overflow.
+        let hotplug_end =
+            round_up(ram_end, G).unwrap() + (mem_config.max_mem - mem_config.mem_size);
+        let mut base = round_up(hotplug_end, G).unwrap();
+        for (epc, obj) in sections {
+            match &self.numa_nodes {
+                Some(nodes) if !nodes.contains_key(&epc.node) => {
+                    bail!(
+                        "NUMA node {} of SGX EPC {} is not found",
+                        epc.node,
+                        epc.memdev
+                    )
+                }
+                None if epc.node != 0 => {
+                    bail!("NUMA node of SGX EPC {} is set without NUMA", epc.memdev)
+                }
+                _ => {}
+            }
+            if base + obj.size > above4g_end {
+                bail!("SGX EPC sections are too large, total size exceeds memory layout");
+            }
+            let region = create_sgx_epc_mem(obj)
+                .with_context(|| format!("Failed to create SGX EPC {}", obj.id))?;
+            self.sys_mem
+                .root()
+                .add_subregion(region, base)
+                .with_context(|| format!("Failed to map SGX EPC {}", obj.id))?;
+            self.sgx_epc.push((
+                SgxEpcSection {
+                    base,
+                    size: obj.size,
+                },
+                epc.node,
+            ));
+            base += obj.size;
+        }
+        Ok(())
+    }
+
+    fn add_cpu_hotplug_device(&mut self, vm: Arc<Mutex<StdMachine>>) -> Result<()> {
+        if self.cpu_topo.max_cpus <= self.cpu_topo.nrcpus {
+            return Ok(());
+        }
+
+        let eject_req = Arc::new(
+            EventFd::new(libc::EFD_NONBLOCK)
+                .with_context(|| MachineError::InitEventFdErr("cpu eject request".to_string()))?,
+        );
+        let apic_ids = (0..self.cpu_topo.max_cpus)
+            .map(|cpu_id| self.cpu_topo.get_apic_id(cpu_id) as u8)
+            .collect();
+        let cpu_hotplug = CpuHotplug::new(
+            self.cpu_topo.nrcpus,
+            self.cpu_topo.max_cpus,
+            eject_req.clone(),
+        )
+        .with_apic_ids(apic_ids)
+        .realize(
+            &mut self.sysbus,
+            MEM_LAYOUT[LayoutEntryType::CpuHotplug as usize].0,
+            CPU_HOTPLUG_REG_SIZE,
+        )
+        .with_context(|| "Failed to realize CPU hotplug controller")?;
+        self.register_cpu_eject_event(eject_req, vm)
+            .with_context(|| "Fail to register CPU eject event")?;
+        self.cpu_hotplug = Some(cpu_hotplug);
+        Ok(())
+    }
+
+    /// Add generic event device which notifies guest of memory and CPU hotplug, and
+    /// the change of power supply. It's needed only if any of them is enabled.
+    fn add_ged_device(&mut self) -> Result<()> {
+        let battery_present = self.vm_config.lock().unwrap().machine_config.battery;
+        if self.mem_hotplug.is_none() && self.cpu_hotplug.is_none() && !battery_present {
+            return Ok(());
+        }
+
+        let mut ged = Ged::default();
+        if self.mem_hotplug.is_some() {
+            ged.enable_mem_hotplug();
+        }
+        if self.cpu_hotplug.is_some() {
+            ged.enable_cpu_hotplug();
+        }
+        let ged_dev = ged
+            .realize(
+                &mut self.sysbus,
+                None,
+                battery_present,
+                MEM_LAYOUT[LayoutEntryType::Ged as usize].0,
+                MEM_LAYOUT[LayoutEntryType::Ged as usize].1,
+            )
+            .with_context(|| "Failed to realize Ged")?;
+        self.ged = Some(ged_dev.clone());
+        if battery_present {
+            let pdev = PowerDev::new(ged_dev)
+                .realize(
+                    &mut self.sysbus,
+                    MEM_LAYOUT[LayoutEntryType::PowerDev as usize].0,
+                    MEM_LAYOUT[LayoutEntryType::PowerDev as usize].1,
+                )
+                .with_context(|| "Failed to realize PowerDev")?;
+            self.power_dev = Some(pdev);
+        }
+        Ok(())
+    }
+
+    fn add_hpet_device(&mut self) -> Result<()> {
+        Hpet::new()?
+            .realize(
+                &mut self.sysbus,
+                MEM_LAYOUT[LayoutEntryType::Hpet as usize].0,
+                MEM_LAYOUT[LayoutEntryType::Hpet as usize].1,
+            )
+            .with_context(|| "Failed to realize HPET")?;
+        self.hpet_present = true;
+        Ok(())
+    }
+
     pub fn mem_show(&self) {
         self.sys_mem.memspace_show();
         self.sys_io.memspace_show();
@@ -338,18 +633,28 @@ impl StdMachineOps for StdMachine {
     fn add_fwcfg_device(&mut self, nr_cpus: u8) -> super::Result<Option<Arc<Mutex<dyn FwCfgOps>>>> {
         let mut fwcfg = FwCfgIO::new(self.sys_mem.clone());
         fwcfg.add_data_entry(FwCfgEntryType::NbCpus, nr_cpus.as_bytes().to_vec())?;
-        fwcfg.add_data_entry(FwCfgEntryType::MaxCpus, nr_cpus.as_bytes().to_vec())?;
+        // Firmware scans APIC IDs below MaxCpus, which are sparse for non-power-of-two
+        // topology.
+        let apic_id_limit = self.cpu_topo.get_apic_id(self.cpu_topo.max_cpus - 1) as u8 + 1;
+        fwcfg.add_data_entry(FwCfgEntryType::MaxCpus, apic_id_limit.as_bytes().to_vec())?;
         fwcfg.add_data_entry(FwCfgEntryType::Irq0Override, 1_u32.as_bytes().to_vec())?;
 
         let boot_order = Vec::<u8>::new();
         fwcfg
             .add_file_entry("bootorder", boot_order)
             .with_context(|| DevErrorKind::AddEntryErr("bootorder".to_string()))?;
+        add_fwcfg_user_files(&mut fwcfg, &self.vm_config.lock().unwrap().fw_cfg)?;
 
         let fwcfg_dev = FwCfgIO::realize(fwcfg, &mut self.sysbus)
             .with_context(|| "Failed to realize fwcfg device")?;
         self.fwcfg_dev = Some(fwcfg_dev.clone());
 
+        // Fwcfg device is created after the devices, so the file of vmcoreinfo is added here.
+        let fwcfg_dev: Arc<Mutex<dyn FwCfgOps>> = fwcfg_dev;
+        if let Some(vmcoreinfo) = &self.vmcoreinfo {
+            vmcoreinfo.lock().unwrap().setup(&fwcfg_dev)?;
+        }
+
         Ok(Some(fwcfg_dev))
     }
 
@@ -361,9 +666,47 @@ impl StdMachineOps for StdMachine {
         &self.cpus
     }
 
+    fn get_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>> {
+        &mut self.cpus
+    }
+
+    fn get_parked_cpus_mut(&mut self) -> &mut Vec<Arc<CPU>> {
+        &mut self.parked_cpus
+    }
+
+    fn get_cpu_hotplug(&self) -> Option<Arc<Mutex<CpuHotplug>>> {
+        self.cpu_hotplug.clone()
+    }
+
+    fn notify_cpu_hotplug(&self) -> Result<()> {
+        let ged = self.ged.as_ref().with_context(|| "Ged is not realized")?;
+        ged.lock().unwrap().inject_acpi_event(AcpiEvent::CpuHotplug);
+        Ok(())
+    }
+
     fn get_guest_numa(&self) -> &Option<NumaNodes> {
         &self.numa_nodes
     }
+
+    fn has_tpm(&self) -> bool {
+        self.tpm_present
+    }
+
+    fn has_hpet(&self) -> bool {
+        self.hpet_present
+    }
+
+    fn get_power_dev(&self) -> Option<Arc<Mutex<PowerDev>>> {
+        self.power_dev.clone()
+    }
+
+    fn get_vmcoreinfo(&self) -> Option<Arc<Mutex<VmCoreInfo>>> {
+        self.vmcoreinfo.clone()
+    }
+
+    fn get_sev_guest(&self) -> Option<Arc<SevSnpGuest>> {
+        self.sev_guest.clone()
+    }
 }
 
 impl MachineOps for StdMachine {
@@ -414,6 +757,7 @@ impl MachineOps for StdMachine {
     }
 
     fn load_boot_source(&self, fwcfg: Option<&Arc<Mutex<dyn FwCfgOps>>>) -> Result<CPUBootConfig> {
+        let _timer = BootStageTimer::new(BootStage::FirmwareLoad, None);
         let boot_source = self.boot_source.lock().unwrap();
         let initrd = boot_source.initrd.as_ref().map(|b| b.initrd_file.clone());
 
@@ -424,12 +768,19 @@ impl MachineOps for StdMachine {
             kernel: boot_source.kernel_file.clone(),
             initrd,
             kernel_cmdline: boot_source.kernel_cmdline.to_string(),
-            cpu_count: self.cpu_topo.nrcpus,
+            apic_ids: (0..self.cpu_topo.nrcpus)
+                .map(|cpu_id| self.cpu_topo.get_apic_id(cpu_id) as u8)
+                .collect(),
             gap_range: (gap_start, gap_end - gap_start),
             ioapic_addr: MEM_LAYOUT[LayoutEntryType::IoApic as usize].0 as u32,
             lapic_addr: MEM_LAYOUT[LayoutEntryType::LocalApic as usize].0 as u32,
             ident_tss_range: Some(MEM_LAYOUT[LayoutEntryType::IdentTss as usize]),
             prot64_mode: false,
+            reserved_ranges: self
+                .sgx_epc
+                .iter()
+                .map(|(epc, _)| (epc.base, epc.size))
+                .collect(),
         };
         let layout = load_linux(&bootloader_config, &self.sys_mem, fwcfg)
             .with_context(|| MachineError::LoadKernErr)?;
@@ -443,8 +794,16 @@ impl MachineOps for StdMachine {
         })
     }
 
-    fn add_rtc_device(&mut self, mem_size: u64) -> Result<()> {
-        let mut rtc = RTC::new().with_context(|| "Failed to create RTC device")?;
+    fn load_cpu_features(&self, vmcfg: &VmConfig) -> Result<CPUFeatures> {
+        let mut features: CPUFeatures = (&vmcfg.machine_config.cpu_config).into();
+        for (index, (epc, _)) in self.sgx_epc.iter().enumerate() {
+            features.sgx_epc[index] = *epc;
+        }
+        Ok(features)
+    }
+
+    fn add_rtc_device(&mut self, rtc_config: &RtcConfig, mem_size: u64) -> Result<()> {
+        let mut rtc = RTC::new(rtc_config).with_context(|| "Failed to create RTC device")?;
         rtc.set_memory(
             mem_size,
             MEM_LAYOUT[LayoutEntryType::MemBelow4g as usize].0
@@ -465,6 +824,55 @@ impl MachineOps for StdMachine {
         Ok(())
     }
 
+    fn get_pvpanic_reqs(&self) -> Result<PvPanicReqs> {
+        Ok(PvPanicReqs {
+            pause_req: self.pause_req.clone(),
+            shutdown_req: self.shutdown_req.clone(),
+        })
+    }
+
+    fn add_pvpanic(&mut self, vm_config: &VmConfig, cfg_args: &str) -> Result<()> {
+        let config =
+            parse_pvpanic(cfg_args).with_context(|| "Failed to parse cmdline for pvpanic")?;
+        let pvpanic = PvPanic::new(
+            &config,
+            vm_config.machine_config.panic_action,
+            self.get_pvpanic_reqs()?,
+        );
+        pvpanic
+            .realize(&mut self.sysbus, PVPANIC_IOPORT, 1)
+            .with_context(|| "Failed to realize pvpanic device")
+    }
+
+    fn add_tpm_crb(&mut self, vm_config: &mut VmConfig, cfg_args: &str) -> Result<()> {
+        if self.tpm_present {
+            bail!("Only one TPM device is supported");
+        }
+        let config = parse_tpm_crb(vm_config, cfg_args)
+            .with_context(|| "Failed to parse cmdline for TPM")?;
+        let tpm = TpmCrb::new(&config);
+        tpm.realize(
+            &mut self.sysbus,
+            MEM_LAYOUT[LayoutEntryType::Tpm as usize].0,
+            MEM_LAYOUT[LayoutEntryType::Tpm as usize].1,
+        )
+        .with_context(|| "Failed to realize TPM device")?;
+        self.tpm_present = true;
+        Ok(())
+    }
+
+    fn add_vmcoreinfo(&mut self, cfg_args: &str) -> Result<()> {
+        if self.vmcoreinfo.is_some() {
+            bail!("Only one vmcoreinfo device is supported");
+        }
+        parse_vmcoreinfo(cfg_args)?;
+        let vmcoreinfo = VmCoreInfo::new()
+            .realize(&mut self.sysbus)
+            .with_context(|| "Failed to realize vmcoreinfo device")?;
+        self.vmcoreinfo = Some(vmcoreinfo);
+        Ok(())
+    }
+
     fn syscall_whitelist(&self) -> Vec<BpfRule> {
         syscall_whitelist()
     }
@@ -479,12 +887,22 @@ impl MachineOps for StdMachine {
         let mut locked_vm = vm.lock().unwrap();
         locked_vm.init_global_config(vm_config)?;
         locked_vm.numa_nodes = locked_vm.add_numa_nodes(vm_config)?;
+        KVM_FDS
+            .load()
+            .init_confidential_guest()
+            .with_context(|| "Failed to init confidential guest")?;
         locked_vm.init_memory(
             &vm_config.machine_config.mem_config,
             &locked_vm.sys_io,
             &locked_vm.sys_mem,
-            nr_cpus,
         )?;
+        KVM_FDS
+            .load()
+            .enable_dirty_ring(vm_config.machine_config.dirty_ring_size)
+            .with_context(|| "Failed to enable kvm dirty ring")?;
+        KVM_FDS
+            .load()
+            .set_clock_advance_on_pause(vm_config.machine_config.clock_advance_on_pause);
 
         locked_vm.init_interrupt_controller(u64::from(nr_cpus))?;
         StdMachine::arch_init()?;
@@ -495,27 +913,56 @@ impl MachineOps for StdMachine {
         locked_vm
             .init_ich9_lpc(clone_vm)
             .with_context(|| "Fail to init LPC bridge")?;
+        locked_vm
+            .register_pause_event(locked_vm.pause_req.clone(), vm.clone())
+            .with_context(|| "Fail to register pause event")?;
+        locked_vm.add_mem_hotplug_device(&vm_config.machine_config.mem_config)?;
+        locked_vm
+            .add_sgx_epc(vm_config)
+            .with_context(|| MachineError::AddDevErr("SGX EPC".to_string()))?;
+        locked_vm.add_cpu_hotplug_device(vm.clone())?;
+        locked_vm
+            .add_ged_device()
+            .with_context(|| MachineError::AddDevErr("Ged".to_string()))?;
         locked_vm.add_devices(vm_config)?;
+        if vm_config.machine_config.hpet {
+            locked_vm
+                .add_hpet_device()
+                .with_context(|| MachineError::AddDevErr("HPET".to_string()))?;
+        }
 
         let fwcfg = locked_vm.add_fwcfg_device(nr_cpus)?;
 
         let migrate = locked_vm.get_migrate_info();
-        let boot_config = if migrate.0 == MigrateMode::Unknown {
-            Some(locked_vm.load_boot_source(fwcfg.as_ref())?)
+        let (boot_config, cpu_config) = if migrate.0 == MigrateMode::Unknown {
+            (
+                Some(locked_vm.load_boot_source(fwcfg.as_ref())?),
+                Some(locked_vm.load_cpu_features(vm_config)?),
+            )
         } else {
-            None
+            (None, None)
         };
         let topology = CPUTopology::new().set_topology((
             vm_config.machine_config.nr_threads,
             vm_config.machine_config.nr_cores,
             vm_config.machine_config.nr_dies,
         ));
-        locked_vm.cpus.extend(<Self as MachineOps>::init_vcpu(
+        if vm_config.machine_config.cpu_config.spec_isolation {
+            set_spec_isolation().with_context(|| "Failed to isolate speculation of vCPUs")?;
+        }
+        let sys_mem = locked_vm.sys_mem.clone();
+        let mut cpus = <Self as MachineOps>::init_vcpu(
             vm.clone(),
+            sys_mem,
             nr_cpus,
+            vm_config.machine_config.max_cpus,
+            &vm_config.machine_config.vcpu_affinity,
             &topology,
             &boot_config,
-        )?);
+            &cpu_config,
+        )?;
+        locked_vm.parked_cpus = cpus.split_off(nr_cpus as usize);
+        locked_vm.cpus.extend(cpus);
 
         if migrate.0 == MigrateMode::Unknown {
             if let Some(fw_cfg) = fwcfg {
@@ -540,6 +987,9 @@ impl MachineOps for StdMachine {
                 locked_vm
                     .build_smbios(&fw_cfg, mem_array)
                     .with_context(|| "Failed to create smbios tables")?;
+                locked_vm
+                    .add_sev_kernel_hashes(vm_config, &fw_cfg)
+                    .with_context(|| "Failed to add kernel hashes of SEV-SNP guest")?;
             }
         }
 
@@ -558,6 +1008,11 @@ impl MachineOps for StdMachine {
             locked_vm.shutdown_req.clone(),
         );
 
+        KVM_FDS
+            .load()
+            .launch_confidential_guest()
+            .with_context(|| "Failed to launch confidential guest")?;
+
         MigrationManager::register_vm_config(locked_vm.get_vm_config());
         MigrationManager::register_vm_instance(vm.clone());
         MigrationManager::register_kvm_instance(
@@ -568,12 +1023,25 @@ impl MachineOps for StdMachine {
             bail!("Failed to set migration status {}", e);
         }
 
+        if let Some(gdb_config) = &vm_config.gdb {
+            gdbstub::start_gdbstub(
+                gdb_config,
+                vm.clone(),
+                locked_vm.vm_state.clone(),
+                locked_vm.cpus.clone(),
+                locked_vm.sys_mem.clone(),
+            )?;
+        }
+
         Ok(())
     }
 
     fn add_pflash_device(&mut self, configs: &[PFlashConfig]) -> Result<()> {
         let mut configs_vec = configs.to_vec();
         configs_vec.sort_by_key(|c| c.unit);
+        if configs_vec[0].unit != 0 {
+            bail!("PFlash unit 0 which holds the firmware code is required");
+        }
         // The two PFlash devices locates below 4GB, this variable represents the end address
         // of current PFlash device.
         let mut flash_end: u64 = MEM_LAYOUT[LayoutEntryType::MemAbove4g as usize].0;
@@ -587,6 +1055,13 @@ impl MachineOps for StdMachine {
                 // KiB is for BIOS code which is stored in the first PFlash.
                 let rom_base = 0xe0000;
                 let rom_size = 0x20000;
+                if pfl_size < rom_size {
+                    bail!(
+                        "PFlash file {} is smaller than the BIOS code size 0x{:X}",
+                        config.path_on_host,
+                        rom_size
+                    );
+                }
                 fd.seek(SeekFrom::Start(pfl_size - rom_size))?;
 
                 let ram1 = Arc::new(HostMemMapping::new(
@@ -598,8 +1073,9 @@ impl MachineOps for StdMachine {
                     false,
                     false,
                 )?);
-                let rom_region = Region::init_ram_region(ram1, "PflashRam");
-                rom_region.write(&mut fd, GuestAddress(rom_base), 0, rom_size)?;
+                // The BIOS code is read-only to guest, writes to it are discarded.
+                let rom_region = Region::init_rom_region(ram1, None, "PflashRom");
+                rom_region.load_rom(&mut fd, 0, rom_size)?;
                 rom_region.set_priority(10);
                 self.sys_mem.root().add_subregion(rom_region, rom_base)?;
 
@@ -698,6 +1174,16 @@ impl MachineOps for StdMachine {
         &self.numa_nodes
     }
 
+    fn get_mem_hotplug(&self) -> Option<Arc<Mutex<MemHotplug>>> {
+        self.mem_hotplug.clone()
+    }
+
+    fn notify_mem_hotplug(&self) -> Result<()> {
+        let ged = self.ged.as_ref().with_context(|| "Ged is not realized")?;
+        ged.lock().unwrap().inject_acpi_event(AcpiEvent::MemHotplug);
+        Ok(())
+    }
+
     fn get_fwcfg_dev(&mut self) -> Option<Arc<Mutex<dyn FwCfgOps>>> {
         if let Some(fwcfg_dev) = &self.fwcfg_dev {
             return Some(fwcfg_dev.clone());
@@ -718,19 +1204,42 @@ impl AcpiBuilder for StdMachine {
     ) -> super::Result<u64> {
         let mut dsdt = AcpiTable::new(*b"DSDT", 2, *b"STRATO", *b"VIRTDSDT", 1);
 
-        // 1. CPU info.
-        let cpus_count = self.cpus.len() as u64;
+        // 1. CPU info, which is built by CPU hotplug controller if it exists.
         let mut sb_scope = AmlScope::new("\\_SB");
-        for cpu_id in 0..cpus_count {
-            let mut dev = AmlDevice::new(format!("C{:03}", cpu_id).as_str());
-            dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
-            dev.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id)));
-            dev.append_child(AmlNameDecl::new("_PXM", AmlInteger(0)));
-            sb_scope.append_child(dev);
+        if self.cpu_hotplug.is_none() {
+            let cpus_count = self.cpus.len() as u64;
+            for cpu_id in 0..cpus_count {
+                let mut dev = AmlDevice::new(format!("C{:03}", cpu_id).as_str());
+                dev.append_child(AmlNameDecl::new("_HID", AmlString("ACPI0007".to_string())));
+                dev.append_child(AmlNameDecl::new("_UID", AmlInteger(cpu_id)));
+                dev.append_child(AmlNameDecl::new("_PXM", AmlInteger(0)));
+                sb_scope.append_child(dev);
+            }
         }
 
         // 2. Create pci host bridge node.
         sb_scope.append_child(self.pci_host.lock().unwrap().clone());
+
+        // SGX EPC device which describes the range of all EPC sections.
+        if let (Some((first, _)), Some((last, _))) = (self.sgx_epc.first(), self.sgx_epc.last()) {
+            let end = last.base + last.size;
+            let mut dev = AmlDevice::new("EPC");
+            dev.append_child(AmlNameDecl::new("_HID", AmlString("INT0E0C".to_string())));
+            dev.append_child(AmlNameDecl::new("_STA", AmlInteger(0xF)));
+            let mut crs = AmlResTemplate::new();
+            crs.append_child(AmlQWordDesc::new_memory(
+                AmlAddressSpaceDecode::Positive,
+                AmlCacheable::NonCacheable,
+                AmlReadAndWrite::ReadWrite,
+                0,
+                first.base,
+                end - 1,
+                0,
+                end - first.base,
+            ));
+            dev.append_child(AmlNameDecl::new("_CRS", crs));
+            sb_scope.append_child(dev);
+        }
         dsdt.append_child(sb_scope.aml_bytes().as_slice());
 
         // 3. Info of devices attached to system bus.
@@ -770,16 +1279,19 @@ impl AcpiBuilder for StdMachine {
         };
         madt.append_child(ioapic.aml_bytes().as_ref());
 
-        self.cpus.iter().for_each(|cpu| {
+        // Possible CPUs which are not present are listed as disabled, so that they can
+        // be hot-added.
+        for cpu_id in 0..self.cpu_topo.max_cpus {
             let lapic = AcpiLocalApic {
                 type_id: 0,
                 length: size_of::<AcpiLocalApic>() as u8,
-                processor_uid: cpu.id(),
-                apic_id: cpu.id(),
-                flags: 1, // Flags: enabled.
+                processor_uid: cpu_id,
+                apic_id: self.cpu_topo.get_apic_id(cpu_id) as u8,
+                // Flags: enabled if the CPU is present.
+                flags: u32::from(self.cpu_topo.get_mask(cpu_id as usize)),
             };
             madt.append_child(&lapic.aml_bytes());
-        });
+        }
 
         let madt_begin = StdMachine::add_table_to_loader(acpi_data, loader, &madt)
             .with_context(|| "Fail to add DSTD table to loader")?;
@@ -792,7 +1304,7 @@ impl AcpiBuilder for StdMachine {
                 &AcpiSratProcessorAffinity {
                     length: size_of::<AcpiSratProcessorAffinity>() as u8,
                     proximity_lo: proximity_domain as u8,
-                    local_apic_id: *cpu,
+                    local_apic_id: self.cpu_topo.get_apic_id(*cpu) as u8,
                     flags: 1,
                     ..Default::default()
                 }
@@ -895,6 +1407,25 @@ impl AcpiBuilder for StdMachine {
             self.build_srat_cpu(*id, node, &mut srat);
             next_base = self.build_srat_mem(next_base, *id, node, &mut srat);
         }
+        if let Some(mem_hotplug) = &self.mem_hotplug {
+            // SAFETY: the SRAT table is created only when numa node configured.
+            let last_node = *self.numa_nodes.as_ref().unwrap().keys().last().unwrap();
+            self.build_srat_hotplug_mem(mem_hotplug, last_node, &mut srat);
+        }
+        for (epc, node) in self.sgx_epc.iter() {
+            srat.append_child(
+                &AcpiSratMemoryAffinity {
+                    type_id: 1,
+                    length: size_of::<AcpiSratMemoryAffinity>() as u8,
+                    proximity_domain: *node,
+                    base_addr: epc.base,
+                    range_length: epc.size,
+                    flags: 1,
+                    ..Default::default()
+                }
+                .aml_bytes(),
+            );
+        }
 
         let srat_begin = StdMachine::add_table_to_loader(acpi_data, loader, &srat)
             .with_context(|| "Fail to add SRAT table to loader")?;
@@ -1007,10 +1538,43 @@ impl MachineAddressInterface for StdMachine {
 
 impl MigrateInterface for StdMachine {
     fn migrate(&self, uri: String) -> Response {
+        if let Err(e) = self
+            .vm_config
+            .lock()
+            .unwrap()
+            .machine_config
+            .cpu_config
+            .check_migratable()
+        {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+        if !self.sgx_epc.is_empty() {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "Migration is blocked because SGX EPC is configured".to_string(),
+                ),
+                None,
+            );
+        }
+        // The TPM state is kept by swtpm, which is not transferred with the VM.
+        if self.tpm_present {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "Migration is blocked because TPM CRB is configured".to_string(),
+                ),
+                None,
+            );
+        }
         match parse_incoming_uri(&uri) {
             Ok((MigrateMode::File, path)) => migration::snapshot(path),
             Ok((MigrateMode::Unix, path)) => migration::migration_unix_mode(path),
             Ok((MigrateMode::Tcp, path)) => migration::migration_tcp_mode(path),
+            Ok((MigrateMode::Exec, cmd)) => migration::migration_exec_mode(cmd, false),
+            Ok((MigrateMode::ExecOneway, cmd)) => migration::migration_exec_mode(cmd, true),
+            Ok((MigrateMode::Fd, fd_name)) => migration::migration_fd_mode(fd_name),
             _ => Response::create_error_response(
                 qmp_schema::QmpErrorClass::GenericError(format!("Invalid uri: {}", uri)),
                 None,
@@ -1025,6 +1589,25 @@ impl MigrateInterface for StdMachine {
     fn cancel_migrate(&self) -> Response {
         migration::cancel_migrate()
     }
+
+    fn colo_lost_heartbeat(&self) -> Response {
+        migration::colo_lost_heartbeat()
+    }
+
+    fn migrate_set_parameters(&self, args: qmp_schema::MigrateSetParametersArgument) -> Response {
+        migration::set_migrate_parameters(args)
+    }
+
+    fn migrate_set_capabilities(
+        &self,
+        capabilities: Vec<qmp_schema::MigrateCapabilities>,
+    ) -> Response {
+        migration::set_migrate_capabilities(capabilities)
+    }
+
+    fn query_migrate_parameters(&self) -> Response {
+        migration::query_migrate_parameters()
+    }
 }
 
 impl MachineInterface for StdMachine {}
@@ -12,7 +12,10 @@
 
 use hypervisor::kvm::*;
 use util::seccomp::{BpfRule, SeccompCmpOpt};
-use util::tap::{TUNGETFEATURES, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ};
+use util::tap::{
+    TUNGETFEATURES, TUNGETIFF, TUNSETIFF, TUNSETOFFLOAD, TUNSETQUEUE, TUNSETVNETHDRSZ,
+};
+use util::userfaultfd::{UFFDIO_API, UFFDIO_REGISTER, UFFDIO_UNREGISTER, UFFDIO_WRITEPROTECT};
 #[cfg(feature = "usb_camera_v4l2")]
 use util::v4l2::{
     VIDIOC_DQBUF, VIDIOC_ENUM_FMT, VIDIOC_ENUM_FRAMEINTERVALS, VIDIOC_ENUM_FRAMESIZES,
@@ -26,6 +29,10 @@ use vfio::{
     VFIO_IOMMU_UNMAP_DMA, VFIO_SET_IOMMU,
 };
 use virtio::VhostKern::*;
+use virtio::VhostVdpa::{
+    VHOST_VDPA_GET_CONFIG, VHOST_VDPA_GET_STATUS, VHOST_VDPA_SET_CONFIG,
+    VHOST_VDPA_SET_CONFIG_CALL, VHOST_VDPA_SET_STATUS, VHOST_VDPA_SET_VRING_ENABLE,
+};
 
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/futex.h
 const FUTEX_WAIT: u32 = 0;
@@ -55,8 +62,8 @@ const KVM_RUN: u32 = 0xae80;
 ///
 /// # Notes
 /// This allowlist limit syscall with:
-/// * x86_64-unknown-gnu: 96 syscalls
-/// * x86_64-unknown-musl: 64 syscalls
+/// * x86_64-unknown-gnu: 98 syscalls
+/// * x86_64-unknown-musl: 66 syscalls
 /// To reduce performance losses, the syscall rules is ordered by frequency.
 pub fn syscall_whitelist() -> Vec<BpfRule> {
     vec![
@@ -182,6 +189,8 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         BpfRule::new(libc::SYS_set_robust_list),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_sched_getaffinity),
+        BpfRule::new(libc::SYS_sched_setaffinity),
+        BpfRule::new(libc::SYS_set_mempolicy),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_pipe2),
         #[cfg(target_env = "gnu")]
@@ -191,6 +200,7 @@ pub fn syscall_whitelist() -> Vec<BpfRule> {
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_futex),
         BpfRule::new(libc::SYS_fallocate),
+        BpfRule::new(libc::SYS_userfaultfd),
         #[cfg(target_env = "gnu")]
         BpfRule::new(libc::SYS_poll),
         #[cfg(target_env = "gnu")]
@@ -237,10 +247,22 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_GET_FEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_SET_MEM_TABLE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_NET_SET_BACKEND() as u32)
+        .add_constraint(
+            SeccompCmpOpt::Eq,
+            1,
+            VHOST_SET_VRING_BUSYLOOP_TIMEOUT() as u32,
+        )
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_GET_FEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_RESET_OWNER() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_GET_STATUS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_SET_STATUS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_GET_CONFIG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_SET_CONFIG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_SET_VRING_ENABLE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, VHOST_VDPA_SET_CONFIG_CALL() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETFEATURES() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETIFF() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, TUNGETIFF() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETOFFLOAD() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETVNETHDRSZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, TUNSETQUEUE() as u32)
@@ -265,6 +287,7 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_VCPU_EVENTS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_PIT2() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_CLOCK() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CLOCK() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_IRQCHIP() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_REGS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SREGS() as u32)
@@ -276,6 +299,7 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_MSRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_SUPPORTED_CPUID() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_CPUID2() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_TSC_KHZ() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MP_STATE() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_SREGS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_REGS() as u32)
@@ -285,7 +309,18 @@ fn ioctl_allow_list() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_LAPIC() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MSRS() as u32)
         .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_VCPU_EVENTS() as u32)
-        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32);
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_GUEST_DEBUG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_TRANSLATE() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_DIRTY_LOG() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_RESET_DIRTY_RINGS() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_CREATE_GUEST_MEMFD() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_USER_MEMORY_REGION2() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_SET_MEMORY_ATTRIBUTES() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, KVM_GET_STATS_FD() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, UFFDIO_API() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, UFFDIO_REGISTER() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, UFFDIO_UNREGISTER() as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 1, UFFDIO_WRITEPROTECT() as u32);
 
     #[cfg(feature = "usb_camera_v4l2")]
     let bpf_rule = bpf_rule
@@ -313,12 +348,18 @@ fn madvise_rule() -> BpfRule {
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_MERGEABLE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_HUGEPAGE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_NOHUGEPAGE as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
     #[cfg(target_env = "gnu")]
     return BpfRule::new(libc::SYS_madvise)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_WILLNEED as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_DONTDUMP as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_MERGEABLE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_HUGEPAGE as u32)
+        .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_NOHUGEPAGE as u32)
         .add_constraint(SeccompCmpOpt::Eq, 2, libc::MADV_REMOVE as u32);
 }
 
@@ -42,8 +42,8 @@ impl StateTransfer for KvmDevice {
         // save pit
         let pit_state = vm_fd.get_pit2()?;
 
-        // save kvm_clock
-        let mut kvm_clock = vm_fd.get_clock()?;
+        // save kvm_clock, which is frozen when VM is paused
+        let mut kvm_clock = kvm_fds.get_clock()?;
         // Reset kvm clock flag.
         kvm_clock.flags = 0;
 
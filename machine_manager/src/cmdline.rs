@@ -10,16 +10,19 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::net::TcpListener;
 use std::os::unix::net::UnixListener;
 
 use anyhow::{bail, Context, Result};
 
 use crate::{
-    config::{add_trace_events, ChardevType, CmdParser, MachineType, VmConfig},
+    config::{add_trace_events, ChardevConfig, ChardevType, CmdParser, MachineType, VmConfig},
+    qmp::qmp_tls::{check_qmp_tls_creds, make_qmp_tls_config, QmpTlsConfig},
     temp_cleaner::TempCleaner,
 };
 use util::arg_parser::{Arg, ArgMatches, ArgParser};
 use util::file::clear_file;
+use util::seclabel::label_path;
 use util::unix::{limit_permission, parse_unix_uri};
 
 /// This macro is to run struct $z 's function $s whose arg is $x 's inner member.
@@ -84,17 +87,19 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("machine")
             .long("machine")
-            .value_name("[type=]<name>[,dump_guest_core=on|off][,mem-share=on|off]")
+            .value_name("[type=]<name>[,dump_guest_core=on|off][,mem-share=on|off][,clock-advance-on-pause=on|off][,hpet=on|off]")
             .help("'type' selects emulated machine type and set properties. \
                    'dump_guest_core' includes guest memory in a core dump. \
-                   'mem-share' sets guest memory is shareable.")
+                   'mem-share' sets guest memory is shareable. \
+                   'clock-advance-on-pause' keeps guest clock running while VM is paused. \
+                   'hpet' emulates HPET for x86_64 standard VM.")
             .takes_value(true),
         )
         .arg(
             Arg::with_name("accel")
             .long("accel")
-            .value_name("[accel]")
-            .help("select accelerator, only 'kvm' is supported now.")
+            .value_name("[accel][,dirty-ring-size=<n>]")
+            .help("select accelerator, only 'kvm' is supported now. 'dirty-ring-size' tracks dirty memory by kvm dirty ring.")
             .takes_value(true),
         )
         .arg(
@@ -112,11 +117,19 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("cpu")
             .long("cpu")
-            .value_name("host[,pmu=on|off]")
+            .value_name("host[,pmu=on|off][,sve=on|off][,sve-vl=<vl1>:<vl2>][,tsc-frequency=<hz>][,invtsc=on|off][,steal-time=on|off]")
             .help("set CPU model and features.")
             .can_no_value(false)
             .takes_value(true)
         )
+        .arg(
+            Arg::with_name("vcpu-affinity")
+            .multiple(true)
+            .long("vcpu-affinity")
+            .value_name("vcpu=<n>,affinity=<host cpus>")
+            .help("pin vCPU to host CPUs, such as: -vcpu-affinity vcpu=0,affinity=0-1:4")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("freeze_cpu")
             .short("S")
@@ -128,7 +141,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("memory")
             .long("m")
-            .value_name("[size=]<megs>[m|M|g|G]")
+            .value_name("[size=]<megs>[m|M|g|G][,slots=<n>,maxmem=<size>]")
             .help("configure guest RAM(default unit: MiB).")
             .takes_value(true),
         )
@@ -177,11 +190,19 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("use 'initrd-file' as initial ram disk")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("dtb-overlay")
+            .multiple(true)
+            .long("dtb-overlay")
+            .value_name("<overlay_path>")
+            .help("merge device tree overlay blob into the generated device tree")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("qmp")
             .long("qmp")
-            .value_name("unix:<socket_path>")
-            .help("set QMP's unix socket path")
+            .value_name("unix:<socket_path> | tcp:<ip>:<port>,tls-creds=<id>[,tls-allowed-cn=<cn1>:<cn2>]")
+            .help("set QMP's unix socket path, or tcp address which requires tls with client certificate")
             .takes_value(true)
         )
         .arg(
@@ -198,6 +219,7 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .value_name("<parameters>")
             .help("\n\t\tset block drive image: -drive id=<drive_id>,file=<path_on_host>[,readonly=on|off][,direct=on|off][,throttling.iops-total=<200>]; \
                    \n\t\tset pflash drive image: -drive file=<pflash_path>,if=pflash,unit=0|1[,readonly=true|false]; \
+                   \n\t\tset virtio block device with its drive image: -drive file=<path_on_host>,if=virtio[,id=<drive_id>][,cache=none|writeback][,serial=<serial_num>]; \
                    \n\t\tset scsi drive image: -drive id=<drive-scsi0-0-0-0>,file=<path_on_host>[,readonly=true|false]")
             .takes_values(true),
         )
@@ -211,12 +233,28 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .help("configure a host TAP network with ID 'str'")
             .takes_values(true),
         )
+        .arg(
+            Arg::with_name("nic")
+            .multiple(true)
+            .long("nic")
+            .value_name("tap[,ifname=<tap_name>][,vhost=on|off][,queues=<N>][,model=virtio][,mac=<macaddr>]")
+            .help("configure a host TAP network and a virtio net device for it")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("chardev")
             .multiple(true)
             .long("chardev")
-            .value_name("socket,id=<str>,path=<socket_path>")
-            .help("set char device virtio console for vm")
+            .value_name("socket,id=<str>,path=<socket_path>[,mux=on|off]")
+            .help("set char device virtio console for vm, 'mux' shares it between multiple frontends")
+            .takes_values(true),
+        )
+        .arg(
+            Arg::with_name("tpmdev")
+            .multiple(true)
+            .long("tpmdev")
+            .value_name("emulator,id=<str>,chardev=<chardev_id>")
+            .help("set TPM backend which is an external swtpm process")
             .takes_values(true),
         )
         .arg(
@@ -246,8 +284,12 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    \n\t\tadd usb tablet: -device usb-tablet,id=<tablet>; \
                    \n\t\tadd usb storage: -device usb-storage,id=<storage>,drive=<drive_id>; \
                    \n\t\tadd scsi controller: -device virtio-scsi-pci,id=<scsi_id>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off][,iothread=<iothread1>][,num-queues=<N>]; \
+                   \n\t\tadd vhost user scsi controller: -device vhost-user-scsi-pci,id=<scsi_id>,chardev=<chardev_id>,bus=<pcie.0>,addr=<0x3>[,multifunction=on|off][,num-queues=<N>]; \
+                   \n\t\tadd vhost vdpa net: -device vhost-vdpa-net-pci,id=<net_id>,vhostdev=</dev/vhost-vdpa-0>,bus=<pcie.0>,addr=<0x3>[,vhostfd=<N>][,queue-size=<N>]; \
+                   \n\t\tadd vhost vdpa block: -device vhost-vdpa-blk-pci,id=<blk_id>,vhostdev=</dev/vhost-vdpa-1>,bus=<pcie.0>,addr=<0x3>[,vhostfd=<N>][,queue-size=<N>]; \
                    \n\t\tadd scsi hard disk: -device scsi-hd,scsi-id=<0>,bus=<scsi0.0>,lun=<0>,drive=<drive-scsi0-0-0-0>,id=<scsi0-0-0-0>; \
-                   \n\t\tadd vhost user fs: -device vhost-user-fs-pci,id=<device_id>,chardev=<chardev_id>,tag=<mount_tag>")
+                   \n\t\tadd vhost user fs: -device vhost-user-fs-pci,id=<device_id>,chardev=<chardev_id>,tag=<mount_tag>; \
+                   \n\t\tadd tpm crb: -device tpm-crb,id=<tpm_id>,tpmdev=<tpmdev_id>")
             .takes_values(true),
         )
         .arg(
@@ -265,6 +307,27 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(true)
             .can_no_value(true),
         )
+        .arg(
+            Arg::with_name("log-filter")
+            .long("log-filter")
+            .value_name("[<level>][,<module>=<level>]...")
+            .help("set the log levels of modules, such as warn,virtio=debug")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-output")
+            .long("log-output")
+            .value_name("<stderr|syslog|journald>")
+            .help("output log to stderr, syslog or journald, conflicts with -D <log path>")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+            .long("log-format")
+            .value_name("<text|json>")
+            .help("set the format of logs written to stderr or log file")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("pidfile")
             .long("pidfile")
@@ -288,15 +351,88 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_value(false)
             .required(false),
         )
+        .arg(
+            Arg::with_name("runas")
+            .long("runas")
+            .value_name("<user>|<uid>:<gid>")
+            .help("switch to the unprivileged user after devices and sockets are opened")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chroot")
+            .long("chroot")
+            .value_name("<dir>")
+            .help("chroot to the directory after devices and sockets are opened")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seclabel")
+            .long("seclabel")
+            .value_name("<selinux|apparmor>,label=<label>")
+            .help("set the security label of files, sockets and memfd created by StratoVirt")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vmm-limits")
+            .long("vmm-limits")
+            .value_name("[memlock=<size>|unlimited][,nofile=<n>][,malloc-arenas=<n>][,cgroup=<path>]")
+            .help("limit the resources used by StratoVirt itself")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lock-config")
+            .long("lock-config")
+            .help("reject qmp commands which change the configuration of VM, such as hotplug")
+            .takes_value(false)
+            .required(false),
+        )
+        .arg(
+            Arg::with_name("hang-watchdog")
+            .long("hang-watchdog")
+            .value_name("timeout=<seconds>[,event=on|off]")
+            .help("detect the main loop and iothreads which are stuck longer than timeout")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("crash-dump")
+            .long("crash-dump")
+            .value_name("dir=<path>[,trace-events=<n>]")
+            .help("write a diagnostics bundle to the directory when StratoVirt panics")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mem-pressure-monitor")
+            .long("mem-pressure-monitor")
+            .value_name("[interval=<seconds>][,dirty-rate=<MiB/s>][,psi-some=<percent>][,psi-full=<percent>][,psi-file=<path>]")
+            .help("emit events when dirty rate of guest memory or memory pressure of host is high")
+            .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("qmp-audit")
+            .long("qmp-audit")
+            .value_name("path=<file>[,enable=on|off]")
+            .help("log every executed qmp command to the file in json lines")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("incoming")
             .long("incoming")
             .value_name("<parameters>")
             .help("\n\t\tdo the migration using tcp socket: -incoming tcp:<ip>:<port>; \
                    \n\t\tdo the migration using unix socket: -incoming unix:<socket path>; \
+                   \n\t\tdo the migration using a spawned command: -incoming exec:<command>; \
+                   \n\t\tdo the migration using a spawned one-way command: -incoming exec-oneway:<command>; \
+                   \n\t\tdo the migration using an inherited fd: -incoming fd:<fd>; \
                    \n\t\tdo the virtual machine snapshot: -incoming file:<file path>")
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("gdb")
+            .long("gdb")
+            .value_name("tcp:[<ip>]:<port>")
+            .help("wait for gdb connection on tcp address, ip is 127.0.0.1 by default")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("object")
             .multiple(true)
@@ -306,8 +442,12 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                    [,host-nodes=<0>][,mem-prealloc=<true|false>][,dump-guest-core=<true|false>][,share=<on|off>]; \
                    \n\t\tadd memory backend file object: -object memory-backend-file,size=<size>,id=<memid>[,host-nodes=<0-1>] \
                    [,policy=bind][,mem-path=<path/to/file>][,dump-guest-core=<true|false>][,mem-prealloc=<true|false>][,share=<on|off>] \
+                   [,readonly=<on|off>][,pmem=<on|off>]; \
                    \n\t\tadd memory backend memfd object: -object memory-backend-memfd,size=<size>,id=<memid>[,host-nodes=0-1][,policy=bind] \
-                   [,mem-prealloc=<true|false>][,dump-guest-core=<true|false>][,share=<on|off>]; \
+                   [,mem-prealloc=<true|false>][,dump-guest-core=<true|false>][,share=<on|off>][,hugetlb=<on|off>][,hugetlbsize=<2M|1G>][,seal=<on|off>]; \
+                   \n\t\tadd memory backend hugetlb object: -object memory-backend-hugetlb,size=<size>,id=<memid>[,hugetlbsize=<2M|1G>] \
+                   [,host-nodes=0-1][,policy=bind][,mem-prealloc=<true|false>][,dump-guest-core=<true|false>][,share=<on|off>]; \
+                   \n\t\tmemory backend objects also support [,prealloc=<on|off>][,prealloc-threads=<n>][,merge=<on|off>][,thp=<on|off>]; \
                    \n\t\tadd iothread object: -object iothread,id=<iothread_id>; \
                    \n\t\tadd rng object: -object rng-random,id=<rng_id>,filename=<file_path>; \
                    \n\t\tadd vnc tls object: -object tls-creds-x509,id=<vnc_id>,dir=</etc/pki/vnc>; \
@@ -354,8 +494,8 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("sandbox")
             .long("sandbox")
-            .value_name("[on,obsolete=deny]")
-            .hidden(true)
+            .value_name("on|off[,mode=enforce|log][,policy=<file>]")
+            .help("set seccomp sandbox, policy is a json file of per thread class profiles")
             .can_no_value(true)
             .takes_value(true),
         )
@@ -370,8 +510,8 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("rtc")
             .long("rtc")
-            .value_name("[base=utc]")
-            .hidden(true)
+            .value_name("[base=utc|localtime|<datetime>][,clock=host|vm]")
+            .help("set the start time and clock of RTC, datetime is in format of \"2006-06-17T16:01:21\"")
             .can_no_value(true)
             .takes_value(true),
         )
@@ -382,6 +522,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .can_no_value(true)
             .takes_value(true),
         )
+        .arg(
+            Arg::with_name("action")
+            .long("action")
+            .value_name("panic=none|pause|shutdown|coredump")
+            .help("set the action taken when guest kernel panics, which is reported by pvpanic device")
+            .takes_value(true),
+        )
         .arg(
             Arg::with_name("battery")
             .long("battery")
@@ -393,8 +540,8 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         .arg(
             Arg::with_name("boot")
             .long("boot")
-            .value_name("[strict=on]")
-            .hidden(true)
+            .value_name("[strict=on|off]")
+            .help("set strict=on to only boot from the devices with bootindex")
             .can_no_value(true)
             .takes_value(true),
         )
@@ -430,10 +577,10 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
         )
         .arg(
             Arg::with_name("trace")
-            .multiple(false)
+            .multiple(true)
             .long("trace")
-            .value_name("events=<file>")
-            .help("specify the file lists trace events to enable")
+            .value_name("[backend=ftrace|lttng][,events=<file>][,enable=<pattern>]")
+            .help("specify the file lists trace events or the pattern of trace events to enable")
             .takes_value(true),
         )
         .arg(
@@ -445,6 +592,14 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
             .takes_values(true)
             .required(false),
         )
+        .arg(
+            Arg::with_name("fw_cfg")
+            .multiple(true)
+            .long("fw_cfg")
+            .value_name("[name=]<name>,file=<file> or [name=]<name>,string=<str>")
+            .help("add named fw_cfg entry with contents from file or string, the name should start with 'opt/'")
+            .takes_values(true),
+        )
         .arg(
             Arg::with_name("smbios")
             .multiple(true)
@@ -531,8 +686,10 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     add_args_to_config!((args.value_of("cpu")), vm_cfg, add_cpu_feature);
     add_args_to_config!((args.value_of("kernel")), vm_cfg, add_kernel);
     add_args_to_config!((args.value_of("initrd-file")), vm_cfg, add_initrd);
+    add_args_to_config!((args.value_of("boot")), vm_cfg, add_boot);
     add_args_to_config!((args.value_of("serial")), vm_cfg, add_serial);
     add_args_to_config!((args.value_of("incoming")), vm_cfg, add_incoming);
+    add_args_to_config!((args.value_of("gdb")), vm_cfg, add_gdb);
     #[cfg(feature = "vnc")]
     add_args_to_config!((args.value_of("vnc")), vm_cfg, add_vnc);
     #[cfg(feature = "gtk")]
@@ -550,6 +707,32 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
         bool
     );
     add_args_to_config!((args.is_present("battery")), vm_cfg, add_battery, bool);
+    add_args_to_config!((args.value_of("action")), vm_cfg, add_action);
+    add_args_to_config!((args.value_of("rtc")), vm_cfg, add_rtc);
+    add_args_to_config!((args.value_of("sandbox")), vm_cfg, add_sandbox);
+    add_args_to_config!((args.value_of("runas")), vm_cfg, add_runas);
+    add_args_to_config!((args.value_of("chroot")), vm_cfg, add_chroot);
+    add_args_to_config!((args.value_of("seclabel")), vm_cfg, add_seclabel);
+    add_args_to_config!((args.value_of("qmp-audit")), vm_cfg, add_qmp_audit);
+    add_args_to_config!((args.value_of("vmm-limits")), vm_cfg, add_vmm_limits);
+    add_args_to_config!((args.value_of("hang-watchdog")), vm_cfg, add_hang_watchdog);
+    add_args_to_config!(
+        (args.value_of("mem-pressure-monitor")),
+        vm_cfg,
+        add_mem_pressure_monitor
+    );
+    add_args_to_config!(
+        (args.is_present("lock-config")),
+        vm_cfg,
+        enable_config_lock,
+        bool
+    );
+    add_args_to_config!(
+        (args.is_present("disable-seccomp")),
+        vm_cfg,
+        disable_sandbox,
+        bool
+    );
     add_args_to_config!(
         (args.is_present("mem-prealloc")),
         vm_cfg,
@@ -565,16 +748,24 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
     add_args_to_config_multi!((args.values_of("drive")), vm_cfg, add_drive);
     add_args_to_config_multi!((args.values_of("object")), vm_cfg, add_object);
     add_args_to_config_multi!((args.values_of("netdev")), vm_cfg, add_netdev);
+    add_args_to_config_multi!((args.values_of("nic")), vm_cfg, add_nic);
     add_args_to_config_multi!((args.values_of("chardev")), vm_cfg, add_chardev);
+    add_args_to_config_multi!((args.values_of("tpmdev")), vm_cfg, add_tpmdev);
     add_args_to_config_multi!((args.values_of("device")), vm_cfg, add_device);
     add_args_to_config_multi!((args.values_of("global")), vm_cfg, add_global_config);
     add_args_to_config_multi!((args.values_of("numa")), vm_cfg, add_numa);
+    add_args_to_config_multi!((args.values_of("vcpu-affinity")), vm_cfg, add_vcpu_affinity);
     #[cfg(feature = "usb_camera")]
     add_args_to_config_multi!((args.values_of("cameradev")), vm_cfg, add_camera_backend);
     add_args_to_config_multi!((args.values_of("smbios")), vm_cfg, add_smbios);
+    add_args_to_config_multi!((args.values_of("fw_cfg")), vm_cfg, add_fw_cfg);
+    add_args_to_config_multi!((args.values_of("dtb-overlay")), vm_cfg, add_dtb_overlay);
+    vm_cfg.add_compat_devices()?;
 
-    if let Some(s) = args.value_of("trace") {
-        add_trace_events(&s)?;
+    if let Some(traces) = args.values_of("trace") {
+        for trace in traces {
+            add_trace_events(&trace)?;
+        }
     }
 
     // Check the mini-set for Vm to start is ok
@@ -592,29 +783,67 @@ pub fn create_vmconfig(args: &ArgMatches) -> Result<VmConfig> {
 ///
 /// * `args` - The structure accepted input cmdline arguments.
 ///
+/// Returns the listeners of qmp sockets, and the multiplexed chardev of monitor if
+/// `-mon` is attached to it.
+///
 /// # Errors
 ///
 /// The value of `qmp` is illegel.
-pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<Vec<UnixListener>> {
+pub fn check_api_channel(
+    args: &ArgMatches,
+    vm_config: &mut VmConfig,
+) -> Result<(
+    Vec<UnixListener>,
+    Option<ChardevConfig>,
+    Option<QmpTlsConfig>,
+)> {
     let mut sock_paths = Vec::new();
+    let mut mon_chardev = None;
+    let mut qmp_tls = None;
     if let Some(qmp_config) = args.value_of("qmp") {
         let mut cmd_parser = CmdParser::new("qmp");
-        cmd_parser.push("").push("server").push("nowait");
+        cmd_parser
+            .push("")
+            .push("server")
+            .push("nowait")
+            .push("tls-creds")
+            .push("tls-allowed-cn");
 
         cmd_parser.parse(&qmp_config)?;
-        if let Some(uri) = cmd_parser.get_value::<String>("")? {
-            let api_path =
-                parse_unix_uri(&uri).with_context(|| "Failed to parse qmp socket path")?;
-            sock_paths.push(api_path);
-        } else {
-            bail!("No uri found for qmp");
-        }
+        let uri = cmd_parser
+            .get_value::<String>("")?
+            .with_context(|| "No uri found for qmp")?;
         if cmd_parser.get_value::<String>("server")?.is_none() {
             bail!("Argument \'server\' is needed for qmp");
         }
         if cmd_parser.get_value::<String>("nowait")?.is_none() {
             bail!("Argument \'nowait\' is needed for qmp");
         }
+        let tls_creds = cmd_parser.get_value::<String>("tls-creds")?;
+        let allowed_cn = cmd_parser.get_value::<String>("tls-allowed-cn")?;
+        if let Some(addr) = uri.strip_prefix("tcp:") {
+            let id = tls_creds
+                .with_context(|| "Argument \'tls-creds\' is needed for qmp on tcp socket")?;
+            let creds = check_qmp_tls_creds(vm_config.object.tls_object.get(&id), &id)?;
+            let server_config = make_qmp_tls_config(creds)?;
+            let allowed_cn = allowed_cn
+                .map(|cn| cn.split(':').map(String::from).collect())
+                .unwrap_or_default();
+            let listener = TcpListener::bind(addr)
+                .with_context(|| format!("Failed to bind qmp tcp socket {}", addr))?;
+            qmp_tls = Some(QmpTlsConfig {
+                listener,
+                server_config,
+                allowed_cn,
+            });
+        } else {
+            if tls_creds.is_some() || allowed_cn.is_some() {
+                bail!("Tls is only supported for qmp on tcp socket");
+            }
+            let api_path =
+                parse_unix_uri(&uri).with_context(|| "Failed to parse qmp socket path")?;
+            sock_paths.push(api_path);
+        }
     }
     if let Some(mon_config) = args.value_of("mon") {
         let mut cmd_parser = CmdParser::new("monitor");
@@ -633,8 +862,10 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
             bail!("Argument \'mode\' of \'mon\' should be set to \'control\'.");
         }
 
-        if let Some(cfg) = vm_config.chardev.remove(&chardev) {
-            if let ChardevType::Socket {
+        if let Some(cfg) = vm_config.take_chardev(&chardev) {
+            if cfg.mux {
+                mon_chardev = Some(cfg);
+            } else if let ChardevType::Socket {
                 path,
                 server,
                 nowait,
@@ -648,14 +879,14 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
                 }
                 sock_paths.push(path);
             } else {
-                bail!("Only socket-type of chardev can be used for monitor");
+                bail!("Only socket-type or multiplexed chardev can be used for monitor");
             }
         } else {
             bail!("No chardev found: {}", &chardev);
         }
     }
 
-    if sock_paths.is_empty() {
+    if sock_paths.is_empty() && mon_chardev.is_none() && qmp_tls.is_none() {
         bail!("Please use \'-qmp\' or \'-mon\' to give a qmp path for Unix socket");
     }
     let mut listeners = Vec::new();
@@ -666,7 +897,7 @@ pub fn check_api_channel(args: &ArgMatches, vm_config: &mut VmConfig) -> Result<
         )
     }
 
-    Ok(listeners)
+    Ok((listeners, mon_chardev, qmp_tls))
 }
 
 fn bind_socket(path: String) -> Result<UnixListener> {
@@ -677,5 +908,6 @@ fn bind_socket(path: String) -> Result<UnixListener> {
     TempCleaner::add_path(path.clone());
     limit_permission(&path)
         .with_context(|| format!("Failed to limit permission for socket file {}", &path))?;
+    label_path(&path)?;
     Ok(listener)
 }
@@ -0,0 +1,249 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{CmdParser, ConfigError, ExBool};
+use util::aio::AioEngine;
+
+/// Default size of the virtqueue driven by benchmark.
+const DEFAULT_BENCH_QUEUE_SIZE: u16 = 256;
+/// Default size of the buffer of each descriptor in bytes.
+const DEFAULT_BENCH_BUF_SIZE: u32 = 4096;
+/// Max size of the buffer of each descriptor in bytes.
+const MAX_BENCH_BUF_SIZE: u32 = 1 << 20;
+/// Default number of descriptor chains processed by benchmark.
+const DEFAULT_BENCH_REQUESTS: u64 = 1_000_000;
+
+/// The path benchmarked by `stratovirt bench`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BenchMode {
+    /// Only pop descriptor chains and push them to used ring.
+    Queue,
+    /// Read or write a file through aio with the buffers of descriptor chains.
+    Aio,
+    /// Send the descriptor chains to tap as packets.
+    Tap,
+}
+
+impl FromStr for BenchMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "queue" => Ok(BenchMode::Queue),
+            "aio" => Ok(BenchMode::Aio),
+            "tap" => Ok(BenchMode::Tap),
+            _ => bail!(
+                "Invalid bench mode {}, only queue, aio and tap are supported",
+                s
+            ),
+        }
+    }
+}
+
+/// Config of the guest-less virtqueue benchmark.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub mode: BenchMode,
+    pub queue_size: u16,
+    /// Number of descriptors in each chain.
+    pub chain_len: u16,
+    /// Size of the buffer of each descriptor in bytes.
+    pub buf_size: u32,
+    /// Total number of descriptor chains to process.
+    pub requests: u64,
+    /// Number of descriptor chains made available for each kick.
+    pub batch: u16,
+    /// File read or written in aio mode.
+    pub file: Option<String>,
+    pub aio: AioEngine,
+    pub direct: bool,
+    /// Write the file rather than read it in aio mode.
+    pub write: bool,
+    /// Name of tap in tap mode.
+    pub ifname: Option<String>,
+}
+
+impl BenchConfig {
+    /// Number of descriptor chains which can be in flight at the same time.
+    pub fn max_inflight(&self) -> u16 {
+        self.queue_size / self.chain_len
+    }
+}
+
+/// Parse the arguments of `stratovirt bench`, in format of
+/// `<queue|aio|tap>[,queue-size=<n>][,chain-len=<n>][,buf-size=<bytes>][,requests=<n>]
+/// [,batch=<n>][,file=<path>][,aio=<off|native|io_uring>][,direct=on|off][,rw=read|write]
+/// [,ifname=<tap>]`.
+pub fn parse_bench(bench_config: &str) -> Result<BenchConfig> {
+    let mut cmd_parser = CmdParser::new("bench");
+    cmd_parser
+        .push("")
+        .push("queue-size")
+        .push("chain-len")
+        .push("buf-size")
+        .push("requests")
+        .push("batch")
+        .push("file")
+        .push("aio")
+        .push("direct")
+        .push("rw")
+        .push("ifname");
+    cmd_parser.parse(bench_config)?;
+
+    let mode = cmd_parser
+        .get_value::<String>("")?
+        .with_context(|| ConfigError::FieldIsMissing("mode".to_string(), "bench".to_string()))?
+        .parse::<BenchMode>()?;
+
+    let queue_size = cmd_parser
+        .get_value::<u16>("queue-size")?
+        .unwrap_or(DEFAULT_BENCH_QUEUE_SIZE);
+    if queue_size == 0 || !queue_size.is_power_of_two() {
+        bail!("queue-size of bench should be power of 2");
+    }
+    let chain_len = cmd_parser.get_value::<u16>("chain-len")?.unwrap_or(1);
+    if chain_len == 0 || chain_len > queue_size {
+        bail!("chain-len of bench should be in range [1, {}]", queue_size);
+    }
+    let buf_size = cmd_parser
+        .get_value::<u32>("buf-size")?
+        .unwrap_or(DEFAULT_BENCH_BUF_SIZE);
+    if buf_size == 0 || buf_size > MAX_BENCH_BUF_SIZE {
+        bail!(
+            "buf-size of bench should be in range [1, {}]",
+            MAX_BENCH_BUF_SIZE
+        );
+    }
+    let requests = cmd_parser
+        .get_value::<u64>("requests")?
+        .unwrap_or(DEFAULT_BENCH_REQUESTS);
+    if requests == 0 {
+        bail!("requests of bench should be larger than 0");
+    }
+    let max_inflight = queue_size / chain_len;
+    let batch = cmd_parser
+        .get_value::<u16>("batch")?
+        .unwrap_or(max_inflight);
+    if batch == 0 || batch > max_inflight {
+        bail!(
+            "batch of bench should be in range [1, {}], which is queue-size / chain-len",
+            max_inflight
+        );
+    }
+
+    let mut config = BenchConfig {
+        mode,
+        queue_size,
+        chain_len,
+        buf_size,
+        requests,
+        batch,
+        file: cmd_parser.get_value::<String>("file")?,
+        aio: AioEngine::Off,
+        direct: true,
+        write: false,
+        ifname: cmd_parser.get_value::<String>("ifname")?,
+    };
+
+    if config.mode != BenchMode::Aio {
+        for arg in ["file", "aio", "direct", "rw"] {
+            if cmd_parser.get_value::<String>(arg)?.is_some() {
+                bail!("{} of bench is only supported in aio mode", arg);
+            }
+        }
+    }
+    if config.mode != BenchMode::Tap && config.ifname.is_some() {
+        bail!("ifname of bench is only supported in tap mode");
+    }
+
+    match config.mode {
+        BenchMode::Queue => {}
+        BenchMode::Aio => {
+            if config.file.is_none() {
+                bail!(ConfigError::FieldIsMissing(
+                    "file".to_string(),
+                    "bench".to_string()
+                ));
+            }
+            config.direct = cmd_parser
+                .get_value::<ExBool>("direct")?
+                .map(bool::from)
+                .unwrap_or(true);
+            config.aio = cmd_parser
+                .get_value::<AioEngine>("aio")?
+                .unwrap_or(if config.direct {
+                    AioEngine::Native
+                } else {
+                    AioEngine::Off
+                });
+            if config.aio == AioEngine::Native && !config.direct {
+                bail!("Native aio of bench requires direct=on");
+            }
+            config.write = match cmd_parser.get_value::<String>("rw")?.as_deref() {
+                None | Some("read") => false,
+                Some("write") => true,
+                Some(rw) => bail!("Invalid rw {} of bench, should be read or write", rw),
+            };
+        }
+        BenchMode::Tap => {
+            if config.ifname.is_none() {
+                bail!(ConfigError::FieldIsMissing(
+                    "ifname".to_string(),
+                    "bench".to_string()
+                ));
+            }
+        }
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bench() {
+        let config = parse_bench("queue").unwrap();
+        assert_eq!(config.mode, BenchMode::Queue);
+        assert_eq!(config.queue_size, DEFAULT_BENCH_QUEUE_SIZE);
+        assert_eq!(config.batch, DEFAULT_BENCH_QUEUE_SIZE);
+        assert_eq!(config.requests, DEFAULT_BENCH_REQUESTS);
+
+        let config = parse_bench("queue,queue-size=1024,chain-len=4,batch=16").unwrap();
+        assert_eq!(config.max_inflight(), 256);
+        assert_eq!(config.batch, 16);
+        assert!(parse_bench("queue,queue-size=100").is_err());
+        assert!(parse_bench("queue,chain-len=512").is_err());
+        assert!(parse_bench("queue,chain-len=2,batch=256").is_err());
+        assert!(parse_bench("queue,buf-size=0").is_err());
+        assert!(parse_bench("queue,file=/tmp/bench").is_err());
+        assert!(parse_bench("disk").is_err());
+
+        let config = parse_bench("aio,file=/tmp/bench,rw=write").unwrap();
+        assert_eq!(config.aio, AioEngine::Native);
+        assert!(config.direct && config.write);
+        let config = parse_bench("aio,file=/tmp/bench,direct=off").unwrap();
+        assert_eq!(config.aio, AioEngine::Off);
+        assert!(parse_bench("aio").is_err());
+        assert!(parse_bench("aio,file=/tmp/bench,direct=off,aio=native").is_err());
+        assert!(parse_bench("aio,file=/tmp/bench,rw=trim").is_err());
+
+        let config = parse_bench("tap,ifname=tap0").unwrap();
+        assert_eq!(config.ifname.as_deref(), Some("tap0"));
+        assert!(parse_bench("tap").is_err());
+        assert!(parse_bench("aio,file=/tmp/bench,ifname=tap0").is_err());
+    }
+}
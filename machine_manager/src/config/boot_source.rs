@@ -13,14 +13,16 @@
 use std::fmt;
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
-use crate::config::{check_arg_too_long, ConfigCheck, VmConfig, MAX_PATH_LENGTH};
+use crate::config::{
+    check_arg_too_long, CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH,
+};
 
 /// Config struct for boot-source.
-/// Contains `kernel_file`, `kernel_cmdline` and `initrd`.
+/// Contains `kernel_file`, `kernel_cmdline`, `initrd`, `dtb_overlays` and `boot_strict`.
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct BootSource {
     /// Path of the kernel image.
@@ -29,6 +31,10 @@ pub struct BootSource {
     pub kernel_cmdline: KernelParams,
     /// Config of initrd.
     pub initrd: Option<InitrdConfig>,
+    /// Paths of device tree overlays merged into the generated device tree.
+    pub dtb_overlays: Vec<PathBuf>,
+    /// Only boot from the devices with bootindex, firmware won't fall back to others.
+    pub boot_strict: bool,
 }
 
 impl BootSource {
@@ -58,6 +64,14 @@ impl ConfigCheck for BootSource {
         if self.initrd.is_some() {
             self.initrd.as_ref().unwrap().check()?;
         }
+        for overlay in &self.dtb_overlays {
+            check_arg_too_long(overlay.to_str().unwrap(), "dtb_overlay")?;
+            if !overlay.is_file() {
+                return Err(anyhow!(ConfigError::UnRegularFile(
+                    "Input dtb_overlay".to_string()
+                )));
+            }
+        }
 
         Ok(())
     }
@@ -234,6 +248,36 @@ impl VmConfig {
         self.boot_source.initrd = Some(InitrdConfig::new(initrd));
         Ok(())
     }
+
+    /// Add `-boot strict=on|off` config to `VmConfig`. Other options passed by libvirt,
+    /// such as `order` and `menu`, are accepted and ignored.
+    pub fn add_boot(&mut self, boot_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("boot");
+        cmd_parser
+            .push("")
+            .push("order")
+            .push("once")
+            .push("menu")
+            .push("splash")
+            .push("splash-time")
+            .push("reboot-timeout")
+            .push("strict");
+        cmd_parser.parse(boot_config)?;
+
+        if let Some(strict) = cmd_parser.get_value::<ExBool>("strict")? {
+            self.boot_source.boot_strict = strict.into();
+        }
+        Ok(())
+    }
+
+    /// Add `-dtb-overlay overlay_path` config to `VmConfig`
+    pub fn add_dtb_overlay(&mut self, overlay: &str) -> Result<()> {
+        if cfg!(not(target_arch = "aarch64")) {
+            bail!("Device tree overlay is only supported on aarch64");
+        }
+        self.boot_source.dtb_overlays.push(PathBuf::from(overlay));
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -296,4 +340,41 @@ mod tests {
         std::fs::remove_file(&kernel_path).unwrap();
         std::fs::remove_file(&initrd_path).unwrap();
     }
+
+    #[test]
+    fn test_boot_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(!vm_config.boot_source.boot_strict);
+        assert!(vm_config.add_boot("strict=on").is_ok());
+        assert!(vm_config.boot_source.boot_strict);
+        assert!(vm_config.add_boot("strict=off").is_ok());
+        assert!(!vm_config.boot_source.boot_strict);
+        assert!(vm_config.add_boot("strict=abc").is_err());
+        assert!(vm_config.add_boot("").is_ok());
+        assert!(vm_config.add_boot("c").is_ok());
+        assert!(vm_config.add_boot("order=c").is_ok());
+        assert!(vm_config.add_boot("menu=on,strict=on").is_ok());
+        assert!(vm_config.boot_source.boot_strict);
+        assert!(vm_config.add_boot("bootdev=c").is_err());
+    }
+
+    #[test]
+    fn test_dtb_overlay_cmdline_parser() {
+        let overlay_path = String::from("overlay.dtbo");
+        let mut vm_config = VmConfig::default();
+        if cfg!(not(target_arch = "aarch64")) {
+            assert!(vm_config.add_dtb_overlay(&overlay_path).is_err());
+            return;
+        }
+
+        assert!(vm_config.add_dtb_overlay(&overlay_path).is_ok());
+        assert_eq!(
+            vm_config.boot_source.dtb_overlays,
+            vec![PathBuf::from(&overlay_path)]
+        );
+        assert!(vm_config.boot_source.check().is_err());
+        File::create(&overlay_path).unwrap();
+        assert!(vm_config.boot_source.check().is_ok());
+        std::fs::remove_file(&overlay_path).unwrap();
+    }
 }
@@ -16,7 +16,8 @@ use serde::{Deserialize, Serialize};
 
 use super::{error::ConfigError, get_pci_bdf, pci_args_check, PciBdf};
 use crate::config::{
-    check_arg_too_long, CmdParser, ConfigCheck, ExBool, VmConfig, MAX_PATH_LENGTH,
+    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, VmConfig,
+    MAX_PATH_LENGTH,
 };
 use crate::qmp::qmp_schema;
 
@@ -25,6 +26,10 @@ const MIN_GUEST_CID: u64 = 3;
 
 /// Default value of max ports for virtio-serial.
 const DEFAULT_SERIAL_PORTS_NUMBER: u32 = 31;
+/// Default size of ringbuf chardev.
+const DEFAULT_RINGBUF_SIZE: u64 = 64 * 1024;
+/// Max size of ringbuf chardev.
+const MAX_RINGBUF_SIZE: u64 = 64 * 1024 * 1024;
 
 /// Character device options.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -37,12 +42,16 @@ pub enum ChardevType {
         nowait: bool,
     },
     File(String),
+    /// Ring buffer in memory with the given size, whose content can be read by qmp.
+    Ringbuf(u64),
 }
 
 /// Config structure for virtio-serial-port.
 #[derive(Debug, Clone)]
 pub struct VirtioSerialPort {
     pub id: String,
+    /// Port name visible to guest, such as `org.qemu.guest_agent.0`. Default is the id.
+    pub name: String,
     pub chardev: ChardevConfig,
     pub nr: u32,
     pub is_console: bool,
@@ -50,7 +59,8 @@ pub struct VirtioSerialPort {
 
 impl ConfigCheck for VirtioSerialPort {
     fn check(&self) -> Result<()> {
-        check_arg_too_long(&self.id, "chardev id")
+        check_arg_too_long(&self.id, "chardev id")?;
+        check_arg_too_long(&self.name, "port name")
     }
 }
 
@@ -59,6 +69,15 @@ impl ConfigCheck for VirtioSerialPort {
 pub struct ChardevConfig {
     pub id: String,
     pub backend: ChardevType,
+    /// Whether the backend is shared by multiple frontends through a multiplexer.
+    #[serde(default)]
+    pub mux: bool,
+    /// Path of file which logs the output of chardev.
+    #[serde(default)]
+    pub logfile: Option<String>,
+    /// Whether to append to the log file instead of truncating it.
+    #[serde(default)]
+    pub logappend: bool,
 }
 
 impl ConfigCheck for ChardevConfig {
@@ -76,6 +95,18 @@ impl ConfigCheck for ChardevConfig {
                 MAX_PATH_LENGTH
             )));
         }
+        if let Some(logfile) = &self.logfile {
+            check_path_too_long(logfile, "chardev logfile")?;
+        }
+
+        if let ChardevType::Ringbuf(size) = self.backend {
+            if !(1..=MAX_RINGBUF_SIZE).contains(&size) || !size.is_power_of_two() {
+                bail!(
+                    "Size of ringbuf chardev should be power of 2 and no more than {}",
+                    MAX_RINGBUF_SIZE
+                );
+            }
+        }
 
         Ok(())
     }
@@ -86,8 +117,14 @@ fn check_chardev_args(cmd_parser: CmdParser) -> Result<()> {
         let chardev_str = chardev_type.as_str();
         let server = cmd_parser.get_value::<String>("server")?;
         let nowait = cmd_parser.get_value::<String>("nowait")?;
+        if chardev_str != "ringbuf" && cmd_parser.get_value::<String>("size")?.is_some() {
+            bail!(
+                "Chardev of {}-type does not support \'size\' argument",
+                chardev_str
+            );
+        }
         match chardev_str {
-            "stdio" | "pty" | "file" => {
+            "stdio" | "pty" | "file" | "ringbuf" => {
                 if server.is_some() {
                     bail!(
                         "Chardev of {}-type does not support \'server\' argument",
@@ -141,6 +178,16 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     } else {
         false
     };
+    let size = cmd_parser.get_value::<u64>("size")?;
+    let mux = cmd_parser
+        .get_value::<ExBool>("mux")?
+        .map(bool::from)
+        .unwrap_or_default();
+    let logfile = cmd_parser.get_value::<String>("logfile")?;
+    let logappend = cmd_parser.get_value::<ExBool>("logappend")?.map(bool::from);
+    if logfile.is_none() && logappend.is_some() {
+        bail!("Argument \'logappend\' of chardev requires \'logfile\'");
+    }
     check_chardev_args(cmd_parser)?;
     let chardev_type = if let Some(backend) = backend {
         match backend.as_str() {
@@ -170,6 +217,7 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
                     )));
                 }
             }
+            "ringbuf" => ChardevType::Ringbuf(size.unwrap_or(DEFAULT_RINGBUF_SIZE)),
             _ => {
                 return Err(anyhow!(ConfigError::InvalidParam(
                     backend,
@@ -187,6 +235,9 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
     Ok(ChardevConfig {
         id: chardev_id,
         backend: chardev_type,
+        mux,
+        logfile,
+        logappend: logappend.unwrap_or_default(),
     })
 }
 
@@ -197,38 +248,77 @@ pub fn parse_chardev(cmd_parser: CmdParser) -> Result<ChardevConfig> {
 /// * `args` - The qmp arguments.
 pub fn get_chardev_config(args: qmp_schema::CharDevAddArgument) -> Result<ChardevConfig> {
     let backend = args.backend;
-    if backend.backend_type.as_str() != "socket" {
-        return Err(anyhow!(ConfigError::InvalidParam(
-            "backend".to_string(),
-            backend.backend_type
-        )));
-    }
-
     let data = backend.backend_data;
-    if data.server {
-        error!("Not support chardev socket as server now.");
-        return Err(anyhow!(ConfigError::InvalidParam(
-            "backend".to_string(),
-            "server".to_string()
-        )));
-    }
+    let chardev_type = match backend.backend_type.as_str() {
+        "socket" => {
+            if data.out.is_some() || data.size.is_some() {
+                bail!("Chardev of socket-type does not support \'out\' or \'size\' argument");
+            }
+            let addr = data.addr.with_context(|| {
+                ConfigError::FieldIsMissing("addr".to_string(), "socket-type chardev".to_string())
+            })?;
+            if addr.addr_type.as_str() != "unix" {
+                error!("Just support \"unix\" addr type option now.");
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "backend".to_string(),
+                    "addr".to_string()
+                )));
+            }
+            let server = data.server.unwrap_or(false);
+            if !server && data.wait.is_some() {
+                bail!("Argument \'wait\' is only supported by socket server");
+            }
+            ChardevType::Socket {
+                path: addr.addr_data.path,
+                server,
+                nowait: server && !data.wait.unwrap_or(true),
+            }
+        }
+        "pty" | "file" | "ringbuf" => {
+            if data.addr.is_some() || data.server.is_some() || data.wait.is_some() {
+                bail!(
+                    "Chardev of {}-type does not support socket arguments",
+                    backend.backend_type
+                );
+            }
+            if backend.backend_type != "file" && data.out.is_some() {
+                bail!(
+                    "Chardev of {}-type does not support \'out\' argument",
+                    backend.backend_type
+                );
+            }
+            if backend.backend_type != "ringbuf" && data.size.is_some() {
+                bail!(
+                    "Chardev of {}-type does not support \'size\' argument",
+                    backend.backend_type
+                );
+            }
+            match backend.backend_type.as_str() {
+                "pty" => ChardevType::Pty,
+                "file" => ChardevType::File(data.out.with_context(|| {
+                    ConfigError::FieldIsMissing("out".to_string(), "file-type chardev".to_string())
+                })?),
+                _ => ChardevType::Ringbuf(data.size.unwrap_or(DEFAULT_RINGBUF_SIZE)),
+            }
+        }
+        _ => {
+            return Err(anyhow!(ConfigError::InvalidParam(
+                "backend".to_string(),
+                backend.backend_type
+            )));
+        }
+    };
 
-    let addr = data.addr;
-    if addr.addr_type.as_str() != "unix" {
-        error!("Just support \"unix\" addr type option now.");
-        return Err(anyhow!(ConfigError::InvalidParam(
-            "backend".to_string(),
-            "addr".to_string()
-        )));
+    if data.logfile.is_none() && data.logappend.is_some() {
+        bail!("Argument \'logappend\' of chardev requires \'logfile\'");
     }
 
     Ok(ChardevConfig {
         id: args.id,
-        backend: ChardevType::Socket {
-            path: addr.addr_data.path,
-            server: data.server,
-            nowait: false,
-        },
+        backend: chardev_type,
+        mux: false,
+        logfile: data.logfile,
+        logappend: data.logappend.unwrap_or_default(),
     })
 }
 
@@ -270,7 +360,12 @@ pub fn parse_virtserialport(
     free_nr: u32,
 ) -> Result<VirtioSerialPort> {
     let mut cmd_parser = CmdParser::new("virtserialport");
-    cmd_parser.push("").push("id").push("chardev").push("nr");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("chardev")
+        .push("nr")
+        .push("name");
     cmd_parser.parse(config_args)?;
 
     let chardev_name = cmd_parser
@@ -281,14 +376,18 @@ pub fn parse_virtserialport(
     let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
         ConfigError::FieldIsMissing("id".to_string(), "virtserialport".to_string())
     })?;
+    let name = cmd_parser
+        .get_value::<String>("name")?
+        .unwrap_or(id.clone());
     let nr = cmd_parser.get_value::<u32>("nr")?.unwrap_or(free_nr);
     if nr == 0 && !is_console {
         bail!("Port number 0 on virtio-serial devices reserved for virtconsole device.");
     }
 
-    if let Some(chardev) = vm_config.chardev.remove(&chardev_name) {
+    if let Some(chardev) = vm_config.take_chardev(&chardev_name) {
         let port_cfg = VirtioSerialPort {
             id,
+            name,
             chardev,
             nr,
             is_console,
@@ -308,7 +407,11 @@ impl VmConfig {
             .push("id")
             .push("path")
             .push("server")
-            .push("nowait");
+            .push("nowait")
+            .push("size")
+            .push("mux")
+            .push("logfile")
+            .push("logappend");
 
         cmd_parser.parse(chardev_config)?;
 
@@ -342,6 +445,19 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Take chardev config which is used by a frontend. Multiplexed chardev is kept for
+    /// other frontends, the others can be used only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The chardev id.
+    pub fn take_chardev(&mut self, id: &str) -> Option<ChardevConfig> {
+        match self.chardev.get(id) {
+            Some(chardev) if chardev.mux => Some(chardev.clone()),
+            _ => self.chardev.remove(id),
+        }
+    }
+
     /// Delete chardev config from vm config.
     ///
     /// # Arguments
@@ -384,7 +500,7 @@ impl VmConfig {
                 "serial_chardev"
             }
         };
-        if let Some(char_dev) = self.chardev.remove(chardev_id) {
+        if let Some(char_dev) = self.take_chardev(chardev_id) {
             self.serial = Some(SerialConfig { chardev: char_dev });
             return Ok(());
         }
@@ -616,6 +732,21 @@ mod tests {
             "virtio-serial-pci,bus=pcie.0,addr=0x1.0x2,multifunction=on"
         )
         .is_ok());
+
+        // Port name defaults to the id.
+        assert_eq!(console_cfg.name, "console1");
+        assert!(vm_config
+            .add_chardev("socket,id=qga0,path=/path/to/qga.sock,server,nowait")
+            .is_ok());
+        let port_cfg = parse_virtserialport(
+            &mut vm_config,
+            "virtserialport,chardev=qga0,id=qga_port,name=org.qemu.guest_agent.0",
+            false,
+            1,
+        )
+        .unwrap();
+        assert_eq!(port_cfg.id, "qga_port");
+        assert_eq!(port_cfg.name, "org.qemu.guest_agent.0");
     }
 
     #[test]
@@ -660,5 +791,121 @@ mod tests {
         } else {
             assert!(false);
         }
+
+        // Log output to file.
+        assert!(vm_config
+            .add_chardev("pty,id=log0,logfile=/path/to/log,logappend=on")
+            .is_ok());
+        let char_dev = vm_config.chardev.remove("log0").unwrap();
+        assert_eq!(char_dev.logfile, Some("/path/to/log".to_string()));
+        assert!(char_dev.logappend);
+        assert!(vm_config.add_chardev("pty,id=log1,logappend=on").is_err());
+    }
+
+    #[test]
+    fn test_mux_chardev_config() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_chardev("stdio,id=mux0,mux=on").unwrap();
+        vm_config.add_chardev("pty,id=pty0,mux=off").unwrap();
+        assert!(vm_config.add_chardev("stdio,id=mux1,mux=1").is_err());
+
+        // Multiplexed chardev can be used by multiple frontends.
+        vm_config.add_serial("chardev:mux0").unwrap();
+        assert!(vm_config.serial.as_ref().unwrap().chardev.mux);
+        let port =
+            parse_virtserialport(&mut vm_config, "virtconsole,id=con0,chardev=mux0", true, 0)
+                .unwrap();
+        assert_eq!(port.chardev.backend, ChardevType::Stdio);
+        assert!(vm_config.take_chardev("mux0").is_some());
+
+        assert!(!vm_config.take_chardev("pty0").unwrap().mux);
+        assert!(vm_config.take_chardev("pty0").is_none());
+    }
+
+    #[test]
+    fn test_ringbuf_chardev_config() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_chardev("ringbuf,id=ring0").unwrap();
+        vm_config.add_chardev("ringbuf,id=ring1,size=4096").unwrap();
+        assert!(vm_config.add_chardev("ringbuf,id=ring2,size=1000").is_err());
+        assert!(vm_config.add_chardev("ringbuf,id=ring3,size=0").is_err());
+        assert!(vm_config
+            .add_chardev("ringbuf,id=ring4,size=134217728")
+            .is_err());
+        assert!(vm_config.add_chardev("pty,id=pty0,size=4096").is_err());
+
+        assert_eq!(
+            vm_config.chardev.get("ring0").unwrap().backend,
+            ChardevType::Ringbuf(DEFAULT_RINGBUF_SIZE)
+        );
+        assert_eq!(
+            vm_config.chardev.get("ring1").unwrap().backend,
+            ChardevType::Ringbuf(4096)
+        );
+    }
+
+    #[test]
+    fn test_chardev_qmp_config() {
+        let parse = |args: &str| {
+            let args: qmp_schema::CharDevAddArgument = serde_json::from_str(args).unwrap();
+            get_chardev_config(args)
+        };
+
+        let chardev = parse(
+            r#"{"id": "sock0", "backend": {"type": "socket", "data": {
+                "addr": {"type": "unix", "data": {"path": "/path/to/socket"}},
+                "server": true, "wait": false}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            chardev.backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: true,
+                nowait: true,
+            }
+        );
+        let chardev = parse(
+            r#"{"id": "sock1", "backend": {"type": "socket", "data": {
+                "addr": {"type": "unix", "data": {"path": "/path/to/socket"}}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            chardev.backend,
+            ChardevType::Socket {
+                path: "/path/to/socket".to_string(),
+                server: false,
+                nowait: false,
+            }
+        );
+        let chardev = parse(r#"{"id": "pty0", "backend": {"type": "pty", "data": {}}}"#).unwrap();
+        assert_eq!(chardev.backend, ChardevType::Pty);
+        let chardev = parse(
+            r#"{"id": "pty1", "backend": {"type": "pty", "data": {"logfile": "/path/to/log"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(chardev.logfile, Some("/path/to/log".to_string()));
+        assert!(!chardev.logappend);
+        let chardev = parse(
+            r#"{"id": "file0", "backend": {"type": "file", "data": {"out": "/path/to/file"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            chardev.backend,
+            ChardevType::File("/path/to/file".to_string())
+        );
+
+        // Invalid arguments.
+        assert!(parse(
+            r#"{"id": "sock2", "backend": {"type": "socket", "data": {
+                "addr": {"type": "unix", "data": {"path": "/path/to/socket"}}, "wait": false}}}"#
+        )
+        .is_err());
+        assert!(parse(r#"{"id": "sock3", "backend": {"type": "socket", "data": {}}}"#).is_err());
+        assert!(parse(r#"{"id": "file1", "backend": {"type": "file", "data": {}}}"#).is_err());
+        assert!(parse(r#"{"id": "stdio0", "backend": {"type": "stdio", "data": {}}}"#).is_err());
+        assert!(
+            parse(r#"{"id": "pty1", "backend": {"type": "pty", "data": {"size": 1024}}}"#).is_err()
+        );
     }
 }
@@ -0,0 +1,231 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Translation of the QEMU arguments which add a device in one go, `-drive if=virtio` and
+//! `-nic`, to the drives, netdevs and devices of StratoVirt.
+
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+use log::warn;
+
+use crate::config::{get_pci_bdf, MachineType, VmConfig};
+
+/// Root bus which the translated pci devices are plugged into.
+const COMPAT_PCI_BUS: &str = "pcie.0";
+/// Slots of root bus for the translated pci devices. Slot 0 is the host bridge, and the last
+/// slot is taken by LPC bridge on x86_64.
+const COMPAT_PCI_SLOTS: std::ops::RangeInclusive<u8> = 1..=0x1e;
+
+/// Split QEMU style `key=value` list, a key without value gets an empty one.
+fn split_props(args: &str) -> Vec<(&str, &str)> {
+    args.split(',')
+        .filter(|prop| !prop.is_empty())
+        .map(|prop| prop.split_once('=').unwrap_or((prop, "")))
+        .collect()
+}
+
+impl VmConfig {
+    /// Add `-drive if=virtio` config, which adds the drive and a virtio block device for it.
+    pub fn add_virtio_drive(&mut self, drive_config: &str) -> Result<()> {
+        let mut drive = Vec::new();
+        let mut device = Vec::new();
+        let mut id = None;
+        for (key, value) in split_props(drive_config) {
+            match key {
+                "if" => (),
+                "id" => id = Some(value.to_string()),
+                "file"
+                | "readonly"
+                | "format"
+                | "aio"
+                | "discard"
+                | "detect-zeroes"
+                | "throttling.iops-total" => drive.push(format!("{}={}", key, value)),
+                // Page cache of host is bypassed only with cache=none or cache=directsync.
+                "cache" => drive.push(format!(
+                    "direct={}",
+                    if matches!(value, "none" | "directsync") {
+                        "on"
+                    } else {
+                        "off"
+                    }
+                )),
+                "serial" | "bootindex" => device.push(format!("{}={}", key, value)),
+                _ => warn!(
+                    "Drive property {}={} of QEMU is not supported, ignore it",
+                    key, value
+                ),
+            }
+        }
+
+        let index = self.compat_devices.len();
+        let id = id.unwrap_or_else(|| format!("virtio-disk{}", index));
+        self.add_block_drive(&format!("id={},{}", id, drive.join(",")))?;
+
+        device.insert(0, format!("drive={},id={}", id, id));
+        self.compat_devices
+            .push(("blk".to_string(), device.join(",")));
+        Ok(())
+    }
+
+    /// Add `-nic` config, which adds a tap netdev and a virtio net device for it.
+    pub fn add_nic(&mut self, nic_config: &str) -> Result<()> {
+        let props = split_props(nic_config);
+        match props.first() {
+            Some(("none", "")) => return Ok(()),
+            Some(("tap", "")) => (),
+            Some((nic_type, _)) => {
+                bail!("Unsupported nic type {}, only tap is supported", nic_type)
+            }
+            None => bail!("No type set for nic"),
+        }
+
+        let id = format!("nic{}", self.compat_devices.len());
+        let mut netdev = vec![format!("tap,id={}", id)];
+        let mut device = vec![format!("netdev={},id={}", id, id)];
+        for (key, value) in props.into_iter().skip(1) {
+            match key {
+                "ifname" | "fd" | "vhost" | "vhostfd" => netdev.push(format!("{}={}", key, value)),
+                "queues" => {
+                    netdev.push(format!("queues={}", value));
+                    if value.parse::<u16>().map_or(false, |queues| queues > 1) {
+                        device.push("mq=on".to_string());
+                    }
+                }
+                "mac" => device.push(format!("mac={}", value)),
+                "model" => {
+                    if !matches!(value, "virtio" | "virtio-net-pci" | "virtio-net-device") {
+                        bail!("Unsupported nic model {}, only virtio is supported", value);
+                    }
+                }
+                // StratoVirt never runs scripts to set up tap.
+                "script" | "downscript" if value == "no" => (),
+                _ => warn!(
+                    "Nic property {}={} of QEMU is not supported, ignore it",
+                    key, value
+                ),
+            }
+        }
+
+        self.add_netdev(&netdev.join(","))?;
+        self.compat_devices
+            .push(("net".to_string(), device.join(",")));
+        Ok(())
+    }
+
+    /// Add the devices of `-drive if=virtio` and `-nic`. They are virtio mmio devices on microvm,
+    /// otherwise virtio pci devices in the free slots of root bus. It should be called after all
+    /// the devices are added, so that the used slots are known.
+    pub fn add_compat_devices(&mut self) -> Result<()> {
+        let compat_devices = std::mem::take(&mut self.compat_devices);
+        if self.machine_config.mach_type == MachineType::MicroVm {
+            for (dev_type, props) in compat_devices {
+                let driver = format!("virtio-{}-device", dev_type);
+                let config = format!("{},{}", driver, props);
+                self.devices.push((driver, config));
+            }
+            return Ok(());
+        }
+
+        let used_slots: HashSet<u8> = self
+            .devices
+            .iter()
+            .filter_map(|(_, config)| get_pci_bdf(config).ok())
+            .filter(|bdf| bdf.bus == COMPAT_PCI_BUS)
+            .map(|bdf| bdf.addr.0)
+            .collect();
+        let mut free_slots = COMPAT_PCI_SLOTS.filter(|slot| !used_slots.contains(slot));
+        for (dev_type, props) in compat_devices {
+            let slot = match free_slots.next() {
+                Some(slot) => slot,
+                None => bail!(
+                    "No free slot of {} for virtio-{} device",
+                    COMPAT_PCI_BUS,
+                    dev_type
+                ),
+            };
+            let driver = format!("virtio-{}-pci", dev_type);
+            let config = format!(
+                "{},{},bus={},addr={:#x}",
+                driver, props, COMPAT_PCI_BUS, slot
+            );
+            self.devices.push((driver, config));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_virtio_drive() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_drive("file=/path/to/rootfs,if=virtio,cache=none,serial=disk0,index=0")
+            .unwrap();
+        vm_config
+            .add_drive("id=data,file=/path/to/data,if=virtio,readonly=on")
+            .unwrap();
+        let drive = vm_config.drives.get("virtio-disk0").unwrap();
+        assert_eq!(drive.path_on_host, "/path/to/rootfs");
+        assert!(drive.direct);
+        let drive = vm_config.drives.get("data").unwrap();
+        assert!(drive.read_only);
+
+        vm_config.add_compat_devices().unwrap();
+        assert_eq!(
+            vm_config.devices,
+            vec![
+                (
+                    "virtio-blk-device".to_string(),
+                    "virtio-blk-device,drive=virtio-disk0,id=virtio-disk0,serial=disk0".to_string()
+                ),
+                (
+                    "virtio-blk-device".to_string(),
+                    "virtio-blk-device,drive=data,id=data".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_nic() {
+        let mut vm_config = VmConfig::default();
+        vm_config.machine_config.mach_type = MachineType::StandardVm;
+        vm_config
+            .add_device("virtio-blk-pci,id=blk0,drive=drive0,bus=pcie.0,addr=0x1")
+            .unwrap();
+        vm_config
+            .add_nic("tap,ifname=tap0,script=no,downscript=no,model=virtio-net-pci,mac=52:54:00:12:34:56,queues=2")
+            .unwrap();
+        vm_config.add_nic("none").unwrap();
+        assert!(vm_config.add_nic("user").is_err());
+        assert!(vm_config.add_nic("tap,ifname=tap1,model=e1000").is_err());
+
+        let netdev = vm_config.netdevs.get("nic0").unwrap();
+        assert_eq!(netdev.ifname, "tap0");
+        assert_eq!(netdev.queues, 4);
+
+        vm_config.add_compat_devices().unwrap();
+        assert_eq!(
+            vm_config.devices.last().unwrap(),
+            &(
+                "virtio-net-pci".to_string(),
+                "virtio-net-pci,netdev=nic0,id=nic0,mac=52:54:00:12:34:56,mq=on,bus=pcie.0,addr=0x2"
+                    .to_string()
+            )
+        );
+    }
+}
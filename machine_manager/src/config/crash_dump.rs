@@ -0,0 +1,86 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, CmdParser, ConfigError, VmConfig};
+
+/// Default number of the recent trace events in crash dump.
+const DEFAULT_TRACE_EVENTS: usize = 256;
+/// Max number of the recent trace events in crash dump.
+const MAX_TRACE_EVENTS: usize = 65536;
+
+/// Config of the crash dump bundle written when VMM panics.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashDumpConfig {
+    /// Directory where the bundle is written.
+    pub dir: String,
+    /// Number of the recent trace events kept in the bundle.
+    pub trace_events: usize,
+}
+
+impl VmConfig {
+    /// Add '-crash-dump' config to `VmConfig`, in format of
+    /// `dir=<path>[,trace-events=<n>]`.
+    pub fn add_crash_dump(&mut self, crash_dump: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("crash-dump");
+        cmd_parser.push("dir").push("trace-events");
+        cmd_parser.parse(crash_dump)?;
+
+        let dir = cmd_parser.get_value::<String>("dir")?.with_context(|| {
+            ConfigError::FieldIsMissing("dir".to_string(), "crash-dump".to_string())
+        })?;
+        check_path_too_long(&dir, "crash-dump dir")?;
+        let trace_events = cmd_parser
+            .get_value::<usize>("trace-events")?
+            .unwrap_or(DEFAULT_TRACE_EVENTS);
+        if trace_events > MAX_TRACE_EVENTS {
+            bail!(
+                "trace-events of crash-dump should be in range [0, {}]",
+                MAX_TRACE_EVENTS
+            );
+        }
+        self.crash_dump = Some(CrashDumpConfig { dir, trace_events });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_crash_dump() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_crash_dump("dir=/var/crash/stratovirt")
+            .unwrap();
+        assert_eq!(
+            vm_config.crash_dump,
+            Some(CrashDumpConfig {
+                dir: "/var/crash/stratovirt".to_string(),
+                trace_events: DEFAULT_TRACE_EVENTS,
+            })
+        );
+        vm_config.add_crash_dump("dir=/tmp,trace-events=0").unwrap();
+        assert_eq!(vm_config.crash_dump.as_ref().unwrap().trace_events, 0);
+
+        assert!(vm_config.add_crash_dump("trace-events=10").is_err());
+        assert!(vm_config
+            .add_crash_dump("dir=/tmp,trace-events=65537")
+            .is_err());
+        assert!(vm_config
+            .add_crash_dump("dir=/tmp,trace-events=-1")
+            .is_err());
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::error::ConfigError;
+use crate::config::{
+    CmdParser, ConfigCheck, MemZoneConfig, UnsignedInteger, VmConfig, MAX_NODES, MAX_STRING_LENGTH,
+};
+
+/// Both the guest address and size of DIMM should be aligned to the memory
+/// section size of guest kernel.
+pub const DIMM_ALIGN: u64 = 128 * 1024 * 1024;
+
+/// Config structure for pc-dimm.
+#[derive(Debug, Clone, Default)]
+pub struct DimmConfig {
+    pub id: String,
+    /// Guest physical address, allocated in hotplug memory window if not set.
+    pub addr: Option<u64>,
+    /// Guest NUMA node the DIMM belongs to.
+    pub node: u32,
+    /// Memory backend of the DIMM.
+    pub mem_zone: MemZoneConfig,
+}
+
+impl ConfigCheck for DimmConfig {
+    fn check(&self) -> Result<()> {
+        if self.id.len() > MAX_STRING_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "pc-dimm id".to_string(),
+                MAX_STRING_LENGTH
+            )));
+        }
+
+        if self.node >= MAX_NODES {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "node".to_string(),
+                0,
+                true,
+                MAX_NODES as u64,
+                false,
+            )));
+        }
+
+        if self.mem_zone.size == 0 || self.mem_zone.size % DIMM_ALIGN != 0 {
+            bail!(
+                "Size of memory backend {} should be multiple of {} bytes",
+                self.mem_zone.id,
+                DIMM_ALIGN
+            );
+        }
+
+        if let Some(addr) = self.addr {
+            if addr % DIMM_ALIGN != 0 {
+                bail!("Address 0x{:x} of pc-dimm is not aligned", addr);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_dimm(vm_config: &mut VmConfig, dimm_config: &str) -> Result<DimmConfig> {
+    let mut cmd_parser = CmdParser::new("pc-dimm");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("memdev")
+        .push("addr")
+        .push("node");
+    cmd_parser.parse(dimm_config)?;
+
+    let mut dimm_cfg = DimmConfig {
+        id: cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "pc-dimm".to_string())
+        })?,
+        addr: cmd_parser
+            .get_value::<UnsignedInteger>("addr")
+            .map_err(|_| {
+                anyhow!(ConfigError::ConvertValueFailed(
+                    String::from("u64"),
+                    "addr".to_string()
+                ))
+            })?
+            .map(|addr| addr.0 as u64),
+        node: cmd_parser.get_value::<u32>("node")?.unwrap_or_default(),
+        ..Default::default()
+    };
+
+    let memdev = cmd_parser.get_value::<String>("memdev")?.with_context(|| {
+        ConfigError::FieldIsMissing("memdev".to_string(), "pc-dimm".to_string())
+    })?;
+    dimm_cfg.mem_zone = vm_config
+        .object
+        .mem_object
+        .remove(&memdev)
+        .with_context(|| format!("Object for memory-backend {} config not found", memdev))?;
+
+    dimm_cfg.check()?;
+    Ok(dimm_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dimm() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("memory-backend-ram,size=1G,id=mem0")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,size=1G,id=mem1")
+            .is_ok());
+        assert!(vm_config
+            .add_object("memory-backend-ram,size=100M,id=mem2")
+            .is_ok());
+
+        let dimm_cfg = parse_dimm(&mut vm_config, "pc-dimm,id=dimm0,memdev=mem0").unwrap();
+        assert_eq!(dimm_cfg.id, "dimm0");
+        assert_eq!(dimm_cfg.addr, None);
+        assert_eq!(dimm_cfg.node, 0);
+        assert_eq!(dimm_cfg.mem_zone.size, 1 << 30);
+        // Memory backend can only be used once.
+        assert!(parse_dimm(&mut vm_config, "pc-dimm,id=dimm1,memdev=mem0").is_err());
+
+        let dimm_cfg = parse_dimm(
+            &mut vm_config,
+            "pc-dimm,id=dimm1,memdev=mem1,addr=0x100000000,node=1",
+        )
+        .unwrap();
+        assert_eq!(dimm_cfg.addr, Some(0x1_0000_0000));
+        assert_eq!(dimm_cfg.node, 1);
+
+        assert!(parse_dimm(&mut vm_config, "pc-dimm,id=dimm2,memdev=mem2").is_err());
+        assert!(parse_dimm(&mut vm_config, "pc-dimm,memdev=mem3").is_err());
+    }
+}
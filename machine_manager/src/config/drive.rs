@@ -21,8 +21,9 @@ use serde::{Deserialize, Serialize};
 
 use super::{error::ConfigError, pci_args_check, M};
 use crate::config::{
-    check_arg_too_long, get_chardev_socket_path, memory_unit_conversion, CmdParser, ConfigCheck,
-    ExBool, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, get_chardev_socket_path, get_queue_iothread, memory_unit_conversion,
+    split_queue_iothreads, CmdParser, ConfigCheck, ExBool, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
+    MAX_PATH_LENGTH, MAX_STRING_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::qmp_schema;
 use util::aio::{aio_probe, AioEngine, WriteZeroesState};
@@ -65,6 +66,10 @@ pub struct BlkDevConfig {
     pub direct: bool,
     pub serial_num: Option<String>,
     pub iothread: Option<String>,
+    /// Iothreads which the queues are assigned to in turn, empty if all the queues run
+    /// in `iothread`.
+    #[serde(default)]
+    pub queue_iothreads: Vec<String>,
     pub iops: Option<u64>,
     pub queues: u16,
     pub boot_index: Option<u8>,
@@ -77,6 +82,13 @@ pub struct BlkDevConfig {
     pub format: DiskFormat,
     pub l2_cache_size: Option<u64>,
     pub refcount_cache_size: Option<u64>,
+    /// Time in microseconds that the iothread keeps polling the queues with guest
+    /// notifications disabled since the last request, 0 means disabled.
+    #[serde(default)]
+    pub poll_us: u64,
+    /// Max number of requests submitted to the backend and not completed yet, 0 means no limit.
+    #[serde(default)]
+    pub max_inflight: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +107,7 @@ impl Default for BlkDevConfig {
             direct: true,
             serial_num: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
             iops: None,
             queues: 1,
             boot_index: None,
@@ -107,10 +120,19 @@ impl Default for BlkDevConfig {
             format: DiskFormat::Raw,
             l2_cache_size: None,
             refcount_cache_size: None,
+            poll_us: 0,
+            max_inflight: 0,
         }
     }
 }
 
+impl BlkDevConfig {
+    /// Get the iothread of the queue `index`.
+    pub fn queue_iothread(&self, index: usize) -> Option<&String> {
+        get_queue_iothread(self.iothread.as_ref(), &self.queue_iothreads, index)
+    }
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DiskFormat {
     Raw,
@@ -277,6 +299,9 @@ impl ConfigCheck for BlkDevConfig {
                 MAX_STRING_LENGTH,
             )));
         }
+        for iothread in self.queue_iothreads.iter() {
+            check_arg_too_long(iothread, "iothread name")?;
+        }
 
         if self.queues < 1 || self.queues > MAX_VIRTIO_QUEUE as u16 {
             return Err(anyhow!(ConfigError::IllegalValue(
@@ -302,6 +327,15 @@ impl ConfigCheck for BlkDevConfig {
             bail!("Queue size should be power of 2!");
         }
 
+        if self.poll_us != 0 {
+            if self.iothread.is_none() {
+                bail!("Property \'poll-us\' of block device needs an iothread");
+            }
+            if self.iops.is_some() {
+                bail!("Property \'poll-us\' of block device conflicts with IO limits");
+            }
+        }
+
         let fake_drive = DriveConfig {
             path_on_host: self.path_on_host.clone(),
             direct: self.direct,
@@ -390,7 +424,12 @@ pub fn parse_blk(
         .push("serial")
         .push("iothread")
         .push("num-queues")
-        .push("queue-size");
+        .push("queue-size")
+        .push("poll-us")
+        .push("max-inflight")
+        .push("vectors")
+        .push("irq-coalesce-us")
+        .push("irq-coalesce-frames");
 
     cmd_parser.parse(drive_config)?;
 
@@ -405,9 +444,8 @@ pub fn parse_blk(
         .get_value::<String>("drive")?
         .with_context(|| ConfigError::FieldIsMissing("drive".to_string(), "blk".to_string()))?;
 
-    if let Some(iothread) = cmd_parser.get_value::<String>("iothread")? {
-        blkdevcfg.iothread = Some(iothread);
-    }
+    (blkdevcfg.iothread, blkdevcfg.queue_iothreads) =
+        split_queue_iothreads(cmd_parser.get_value::<String>("iothread")?);
 
     if let Some(serial) = cmd_parser.get_value::<String>("serial")? {
         blkdevcfg.serial_num = Some(serial);
@@ -427,6 +465,14 @@ pub fn parse_blk(
         blkdevcfg.queue_size = queue_size;
     }
 
+    if let Some(poll_us) = cmd_parser.get_value::<u64>("poll-us")? {
+        blkdevcfg.poll_us = poll_us;
+    }
+
+    if let Some(max_inflight) = cmd_parser.get_value::<u32>("max-inflight")? {
+        blkdevcfg.max_inflight = max_inflight;
+    }
+
     let drive_arg = &vm_config
         .drives
         .remove(&blkdrive)
@@ -546,6 +592,9 @@ impl VmConfig {
             "pflash" => {
                 self.add_pflash(drive_config)?;
             }
+            "virtio" => {
+                self.add_virtio_drive(drive_config)?;
+            }
             _ => {
                 bail!("Unknow 'if' argument: {:?}", drive_type.as_str());
             }
@@ -639,6 +688,14 @@ impl VmConfig {
             device_info = format!("{},bootindex={}", device_info, boot_index);
         }
 
+        if let Some(poll_us) = &args.poll_us {
+            device_info = format!("{},poll-us={}", device_info, poll_us);
+        }
+
+        if let Some(max_inflight) = &args.max_inflight {
+            device_info = format!("{},max-inflight={}", device_info, max_inflight);
+        }
+
         self.devices.push((args.driver.clone(), device_info));
     }
     /// Delete drive config in vm config by id.
@@ -734,6 +791,23 @@ mod tests {
         assert_eq!(blk_device_config.read_only, false);
         assert_eq!(blk_device_config.serial_num, Some(String::from("111111")));
         assert_eq!(blk_device_config.queues, 4);
+        assert_eq!(blk_device_config.iothread, Some(String::from("iothread1")));
+        assert!(blk_device_config.queue_iothreads.is_empty());
+
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg_res = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,iothread=iothread1:iothread2,num-queues=4",
+            None,
+        );
+        let blk_device_config = blk_cfg_res.unwrap();
+        assert_eq!(blk_device_config.iothread, Some(String::from("iothread1")));
+        assert_eq!(
+            blk_device_config.queue_iothread(3),
+            Some(&String::from("iothread2"))
+        );
 
         let mut vm_config = VmConfig::default();
         assert!(vm_config
@@ -747,6 +821,71 @@ mod tests {
         assert!(blk_cfg_res.is_err()); // Can not find drive named "rootfs1".
     }
 
+    #[test]
+    fn test_blk_poll_us_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,iothread=iothread1,poll-us=50",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.poll_us, 50);
+
+        // Polling needs an iothread.
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        assert!(parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,poll-us=50",
+            None,
+        )
+        .is_err());
+
+        // Polling conflicts with IO limits.
+        assert!(vm_config
+            .add_drive(
+                "id=rootfs,file=/path/to/rootfs,readonly=off,direct=on,throttling.iops-total=200"
+            )
+            .is_ok());
+        assert!(parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,iothread=iothread1,poll-us=50",
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_blk_max_inflight_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs,max-inflight=16",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.max_inflight, 16);
+
+        assert!(vm_config
+            .add_drive("id=rootfs,file=/path/to/rootfs,readonly=off,direct=on")
+            .is_ok());
+        let blk_cfg = parse_blk(
+            &mut vm_config,
+            "virtio-blk-device,drive=rootfs,id=rootfs",
+            None,
+        )
+        .unwrap();
+        assert_eq!(blk_cfg.max_inflight, 0);
+    }
+
     #[test]
     fn test_pci_block_config_cmdline_parser() {
         let mut vm_config = VmConfig::default();
@@ -0,0 +1,168 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::error::ConfigError;
+use crate::config::{check_path_too_long, CmdParser, ConfigCheck, VmConfig};
+
+/// Max length of fw_cfg file name, including the terminating null byte.
+const FW_CFG_MAX_FILE_PATH: usize = 56;
+/// Prefix of the file names reserved for user.
+const FW_CFG_USER_FILE_PREFIX: &str = "opt/";
+
+/// Config structure for the file passed to guest through fw_cfg device.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FwCfgConfig {
+    /// Name of the fw_cfg file.
+    pub name: String,
+    /// Host file whose content is passed.
+    pub file: Option<String>,
+    /// String which is passed as the content.
+    pub string: Option<String>,
+}
+
+impl ConfigCheck for FwCfgConfig {
+    fn check(&self) -> Result<()> {
+        if !self.name.starts_with(FW_CFG_USER_FILE_PREFIX)
+            || self.name.len() == FW_CFG_USER_FILE_PREFIX.len()
+        {
+            bail!(
+                "Name {:?} of fw_cfg file should start with {:?}",
+                self.name,
+                FW_CFG_USER_FILE_PREFIX
+            );
+        }
+        if self.name.len() >= FW_CFG_MAX_FILE_PATH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "fw_cfg name".to_string(),
+                FW_CFG_MAX_FILE_PATH - 1,
+            )));
+        }
+
+        match (&self.file, &self.string) {
+            (Some(file), None) => {
+                check_path_too_long(file, "fw_cfg file")?;
+                if !Path::new(file).is_file() {
+                    return Err(anyhow!(ConfigError::UnRegularFile(
+                        "Input fw_cfg file".to_string()
+                    )));
+                }
+            }
+            (None, Some(_)) => {}
+            _ => bail!("Either \'file\' or \'string\' should be set for fw_cfg"),
+        }
+
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Add file passed to guest through fw_cfg device to `VmConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `fw_cfg` - Config args of fw_cfg file.
+    pub fn add_fw_cfg(&mut self, fw_cfg: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("fw_cfg");
+        cmd_parser.push("").push("name").push("file").push("string");
+        cmd_parser.parse(fw_cfg)?;
+
+        let name = match cmd_parser.get_value::<String>("name")? {
+            Some(name) => name,
+            None => cmd_parser.get_value::<String>("")?.with_context(|| {
+                ConfigError::FieldIsMissing("name".to_string(), "fw_cfg".to_string())
+            })?,
+        };
+        let config = FwCfgConfig {
+            name,
+            file: cmd_parser.get_value::<String>("file")?,
+            string: cmd_parser.get_value::<String>("string")?,
+        };
+        config.check()?;
+
+        if self.fw_cfg.iter().any(|c| c.name == config.name) {
+            return Err(anyhow!(ConfigError::IdRepeat(
+                "fw_cfg".to_string(),
+                config.name
+            )));
+        }
+        self.fw_cfg.push(config);
+        Ok(())
+    }
+}
+
+/// Parse the config of vmcoreinfo device, which has no properties except id.
+pub fn parse_vmcoreinfo(cfg_args: &str) -> Result<()> {
+    let mut cmd_parser = CmdParser::new("vmcoreinfo");
+    cmd_parser.push("").push("id");
+    cmd_parser.parse(cfg_args)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn test_add_fw_cfg() {
+        let file = "/tmp/test_add_fw_cfg.ign";
+        File::create(file).unwrap();
+        let mut vm_config = VmConfig::default();
+
+        assert!(vm_config
+            .add_fw_cfg("name=opt/com.coreos/config,file=/tmp/test_add_fw_cfg.ign")
+            .is_ok());
+        assert!(vm_config
+            .add_fw_cfg("opt/org.test/str,string=hello")
+            .is_ok());
+        assert_eq!(vm_config.fw_cfg.len(), 2);
+        assert_eq!(vm_config.fw_cfg[0].name, "opt/com.coreos/config");
+        assert_eq!(vm_config.fw_cfg[0].file, Some(file.to_string()));
+        assert_eq!(vm_config.fw_cfg[1].name, "opt/org.test/str");
+        assert_eq!(vm_config.fw_cfg[1].string, Some("hello".to_string()));
+
+        // Repeated name.
+        assert!(vm_config.add_fw_cfg("opt/org.test/str,string=bye").is_err());
+        // Name without "opt/" prefix or too long.
+        assert!(vm_config.add_fw_cfg("etc/test,string=hello").is_err());
+        assert!(vm_config.add_fw_cfg("opt/,string=hello").is_err());
+        let long_name = format!("opt/{}", "a".repeat(52));
+        assert!(vm_config
+            .add_fw_cfg(&format!("{},string=hello", long_name))
+            .is_err());
+        // Neither or both of file and string.
+        assert!(vm_config.add_fw_cfg("opt/org.test/none").is_err());
+        assert!(vm_config
+            .add_fw_cfg("opt/org.test/both,file=/tmp/test_add_fw_cfg.ign,string=hello")
+            .is_err());
+        // File not exists.
+        assert!(vm_config
+            .add_fw_cfg("opt/org.test/nofile,file=/tmp/test_add_fw_cfg_none")
+            .is_err());
+        assert_eq!(vm_config.fw_cfg.len(), 2);
+
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_parse_vmcoreinfo() {
+        assert!(parse_vmcoreinfo("vmcoreinfo").is_ok());
+        assert!(parse_vmcoreinfo("vmcoreinfo,id=vmcoreinfo0").is_ok());
+        assert!(parse_vmcoreinfo("vmcoreinfo,addr=0x1").is_err());
+    }
+}
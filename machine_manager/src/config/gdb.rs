@@ -0,0 +1,85 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::VmConfig;
+
+/// Address gdbstub listens on if the ip is omitted.
+const DEFAULT_GDB_IP: &str = "127.0.0.1";
+
+/// Config structure of gdbstub.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GdbConfig {
+    /// Tcp address which gdbstub listens on, in format of `<ip>:<port>`.
+    pub addr: String,
+}
+
+/// Parse `-gdb` cmdline, which is in format of `tcp:[<ip>]:<port>`.
+pub fn parse_gdb(gdb_config: &str) -> Result<GdbConfig> {
+    let (ip, port) = match gdb_config
+        .strip_prefix("tcp:")
+        .and_then(|addr| addr.rsplit_once(':'))
+    {
+        Some(addr) => addr,
+        None => bail!("Invalid gdb config {}, only tcp is supported", gdb_config),
+    };
+
+    let ip = if ip.is_empty() { DEFAULT_GDB_IP } else { ip };
+    if ip.parse::<Ipv4Addr>().is_err() {
+        bail!("Invalid ip address {}", ip);
+    }
+    if port.parse::<u16>().is_err() {
+        bail!("Invalid ip port {}", port);
+    }
+
+    Ok(GdbConfig {
+        addr: format!("{}:{}", ip, port),
+    })
+}
+
+impl VmConfig {
+    /// Add gdbstub config.
+    pub fn add_gdb(&mut self, gdb_config: &str) -> Result<()> {
+        if cfg!(target_arch = "aarch64") {
+            bail!("Gdbstub is only supported on x86_64");
+        }
+        self.gdb = Some(parse_gdb(gdb_config)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gdb() {
+        assert_eq!(parse_gdb("tcp::1234").unwrap().addr, "127.0.0.1:1234");
+        assert_eq!(parse_gdb("tcp:0.0.0.0:1234").unwrap().addr, "0.0.0.0:1234");
+        assert!(parse_gdb("tcp:1234").is_err());
+        assert!(parse_gdb("tcp::65536").is_err());
+        assert!(parse_gdb("tcp:300.0.0.1:1234").is_err());
+        assert!(parse_gdb("unix:/tmp/gdb.sock").is_err());
+
+        let mut vm_config = VmConfig::default();
+        if cfg!(target_arch = "x86_64") {
+            assert!(vm_config.add_gdb("tcp::1234").is_ok());
+            assert_eq!(vm_config.gdb.unwrap().addr, "127.0.0.1:1234");
+        } else {
+            assert!(vm_config.add_gdb("tcp::1234").is_err());
+        }
+    }
+}
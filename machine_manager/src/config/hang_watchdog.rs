@@ -0,0 +1,79 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, ConfigError, ExBool, VmConfig};
+
+/// Max timeout of hang watchdog in seconds.
+const MAX_HANG_TIMEOUT: u64 = 3600;
+
+/// Config of the watchdog which detects the stuck main loop and iothreads.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HangWatchdogConfig {
+    /// Seconds a loop can stay busy before it's regarded as stuck.
+    pub timeout: u64,
+    /// Whether to emit `THREAD_HANG` event when a stuck loop is detected.
+    pub event: bool,
+}
+
+impl VmConfig {
+    /// Add '-hang-watchdog' config to `VmConfig`, in format of
+    /// `timeout=<seconds>[,event=on|off]`.
+    pub fn add_hang_watchdog(&mut self, hang_watchdog: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("hang-watchdog");
+        cmd_parser.push("timeout").push("event");
+        cmd_parser.parse(hang_watchdog)?;
+
+        let timeout = cmd_parser.get_value::<u64>("timeout")?.with_context(|| {
+            ConfigError::FieldIsMissing("timeout".to_string(), "hang-watchdog".to_string())
+        })?;
+        if timeout == 0 || timeout > MAX_HANG_TIMEOUT {
+            bail!(
+                "timeout of hang-watchdog should be in range [1, {}]",
+                MAX_HANG_TIMEOUT
+            );
+        }
+        let event = cmd_parser
+            .get_value::<ExBool>("event")?
+            .map(bool::from)
+            .unwrap_or(false);
+        self.hang_watchdog = Some(HangWatchdogConfig { timeout, event });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_hang_watchdog() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_hang_watchdog("timeout=10").unwrap();
+        assert_eq!(
+            vm_config.hang_watchdog,
+            Some(HangWatchdogConfig {
+                timeout: 10,
+                event: false,
+            })
+        );
+        vm_config.add_hang_watchdog("timeout=5,event=on").unwrap();
+        assert!(vm_config.hang_watchdog.as_ref().unwrap().event);
+
+        assert!(vm_config.add_hang_watchdog("event=on").is_err());
+        assert!(vm_config.add_hang_watchdog("timeout=0").is_err());
+        assert!(vm_config.add_hang_watchdog("timeout=3601").is_err());
+        assert!(vm_config.add_hang_watchdog("timeout=10,event=x").is_err());
+    }
+}
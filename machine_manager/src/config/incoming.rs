@@ -22,6 +22,10 @@ pub enum MigrateMode {
     File,
     Unix,
     Tcp,
+    Exec,
+    /// Exec mode whose command only relays the migration data, without replies.
+    ExecOneway,
+    Fd,
     Unknown,
 }
 
@@ -31,6 +35,9 @@ impl From<&str> for MigrateMode {
             "file" | "File" | "FILE" => MigrateMode::File,
             "unix" | "Unix" | "UNIX" => MigrateMode::Unix,
             "tcp" | "Tcp" | "TCP" => MigrateMode::Tcp,
+            "exec" | "Exec" | "EXEC" => MigrateMode::Exec,
+            "exec-oneway" | "Exec-oneway" | "EXEC-ONEWAY" => MigrateMode::ExecOneway,
+            "fd" | "Fd" | "FD" => MigrateMode::Fd,
             _ => MigrateMode::Unknown,
         }
     }
@@ -38,6 +45,17 @@ impl From<&str> for MigrateMode {
 
 /// Parse `-incoming` cmdline to migrate mode and path.
 pub fn parse_incoming_uri(uri: &str) -> Result<(MigrateMode, String)> {
+    // Command of exec mode may contain ':', so only split the prefix.
+    if let Some((mode, path)) = uri.split_once(':') {
+        let mode = MigrateMode::from(mode);
+        if mode == MigrateMode::Exec || mode == MigrateMode::ExecOneway || mode == MigrateMode::Fd {
+            if path.is_empty() {
+                bail!("Invalid incoming uri {}", uri);
+            }
+            return Ok((mode, String::from(path)));
+        }
+    }
+
     let parse_vec: Vec<&str> = uri.split(':').collect();
     if parse_vec.len() == 2 {
         match MigrateMode::from(parse_vec[0]) {
@@ -78,6 +96,14 @@ impl VmConfig {
             MigrateMode::File => (MigrateMode::File, uri),
             MigrateMode::Unix => (MigrateMode::Unix, uri),
             MigrateMode::Tcp => (MigrateMode::Tcp, uri),
+            MigrateMode::Exec => (MigrateMode::Exec, uri),
+            MigrateMode::ExecOneway => (MigrateMode::ExecOneway, uri),
+            MigrateMode::Fd => {
+                if uri.parse::<i32>().map_or(true, |fd| fd < 0) {
+                    bail!("Invalid incoming fd {}", uri);
+                }
+                (MigrateMode::Fd, uri)
+            }
             MigrateMode::Unknown => {
                 bail!("Unsupported incoming unix path type")
             }
@@ -97,7 +123,10 @@ mod tests {
         assert_eq!(MigrateMode::from("File"), MigrateMode::File);
         assert_eq!(MigrateMode::from("UNIX"), MigrateMode::Unix);
         assert_eq!(MigrateMode::from("tcp"), MigrateMode::Tcp);
-        assert_eq!(MigrateMode::from("fd"), MigrateMode::Unknown);
+        assert_eq!(MigrateMode::from("exec"), MigrateMode::Exec);
+        assert_eq!(MigrateMode::from("exec-oneway"), MigrateMode::ExecOneway);
+        assert_eq!(MigrateMode::from("fd"), MigrateMode::Fd);
+        assert_eq!(MigrateMode::from("rdma"), MigrateMode::Unknown);
     }
 
     #[test]
@@ -127,6 +156,24 @@ mod tests {
         let incoming_case5 = "tcp:192.168.1.2:65568";
         let result_5 = parse_incoming_uri(incoming_case5);
         assert!(result_5.is_err());
+
+        let incoming_case6 = "exec:ssh host nc 192.168.1.2:2022";
+        let result_6 = parse_incoming_uri(incoming_case6).unwrap();
+        assert_eq!(result_6.0, MigrateMode::Exec);
+        assert_eq!(result_6.1, "ssh host nc 192.168.1.2:2022".to_string());
+
+        let incoming_case7 = "fd:migfd";
+        let result_7 = parse_incoming_uri(incoming_case7).unwrap();
+        assert_eq!(result_7.0, MigrateMode::Fd);
+        assert_eq!(result_7.1, "migfd".to_string());
+
+        let incoming_case8 = "exec:";
+        assert!(parse_incoming_uri(incoming_case8).is_err());
+
+        let incoming_case9 = "exec-oneway:gzip -dc /tmp/vm.gz";
+        let result_9 = parse_incoming_uri(incoming_case9).unwrap();
+        assert_eq!(result_9.0, MigrateMode::ExecOneway);
+        assert_eq!(result_9.1, "gzip -dc /tmp/vm.gz".to_string());
     }
 
     #[test]
@@ -147,6 +194,16 @@ mod tests {
             (MigrateMode::Unix, "/tmp/stratovirt.sock".to_string())
         );
 
+        let mut vm_config_case3 = VmConfig::default();
+        assert!(vm_config_case3.add_incoming("fd:10").is_ok());
+        assert_eq!(
+            vm_config_case3.incoming.unwrap(),
+            (MigrateMode::Fd, "10".to_string())
+        );
+
+        let mut vm_config_case4 = VmConfig::default();
+        assert!(vm_config_case4.add_incoming("fd:migfd").is_err());
+
         let mut vm_config_case2 = VmConfig::default();
         assert!(vm_config_case2.add_incoming("unknown:/tmp/").is_err());
     }
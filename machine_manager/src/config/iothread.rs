@@ -10,11 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
-use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, VmConfig};
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck, IntegerList, VmConfig, MAX_NODES};
 
 const MAX_IOTHREAD_NUM: usize = 8;
 
@@ -22,25 +22,91 @@ const MAX_IOTHREAD_NUM: usize = 8;
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct IothreadConfig {
     pub id: String,
+    /// Host CPUs which the iothread is pinned to.
+    #[serde(default)]
+    pub affinity: Option<Vec<u64>>,
+    /// Host NUMA nodes which the memory allocated by the iothread is bound to.
+    #[serde(default)]
+    pub host_nodes: Option<Vec<u32>>,
 }
 
 impl ConfigCheck for IothreadConfig {
     fn check(&self) -> Result<()> {
-        check_arg_too_long(&self.id, "iothread id")
+        check_arg_too_long(&self.id, "iothread id")?;
+        if let Some(host_nodes) = &self.host_nodes {
+            if host_nodes.iter().any(|node| *node >= MAX_NODES) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "host_nodes".to_string(),
+                    0,
+                    true,
+                    MAX_NODES as u64,
+                    false,
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split the `iothread` argument of a virtio device, which is either one iothread for all
+/// the queues, or a colon separated list of iothreads assigned to the queues (or queue pairs)
+/// in turn. Return the iothread of the device, which is the first one, and the per-queue
+/// iothreads if more than one is given.
+pub fn split_queue_iothreads(iothread: Option<String>) -> (Option<String>, Vec<String>) {
+    let iothread = match iothread {
+        Some(iothread) => iothread,
+        None => return (None, Vec::new()),
+    };
+    let iothreads: Vec<String> = iothread.split(':').map(String::from).collect();
+    if iothreads.len() == 1 {
+        return (Some(iothread), Vec::new());
+    }
+    (Some(iothreads[0].clone()), iothreads)
+}
+
+/// Get the iothread of the queue (or queue pair) `index`.
+pub fn get_queue_iothread<'a>(
+    iothread: Option<&'a String>,
+    queue_iothreads: &'a [String],
+    index: usize,
+) -> Option<&'a String> {
+    if queue_iothreads.is_empty() {
+        return iothread;
     }
+    queue_iothreads.get(index % queue_iothreads.len())
 }
 
 impl VmConfig {
     /// Add new iothread device to `VmConfig`.
     pub fn add_iothread(&mut self, iothread_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("iothread");
-        cmd_parser.push("").push("id");
+        cmd_parser
+            .push("")
+            .push("id")
+            .push("affinity")
+            .push("host-nodes");
         cmd_parser.parse(iothread_config)?;
 
         let mut iothread = IothreadConfig::default();
         if let Some(id) = cmd_parser.get_value::<String>("id")? {
             iothread.id = id;
         }
+        if let Some(affinity) = cmd_parser
+            .get_value::<IntegerList>("affinity")
+            .with_context(|| {
+                ConfigError::ConvertValueFailed(String::from("u64"), "affinity".to_string())
+            })?
+        {
+            iothread.affinity = Some(affinity.0);
+        }
+        if let Some(host_nodes) = cmd_parser
+            .get_value::<IntegerList>("host-nodes")
+            .with_context(|| {
+                ConfigError::ConvertValueFailed(String::from("u32"), "host-nodes".to_string())
+            })?
+        {
+            iothread.host_nodes = Some(host_nodes.0.iter().map(|e| *e as u32).collect());
+        }
         iothread.check()?;
 
         if self.iothreads.is_some() {
@@ -99,10 +165,65 @@ mod tests {
         assert!(vm_config.add_object("iothread,id=iothread8").is_err());
     }
 
+    #[test]
+    fn test_iothread_host_nodes_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("iothread,id=iothread0,host-nodes=0-1")
+            .is_ok());
+        let iothreads = vm_config.iothreads.as_ref().unwrap();
+        assert_eq!(iothreads[0].host_nodes, Some(vec![0, 1]));
+
+        assert!(vm_config
+            .add_object("iothread,id=iothread1,host-nodes=128")
+            .is_err());
+    }
+
     #[test]
     fn test_iothread_config_cmdline_parser_03() {
         let mut vm_config = VmConfig::default();
         assert!(vm_config.add_object("iothread,id=iothread0").is_ok());
         assert!(vm_config.add_object("iothread,id=iothread0").is_err());
     }
+
+    #[test]
+    fn test_iothread_config_affinity() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_object("iothread,id=iothread0,affinity=0-2:5")
+            .is_ok());
+        assert!(vm_config.add_object("iothread,id=iothread1").is_ok());
+        assert!(vm_config
+            .add_object("iothread,id=iothread2,affinity=2-1")
+            .is_err());
+        let iothreads = vm_config.iothreads.unwrap();
+        assert_eq!(iothreads[0].affinity, Some(vec![0, 1, 2, 5]));
+        assert_eq!(iothreads[1].affinity, None);
+    }
+
+    #[test]
+    fn test_split_queue_iothreads() {
+        assert_eq!(split_queue_iothreads(None), (None, Vec::new()));
+
+        let (iothread, queue_iothreads) = split_queue_iothreads(Some("iothread0".to_string()));
+        assert_eq!(iothread, Some("iothread0".to_string()));
+        assert!(queue_iothreads.is_empty());
+        assert_eq!(
+            get_queue_iothread(iothread.as_ref(), &queue_iothreads, 3),
+            Some(&"iothread0".to_string())
+        );
+
+        let (iothread, queue_iothreads) =
+            split_queue_iothreads(Some("iothread0:iothread1".to_string()));
+        assert_eq!(iothread, Some("iothread0".to_string()));
+        assert_eq!(queue_iothreads.len(), 2);
+        assert_eq!(
+            get_queue_iothread(iothread.as_ref(), &queue_iothreads, 1),
+            Some(&"iothread1".to_string())
+        );
+        assert_eq!(
+            get_queue_iothread(iothread.as_ref(), &queue_iothreads, 2),
+            Some(&"iothread0".to_string())
+        );
+    }
 }
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::HashMap;
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -17,9 +18,11 @@ use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
 use crate::config::{
-    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, IntegerList, VmConfig,
-    MAX_NODES,
+    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, ExBool, IntegerList,
+    SgxEpcConfig, VmConfig, MAX_NODES,
 };
+#[cfg(target_arch = "x86_64")]
+use crate::config::{parse_sgx_epc, sgx_epc_push_params};
 
 const DEFAULT_CPUS: u8 = 1;
 const DEFAULT_THREADS: u8 = 1;
@@ -31,8 +34,14 @@ const DEFAULT_MAX_CPUS: u8 = 1;
 const DEFAULT_MEMSIZE: u64 = 256;
 const MAX_NR_CPUS: u64 = 254;
 const MIN_NR_CPUS: u64 = 1;
+// Max vector length in bits of SVE defined by architecture.
+const MAX_SVE_VECTOR_LENGTH: u64 = 2048;
+// Range of guest TSC frequency in Hz, kvm takes the frequency in kHz as u32.
+const MIN_TSC_FREQUENCY: u64 = 1_000;
+const MAX_TSC_FREQUENCY: u64 = u32::MAX as u64 * 1_000;
 const MAX_MEMSIZE: u64 = 549_755_813_888;
 const MIN_MEMSIZE: u64 = 134_217_728;
+const MAX_MEM_SLOTS: u64 = 256;
 pub const K: u64 = 1024;
 pub const M: u64 = 1024 * 1024;
 pub const G: u64 = 1024 * 1024 * 1024;
@@ -93,6 +102,30 @@ pub struct MemZoneConfig {
     pub share: bool,
     pub prealloc: bool,
     pub memfd: bool,
+    /// Map the backend file read-only, guest writes to it are discarded.
+    #[serde(default)]
+    pub readonly: bool,
+    /// The backend file is on persistent memory.
+    #[serde(default)]
+    pub pmem: bool,
+    /// Back memory with anonymous hugepages.
+    #[serde(default)]
+    pub hugetlb: bool,
+    /// Size of hugepage, 0 means the default hugepage size of host.
+    #[serde(default)]
+    pub hugetlb_size: u64,
+    /// Seal the size of memfd.
+    #[serde(default)]
+    pub seal: bool,
+    /// Number of threads to preallocate memory, 0 means sized to host CPUs.
+    #[serde(default)]
+    pub prealloc_threads: u8,
+    /// Allow KSM to merge identical pages of this backend.
+    #[serde(default)]
+    pub merge: bool,
+    /// Transparent hugepage policy, None means following the host setting.
+    #[serde(default)]
+    pub thp: Option<bool>,
 }
 
 impl Default for MemZoneConfig {
@@ -107,6 +140,14 @@ impl Default for MemZoneConfig {
             share: false,
             prealloc: false,
             memfd: false,
+            readonly: false,
+            pmem: false,
+            hugetlb: false,
+            hugetlb_size: 0,
+            seal: false,
+            prealloc_threads: 0,
+            merge: false,
+            thp: None,
         }
     }
 }
@@ -120,6 +161,12 @@ pub struct MachineMemConfig {
     pub mem_share: bool,
     pub mem_prealloc: bool,
     pub mem_zones: Option<Vec<MemZoneConfig>>,
+    /// Number of DIMM slots for memory hotplug.
+    #[serde(default)]
+    pub slots: u32,
+    /// Maximum memory size including hotplugged DIMMs.
+    #[serde(default)]
+    pub max_mem: u64,
 }
 
 impl Default for MachineMemConfig {
@@ -131,13 +178,70 @@ impl Default for MachineMemConfig {
             mem_share: false,
             mem_prealloc: false,
             mem_zones: None,
+            slots: 0,
+            max_mem: 0,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CpuConfig {
     pub pmu: PmuConfig,
+    pub sve: SveConfig,
+    /// Fixed TSC frequency in Hz of guest, host TSC frequency is used if it's not set.
+    #[serde(default)]
+    pub tsc_frequency: Option<u64>,
+    /// Whether invariant TSC is exposed to guest.
+    #[serde(default)]
+    pub invtsc: bool,
+    /// Whether steal time is reported to guest.
+    #[serde(default = "default_steal_time")]
+    pub steal_time: bool,
+    /// Whether IBRS/IBPB/STIBP are exposed to guest, host support is followed if not set.
+    #[serde(default)]
+    pub spec_ctrl: Option<bool>,
+    /// Whether SSBD is exposed to guest, host support is followed if not set.
+    #[serde(default)]
+    pub ssbd: Option<bool>,
+    /// Whether MD_CLEAR is exposed to guest, host support is followed if not set.
+    #[serde(default)]
+    pub md_clear: Option<bool>,
+    /// Whether speculative store bypass and indirect branch speculation are disabled
+    /// on host for vCPU threads, which isolates the VM from other tasks on host.
+    #[serde(default)]
+    pub spec_isolation: bool,
+}
+
+fn default_steal_time() -> bool {
+    true
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        CpuConfig {
+            pmu: PmuConfig::default(),
+            sve: SveConfig::default(),
+            tsc_frequency: None,
+            invtsc: false,
+            steal_time: default_steal_time(),
+            spec_ctrl: None,
+            ssbd: None,
+            md_clear: None,
+            spec_isolation: false,
+        }
+    }
+}
+
+impl CpuConfig {
+    /// Check whether VM with this cpu config can be migrated. Guest with invariant
+    /// TSC relies on a constant TSC frequency, which is only guaranteed across hosts
+    /// when the frequency is pinned.
+    pub fn check_migratable(&self) -> Result<()> {
+        if self.invtsc && self.tsc_frequency.is_none() {
+            bail!("Migration is blocked because invtsc is enabled without tsc-frequency");
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -147,6 +251,15 @@ pub enum PmuConfig {
     Off,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct SveConfig {
+    /// Whether SVE is enabled for guest.
+    pub enabled: bool,
+    /// Vector lengths in bits supported by guest, all the lengths supported
+    /// by host are used if it's empty.
+    pub vector_lengths: Vec<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub enum ShutdownAction {
     #[default]
@@ -154,6 +267,32 @@ pub enum ShutdownAction {
     ShutdownActionPause,
 }
 
+/// Action taken when guest kernel panics, which is reported by pvpanic device.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PanicAction {
+    /// Only report the panic event.
+    #[default]
+    None,
+    Pause,
+    Shutdown,
+    /// Abort StratoVirt to generate the core dump of the process.
+    Coredump,
+}
+
+impl FromStr for PanicAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(PanicAction::None),
+            "pause" => Ok(PanicAction::Pause),
+            "shutdown" => Ok(PanicAction::Shutdown),
+            "coredump" => Ok(PanicAction::Coredump),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Config struct for machine-config.
 /// Contains some basic Vm config about cpu, memory, name.
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -169,7 +308,31 @@ pub struct MachineConfig {
     pub mem_config: MachineMemConfig,
     pub cpu_config: CpuConfig,
     pub shutdown_action: ShutdownAction,
+    #[serde(default)]
+    pub panic_action: PanicAction,
     pub battery: bool,
+    /// Number of entries of each vCPU's kvm dirty ring, 0 means dirty bitmap is used.
+    #[serde(default)]
+    pub dirty_ring_size: u32,
+    /// Host CPUs which each vCPU is pinned to, indexed by vCPU id.
+    #[serde(default)]
+    pub vcpu_affinity: HashMap<u8, Vec<u64>>,
+    /// Guest clock keeps running while VM is paused or not.
+    #[serde(default)]
+    pub clock_advance_on_pause: bool,
+    /// Whether HPET is emulated for guest, only used by x86_64 standard VM.
+    #[serde(default = "default_hpet")]
+    pub hpet: bool,
+    /// Id of the object which launches the confidential guest, such as SEV-SNP guest.
+    #[serde(default)]
+    pub confidential_guest: Option<String>,
+    /// SGX EPC sections exposed to guest, only used by x86_64 standard VM.
+    #[serde(default)]
+    pub sgx_epc: Vec<SgxEpcConfig>,
+}
+
+fn default_hpet() -> bool {
+    true
 }
 
 impl Default for MachineConfig {
@@ -187,7 +350,14 @@ impl Default for MachineConfig {
             mem_config: MachineMemConfig::default(),
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            panic_action: PanicAction::default(),
             battery: false,
+            dirty_ring_size: 0,
+            vcpu_affinity: HashMap::new(),
+            clock_advance_on_pause: false,
+            hpet: true,
+            confidential_guest: None,
+            sgx_epc: Vec::new(),
         }
     }
 }
@@ -199,6 +369,24 @@ impl ConfigCheck for MachineConfig {
             &self.mem_config.mem_size);
         }
 
+        if self.mem_config.slots != 0
+            && (self.mem_config.max_mem <= self.mem_config.mem_size
+                || self.mem_config.max_mem > MAX_MEMSIZE)
+        {
+            bail!(
+                "Max memory size must > memory size and <= 512GiB, current max memory size: {:?} bytes",
+                &self.mem_config.max_mem
+            );
+        }
+
+        if let Some(vcpu) = self.vcpu_affinity.keys().find(|id| **id >= self.max_cpus) {
+            bail!(
+                "vCPU {} of affinity is out of max cpus {}",
+                vcpu,
+                self.max_cpus
+            );
+        }
+
         Ok(())
     }
 }
@@ -217,9 +405,16 @@ impl VmConfig {
             .push("accel")
             .push("usb")
             .push("dump-guest-core")
-            .push("mem-share");
+            .push("mem-share")
+            .push("clock-advance-on-pause")
+            .push("confidential-guest-support");
         #[cfg(target_arch = "aarch64")]
         cmd_parser.push("gic-version");
+        #[cfg(target_arch = "x86_64")]
+        {
+            cmd_parser.push("hpet");
+            sgx_epc_push_params(&mut cmd_parser);
+        }
         cmd_parser.parse(mach_config)?;
 
         #[cfg(target_arch = "aarch64")]
@@ -258,6 +453,23 @@ impl VmConfig {
         if let Some(mem_share) = cmd_parser.get_value::<ExBool>("mem-share")? {
             self.machine_config.mem_config.mem_share = mem_share.into();
         }
+        if let Some(advance) = cmd_parser.get_value::<ExBool>("clock-advance-on-pause")? {
+            self.machine_config.clock_advance_on_pause = advance.into();
+        }
+        if let Some(id) = cmd_parser.get_value::<String>("confidential-guest-support")? {
+            self.machine_config.confidential_guest = Some(id);
+        }
+        #[cfg(target_arch = "x86_64")]
+        if let Some(hpet) = cmd_parser.get_value::<ExBool>("hpet")? {
+            self.machine_config.hpet = hpet.into();
+        }
+        #[cfg(target_arch = "x86_64")]
+        {
+            let sgx_epc = parse_sgx_epc(&cmd_parser)?;
+            if !sgx_epc.is_empty() {
+                self.machine_config.sgx_epc = sgx_epc;
+            }
+        }
 
         Ok(())
     }
@@ -265,7 +477,7 @@ impl VmConfig {
     /// Add '-accel' accelerator config to `VmConfig`.
     pub fn add_accel(&mut self, accel_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("accel");
-        cmd_parser.push("");
+        cmd_parser.push("").push("dirty-ring-size");
         cmd_parser.parse(accel_config)?;
 
         if let Some(accel) = cmd_parser.get_value::<String>("")? {
@@ -273,6 +485,12 @@ impl VmConfig {
                 bail!("Only \'kvm\' is supported for \'accel\'");
             }
         }
+        if let Some(size) = cmd_parser.get_value::<u32>("dirty-ring-size")? {
+            if size != 0 && !size.is_power_of_two() {
+                bail!("Dirty ring size {} should be power of 2", size);
+            }
+            self.machine_config.dirty_ring_size = size;
+        }
 
         Ok(())
     }
@@ -280,7 +498,11 @@ impl VmConfig {
     /// Add '-m' memory config to `VmConfig`.
     pub fn add_memory(&mut self, mem_config: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("m");
-        cmd_parser.push("").push("size");
+        cmd_parser
+            .push("")
+            .push("size")
+            .push("slots")
+            .push("maxmem");
 
         cmd_parser.parse(mem_config)?;
 
@@ -297,6 +519,29 @@ impl VmConfig {
 
         self.machine_config.mem_config.mem_size = mem;
 
+        let slots = cmd_parser.get_value::<u64>("slots")?;
+        let max_mem = cmd_parser
+            .get_value::<String>("maxmem")?
+            .map(|max_mem| memory_unit_conversion(&max_mem, M))
+            .transpose()?;
+        match (slots, max_mem) {
+            (Some(slots), Some(max_mem)) => {
+                if slots == 0 || slots > MAX_MEM_SLOTS {
+                    return Err(anyhow!(ConfigError::IllegalValue(
+                        "slots".to_string(),
+                        1,
+                        true,
+                        MAX_MEM_SLOTS,
+                        true
+                    )));
+                }
+                self.machine_config.mem_config.slots = slots as u32;
+                self.machine_config.mem_config.max_mem = max_mem;
+            }
+            (None, None) => {}
+            _ => bail!("Both \'slots\' and \'maxmem\' should be set for memory hotplug"),
+        }
+
         Ok(())
     }
 
@@ -341,6 +586,16 @@ impl VmConfig {
 
         let clusters = smp_read_and_check(&cmd_parser, "clusters", 1)?;
 
+        // Cluster is the topology level of arm, and die is the one of x86.
+        #[cfg(target_arch = "x86_64")]
+        if clusters > 1 {
+            bail!("clusters is not supported on x86_64");
+        }
+        #[cfg(target_arch = "aarch64")]
+        if dies > 1 {
+            bail!("dies is not supported on aarch64");
+        }
+
         let cores = smp_read_and_check(&cmd_parser, "cores", 0)?;
 
         let threads = smp_read_and_check(&cmd_parser, "threads", 0)?;
@@ -399,7 +654,17 @@ impl VmConfig {
     pub fn add_cpu_feature(&mut self, features: &str) -> Result<()> {
         let mut cmd_parser = CmdParser::new("cpu");
         cmd_parser.push("");
-        cmd_parser.push("pmu");
+        cmd_parser
+            .push("pmu")
+            .push("sve")
+            .push("sve-vl")
+            .push("tsc-frequency")
+            .push("invtsc")
+            .push("steal-time")
+            .push("spec-ctrl")
+            .push("ssbd")
+            .push("md-clear")
+            .push("spec-isolation");
         cmd_parser.parse(features)?;
         // Check PMU when actually enabling PMU.
         if let Some(k) = cmd_parser.get_value::<String>("pmu")? {
@@ -409,6 +674,93 @@ impl VmConfig {
                 _ => bail!("Invalid PMU option,must be one of \'on\" or \"off\"."),
             }
         }
+        if let Some(sve) = cmd_parser.get_value::<ExBool>("sve")? {
+            self.machine_config.cpu_config.sve.enabled = sve.into();
+        }
+        if let Some(vls) = cmd_parser
+            .get_value::<IntegerList>("sve-vl")
+            .with_context(|| {
+                ConfigError::ConvertValueFailed(String::from("u64"), "sve-vl".to_string())
+            })?
+        {
+            if !self.machine_config.cpu_config.sve.enabled {
+                bail!("SVE vector lengths are set while SVE is not enabled");
+            }
+            let mut vector_lengths = vls.0;
+            for vl in vector_lengths.iter() {
+                if *vl == 0 || *vl > MAX_SVE_VECTOR_LENGTH || *vl % 128 != 0 {
+                    bail!(
+                        "Invalid SVE vector length {}, it must be a multiple of 128 no more than {}",
+                        vl,
+                        MAX_SVE_VECTOR_LENGTH
+                    );
+                }
+            }
+            vector_lengths.sort_unstable();
+            vector_lengths.dedup();
+            self.machine_config.cpu_config.sve.vector_lengths = vector_lengths;
+        }
+        if let Some(freq) = cmd_parser.get_value::<u64>("tsc-frequency")? {
+            if !(MIN_TSC_FREQUENCY..=MAX_TSC_FREQUENCY).contains(&freq) {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "tsc-frequency".to_string(),
+                    MIN_TSC_FREQUENCY,
+                    true,
+                    MAX_TSC_FREQUENCY,
+                    true,
+                )));
+            }
+            self.machine_config.cpu_config.tsc_frequency = Some(freq);
+        }
+        if let Some(invtsc) = cmd_parser.get_value::<ExBool>("invtsc")? {
+            self.machine_config.cpu_config.invtsc = invtsc.into();
+        }
+        if let Some(steal_time) = cmd_parser.get_value::<ExBool>("steal-time")? {
+            self.machine_config.cpu_config.steal_time = steal_time.into();
+        }
+        if let Some(spec_ctrl) = cmd_parser.get_value::<ExBool>("spec-ctrl")? {
+            self.machine_config.cpu_config.spec_ctrl = Some(spec_ctrl.into());
+        }
+        if let Some(ssbd) = cmd_parser.get_value::<ExBool>("ssbd")? {
+            self.machine_config.cpu_config.ssbd = Some(ssbd.into());
+        }
+        if let Some(md_clear) = cmd_parser.get_value::<ExBool>("md-clear")? {
+            self.machine_config.cpu_config.md_clear = Some(md_clear.into());
+        }
+        if let Some(spec_isolation) = cmd_parser.get_value::<ExBool>("spec-isolation")? {
+            self.machine_config.cpu_config.spec_isolation = spec_isolation.into();
+        }
+        Ok(())
+    }
+
+    /// Add '-vcpu-affinity' config of vCPU to `VmConfig`.
+    pub fn add_vcpu_affinity(&mut self, affinity_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("vcpu-affinity");
+        cmd_parser.push("vcpu").push("affinity");
+        cmd_parser.parse(affinity_config)?;
+
+        let vcpu = cmd_parser.get_value::<u8>("vcpu")?.with_context(|| {
+            ConfigError::FieldIsMissing("vcpu".to_string(), "vcpu-affinity".to_string())
+        })?;
+        let affinity = cmd_parser
+            .get_value::<IntegerList>("affinity")
+            .with_context(|| {
+                ConfigError::ConvertValueFailed(String::from("u64"), "affinity".to_string())
+            })?
+            .with_context(|| {
+                ConfigError::FieldIsMissing("affinity".to_string(), "vcpu-affinity".to_string())
+            })?;
+        if self
+            .machine_config
+            .vcpu_affinity
+            .insert(vcpu, affinity.0)
+            .is_some()
+        {
+            return Err(anyhow!(ConfigError::IdRepeat(
+                "vcpu-affinity".to_string(),
+                vcpu.to_string()
+            )));
+        }
         Ok(())
     }
 
@@ -426,6 +778,23 @@ impl VmConfig {
         true
     }
 
+    /// Add '-action' config of guest events to `VmConfig`.
+    pub fn add_action(&mut self, action_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("action");
+        cmd_parser.push("panic");
+        cmd_parser.parse(action_config)?;
+
+        if let Some(action) = cmd_parser.get_value::<String>("panic")? {
+            self.machine_config.panic_action = PanicAction::from_str(&action).map_err(|_| {
+                anyhow!(
+                    "Invalid panic action {}, must be one of \"none\", \"pause\", \"shutdown\" or \"coredump\"",
+                    action
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     pub fn add_battery(&mut self) -> bool {
         self.machine_config.battery = true;
         true
@@ -528,6 +897,85 @@ impl VmConfig {
         if let Some(mem_prealloc) = cmd_parser.get_value::<ExBool>("mem-prealloc")? {
             return Ok(mem_prealloc.into());
         }
+        if let Some(prealloc) = cmd_parser.get_value::<ExBool>("prealloc")? {
+            return Ok(prealloc.into());
+        }
+        Ok(false)
+    }
+
+    fn get_mem_prealloc_threads(&self, cmd_parser: &CmdParser) -> Result<u8> {
+        if let Some(threads) = cmd_parser.get_value::<u8>("prealloc-threads")? {
+            if threads == 0 {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "prealloc-threads".to_string(),
+                    1,
+                    true,
+                    u8::MAX as u64,
+                    true,
+                )));
+            }
+            return Ok(threads);
+        }
+        Ok(0)
+    }
+
+    fn get_mem_readonly(&self, cmd_parser: &CmdParser) -> Result<bool> {
+        if let Some(readonly) = cmd_parser.get_value::<ExBool>("readonly")? {
+            return Ok(readonly.into());
+        }
+        Ok(false)
+    }
+
+    fn get_mem_merge(&self, cmd_parser: &CmdParser) -> Result<bool> {
+        if let Some(merge) = cmd_parser.get_value::<ExBool>("merge")? {
+            return Ok(merge.into());
+        }
+        Ok(false)
+    }
+
+    fn get_mem_thp(&self, cmd_parser: &CmdParser) -> Result<Option<bool>> {
+        Ok(cmd_parser.get_value::<ExBool>("thp")?.map(|thp| thp.into()))
+    }
+
+    fn get_mem_hugetlb(&self, cmd_parser: &CmdParser, mem_type: &str) -> Result<bool> {
+        if let Some(hugetlb) = cmd_parser.get_value::<ExBool>("hugetlb")? {
+            if mem_type.ne("memory-backend-memfd") {
+                bail!("Object type: {} does not support hugetlb", mem_type);
+            }
+            return Ok(hugetlb.into());
+        }
+        Ok(mem_type.eq("memory-backend-hugetlb"))
+    }
+
+    fn get_mem_seal(&self, cmd_parser: &CmdParser, mem_type: &str) -> Result<bool> {
+        let memfd = mem_type.eq("memory-backend-memfd") || mem_type.eq("memory-backend-hugetlb");
+        if let Some(seal) = cmd_parser.get_value::<ExBool>("seal")? {
+            if !memfd {
+                bail!("Object type: {} does not support seal", mem_type);
+            }
+            return Ok(seal.into());
+        }
+        Ok(memfd)
+    }
+
+    fn get_mem_hugetlb_size(&self, cmd_parser: &CmdParser) -> Result<u64> {
+        if let Some(size) = cmd_parser.get_value::<String>("hugetlbsize")? {
+            let size = memory_unit_conversion(&size, M)?;
+            if !size.is_power_of_two() || size < 64 * 1024 {
+                return Err(anyhow!(ConfigError::InvalidParam(
+                    "hugetlbsize".to_string(),
+                    size.to_string()
+                )));
+            }
+            return Ok(size);
+        }
+        Ok(0)
+    }
+
+    fn get_mem_pmem(&self, cmd_parser: &CmdParser) -> Result<bool> {
+        if let Some(pmem) = cmd_parser.get_value::<ExBool>("pmem")? {
+            return Ok(pmem.into());
+        }
         Ok(false)
     }
 
@@ -548,7 +996,16 @@ impl VmConfig {
             .push("share")
             .push("mem-path")
             .push("dump-guest-core")
-            .push("mem-prealloc");
+            .push("mem-prealloc")
+            .push("prealloc")
+            .push("prealloc-threads")
+            .push("readonly")
+            .push("pmem")
+            .push("hugetlb")
+            .push("hugetlbsize")
+            .push("seal")
+            .push("merge")
+            .push("thp");
         cmd_parser.parse(mem_zone)?;
 
         let zone_config = MemZoneConfig {
@@ -561,6 +1018,14 @@ impl VmConfig {
             mem_path: self.get_mem_path(&cmd_parser)?,
             prealloc: self.get_mem_prealloc(&cmd_parser)?,
             memfd: mem_type.eq("memory-backend-memfd"),
+            readonly: self.get_mem_readonly(&cmd_parser)?,
+            pmem: self.get_mem_pmem(&cmd_parser)?,
+            hugetlb: self.get_mem_hugetlb(&cmd_parser, &mem_type)?,
+            hugetlb_size: self.get_mem_hugetlb_size(&cmd_parser)?,
+            seal: self.get_mem_seal(&cmd_parser, &mem_type)?,
+            prealloc_threads: self.get_mem_prealloc_threads(&cmd_parser)?,
+            merge: self.get_mem_merge(&cmd_parser)?,
+            thp: self.get_mem_thp(&cmd_parser)?,
         };
 
         if (zone_config.mem_path.is_none() && mem_type.eq("memory-backend-file"))
@@ -568,6 +1033,49 @@ impl VmConfig {
         {
             bail!("Object type: {} config path err", mem_type);
         }
+        if (zone_config.readonly || zone_config.pmem) && mem_type.ne("memory-backend-file") {
+            bail!(
+                "Object type: {} does not support readonly or pmem",
+                mem_type
+            );
+        }
+        if zone_config.prealloc_threads != 0 && !zone_config.prealloc {
+            bail!(
+                "Prealloc threads of memory backend {} is set without prealloc",
+                zone_config.id
+            );
+        }
+        if zone_config.readonly && zone_config.prealloc {
+            bail!(
+                "Read-only memory backend {} can't be preallocated",
+                zone_config.id
+            );
+        }
+        if zone_config.pmem && !zone_config.share {
+            bail!("Memory backend {} on pmem must be shared", zone_config.id);
+        }
+        if zone_config.pmem && zone_config.readonly {
+            bail!(
+                "Memory backend {} on pmem can't be read-only, as pmem is mapped writable",
+                zone_config.id
+            );
+        }
+        if zone_config.thp.is_some() && (zone_config.hugetlb || zone_config.mem_path.is_some()) {
+            bail!(
+                "Transparent hugepage policy is not supported by memory backend {}",
+                zone_config.id
+            );
+        }
+        if zone_config.hugetlb_size != 0 && !zone_config.hugetlb {
+            bail!("Object type: {} does not support hugetlbsize", mem_type);
+        }
+        if zone_config.hugetlb_size != 0 && zone_config.size % zone_config.hugetlb_size != 0 {
+            bail!(
+                "Size of memory backend {} should be multiple of hugepage size {}",
+                zone_config.id,
+                zone_config.hugetlb_size
+            );
+        }
 
         if self.object.mem_object.get(&zone_config.id).is_none() {
             self.object
@@ -577,10 +1085,6 @@ impl VmConfig {
             bail!("Object: {} has been added", zone_config.id);
         }
 
-        if zone_config.host_numa_nodes.is_none() {
-            return Ok(zone_config);
-        }
-
         if self.machine_config.mem_config.mem_zones.is_some() {
             self.machine_config
                 .mem_config
@@ -725,6 +1229,8 @@ mod tests {
             dump_guest_core: false,
             mem_prealloc: false,
             mem_zones: None,
+            slots: 0,
+            max_mem: 0,
         };
         let mut machine_config = MachineConfig {
             mach_type: MachineType::MicroVm,
@@ -738,7 +1244,14 @@ mod tests {
             mem_config: memory_config,
             cpu_config: CpuConfig::default(),
             shutdown_action: ShutdownAction::default(),
+            panic_action: PanicAction::default(),
             battery: false,
+            dirty_ring_size: 0,
+            vcpu_affinity: HashMap::new(),
+            clock_advance_on_pause: false,
+            hpet: true,
+            confidential_guest: None,
+            sgx_epc: Vec::new(),
         };
         assert!(machine_config.check().is_ok());
 
@@ -937,6 +1450,23 @@ mod tests {
         assert!(mem_cfg_ret.is_ok());
         let mem_size = vm_config.machine_config.mem_config.mem_size;
         assert_eq!(mem_size, 8 * 1024 * 1024 * 1024);
+
+        let memory_cfg = "size=4G,slots=4,maxmem=8G";
+        assert!(vm_config.add_memory(memory_cfg).is_ok());
+        let mem_config = &vm_config.machine_config.mem_config;
+        assert_eq!(mem_config.slots, 4);
+        assert_eq!(mem_config.max_mem, 8 * 1024 * 1024 * 1024);
+        assert!(vm_config.machine_config.check().is_ok());
+
+        let memory_cfg = "size=4G,slots=4,maxmem=2G";
+        assert!(vm_config.add_memory(memory_cfg).is_ok());
+        assert!(vm_config.machine_config.check().is_err());
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_memory("size=4G,slots=4").is_err());
+        assert!(vm_config.add_memory("size=4G,maxmem=8G").is_err());
+        assert!(vm_config.add_memory("size=4G,slots=0,maxmem=8G").is_err());
+        assert!(vm_config.add_memory("size=4G,slots=257,maxmem=8G").is_err());
     }
 
     #[test]
@@ -958,6 +1488,27 @@ mod tests {
         assert_eq!(machine_cfg.mach_type, MachineType::None);
         assert_eq!(machine_cfg.mem_config.dump_guest_core, false);
         assert_eq!(machine_cfg.mem_config.mem_share, false);
+        assert!(!machine_cfg.clock_advance_on_pause);
+
+        let mut vm_config = VmConfig::default();
+        let memory_cfg_str = "type=none,clock-advance-on-pause=on";
+        let machine_cfg_ret = vm_config.add_machine(memory_cfg_str);
+        assert!(machine_cfg_ret.is_ok());
+        assert!(vm_config.machine_config.clock_advance_on_pause);
+
+        let mut vm_config = VmConfig::default();
+        let memory_cfg_str = "type=none,clock-advance-on-pause=1";
+        let machine_cfg_ret = vm_config.add_machine(memory_cfg_str);
+        assert!(machine_cfg_ret.is_err());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.machine_config.hpet);
+            vm_config.add_machine("type=q35,hpet=off").unwrap();
+            assert!(!vm_config.machine_config.hpet);
+            assert!(vm_config.add_machine("type=q35,hpet=2").is_err());
+        }
 
         let mut vm_config = VmConfig::default();
         let memory_cfg_str = "type=none,accel=kvm-tcg";
@@ -998,6 +1549,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_add_accel() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_accel("kvm").is_ok());
+        assert_eq!(vm_config.machine_config.dirty_ring_size, 0);
+        assert!(vm_config.add_accel("kvm,dirty-ring-size=4096").is_ok());
+        assert_eq!(vm_config.machine_config.dirty_ring_size, 4096);
+
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_accel("tcg").is_err());
+        assert!(vm_config.add_accel("kvm,dirty-ring-size=1000").is_err());
+    }
+
     #[test]
     fn test_add_mem_path() {
         let mut vm_config = VmConfig::default();
@@ -1056,6 +1620,88 @@ mod tests {
         assert!(cpu_cfg_ret.is_err());
     }
 
+    #[test]
+    fn test_add_cpu_topology() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_cpu("cpus=8,maxcpus=12,sockets=2,cores=3,threads=2")
+            .is_ok());
+        let machine_config = &vm_config.machine_config;
+        assert_eq!(machine_config.nr_cpus, 8);
+        assert_eq!(machine_config.max_cpus, 12);
+        assert_eq!(machine_config.nr_sockets, 2);
+        assert_eq!(machine_config.nr_cores, 3);
+        assert_eq!(machine_config.nr_threads, 2);
+
+        // Topology does not match maxcpus.
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_cpu("cpus=8,maxcpus=12,sockets=2,cores=2,threads=2")
+            .is_err());
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config.add_cpu("cpus=8,sockets=2,dies=2,cores=2").is_ok());
+            assert_eq!(vm_config.machine_config.nr_dies, 2);
+            assert_eq!(vm_config.machine_config.nr_threads, 1);
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config
+                .add_cpu("cpus=8,sockets=2,clusters=2,cores=2")
+                .is_err());
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config
+                .add_cpu("cpus=8,sockets=2,clusters=2,cores=2")
+                .is_ok());
+            assert_eq!(vm_config.machine_config.nr_clusters, 2);
+            let mut vm_config = VmConfig::default();
+            assert!(vm_config
+                .add_cpu("cpus=8,sockets=2,dies=2,cores=2")
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_add_vcpu_affinity() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_cpu("cpus=2,maxcpus=4").is_ok());
+        assert!(vm_config.add_vcpu_affinity("vcpu=0,affinity=0-1:4").is_ok());
+        assert!(vm_config.add_vcpu_affinity("vcpu=3,affinity=2").is_ok());
+        assert_eq!(
+            vm_config.machine_config.vcpu_affinity.get(&0),
+            Some(&vec![0, 1, 4])
+        );
+        assert!(vm_config.machine_config.check().is_ok());
+
+        assert!(vm_config.add_vcpu_affinity("vcpu=0,affinity=2").is_err());
+        assert!(vm_config.add_vcpu_affinity("vcpu=1").is_err());
+        assert!(vm_config.add_vcpu_affinity("affinity=2").is_err());
+        assert!(vm_config.add_vcpu_affinity("vcpu=1,affinity=3-2").is_err());
+
+        assert!(vm_config.add_vcpu_affinity("vcpu=4,affinity=2").is_ok());
+        assert!(vm_config.machine_config.check().is_err());
+    }
+
+    #[test]
+    fn test_add_action() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::None);
+        assert!(vm_config.add_action("panic=pause").is_ok());
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::Pause);
+        assert!(vm_config.add_action("panic=shutdown").is_ok());
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::Shutdown);
+        assert!(vm_config.add_action("panic=coredump").is_ok());
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::Coredump);
+        assert!(vm_config.add_action("panic=none").is_ok());
+        assert_eq!(vm_config.machine_config.panic_action, PanicAction::None);
+
+        assert!(vm_config.add_action("panic=reboot").is_err());
+        assert!(vm_config.add_action("reset=pause").is_err());
+    }
+
     #[test]
     fn test_add_mem_zone() {
         let mut vm_config = VmConfig::default();
@@ -1103,6 +1749,223 @@ mod tests {
             )
             .unwrap();
         assert_eq!(zone_config_5.memfd, true);
+        assert!(zone_config_5.seal);
+        assert!(!zone_config_5.hugetlb);
+        assert_eq!(
+            vm_config.machine_config.mem_config.mem_zones.unwrap().len(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_add_file_mem_zone() {
+        let mut vm_config = VmConfig::default();
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem1,mem-path=/dev/shm,share=on",
+                String::from("memory-backend-file"),
+            )
+            .unwrap();
+        assert_eq!(zone_config.mem_path, Some("/dev/shm".to_string()));
+        assert!(zone_config.share);
+        assert!(!zone_config.readonly);
+        assert!(!zone_config.pmem);
+
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem2,mem-path=/dev/dax0.0,share=on,pmem=on",
+                String::from("memory-backend-file"),
+            )
+            .unwrap();
+        assert!(zone_config.pmem);
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem3,mem-path=/path/to/rom,readonly=on",
+                String::from("memory-backend-file"),
+            )
+            .unwrap();
+        assert!(zone_config.readonly);
+
+        // Pmem backend must be shared.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem4,mem-path=/dev/dax0.0,pmem=on",
+                String::from("memory-backend-file"),
+            )
+            .is_err());
+        // Pmem backend can't be read-only.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem4,mem-path=/dev/dax0.0,share=on,pmem=on,readonly=on",
+                String::from("memory-backend-file"),
+            )
+            .is_err());
+        // Read-only backend can't be preallocated.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-file,size=2M,id=mem4,mem-path=/path/to/rom,readonly=on,mem-prealloc=on",
+                String::from("memory-backend-file"),
+            )
+            .is_err());
+        // Only file backend supports readonly and pmem.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2M,id=mem4,readonly=on",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2M,id=mem4,share=on,pmem=on",
+                String::from("memory-backend-memfd"),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_hugetlb_mem_zone() {
+        let mut vm_config = VmConfig::default();
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-hugetlb,size=2G,id=mem1,hugetlbsize=1G",
+                String::from("memory-backend-hugetlb"),
+            )
+            .unwrap();
+        assert!(zone_config.hugetlb);
+        assert!(!zone_config.memfd);
+        assert_eq!(zone_config.hugetlb_size, 1 << 30);
+
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-hugetlb,size=4M,id=mem2,share=on",
+                String::from("memory-backend-hugetlb"),
+            )
+            .unwrap();
+        assert_eq!(zone_config.hugetlb_size, 0);
+
+        // Size must be multiple of hugepage size.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-hugetlb,size=3M,id=mem3,hugetlbsize=2M",
+                String::from("memory-backend-hugetlb"),
+            )
+            .is_err());
+        // Hugepage size must be power of 2.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-hugetlb,size=6M,id=mem3,hugetlbsize=3M",
+                String::from("memory-backend-hugetlb"),
+            )
+            .is_err());
+        // Only hugetlb backend supports hugetlbsize.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=4M,id=mem3,hugetlbsize=2M",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_add_memfd_mem_zone() {
+        let mut vm_config = VmConfig::default();
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2G,id=mem1,hugetlb=on,hugetlbsize=1G,seal=off",
+                String::from("memory-backend-memfd"),
+            )
+            .unwrap();
+        assert!(zone_config.memfd);
+        assert!(zone_config.hugetlb);
+        assert!(!zone_config.seal);
+        assert_eq!(zone_config.hugetlb_size, 1 << 30);
+
+        // Hugepage size is only valid for hugetlb memfd.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2G,id=mem2,hugetlbsize=1G",
+                String::from("memory-backend-memfd"),
+            )
+            .is_err());
+        // Only memfd backend supports seal and hugetlb.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem2,seal=on",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem2,hugetlb=on",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_mem_zone_prealloc() {
+        let mut vm_config = VmConfig::default();
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem1,prealloc=on",
+                String::from("memory-backend-ram"),
+            )
+            .unwrap();
+        assert!(zone_config.prealloc);
+        assert_eq!(zone_config.prealloc_threads, 0);
+
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem2,mem-prealloc=on,prealloc-threads=8",
+                String::from("memory-backend-ram"),
+            )
+            .unwrap();
+        assert!(zone_config.prealloc);
+        assert_eq!(zone_config.prealloc_threads, 8);
+
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem3,prealloc=on,prealloc-threads=0",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem3,prealloc-threads=4",
+                String::from("memory-backend-ram"),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_mem_zone_madvise() {
+        let mut vm_config = VmConfig::default();
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-ram,size=2G,id=mem1,merge=on,thp=off,dump-guest-core=off",
+                String::from("memory-backend-ram"),
+            )
+            .unwrap();
+        assert!(zone_config.merge);
+        assert_eq!(zone_config.thp, Some(false));
+        assert!(!zone_config.dump_guest_core);
+
+        let zone_config = vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2G,id=mem2,thp=on",
+                String::from("memory-backend-memfd"),
+            )
+            .unwrap();
+        assert!(!zone_config.merge);
+        assert_eq!(zone_config.thp, Some(true));
+
+        // Hugetlb pages can't be transparent hugepages.
+        assert!(vm_config
+            .add_mem_zone(
+                "-object memory-backend-memfd,size=2G,id=mem3,hugetlb=on,thp=on",
+                String::from("memory-backend-memfd"),
+            )
+            .is_err());
     }
 
     #[test]
@@ -1132,5 +1995,100 @@ mod tests {
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
         vm_config.add_cpu_feature("pmu=on").unwrap();
         assert!(vm_config.machine_config.cpu_config.pmu == PmuConfig::On);
+
+        // Test SVE flags
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(!vm_config.machine_config.cpu_config.sve.enabled);
+        vm_config.add_cpu_feature("host,sve=on").unwrap();
+        assert!(vm_config.machine_config.cpu_config.sve.enabled);
+        assert!(vm_config
+            .machine_config
+            .cpu_config
+            .sve
+            .vector_lengths
+            .is_empty());
+        vm_config
+            .add_cpu_feature("host,sve=on,sve-vl=512:128:256")
+            .unwrap();
+        assert_eq!(
+            vm_config.machine_config.cpu_config.sve.vector_lengths,
+            vec![128, 256, 512]
+        );
+        vm_config.add_cpu_feature("host,sve=off").unwrap();
+        assert!(!vm_config.machine_config.cpu_config.sve.enabled);
+        assert!(vm_config.add_cpu_feature("host,sve-vl=128").is_err());
+        assert!(vm_config.add_cpu_feature("host,sve=on,sve-vl=100").is_err());
+        assert!(vm_config
+            .add_cpu_feature("host,sve=on,sve-vl=4096")
+            .is_err());
+        assert!(vm_config.add_cpu_feature("host,sve=on,sve-vl=0").is_err());
+        assert!(vm_config.add_cpu_feature("host,sve=maybe").is_err());
+
+        // Test TSC flags
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(vm_config.machine_config.cpu_config.tsc_frequency.is_none());
+        assert!(!vm_config.machine_config.cpu_config.invtsc);
+        assert!(vm_config
+            .machine_config
+            .cpu_config
+            .check_migratable()
+            .is_ok());
+        vm_config.add_cpu_feature("host,invtsc=on").unwrap();
+        assert!(vm_config.machine_config.cpu_config.invtsc);
+        assert!(vm_config
+            .machine_config
+            .cpu_config
+            .check_migratable()
+            .is_err());
+        vm_config
+            .add_cpu_feature("host,invtsc=on,tsc-frequency=2500000000")
+            .unwrap();
+        assert_eq!(
+            vm_config.machine_config.cpu_config.tsc_frequency,
+            Some(2_500_000_000)
+        );
+        assert!(vm_config
+            .machine_config
+            .cpu_config
+            .check_migratable()
+            .is_ok());
+        assert!(vm_config.add_cpu_feature("host,tsc-frequency=999").is_err());
+        assert!(vm_config
+            .add_cpu_feature("host,tsc-frequency=5000000000000")
+            .is_err());
+        assert!(vm_config
+            .add_cpu_feature("host,tsc-frequency=2.5G")
+            .is_err());
+        assert!(vm_config.add_cpu_feature("host,invtsc=maybe").is_err());
+
+        // Test steal time flags
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        assert!(vm_config.machine_config.cpu_config.steal_time);
+        vm_config.add_cpu_feature("host,steal-time=off").unwrap();
+        assert!(!vm_config.machine_config.cpu_config.steal_time);
+        vm_config.add_cpu_feature("host,steal-time=on").unwrap();
+        assert!(vm_config.machine_config.cpu_config.steal_time);
+        assert!(vm_config.add_cpu_feature("host,steal-time=maybe").is_err());
+
+        // Test speculation control flags
+        let mut vm_config = VmConfig::default();
+        vm_config.add_cpu_feature("host").unwrap();
+        let cpu_config = &vm_config.machine_config.cpu_config;
+        assert!(cpu_config.spec_ctrl.is_none());
+        assert!(cpu_config.ssbd.is_none());
+        assert!(cpu_config.md_clear.is_none());
+        assert!(!cpu_config.spec_isolation);
+        vm_config
+            .add_cpu_feature("host,spec-ctrl=off,ssbd=on,md-clear=off,spec-isolation=on")
+            .unwrap();
+        let cpu_config = &vm_config.machine_config.cpu_config;
+        assert_eq!(cpu_config.spec_ctrl, Some(false));
+        assert_eq!(cpu_config.ssbd, Some(true));
+        assert_eq!(cpu_config.md_clear, Some(false));
+        assert!(cpu_config.spec_isolation);
+        assert!(vm_config.add_cpu_feature("host,ssbd=maybe").is_err());
     }
 }
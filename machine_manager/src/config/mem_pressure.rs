@@ -0,0 +1,139 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, VmConfig};
+
+/// Default interval of memory pressure monitor in seconds.
+const DEFAULT_MEM_PRESSURE_INTERVAL: u64 = 10;
+/// Max interval of memory pressure monitor in seconds.
+const MAX_MEM_PRESSURE_INTERVAL: u64 = 3600;
+/// Default file of the memory pressure stall information.
+const DEFAULT_PSI_FILE: &str = "/proc/pressure/memory";
+
+/// Config of the monitor which reports high dirty rate of guest memory and high
+/// memory pressure of host.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MemPressureConfig {
+    /// Seconds between two samples.
+    pub interval: u64,
+    /// Threshold of the dirty rate of guest memory in MiB/s.
+    pub dirty_rate: Option<u64>,
+    /// Threshold of the `some` avg10 of memory pressure in percent.
+    pub psi_some: Option<f64>,
+    /// Threshold of the `full` avg10 of memory pressure in percent.
+    pub psi_full: Option<f64>,
+    /// File of the memory pressure stall information, such as `memory.pressure` of cgroup.
+    pub psi_file: String,
+}
+
+impl VmConfig {
+    /// Add '-mem-pressure-monitor' config to `VmConfig`, in format of
+    /// `[interval=<seconds>][,dirty-rate=<MiB/s>][,psi-some=<percent>][,psi-full=<percent>]
+    /// [,psi-file=<path>]`.
+    pub fn add_mem_pressure_monitor(&mut self, mem_pressure: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("mem-pressure-monitor");
+        cmd_parser
+            .push("interval")
+            .push("dirty-rate")
+            .push("psi-some")
+            .push("psi-full")
+            .push("psi-file");
+        cmd_parser.parse(mem_pressure)?;
+
+        let interval = cmd_parser
+            .get_value::<u64>("interval")?
+            .unwrap_or(DEFAULT_MEM_PRESSURE_INTERVAL);
+        if interval == 0 || interval > MAX_MEM_PRESSURE_INTERVAL {
+            bail!(
+                "interval of mem-pressure-monitor should be in range [1, {}]",
+                MAX_MEM_PRESSURE_INTERVAL
+            );
+        }
+        let dirty_rate = cmd_parser.get_value::<u64>("dirty-rate")?;
+        if dirty_rate == Some(0) {
+            bail!("dirty-rate of mem-pressure-monitor should be larger than 0");
+        }
+        let psi_some = cmd_parser.get_value::<f64>("psi-some")?;
+        let psi_full = cmd_parser.get_value::<f64>("psi-full")?;
+        for (name, psi) in [("psi-some", psi_some), ("psi-full", psi_full)] {
+            if let Some(psi) = psi {
+                if !(psi > 0.0 && psi <= 100.0) {
+                    bail!(
+                        "{} of mem-pressure-monitor should be in range (0, 100]",
+                        name
+                    );
+                }
+            }
+        }
+        if dirty_rate.is_none() && psi_some.is_none() && psi_full.is_none() {
+            bail!(
+                "mem-pressure-monitor requires at least one of dirty-rate, psi-some and psi-full"
+            );
+        }
+        let psi_file = cmd_parser
+            .get_value::<String>("psi-file")?
+            .unwrap_or_else(|| DEFAULT_PSI_FILE.to_string());
+
+        self.mem_pressure = Some(MemPressureConfig {
+            interval,
+            dirty_rate,
+            psi_some,
+            psi_full,
+            psi_file,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_mem_pressure_monitor() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_mem_pressure_monitor("dirty-rate=512")
+            .unwrap();
+        assert_eq!(
+            vm_config.mem_pressure,
+            Some(MemPressureConfig {
+                interval: DEFAULT_MEM_PRESSURE_INTERVAL,
+                dirty_rate: Some(512),
+                psi_some: None,
+                psi_full: None,
+                psi_file: DEFAULT_PSI_FILE.to_string(),
+            })
+        );
+        vm_config
+            .add_mem_pressure_monitor(
+                "interval=5,psi-some=10.5,psi-full=2,psi-file=/sys/fs/cgroup/vm1/memory.pressure",
+            )
+            .unwrap();
+        let config = vm_config.mem_pressure.as_ref().unwrap();
+        assert_eq!(config.interval, 5);
+        assert_eq!(config.psi_some, Some(10.5));
+        assert_eq!(config.psi_file, "/sys/fs/cgroup/vm1/memory.pressure");
+
+        assert!(vm_config.add_mem_pressure_monitor("interval=10").is_err());
+        assert!(vm_config
+            .add_mem_pressure_monitor("interval=0,dirty-rate=512")
+            .is_err());
+        assert!(vm_config.add_mem_pressure_monitor("dirty-rate=0").is_err());
+        assert!(vm_config.add_mem_pressure_monitor("psi-some=0").is_err());
+        assert!(vm_config.add_mem_pressure_monitor("psi-full=101").is_err());
+        assert!(vm_config.add_mem_pressure_monitor("psi-full=x").is_err());
+    }
+}
@@ -19,63 +19,99 @@ pub mod error;
 pub mod vnc;
 
 mod balloon;
+mod bench;
 mod boot_source;
 mod chardev;
+mod compat;
+mod crash_dump;
 #[cfg(feature = "demo_device")]
 mod demo_dev;
 mod devices;
+mod dimm;
 mod drive;
 mod fs;
+mod fw_cfg;
+mod gdb;
 #[cfg(feature = "virtio_gpu")]
 mod gpu;
+mod hang_watchdog;
 mod incoming;
 mod iothread;
 mod machine_config;
+mod mem_pressure;
 mod network;
 mod numa;
 mod pci;
+mod privilege;
+mod pvpanic;
+mod qmp_audit;
 #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
 mod ramfb;
 mod rng;
+mod rtc;
+mod sandbox;
 mod sasl_auth;
 #[cfg(feature = "scream")]
 pub mod scream;
 mod scsi;
+mod seclabel;
+mod sev;
+mod sgx;
 mod smbios;
 mod tls_creds;
+mod tpm;
 mod usb;
+mod vdpa;
 mod vfio;
+mod vmm_limits;
 
 pub use balloon::*;
+pub use bench::*;
 pub use boot_source::*;
 #[cfg(feature = "usb_camera")]
 pub use camera::*;
 pub use chardev::*;
+pub use crash_dump::*;
 #[cfg(feature = "demo_device")]
 pub use demo_dev::*;
 pub use devices::*;
+pub use dimm::*;
 #[cfg(feature = "gtk")]
 pub use display::*;
 pub use drive::*;
 pub use error::ConfigError;
 pub use fs::*;
+pub use fw_cfg::*;
+pub use gdb::*;
 #[cfg(feature = "virtio_gpu")]
 pub use gpu::*;
+pub use hang_watchdog::*;
 pub use incoming::*;
 pub use iothread::*;
 pub use machine_config::*;
+pub use mem_pressure::*;
 pub use network::*;
 pub use numa::*;
 pub use pci::*;
+pub use privilege::*;
+pub use pvpanic::*;
+pub use qmp_audit::*;
 #[cfg(all(feature = "ramfb", target_arch = "aarch64"))]
 pub use ramfb::*;
 pub use rng::*;
+pub use rtc::*;
+pub use sandbox::*;
 pub use sasl_auth::*;
 pub use scsi::*;
+pub use sev::*;
+pub use sgx::*;
 pub use smbios::*;
 pub use tls_creds::*;
+pub use tpm::*;
 pub use usb::*;
+pub use vdpa::*;
 pub use vfio::*;
+pub use vmm_limits::*;
 #[cfg(feature = "vnc")]
 pub use vnc::*;
 
@@ -92,8 +128,9 @@ use util::device_tree::{self, FdtBuilder};
 use util::{
     file::{get_file_alignment, open_file},
     num_ops::str_to_usize,
+    seclabel::SecurityLabel,
     test_helper::is_test_enabled,
-    trace::enable_trace_events,
+    trace::{enable_trace_events, set_trace_backend, set_trace_event_state, TraceBackend},
     AsAny,
 };
 
@@ -116,6 +153,8 @@ pub struct ObjectConfig {
     pub mem_object: HashMap<String, MemZoneConfig>,
     pub tls_object: HashMap<String, TlsCredObjConfig>,
     pub sasl_object: HashMap<String, SaslAuthObjConfig>,
+    pub sev_snp_object: HashMap<String, SevSnpObjConfig>,
+    pub sgx_epc_object: HashMap<String, SgxEpcObjConfig>,
 }
 
 /// This main config structure for Vm, contains Vm's basic configuration and devices.
@@ -127,16 +166,19 @@ pub struct VmConfig {
     pub drives: HashMap<String, DriveConfig>,
     pub netdevs: HashMap<String, NetDevcfg>,
     pub chardev: HashMap<String, ChardevConfig>,
+    pub tpmdevs: HashMap<String, TpmDevConfig>,
     pub virtio_serial: Option<VirtioSerialInfo>,
     pub devices: Vec<(String, String)>,
     pub serial: Option<SerialConfig>,
     pub iothreads: Option<Vec<IothreadConfig>>,
     pub object: ObjectConfig,
     pub pflashs: Option<Vec<PFlashConfig>>,
+    pub fw_cfg: Vec<FwCfgConfig>,
     pub dev_name: HashMap<String, u8>,
     pub global_config: HashMap<String, String>,
     pub numa_nodes: Vec<(String, String)>,
     pub incoming: Option<Incoming>,
+    pub gdb: Option<GdbConfig>,
     #[cfg(feature = "vnc")]
     pub vnc: Option<VncConfig>,
     #[cfg(feature = "gtk")]
@@ -146,6 +188,20 @@ pub struct VmConfig {
     #[cfg(feature = "windows_emu_pid")]
     pub windows_emu_pid: Option<String>,
     pub smbios: SmbiosConfig,
+    pub rtc: RtcConfig,
+    pub sandbox: SandboxConfig,
+    pub privilege: PrivilegeConfig,
+    pub seclabel: Option<SecurityLabel>,
+    pub qmp_audit: Option<QmpAuditConfig>,
+    pub vmm_limits: VmmLimitsConfig,
+    /// Whether the configuration of VM is locked, it can't be changed by qmp then.
+    pub lock_config: bool,
+    pub hang_watchdog: Option<HangWatchdogConfig>,
+    pub crash_dump: Option<CrashDumpConfig>,
+    pub mem_pressure: Option<MemPressureConfig>,
+    /// Virtio devices added by `-drive if=virtio` and `-nic`, in pairs of virtio device type
+    /// and properties, which are put into `devices` after all the arguments are parsed.
+    pub compat_devices: Vec<(String, String)>,
 }
 
 impl VmConfig {
@@ -161,6 +217,18 @@ impl VmConfig {
         {
             bail!("kernel file is required for microvm machine type, which is not provided");
         }
+        if !self.fw_cfg.is_empty() && self.machine_config.mach_type == MachineType::MicroVm {
+            bail!("fw_cfg file is not supported for microvm machine type");
+        }
+        if self
+            .mem_pressure
+            .as_ref()
+            .map(|config| config.dirty_rate.is_some())
+            .unwrap_or(false)
+            && self.machine_config.mach_type == MachineType::MicroVm
+        {
+            bail!("dirty-rate of mem-pressure-monitor is not supported for microvm machine type");
+        }
 
         if self.boot_source.initrd.is_none()
             && self.drives.is_empty()
@@ -228,7 +296,10 @@ impl VmConfig {
                     bail!("Object: {} has been added", id);
                 }
             }
-            "memory-backend-ram" | "memory-backend-file" | "memory-backend-memfd" => {
+            "memory-backend-ram"
+            | "memory-backend-file"
+            | "memory-backend-memfd"
+            | "memory-backend-hugetlb" => {
                 self.add_mem_zone(object_args, device_type)?;
             }
             "tls-creds-x509" => {
@@ -237,6 +308,12 @@ impl VmConfig {
             "authz-simple" => {
                 self.add_saslauth(object_args)?;
             }
+            "sev-snp-guest" => {
+                self.add_sev_snp(object_args)?;
+            }
+            "memory-backend-epc" => {
+                self.add_sgx_epc_object(object_args)?;
+            }
             _ => {
                 bail!("Unknow object type: {:?}", &device_type);
             }
@@ -286,6 +363,11 @@ impl VmConfig {
         Ok(())
     }
 
+    /// Lock the configuration of VM by '-lock-config'.
+    pub fn enable_config_lock(&mut self) {
+        self.lock_config = true;
+    }
+
     /// Add a file to drive file store.
     pub fn add_drive_file(
         drive_files: &mut HashMap<String, DriveFile>,
@@ -608,16 +690,29 @@ impl From<ExBool> for bool {
     }
 }
 
+/// Enable trace events by '-trace', in format of
+/// `[backend=ftrace|lttng][,events=<file>][,enable=<pattern>]`.
 pub fn add_trace_events(config: &str) -> Result<()> {
     let mut cmd_parser = CmdParser::new("trace");
-    cmd_parser.push("events");
+    cmd_parser.push("backend").push("events").push("enable");
     cmd_parser.get_parameters(config)?;
 
-    if let Some(file) = cmd_parser.get_value::<String>("events")? {
+    let backend = cmd_parser.get_value::<TraceBackend>("backend")?;
+    let file = cmd_parser.get_value::<String>("events")?;
+    let pattern = cmd_parser.get_value::<String>("enable")?;
+    if backend.is_none() && file.is_none() && pattern.is_none() {
+        bail!("trace: backend, events file or enable pattern must be set.");
+    }
+    if let Some(backend) = backend {
+        set_trace_backend(backend)?;
+    }
+    if let Some(file) = file {
         enable_trace_events(&file)?;
-        return Ok(());
     }
-    bail!("trace: events file must be set.");
+    if let Some(pattern) = pattern {
+        set_trace_event_state(&pattern, true)?;
+    }
+    Ok(())
 }
 
 /// This struct is a wrapper for `usize`.
@@ -797,6 +892,8 @@ mod tests {
         assert!(add_trace_events("event=test_trace_events").is_err());
         assert!(add_trace_events("events").is_err());
         assert!(add_trace_events("events=test_trace_events").is_err());
+        assert!(add_trace_events("enable=nonexistent_*").is_err());
+        assert!(add_trace_events("backend=perf").is_err());
     }
 
     #[test]
@@ -808,11 +905,14 @@ mod tests {
 
         let file = "/tmp/test_trace_events";
         let mut fd = File::create(file).unwrap();
-        let event = "add_trace_events";
-        fd.write(event.as_bytes()).unwrap();
-        add_trace_events(format!("events={}", file).as_str()).unwrap();
+        fd.write(b"# trace startup of micro vm\n\nsysbus\n")
+            .unwrap();
+        add_trace_events(format!("events={},enable=cpu_*", file).as_str()).unwrap();
 
-        assert!(is_trace_event_enabled(event));
+        assert!(is_trace_event_enabled("sysbus"));
+        assert!(is_trace_event_enabled("cpu_topo"));
+        assert!(is_trace_event_enabled("cpu_boot_config"));
+        assert!(!is_trace_event_enabled("vm_state"));
         std::fs::remove_file(file).unwrap();
     }
 
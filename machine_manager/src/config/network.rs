@@ -11,6 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use std::os::unix::io::RawFd;
+use std::path::Path;
 
 use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
@@ -18,8 +19,8 @@ use serde::{Deserialize, Serialize};
 use super::{error::ConfigError, pci_args_check};
 use crate::config::get_chardev_socket_path;
 use crate::config::{
-    check_arg_too_long, CmdParser, ConfigCheck, ExBool, VmConfig, DEFAULT_VIRTQUEUE_SIZE,
-    MAX_PATH_LENGTH, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, get_queue_iothread, split_queue_iothreads, CmdParser, ConfigCheck, ExBool,
+    VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_PATH_LENGTH, MAX_VIRTIO_QUEUE,
 };
 use crate::qmp::{qmp_channel::QmpChannel, qmp_schema};
 
@@ -29,6 +30,9 @@ const MAC_ADDRESS_LENGTH: usize = 17;
 pub const MAX_QUEUE_SIZE_NET: u16 = 4096;
 /// Max num of virtqueues.
 const MAX_QUEUE_PAIRS: usize = MAX_VIRTIO_QUEUE / 2;
+/// Default number of used entries added before notifying the guest, which notifies for
+/// every packet.
+pub const DEFAULT_NOTIFY_BATCH: u16 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetDevcfg {
@@ -39,6 +43,10 @@ pub struct NetDevcfg {
     pub ifname: String,
     pub queues: u16,
     pub chardev: Option<String>,
+    /// Access tap through io_uring rather than readv/writev.
+    pub io_uring: bool,
+    /// Busy polling timeout of vhost-net vrings in microseconds, 0 means disabled.
+    pub poll_us: u32,
 }
 
 impl Default for NetDevcfg {
@@ -51,6 +59,8 @@ impl Default for NetDevcfg {
             ifname: "".to_string(),
             queues: 2,
             chardev: None,
+            io_uring: false,
+            poll_us: 0,
         }
     }
 }
@@ -92,11 +102,31 @@ pub struct NetworkInterfaceConfig {
     pub vhost_type: Option<String>,
     pub vhost_fds: Option<Vec<i32>>,
     pub iothread: Option<String>,
+    /// Iothreads which the queue pairs are assigned to in turn, empty if all the queue
+    /// pairs run in `iothread`.
+    #[serde(default)]
+    pub queue_iothreads: Vec<String>,
     pub queues: u16,
     pub mq: bool,
     pub socket_path: Option<String>,
     /// All queues of a net device have the same queue size now.
     pub queue_size: u16,
+    pub boot_index: Option<u8>,
+    /// Option ROM image for network boot, such as iPXE.
+    pub romfile: Option<String>,
+    /// Access tap through io_uring rather than readv/writev.
+    pub io_uring: bool,
+    /// Busy polling timeout of vhost-net vrings in microseconds, 0 means disabled.
+    #[serde(default)]
+    pub poll_us: u32,
+    /// Max number of used entries added in a burst before checking whether the guest
+    /// needs to be notified. The rest are notified at the end of the burst.
+    #[serde(default = "default_notify_batch")]
+    pub notify_batch: u16,
+}
+
+fn default_notify_batch() -> u16 {
+    DEFAULT_NOTIFY_BATCH
 }
 
 impl Default for NetworkInterfaceConfig {
@@ -109,14 +139,27 @@ impl Default for NetworkInterfaceConfig {
             vhost_type: None,
             vhost_fds: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
             queues: 2,
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            boot_index: None,
+            romfile: None,
+            io_uring: false,
+            poll_us: 0,
+            notify_batch: DEFAULT_NOTIFY_BATCH,
         }
     }
 }
 
+impl NetworkInterfaceConfig {
+    /// Get the iothread of the queue pair `index`.
+    pub fn queue_iothread(&self, index: usize) -> Option<&String> {
+        get_queue_iothread(self.iothread.as_ref(), &self.queue_iothreads, index)
+    }
+}
+
 impl ConfigCheck for NetworkInterfaceConfig {
     fn check(&self) -> Result<()> {
         check_arg_too_long(&self.id, "id")?;
@@ -129,6 +172,9 @@ impl ConfigCheck for NetworkInterfaceConfig {
         if self.iothread.is_some() {
             check_arg_too_long(self.iothread.as_ref().unwrap(), "iothread name")?;
         }
+        for iothread in self.queue_iothreads.iter() {
+            check_arg_too_long(iothread, "iothread name")?;
+        }
 
         if self.socket_path.is_some() && self.socket_path.as_ref().unwrap().len() > MAX_PATH_LENGTH
         {
@@ -152,6 +198,23 @@ impl ConfigCheck for NetworkInterfaceConfig {
             bail!("queue size of net device should be power of 2!");
         }
 
+        if self.notify_batch == 0 || self.notify_batch > self.queue_size {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "notify batch of net device".to_string(),
+                1,
+                true,
+                self.queue_size as u64,
+                true
+            )));
+        }
+
+        if let Some(romfile) = &self.romfile {
+            check_arg_too_long(romfile, "romfile")?;
+            if !Path::new(romfile).is_file() {
+                return Err(anyhow!(ConfigError::UnRegularFile("romfile".to_string())));
+            }
+        }
+
         Ok(())
     }
 }
@@ -243,6 +306,18 @@ fn parse_netdev(cmd_parser: CmdParser) -> Result<NetDevcfg> {
     if net.vhost_fds.is_some() && net.vhost_type.is_none() {
         bail!("Argument \'vhostfd\' is not needed for virtio-net device");
     }
+    if let Some(io_uring) = cmd_parser.get_value::<ExBool>("io-uring")? {
+        net.io_uring = io_uring.into();
+    }
+    if net.io_uring && net.vhost_type.is_some() {
+        bail!("Argument \'io-uring\' is not supported by vhost net device");
+    }
+    if let Some(poll_us) = cmd_parser.get_value::<u32>("poll-us")? {
+        net.poll_us = poll_us;
+    }
+    if net.poll_us != 0 && net.vhost_type != Some(String::from("vhost-kernel")) {
+        bail!("Argument \'poll-us\' is only supported by vhost-kernel net device");
+    }
     if net.tap_fds.is_none() && net.ifname.eq("") && netdev_type.ne("vhost-user") {
         bail!("Tap device is missing, use \'ifname\' or \'fd\' to configure a tap device");
     }
@@ -260,12 +335,17 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         .push("netdev")
         .push("mq")
         .push("vectors")
+        .push("irq-coalesce-us")
+        .push("irq-coalesce-frames")
         .push("bus")
         .push("addr")
         .push("multifunction")
         .push("mac")
         .push("iothread")
-        .push("queue-size");
+        .push("queue-size")
+        .push("bootindex")
+        .push("romfile")
+        .push("notify-batch");
 
     cmd_parser.parse(net_config)?;
     pci_args_check(&cmd_parser)?;
@@ -279,11 +359,19 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
     if let Some(mq) = cmd_parser.get_value::<ExBool>("mq")? {
         netdevinterfacecfg.mq = mq.inner;
     }
-    netdevinterfacecfg.iothread = cmd_parser.get_value::<String>("iothread")?;
+    (
+        netdevinterfacecfg.iothread,
+        netdevinterfacecfg.queue_iothreads,
+    ) = split_queue_iothreads(cmd_parser.get_value::<String>("iothread")?);
     netdevinterfacecfg.mac = cmd_parser.get_value::<String>("mac")?;
     if let Some(queue_size) = cmd_parser.get_value::<u16>("queue-size")? {
         netdevinterfacecfg.queue_size = queue_size;
     }
+    netdevinterfacecfg.boot_index = cmd_parser.get_value::<u8>("bootindex")?;
+    netdevinterfacecfg.romfile = cmd_parser.get_value::<String>("romfile")?;
+    if let Some(notify_batch) = cmd_parser.get_value::<u16>("notify-batch")? {
+        netdevinterfacecfg.notify_batch = notify_batch;
+    }
 
     if let Some(netcfg) = &vm_config.netdevs.remove(&netdev) {
         netdevinterfacecfg.id = netid;
@@ -292,6 +380,8 @@ pub fn parse_net(vm_config: &mut VmConfig, net_config: &str) -> Result<NetworkIn
         netdevinterfacecfg.vhost_fds = netcfg.vhost_fds.clone();
         netdevinterfacecfg.vhost_type = netcfg.vhost_type.clone();
         netdevinterfacecfg.queues = netcfg.queues;
+        netdevinterfacecfg.io_uring = netcfg.io_uring;
+        netdevinterfacecfg.poll_us = netcfg.poll_us;
         if let Some(chardev) = &netcfg.chardev {
             netdevinterfacecfg.socket_path = Some(get_chardev_socket_path(chardev, vm_config)?);
         }
@@ -352,6 +442,8 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
         ifname: String::new(),
         queues,
         chardev: args.chardev,
+        io_uring: args.io_uring.unwrap_or_default(),
+        poll_us: args.poll_us.unwrap_or_default(),
     };
 
     if let Some(tap_fd) = args.fd {
@@ -409,6 +501,12 @@ pub fn get_netdev_config(args: Box<qmp_schema::NetDevAddArgument>) -> Result<Net
     if config.vhost_fds.is_some() && config.vhost_type.is_none() {
         bail!("Argument 'vhostfd' or 'vhostfds' are not needed for virtio-net device");
     }
+    if config.io_uring && config.vhost_type.is_some() {
+        bail!("Argument 'io-uring' is not supported by vhost net device");
+    }
+    if config.poll_us != 0 && config.vhost_type != Some(String::from("vhost-kernel")) {
+        bail!("Argument 'poll-us' is only supported by vhost-kernel net device");
+    }
     if config.tap_fds.is_none() && config.ifname.eq("") && netdev_type.ne("vhost-user") {
         bail!("Tap device is missing, use 'ifname' or 'fd' to configure a tap device");
     }
@@ -429,7 +527,9 @@ impl VmConfig {
             .push("vhostfd")
             .push("vhostfds")
             .push("queues")
-            .push("chardev");
+            .push("chardev")
+            .push("io-uring")
+            .push("poll-us");
 
         cmd_parser.parse(netdev_config)?;
         let drive_cfg = parse_netdev(cmd_parser)?;
@@ -493,6 +593,18 @@ impl VmConfig {
             device_info = format!("{},mq={}", device_info, mq);
         }
 
+        if let Some(boot_index) = &args.boot_index {
+            device_info = format!("{},bootindex={}", device_info, boot_index);
+        }
+
+        if let Some(romfile) = &args.romfile {
+            device_info = format!("{},romfile={}", device_info, romfile);
+        }
+
+        if let Some(notify_batch) = &args.notify_batch {
+            device_info = format!("{},notify-batch={}", device_info, notify_batch);
+        }
+
         self.devices.push((args.driver.clone(), device_info));
     }
 }
@@ -696,6 +808,21 @@ mod tests {
             "virtio-net-pci,id=netid2,netdev=netdevid2,bus=pcie.0,addr=0x2.0x0,mac=12:34:56:78:9A:BC";
         let net_cfg_res = parse_net(&mut vm_config, net_cfg);
         assert!(net_cfg_res.is_err());
+
+        // For network boot
+        let romfile = "/tmp/test_pci_network_boot.rom";
+        let net_cfg = format!(
+            "virtio-net-pci,id=net1,netdev=eth1,bus=pcie.0,addr=0x3.0x0,bootindex=1,romfile={}",
+            romfile
+        );
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        assert!(parse_net(&mut vm_config, &net_cfg).is_err());
+        std::fs::File::create(romfile).unwrap();
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let network_configs = parse_net(&mut vm_config, &net_cfg).unwrap();
+        assert_eq!(network_configs.boot_index, Some(1));
+        assert_eq!(network_configs.romfile, Some(romfile.to_string()));
+        std::fs::remove_file(romfile).unwrap();
     }
 
     #[test]
@@ -729,6 +856,69 @@ mod tests {
         assert!(netdev_conf.check().is_err());
     }
 
+    #[test]
+    fn test_netdev_io_uring_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,io-uring=on")
+            .is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0").unwrap();
+        assert!(net_cfg.io_uring);
+
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net1,netdev=eth1").unwrap();
+        assert!(!net_cfg.io_uring);
+
+        assert!(vm_config
+            .add_netdev("tap,id=eth2,ifname=tap2,vhost=on,io-uring=on")
+            .is_err());
+    }
+
+    #[test]
+    fn test_netdev_poll_us_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_netdev("tap,id=eth0,ifname=tap0,vhost=on,poll-us=50")
+            .is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0").unwrap();
+        assert_eq!(net_cfg.poll_us, 50);
+
+        assert!(vm_config
+            .add_netdev("tap,id=eth1,ifname=tap1,vhost=on")
+            .is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net1,netdev=eth1").unwrap();
+        assert_eq!(net_cfg.poll_us, 0);
+
+        assert!(vm_config
+            .add_netdev("tap,id=eth2,ifname=tap2,poll-us=50")
+            .is_err());
+    }
+
+    #[test]
+    fn test_net_notify_batch_cmdline_parser() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config.add_netdev("tap,id=eth0,ifname=tap0").is_ok());
+        let net_cfg = parse_net(&mut vm_config, "virtio-net-device,id=net0,netdev=eth0").unwrap();
+        assert_eq!(net_cfg.notify_batch, DEFAULT_NOTIFY_BATCH);
+
+        assert!(vm_config.add_netdev("tap,id=eth1,ifname=tap1").is_ok());
+        let net_cfg = parse_net(
+            &mut vm_config,
+            "virtio-net-device,id=net1,netdev=eth1,notify-batch=16",
+        )
+        .unwrap();
+        assert_eq!(net_cfg.notify_batch, 16);
+
+        for notify_batch in ["0", "512"] {
+            assert!(vm_config.add_netdev("tap,id=eth2,ifname=tap2").is_ok());
+            let net_cmd = format!(
+                "virtio-net-device,id=net2,netdev=eth2,notify-batch={}",
+                notify_batch
+            );
+            assert!(parse_net(&mut vm_config, &net_cmd).is_err());
+        }
+    }
+
     #[test]
     fn test_add_netdev_with_different_queues() {
         let mut vm_config = VmConfig::default();
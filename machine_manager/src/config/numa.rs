@@ -64,6 +64,9 @@ pub fn complete_numa_node(numa_nodes: &mut NumaNodes, nr_cpus: u8, mem_size: u64
     let mut max_cpu_id = 0_u8;
     let mut cpus_id = HashSet::<u8>::new();
     for (_, node) in numa_nodes.iter() {
+        if let Some(dst) = node.distances.keys().find(|d| !numa_nodes.contains_key(d)) {
+            bail!("Numa node id is not found {}", dst);
+        }
         total_ram_size += node.size;
         for id in node.cpus.iter() {
             if cpus_id.contains(id) {
@@ -109,6 +112,39 @@ pub fn complete_numa_node(numa_nodes: &mut NumaNodes, nr_cpus: u8, mem_size: u64
     Ok(())
 }
 
+/// Check that all the threads of one core belong to the same NUMA node, as
+/// guest kernel can not handle a core split across NUMA nodes.
+///
+/// # Arguments
+///
+/// * `numa_nodes` - The NUMA nodes.
+/// * `nr_threads` - The number of threads per core.
+pub fn check_numa_cpu_topology(numa_nodes: &NumaNodes, nr_threads: u8) -> Result<()> {
+    if nr_threads <= 1 {
+        return Ok(());
+    }
+
+    let mut core_nodes = BTreeMap::<u8, u32>::new();
+    for (id, node) in numa_nodes.iter() {
+        for cpu in node.cpus.iter() {
+            let core = *cpu / nr_threads;
+            match core_nodes.get(&core) {
+                Some(node_id) if node_id != id => bail!(
+                    "Threads of core {} are split across NUMA node {} and {}",
+                    core,
+                    node_id,
+                    id
+                ),
+                _ => {
+                    core_nodes.insert(core, *id);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
 /// Parse the NUMA node memory parameters.
 ///
 /// # Arguments
@@ -120,7 +156,8 @@ pub fn parse_numa_mem(numa_config: &str) -> Result<NumaConfig> {
         .push("")
         .push("nodeid")
         .push("cpus")
-        .push("memdev");
+        .push("memdev")
+        .push("distance");
     cmd_parser.parse(numa_config)?;
 
     let mut config: NumaConfig = NumaConfig::default();
@@ -157,10 +194,66 @@ pub fn parse_numa_mem(numa_config: &str) -> Result<NumaConfig> {
     config.mem_dev = cmd_parser
         .get_value::<String>("memdev")?
         .with_context(|| ConfigError::FieldIsMissing("memdev".to_string(), "numa".to_string()))?;
+    if let Some(distances) = cmd_parser.get_value::<String>("distance")? {
+        config.distances = Some(parse_node_distances(config.numa_id, &distances)?);
+    }
 
     Ok(config)
 }
 
+/// Parse the distances in `-numa node`, which are in format of
+/// `<dst>-<val>[:<dst>-<val>]`.
+fn parse_node_distances(numa_id: u32, distances: &str) -> Result<Vec<NumaDistance>> {
+    let mut result = Vec::new();
+    for item in distances.split(':') {
+        let (dst, val) = item
+            .split_once('-')
+            .with_context(|| format!("Invalid NUMA distance {}, format is <dst>-<val>", item))?;
+        let destination = dst.parse::<u32>().with_context(|| {
+            ConfigError::ConvertValueFailed(dst.to_string(), "distance".to_string())
+        })?;
+        let distance = val.parse::<u8>().with_context(|| {
+            ConfigError::ConvertValueFailed(val.to_string(), "distance".to_string())
+        })?;
+        if destination >= MAX_NODES {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "distance".to_string(),
+                0,
+                true,
+                MAX_NODES as u64,
+                false,
+            )));
+        }
+        if result
+            .iter()
+            .any(|d: &NumaDistance| d.destination == destination)
+        {
+            bail!("Numa destination info {} repeat settings", destination);
+        }
+        check_numa_distance(numa_id, destination, distance)?;
+        result.push(NumaDistance {
+            destination,
+            distance,
+        });
+    }
+
+    Ok(result)
+}
+
+fn check_numa_distance(src: u32, dst: u32, val: u8) -> Result<()> {
+    if val < MIN_NUMA_DISTANCE {
+        bail!("NUMA distance shouldn't be less than 10");
+    }
+    if src == dst && val != MIN_NUMA_DISTANCE {
+        bail!("Local distance of node {} should be 10.", src);
+    }
+    if src != dst && val == MIN_NUMA_DISTANCE {
+        bail!("Remote distance of node {} should be more than 10.", src);
+    }
+
+    Ok(())
+}
+
 /// Parse the NUMA node distance parameters.
 ///
 /// # Arguments
@@ -207,19 +300,7 @@ pub fn parse_numa_distance(numa_dist: &str) -> Result<(u32, NumaDistance)> {
         )));
     }
     if let Some(val) = cmd_parser.get_value::<u8>("val")? {
-        if val < MIN_NUMA_DISTANCE {
-            bail!("NUMA distance shouldn't be less than 10");
-        }
-        if numa_id == dist.destination && val != MIN_NUMA_DISTANCE {
-            bail!("Local distance of node {} should be 10.", numa_id);
-        }
-        if numa_id != dist.destination && val == MIN_NUMA_DISTANCE {
-            bail!(
-                "Remote distance of node {} should be more than 10.",
-                numa_id
-            );
-        }
-
+        check_numa_distance(numa_id, dist.destination, val)?;
         dist.distance = val;
     } else {
         return Err(anyhow!(ConfigError::FieldIsMissing(
@@ -313,6 +394,49 @@ mod tests {
         assert!(parse_numa_distance(numa.1.as_str()).is_err());
     }
 
+    #[test]
+    fn test_parse_numa_node_distance() {
+        let numa_config =
+            parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=1-20:2-30").unwrap();
+        let distances = numa_config.distances.unwrap();
+        assert_eq!(distances.len(), 2);
+        assert_eq!(distances[0].destination, 1);
+        assert_eq!(distances[0].distance, 20);
+        assert_eq!(distances[1].destination, 2);
+        assert_eq!(distances[1].distance, 30);
+
+        let numa_config = parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0").unwrap();
+        assert!(numa_config.distances.is_none());
+
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=1").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=1-10").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=0-20").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=128-20").is_err());
+        assert!(parse_numa_mem("node,nodeid=0,cpus=0-1,memdev=mem0,distance=1-20:1-30").is_err());
+    }
+
+    #[test]
+    fn test_check_numa_cpu_topology() {
+        let mut numa_nodes = BTreeMap::new();
+        numa_nodes.insert(
+            0,
+            NumaNode {
+                cpus: vec![0, 1],
+                ..Default::default()
+            },
+        );
+        numa_nodes.insert(
+            1,
+            NumaNode {
+                cpus: vec![2, 3],
+                ..Default::default()
+            },
+        );
+        assert!(check_numa_cpu_topology(&numa_nodes, 1).is_ok());
+        assert!(check_numa_cpu_topology(&numa_nodes, 2).is_ok());
+        assert!(check_numa_cpu_topology(&numa_nodes, 4).is_err());
+    }
+
     #[test]
     fn test_check_numa_nodes() {
         let nr_cpus = 4;
@@ -385,5 +509,15 @@ mod tests {
         numa_nodes.remove(&1);
         numa_nodes.insert(1, numa_node7);
         assert!(complete_numa_node(&mut numa_nodes, nr_cpus, mem_size).is_err());
+
+        let numa_node8 = NumaNode {
+            cpus: vec![2, 3],
+            distances: BTreeMap::from([(3, 20)]),
+            size: 1073741824,
+            mem_dev: String::from("numa_node8"),
+        };
+        numa_nodes.remove(&1);
+        numa_nodes.insert(1, numa_node8);
+        assert!(complete_numa_node(&mut numa_nodes, nr_cpus, mem_size).is_err());
     }
 }
@@ -10,11 +10,11 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use serde::{Deserialize, Serialize};
 
 use super::error::ConfigError;
-use super::{CmdParser, ConfigCheck, UnsignedInteger};
+use super::{CmdParser, ConfigCheck, UnsignedInteger, MAX_VIRTIO_QUEUE};
 use crate::config::{check_arg_too_long, ExBool};
 use util::num_ops::str_to_usize;
 
@@ -131,6 +131,75 @@ pub fn get_multi_function(pci_cfg: &str) -> Result<bool> {
     Ok(false)
 }
 
+/// Max delay of a coalesced interrupt.
+const MAX_IRQ_COALESCE_US: u32 = 100_000;
+
+/// MSI-X settings of a virtio pci device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VirtioPciMsixConfig {
+    /// Number of MSI-X vectors, one per queue plus one for config space if None. With fewer
+    /// vectors, guest driver maps several queues to one vector.
+    pub vectors: Option<u16>,
+    /// Max time in microseconds that a queue interrupt is delayed to be coalesced with the
+    /// following ones on the same vector, 0 means no interrupt moderation.
+    pub coalesce_us: u32,
+    /// Number of coalesced interrupts which fires the vector at once, 0 means no limit.
+    pub coalesce_frames: u32,
+}
+
+impl ConfigCheck for VirtioPciMsixConfig {
+    fn check(&self) -> Result<()> {
+        if let Some(vectors) = self.vectors {
+            if vectors < 2 || vectors as usize > MAX_VIRTIO_QUEUE + 1 {
+                return Err(anyhow!(ConfigError::IllegalValue(
+                    "MSI-X vectors of virtio pci device".to_string(),
+                    2,
+                    true,
+                    MAX_VIRTIO_QUEUE as u64 + 1,
+                    true,
+                )));
+            }
+        }
+        if self.coalesce_us > MAX_IRQ_COALESCE_US {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "irq-coalesce-us of virtio pci device".to_string(),
+                0,
+                true,
+                MAX_IRQ_COALESCE_US as u64,
+                true,
+            )));
+        }
+        if self.coalesce_frames != 0 && self.coalesce_us == 0 {
+            bail!("irq-coalesce-frames needs irq-coalesce-us to bound the interrupt delay");
+        }
+        Ok(())
+    }
+}
+
+/// Get the MSI-X settings of virtio pci device, `vectors`, `irq-coalesce-us` and
+/// `irq-coalesce-frames`.
+pub fn get_virtio_pci_msix(pci_cfg: &str) -> Result<VirtioPciMsixConfig> {
+    let mut cmd_parser = CmdParser::new("msix");
+    cmd_parser
+        .push("")
+        .push("vectors")
+        .push("irq-coalesce-us")
+        .push("irq-coalesce-frames");
+    cmd_parser.get_parameters(pci_cfg)?;
+
+    let msix = VirtioPciMsixConfig {
+        vectors: cmd_parser.get_value::<u16>("vectors")?,
+        coalesce_us: cmd_parser
+            .get_value::<u32>("irq-coalesce-us")?
+            .unwrap_or_default(),
+        coalesce_frames: cmd_parser
+            .get_value::<u32>("irq-coalesce-frames")?
+            .unwrap_or_default(),
+    };
+    msix.check()?;
+    Ok(msix)
+}
+
 pub fn parse_root_port(rootport_cfg: &str) -> Result<RootPortConfig> {
     let mut cmd_parser = CmdParser::new("pcie-root-port");
     cmd_parser
@@ -265,4 +334,25 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn test_get_virtio_pci_msix() {
+        let msix = get_virtio_pci_msix("virtio-net-pci,bus=pcie.0,addr=0x1").unwrap();
+        assert_eq!(msix, VirtioPciMsixConfig::default());
+
+        let msix = get_virtio_pci_msix(
+            "virtio-net-pci,bus=pcie.0,addr=0x1,vectors=4,irq-coalesce-us=50,irq-coalesce-frames=8",
+        )
+        .unwrap();
+        assert_eq!(msix.vectors, Some(4));
+        assert_eq!(msix.coalesce_us, 50);
+        assert_eq!(msix.coalesce_frames, 8);
+
+        assert!(get_virtio_pci_msix("virtio-net-pci,bus=pcie.0,addr=0x1,vectors=1").is_err());
+        assert!(get_virtio_pci_msix("virtio-net-pci,bus=pcie.0,addr=0x1,vectors=34").is_err());
+        assert!(
+            get_virtio_pci_msix("virtio-net-pci,bus=pcie.0,addr=0x1,irq-coalesce-frames=8")
+                .is_err()
+        );
+    }
 }
@@ -0,0 +1,77 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, VmConfig};
+use util::unix::lookup_user;
+
+/// Privileges which are dropped after devices and sockets are opened.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrivilegeConfig {
+    /// User id and group id to switch to.
+    pub runas: Option<(u32, u32)>,
+    /// Directory to change root to.
+    pub chroot: Option<String>,
+}
+
+impl VmConfig {
+    /// Add '-runas' config to `VmConfig`, in format of `<user>` or `<uid>:<gid>`.
+    pub fn add_runas(&mut self, runas: &str) -> Result<()> {
+        let ids = match runas.split_once(':') {
+            Some((uid, gid)) => match (uid.parse::<u32>(), gid.parse::<u32>()) {
+                (Ok(uid), Ok(gid)) => (uid, gid),
+                _ => bail!("Invalid runas {}, should be <user> or <uid>:<gid>", runas),
+            },
+            // The user is looked up now, as password database is unavailable after chroot.
+            None => lookup_user(runas)?,
+        };
+        self.privilege.runas = Some(ids);
+        Ok(())
+    }
+
+    /// Add '-chroot' config to `VmConfig`.
+    pub fn add_chroot(&mut self, dir: &str) -> Result<()> {
+        check_path_too_long(dir, "chroot")?;
+        if !Path::new(dir).is_dir() {
+            bail!("Chroot directory {} doesn't exist", dir);
+        }
+        self.privilege.chroot = Some(dir.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_privilege() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.privilege, PrivilegeConfig::default());
+
+        vm_config.add_runas("107:108").unwrap();
+        assert_eq!(vm_config.privilege.runas, Some((107, 108)));
+        vm_config.add_runas("root").unwrap();
+        assert_eq!(vm_config.privilege.runas, Some((0, 0)));
+        assert!(vm_config.add_runas("107").is_err());
+        assert!(vm_config.add_runas("107:qemu").is_err());
+        assert!(vm_config.add_runas("no_such_user_for_test").is_err());
+
+        vm_config.add_chroot("/tmp").unwrap();
+        assert_eq!(vm_config.privilege.chroot, Some("/tmp".to_string()));
+        assert!(vm_config.add_chroot("/tmp/no_such_dir_for_test").is_err());
+    }
+}
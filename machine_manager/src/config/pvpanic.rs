@@ -0,0 +1,105 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, Result};
+
+use super::error::ConfigError;
+use crate::config::{check_arg_too_long, CmdParser, ConfigCheck};
+
+/// Guest kernel has panicked.
+pub const PVPANIC_PANICKED: u32 = 1 << 0;
+/// Guest kernel has loaded the crash kernel after panic.
+pub const PVPANIC_CRASH_LOADED: u32 = 1 << 1;
+
+/// Config structure for pvpanic device.
+#[derive(Debug, Clone)]
+pub struct PvPanicConfig {
+    pub id: String,
+    /// Events that the device reports to guest as supported.
+    pub supported_features: u32,
+}
+
+impl Default for PvPanicConfig {
+    fn default() -> Self {
+        PvPanicConfig {
+            id: String::new(),
+            supported_features: PVPANIC_PANICKED | PVPANIC_CRASH_LOADED,
+        }
+    }
+}
+
+impl ConfigCheck for PvPanicConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "pvpanic id")?;
+
+        if self.supported_features & !(PVPANIC_PANICKED | PVPANIC_CRASH_LOADED) != 0 {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "supported-features of pvpanic".to_string(),
+                0,
+                true,
+                u64::from(PVPANIC_PANICKED | PVPANIC_CRASH_LOADED),
+                true,
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the config of pvpanic device.
+///
+/// # Arguments
+///
+/// * `cfg_args` - Config args of pvpanic device.
+pub fn parse_pvpanic(cfg_args: &str) -> Result<PvPanicConfig> {
+    let mut cmd_parser = CmdParser::new("pvpanic");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("supported-features");
+    cmd_parser.parse(cfg_args)?;
+
+    let mut pvpanic_cfg = PvPanicConfig::default();
+    if let Some(id) = cmd_parser.get_value::<String>("id")? {
+        pvpanic_cfg.id = id;
+    }
+    if let Some(features) = cmd_parser.get_value::<u32>("supported-features")? {
+        pvpanic_cfg.supported_features = features;
+    }
+    pvpanic_cfg.check()?;
+
+    Ok(pvpanic_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pvpanic() {
+        let pvpanic_cfg = parse_pvpanic("pvpanic-pci,id=pvpanic0,bus=pcie.0,addr=0x7").unwrap();
+        assert_eq!(pvpanic_cfg.id, "pvpanic0");
+        assert_eq!(
+            pvpanic_cfg.supported_features,
+            PVPANIC_PANICKED | PVPANIC_CRASH_LOADED
+        );
+
+        let pvpanic_cfg = parse_pvpanic("pvpanic,id=pvpanic0,supported-features=1").unwrap();
+        assert_eq!(pvpanic_cfg.supported_features, PVPANIC_PANICKED);
+
+        assert!(parse_pvpanic("pvpanic,id=pvpanic0,supported-features=4").is_err());
+        assert!(parse_pvpanic("pvpanic,id=pvpanic0,supported-features=x").is_err());
+        assert!(parse_pvpanic("pvpanic,id=pvpanic0,unknown=1").is_err());
+    }
+}
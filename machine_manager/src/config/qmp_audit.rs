@@ -0,0 +1,73 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, CmdParser, ConfigError, ExBool, VmConfig};
+
+/// Config of the qmp audit log.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QmpAuditConfig {
+    /// Path of the log file, which is opened in append-only mode at startup.
+    pub path: String,
+    /// Whether to log commands at startup, it can be changed by qmp at runtime.
+    pub enable: bool,
+}
+
+impl VmConfig {
+    /// Add '-qmp-audit' config to `VmConfig`, in format of `path=<file>[,enable=on|off]`.
+    pub fn add_qmp_audit(&mut self, qmp_audit: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("qmp-audit");
+        cmd_parser.push("path").push("enable");
+        cmd_parser.parse(qmp_audit)?;
+
+        let path = cmd_parser.get_value::<String>("path")?.with_context(|| {
+            ConfigError::FieldIsMissing("path".to_string(), "qmp-audit".to_string())
+        })?;
+        check_path_too_long(&path, "qmp-audit path")?;
+        let enable = cmd_parser
+            .get_value::<ExBool>("enable")?
+            .map(bool::from)
+            .unwrap_or(true);
+        self.qmp_audit = Some(QmpAuditConfig { path, enable });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_qmp_audit() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_qmp_audit("path=/var/log/stratovirt/qmp-audit.log")
+            .unwrap();
+        assert_eq!(
+            vm_config.qmp_audit,
+            Some(QmpAuditConfig {
+                path: "/var/log/stratovirt/qmp-audit.log".to_string(),
+                enable: true,
+            })
+        );
+        vm_config
+            .add_qmp_audit("path=/tmp/qmp-audit.log,enable=off")
+            .unwrap();
+        assert!(!vm_config.qmp_audit.as_ref().unwrap().enable);
+
+        assert!(vm_config.add_qmp_audit("enable=on").is_err());
+        assert!(vm_config.add_qmp_audit("path=/tmp/a.log,enable=x").is_err());
+        assert!(vm_config.add_qmp_audit("/tmp/a.log").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CmdParser, VmConfig};
+use util::time::mktime64;
+
+/// Time which RTC starts from.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RtcBase {
+    /// Current time in UTC.
+    #[default]
+    Utc,
+    /// Current time in the local time zone of host.
+    LocalTime,
+    /// Fixed time in seconds since 1970-01-01 00:00:00.
+    Datetime(u64),
+}
+
+impl FromStr for RtcBase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "utc" => Ok(RtcBase::Utc),
+            "localtime" => Ok(RtcBase::LocalTime),
+            _ => parse_datetime(s).map(RtcBase::Datetime),
+        }
+    }
+}
+
+/// Clock which RTC advances with.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RtcClock {
+    /// Host clock, RTC keeps running when VM is paused.
+    #[default]
+    Host,
+    /// VM clock, RTC stops when VM is paused, so guest won't see time jump after resumed.
+    Vm,
+}
+
+impl FromStr for RtcClock {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(RtcClock::Host),
+            "vm" => Ok(RtcClock::Vm),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Config of RTC devices.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct RtcConfig {
+    pub base: RtcBase,
+    pub clock: RtcClock,
+}
+
+impl RtcConfig {
+    /// Get the time when RTC starts, in seconds since 1970-01-01 00:00:00.
+    pub fn start_time(&self) -> u64 {
+        // Since 1970-01-01 00:00:00, it never cause overflow.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time wrong")
+            .as_secs();
+        match self.base {
+            RtcBase::Utc => now,
+            RtcBase::LocalTime => {
+                // SAFETY: `libc::tm` is a plain C struct, all zero is a valid value.
+                let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+                // SAFETY: `localtime_r` only writes the broken-down local time to `tm`.
+                unsafe { libc::localtime_r(&(now as libc::time_t), &mut tm) };
+                now.saturating_add_signed(tm.tm_gmtoff)
+            }
+            RtcBase::Datetime(time) => time,
+        }
+    }
+}
+
+/// Parse datetime in format of "YYYY-MM-DDTHH:MM:SS" or "YYYY-MM-DD", returns seconds
+/// since 1970-01-01 00:00:00.
+fn parse_datetime(datetime: &str) -> Result<u64> {
+    let err = || {
+        anyhow!(
+            "Invalid rtc base {:?}, must be \"utc\", \"localtime\" or in format of \"YYYY-MM-DDTHH:MM:SS\"",
+            datetime
+        )
+    };
+    let (date, time) = datetime.split_once('T').unwrap_or((datetime, "00:00:00"));
+    let parse_fields = |s: &str, sep: char| -> Result<Vec<u64>> {
+        let fields = s
+            .split(sep)
+            .map(|f| f.parse::<u64>().map_err(|_| err()))
+            .collect::<Result<Vec<u64>>>()?;
+        if fields.len() != 3 {
+            return Err(err());
+        }
+        Ok(fields)
+    };
+    let date = parse_fields(date, '-')?;
+    let time = parse_fields(time, ':')?;
+
+    if date[0] < 1970
+        || !(1..=12).contains(&date[1])
+        || !(1..=31).contains(&date[2])
+        || time[0] > 23
+        || time[1] > 59
+        || time[2] > 59
+    {
+        return Err(err());
+    }
+    Ok(mktime64(
+        date[0], date[1], date[2], time[0], time[1], time[2],
+    ))
+}
+
+impl VmConfig {
+    /// Add '-rtc' config to `VmConfig`. `driftfix` passed by libvirt is accepted and ignored.
+    pub fn add_rtc(&mut self, rtc_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("rtc");
+        cmd_parser
+            .push("")
+            .push("base")
+            .push("clock")
+            .push("driftfix");
+        cmd_parser.parse(rtc_config)?;
+
+        if let Some(base) = cmd_parser.get_value::<String>("base")? {
+            self.rtc.base = RtcBase::from_str(&base)?;
+        }
+        if let Some(clock) = cmd_parser.get_value::<String>("clock")? {
+            self.rtc.clock = RtcClock::from_str(&clock).map_err(|_| {
+                anyhow!(
+                    "Invalid rtc clock {}, must be one of \"host\" or \"vm\"",
+                    clock
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_rtc() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.rtc, RtcConfig::default());
+
+        vm_config.add_rtc("base=utc,clock=vm").unwrap();
+        assert_eq!(vm_config.rtc.base, RtcBase::Utc);
+        assert_eq!(vm_config.rtc.clock, RtcClock::Vm);
+
+        vm_config.add_rtc("base=2006-06-17T16:01:21").unwrap();
+        assert_eq!(vm_config.rtc.base, RtcBase::Datetime(1150560081));
+        assert_eq!(vm_config.rtc.start_time(), 1150560081);
+        vm_config.add_rtc("base=2006-06-17,clock=host").unwrap();
+        assert_eq!(vm_config.rtc.base, RtcBase::Datetime(1150502400));
+        assert_eq!(vm_config.rtc.clock, RtcClock::Host);
+
+        assert!(vm_config.add_rtc("base=gmt").is_err());
+        assert!(vm_config.add_rtc("base=1969-12-31").is_err());
+        assert!(vm_config.add_rtc("base=2006-13-17").is_err());
+        assert!(vm_config.add_rtc("base=2006-06-17T24:00:00").is_err());
+        assert!(vm_config.add_rtc("base=2006-06-17T16:01").is_err());
+        assert!(vm_config.add_rtc("clock=rt").is_err());
+        assert!(vm_config.add_rtc("rate=slew").is_err());
+
+        vm_config.add_rtc("base=utc,driftfix=slew").unwrap();
+        assert_eq!(vm_config.rtc.base, RtcBase::Utc);
+        vm_config.add_rtc("").unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::read_to_string;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, CmdParser, ExBool, VmConfig};
+use util::seccomp::{syscall_num, SeccompOpt};
+
+/// Action taken when a thread makes a syscall which isn't allowed by its profile.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SandboxMode {
+    /// The syscall is trapped.
+    #[default]
+    Enforce,
+    /// The syscall is allowed and logged to audit log, used to develop policy.
+    Log,
+}
+
+impl FromStr for SandboxMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(SandboxMode::Enforce),
+            "log" => Ok(SandboxMode::Log),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<SandboxMode> for SeccompOpt {
+    fn from(mode: SandboxMode) -> Self {
+        match mode {
+            SandboxMode::Enforce => SeccompOpt::Trap,
+            SandboxMode::Log => SeccompOpt::Log,
+        }
+    }
+}
+
+/// Syscalls which are allowed or denied for a class of threads, based on the
+/// built-in allowlist of the machine.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxProfile {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl SandboxProfile {
+    fn check(&self) -> Result<()> {
+        for name in self.allow.iter().chain(self.deny.iter()) {
+            if syscall_num(name).is_none() {
+                bail!("Unknown syscall {} in sandbox policy", name);
+            }
+        }
+        if let Some(name) = self.allow.iter().find(|name| self.deny.contains(name)) {
+            bail!(
+                "Syscall {} is both allowed and denied in sandbox policy",
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Sandbox policy which specifies the profiles of thread classes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxPolicy {
+    #[serde(default)]
+    pub main: SandboxProfile,
+    #[serde(default)]
+    pub vcpu: SandboxProfile,
+    #[serde(default)]
+    pub iothread: SandboxProfile,
+    #[serde(default)]
+    pub migration: SandboxProfile,
+}
+
+impl SandboxPolicy {
+    fn from_file(path: &str) -> Result<Self> {
+        let content = read_to_string(path)
+            .with_context(|| format!("Failed to read sandbox policy {}", path))?;
+        let policy: SandboxPolicy = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid sandbox policy {}", path))?;
+        for profile in [
+            &policy.main,
+            &policy.vcpu,
+            &policy.iothread,
+            &policy.migration,
+        ] {
+            profile.check()?;
+        }
+        Ok(policy)
+    }
+}
+
+/// Config of seccomp sandbox.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SandboxConfig {
+    pub enable: bool,
+    pub mode: SandboxMode,
+    /// Per thread class profiles, all threads share the built-in allowlist if absent.
+    pub policy: Option<SandboxPolicy>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        SandboxConfig {
+            enable: true,
+            mode: SandboxMode::default(),
+            policy: None,
+        }
+    }
+}
+
+impl VmConfig {
+    /// Add '-sandbox' config to `VmConfig`.
+    pub fn add_sandbox(&mut self, sandbox_config: &str) -> Result<()> {
+        // `-sandbox` without value enables sandbox.
+        if sandbox_config.is_empty() {
+            self.sandbox.enable = true;
+            return Ok(());
+        }
+
+        let mut cmd_parser = CmdParser::new("sandbox");
+        cmd_parser.push("").push("mode").push("policy");
+        // Options passed by libvirt, which are ignored.
+        cmd_parser
+            .push("obsolete")
+            .push("elevateprivileges")
+            .push("spawn")
+            .push("resourcecontrol");
+        cmd_parser.parse(sandbox_config)?;
+
+        if let Some(enable) = cmd_parser.get_value::<ExBool>("")? {
+            self.sandbox.enable = enable.into();
+        }
+        if let Some(mode) = cmd_parser.get_value::<String>("mode")? {
+            self.sandbox.mode = SandboxMode::from_str(&mode).map_err(|_| {
+                anyhow!(
+                    "Invalid sandbox mode {}, must be one of \"enforce\" or \"log\"",
+                    mode
+                )
+            })?;
+        }
+        if let Some(path) = cmd_parser.get_value::<String>("policy")? {
+            check_path_too_long(&path, "sandbox policy")?;
+            self.sandbox.policy = Some(SandboxPolicy::from_file(&path)?);
+        }
+        Ok(())
+    }
+
+    /// Disable sandbox by '-disable-seccomp', which is kept for compatibility.
+    pub fn disable_sandbox(&mut self) {
+        self.sandbox.enable = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_sandbox() {
+        let mut vm_config = VmConfig::default();
+        assert_eq!(vm_config.sandbox, SandboxConfig::default());
+        assert!(vm_config.sandbox.enable);
+
+        vm_config.add_sandbox("off").unwrap();
+        assert!(!vm_config.sandbox.enable);
+        vm_config.add_sandbox("").unwrap();
+        assert!(vm_config.sandbox.enable);
+        vm_config
+            .add_sandbox("on,obsolete=deny,spawn=deny")
+            .unwrap();
+        assert!(vm_config.sandbox.enable);
+        vm_config.add_sandbox("on,mode=log").unwrap();
+        assert_eq!(vm_config.sandbox.mode, SandboxMode::Log);
+        assert_eq!(SeccompOpt::from(vm_config.sandbox.mode), SeccompOpt::Log);
+
+        assert!(vm_config.add_sandbox("on,mode=kill").is_err());
+        assert!(vm_config.add_sandbox("enable").is_err());
+        assert!(vm_config.add_sandbox("on,policy=/tmp/none.json").is_err());
+    }
+
+    #[test]
+    fn test_sandbox_policy() {
+        let path = "/tmp/test_sandbox_policy.json";
+        let mut vm_config = VmConfig::default();
+        std::fs::write(
+            path,
+            r#"{ "vcpu": { "deny": ["openat", "socket"] }, "migration": { "allow": ["fsync"] } }"#,
+        )
+        .unwrap();
+        vm_config
+            .add_sandbox(&format!("on,policy={}", path))
+            .unwrap();
+        let policy = vm_config.sandbox.policy.as_ref().unwrap();
+        assert_eq!(policy.vcpu.deny, vec!["openat", "socket"]);
+        assert_eq!(policy.migration.allow, vec!["fsync"]);
+        assert_eq!(policy.main, SandboxProfile::default());
+
+        let invalid_policies = [
+            r#"{ "vcpu": { "deny": ["no_such_syscall"] } }"#,
+            r#"{ "vcpu": { "allow": ["fsync"], "deny": ["fsync"] } }"#,
+            r#"{ "vhost": { "deny": ["openat"] } }"#,
+            r#"{ "vcpu": ["openat"] }"#,
+        ];
+        for content in invalid_policies {
+            std::fs::write(path, content).unwrap();
+            assert!(vm_config
+                .add_sandbox(&format!("on,policy={}", path))
+                .is_err());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
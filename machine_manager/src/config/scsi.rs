@@ -14,7 +14,8 @@ use anyhow::{anyhow, bail, Context, Result};
 
 use super::{error::ConfigError, pci_args_check, DiskFormat};
 use crate::config::{
-    check_arg_too_long, CmdParser, ConfigCheck, VmConfig, DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
+    check_arg_too_long, get_chardev_socket_path, CmdParser, ConfigCheck, VmConfig,
+    DEFAULT_VIRTQUEUE_SIZE, MAX_VIRTIO_QUEUE,
 };
 use util::aio::AioEngine;
 
@@ -46,6 +47,10 @@ pub struct ScsiCntlrConfig {
     pub boot_prefix: Option<String>,
     /// Virtqueue size for all queues.
     pub queue_size: u16,
+    /// Chardev of the vhost-user-scsi backend.
+    pub chardev: Option<String>,
+    /// Socket path of the vhost-user-scsi backend.
+    pub socket_path: Option<String>,
 }
 
 impl Default for ScsiCntlrConfig {
@@ -57,6 +62,8 @@ impl Default for ScsiCntlrConfig {
             queues: 1,
             boot_prefix: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            chardev: None,
+            socket_path: None,
         }
     }
 }
@@ -140,6 +147,54 @@ pub fn parse_scsi_controller(
     Ok(cntlr_cfg)
 }
 
+pub fn parse_vhost_user_scsi(
+    vm_config: &mut VmConfig,
+    drive_config: &str,
+    queues_auto: Option<u16>,
+) -> Result<ScsiCntlrConfig> {
+    let mut cmd_parser = CmdParser::new("vhost-user-scsi-pci");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("chardev")
+        .push("num-queues")
+        .push("queue-size");
+
+    cmd_parser.parse(drive_config)?;
+
+    pci_args_check(&cmd_parser)?;
+
+    let mut cntlr_cfg = ScsiCntlrConfig::default();
+
+    cntlr_cfg.id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+        ConfigError::FieldIsMissing("id".to_string(), "vhost-user-scsi-pci".to_string())
+    })?;
+
+    let chardev = cmd_parser
+        .get_value::<String>("chardev")?
+        .with_context(|| {
+            ConfigError::FieldIsMissing("chardev".to_string(), "vhost-user-scsi-pci".to_string())
+        })?;
+    cntlr_cfg.socket_path = Some(get_chardev_socket_path(&chardev, vm_config)?);
+    cntlr_cfg.chardev = Some(chardev);
+
+    if let Some(queues) = cmd_parser.get_value::<u32>("num-queues")? {
+        cntlr_cfg.queues = queues;
+    } else if let Some(queues) = queues_auto {
+        cntlr_cfg.queues = queues as u32;
+    }
+
+    if let Some(size) = cmd_parser.get_value::<u16>("queue-size")? {
+        cntlr_cfg.queue_size = size;
+    }
+
+    cntlr_cfg.check()?;
+    Ok(cntlr_cfg)
+}
+
 #[derive(Clone, Debug)]
 pub struct ScsiDevConfig {
     /// Scsi Device id.
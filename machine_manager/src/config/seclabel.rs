@@ -0,0 +1,78 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Result};
+
+use crate::config::{CmdParser, VmConfig};
+use util::seclabel::{SecurityLabel, SecurityModel};
+
+impl VmConfig {
+    /// Add '-seclabel' config to `VmConfig`, in format of `<model>,label=<label>`. The label
+    /// should be the last one and is taken as it is, as SELinux categories are separated by
+    /// comma, e.g. `selinux,label=system_u:object_r:svirt_image_t:s0:c1,c2`.
+    pub fn add_seclabel(&mut self, seclabel: &str) -> Result<()> {
+        let (params, label) = match seclabel.split_once(",label=") {
+            Some((params, label)) if !label.is_empty() => (params, label),
+            _ => bail!("Label of seclabel is not set"),
+        };
+        if label.contains('\0') {
+            bail!("Invalid seclabel label {}", label);
+        }
+
+        let mut cmd_parser = CmdParser::new("seclabel");
+        cmd_parser.push("");
+        cmd_parser.parse(params)?;
+        let model = match cmd_parser.get_value::<String>("")?.as_deref() {
+            Some("selinux") => SecurityModel::Selinux,
+            Some("apparmor") => SecurityModel::Apparmor,
+            _ => bail!("Invalid seclabel model, must be one of \"selinux\" or \"apparmor\""),
+        };
+        self.seclabel = Some(SecurityLabel {
+            model,
+            label: label.to_string(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_seclabel() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_seclabel("selinux,label=system_u:object_r:svirt_image_t:s0:c1,c2")
+            .unwrap();
+        assert_eq!(
+            vm_config.seclabel,
+            Some(SecurityLabel {
+                model: SecurityModel::Selinux,
+                label: "system_u:object_r:svirt_image_t:s0:c1,c2".to_string(),
+            })
+        );
+        vm_config
+            .add_seclabel("apparmor,label=libvirt-1234")
+            .unwrap();
+        assert_eq!(
+            vm_config.seclabel.as_ref().unwrap().model,
+            SecurityModel::Apparmor
+        );
+
+        assert!(vm_config.add_seclabel("selinux").is_err());
+        assert!(vm_config.add_seclabel("selinux,label=").is_err());
+        assert!(vm_config.add_seclabel("smack,label=vm").is_err());
+        assert!(vm_config.add_seclabel("selinux,type=x,label=vm").is_err());
+        assert!(vm_config.add_seclabel("label=vm").is_err());
+    }
+}
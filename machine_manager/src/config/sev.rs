@@ -0,0 +1,187 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, CmdParser, ConfigError, ExBool, VmConfig};
+
+/// Default guest policy of SEV-SNP guest, SMT is allowed.
+const DEFAULT_SNP_POLICY: u64 = 0x3_0000;
+const DEFAULT_SEV_DEVICE: &str = "/dev/sev";
+/// Length of host data in bytes.
+const SNP_HOST_DATA_LEN: usize = 32;
+
+/// Config of SEV-SNP guest object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SevSnpObjConfig {
+    /// Object Id.
+    pub id: String,
+    /// Guest policy which is enforced by SEV firmware.
+    pub policy: u64,
+    /// Data provided by host, which is included in attestation report.
+    pub host_data: [u8; SNP_HOST_DATA_LEN],
+    /// Path of SEV firmware device.
+    pub sev_device: String,
+    /// Whether to add the hashes of kernel, initrd and cmdline to the hashes table
+    /// of firmware, so that they are included in the launch measurement.
+    pub kernel_hashes: bool,
+}
+
+fn parse_snp_policy(policy: &str) -> Result<u64> {
+    let ret = match policy.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => policy.parse::<u64>(),
+    };
+    ret.map_err(|_| {
+        anyhow!(ConfigError::ConvertValueFailed(
+            policy.to_string(),
+            "policy".to_string()
+        ))
+    })
+}
+
+fn parse_host_data(host_data: &str) -> Result<[u8; SNP_HOST_DATA_LEN]> {
+    if host_data.len() != SNP_HOST_DATA_LEN * 2 || !host_data.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        bail!(
+            "host-data should be {} hex characters, current is {}",
+            SNP_HOST_DATA_LEN * 2,
+            host_data
+        );
+    }
+    let mut data = [0_u8; SNP_HOST_DATA_LEN];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&host_data[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(data)
+}
+
+impl VmConfig {
+    /// Add '-object sev-snp-guest' config to `VmConfig`, in format of
+    /// `sev-snp-guest,id=<id>[,policy=<policy>][,host-data=<hex>][,sev-device=<path>]
+    /// [,kernel-hashes=on|off]`.
+    pub fn add_sev_snp(&mut self, sev_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("sev-snp-guest");
+        cmd_parser
+            .push("")
+            .push("id")
+            .push("policy")
+            .push("host-data")
+            .push("sev-device")
+            .push("kernel-hashes");
+        cmd_parser.parse(sev_config)?;
+
+        let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "sev-snp-guest".to_string())
+        })?;
+        let policy = match cmd_parser.get_value::<String>("policy")? {
+            Some(policy) => parse_snp_policy(&policy)?,
+            None => DEFAULT_SNP_POLICY,
+        };
+        let host_data = match cmd_parser.get_value::<String>("host-data")? {
+            Some(host_data) => parse_host_data(&host_data)?,
+            None => [0; SNP_HOST_DATA_LEN],
+        };
+        let sev_device = cmd_parser
+            .get_value::<String>("sev-device")?
+            .unwrap_or_else(|| DEFAULT_SEV_DEVICE.to_string());
+        check_path_too_long(&sev_device, "sev-device")?;
+        let kernel_hashes = cmd_parser
+            .get_value::<ExBool>("kernel-hashes")?
+            .map_or(false, |v| v.into());
+
+        if self.object.sev_snp_object.contains_key(&id) {
+            bail!(ConfigError::IdRepeat("sev-snp-guest".to_string(), id));
+        }
+        self.object.sev_snp_object.insert(
+            id.clone(),
+            SevSnpObjConfig {
+                id,
+                policy,
+                host_data,
+                sev_device,
+                kernel_hashes,
+            },
+        );
+        Ok(())
+    }
+
+    /// Get the SEV-SNP guest object referred by `confidential-guest-support` of machine.
+    pub fn get_sev_snp(&self) -> Result<Option<&SevSnpObjConfig>> {
+        match &self.machine_config.confidential_guest {
+            Some(id) => Ok(Some(self.object.sev_snp_object.get(id).with_context(
+                || format!("Confidential guest object {} is not found", id),
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_sev_snp() {
+        let mut vm_config = VmConfig::default();
+        vm_config.add_object("sev-snp-guest,id=snp0").unwrap();
+        assert_eq!(
+            vm_config.object.sev_snp_object.get("snp0"),
+            Some(&SevSnpObjConfig {
+                id: "snp0".to_string(),
+                policy: 0x30000,
+                host_data: [0; 32],
+                sev_device: "/dev/sev".to_string(),
+                kernel_hashes: false,
+            })
+        );
+        assert!(vm_config.add_object("sev-snp-guest,id=snp0").is_err());
+
+        let host_data = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        vm_config
+            .add_object(&format!(
+                "sev-snp-guest,id=snp1,policy=0xb0000,host-data={},sev-device=/dev/sev1,kernel-hashes=on",
+                host_data
+            ))
+            .unwrap();
+        let snp = vm_config.object.sev_snp_object.get("snp1").unwrap();
+        assert_eq!(snp.policy, 0xb0000);
+        assert_eq!(snp.host_data[1], 0x11);
+        assert_eq!(snp.host_data[31], 0xff);
+        assert_eq!(snp.sev_device, "/dev/sev1");
+        assert!(snp.kernel_hashes);
+
+        assert!(vm_config.get_sev_snp().unwrap().is_none());
+        vm_config
+            .add_machine("q35,confidential-guest-support=snp1")
+            .unwrap();
+        assert_eq!(vm_config.get_sev_snp().unwrap().unwrap().id, "snp1");
+        vm_config
+            .add_machine("q35,confidential-guest-support=snp2")
+            .unwrap();
+        assert!(vm_config.get_sev_snp().is_err());
+
+        assert!(vm_config
+            .add_object("sev-snp-guest,policy=0x30000")
+            .is_err());
+        assert!(vm_config
+            .add_object("sev-snp-guest,id=snp2,policy=0xg0000")
+            .is_err());
+        assert!(vm_config
+            .add_object("sev-snp-guest,id=snp2,host-data=0011")
+            .is_err());
+        assert!(vm_config
+            .add_object("sev-snp-guest,id=snp2,kernel-hashes=maybe")
+            .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{memory_unit_conversion, CmdParser, ConfigError, ExBool, VmConfig, M};
+
+/// Max number of SGX EPC sections exposed to guest.
+pub const MAX_SGX_EPC_SECTIONS: usize = 8;
+/// Size of SGX EPC section must be aligned to page size.
+const SGX_EPC_ALIGN: u64 = 4096;
+
+/// Config of SGX EPC memory backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SgxEpcObjConfig {
+    /// Object Id.
+    pub id: String,
+    /// Size of the EPC section.
+    pub size: u64,
+    /// Allocate EPC pages from host at startup.
+    pub prealloc: bool,
+}
+
+/// SGX EPC section of machine, which refers to an EPC memory backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SgxEpcConfig {
+    /// Id of the EPC memory backend.
+    pub memdev: String,
+    /// Guest NUMA node which the EPC section belongs to.
+    pub node: u32,
+}
+
+impl VmConfig {
+    /// Add '-object memory-backend-epc' config to `VmConfig`, in format of
+    /// `memory-backend-epc,id=<id>,size=<size>[,prealloc=on|off]`.
+    pub fn add_sgx_epc_object(&mut self, epc_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("memory-backend-epc");
+        cmd_parser.push("").push("id").push("size").push("prealloc");
+        cmd_parser.parse(epc_config)?;
+
+        let id = cmd_parser.get_value::<String>("id")?.with_context(|| {
+            ConfigError::FieldIsMissing("id".to_string(), "memory-backend-epc".to_string())
+        })?;
+        let size = cmd_parser.get_value::<String>("size")?.with_context(|| {
+            ConfigError::FieldIsMissing("size".to_string(), "memory-backend-epc".to_string())
+        })?;
+        let size = memory_unit_conversion(&size, M)?;
+        if size == 0 || size % SGX_EPC_ALIGN != 0 {
+            bail!(
+                "Size of memory-backend-epc {} should be nonzero and aligned to 4KiB",
+                id
+            );
+        }
+        let prealloc = cmd_parser
+            .get_value::<ExBool>("prealloc")?
+            .map(bool::from)
+            .unwrap_or(false);
+
+        if self.object.sgx_epc_object.contains_key(&id) {
+            bail!(ConfigError::IdRepeat("memory-backend-epc".to_string(), id));
+        }
+        self.object
+            .sgx_epc_object
+            .insert(id.clone(), SgxEpcObjConfig { id, size, prealloc });
+        Ok(())
+    }
+
+    /// Get SGX EPC sections of machine with their memory backends, in the order of
+    /// sections.
+    pub fn get_sgx_epc(&self) -> Result<Vec<(&SgxEpcConfig, &SgxEpcObjConfig)>> {
+        let mut sections = Vec::new();
+        for epc in self.machine_config.sgx_epc.iter() {
+            let obj = self
+                .object
+                .sgx_epc_object
+                .get(&epc.memdev)
+                .with_context(|| format!("SGX EPC memory backend {} is not found", epc.memdev))?;
+            if sections
+                .iter()
+                .any(|(e, _): &(&SgxEpcConfig, _)| e.memdev == epc.memdev)
+            {
+                bail!(
+                    "SGX EPC memory backend {} is used more than once",
+                    epc.memdev
+                );
+            }
+            sections.push((epc, obj));
+        }
+        Ok(sections)
+    }
+}
+
+/// Parse `sgx-epc.<n>.memdev=<id>[,sgx-epc.<n>.node=<node>]` of machine, the sections
+/// are numbered from 0 continuously.
+///
+/// # Arguments
+///
+/// * `cmd_parser` - Parser of machine config, which `sgx_epc_push_params` is called for.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn parse_sgx_epc(cmd_parser: &CmdParser) -> Result<Vec<SgxEpcConfig>> {
+    let mut sgx_epc = Vec::new();
+    let mut end = false;
+    for index in 0..MAX_SGX_EPC_SECTIONS {
+        let memdev = cmd_parser.get_value::<String>(&format!("sgx-epc.{}.memdev", index))?;
+        let node = cmd_parser.get_value::<u32>(&format!("sgx-epc.{}.node", index))?;
+        match memdev {
+            Some(memdev) if !end => sgx_epc.push(SgxEpcConfig {
+                memdev,
+                node: node.unwrap_or(0),
+            }),
+            Some(_) => bail!("SGX EPC sections should be numbered from 0 continuously"),
+            None if node.is_some() => {
+                bail!("memdev of SGX EPC section {} is not set", index)
+            }
+            None => end = true,
+        }
+    }
+    Ok(sgx_epc)
+}
+
+/// Add parameters of SGX EPC sections to the parser of machine config.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn sgx_epc_push_params(cmd_parser: &mut CmdParser) {
+    for index in 0..MAX_SGX_EPC_SECTIONS {
+        cmd_parser
+            .push(&format!("sgx-epc.{}.memdev", index))
+            .push(&format!("sgx-epc.{}.node", index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_add_sgx_epc() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_object("memory-backend-epc,id=epc0,size=64M,prealloc=on")
+            .unwrap();
+        vm_config
+            .add_object("memory-backend-epc,id=epc1,size=32M")
+            .unwrap();
+        assert_eq!(
+            vm_config.object.sgx_epc_object.get("epc0"),
+            Some(&SgxEpcObjConfig {
+                id: "epc0".to_string(),
+                size: 64 * M,
+                prealloc: true,
+            })
+        );
+        assert!(!vm_config.object.sgx_epc_object["epc1"].prealloc);
+        assert!(vm_config
+            .add_object("memory-backend-epc,id=epc0,size=64M")
+            .is_err());
+        assert!(vm_config.add_object("memory-backend-epc,id=epc2").is_err());
+        assert!(vm_config
+            .add_object("memory-backend-epc,id=epc2,size=5K")
+            .is_err());
+
+        vm_config
+            .add_machine("q35,sgx-epc.0.memdev=epc0,sgx-epc.1.memdev=epc1,sgx-epc.1.node=1")
+            .unwrap();
+        let sections = vm_config.get_sgx_epc().unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].0.node, 0);
+        assert_eq!(sections[0].1.size, 64 * M);
+        assert_eq!(sections[1].0.node, 1);
+        assert_eq!(sections[1].1.id, "epc1");
+
+        vm_config
+            .add_machine("q35,sgx-epc.0.memdev=epc0,sgx-epc.1.memdev=epc0")
+            .unwrap();
+        assert!(vm_config.get_sgx_epc().is_err());
+        vm_config.add_machine("q35,sgx-epc.0.memdev=epc3").unwrap();
+        assert!(vm_config.get_sgx_epc().is_err());
+        assert!(vm_config.add_machine("q35,sgx-epc.1.memdev=epc0").is_err());
+        assert!(vm_config.add_machine("q35,sgx-epc.0.node=0").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::error::ConfigError;
+use crate::config::{
+    check_arg_too_long, get_chardev_socket_path, CmdParser, ConfigCheck, VmConfig,
+    MAX_SOCK_PATH_LENGTH,
+};
+
+/// Config structure for TPM backend, which is an external swtpm process.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TpmDevConfig {
+    pub id: String,
+    /// Id of the chardev connected to the control channel of swtpm.
+    pub chardev: String,
+}
+
+impl ConfigCheck for TpmDevConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "tpmdev id")?;
+        check_arg_too_long(&self.chardev, "tpmdev chardev")
+    }
+}
+
+/// Config structure for TPM CRB device.
+#[derive(Debug, Clone, Default)]
+pub struct TpmConfig {
+    pub id: String,
+    /// Path of the control socket of swtpm.
+    pub sock_path: String,
+}
+
+impl ConfigCheck for TpmConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "tpm-crb id")?;
+
+        if self.sock_path.len() > MAX_SOCK_PATH_LENGTH {
+            return Err(anyhow!(ConfigError::StringLengthTooLong(
+                "tpm socket path".to_string(),
+                MAX_SOCK_PATH_LENGTH,
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl VmConfig {
+    /// Add TPM backend config to `VmConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tpmdev_config` - Config args of TPM backend.
+    pub fn add_tpmdev(&mut self, tpmdev_config: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("tpmdev");
+        cmd_parser.push("").push("id").push("chardev");
+        cmd_parser.parse(tpmdev_config)?;
+
+        let backend = cmd_parser.get_value::<String>("")?.unwrap_or_default();
+        if backend != "emulator" {
+            bail!("Unsupported tpmdev backend: {:?}", backend);
+        }
+        let tpmdev = TpmDevConfig {
+            id: cmd_parser.get_value::<String>("id")?.with_context(|| {
+                ConfigError::FieldIsMissing("id".to_string(), "tpmdev".to_string())
+            })?,
+            chardev: cmd_parser
+                .get_value::<String>("chardev")?
+                .with_context(|| {
+                    ConfigError::FieldIsMissing("chardev".to_string(), "tpmdev".to_string())
+                })?,
+        };
+        tpmdev.check()?;
+
+        if self.tpmdevs.contains_key(&tpmdev.id) {
+            return Err(anyhow!(ConfigError::IdRepeat(
+                "tpmdev".to_string(),
+                tpmdev.id
+            )));
+        }
+        self.tpmdevs.insert(tpmdev.id.clone(), tpmdev);
+        Ok(())
+    }
+}
+
+/// Parse the config of TPM CRB device.
+///
+/// # Arguments
+///
+/// * `vm_config` - VM configuration, which holds the TPM backend and chardev.
+/// * `cfg_args` - Config args of TPM CRB device.
+pub fn parse_tpm_crb(vm_config: &mut VmConfig, cfg_args: &str) -> Result<TpmConfig> {
+    let mut cmd_parser = CmdParser::new("tpm-crb");
+    cmd_parser.push("").push("id").push("tpmdev");
+    cmd_parser.parse(cfg_args)?;
+
+    let tpmdev_id = cmd_parser.get_value::<String>("tpmdev")?.with_context(|| {
+        ConfigError::FieldIsMissing("tpmdev".to_string(), "tpm-crb".to_string())
+    })?;
+    let tpmdev = vm_config
+        .tpmdevs
+        .remove(&tpmdev_id)
+        .with_context(|| format!("Tpmdev {:?} not found or is in use", tpmdev_id))?;
+
+    let tpm_cfg = TpmConfig {
+        id: cmd_parser.get_value::<String>("id")?.unwrap_or_default(),
+        sock_path: get_chardev_socket_path(&tpmdev.chardev, vm_config)?,
+    };
+    tpm_cfg.check()?;
+
+    Ok(tpm_cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tpm_crb() {
+        let mut vm_config = VmConfig::default();
+        assert!(vm_config
+            .add_tpmdev("passthrough,id=tpm0,chardev=chrtpm")
+            .is_err());
+        assert!(vm_config.add_tpmdev("emulator,chardev=chrtpm").is_err());
+        assert!(vm_config.add_tpmdev("emulator,id=tpm0").is_err());
+        assert!(vm_config
+            .add_tpmdev("emulator,id=tpm0,chardev=chrtpm")
+            .is_ok());
+        assert!(vm_config
+            .add_tpmdev("emulator,id=tpm0,chardev=chrtpm")
+            .is_err());
+        vm_config
+            .add_chardev("socket,id=chrtpm,path=/tmp/swtpm-sock")
+            .unwrap();
+
+        assert!(parse_tpm_crb(&mut vm_config, "tpm-crb,id=tpm-crb0").is_err());
+        assert!(parse_tpm_crb(&mut vm_config, "tpm-crb,tpmdev=tpm1").is_err());
+        let tpm_cfg = parse_tpm_crb(&mut vm_config, "tpm-crb,id=tpm-crb0,tpmdev=tpm0").unwrap();
+        assert_eq!(tpm_cfg.id, "tpm-crb0");
+        assert_eq!(tpm_cfg.sock_path, "/tmp/swtpm-sock");
+
+        // The backend can not be shared by multiple TPM devices.
+        assert!(parse_tpm_crb(&mut vm_config, "tpm-crb,id=tpm-crb1,tpmdev=tpm0").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use super::{error::ConfigError, pci_args_check};
+use crate::config::{
+    check_arg_too_long, check_path_too_long, CmdParser, ConfigCheck, DEFAULT_VIRTQUEUE_SIZE,
+};
+
+/// Max size of each virtqueue for vhost-vdpa device, the actual size is also limited by the
+/// vdpa device.
+const MAX_QUEUE_SIZE_VDPA: u16 = 4096;
+
+/// Config struct for vhost-vdpa device.
+#[derive(Debug, Clone)]
+pub struct VdpaConfig {
+    /// Device id.
+    pub id: String,
+    /// Path of the vhost-vdpa character device, such as `/dev/vhost-vdpa-0`.
+    pub vhostdev: String,
+    /// Fd of the opened vhost-vdpa character device passed by management.
+    pub vhostfd: Option<i32>,
+    /// Virtqueue size for all queues.
+    pub queue_size: u16,
+}
+
+impl Default for VdpaConfig {
+    fn default() -> Self {
+        VdpaConfig {
+            id: "".to_string(),
+            vhostdev: "".to_string(),
+            vhostfd: None,
+            queue_size: DEFAULT_VIRTQUEUE_SIZE,
+        }
+    }
+}
+
+impl ConfigCheck for VdpaConfig {
+    fn check(&self) -> Result<()> {
+        check_arg_too_long(&self.id, "vhost-vdpa device id")?;
+        check_path_too_long(&self.vhostdev, "vhostdev")?;
+
+        if self.queue_size < 2 || self.queue_size > MAX_QUEUE_SIZE_VDPA {
+            return Err(anyhow!(ConfigError::IllegalValue(
+                "queue size of vhost-vdpa device".to_string(),
+                2,
+                true,
+                MAX_QUEUE_SIZE_VDPA as u64,
+                true
+            )));
+        }
+
+        if self.queue_size & (self.queue_size - 1) != 0 {
+            bail!("queue size of vhost-vdpa device should be power of 2!");
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse `vhost-vdpa-net-pci` or `vhost-vdpa-blk-pci` device config.
+pub fn parse_vhost_vdpa(vdpa_config: &str) -> Result<VdpaConfig> {
+    let mut cmd_parser = CmdParser::new("vhost-vdpa");
+    cmd_parser
+        .push("")
+        .push("id")
+        .push("bus")
+        .push("addr")
+        .push("multifunction")
+        .push("vhostdev")
+        .push("vhostfd")
+        .push("queue-size");
+
+    cmd_parser.parse(vdpa_config)?;
+
+    pci_args_check(&cmd_parser)?;
+
+    let mut vdpa = VdpaConfig::default();
+    vdpa.id = cmd_parser
+        .get_value::<String>("id")?
+        .with_context(|| ConfigError::FieldIsMissing("id".to_string(), "vhost-vdpa".to_string()))?;
+    vdpa.vhostdev = cmd_parser
+        .get_value::<String>("vhostdev")?
+        .with_context(|| {
+            ConfigError::FieldIsMissing("vhostdev".to_string(), "vhost-vdpa".to_string())
+        })?;
+    vdpa.vhostfd = cmd_parser.get_value::<i32>("vhostfd")?;
+
+    if let Some(size) = cmd_parser.get_value::<u16>("queue-size")? {
+        vdpa.queue_size = size;
+    }
+
+    vdpa.check()?;
+    Ok(vdpa)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_vdpa_config_cmdline_parser() {
+        let vdpa_config = parse_vhost_vdpa(
+            "vhost-vdpa-net-pci,id=net0,vhostdev=/dev/vhost-vdpa-0,bus=pcie.0,addr=0x3",
+        )
+        .unwrap();
+        assert_eq!(vdpa_config.id, "net0");
+        assert_eq!(vdpa_config.vhostdev, "/dev/vhost-vdpa-0");
+        assert_eq!(vdpa_config.vhostfd, None);
+        assert_eq!(vdpa_config.queue_size, DEFAULT_VIRTQUEUE_SIZE);
+
+        let vdpa_config = parse_vhost_vdpa(
+            "vhost-vdpa-blk-pci,id=blk0,vhostdev=/dev/vhost-vdpa-1,vhostfd=33,queue-size=1024",
+        )
+        .unwrap();
+        assert_eq!(vdpa_config.vhostfd, Some(33));
+        assert_eq!(vdpa_config.queue_size, 1024);
+
+        assert!(parse_vhost_vdpa("vhost-vdpa-net-pci,id=net0").is_err());
+        assert!(parse_vhost_vdpa("vhost-vdpa-net-pci,vhostdev=/dev/vhost-vdpa-0").is_err());
+        assert!(parse_vhost_vdpa(
+            "vhost-vdpa-net-pci,id=net0,vhostdev=/dev/vhost-vdpa-0,queue-size=100"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{check_path_too_long, memory_unit_conversion, CmdParser, VmConfig, M};
+
+/// Limits of the resources used by StratoVirt itself, which keep VMM from starving host.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VmmLimitsConfig {
+    /// RLIMIT_MEMLOCK in bytes, `u64::MAX` means unlimited.
+    pub memlock: Option<u64>,
+    /// RLIMIT_NOFILE.
+    pub nofile: Option<u64>,
+    /// Max number of malloc arenas.
+    pub malloc_arenas: Option<u32>,
+    /// Threaded cgroup v2 which the threads of VMM except vCPUs are moved to.
+    pub cgroup: Option<String>,
+}
+
+impl VmConfig {
+    /// Add '-vmm-limits' config to `VmConfig`, in format of
+    /// `[memlock=<size>|unlimited][,nofile=<n>][,malloc-arenas=<n>][,cgroup=<path>]`.
+    pub fn add_vmm_limits(&mut self, limits: &str) -> Result<()> {
+        let mut cmd_parser = CmdParser::new("vmm-limits");
+        cmd_parser
+            .push("memlock")
+            .push("nofile")
+            .push("malloc-arenas")
+            .push("cgroup");
+        cmd_parser.parse(limits)?;
+
+        let mut config = VmmLimitsConfig::default();
+        if let Some(memlock) = cmd_parser.get_value::<String>("memlock")? {
+            config.memlock = Some(match memlock.as_str() {
+                "unlimited" => u64::MAX,
+                size => memory_unit_conversion(size, M)?,
+            });
+        }
+        if let Some(nofile) = cmd_parser.get_value::<u64>("nofile")? {
+            if nofile == 0 {
+                bail!("nofile of vmm-limits should be nonzero");
+            }
+            config.nofile = Some(nofile);
+        }
+        if let Some(arenas) = cmd_parser.get_value::<u32>("malloc-arenas")? {
+            if arenas == 0 || arenas > i32::MAX as u32 {
+                bail!(
+                    "malloc-arenas of vmm-limits should be in range [1, {}]",
+                    i32::MAX
+                );
+            }
+            config.malloc_arenas = Some(arenas);
+        }
+        if let Some(cgroup) = cmd_parser.get_value::<String>("cgroup")? {
+            check_path_too_long(&cgroup, "cgroup")?;
+            config.cgroup = Some(cgroup);
+        }
+        if config == VmmLimitsConfig::default() {
+            bail!("No limit is set by vmm-limits");
+        }
+        self.vmm_limits = config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_vmm_limits() {
+        let mut vm_config = VmConfig::default();
+        vm_config
+            .add_vmm_limits("memlock=1G,nofile=4096,malloc-arenas=2,cgroup=/sys/fs/cgroup/vm1/vmm")
+            .unwrap();
+        assert_eq!(
+            vm_config.vmm_limits,
+            VmmLimitsConfig {
+                memlock: Some(1 << 30),
+                nofile: Some(4096),
+                malloc_arenas: Some(2),
+                cgroup: Some("/sys/fs/cgroup/vm1/vmm".to_string()),
+            }
+        );
+        vm_config.add_vmm_limits("memlock=unlimited").unwrap();
+        assert_eq!(vm_config.vmm_limits.memlock, Some(u64::MAX));
+        assert!(vm_config.vmm_limits.nofile.is_none());
+
+        assert!(vm_config.add_vmm_limits("").is_err());
+        assert!(vm_config.add_vmm_limits("nofile=0").is_err());
+        assert!(vm_config.add_vmm_limits("malloc-arenas=0").is_err());
+        assert!(vm_config.add_vmm_limits("memlock=lots").is_err());
+        assert!(vm_config.add_vmm_limits("cpu=1").is_err());
+    }
+}
@@ -13,18 +13,38 @@
 use std::collections::HashMap;
 use std::os::unix::prelude::RawFd;
 use std::sync::{Arc, Mutex};
-use std::{process, thread};
+use std::thread;
 
 use anyhow::bail;
-use log::info;
+use log::{error, info};
 
-use super::config::IothreadConfig;
+use super::config::{HostMemPolicy, IothreadConfig};
+use crate::event;
 use crate::machine::IOTHREADS;
-use crate::qmp::qmp_schema::IothreadInfo;
+use crate::qmp::qmp_channel::QmpChannel;
+use crate::qmp::qmp_schema::{EventLoopStall, IothreadInfo};
 use crate::signal_handler::get_signal;
 use util::loop_context::{
-    gen_delete_notifiers, get_notifiers_fds, EventLoopContext, EventLoopManager, EventNotifier,
+    gen_delete_notifiers, get_notifiers_fds, set_slow_callback_hook, EventLoopContext,
+    EventLoopManager, EventLoopMetrics, EventNotifier,
 };
+use util::seccomp::{apply_thread_filter, ThreadClass};
+use util::syscall::set_mempolicy;
+use util::unix::{gettid, set_thread_affinity};
+
+/// Bind the memory allocated by the current thread, such as its stack and the IO buffers,
+/// to the host NUMA `nodes`.
+fn bind_thread_host_nodes(nodes: &[u32]) -> util::Result<()> {
+    let max_node = match nodes.iter().max() {
+        Some(node) => *node as usize,
+        None => return Ok(()),
+    };
+    let mut nmask = vec![0_u64; max_node / 64 + 1];
+    for node in nodes.iter() {
+        nmask[(*node / 64) as usize] |= 1_u64 << (*node % 64);
+    }
+    set_mempolicy(HostMemPolicy::Bind as u32, nmask, max_node as u64)
+}
 
 /// This struct used to manage all events occur during VM lifetime.
 /// # Notes
@@ -49,9 +69,15 @@ impl EventLoop {
     /// * `iothreads` - refer to `-iothread` params
     pub fn object_init(iothreads: &Option<Vec<IothreadConfig>>) -> util::Result<()> {
         let mut io_threads = HashMap::new();
+        let mut affinities = HashMap::new();
+        let mut host_nodes = HashMap::new();
         if let Some(thrs) = iothreads {
             for thr in thrs {
-                io_threads.insert(thr.id.clone(), EventLoopContext::new());
+                let mut ctx = EventLoopContext::new();
+                ctx.set_name(&thr.id);
+                io_threads.insert(thr.id.clone(), ctx);
+                affinities.insert(thr.id.clone(), thr.affinity.clone());
+                host_nodes.insert(thr.id.clone(), thr.host_nodes.clone());
             }
         }
 
@@ -63,20 +89,48 @@ impl EventLoop {
                     main_loop: EventLoopContext::new(),
                     io_threads,
                 });
+                set_slow_callback_hook(Box::new(|id, fd, duration, budget| {
+                    let stall = EventLoopStall {
+                        id: id.to_string(),
+                        fd,
+                        duration_us: duration.as_micros() as u64,
+                        budget_us: budget.as_micros() as u64,
+                    };
+                    event!(EventLoopStall; stall);
+                }));
 
                 if let Some(event_loop) = GLOBAL_EVENT_LOOP.as_mut() {
                     for (id, ctx) in &mut event_loop.io_threads {
+                        let affinity = affinities.remove(id).flatten();
+                        let nodes = host_nodes.remove(id).flatten();
                         thread::Builder::new().name(id.to_string()).spawn(move || {
+                            if let Some(cpus) = affinity {
+                                if let Err(e) = set_thread_affinity(0, &cpus) {
+                                    error!("Failed to pin iothread {}: {:?}", id, e);
+                                }
+                            }
+                            if let Some(nodes) = nodes {
+                                if let Err(e) = bind_thread_host_nodes(&nodes) {
+                                    error!("Failed to bind iothread {} to host nodes: {:?}", id, e);
+                                }
+                            }
                             let iothread_info = IothreadInfo {
                                 shrink: 0,
-                                pid: process::id(),
+                                pid: gettid() as u32,
                                 grow: 0,
                                 max: 0,
                                 id: id.to_string(),
                             };
                             IOTHREADS.lock().unwrap().push(iothread_info);
-                            while let Ok(ret) = ctx.iothread_run() {
-                                if !ret {
+                            loop {
+                                if let Err(e) = apply_thread_filter(ThreadClass::IoThread) {
+                                    error!(
+                                        "Failed to apply seccomp filter of iothread {}: {:?}",
+                                        id, e
+                                    );
+                                    break;
+                                }
+                                if !matches!(ctx.iothread_run(), Ok(true)) {
                                     break;
                                 }
                             }
@@ -137,6 +191,41 @@ impl EventLoop {
         }
     }
 
+    /// Get the health metrics of main loop and iothreads. The main loop is the first,
+    /// and the iothreads are sorted by id.
+    ///
+    /// # Arguments
+    ///
+    /// * `reset` - Clear the metrics after they are got.
+    pub fn query_metrics(reset: bool) -> Vec<(String, EventLoopMetrics)> {
+        let mut metrics = Vec::new();
+        if let Some(ctx) = Self::get_ctx(None) {
+            metrics.push(("main".to_string(), ctx.metrics(reset)));
+        }
+        let mut ids: Vec<String> = IOTHREADS
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|iothread| iothread.id.clone())
+            .collect();
+        ids.sort();
+        for id in ids {
+            if let Some(ctx_metrics) = Self::get_ctx(Some(&id)).map(|ctx| ctx.metrics(reset)) {
+                metrics.push((id, ctx_metrics));
+            }
+        }
+        metrics
+    }
+
+    /// Wake up all the iothreads.
+    pub fn kick_iothreads() {
+        for iothread in IOTHREADS.lock().unwrap().iter() {
+            if let Some(ctx) = Self::get_ctx(Some(&iothread.id)) {
+                ctx.kick();
+            }
+        }
+    }
+
     /// Start to run main loop
     ///
     /// # Notes
@@ -0,0 +1,164 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Watchdog which detects the main loop and iothreads making no progress.
+//!
+//! A loop is busy from the time it returns from waiting for events until it goes
+//! back to wait. The watchdog thread checks the loops periodically, and reports the
+//! loop which has been busy longer than the timeout, with the stack of the stuck
+//! thread got from procfs.
+
+use std::collections::HashMap;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+
+use crate::config::HangWatchdogConfig;
+use crate::event;
+use crate::event_loop::EventLoop;
+use crate::machine::IOTHREADS;
+use crate::qmp::qmp_channel::QmpChannel;
+use crate::qmp::qmp_schema::ThreadHang;
+use crate::signal_handler::get_signal;
+
+/// Max interval between two checks of the loops.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Start the watchdog thread which checks the main loop and iothreads.
+///
+/// # Arguments
+///
+/// * `config` - Config of hang watchdog.
+pub fn start_hang_watchdog(config: &HangWatchdogConfig) -> Result<()> {
+    let timeout = Duration::from_secs(config.timeout);
+    let interval = std::cmp::min(timeout / 4, MAX_CHECK_INTERVAL);
+    let emit_event = config.event;
+    thread::Builder::new()
+        .name("hang-watchdog".to_string())
+        .spawn(move || {
+            let mut watchdog = HangWatchdog::new(timeout, emit_event);
+            while get_signal() == 0 {
+                thread::sleep(interval);
+                watchdog.check();
+            }
+        })
+        .with_context(|| "Failed to spawn hang watchdog thread")?;
+    info!("Hang watchdog started, timeout {:?}", timeout);
+    Ok(())
+}
+
+struct HangWatchdog {
+    timeout: Duration,
+    emit_event: bool,
+    /// Busy time of the stuck loops which have been reported, keyed by the id of loop.
+    reported: HashMap<String, Instant>,
+}
+
+impl HangWatchdog {
+    fn new(timeout: Duration, emit_event: bool) -> Self {
+        HangWatchdog {
+            timeout,
+            emit_event,
+            reported: HashMap::new(),
+        }
+    }
+
+    fn check(&mut self) {
+        let mut ids = vec![None];
+        ids.extend(
+            IOTHREADS
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|iothread| Some(iothread.id.clone())),
+        );
+
+        for id in ids {
+            let state =
+                EventLoop::get_ctx(id.as_ref()).map(|ctx| (ctx.busy_since(), ctx.thread_id()));
+            let name = id.unwrap_or_else(|| "main".to_string());
+            match state {
+                Some((Some(since), Some(tid))) => self.check_loop(name, since, tid),
+                _ => self.recover(&name),
+            }
+        }
+    }
+
+    fn check_loop(&mut self, name: String, since: Instant, tid: u64) {
+        if self.reported.get(&name) == Some(&since) {
+            return;
+        }
+        let duration = since.elapsed();
+        if duration < self.timeout {
+            self.recover(&name);
+            return;
+        }
+
+        error!(
+            "Event loop {} (tid {}) has made no progress for {:?}, stack:\n{}",
+            name,
+            tid,
+            duration,
+            thread_stack(tid)
+        );
+        if self.emit_event {
+            let hang = ThreadHang {
+                id: name.clone(),
+                tid,
+                duration_ms: duration.as_millis() as u64,
+            };
+            event!(ThreadHang; hang);
+        }
+        self.reported.insert(name, since);
+    }
+
+    fn recover(&mut self, name: &str) {
+        if let Some(since) = self.reported.remove(name) {
+            info!(
+                "Event loop {} recovered after being stuck for {:?}",
+                name,
+                since.elapsed()
+            );
+        }
+    }
+}
+
+/// Get the stack of the thread from procfs. The kernel stack needs CAP_SYS_ADMIN, and
+/// the current syscall shows the user stack pointer and instruction pointer.
+fn thread_stack(tid: u64) -> String {
+    let mut stack = String::new();
+    for item in ["syscall", "wchan", "stack"] {
+        let path = format!("/proc/self/task/{}/{}", tid, item);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content.trim_end().to_string(),
+            Err(e) => format!("unavailable: {}", e),
+        };
+        stack.push_str(&format!("[{}]\n{}\n", item, content));
+    }
+    stack
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_stack() {
+        let stack = thread_stack(util::unix::gettid());
+        assert!(stack.starts_with("[syscall]\n"));
+        assert!(stack.contains("[wchan]\n"));
+        assert!(stack.contains("[stack]\n"));
+    }
+}
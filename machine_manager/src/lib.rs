@@ -25,6 +25,7 @@ pub mod cmdline;
 pub mod config;
 pub mod error;
 pub mod event_loop;
+pub mod hang_watchdog;
 pub mod machine;
 pub mod qmp;
 pub mod signal_handler;
@@ -19,12 +19,15 @@ use strum::VariantNames;
 use crate::config::ShutdownAction;
 use crate::qmp::qmp_response::{Response, Version};
 use crate::qmp::qmp_schema::{
-    BlockDevAddArgument, BlockdevSnapshotInternalArgument, CameraDevAddArgument,
-    CharDevAddArgument, ChardevInfo, Cmd, CmdLine, CmdParameter, DeviceAddArgument, DeviceProps,
-    Events, GicCap, HumanMonitorCmdArgument, IothreadInfo, KvmInfo, MachineInfo,
-    MigrateCapabilities, NetDevAddArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent, Target,
-    TypeLists, UpdateRegionArgument,
+    Any, BlockDevAddArgument, BlockdevSnapshotInternalArgument, CameraDevAddArgument,
+    CharDevAddArgument, ChardevInfo, Cmd, CmdLine, CmdParameter, DataFormat, DeviceAddArgument,
+    DeviceProps, Events, GicCap, HumanMonitorCmdArgument, InputEvent, IothreadInfo, KeyValue,
+    KvmInfo, LatencyBucket, LatencyHistogramInfo, MachineInfo, MigrateCapabilities,
+    MigrateSetParametersArgument, NetDevAddArgument, PropList, QmpCommand, QmpErrorClass, QmpEvent,
+    QueryLatencyHistogramsArgument, QueryVcpuExitStatsArgument, QueryVirtqueueStatsArgument,
+    Target, TypeLists, UpdateRegionArgument,
 };
+use util::latency_histogram::latency_histograms;
 
 #[derive(Clone)]
 pub struct PathInfo {
@@ -205,6 +208,67 @@ pub trait DeviceInterface {
     /// Set balloon's size.
     fn balloon(&self, size: u64) -> Response;
 
+    /// Set the status of the emulated AC adapter and battery.
+    fn set_power_supply(&self, _ac_online: Option<bool>, _battery_level: Option<u8>) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-power-supply not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Query the status of AC adapter and battery.
+    fn query_power_supply(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-power-supply not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Dump guest memory to an ELF core file.
+    fn dump_guest_memory(&self, _paging: bool, _protocol: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("dump-guest-memory not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Resize an output of the display device.
+    fn display_resize(&self, _output: u32, _width: u32, _height: u32) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("display-resize not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Read data from a ringbuf chardev.
+    fn ringbuf_read(&self, _device: String, _size: u64, _format: DataFormat) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("ringbuf-read not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Write data to the frontend of a ringbuf chardev.
+    fn ringbuf_write(&self, _device: String, _data: String, _format: DataFormat) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("ringbuf-write not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Send a command to the guest agent, the result is reported by event.
+    fn guest_agent_command(
+        &self,
+        _command: String,
+        _arguments: Option<Any>,
+        _timeout: Option<u64>,
+    ) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("guest-agent-command not supported for VM".to_string()),
+            None,
+        )
+    }
+
     /// Query the version of StratoVirt.
     fn query_version(&self) -> Response {
         let version = Version::new(1, 0, 5);
@@ -398,6 +462,22 @@ pub trait DeviceInterface {
         Response::create_empty_response()
     }
 
+    /// Query information of SEV-SNP guest.
+    fn query_sev(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("SEV is not enabled".to_string()),
+            None,
+        )
+    }
+
+    /// Get the attestation report of SEV-SNP guest from the firmware.
+    fn query_sev_attestation_report(&self, _mnonce: String) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("SEV is not enabled".to_string()),
+            None,
+        )
+    }
+
     fn query_chardev(&self) -> Response {
         let mut vec_chardev_info: Vec<ChardevInfo> = Vec::new();
         let locked_paths = PTY_PATH.lock().unwrap().clone();
@@ -459,6 +539,54 @@ pub trait DeviceInterface {
         Response::create_response(serde_json::to_value(&vec_iothreads).unwrap(), None)
     }
 
+    /// Query latency histograms of block and net devices.
+    fn query_latency_histograms(&self, args: QueryLatencyHistogramsArgument) -> Response {
+        let histograms = latency_histograms(args.device.as_deref());
+        if histograms.is_empty() {
+            if let Some(device) = args.device {
+                return Response::create_error_response(
+                    QmpErrorClass::DeviceNotFound(format!(
+                        "No latency histogram for device {}",
+                        device
+                    )),
+                    None,
+                );
+            }
+        }
+
+        let mut infos: Vec<LatencyHistogramInfo> = Vec::new();
+        for (device, operation, histogram) in histograms {
+            let snapshot = histogram.snapshot();
+            if args.reset {
+                histogram.reset();
+            }
+            let p = &snapshot.percentiles;
+            infos.push(LatencyHistogramInfo {
+                device,
+                operation,
+                count: snapshot.count,
+                min: snapshot.min,
+                max: snapshot.max,
+                mean: snapshot.mean,
+                p50: p[0],
+                p90: p[1],
+                p99: p[2],
+                p99_9: p[3],
+                p99_99: p[4],
+                buckets: snapshot
+                    .buckets
+                    .iter()
+                    .map(|(start, end, count)| LatencyBucket {
+                        start: *start,
+                        end: *end,
+                        count: *count,
+                    })
+                    .collect(),
+            });
+        }
+        Response::create_response(serde_json::to_value(infos).unwrap(), None)
+    }
+
     fn update_region(&mut self, args: UpdateRegionArgument) -> Response;
 
     // Send event to input device for testing only.
@@ -466,6 +594,83 @@ pub trait DeviceInterface {
         Response::create_empty_response()
     }
 
+    /// Send input events to the keyboard and pointer.
+    fn input_send_event(&self, _events: Vec<InputEvent>) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("input-send-event not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Press and release the keys.
+    fn send_key(&self, _keys: Vec<KeyValue>, _hold_time: Option<u64>) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("send-key not supported for VM".to_string()),
+            None,
+        )
+    }
+
+    /// Pin the thread of a vCPU to host CPUs.
+    fn set_vcpu_affinity(&self, _vcpu: u8, _cpus: Vec<u64>) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("set-vcpu-affinity is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Limit the CPU time consumed by vCPUs to (100 - `percentage`)%.
+    fn vcpu_throttle(&self, _percentage: u8) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("vcpu-throttle is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Query the exit statistics of vCPUs.
+    fn query_vcpu_exit_stats(&self, _args: QueryVcpuExitStatsArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-vcpu-exit-stats is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Query the statistics of virtqueues.
+    fn query_virtqueue_stats(&self, _args: QueryVirtqueueStatsArgument) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-virtqueue-stats is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Query the breakdown of resident memory of VMM.
+    fn query_vmm_memory(&self) -> Response {
+        Response::create_error_response(
+            QmpErrorClass::GenericError("query-vmm-memory is not supported yet".to_string()),
+            None,
+        )
+    }
+
+    /// Pin an iothread to host CPUs.
+    fn set_iothread_affinity(&self, id: String, cpus: Vec<u64>) -> Response {
+        let locked_threads = IOTHREADS.lock().unwrap();
+        let thread = match locked_threads.iter().find(|t| t.id == id) {
+            Some(t) => t,
+            None => {
+                return Response::create_error_response(
+                    QmpErrorClass::GenericError(format!("Iothread {} not found", id)),
+                    None,
+                );
+            }
+        };
+        match util::unix::set_thread_affinity(u64::from(thread.pid), &cpus) {
+            Ok(()) => Response::create_empty_response(),
+            Err(e) => Response::create_error_response(
+                QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            ),
+        }
+    }
+
     fn human_monitor_command(&self, _args: HumanMonitorCmdArgument) -> Response {
         Response::create_error_response(
             QmpErrorClass::GenericError("human-monitor-command is not supported yet".to_string()),
@@ -504,6 +709,26 @@ pub trait MigrateInterface {
     fn cancel_migrate(&self) -> Response {
         Response::create_empty_response()
     }
+
+    /// Set the parameters of migration.
+    fn migrate_set_parameters(&self, _args: MigrateSetParametersArgument) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Returns the parameters of migration.
+    fn query_migrate_parameters(&self) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Sets the capabilities of migration.
+    fn migrate_set_capabilities(&self, _capabilities: Vec<MigrateCapabilities>) -> Response {
+        Response::create_empty_response()
+    }
+
+    /// Leave COLO mode after the other side is lost.
+    fn colo_lost_heartbeat(&self) -> Response {
+        Response::create_empty_response()
+    }
 }
 
 /// Machine interface which is exposed to inner hypervisor.
@@ -26,6 +26,7 @@
 //! `qmp-schema.json`. It's can be compatible by Qemu's zoology. Those
 //! transformed structures can be found in `machine_manager/src/qmp/qmp_schema.rs`
 
+pub mod qmp_audit;
 pub mod qmp_channel;
 pub mod qmp_response;
 #[allow(non_upper_case_globals)]
@@ -33,3 +34,4 @@ pub mod qmp_response;
 #[allow(non_snake_case)]
 pub mod qmp_schema;
 pub mod qmp_socket;
+pub mod qmp_tls;
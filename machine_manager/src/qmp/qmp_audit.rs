@@ -0,0 +1,274 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::RawFd;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use serde::Serialize;
+
+use super::qmp_channel::{create_timestamp, TimeStamp};
+use super::qmp_response::Response;
+use super::qmp_schema::QmpCommand;
+use crate::config::QmpAuditConfig;
+use util::seccomp::BpfRule;
+use util::seclabel::label_path;
+
+/// Global qmp audit log, which is `None` if `-qmp-audit` isn't set.
+static QMP_AUDIT: Mutex<Option<QmpAudit>> = Mutex::new(None);
+
+struct QmpAudit {
+    file: File,
+    enabled: bool,
+}
+
+/// Peer which sends the qmp command.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct QmpPeer {
+    /// Transport of the qmp connection: `unix`, `tls` or `chardev`.
+    pub transport: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    /// Address of the tcp client, or id of the chardev.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Common name of the client certificate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cn: Option<String>,
+}
+
+impl QmpPeer {
+    /// Get credentials of the process connected to the unix socket. Only the transport
+    /// is recorded if credentials can't be got.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - Fd of the connected unix stream.
+    pub fn unix(fd: RawFd) -> Self {
+        let mut peer = QmpPeer {
+            transport: "unix".to_string(),
+            ..Default::default()
+        };
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: cred and len are valid and the size of cred is given in len.
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret == 0 {
+            peer.pid = Some(cred.pid);
+            peer.uid = Some(cred.uid);
+            peer.gid = Some(cred.gid);
+        }
+        peer
+    }
+
+    /// Peer of qmp over TLS.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - Address of the tcp client.
+    /// * `cn` - Common name of the client certificate.
+    pub fn tls(address: String, cn: Option<String>) -> Self {
+        QmpPeer {
+            transport: "tls".to_string(),
+            address: Some(address),
+            cn,
+            ..Default::default()
+        }
+    }
+
+    /// Peer of qmp monitor on chardev.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the chardev.
+    pub fn chardev(id: &str) -> Self {
+        QmpPeer {
+            transport: "chardev".to_string(),
+            address: Some(id.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// One line of the audit log.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: TimeStamp,
+    peer: &'a QmpPeer,
+    command: &'a QmpCommand,
+    /// `success` or `error`.
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// Open the audit log file, which should be done before chroot and seccomp.
+///
+/// # Arguments
+///
+/// * `cfg` - Config of the audit log.
+pub fn set_qmp_audit(cfg: &QmpAuditConfig) -> Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(&cfg.path)
+        .with_context(|| format!("Failed to open qmp audit log {}", cfg.path))?;
+    label_path(&cfg.path)?;
+    *QMP_AUDIT.lock().unwrap() = Some(QmpAudit {
+        file,
+        enabled: cfg.enable,
+    });
+    Ok(())
+}
+
+/// Enable or disable the audit log at runtime.
+pub fn set_qmp_audit_state(enable: bool) -> Result<()> {
+    match QMP_AUDIT.lock().unwrap().as_mut() {
+        Some(audit) => audit.enabled = enable,
+        None => bail!("Qmp audit log is not configured, use -qmp-audit to set it"),
+    }
+    Ok(())
+}
+
+/// Log the executed qmp command and its result. The command which changes the state of
+/// audit log is always logged, so that it's known when commands are not logged.
+///
+/// # Arguments
+///
+/// * `peer` - Get the peer which sends the command, it's called only if the command is logged.
+/// * `command` - The executed command.
+/// * `response` - Response of the command.
+pub fn audit_qmp_command(peer: &dyn Fn() -> QmpPeer, command: &QmpCommand, response: &Response) {
+    let mut locked_audit = QMP_AUDIT.lock().unwrap();
+    let audit = match locked_audit.as_mut() {
+        Some(audit) => audit,
+        None => return,
+    };
+    if !audit.enabled && !matches!(command, QmpCommand::qmp_audit_set_state { .. }) {
+        return;
+    }
+
+    let error = response.error_desc();
+    let record = AuditRecord {
+        timestamp: create_timestamp(),
+        peer: &peer(),
+        command,
+        result: if error.is_some() { "error" } else { "success" },
+        error,
+    };
+    let mut line = match serde_json::to_string(&record) {
+        Ok(line) => line,
+        Err(e) => {
+            error!("Failed to serialize qmp audit record: {:?}", e);
+            return;
+        }
+    };
+    line.push('\n');
+    // Write the record at once, so that it won't be interleaved with others.
+    if let Err(e) = audit.file.write_all(line.as_bytes()) {
+        error!("Failed to write qmp audit log: {:?}", e);
+    }
+}
+
+/// Create syscall bpf rules for getting credentials of qmp clients.
+pub fn qmp_audit_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
+    if QMP_AUDIT.lock().unwrap().is_some() {
+        syscall_allow_list.push(BpfRule::new(libc::SYS_getsockopt));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::qmp::qmp_schema;
+
+    #[test]
+    fn test_qmp_audit() {
+        let (stream, _) = UnixStream::pair().unwrap();
+        let peer = QmpPeer::unix(std::os::unix::io::AsRawFd::as_raw_fd(&stream));
+        // SAFETY: getpid, getuid and getgid are always successful.
+        let (pid, uid, gid) = unsafe { (libc::getpid(), libc::getuid(), libc::getgid()) };
+        assert_eq!(peer.pid, Some(pid));
+        assert_eq!(peer.uid, Some(uid));
+        assert_eq!(peer.gid, Some(gid));
+        assert_eq!(QmpPeer::unix(-1).pid, None);
+
+        let command: QmpCommand =
+            serde_json::from_str(r#"{"execute":"balloon","arguments":{"value":1024}}"#).unwrap();
+        let set_state: QmpCommand = serde_json::from_str(
+            r#"{"execute":"qmp-audit-set-state","arguments":{"enable":false}}"#,
+        )
+        .unwrap();
+        let ok = Response::create_empty_response();
+        let err = Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError("no balloon".to_string()),
+            None,
+        );
+        let peer = QmpPeer::tls("127.0.0.1:5555".to_string(), Some("admin".to_string()));
+        assert!(set_qmp_audit_state(true).is_err());
+        audit_qmp_command(&|| peer.clone(), &command, &ok);
+
+        let path = format!("/tmp/test_qmp_audit_{}.log", std::process::id());
+        let _ = std::fs::remove_file(&path);
+        set_qmp_audit(&QmpAuditConfig {
+            path: path.clone(),
+            enable: true,
+        })
+        .unwrap();
+        audit_qmp_command(&|| peer.clone(), &command, &ok);
+        audit_qmp_command(&|| peer.clone(), &command, &err);
+        set_qmp_audit_state(false).unwrap();
+        audit_qmp_command(&|| peer.clone(), &set_state, &ok);
+        audit_qmp_command(&|| peer.clone(), &command, &ok);
+        *QMP_AUDIT.lock().unwrap() = None;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["result"], "success");
+        assert_eq!(records[0]["peer"]["transport"], "tls");
+        assert_eq!(records[0]["peer"]["cn"], "admin");
+        assert_eq!(records[0]["command"]["execute"], "balloon");
+        assert_eq!(records[0]["command"]["arguments"]["value"], 1024);
+        assert!(records[0].get("error").is_none());
+        assert_eq!(records[1]["result"], "error");
+        assert_eq!(records[1]["error"], "no balloon");
+        assert_eq!(records[2]["command"]["execute"], "qmp-audit-set-state");
+    }
+}
@@ -20,7 +20,6 @@ use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 
 use super::qmp_schema::{self as schema};
-use crate::socket::SocketRWHandler;
 use util::time::NANOSECONDS_PER_SECOND;
 
 static mut QMP_CHANNEL: Option<Arc<QmpChannel>> = None;
@@ -86,7 +85,7 @@ pub fn create_timestamp() -> TimeStamp {
 /// which was sended by client.
 pub struct QmpChannel {
     /// The `writer` to send `QmpEvent`.
-    event_writer: RwLock<Option<SocketRWHandler>>,
+    event_writer: RwLock<Option<Box<dyn Write + Send + Sync>>>,
     /// Restore file descriptor received from client.
     fds: Arc<RwLock<BTreeMap<String, RawFd>>>,
 }
@@ -104,21 +103,21 @@ impl QmpChannel {
         }
     }
 
-    /// Bind a `SocketRWHandler` to `QMP_CHANNEL`.
+    /// Bind a writer to `QMP_CHANNEL`.
     ///
     /// # Arguments
     ///
-    /// * `writer` - The `SocketRWHandler` used to communicate with client.
-    pub(crate) fn bind_writer(writer: SocketRWHandler) {
+    /// * `writer` - The writer used to communicate with client, such as `SocketRWHandler`.
+    pub(crate) fn bind_writer(writer: Box<dyn Write + Send + Sync>) {
         *Self::inner().event_writer.write().unwrap() = Some(writer);
     }
 
-    /// Unbind `SocketRWHandler` from `QMP_CHANNEL`.
+    /// Unbind writer from `QMP_CHANNEL`.
     pub(crate) fn unbind() {
         *Self::inner().event_writer.write().unwrap() = None;
     }
 
-    /// Check whether a `SocketRWHandler` bind with `QMP_CHANNEL` or not.
+    /// Check whether a writer bind with `QMP_CHANNEL` or not.
     pub fn is_connected() -> bool {
         Self::inner().event_writer.read().unwrap().is_some()
     }
@@ -161,6 +161,11 @@ impl Response {
     pub(crate) fn change_id(&mut self, id: Option<String>) {
         self.id = id;
     }
+
+    /// Get the error description if the command failed.
+    pub(crate) fn error_desc(&self) -> Option<&str> {
+        self.error.as_ref().map(|e| e.desc.as_str())
+    }
 }
 
 impl From<bool> for Response {
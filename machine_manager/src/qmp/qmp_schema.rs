@@ -12,6 +12,8 @@
 
 pub use serde_json::Value as Any;
 
+use std::collections::BTreeMap;
+
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use strum_macros::{EnumIter, EnumString, EnumVariantNames};
@@ -198,6 +200,49 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "set-power-supply")]
+    set_power_supply {
+        arguments: set_power_supply,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-power-supply")]
+    query_power_supply {
+        #[serde(default)]
+        arguments: query_power_supply,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "dump-guest-memory")]
+    dump_guest_memory {
+        arguments: dump_guest_memory,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "display-resize")]
+    display_resize {
+        arguments: display_resize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "ringbuf-read")]
+    ringbuf_read {
+        arguments: ringbuf_read,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "ringbuf-write")]
+    ringbuf_write {
+        arguments: ringbuf_write,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "guest-agent-command")]
+    guest_agent_command {
+        arguments: guest_agent_command,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-vnc")]
     #[strum(serialize = "query-vnc")]
     query_vnc {
@@ -226,6 +271,36 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "migrate-set-parameters")]
+    #[strum(serialize = "migrate-set-parameters")]
+    migrate_set_parameters {
+        arguments: migrate_set_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "migrate-set-capabilities")]
+    #[strum(serialize = "migrate-set-capabilities")]
+    migrate_set_capabilities {
+        arguments: migrate_set_capabilities,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "x-colo-lost-heartbeat")]
+    #[strum(serialize = "x-colo-lost-heartbeat")]
+    x_colo_lost_heartbeat {
+        #[serde(default)]
+        arguments: x_colo_lost_heartbeat,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-migrate-parameters")]
+    #[strum(serialize = "query-migrate-parameters")]
+    query_migrate_parameters {
+        #[serde(default)]
+        arguments: query_migrate_parameters,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-version")]
     query_version {
         #[serde(default)]
@@ -333,6 +408,19 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "query-sev")]
+    query_sev {
+        #[serde(default)]
+        arguments: query_sev,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-sev-attestation-report")]
+    query_sev_attestation_report {
+        arguments: query_sev_attestation_report,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "query-chardev")]
     #[strum(serialize = "query-chardev")]
     query_chardev {
@@ -405,6 +493,59 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "query-vcpu-exit-stats")]
+    #[strum(serialize = "query-vcpu-exit-stats")]
+    query_vcpu_exit_stats {
+        #[serde(default)]
+        arguments: query_vcpu_exit_stats,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-virtqueue-stats")]
+    #[strum(serialize = "query-virtqueue-stats")]
+    query_virtqueue_stats {
+        #[serde(default)]
+        arguments: query_virtqueue_stats,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-vmm-memory")]
+    #[strum(serialize = "query-vmm-memory")]
+    query_vmm_memory {
+        #[serde(default)]
+        arguments: query_vmm_memory,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-latency-histograms")]
+    #[strum(serialize = "query-latency-histograms")]
+    query_latency_histograms {
+        #[serde(default)]
+        arguments: query_latency_histograms,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-vcpu-affinity")]
+    #[strum(serialize = "set-vcpu-affinity")]
+    set_vcpu_affinity {
+        arguments: set_vcpu_affinity,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-iothread-affinity")]
+    #[strum(serialize = "set-iothread-affinity")]
+    set_iothread_affinity {
+        arguments: set_iothread_affinity,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "vcpu-throttle")]
+    #[strum(serialize = "vcpu-throttle")]
+    vcpu_throttle {
+        arguments: vcpu_throttle,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "update_region")]
     #[strum(serialize = "update_region")]
     update_region {
@@ -419,6 +560,18 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "input-send-event")]
+    input_send_event {
+        arguments: input_send_event,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "send-key")]
+    send_key {
+        arguments: send_key,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
     #[serde(rename = "human-monitor-command")]
     human_monitor_command {
         arguments: human_monitor_command,
@@ -437,6 +590,57 @@ pub enum QmpCommand {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         id: Option<String>,
     },
+    #[serde(rename = "qmp-audit-set-state")]
+    qmp_audit_set_state {
+        arguments: qmp_audit_set_state,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "trace-event-get-state")]
+    trace_event_get_state {
+        arguments: trace_event_get_state,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "trace-event-set-state")]
+    trace_event_set_state {
+        arguments: trace_event_set_state,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-event-loops")]
+    query_event_loops {
+        #[serde(default)]
+        arguments: query_event_loops,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-event-loop-budget")]
+    set_event_loop_budget {
+        arguments: set_event_loop_budget,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "set-log")]
+    set_log {
+        arguments: set_log,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-log")]
+    query_log {
+        #[serde(default)]
+        arguments: query_log,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+    #[serde(rename = "query-boottime")]
+    query_boottime {
+        #[serde(default)]
+        arguments: query_boottime,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
 }
 
 /// Command trait for Deserialize and find back Response.
@@ -639,6 +843,12 @@ pub struct device_add {
     pub sysfsdev: Option<String>,
     #[serde(rename = "queue-size")]
     pub queue_size: Option<u16>,
+    #[serde(rename = "notify-batch")]
+    pub notify_batch: Option<u16>,
+    #[serde(rename = "poll-us")]
+    pub poll_us: Option<u64>,
+    #[serde(rename = "max-inflight")]
+    pub max_inflight: Option<u32>,
     pub port: Option<String>,
     pub backend: Option<String>,
     pub path: Option<String>,
@@ -650,6 +860,11 @@ pub struct device_add {
     pub productid: Option<String>,
     pub isobufs: Option<String>,
     pub isobsize: Option<String>,
+    pub memdev: Option<String>,
+    pub node: Option<u32>,
+    #[serde(rename = "cpu-id")]
+    pub cpu_id: Option<u8>,
+    pub nr: Option<u32>,
 }
 
 pub type DeviceAddArgument = device_add;
@@ -826,6 +1041,10 @@ pub struct netdev_add {
     pub script: Option<String>,
     pub queues: Option<u16>,
     pub chardev: Option<String>,
+    #[serde(rename = "io-uring")]
+    pub io_uring: Option<bool>,
+    #[serde(rename = "poll-us")]
+    pub poll_us: Option<u32>,
 }
 
 pub type NetDevAddArgument = netdev_add;
@@ -890,8 +1109,20 @@ pub struct AddrOptions {
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BackendDataOptions {
-    pub addr: AddrOptions,
-    pub server: bool,
+    /// Address of socket backend.
+    pub addr: Option<AddrOptions>,
+    /// Whether socket backend runs as a server.
+    pub server: Option<bool>,
+    /// Whether socket server waits for connection.
+    pub wait: Option<bool>,
+    /// Path of file backend.
+    pub out: Option<String>,
+    /// Size of ringbuf backend.
+    pub size: Option<u64>,
+    /// Path of file which logs the output of chardev.
+    pub logfile: Option<String>,
+    /// Whether to append to the log file instead of truncating it.
+    pub logappend: Option<bool>,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -920,6 +1151,9 @@ pub struct BackendOptions {
 ///            "addr": { "type": "unix", "data": { "path": "/path/to/socket" } },
 ///            "server": false }}}}
 /// <- { "return": {} }
+/// -> { "execute": "chardev-add",
+///      "arguments": { "id": "chardev_pty", "backend": { "type": "pty", "data": {} }}}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -1384,6 +1618,132 @@ pub struct MigrationInfo {
     pub status: Option<String>,
 }
 
+/// migrate-set-parameters
+///
+/// Set the parameters of migration.
+///
+/// # Arguments
+///
+/// * `max_bandwidth` - max bandwidth of migration in bytes per second, 0 means unlimited.
+/// * `downtime_limit` - max tolerated downtime of migration in milliseconds.
+/// * `xbzrle_cache_size` - size of xbzrle cache in bytes, 0 means xbzrle is disabled.
+/// * `x_checkpoint_delay` - max interval between two COLO checkpoints in milliseconds.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate-set-parameters",
+///      "arguments": { "max-bandwidth": 33554432, "downtime-limit": 300 } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_parameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: Option<u64>,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: Option<u64>,
+    #[serde(rename = "xbzrle-cache-size")]
+    pub xbzrle_cache_size: Option<u64>,
+    #[serde(rename = "x-checkpoint-delay")]
+    pub x_checkpoint_delay: Option<u64>,
+}
+pub type MigrateSetParametersArgument = migrate_set_parameters;
+
+impl Command for migrate_set_parameters {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// migrate-set-capabilities
+///
+/// Enable or disable the capabilities of migration.
+///
+/// # Arguments
+///
+/// * `capabilities` - list of capabilities and their states.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "migrate-set-capabilities",
+///      "arguments": { "capabilities": [ { "capability": "background-snapshot", "state": true } ] } }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct migrate_set_capabilities {
+    pub capabilities: Vec<MigrateCapabilities>,
+}
+
+impl Command for migrate_set_capabilities {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// x-colo-lost-heartbeat
+///
+/// Tell VM in COLO mode that the other side is lost, VM leaves COLO mode and
+/// runs alone.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "x-colo-lost-heartbeat" }
+/// <- { "return": {} }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct x_colo_lost_heartbeat {}
+
+impl Command for x_colo_lost_heartbeat {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-migrate-parameters
+///
+/// Returns the parameters of migration.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-migrate-parameters" }
+/// <- { "return": { "max-bandwidth": 33554432, "downtime-limit": 300, "xbzrle-cache-size": 0,
+///                  "x-checkpoint-delay": 20000 } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_migrate_parameters {}
+
+impl Command for query_migrate_parameters {
+    type Res = MigrationParameters;
+
+    fn back(self) -> MigrationParameters {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationParameters {
+    #[serde(rename = "max-bandwidth")]
+    pub max_bandwidth: u64,
+    #[serde(rename = "downtime-limit")]
+    pub downtime_limit: u64,
+    #[serde(rename = "xbzrle-cache-size")]
+    pub xbzrle_cache_size: u64,
+    #[serde(rename = "x-checkpoint-delay")]
+    pub x_checkpoint_delay: u64,
+}
+
 /// getfd
 ///
 /// Receive a file descriptor via SCM rights and assign it a name
@@ -1492,82 +1852,515 @@ pub struct DeviceDeleted {
     pub path: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
-#[serde(tag = "event")]
-pub enum QmpEvent {
-    #[serde(rename = "SHUTDOWN")]
-    Shutdown {
-        data: Shutdown,
-        timestamp: TimeStamp,
-    },
-    #[serde(rename = "RESET")]
-    Reset { data: Reset, timestamp: TimeStamp },
-    #[serde(rename = "STOP")]
-    Stop {
-        #[serde(default)]
-        data: Stop,
-        timestamp: TimeStamp,
-    },
-    #[serde(rename = "RESUME")]
-    Resume {
-        #[serde(default)]
-        data: Resume,
-        timestamp: TimeStamp,
-    },
-    #[serde(rename = "POWERDOWN")]
-    Powerdown {
-        #[serde(default)]
-        data: Powerdown,
-        timestamp: TimeStamp,
-    },
-    #[serde(rename = "DEVICE_DELETED")]
-    DeviceDeleted {
-        data: DeviceDeleted,
-        timestamp: TimeStamp,
-    },
-    #[serde(rename = "BALLOON_CHANGED")]
-    BalloonChanged {
-        data: BalloonInfo,
-        timestamp: TimeStamp,
-    },
-}
-
-/// query-balloon:
-///
-/// Query the actual size of memory of VM.
-///
-/// # Returns
+/// GuestPanicked
 ///
-/// `BalloonInfo` includs the actual size of memory
+/// Emitted when guest kernel panics, which is reported by pvpanic device.
 ///
-/// # Example
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-balloon" }
-/// <- {"return":{"actual":8589934592}}
+/// <- { "event": "GUEST_PANICKED",
+///      "data": { "action": "pause" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
 /// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_balloon {}
-impl Command for query_balloon {
-    type Res = BalloonInfo;
-    fn back(self) -> BalloonInfo {
-        Default::default()
-    }
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GuestPanicked {
+    /// Action taken for the panic, one of "run", "pause", "poweroff" and "coredump".
+    pub action: String,
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct BalloonInfo {
-    pub actual: u64,
+/// GuestCrashloaded
+///
+/// Emitted when guest kernel has loaded the crash kernel after panic, which is
+/// reported by pvpanic device.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "GUEST_CRASHLOADED",
+///      "data": { "action": "run" },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GuestCrashloaded {
+    /// Action taken for the crash, which is always "run".
+    pub action: String,
 }
 
-/// query-vnc:
-/// Information about current VNC server.
+/// GuestAgentResponse
+///
+/// Emitted when the command sent by `guest-agent-command` is completed. Either `return`
+/// of the guest agent or `error` is reported.
 ///
 /// # Examples
 ///
-/// For pc machine type started with -vnc ip:port(for example: 0.0.0.0:0):
 /// ```text
-/// -> { "execute": "query-vnc" }
+/// <- { "event": "GUEST_AGENT_RESPONSE",
+///      "data": { "command": "guest-exec", "return": { "pid": 1234 } },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct GuestAgentResponse {
+    /// Name of the guest agent command.
+    pub command: String,
+    /// Return of the guest agent command.
+    #[serde(rename = "return", default, skip_serializing_if = "Option::is_none")]
+    pub ret: Option<Any>,
+    /// Error description if the command failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// EventLoopStall
+///
+/// Emitted when a callback of event loop takes longer than the budget set by
+/// `set-event-loop-budget`. It's emitted at most once per second for each loop.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "EVENT_LOOP_STALL",
+///      "data": { "id": "main", "fd": 23, "duration-us": 153021, "budget-us": 100000 },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EventLoopStall {
+    /// `main` for main loop, or id of iothread.
+    pub id: String,
+    /// Fd of the callback, not set if the callback is timer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fd: Option<i32>,
+    #[serde(rename = "duration-us")]
+    pub duration_us: u64,
+    #[serde(rename = "budget-us")]
+    pub budget_us: u64,
+}
+
+/// ThreadHang
+///
+/// Emitted when the main loop or an iothread has been stuck longer than the timeout
+/// of `-hang-watchdog`. It's emitted once for each stuck.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "THREAD_HANG",
+///      "data": { "id": "iothread1", "tid": 1726, "duration-ms": 10012 },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ThreadHang {
+    /// `main` for main loop, or id of iothread.
+    pub id: String,
+    /// Thread id of the stuck loop.
+    pub tid: u64,
+    #[serde(rename = "duration-ms")]
+    pub duration_ms: u64,
+}
+
+/// DirtyRateHigh
+///
+/// Emitted every interval of `-mem-pressure-monitor` while the dirty rate of guest memory
+/// is above the threshold.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "DIRTY_RATE_HIGH",
+///      "data": { "dirty-rate": 1032, "threshold": 512 },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DirtyRateHigh {
+    /// Dirty rate of guest memory in MiB/s.
+    #[serde(rename = "dirty-rate")]
+    pub dirty_rate: u64,
+    /// Threshold of dirty rate in MiB/s.
+    pub threshold: u64,
+}
+
+/// MemoryPressure
+///
+/// Emitted every interval of `-mem-pressure-monitor` while the memory pressure of host
+/// is above the threshold of `some` or `full` avg10.
+///
+/// # Examples
+///
+/// ```text
+/// <- { "event": "MEMORY_PRESSURE",
+///      "data": { "some-avg10": 23.1, "full-avg10": 4.5 },
+///      "timestamp": { "seconds": 1265044230, "microseconds": 450486 } }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryPressure {
+    /// Percent of time in the last 10 seconds some tasks stalled on memory.
+    #[serde(rename = "some-avg10")]
+    pub some_avg10: f64,
+    /// Percent of time in the last 10 seconds all tasks stalled on memory.
+    #[serde(rename = "full-avg10")]
+    pub full_avg10: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, EnumIter, EnumVariantNames, EnumString)]
+#[serde(tag = "event")]
+pub enum QmpEvent {
+    #[serde(rename = "SHUTDOWN")]
+    Shutdown {
+        data: Shutdown,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "RESET")]
+    Reset { data: Reset, timestamp: TimeStamp },
+    #[serde(rename = "STOP")]
+    Stop {
+        #[serde(default)]
+        data: Stop,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "RESUME")]
+    Resume {
+        #[serde(default)]
+        data: Resume,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "POWERDOWN")]
+    Powerdown {
+        #[serde(default)]
+        data: Powerdown,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "DEVICE_DELETED")]
+    DeviceDeleted {
+        data: DeviceDeleted,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "BALLOON_CHANGED")]
+    BalloonChanged {
+        data: BalloonInfo,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "GUEST_PANICKED")]
+    GuestPanicked {
+        data: GuestPanicked,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "GUEST_CRASHLOADED")]
+    GuestCrashloaded {
+        data: GuestCrashloaded,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "GUEST_AGENT_RESPONSE")]
+    GuestAgentResponse {
+        data: GuestAgentResponse,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "EVENT_LOOP_STALL")]
+    EventLoopStall {
+        data: EventLoopStall,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "THREAD_HANG")]
+    ThreadHang {
+        data: ThreadHang,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "DIRTY_RATE_HIGH")]
+    DirtyRateHigh {
+        data: DirtyRateHigh,
+        timestamp: TimeStamp,
+    },
+    #[serde(rename = "MEMORY_PRESSURE")]
+    MemoryPressure {
+        data: MemoryPressure,
+        timestamp: TimeStamp,
+    },
+}
+
+/// query-balloon:
+///
+/// Query the actual size of memory of VM.
+///
+/// # Returns
+///
+/// `BalloonInfo` includs the actual size of memory
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-balloon" }
+/// <- {"return":{"actual":8589934592}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_balloon {}
+impl Command for query_balloon {
+    type Res = BalloonInfo;
+    fn back(self) -> BalloonInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonInfo {
+    pub actual: u64,
+}
+
+/// set-power-supply:
+///
+/// Set the status of the emulated AC adapter and battery. After that, the status
+/// of host power supply is no longer mirrored to VM.
+///
+/// # Arguments
+///
+/// * `ac-online` - Whether AC adapter is plugged, optional.
+/// * `battery-level` - Charge level of battery in percentage, optional.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "set-power-supply",
+///      "arguments": { "ac-online": false, "battery-level": 30 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_power_supply {
+    #[serde(rename = "ac-online")]
+    pub ac_online: Option<bool>,
+    #[serde(rename = "battery-level")]
+    pub battery_level: Option<u8>,
+}
+
+impl Command for set_power_supply {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-power-supply:
+///
+/// Query the status of AC adapter and battery.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-power-supply" }
+/// <- {"return":{"ac-online":false,"battery-level":30,"charging":false}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_power_supply {}
+impl Command for query_power_supply {
+    type Res = PowerSupplyInfo;
+    fn back(self) -> PowerSupplyInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PowerSupplyInfo {
+    #[serde(rename = "ac-online")]
+    pub ac_online: bool,
+    #[serde(rename = "battery-level")]
+    pub battery_level: u8,
+    pub charging: bool,
+}
+
+/// dump-guest-memory:
+///
+/// Dump guest memory to an ELF core file. If vmcoreinfo device is configured, the
+/// vmcoreinfo note registered by guest kernel is included, so that the file can
+/// be analyzed by crash or drgn directly. VM is paused during the dump.
+///
+/// # Arguments
+///
+/// * `paging` - Whether to dump by guest virtual address, only false is supported.
+/// * `protocol` - Destination of the dump, in format of "file:<path>".
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "dump-guest-memory",
+///      "arguments": { "paging": false, "protocol": "file:/tmp/vmcore" } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct dump_guest_memory {
+    pub paging: bool,
+    pub protocol: String,
+}
+
+impl Command for dump_guest_memory {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// display-resize:
+///
+/// Resize an output of virtio-gpu, as if the window of display client is resized.
+/// Guest is notified by display change event and gets the new EDID of the output,
+/// so that multiple outputs can be used as a multi-monitor desktop.
+///
+/// # Arguments
+///
+/// * `output` - Index of the output, which is less than `max_outputs` of virtio-gpu.
+/// * `width` - Width of the output, zero disconnects the output.
+/// * `height` - Height of the output, zero disconnects the output.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "display-resize",
+///      "arguments": { "output": 1, "width": 1920, "height": 1080 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct display_resize {
+    pub output: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Command for display_resize {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Encoding of data transferred by ringbuf commands.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataFormat {
+    /// Data is UTF-8 string.
+    #[default]
+    #[serde(rename = "utf8")]
+    Utf8,
+    /// Data is encoded in base64.
+    #[serde(rename = "base64")]
+    Base64,
+}
+
+/// ringbuf-read:
+///
+/// Read and remove data from a ringbuf chardev.
+///
+/// # Arguments
+///
+/// * `device` - Id of the ringbuf chardev.
+/// * `size` - Max bytes to read.
+/// * `format` - Encoding of the returned data, `utf8` or `base64`. Default is `utf8`.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "ringbuf-read",
+///      "arguments": { "device": "ring0", "size": 1024, "format": "utf8" } }
+/// <- {"return":"login: "}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ringbuf_read {
+    pub device: String,
+    pub size: u64,
+    #[serde(default)]
+    pub format: DataFormat,
+}
+
+impl Command for ringbuf_read {
+    type Res = String;
+    fn back(self) -> String {
+        Default::default()
+    }
+}
+
+/// ringbuf-write:
+///
+/// Write data to the frontend of a ringbuf chardev as input.
+///
+/// # Arguments
+///
+/// * `device` - Id of the ringbuf chardev.
+/// * `data` - Data to write.
+/// * `format` - Encoding of the data, `utf8` or `base64`. Default is `utf8`.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "ringbuf-write",
+///      "arguments": { "device": "ring0", "data": "root\r", "format": "utf8" } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ringbuf_write {
+    pub device: String,
+    pub data: String,
+    #[serde(default)]
+    pub format: DataFormat,
+}
+
+impl Command for ringbuf_write {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// guest-agent-command:
+///
+/// Send a command to qemu-guest-agent in guest through the virtio serial port named
+/// `org.qemu.guest_agent.0`. The command is executed asynchronously, and its result
+/// is reported by `GUEST_AGENT_RESPONSE` event.
+///
+/// # Arguments
+///
+/// * `command` - Name of the guest agent command, such as `guest-exec`.
+/// * `arguments` - Arguments of the guest agent command.
+/// * `timeout` - Seconds to wait for the response of guest agent. Default is 10.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "guest-agent-command",
+///      "arguments": { "command": "guest-exec",
+///                     "arguments": { "path": "/bin/ls", "capture-output": true } } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct guest_agent_command {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Any>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl Command for guest_agent_command {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-vnc:
+/// Information about current VNC server.
+///
+/// # Examples
+///
+/// For pc machine type started with -vnc ip:port(for example: 0.0.0.0:0):
+/// ```text
+/// -> { "execute": "query-vnc" }
 /// <- {"return": {
 ///         "enabled": true,
 ///         "host": "0.0.0.0",
@@ -1583,434 +2376,1003 @@ pub struct BalloonInfo {
 ///     }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_vnc {}
-impl Command for query_vnc {
-    type Res = VncInfo;
-    fn back(self) -> VncInfo {
+pub struct query_vnc {}
+impl Command for query_vnc {
+    type Res = VncInfo;
+    fn back(self) -> VncInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VncInfo {
+    #[serde(rename = "enabled")]
+    pub enabled: bool,
+    #[serde(rename = "host")]
+    pub host: String,
+    #[serde(rename = "service")]
+    pub service: String,
+    #[serde(rename = "auth")]
+    pub auth: String,
+    #[serde(rename = "family")]
+    pub family: String,
+    #[serde(rename = "clients")]
+    pub clients: Vec<VncClientInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VncClientInfo {
+    #[serde(rename = "host")]
+    pub host: String,
+    #[serde(rename = "service")]
+    pub service: String,
+    #[serde(rename = "family")]
+    pub family: String,
+}
+
+/// balloon:
+///
+/// Advice VM to change memory size with the argument `value`.
+///
+/// # Arguments
+///
+/// * `value` - Memoey size.
+///
+/// # Notes
+///
+/// This is only an advice instead of command to VM,
+/// therefore, the VM changes its memory according to `value` and its condation.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "balloon", "arguments": { "value": 589934492 } }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct balloon {
+    #[serde(rename = "value")]
+    pub value: u64,
+}
+
+impl Command for balloon {
+    type Res = Empty;
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// version:
+///
+/// Query version of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-version" }
+/// <- {"return":{"version":{"qemu":{"minor":1,"micro":0,"major":5},"package":"StratoVirt-2.3.0"},"capabilities":[]}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_version {}
+
+impl Command for query_version {
+    type Res = Version;
+
+    fn back(self) -> Version {
+        Default::default()
+    }
+}
+
+/// Query commands:
+///
+/// Query all qmp commands of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-commands" }
+/// <- {"return":[{"name":"qmp_capabilities"},{"name":"quit"},{"name":"stop"},{"name":"cont"},
+/// {"name":"system_powerdown"},{"name":"system_reset"},{"name":"device_add"},{"name":"device_del"},
+/// {"name":"chardev_add"},{"name":"chardev_remove"},{"name":"netdev_add"},{"name":"netdev_del"},
+/// {"name":"cameradev_add"},{"name":"cameradev_del"},{"name":"query-hotpluggable-cpus"},
+/// {"name":"query-cpus"},{"name":"query_status"},{"name":"getfd"},{"name":"blockdev_add"},
+/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},{"name":"query-vnc"},
+/// {"name":"migrate"},{"name":"query_migrate"},{"name":"cancel_migrate"},{"name":"query_version"},
+/// {"name":"query_commands"},{"name":"query_target"},{"name":"query_kvm"},{"name":"query_machines"},
+/// {"name":"query-events"},{"name":"list_type"},{"name":"device_list_properties"},{"name":"block-commit"},
+/// {"name":"query_tpm_models"},{"name":"query_tpm_types"},{"name":"query_command_line_options"},
+/// {"name":"query_migrate_capabilities"},{"name":"query_qmp_schema"},{"name":"query_sev_capabilities"},
+/// {"name":"query-chardev"},{"name":"qom-list"},{"name":"qom_get"},{"name":"query-block"},{"name":"query-named-block-nodes"},
+/// {"name":"query-blockstats"},{"name":"query-block-jobs"},{"name":"query-gic-capabilities"},{"name":"query-iothreads"},
+/// {"name":"set-vcpu-affinity"},{"name":"set-iothread-affinity"},{"name":"vcpu-throttle"},
+/// {"name":"update_region"},{"name":"input_event"},{"name":"human_monitor_command"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_commands {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Cmd {
+    pub name: String,
+}
+
+impl Command for query_commands {
+    type Res = Vec<Cmd>;
+
+    fn back(self) -> Vec<Cmd> {
+        Default::default()
+    }
+}
+
+/// Query target:
+///
+/// Query the target platform where the StratoVirt is running.
+///
+/// # Example
+///
+/// ```text
+/// # for X86 platform.
+/// -> { "execute": "query-target" }
+/// <- {"return":{"arch":"x86_64"}}
+///
+/// # for Aarch64 platform.
+/// -> { "execute": "query-target" }
+/// <- {"return":{"arch":"aarch64"}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_target {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Target {
+    pub arch: String,
+}
+
+impl Command for query_target {
+    type Res = Target;
+
+    fn back(self) -> Target {
+        Default::default()
+    }
+}
+
+/// Query machines:
+///
+/// Query machine information.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-machines" }
+/// <- {"return":[{"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"none","numa-mem-supported":false},
+/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"microvm","numa-mem-supported":false},
+/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"standardvm","numa-mem-supported":false}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_machines {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct MachineInfo {
+    #[serde(rename = "hotpluggable-cpus")]
+    pub hotplug: bool,
+    pub name: String,
+    #[serde(rename = "numa-mem-supported")]
+    pub numa_mem_support: bool,
+    #[serde(rename = "cpu-max")]
+    pub cpu_max: u8,
+    pub deprecated: bool,
+}
+
+impl Command for query_machines {
+    type Res = Vec<MachineInfo>;
+
+    fn back(self) -> Vec<MachineInfo> {
+        Default::default()
+    }
+}
+
+/// Query events:
+///
+/// Query all events of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-events" }
+/// <- {"return":[{"name":"Shutdown"},{"name":"Reset"},
+/// {"name":"Stop"},{"name":"Resume"},{"name":"DeviceDeleted"},
+/// {"name":"BalloonChanged"},{"name":"GuestPanicked"},{"name":"GuestCrashloaded"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Events {
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_events {}
+
+impl Command for query_events {
+    type Res = Vec<Events>;
+
+    fn back(self) -> Vec<Events> {
+        Default::default()
+    }
+}
+
+/// Query KVM:
+///
+/// Query if KVM is enabled.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-kvm" }
+/// <- {"return":{"enabled":true,"present":true}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_kvm {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct KvmInfo {
+    pub enabled: bool,
+    pub present: bool,
+}
+
+impl Command for query_kvm {
+    type Res = KvmInfo;
+
+    fn back(self) -> KvmInfo {
+        Default::default()
+    }
+}
+
+/// List all Qom type.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom-list-types" }
+/// <- {"return":[{"name":"ioh3420","parent":"pcie-root-port-base"},
+/// {"name":"pcie-root-port","parent":"pcie-root-port-base"},
+/// {"name":"pcie-pci-bridge","parent":"base-pci-bridge"},
+/// {"name":"pci-bridge","parent":"base-pci-bridge"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct list_type {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TypeLists {
+    name: String,
+    parent: String,
+}
+
+impl TypeLists {
+    pub fn new(name: String, parent: String) -> Self {
+        TypeLists { name, parent }
+    }
+}
+
+impl Command for list_type {
+    type Res = Vec<TypeLists>;
+
+    fn back(self) -> Vec<TypeLists> {
+        Default::default()
+    }
+}
+
+/// Get device list properties.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "device-list-properties", "arguments": {"typename": "virtio-blk-pci"} }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct device_list_properties {
+    pub typename: String,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceProps {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub prop_type: String,
+}
+
+impl Command for device_list_properties {
+    type Res = Vec<DeviceProps>;
+
+    fn back(self) -> Vec<DeviceProps> {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct block_commit {}
+
+impl Command for block_commit {
+    type Res = Vec<DeviceProps>;
+
+    fn back(self) -> Vec<DeviceProps> {
+        Default::default()
+    }
+}
+
+/// Query tpm models of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-tpm-models" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_tpm_models {}
+
+impl Command for query_tpm_models {
+    type Res = Vec<String>;
+
+    fn back(self) -> Vec<String> {
         Default::default()
     }
 }
 
+/// Query target of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-tpm-types" }
+/// <- {"return":[]}
+/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct VncInfo {
-    #[serde(rename = "enabled")]
-    pub enabled: bool,
-    #[serde(rename = "host")]
-    pub host: String,
-    #[serde(rename = "service")]
-    pub service: String,
-    #[serde(rename = "auth")]
-    pub auth: String,
-    #[serde(rename = "family")]
-    pub family: String,
-    #[serde(rename = "clients")]
-    pub clients: Vec<VncClientInfo>,
+pub struct query_tpm_types {}
+
+impl Command for query_tpm_types {
+    type Res = Vec<String>;
+
+    fn back(self) -> Vec<String> {
+        Default::default()
+    }
 }
 
+/// Query command line options.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-command-line-options" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_command_line_options {}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct VncClientInfo {
-    #[serde(rename = "host")]
-    pub host: String,
-    #[serde(rename = "service")]
-    pub service: String,
-    #[serde(rename = "family")]
-    pub family: String,
+pub struct CmdParameter {
+    pub name: String,
+    pub help: String,
+    #[serde(rename = "type")]
+    pub parameter_type: String,
 }
 
-/// balloon:
-///
-/// Advice VM to change memory size with the argument `value`.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct CmdLine {
+    pub parameters: Vec<CmdParameter>,
+    pub option: String,
+}
+
+impl Command for query_command_line_options {
+    type Res = Vec<CmdLine>;
+
+    fn back(self) -> Vec<CmdLine> {
+        Default::default()
+    }
+}
+
+/// Query capabilities of migration.
 ///
-/// # Arguments
+/// # Example
 ///
-/// * `value` - Memoey size.
+/// ```text
+/// -> { "execute": "query-migrate-capabilities" }
+/// <- {"return":[{"state":false,"capability":"background-snapshot"}]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_migrate_capabilities {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct MigrateCapabilities {
+    pub state: bool,
+    pub capability: String,
+}
+
+impl Command for query_migrate_capabilities {
+    type Res = Vec<MigrateCapabilities>;
+
+    fn back(self) -> Vec<MigrateCapabilities> {
+        Default::default()
+    }
+}
+
+/// Query target of StratoVirt.
 ///
-/// # Notes
+/// # Example
 ///
-/// This is only an advice instead of command to VM,
-/// therefore, the VM changes its memory according to `value` and its condation.
+/// ```text
+/// -> { "execute": "query-qmp-schema" }
+/// <- {"return":{}}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_qmp_schema {}
+
+impl Command for query_qmp_schema {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// Query capabilities of sev.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "balloon", "arguments": { "value": 589934492 } }
+/// -> { "execute": "query-sev-capabilities" }
 /// <- {"return":{}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct balloon {
-    #[serde(rename = "value")]
-    pub value: u64,
-}
+pub struct query_sev_capabilities {}
 
-impl Command for balloon {
+impl Command for query_sev_capabilities {
     type Res = Empty;
+
     fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// version:
+/// query-sev:
 ///
-/// Query version of StratoVirt.
+/// Query information of the SEV-SNP guest and the platform, which is used to verify
+/// the attestation report requested by guest.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-version" }
-/// <- {"return":{"version":{"qemu":{"minor":1,"micro":0,"major":5},"package":"StratoVirt-2.3.0"},"capabilities":[]}}
+/// -> { "execute": "query-sev" }
+/// <- {"return":{"enabled":true,"sev-type":"SEV-SNP","state":"running","policy":196608,
+///      "host-data":"0000...0000","api-major":1,"api-minor":55,"build-id":21,
+///      "tcb-version":15352208179330580483}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_version {}
+pub struct query_sev {}
 
-impl Command for query_version {
-    type Res = Version;
+impl Command for query_sev {
+    type Res = SevInfo;
 
-    fn back(self) -> Version {
+    fn back(self) -> SevInfo {
         Default::default()
     }
 }
 
-/// Query commands:
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct SevInfo {
+    pub enabled: bool,
+    #[serde(rename = "sev-type")]
+    pub sev_type: String,
+    /// `uninit`, `launch-update` or `running`.
+    pub state: String,
+    pub policy: u64,
+    /// Host data in hex, which is included in attestation report.
+    #[serde(rename = "host-data")]
+    pub host_data: String,
+    #[serde(rename = "api-major")]
+    pub api_major: u8,
+    #[serde(rename = "api-minor")]
+    pub api_minor: u8,
+    #[serde(rename = "build-id")]
+    pub build_id: u32,
+    /// Reported TCB version of the platform.
+    #[serde(rename = "tcb-version")]
+    pub tcb_version: u64,
+}
+
+/// query-sev-attestation-report:
 ///
-/// Query all qmp commands of StratoVirt.
+/// Request an attestation report of SEV-SNP guest from the firmware. The report is
+/// signed by the platform and contains the launch measurement calculated by the
+/// firmware. It's available after launch finishes.
+///
+/// # Arguments
+///
+/// * `mnonce` - 16 bytes nonce in base64, which is included in the report.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-commands" }
-/// <- {"return":[{"name":"qmp_capabilities"},{"name":"quit"},{"name":"stop"},{"name":"cont"},
-/// {"name":"system_powerdown"},{"name":"system_reset"},{"name":"device_add"},{"name":"device_del"},
-/// {"name":"chardev_add"},{"name":"chardev_remove"},{"name":"netdev_add"},{"name":"netdev_del"},
-/// {"name":"cameradev_add"},{"name":"cameradev_del"},{"name":"query-hotpluggable-cpus"},
-/// {"name":"query-cpus"},{"name":"query_status"},{"name":"getfd"},{"name":"blockdev_add"},
-/// {"name":"blockdev_del"},{"name":"balloon"},{"name":"query_balloon"},{"name":"query-vnc"},
-/// {"name":"migrate"},{"name":"query_migrate"},{"name":"cancel_migrate"},{"name":"query_version"},
-/// {"name":"query_commands"},{"name":"query_target"},{"name":"query_kvm"},{"name":"query_machines"},
-/// {"name":"query-events"},{"name":"list_type"},{"name":"device_list_properties"},{"name":"block-commit"},
-/// {"name":"query_tpm_models"},{"name":"query_tpm_types"},{"name":"query_command_line_options"},
-/// {"name":"query_migrate_capabilities"},{"name":"query_qmp_schema"},{"name":"query_sev_capabilities"},
-/// {"name":"query-chardev"},{"name":"qom-list"},{"name":"qom_get"},{"name":"query-block"},{"name":"query-named-block-nodes"},
-/// {"name":"query-blockstats"},{"name":"query-block-jobs"},{"name":"query-gic-capabilities"},{"name":"query-iothreads"},
-/// {"name":"update_region"},{"name":"input_event"},{"name":"human_monitor_command"}]}
+/// -> { "execute": "query-sev-attestation-report",
+///      "arguments": { "mnonce": "aaaaaaaaaaaaaaaaaaaaaa==" } }
+/// <- {"return":{"data":"AgAAAAAAAAA..."}}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_commands {}
+#[serde(deny_unknown_fields)]
+pub struct query_sev_attestation_report {
+    pub mnonce: String,
+}
+
+impl Command for query_sev_attestation_report {
+    type Res = SevAttestationReport;
+
+    fn back(self) -> SevAttestationReport {
+        Default::default()
+    }
+}
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Cmd {
+pub struct SevAttestationReport {
+    /// Attestation report in base64.
+    pub data: String,
+}
+
+/// List all Qom.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom-list" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct qom_list {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct PropList {
     pub name: String,
+    #[serde(rename = "type")]
+    pub prop_type: String,
 }
 
-impl Command for query_commands {
-    type Res = Vec<Cmd>;
+impl Command for qom_list {
+    type Res = Vec<PropList>;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Vec<PropList> {
         Default::default()
     }
 }
 
-/// Query target:
-///
-/// Query the target platform where the StratoVirt is running.
+/// Query char devices.
 ///
 /// # Example
 ///
 /// ```text
-/// # for X86 platform.
-/// -> { "execute": "query-target" }
-/// <- {"return":{"arch":"x86_64"}}
+/// -> { "execute": "query-chardev" }
+/// <- {"return":[]}
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct query_chardev {}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ChardevInfo {
+    #[serde(rename = "frontend-open")]
+    pub open: bool,
+    pub filename: String,
+    pub label: String,
+}
+
+impl Command for query_chardev {
+    type Res = Vec<ChardevInfo>;
+
+    fn back(self) -> Vec<ChardevInfo> {
+        Default::default()
+    }
+}
+
+/// Get qom properties.
 ///
-/// # for Aarch64 platform.
-/// -> { "execute": "query-target" }
-/// <- {"return":{"arch":"aarch64"}}
+/// # Example
+///
+/// ```text
+/// -> { "execute": "qom_get" }
+/// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_target {}
+pub struct qom_get {}
 
+impl Command for qom_get {
+    type Res = bool;
+
+    fn back(self) -> bool {
+        Default::default()
+    }
+}
+
+/// Query blocks of StratoVirt.
+///
+/// # Example
+///
+/// ```text
+/// -> { "execute": "query-block" }
+/// <- {"return":[]}
+/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Target {
-    pub arch: String,
-}
+pub struct query_block {}
 
-impl Command for query_target {
-    type Res = Target;
+impl Command for query_block {
+    type Res = Vec<Cmd>;
 
-    fn back(self) -> Target {
+    fn back(self) -> Vec<Cmd> {
         Default::default()
     }
 }
 
-/// Query machines:
-///
-/// Query machine information.
+/// Query named block node.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-machines" }
-/// <- {"return":[{"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"none","numa-mem-supported":false},
-/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"microvm","numa-mem-supported":false},
-/// {"cpu-max":255,"deprecated":false,"hotpluggable-cpus":true,"name":"standardvm","numa-mem-supported":false}]}
+/// -> { "execute": "query-named-block-nodes" }
+/// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_machines {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct MachineInfo {
-    #[serde(rename = "hotpluggable-cpus")]
-    pub hotplug: bool,
-    pub name: String,
-    #[serde(rename = "numa-mem-supported")]
-    pub numa_mem_support: bool,
-    #[serde(rename = "cpu-max")]
-    pub cpu_max: u8,
-    pub deprecated: bool,
-}
+pub struct query_named_block_nodes {}
 
-impl Command for query_machines {
-    type Res = Vec<MachineInfo>;
+impl Command for query_named_block_nodes {
+    type Res = Vec<Cmd>;
 
-    fn back(self) -> Vec<MachineInfo> {
+    fn back(self) -> Vec<Cmd> {
         Default::default()
     }
 }
 
-/// Query events:
-///
-/// Query all events of StratoVirt.
+/// Query status of blocks.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-events" }
-/// <- {"return":[{"name":"Shutdown"},{"name":"Reset"},
-/// {"name":"Stop"},{"name":"Resume"},{"name":"DeviceDeleted"},
-/// {"name":"BalloonChanged"}]}
+/// -> { "execute": "query-blockstats" }
+/// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct Events {
-    pub name: String,
-}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_events {}
+pub struct query_blockstats {}
 
-impl Command for query_events {
-    type Res = Vec<Events>;
+impl Command for query_blockstats {
+    type Res = Vec<Cmd>;
 
-    fn back(self) -> Vec<Events> {
+    fn back(self) -> Vec<Cmd> {
         Default::default()
     }
 }
 
-/// Query KVM:
-///
-/// Query if KVM is enabled.
+/// Query jobs of blocks.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "query-kvm" }
-/// <- {"return":{"enabled":true,"present":true}}
+/// -> { "execute": "query-block_jobs" }
+/// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_kvm {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct KvmInfo {
-    pub enabled: bool,
-    pub present: bool,
-}
+pub struct query_block_jobs {}
 
-impl Command for query_kvm {
-    type Res = KvmInfo;
+impl Command for query_block_jobs {
+    type Res = Vec<Cmd>;
 
-    fn back(self) -> KvmInfo {
+    fn back(self) -> Vec<Cmd> {
         Default::default()
     }
 }
 
-/// List all Qom type.
+/// Query capabilities of gic.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "qom-list-types" }
-/// <- {"return":[{"name":"ioh3420","parent":"pcie-root-port-base"},
-/// {"name":"pcie-root-port","parent":"pcie-root-port-base"},
-/// {"name":"pcie-pci-bridge","parent":"base-pci-bridge"},
-/// {"name":"pci-bridge","parent":"base-pci-bridge"}]}
+/// -> { "execute": "query-gic-capabilities" }
+/// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct list_type {}
+pub struct query_gic_capabilities {}
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct TypeLists {
-    name: String,
-    parent: String,
-}
-
-impl TypeLists {
-    pub fn new(name: String, parent: String) -> Self {
-        TypeLists { name, parent }
-    }
+pub struct GicCap {
+    emulated: bool,
+    version: u32,
+    kernel: bool,
 }
 
-impl Command for list_type {
-    type Res = Vec<TypeLists>;
+impl Command for query_gic_capabilities {
+    type Res = Vec<GicCap>;
 
-    fn back(self) -> Vec<TypeLists> {
+    fn back(self) -> Vec<GicCap> {
         Default::default()
     }
 }
 
-/// Get device list properties.
+/// Query information of iothreads.
 ///
 /// # Example
 ///
 /// ```text
-/// -> { "execute": "device-list-properties", "arguments": {"typename": "virtio-blk-pci"} }
+/// -> { "execute": "query-iothreads" }
 /// <- {"return":[]}
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct device_list_properties {
-    pub typename: String,
-}
+pub struct query_iothreads {}
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct DeviceProps {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub prop_type: String,
+pub struct IothreadInfo {
+    #[serde(rename = "poll-shrink")]
+    pub shrink: u32,
+    #[serde(rename = "thread-id")]
+    pub pid: u32,
+    #[serde(rename = "poll-grow")]
+    pub grow: u32,
+    #[serde(rename = "poll-max-ns")]
+    pub max: u32,
+    pub id: String,
 }
 
-impl Command for device_list_properties {
-    type Res = Vec<DeviceProps>;
+impl Command for query_iothreads {
+    type Res = Vec<IothreadInfo>;
 
-    fn back(self) -> Vec<DeviceProps> {
+    fn back(self) -> Vec<IothreadInfo> {
         Default::default()
     }
 }
 
+/// query-vcpu-exit-stats
+///
+/// Query the exits of each vCPU. `exits` counts the exits handled by StratoVirt by
+/// reason, and `top-addresses` lists the PIO and MMIO addresses causing most exits.
+/// `kvm-stats` are the cumulative statistics of kvm, which count the exits handled
+/// in kernel too, e.g. `pf_taken` counts the exits for EPT violation on x86_64. It's
+/// empty if kvm doesn't support binary statistics.
+///
+/// # Arguments
+///
+/// * `reset` - Clear the statistics after they are queried.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-vcpu-exit-stats" }
+/// <- { "return": [ { "cpu-index": 0,
+///      "exits": { "hlt": 0, "intr": 12, "io-in": 35, "io-out": 1200, "mmio-read": 25,
+///      "mmio-write": 1983, ... },
+///      "top-addresses": [ { "space": "mmio", "addr": 4273803264, "count": 1980 },
+///      { "space": "pio", "addr": 1016, "count": 1200 } ],
+///      "kvm-stats": { "exits": 3301, "halt_exits": 10, "pf_taken": 28, ... } } ] }
+/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct block_commit {}
+#[serde(deny_unknown_fields)]
+pub struct query_vcpu_exit_stats {
+    #[serde(default)]
+    pub reset: bool,
+}
+pub type QueryVcpuExitStatsArgument = query_vcpu_exit_stats;
 
-impl Command for block_commit {
-    type Res = Vec<DeviceProps>;
+impl Command for query_vcpu_exit_stats {
+    type Res = Vec<VcpuExitStatsInfo>;
 
-    fn back(self) -> Vec<DeviceProps> {
+    fn back(self) -> Vec<VcpuExitStatsInfo> {
         Default::default()
     }
 }
 
-/// Query tpm models of StratoVirt.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VcpuExitStatsInfo {
+    #[serde(rename = "cpu-index")]
+    pub cpu_index: u8,
+    pub exits: BTreeMap<String, u64>,
+    #[serde(rename = "top-addresses")]
+    pub top_addresses: Vec<ExitAddressInfo>,
+    #[serde(rename = "kvm-stats")]
+    pub kvm_stats: BTreeMap<String, u64>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ExitAddressInfo {
+    /// `pio` or `mmio`.
+    pub space: String,
+    pub addr: u64,
+    pub count: u64,
+}
+
+/// query-virtqueue-stats
 ///
-/// # Example
+/// Query the statistics of virtqueues of virtio devices, which are reset when the
+/// device is activated by guest.
+///
+/// # Arguments
+///
+/// * `device` - Id of the device, all devices are queried if it's not set, optional.
+/// * `reset` - Clear the statistics after they are queried, optional.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-tpm-models" }
-/// <- {"return":[]}
+/// -> { "execute": "query-virtqueue-stats", "arguments": { "device": "drive-0" } }
+/// <- { "return": [ { "device": "drive-0", "queue": 0, "size": 256, "kicks": 5120,
+///                    "interrupts": 4980, "avail-high-water": 32,
+///                    "event-idx-suppressions": 140 } ] }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_tpm_models {}
+#[serde(deny_unknown_fields)]
+pub struct query_virtqueue_stats {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub reset: bool,
+}
+pub type QueryVirtqueueStatsArgument = query_virtqueue_stats;
 
-impl Command for query_tpm_models {
-    type Res = Vec<String>;
+impl Command for query_virtqueue_stats {
+    type Res = Vec<VirtqueueStatsInfo>;
 
-    fn back(self) -> Vec<String> {
+    fn back(self) -> Vec<VirtqueueStatsInfo> {
         Default::default()
     }
 }
 
-/// Query target of StratoVirt.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct VirtqueueStatsInfo {
+    pub device: String,
+    /// Index of the queue.
+    pub queue: u16,
+    pub size: u16,
+    pub kicks: u64,
+    pub interrupts: u64,
+    #[serde(rename = "avail-high-water")]
+    pub avail_high_water: u16,
+    #[serde(rename = "event-idx-suppressions")]
+    pub event_idx_suppressions: u64,
+}
+
+/// query-vmm-memory
 ///
-/// # Example
+/// Query the breakdown of resident memory of VMM in bytes, which is gathered from
+/// `/proc/self/smaps`. Stacks of the threads busy in user space are not attributed
+/// to the threads, but they are still counted in `stack-rss`.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-tpm-types" }
-/// <- {"return":[]}
+/// -> { "execute": "query-vmm-memory" }
+/// <- { "return": { "total-rss": 1109196800, "guest-ram-rss": 1073741824,
+///                  "heap-rss": 23068672, "stack-rss": 405504, "other-rss": 11980800,
+///                  "threads": [ { "tid": 3201, "name": "stratovirt", "stack-rss": 139264 },
+///                               { "tid": 3210, "name": "CPU 0/KVM", "stack-rss": 16384 } ] } }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_tpm_types {}
+#[serde(deny_unknown_fields)]
+pub struct query_vmm_memory {}
 
-impl Command for query_tpm_types {
-    type Res = Vec<String>;
+impl Command for query_vmm_memory {
+    type Res = VmmMemoryInfo;
 
-    fn back(self) -> Vec<String> {
+    fn back(self) -> VmmMemoryInfo {
         Default::default()
     }
 }
 
-/// Query command line options.
-///
-/// # Example
-///
-/// ```text
-/// -> { "execute": "query-command-line-options" }
-/// <- {"return":[]}
-/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_command_line_options {}
+pub struct VmmMemoryInfo {
+    #[serde(rename = "total-rss")]
+    pub total_rss: u64,
+    /// Resident memory of the mappings of guest memory.
+    #[serde(rename = "guest-ram-rss")]
+    pub guest_ram_rss: u64,
+    /// Resident memory of heap and other anonymous mappings of VMM.
+    #[serde(rename = "heap-rss")]
+    pub heap_rss: u64,
+    #[serde(rename = "stack-rss")]
+    pub stack_rss: u64,
+    /// Resident memory of executable, libraries and other file mappings.
+    #[serde(rename = "other-rss")]
+    pub other_rss: u64,
+    pub threads: Vec<ThreadStackInfo>,
+}
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct CmdParameter {
+pub struct ThreadStackInfo {
+    pub tid: u64,
     pub name: String,
-    pub help: String,
-    #[serde(rename = "type")]
-    pub parameter_type: String,
+    #[serde(rename = "stack-rss")]
+    pub stack_rss: u64,
 }
 
+/// query-latency-histograms
+///
+/// Query latency histograms of requests of block and net devices, from popping the
+/// request from virtqueue to putting it into used ring. All the values are in
+/// nanoseconds.
+///
+/// # Arguments
+///
+/// * `device` - Id of the device, all devices are queried if it's not set.
+/// * `reset` - Clear the histograms after they are queried.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "query-latency-histograms",
+///      "arguments": { "device": "drive-0", "reset": true } }
+/// <- { "return": [ { "device": "drive-0", "operation": "read", "count": 3,
+///      "min": 81920, "max": 393216, "mean": 190122, "p50": 98303, "p90": 393216,
+///      "p99": 393216, "p99.9": 393216, "p99.99": 393216,
+///      "buckets": [ { "start": 81920, "end": 86015, "count": 1 },
+///      { "start": 94208, "end": 98303, "count": 1 },
+///      { "start": 393216, "end": 409599, "count": 1 } ] } ] }
+/// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct CmdLine {
-    pub parameters: Vec<CmdParameter>,
-    pub option: String,
+#[serde(deny_unknown_fields)]
+pub struct query_latency_histograms {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub reset: bool,
 }
+pub type QueryLatencyHistogramsArgument = query_latency_histograms;
 
-impl Command for query_command_line_options {
-    type Res = Vec<CmdLine>;
+impl Command for query_latency_histograms {
+    type Res = Vec<LatencyHistogramInfo>;
 
-    fn back(self) -> Vec<CmdLine> {
+    fn back(self) -> Vec<LatencyHistogramInfo> {
         Default::default()
     }
 }
 
-/// Query capabilities of migration.
-///
-/// # Example
-///
-/// ```text
-/// -> { "execute": "query-migrate-capabilities" }
-/// <- {"return":[]}
-/// ```
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_migrate_capabilities {}
-
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct MigrateCapabilities {
-    pub state: bool,
-    pub capability: String,
+pub struct LatencyHistogramInfo {
+    pub device: String,
+    /// `read`, `write` and `flush` for block, `rx` and `tx` for net.
+    pub operation: String,
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    #[serde(rename = "p99.9")]
+    pub p99_9: u64,
+    #[serde(rename = "p99.99")]
+    pub p99_99: u64,
+    /// Non-empty buckets.
+    pub buckets: Vec<LatencyBucket>,
 }
 
-impl Command for query_migrate_capabilities {
-    type Res = Vec<MigrateCapabilities>;
-
-    fn back(self) -> Vec<MigrateCapabilities> {
-        Default::default()
-    }
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyBucket {
+    pub start: u64,
+    pub end: u64,
+    pub count: u64,
 }
 
-/// Query target of StratoVirt.
+/// set-vcpu-affinity
 ///
-/// # Example
+/// Pin the thread of a vCPU to host CPUs.
+///
+/// # Arguments
+///
+/// * `vcpu` - the index of the vCPU.
+/// * `cpus` - the host CPUs the vCPU thread is allowed to run on.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-qmp-schema" }
-/// <- {"return":{}}
+/// -> { "execute": "set-vcpu-affinity",
+///      "arguments": { "vcpu": 1, "cpus": [2, 3] }}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_qmp_schema {}
+#[serde(deny_unknown_fields)]
+pub struct set_vcpu_affinity {
+    pub vcpu: u8,
+    pub cpus: Vec<u64>,
+}
 
-impl Command for query_qmp_schema {
+impl Command for set_vcpu_affinity {
     type Res = Empty;
 
     fn back(self) -> Empty {
@@ -2018,18 +3380,30 @@ impl Command for query_qmp_schema {
     }
 }
 
-/// Query capabilities of sev.
+/// set-iothread-affinity
 ///
-/// # Example
+/// Pin an iothread to host CPUs.
+///
+/// # Arguments
+///
+/// * `id` - the id of the iothread.
+/// * `cpus` - the host CPUs the iothread is allowed to run on.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-sev-capabilities" }
-/// <- {"return":{}}
+/// -> { "execute": "set-iothread-affinity",
+///      "arguments": { "id": "iothread1", "cpus": [4] }}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_sev_capabilities {}
+#[serde(deny_unknown_fields)]
+pub struct set_iothread_affinity {
+    pub id: String,
+    pub cpus: Vec<u64>,
+}
 
-impl Command for query_sev_capabilities {
+impl Command for set_iothread_affinity {
     type Res = Empty;
 
     fn back(self) -> Empty {
@@ -2037,312 +3411,578 @@ impl Command for query_sev_capabilities {
     }
 }
 
-/// List all Qom.
+/// vcpu-throttle
 ///
-/// # Example
+/// Limit the CPU time consumed by vCPUs, vCPUs are forced to sleep for
+/// `percentage` of time.
+///
+/// # Arguments
+///
+/// * `percentage` - the percentage of time vCPUs sleep, 0 stops throttling, max is 99.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "qom-list" }
-/// <- {"return":[]}
+/// -> { "execute": "vcpu-throttle",
+///      "arguments": { "percentage": 20 }}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct qom_list {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct PropList {
-    pub name: String,
-    #[serde(rename = "type")]
-    pub prop_type: String,
+#[serde(deny_unknown_fields)]
+pub struct vcpu_throttle {
+    pub percentage: u8,
 }
 
-impl Command for qom_list {
-    type Res = Vec<PropList>;
+impl Command for vcpu_throttle {
+    type Res = Empty;
 
-    fn back(self) -> Vec<PropList> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
-
-/// Query char devices.
+/// input_event
 ///
-/// # Example
+/// # Arguments
+///
+/// * `key` - the input type such as 'keyboard' or 'pointer'.
+/// * `value` - the input value.
+
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-chardev" }
-/// <- {"return":[]}
+/// -> { "execute": "input_event",
+///      "arguments": { "key": "pointer", "value": "100,200,1" }}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_chardev {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct ChardevInfo {
-    #[serde(rename = "frontend-open")]
-    pub open: bool,
-    pub filename: String,
-    pub label: String,
+pub struct input_event {
+    pub key: String,
+    pub value: String,
 }
 
-impl Command for query_chardev {
-    type Res = Vec<ChardevInfo>;
+impl Command for input_event {
+    type Res = Vec<input_event>;
 
-    fn back(self) -> Vec<ChardevInfo> {
+    fn back(self) -> Vec<input_event> {
         Default::default()
     }
 }
 
-/// Get qom properties.
+/// Key, which is either a keycode or a qcode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum KeyValue {
+    /// Keycode of PC keyboard, extended keys have the bit 0x80 set.
+    #[serde(rename = "number")]
+    Number(u16),
+    /// Name of key, such as "ctrl" and "a".
+    #[serde(rename = "qcode")]
+    Qcode(String),
+}
+
+/// Button of pointer.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum InputButton {
+    #[serde(rename = "left")]
+    Left,
+    #[serde(rename = "middle")]
+    Middle,
+    #[serde(rename = "right")]
+    Right,
+    #[serde(rename = "wheel-up")]
+    WheelUp,
+    #[serde(rename = "wheel-down")]
+    WheelDown,
+    #[serde(rename = "wheel-left")]
+    WheelLeft,
+    #[serde(rename = "wheel-right")]
+    WheelRight,
+}
+
+/// Axis of pointer.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum InputAxis {
+    #[serde(rename = "x")]
+    X,
+    #[serde(rename = "y")]
+    Y,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputKeyEvent {
+    pub key: KeyValue,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputBtnEvent {
+    pub button: InputButton,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InputMoveEvent {
+    pub axis: InputAxis,
+    pub value: i64,
+}
+
+/// Input event sent by `input-send-event`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum InputEvent {
+    /// Key is pressed or released.
+    #[serde(rename = "key")]
+    Key(InputKeyEvent),
+    /// Button of pointer is pressed or released.
+    #[serde(rename = "btn")]
+    Btn(InputBtnEvent),
+    /// Pointer moves to the absolute position in range [0, 0x7fff].
+    #[serde(rename = "abs")]
+    Abs(InputMoveEvent),
+    /// Pointer moves by the relative distance.
+    #[serde(rename = "rel")]
+    Rel(InputMoveEvent),
+}
+
+/// input-send-event
 ///
-/// # Example
+/// Send input events to the keyboard and pointer of guest, e.g. usb-kbd and usb-tablet.
+///
+/// # Arguments
+///
+/// * `events` - List of input events.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "qom_get" }
-/// <- {"return":[]}
+/// -> { "execute": "input-send-event",
+///      "arguments": { "events": [
+///          { "type": "abs", "data": { "axis": "x", "value": 16384 } },
+///          { "type": "abs", "data": { "axis": "y", "value": 16384 } },
+///          { "type": "btn", "data": { "button": "left", "down": true } } ] } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct qom_get {}
+#[serde(deny_unknown_fields)]
+pub struct input_send_event {
+    pub events: Vec<InputEvent>,
+}
 
-impl Command for qom_get {
-    type Res = bool;
+impl Command for input_send_event {
+    type Res = Empty;
 
-    fn back(self) -> bool {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query blocks of StratoVirt.
+/// send-key
 ///
-/// # Example
+/// Press the keys in order, and release them in reverse order after holding.
+///
+/// # Arguments
+///
+/// * `keys` - List of keys to press.
+/// * `hold-time` - Milliseconds to hold the keys. Default is 100.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-block" }
-/// <- {"return":[]}
+/// -> { "execute": "send-key",
+///      "arguments": { "keys": [ { "type": "qcode", "data": "ctrl" },
+///                               { "type": "qcode", "data": "alt" },
+///                               { "type": "qcode", "data": "delete" } ] } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_block {}
+#[serde(deny_unknown_fields)]
+pub struct send_key {
+    pub keys: Vec<KeyValue>,
+    #[serde(rename = "hold-time")]
+    pub hold_time: Option<u64>,
+}
 
-impl Command for query_block {
-    type Res = Vec<Cmd>;
+impl Command for send_key {
+    type Res = Empty;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query named block node.
+/// human-monitor-command
 ///
-/// # Example
+/// # Arguments
+///
+/// * `command_line` - the command line will be executed.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-named-block-nodes" }
-/// <- {"return":[]}
+/// -> { "execute": "human-monitor-command",
+///      "arguments": { "command-line": "drive_add dummy
+///      file=/path/to/file,format=raw,if=none,id=drive-id" }}
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_named_block_nodes {}
-
-impl Command for query_named_block_nodes {
-    type Res = Vec<Cmd>;
-
-    fn back(self) -> Vec<Cmd> {
-        Default::default()
-    }
+#[serde(deny_unknown_fields)]
+pub struct human_monitor_command {
+    #[serde(rename = "command-line")]
+    pub command_line: String,
 }
+pub type HumanMonitorCmdArgument = human_monitor_command;
 
-/// Query status of blocks.
+/// blockdev-snapshot-internal-sync
 ///
-/// # Example
+/// Create disk internal snapshot.
+///
+/// # Arguments
+///
+/// * `device` - the valid block device.
+/// * `name` - the snapshot name.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-blockstats" }
-/// <- {"return":[]}
+/// -> { "execute": "blockdev-snapshot-internal-sync",
+///      "arguments": { "device": "disk0",
+///                     "name": "snapshot1" }}
+/// <- { "return": {} }
+/// ```
+///
+/// blockdev-snapshot-delete-internal-sync
+///
+/// Delete disk internal snapshot.
+///
+/// # Arguments
+///
+/// * `device` - the valid block device.
+/// * `name` - the snapshot name.
+///
+/// # Examples
+///
+/// ```text
+/// -> { "execute": "blockdev-snapshot-delete-internal-sync",
+///      "arguments": { "device": "disk0",
+///                     "name": "snapshot1" }}
+/// <- { "return": {
+///                    "id": "1",
+///                    "name": "snapshot0",
+///                    "vm-state-size": 0,
+///                    "date-sec": 1000012,
+///                    "date-nsec": 10,
+///                    "vm-clock-sec": 100,
+///                    "vm-clock-nsec": 20,
+///                    "icount": 220414
+///  } }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_blockstats {}
-
-impl Command for query_blockstats {
-    type Res = Vec<Cmd>;
-
-    fn back(self) -> Vec<Cmd> {
-        Default::default()
-    }
+#[serde(deny_unknown_fields)]
+pub struct blockdev_snapshot_internal {
+    pub device: String,
+    pub name: String,
 }
+pub type BlockdevSnapshotInternalArgument = blockdev_snapshot_internal;
 
-/// Query jobs of blocks.
+/// qmp-audit-set-state
 ///
-/// # Example
+/// Enable or disable the qmp audit log set by `-qmp-audit`. The command itself is
+/// always logged.
+///
+/// # Arguments
+///
+/// * `enable` - Whether to log executed commands.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-block_jobs" }
-/// <- {"return":[]}
+/// -> { "execute": "qmp-audit-set-state", "arguments": { "enable": false } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_block_jobs {}
+#[serde(deny_unknown_fields)]
+pub struct qmp_audit_set_state {
+    pub enable: bool,
+}
 
-impl Command for query_block_jobs {
-    type Res = Vec<Cmd>;
+impl Command for qmp_audit_set_state {
+    type Res = Empty;
 
-    fn back(self) -> Vec<Cmd> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
 
-/// Query capabilities of gic.
+/// trace-event-get-state
 ///
-/// # Example
+/// Query the state of trace events.
+///
+/// # Arguments
+///
+/// * `name` - Name of the event, `*` matches any sequence of characters.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-gic-capabilities" }
-/// <- {"return":[]}
+/// -> { "execute": "trace-event-get-state", "arguments": { "name": "virtio_*" } }
+/// <- { "return": [ { "name": "virtio_receive_request", "state": "enabled" },
+///                  { "name": "virtio_send_interrupt", "state": "disabled" } ] }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_gic_capabilities {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct GicCap {
-    emulated: bool,
-    version: u32,
-    kernel: bool,
+#[serde(deny_unknown_fields)]
+pub struct trace_event_get_state {
+    pub name: String,
 }
 
-impl Command for query_gic_capabilities {
-    type Res = Vec<GicCap>;
+impl Command for trace_event_get_state {
+    type Res = Vec<TraceEventInfo>;
 
-    fn back(self) -> Vec<GicCap> {
+    fn back(self) -> Vec<TraceEventInfo> {
         Default::default()
     }
 }
 
-/// Query information of iothreads.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEventInfo {
+    pub name: String,
+    /// `enabled` or `disabled`.
+    pub state: String,
+}
+
+/// trace-event-set-state
 ///
-/// # Example
+/// Enable or disable trace events at runtime.
+///
+/// # Arguments
+///
+/// * `name` - Name of the event, `*` matches any sequence of characters.
+/// * `enable` - Whether to enable the events.
+///
+/// # Examples
 ///
 /// ```text
-/// -> { "execute": "query-iothreads" }
-/// <- {"return":[]}
+/// -> { "execute": "trace-event-set-state",
+///      "arguments": { "name": "virtio_*", "enable": true } }
+/// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct query_iothreads {}
-
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct IothreadInfo {
-    #[serde(rename = "poll-shrink")]
-    pub shrink: u32,
-    #[serde(rename = "thread-id")]
-    pub pid: u32,
-    #[serde(rename = "poll-grow")]
-    pub grow: u32,
-    #[serde(rename = "poll-max-ns")]
-    pub max: u32,
-    pub id: String,
+#[serde(deny_unknown_fields)]
+pub struct trace_event_set_state {
+    pub name: String,
+    pub enable: bool,
 }
 
-impl Command for query_iothreads {
-    type Res = Vec<IothreadInfo>;
+impl Command for trace_event_set_state {
+    type Res = Empty;
 
-    fn back(self) -> Vec<IothreadInfo> {
+    fn back(self) -> Empty {
         Default::default()
     }
 }
-/// input_event
+
+/// query-event-loops
+///
+/// Query health metrics of main loop and iothreads. All the durations are in
+/// nanoseconds.
 ///
 /// # Arguments
 ///
-/// * `key` - the input type such as 'keyboard' or 'pointer'.
-/// * `value` - the input value.
-
+/// * `reset` - Clear the metrics after they are queried, optional.
+///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "input_event",
-///      "arguments": { "key": "pointer", "value": "100,200,1" }}
-/// <- { "return": {} }
+/// -> { "execute": "query-event-loops" }
+/// <- { "return": [ { "id": "main", "iterations": 1024, "busy-ns": 52428800,
+///                    "iteration-p50-ns": 20479, "iteration-p99-ns": 2097151,
+///                    "iteration-max-ns": 8912345, "longest-callback-ns": 8901234,
+///                    "longest-callback-fd": 23, "slow-callbacks": 0,
+///                    "dispatches": [ { "fd": 23, "count": 998 } ] } ] }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct input_event {
-    pub key: String,
-    pub value: String,
+#[serde(deny_unknown_fields)]
+pub struct query_event_loops {
+    #[serde(default)]
+    pub reset: bool,
 }
 
-impl Command for input_event {
-    type Res = Vec<input_event>;
+impl Command for query_event_loops {
+    type Res = Vec<EventLoopInfo>;
 
-    fn back(self) -> Vec<input_event> {
+    fn back(self) -> Vec<EventLoopInfo> {
         Default::default()
     }
 }
 
-/// human-monitor-command
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct EventLoopInfo {
+    /// `main` for main loop, or id of iothread.
+    pub id: String,
+    pub iterations: u64,
+    #[serde(rename = "busy-ns")]
+    pub busy_ns: u64,
+    #[serde(rename = "iteration-p50-ns")]
+    pub iteration_p50_ns: u64,
+    #[serde(rename = "iteration-p99-ns")]
+    pub iteration_p99_ns: u64,
+    #[serde(rename = "iteration-max-ns")]
+    pub iteration_max_ns: u64,
+    #[serde(rename = "longest-callback-ns")]
+    pub longest_callback_ns: u64,
+    /// Not set if the longest callback is timer.
+    #[serde(
+        rename = "longest-callback-fd",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub longest_callback_fd: Option<i32>,
+    #[serde(rename = "slow-callbacks")]
+    pub slow_callbacks: u64,
+    pub dispatches: Vec<FdDispatchInfo>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct FdDispatchInfo {
+    pub fd: i32,
+    pub count: u64,
+}
+
+/// set-event-loop-budget
+///
+/// Set the budget of callbacks in main loop and iothreads. `EVENT_LOOP_STALL` is
+/// emitted when a callback exceeds the budget.
 ///
 /// # Arguments
 ///
-/// * `command_line` - the command line will be executed.
+/// * `budget-us` - Budget in microseconds, 0 disables the check.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "human-monitor-command",
-///      "arguments": { "command-line": "drive_add dummy
-///      file=/path/to/file,format=raw,if=none,id=drive-id" }}
+/// -> { "execute": "set-event-loop-budget", "arguments": { "budget-us": 100000 } }
 /// <- { "return": {} }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct human_monitor_command {
-    #[serde(rename = "command-line")]
-    pub command_line: String,
+pub struct set_event_loop_budget {
+    #[serde(rename = "budget-us")]
+    pub budget_us: u64,
 }
-pub type HumanMonitorCmdArgument = human_monitor_command;
 
-/// blockdev-snapshot-internal-sync
+impl Command for set_event_loop_budget {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// set-log
 ///
-/// Create disk internal snapshot.
+/// Set the log levels at runtime.
 ///
 /// # Arguments
 ///
-/// * `device` - the valid block device.
-/// * `name` - the snapshot name.
+/// * `filter` - Log filter in format of `[<level>][,<module>=<level>]...`, the
+///   modules not set use the default level.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "blockdev-snapshot-internal-sync",
-///      "arguments": { "device": "disk0",
-///                     "name": "snapshot1" }}
+/// -> { "execute": "set-log", "arguments": { "filter": "warn,virtio=debug" } }
 /// <- { "return": {} }
 /// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct set_log {
+    pub filter: String,
+}
+
+impl Command for set_log {
+    type Res = Empty;
+
+    fn back(self) -> Empty {
+        Default::default()
+    }
+}
+
+/// query-log
 ///
-/// blockdev-snapshot-delete-internal-sync
+/// Query the current log levels.
 ///
-/// Delete disk internal snapshot.
+/// # Examples
 ///
-/// # Arguments
+/// ```text
+/// -> { "execute": "query-log" }
+/// <- { "return": { "filter": "warn,virtio=debug" } }
+/// ```
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct query_log {}
+
+impl Command for query_log {
+    type Res = LogInfo;
+
+    fn back(self) -> LogInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct LogInfo {
+    pub filter: String,
+}
+
+/// query-boottime
 ///
-/// * `device` - the valid block device.
-/// * `name` - the snapshot name.
+/// Query the breakdown of boot time. All the times are in microseconds and relative to
+/// the start of StratoVirt.
 ///
 /// # Examples
 ///
 /// ```text
-/// -> { "execute": "blockdev-snapshot-delete-internal-sync",
-///      "arguments": { "device": "disk0",
-///                     "name": "snapshot1" }}
-/// <- { "return": {
-///                    "id": "1",
-///                    "name": "snapshot0",
-///                    "vm-state-size": 0,
-///                    "date-sec": 1000012,
-///                    "date-nsec": 10,
-///                    "vm-clock-sec": 100,
-///                    "vm-clock-nsec": 20,
-///                    "icount": 220414
-///  } }
+/// -> { "execute": "query-boottime" }
+/// <- { "return": { "stages": [ { "stage": "config-parse", "start-us": 3, "duration-us": 412 },
+///                              { "stage": "memory-setup", "start-us": 1630, "duration-us": 2305 },
+///                              { "stage": "device-realize", "id": "drive-0", "start-us": 4301,
+///                                "duration-us": 871 },
+///                              { "stage": "firmware-load", "start-us": 5502, "duration-us": 3120 } ],
+///                  "first-vcpu-run-us": 9874 } }
 /// ```
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
-pub struct blockdev_snapshot_internal {
-    pub device: String,
-    pub name: String,
+pub struct query_boottime {}
+
+impl Command for query_boottime {
+    type Res = BootTimeInfo;
+
+    fn back(self) -> BootTimeInfo {
+        Default::default()
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BootTimeInfo {
+    pub stages: Vec<BootStageInfo>,
+    /// Not reported before any vCPU runs.
+    #[serde(rename = "first-vcpu-run-us", skip_serializing_if = "Option::is_none")]
+    pub first_vcpu_run_us: Option<u64>,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct BootStageInfo {
+    pub stage: String,
+    /// Id of the device realized in `device-realize` stage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "start-us")]
+    pub start_us: u64,
+    #[serde(rename = "duration-us")]
+    pub duration_us: u64,
 }
-pub type BlockdevSnapshotInternalArgument = blockdev_snapshot_internal;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotInfo {
@@ -2945,6 +4585,181 @@ mod tests {
         assert!(err_msg.contains(part_msg));
     }
 
+    #[test]
+    fn test_qmp_input_send_event() {
+        let json_msg = r#"
+        {
+            "execute": "input-send-event" ,
+            "arguments": {
+                "events": [
+                    { "type": "key", "data": { "key": { "type": "qcode", "data": "a" }, "down": true } },
+                    { "type": "abs", "data": { "axis": "x", "value": 100 } },
+                    { "type": "btn", "data": { "button": "wheel-up", "down": true } }
+                ]
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::input_send_event { arguments, .. } => {
+                assert_eq!(arguments.events.len(), 3);
+                assert!(matches!(
+                    &arguments.events[0],
+                    InputEvent::Key(InputKeyEvent { key: KeyValue::Qcode(q), down: true }) if q == "a"
+                ));
+                assert!(matches!(
+                    arguments.events[2],
+                    InputEvent::Btn(InputBtnEvent {
+                        button: InputButton::WheelUp,
+                        down: true
+                    })
+                ));
+            }
+            _ => panic!("Wrong command"),
+        }
+
+        // unknown button
+        let json_msg = r#"
+        {
+            "execute": "input-send-event" ,
+            "arguments": {
+                "events": [ { "type": "btn", "data": { "button": "side", "down": true } } ]
+            }
+        }
+        "#;
+        assert!(serde_json::from_str::<QmpCommand>(json_msg).is_err());
+
+        let json_msg = r#"
+        {
+            "execute": "send-key" ,
+            "arguments": {
+                "keys": [ { "type": "qcode", "data": "ctrl" }, { "type": "number", "data": 211 } ],
+                "hold-time": 50
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::send_key { arguments, .. } => {
+                assert!(matches!(arguments.keys[1], KeyValue::Number(211)));
+                assert_eq!(arguments.hold_time, Some(50));
+            }
+            _ => panic!("Wrong command"),
+        }
+    }
+
+    #[test]
+    fn test_qmp_set_affinity() {
+        let json_msg = r#"
+        {
+            "execute": "set-vcpu-affinity" ,
+            "arguments": {
+                "vcpu": 1,
+                "cpus": [2, 3]
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("ok"));
+
+        let json_msg = r#"
+        {
+            "execute": "set-iothread-affinity" ,
+            "arguments": {
+                "id": "iothread1",
+                "cpus": [4]
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("ok"));
+
+        // missing cpus.
+        let json_msg = r#"
+        {
+            "execute": "set-vcpu-affinity" ,
+            "arguments": {
+                "vcpu": 1
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("missing field `cpus`"));
+    }
+
+    #[test]
+    fn test_qmp_vcpu_throttle() {
+        let json_msg = r#"
+        {
+            "execute": "vcpu-throttle" ,
+            "arguments": {
+                "percentage": 20
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("ok"));
+
+        // percentage out of u8 range.
+        let json_msg = r#"
+        {
+            "execute": "vcpu-throttle" ,
+            "arguments": {
+                "percentage": 256
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("invalid value"));
+    }
+
+    #[test]
+    fn test_qmp_set_power_supply() {
+        let json_msg = r#"
+        {
+            "execute": "set-power-supply" ,
+            "arguments": {
+                "battery-level": 30
+            }
+        }
+        "#;
+        match serde_json::from_str::<QmpCommand>(json_msg).unwrap() {
+            QmpCommand::set_power_supply { arguments, .. } => {
+                assert_eq!(arguments.ac_online, None);
+                assert_eq!(arguments.battery_level, Some(30));
+            }
+            _ => panic!("Unexpected qmp command"),
+        }
+
+        // Unknown argument.
+        let json_msg = r#"
+        {
+            "execute": "set-power-supply" ,
+            "arguments": {
+                "ac": true
+            }
+        }
+        "#;
+        let err_msg = match serde_json::from_str::<QmpCommand>(json_msg) {
+            Ok(_) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        assert!(err_msg.contains("unknown field"));
+    }
+
     #[test]
     fn test_qmp_human_monitor_command() {
         // Normal test.
@@ -13,12 +13,15 @@
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use log::{error, info, warn};
 use vmm_sys_util::epoll::EventSet;
 
+use super::qmp_audit::{audit_qmp_command, set_qmp_audit_state, QmpPeer};
 use super::qmp_schema;
 use super::qmp_schema::QmpCommand;
 use super::{qmp_channel::QmpChannel, qmp_response::QmpGreeting, qmp_response::Response};
@@ -28,12 +31,14 @@ use crate::machine::MachineExternalInterface;
 use crate::socket::SocketHandler;
 use crate::socket::SocketRWHandler;
 use crate::temp_cleaner::TempCleaner;
+use util::boot_time::{boot_records, first_vcpu_run};
 use util::leak_bucket::LeakBucket;
+use util::logger::{log_filter, set_log_filter};
 use util::loop_context::{
-    gen_delete_notifiers, read_fd, EventNotifier, EventNotifierHelper, NotifierCallback,
-    NotifierOperation,
+    gen_delete_notifiers, read_fd, set_callback_budget, EventNotifier, EventNotifierHelper,
+    NotifierCallback, NotifierOperation,
 };
-use util::set_termi_canon_mode;
+use util::{set_termi_canon_mode, trace};
 
 const LEAK_BUCKET_LIMIT: u64 = 100;
 
@@ -183,7 +188,7 @@ impl Socket {
         if self.is_connected() {
             let mut handler = self.get_socket_handler();
             let resp = if is_greeting {
-                serde_json::to_string(&QmpGreeting::create_greeting(1, 0, 5)).unwrap()
+                qmp_greeting()
             } else {
                 serde_json::to_string(&Response::create_empty_response()).unwrap()
             };
@@ -207,7 +212,7 @@ impl Socket {
         let leak_bucket_fd = leak_bucket.lock().unwrap().as_raw_fd();
 
         self.accept();
-        QmpChannel::bind_writer(SocketRWHandler::new(self.get_stream_fd()));
+        QmpChannel::bind_writer(Box::new(SocketRWHandler::new(self.get_stream_fd())));
         if let Err(e) = self.send_response(true) {
             error!("{:?}", e);
             QmpChannel::unbind();
@@ -339,6 +344,84 @@ macro_rules! create_command_matches {
     };
 }
 
+/// Whether the configuration of VM is locked by `-lock-config`.
+static CONFIG_LOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Lock the configuration of VM, the qmp commands which change it are rejected then.
+/// It can't be unlocked.
+pub fn lock_config() {
+    CONFIG_LOCKED.store(true, Ordering::SeqCst);
+}
+
+/// Whether the qmp command may change the configuration of VM, such as hotplug.
+/// Only the commands which are known to keep the configuration are allowed, so that
+/// new commands are rejected by default when the configuration is locked.
+fn is_config_mutation(qmp_command: &QmpCommand) -> bool {
+    !matches!(
+        qmp_command,
+        // Lifecycle of VM.
+        QmpCommand::qmp_capabilities { .. }
+            | QmpCommand::quit { .. }
+            | QmpCommand::stop { .. }
+            | QmpCommand::cont { .. }
+            | QmpCommand::system_powerdown { .. }
+            | QmpCommand::system_reset { .. }
+            | QmpCommand::migrate { .. }
+            | QmpCommand::cancel_migrate { .. }
+            // Input and output of guest.
+            | QmpCommand::ringbuf_read { .. }
+            | QmpCommand::ringbuf_write { .. }
+            | QmpCommand::guest_agent_command { .. }
+            | QmpCommand::input_event { .. }
+            | QmpCommand::input_send_event { .. }
+            | QmpCommand::send_key { .. }
+            | QmpCommand::dump_guest_memory { .. }
+            // Read-only queries.
+            | QmpCommand::query_hotpluggable_cpus { .. }
+            | QmpCommand::query_cpus { .. }
+            | QmpCommand::query_status { .. }
+            | QmpCommand::query_mem { .. }
+            | QmpCommand::query_balloon { .. }
+            | QmpCommand::query_power_supply { .. }
+            | QmpCommand::query_vnc { .. }
+            | QmpCommand::query_migrate { .. }
+            | QmpCommand::query_migrate_parameters { .. }
+            | QmpCommand::query_version { .. }
+            | QmpCommand::query_commands { .. }
+            | QmpCommand::query_target { .. }
+            | QmpCommand::query_kvm { .. }
+            | QmpCommand::query_machines { .. }
+            | QmpCommand::query_events { .. }
+            | QmpCommand::list_type { .. }
+            | QmpCommand::device_list_properties { .. }
+            | QmpCommand::query_tpm_models { .. }
+            | QmpCommand::query_tpm_types { .. }
+            | QmpCommand::query_command_line_options { .. }
+            | QmpCommand::query_migrate_capabilities { .. }
+            | QmpCommand::query_qmp_schema { .. }
+            | QmpCommand::query_sev_capabilities { .. }
+            | QmpCommand::query_sev { .. }
+            | QmpCommand::query_sev_attestation_report { .. }
+            | QmpCommand::query_chardev { .. }
+            | QmpCommand::qom_list { .. }
+            | QmpCommand::qom_get { .. }
+            | QmpCommand::query_block { .. }
+            | QmpCommand::query_named_block_nodes { .. }
+            | QmpCommand::query_blockstats { .. }
+            | QmpCommand::query_block_jobs { .. }
+            | QmpCommand::query_gic_capabilities { .. }
+            | QmpCommand::query_iothreads { .. }
+            | QmpCommand::query_vcpu_exit_stats { .. }
+            | QmpCommand::query_virtqueue_stats { .. }
+            | QmpCommand::query_vmm_memory { .. }
+            | QmpCommand::query_latency_histograms { .. }
+            | QmpCommand::trace_event_get_state { .. }
+            | QmpCommand::query_event_loops { .. }
+            | QmpCommand::query_log { .. }
+            | QmpCommand::query_boottime { .. }
+    )
+}
+
 /// Accept qmp command, analyze and exec it.
 ///
 /// # Arguments
@@ -375,21 +458,14 @@ fn handle_qmp(
         (Ok(buffer), if_fd) => {
             info!("QMP: --> {:?}", buffer);
             let qmp_command: QmpCommand = buffer.unwrap();
-            let (return_msg, shutdown_flag) = qmp_command_exec(qmp_command, controller, if_fd);
+            let (return_msg, shutdown_flag) =
+                qmp_command_exec(qmp_command, controller, if_fd, &|| QmpPeer::unix(stream_fd));
             info!("QMP: <-- {:?}", return_msg);
             qmp_service.send_str(&return_msg)?;
 
             // handle shutdown command
             if shutdown_flag {
-                let shutdown_msg = qmp_schema::Shutdown {
-                    guest: false,
-                    reason: "host-qmp-quit".to_string(),
-                };
-                event!(Shutdown; shutdown_msg);
-                TempCleaner::clean();
-                set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
-
-                std::process::exit(0);
+                qmp_quit();
             }
 
             Ok(())
@@ -405,16 +481,80 @@ fn handle_qmp(
     }
 }
 
+/// Exit the process for qmp command `quit`.
+pub fn qmp_quit() {
+    let shutdown_msg = qmp_schema::Shutdown {
+        guest: false,
+        reason: "host-qmp-quit".to_string(),
+    };
+    event!(Shutdown; shutdown_msg);
+    TempCleaner::clean();
+    set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
+
+    std::process::exit(0);
+}
+
+/// Get the greeting message sent to client when qmp connection is established.
+pub fn qmp_greeting() -> String {
+    serde_json::to_string(&QmpGreeting::create_greeting(1, 0, 5)).unwrap()
+}
+
+/// Handle a qmp command in json which isn't received from socket, so that no file
+/// descriptor is passed with it. Returns the response, and whether the command is `quit`.
+///
+/// # Arguments
+///
+/// * `json` - The qmp command in json.
+/// * `controller` - The controller which execute actual qmp command.
+/// * `peer` - Peer which sends the command, for audit log.
+pub fn handle_qmp_json(
+    json: &str,
+    controller: &Arc<Mutex<dyn MachineExternalInterface>>,
+    peer: &QmpPeer,
+) -> (String, bool) {
+    info!("QMP: --> {:?}", json);
+    let (resp, shutdown_flag) = match serde_json::from_str::<QmpCommand>(json) {
+        Ok(qmp_command) => qmp_command_exec(qmp_command, controller, None, &|| peer.clone()),
+        Err(e) => {
+            warn!("Qmp json parser made an error: {:?}", e);
+            let err_resp = qmp_schema::QmpErrorClass::GenericError(format!("{}", &e));
+            (
+                serde_json::to_string(&Response::create_error_response(err_resp, None)).unwrap(),
+                false,
+            )
+        }
+    };
+    info!("QMP: <-- {:?}", resp);
+    (resp, shutdown_flag)
+}
+
 /// Create a match , where `qmp_command` and its arguments matching by handle
 /// function, and exec this qmp command.
 fn qmp_command_exec(
     qmp_command: QmpCommand,
     controller: &Arc<Mutex<dyn MachineExternalInterface>>,
     if_fd: Option<RawFd>,
+    peer: &dyn Fn() -> QmpPeer,
 ) -> (String, bool) {
     let mut qmp_response = Response::create_empty_response();
     let mut shutdown_flag = false;
 
+    if CONFIG_LOCKED.load(Ordering::SeqCst) && is_config_mutation(&qmp_command) {
+        qmp_response = Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(
+                "Configuration of VM is locked by -lock-config".to_string(),
+            ),
+            None,
+        );
+        audit_qmp_command(peer, &qmp_command, &qmp_response);
+        // All the commands carry an optional id, which is echoed in the response.
+        let id = serde_json::to_value(&qmp_command)
+            .ok()
+            .and_then(|cmd| cmd.get("id")?.as_str().map(String::from));
+        qmp_response.change_id(id);
+        return (serde_json::to_string(&qmp_response).unwrap(), false);
+    }
+
     // Use macro create match to cover most Qmp command
     let mut id = create_command_matches!(
         qmp_command.clone(); controller.lock().unwrap(); qmp_response;
@@ -435,6 +575,7 @@ fn qmp_command_exec(
         (query_migrate_capabilities, query_migrate_capabilities),
         (query_qmp_schema, query_qmp_schema),
         (query_sev_capabilities, query_sev_capabilities),
+        (query_sev, query_sev),
         (query_chardev, query_chardev),
         (qom_list, qom_list),
         (qom_get, qom_get),
@@ -446,13 +587,22 @@ fn qmp_command_exec(
         (query_iothreads, query_iothreads),
         (query_migrate, query_migrate),
         (cancel_migrate, cancel_migrate),
+        (x_colo_lost_heartbeat, colo_lost_heartbeat),
+        (query_migrate_parameters, query_migrate_parameters),
         (query_cpus, query_cpus),
         (query_balloon, query_balloon),
+        (query_power_supply, query_power_supply),
         (query_mem, query_mem),
         (query_vnc, query_vnc),
         (list_type, list_type),
+        (query_vmm_memory, query_vmm_memory),
         (query_hotpluggable_cpus, query_hotpluggable_cpus);
         (input_event, input_event, key, value),
+        (input_send_event, input_send_event, events),
+        (send_key, send_key, keys, hold_time),
+        (set_vcpu_affinity, set_vcpu_affinity, vcpu, cpus),
+        (set_iothread_affinity, set_iothread_affinity, id, cpus),
+        (vcpu_throttle, vcpu_throttle, percentage),
         (device_list_properties, device_list_properties, typename),
         (device_del, device_del, id),
         (blockdev_del, blockdev_del, node_name),
@@ -460,13 +610,25 @@ fn qmp_command_exec(
         (chardev_remove, chardev_remove, id),
         (cameradev_del, cameradev_del,id),
         (balloon, balloon, value),
-        (migrate, migrate, uri);
+        (set_power_supply, set_power_supply, ac_online, battery_level),
+        (dump_guest_memory, dump_guest_memory, paging, protocol),
+        (display_resize, display_resize, output, width, height),
+        (ringbuf_read, ringbuf_read, device, size, format),
+        (query_sev_attestation_report, query_sev_attestation_report, mnonce),
+        (ringbuf_write, ringbuf_write, device, data, format),
+        (guest_agent_command, guest_agent_command, command, arguments, timeout),
+        (migrate, migrate, uri),
+        (migrate_set_capabilities, migrate_set_capabilities, capabilities);
         (device_add, device_add),
         (blockdev_add, blockdev_add),
         (netdev_add, netdev_add),
         (chardev_add, chardev_add),
         (cameradev_add, cameradev_add),
         (update_region, update_region),
+        (query_latency_histograms, query_latency_histograms),
+        (query_vcpu_exit_stats, query_vcpu_exit_stats),
+        (query_virtqueue_stats, query_virtqueue_stats),
+        (migrate_set_parameters, migrate_set_parameters),
         (human_monitor_command, human_monitor_command),
         (blockdev_snapshot_internal_sync, blockdev_snapshot_internal_sync),
         (blockdev_snapshot_delete_internal_sync, blockdev_snapshot_delete_internal_sync)
@@ -474,7 +636,7 @@ fn qmp_command_exec(
 
     // Handle the Qmp command which macro can't cover
     if id.is_none() {
-        id = match qmp_command {
+        id = match qmp_command.clone() {
             QmpCommand::quit { id, .. } => {
                 controller.lock().unwrap().destroy();
                 shutdown_flag = true;
@@ -484,10 +646,110 @@ fn qmp_command_exec(
                 qmp_response = controller.lock().unwrap().getfd(arguments.fd_name, if_fd);
                 id
             }
+            QmpCommand::qmp_audit_set_state { arguments, id } => {
+                if let Err(e) = set_qmp_audit_state(arguments.enable) {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::trace_event_get_state { arguments, id } => {
+                qmp_response = match trace::get_trace_event_state(&arguments.name) {
+                    Ok(states) => {
+                        let infos: Vec<qmp_schema::TraceEventInfo> = states
+                            .into_iter()
+                            .map(|(name, enabled)| qmp_schema::TraceEventInfo {
+                                name: name.to_string(),
+                                state: if enabled { "enabled" } else { "disabled" }.to_string(),
+                            })
+                            .collect();
+                        Response::create_response(serde_json::to_value(infos).unwrap(), None)
+                    }
+                    Err(e) => Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    ),
+                };
+                id
+            }
+            QmpCommand::trace_event_set_state { arguments, id } => {
+                if let Err(e) = trace::set_trace_event_state(&arguments.name, arguments.enable) {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::query_event_loops { arguments, id } => {
+                let infos: Vec<qmp_schema::EventLoopInfo> =
+                    EventLoop::query_metrics(arguments.reset)
+                        .into_iter()
+                        // Percentiles of latency are p50, p90, p99, p99.9 and p99.99.
+                        .map(|(id, metrics)| qmp_schema::EventLoopInfo {
+                            id,
+                            iterations: metrics.iterations,
+                            busy_ns: metrics.busy_time.as_nanos() as u64,
+                            iteration_p50_ns: metrics.iteration_latency.percentiles[0],
+                            iteration_p99_ns: metrics.iteration_latency.percentiles[2],
+                            iteration_max_ns: metrics.iteration_latency.max,
+                            longest_callback_ns: metrics.longest_callback.as_nanos() as u64,
+                            longest_callback_fd: metrics.longest_callback_fd,
+                            slow_callbacks: metrics.slow_callbacks,
+                            dispatches: metrics
+                                .dispatches
+                                .into_iter()
+                                .map(|(fd, count)| qmp_schema::FdDispatchInfo { fd, count })
+                                .collect(),
+                        })
+                        .collect();
+                qmp_response =
+                    Response::create_response(serde_json::to_value(infos).unwrap(), None);
+                id
+            }
+            QmpCommand::set_event_loop_budget { arguments, id } => {
+                set_callback_budget(Duration::from_micros(arguments.budget_us));
+                id
+            }
+            QmpCommand::set_log { arguments, id } => {
+                if let Err(e) = set_log_filter(&arguments.filter) {
+                    qmp_response = Response::create_error_response(
+                        qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                        None,
+                    );
+                }
+                id
+            }
+            QmpCommand::query_log { id, .. } => {
+                let info = qmp_schema::LogInfo {
+                    filter: log_filter(),
+                };
+                qmp_response = Response::create_response(serde_json::to_value(info).unwrap(), None);
+                id
+            }
+            QmpCommand::query_boottime { id, .. } => {
+                let info = qmp_schema::BootTimeInfo {
+                    stages: boot_records()
+                        .into_iter()
+                        .map(|record| qmp_schema::BootStageInfo {
+                            stage: record.stage.as_str().to_string(),
+                            id: record.id,
+                            start_us: record.start.as_micros() as u64,
+                            duration_us: record.duration.as_micros() as u64,
+                        })
+                        .collect(),
+                    first_vcpu_run_us: first_vcpu_run().map(|time| time.as_micros() as u64),
+                };
+                qmp_response = Response::create_response(serde_json::to_value(info).unwrap(), None);
+                id
+            }
             _ => None,
         }
     }
 
+    audit_qmp_command(peer, &qmp_command, &qmp_response);
     // Change response id with input qmp message
     qmp_response.change_id(id);
     (serde_json::to_string(&qmp_response).unwrap(), shutdown_flag)
@@ -496,7 +758,6 @@ fn qmp_command_exec(
 #[cfg(test)]
 mod tests {
     use std::os::unix::net::{UnixListener, UnixStream};
-    use std::time::Duration;
 
     use super::*;
     use serde_json;
@@ -562,7 +823,7 @@ mod tests {
         // Use event! macro to send event msg to client
         let socket = Socket::from_unix_listener(listener, None);
         socket.bind_unix_stream(server);
-        QmpChannel::bind_writer(SocketRWHandler::new(socket.get_stream_fd()));
+        QmpChannel::bind_writer(Box::new(SocketRWHandler::new(socket.get_stream_fd())));
 
         // 1.send no-content event
         event!(Stop);
@@ -634,4 +895,29 @@ mod tests {
         recover_unix_socket_environment("07");
         drop(socket);
     }
+
+    #[test]
+    fn test_is_config_mutation() {
+        let mutations = [
+            r#"{"execute":"device_add","arguments":{"id":"net-0","driver":"virtio-net-pci"}}"#,
+            r#"{"execute":"netdev_add","arguments":{"id":"net-0","ifname":"tap0"}}"#,
+            r#"{"execute":"balloon","arguments":{"value":536870912}}"#,
+            r#"{"execute":"device_del","arguments":{"id":"net-0"}}"#,
+            r#"{"execute":"migrate-set-capabilities","arguments":{"capabilities":[]}}"#,
+            r#"{"execute":"vcpu-throttle","arguments":{"percentage":10}}"#,
+        ];
+        for cmd in mutations {
+            let qmp_command: QmpCommand = serde_json::from_str(cmd).unwrap();
+            assert!(is_config_mutation(&qmp_command), "{}", cmd);
+        }
+        let others = [
+            r#"{"execute":"query-status"}"#,
+            r#"{"execute":"stop"}"#,
+            r#"{"execute":"query-balloon"}"#,
+        ];
+        for cmd in others {
+            let qmp_command: QmpCommand = serde_json::from_str(cmd).unwrap();
+            assert!(!is_config_mutation(&qmp_command), "{}", cmd);
+        }
+    }
 }
@@ -0,0 +1,579 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig, ServerConnection};
+use vmm_sys_util::epoll::EventSet;
+
+use super::qmp_audit::QmpPeer;
+use super::qmp_channel::QmpChannel;
+use super::qmp_socket::{handle_qmp_json, qmp_greeting, qmp_quit};
+use crate::config::TlsCredObjConfig;
+use crate::event_loop::EventLoop;
+use crate::machine::MachineExternalInterface;
+use util::loop_context::{
+    gen_delete_notifiers, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+const TLS_CREDS_CACERT: &str = "cacert.pem";
+const TLS_CREDS_SERVERCERT: &str = "servercert.pem";
+const TLS_CREDS_SERVERKEY: &str = "serverkey.pem";
+/// Max length of one qmp command.
+const QMP_TLS_MAX_LINE: usize = 64 * 1024;
+/// Size of plaintext read each time.
+const QMP_TLS_RECV_SIZE: usize = 4096;
+
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+const DER_OID: u8 = 0x06;
+/// Tag of the explicit version in `TBSCertificate`.
+const DER_CONTEXT_0: u8 = 0xa0;
+/// OID 2.5.4.3 of common name.
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Config of qmp over TLS on TCP socket, client certificates are always required.
+pub struct QmpTlsConfig {
+    pub listener: TcpListener,
+    pub server_config: Arc<ServerConfig>,
+    /// Common names of client certificates which are allowed, any is allowed if empty.
+    pub allowed_cn: Vec<String>,
+}
+
+/// Check that the tls-creds-x509 object can be used by qmp server, which always
+/// verifies client certificates.
+///
+/// # Arguments
+///
+/// * `creds` - Config of tls-creds-x509 object, `None` if not found.
+/// * `id` - Id of the object.
+pub fn check_qmp_tls_creds<'a>(
+    creds: Option<&'a TlsCredObjConfig>,
+    id: &str,
+) -> Result<&'a TlsCredObjConfig> {
+    let creds = creds.with_context(|| format!("No tls-creds object found: {}", id))?;
+    if creds.endpoint.as_deref() != Some("server") {
+        bail!("Endpoint of tls-creds {} for qmp should be server", id);
+    }
+    if !creds.verifypeer {
+        bail!("Verify-peer of tls-creds {} for qmp should be true", id);
+    }
+    Ok(creds)
+}
+
+/// Create TLS config of qmp server from x509 credentials, which are `cacert.pem`,
+/// `servercert.pem` and `serverkey.pem` in the directory.
+///
+/// # Arguments
+///
+/// * `creds` - Config of tls-creds-x509 object.
+pub fn make_qmp_tls_config(creds: &TlsCredObjConfig) -> Result<Arc<ServerConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&format!("{}/{}", creds.dir, TLS_CREDS_CACERT))? {
+        roots
+            .add(&cert)
+            .with_context(|| "Invalid CA certificate for qmp")?;
+    }
+    let certs = load_certs(&format!("{}/{}", creds.dir, TLS_CREDS_SERVERCERT))?;
+    let key = load_private_key(&format!("{}/{}", creds.dir, TLS_CREDS_SERVERKEY))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        .with_single_cert(certs, key)
+        .with_context(|| "Invalid server certificate or key for qmp")?;
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse certificates in {}", path))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path);
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &str) -> Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+    let mut reader = BufReader::new(file);
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .with_context(|| format!("Failed to parse private key in {}", path))?
+        {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => {}
+            None => bail!("No private key found in {}", path),
+        }
+    }
+}
+
+/// Read a DER element, returns its tag, content and the rest of data.
+fn der_read(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&first, mut data) = data.split_first()?;
+    let len = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let num = (first & 0x7f) as usize;
+        if num == 0 || num > 4 || data.len() < num {
+            return None;
+        }
+        let len = data[..num]
+            .iter()
+            .fold(0_usize, |len, &b| (len << 8) | b as usize);
+        data = &data[num..];
+        len
+    };
+    if data.len() < len {
+        return None;
+    }
+    Some((tag, &data[..len], &data[len..]))
+}
+
+/// Get the common name in subject of the DER encoded x509 certificate.
+fn x509_common_name(der: &[u8]) -> Option<String> {
+    let (tag, cert, _) = der_read(der)?;
+    if tag != DER_SEQUENCE {
+        return None;
+    }
+    let (tag, mut tbs, _) = der_read(cert)?;
+    if tag != DER_SEQUENCE {
+        return None;
+    }
+    if der_read(tbs)?.0 == DER_CONTEXT_0 {
+        tbs = der_read(tbs)?.2;
+    }
+    // Skip serial number, signature algorithm, issuer and validity.
+    for _ in 0..4 {
+        tbs = der_read(tbs)?.2;
+    }
+    let (tag, mut subject, _) = der_read(tbs)?;
+    if tag != DER_SEQUENCE {
+        return None;
+    }
+    while !subject.is_empty() {
+        let (tag, rdn, rest) = der_read(subject)?;
+        subject = rest;
+        if tag != DER_SET {
+            return None;
+        }
+        let (_, attr, _) = der_read(rdn)?;
+        let (tag, oid, value) = der_read(attr)?;
+        if tag == DER_OID && oid == OID_COMMON_NAME {
+            let (_, name, _) = der_read(value)?;
+            return Some(String::from_utf8_lossy(name).into_owned());
+        }
+    }
+    None
+}
+
+/// Check common name of the client certificate, which has been verified by the CA.
+/// Returns the common name if there is one.
+fn check_peer_cn(certs: Option<&[Certificate]>, allowed_cn: &[String]) -> Result<Option<String>> {
+    let cn = certs
+        .and_then(|certs| certs.first())
+        .and_then(|cert| x509_common_name(&cert.0));
+    if allowed_cn.is_empty() {
+        return Ok(cn);
+    }
+    let cn = cn.with_context(|| "No common name in client certificate")?;
+    if !allowed_cn.contains(&cn) {
+        bail!("Client {} is not allowed", cn);
+    }
+    Ok(Some(cn))
+}
+
+struct TlsSession {
+    stream: TcpStream,
+    conn: ServerConnection,
+    /// Whether EPOLLOUT of the stream is listened, for sending the pending TLS data.
+    out_listened: bool,
+}
+
+impl TlsSession {
+    /// Write TLS data to the stream until it would block. The pending data is kept in
+    /// the connection, and sent when the stream is writable again.
+    fn write_tls(&mut self) -> std::io::Result<()> {
+        while self.conn.wants_write() {
+            match self.conn.write_tls(&mut self.stream) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        self.update_out_event();
+        Ok(())
+    }
+
+    /// Listen EPOLLOUT of the stream only when there is pending TLS data.
+    fn update_out_event(&mut self) {
+        let wants_write = self.conn.wants_write();
+        if wants_write == self.out_listened {
+            return;
+        }
+        let mut event = EventSet::IN | EventSet::HANG_UP;
+        if wants_write {
+            event |= EventSet::OUT;
+        }
+        let notifier = EventNotifier::new(
+            NotifierOperation::Modify,
+            self.stream.as_raw_fd(),
+            None,
+            event,
+            Vec::new(),
+        );
+        match EventLoop::update_event(vec![notifier], None) {
+            Ok(()) => self.out_listened = wants_write,
+            Err(e) => error!("Failed to update event of qmp tls client: {:?}", e),
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.conn.writer().write_all(data)?;
+        self.write_tls()
+    }
+}
+
+/// Writer of qmp events to the TLS client.
+struct TlsEventWriter(Arc<Mutex<TlsSession>>);
+
+impl Write for TlsEventWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().send(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct QmpTlsClient {
+    session: Arc<Mutex<TlsSession>>,
+    /// Whether handshake is done and the client is allowed.
+    authorized: bool,
+    /// Whether the connection is closed. The stream is kept open until the next client
+    /// is accepted, so that its fd can be removed from event loop safely.
+    closed: bool,
+    /// Input which hasn't formed a line.
+    line: Vec<u8>,
+    /// Address and common name of the client, for audit log.
+    peer: QmpPeer,
+}
+
+/// Qmp monitor over TLS, which serves one client at a time like qmp socket.
+pub struct QmpTlsMonitor {
+    listener: TcpListener,
+    server_config: Arc<ServerConfig>,
+    allowed_cn: Vec<String>,
+    controller: Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>,
+    client: Option<QmpTlsClient>,
+}
+
+impl QmpTlsMonitor {
+    /// Create monitor on the TCP listener and register it to event loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - Config of qmp over TLS.
+    /// * `controller` - The VM which executes qmp commands.
+    pub fn realize(
+        cfg: QmpTlsConfig,
+        controller: Arc<Mutex<dyn MachineExternalInterface + Send + Sync>>,
+    ) -> Result<()> {
+        let monitor = Arc::new(Mutex::new(QmpTlsMonitor {
+            listener: cfg.listener,
+            server_config: cfg.server_config,
+            allowed_cn: cfg.allowed_cn,
+            controller,
+            client: None,
+        }));
+        EventLoop::update_event(EventNotifierHelper::internal_notifiers(monitor), None)
+            .with_context(|| "Failed to register qmp tls monitor to event loop")
+    }
+
+    fn accept(&mut self, monitor: Arc<Mutex<Self>>) -> Result<Vec<EventNotifier>> {
+        let (stream, addr) = self.listener.accept()?;
+        if self.client.as_ref().is_some_and(|client| !client.closed) {
+            warn!(
+                "Qmp tls client {} is refused, as another one is connected",
+                addr
+            );
+            return Ok(Vec::new());
+        }
+        stream.set_nonblocking(true)?;
+        let conn = ServerConnection::new(self.server_config.clone())
+            .with_context(|| "Failed to create tls connection")?;
+        info!("Qmp tls client {} is connected", addr);
+
+        let stream_fd = stream.as_raw_fd();
+        self.client = Some(QmpTlsClient {
+            session: Arc::new(Mutex::new(TlsSession {
+                stream,
+                conn,
+                out_listened: false,
+            })),
+            authorized: false,
+            closed: false,
+            line: Vec::new(),
+            peer: QmpPeer::tls(addr.to_string(), None),
+        });
+        let handler: Rc<NotifierCallback> = Rc::new(move |event, fd| {
+            let mut locked_monitor = monitor.lock().unwrap();
+            let mut closed = event & EventSet::HANG_UP == EventSet::HANG_UP;
+            if !closed && event & EventSet::OUT == EventSet::OUT {
+                if let Err(e) = locked_monitor.flush_output() {
+                    error!("Qmp tls client is disconnected: {:?}", e);
+                    closed = true;
+                }
+            }
+            if !closed && event & EventSet::IN == EventSet::IN {
+                closed = locked_monitor.handle_input().unwrap_or_else(|e| {
+                    error!("Qmp tls client is disconnected: {:?}", e);
+                    true
+                });
+            }
+            if closed {
+                locked_monitor.disconnect();
+                return Some(gen_delete_notifiers(&[fd]));
+            }
+            None
+        });
+        Ok(vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            stream_fd,
+            Some(self.listener.as_raw_fd()),
+            EventSet::IN | EventSet::HANG_UP,
+            vec![handler],
+        )])
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(client) = self.client.as_mut() {
+            if client.authorized {
+                QmpChannel::unbind();
+            }
+            client.closed = true;
+            let _ = client
+                .session
+                .lock()
+                .unwrap()
+                .stream
+                .shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Send the pending TLS data when the stream is writable.
+    fn flush_output(&mut self) -> Result<()> {
+        let client = self.client.as_ref().with_context(|| "No qmp tls client")?;
+        client.session.lock().unwrap().write_tls()?;
+        Ok(())
+    }
+
+    /// Handle input of the client, returns whether the connection is closed.
+    fn handle_input(&mut self) -> Result<bool> {
+        let client = self.client.as_mut().with_context(|| "No qmp tls client")?;
+        let session = client.session.clone();
+        let mut locked_session = session.lock().unwrap();
+        let TlsSession { stream, conn, .. } = &mut *locked_session;
+        match conn.read_tls(stream) {
+            Ok(0) => return Ok(true),
+            Ok(_) => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(anyhow!(e)),
+        }
+        let ret = conn.process_new_packets();
+        // Send alert to client if failed.
+        locked_session.write_tls()?;
+        ret.with_context(|| "Tls error")?;
+        if locked_session.conn.is_handshaking() {
+            return Ok(false);
+        }
+
+        if !client.authorized {
+            client.peer.cn =
+                check_peer_cn(locked_session.conn.peer_certificates(), &self.allowed_cn)?;
+            client.authorized = true;
+            locked_session.send(format!("{}\r\n", qmp_greeting()).as_bytes())?;
+            QmpChannel::bind_writer(Box::new(TlsEventWriter(session.clone())));
+        }
+
+        let mut closed = false;
+        let mut data = Vec::new();
+        let mut buf = [0_u8; QMP_TLS_RECV_SIZE];
+        loop {
+            match locked_session.conn.reader().read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(len) => data.extend_from_slice(&buf[..len]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(anyhow!(e)),
+            }
+        }
+        // Events may be sent when executing commands, which lock the session.
+        drop(locked_session);
+
+        for &ch in data.iter() {
+            if ch != b'\n' {
+                if client.line.len() >= QMP_TLS_MAX_LINE {
+                    bail!("Qmp command is too long");
+                }
+                client.line.push(ch);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&client.line).into_owned();
+            client.line.clear();
+            if line.trim().is_empty() {
+                continue;
+            }
+            let controller: Arc<Mutex<dyn MachineExternalInterface>> = self.controller.clone();
+            let (resp, quit) = handle_qmp_json(line.trim(), &controller, &client.peer);
+            session
+                .lock()
+                .unwrap()
+                .send(format!("{}\r\n", resp).as_bytes())?;
+            if quit {
+                qmp_quit();
+            }
+        }
+        Ok(closed)
+    }
+}
+
+impl EventNotifierHelper for QmpTlsMonitor {
+    fn internal_notifiers(monitor: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let cloned_monitor = monitor.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, _| {
+            let monitor = cloned_monitor.clone();
+            match cloned_monitor.lock().unwrap().accept(monitor) {
+                Ok(notifiers) => Some(notifiers),
+                Err(e) => {
+                    error!("Failed to accept qmp tls client: {:?}", e);
+                    None
+                }
+            }
+        });
+        let listener_fd: RawFd = monitor.lock().unwrap().listener.as_raw_fd();
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            listener_fd,
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut data = vec![tag];
+        if content.len() < 0x80 {
+            data.push(content.len() as u8);
+        } else {
+            data.extend_from_slice(&[0x82, (content.len() >> 8) as u8, content.len() as u8]);
+        }
+        data.extend_from_slice(content);
+        data
+    }
+
+    fn rdn(oid: &[u8], value: &[u8]) -> Vec<u8> {
+        let attr = [der(DER_OID, oid), der(0x0c, value)].concat();
+        der(DER_SET, &der(DER_SEQUENCE, &attr))
+    }
+
+    fn fake_cert(subject: &[u8], with_version: bool) -> Vec<u8> {
+        let mut tbs = Vec::new();
+        if with_version {
+            tbs.extend(der(DER_CONTEXT_0, &der(0x02, &[2])));
+        }
+        tbs.extend(der(0x02, &[1]));
+        tbs.extend(der(DER_SEQUENCE, &der(DER_OID, &[0x2a, 0x86, 0x48])));
+        tbs.extend(der(DER_SEQUENCE, &rdn(OID_COMMON_NAME, b"ca")));
+        tbs.extend(der(DER_SEQUENCE, &[0_u8; 200]));
+        tbs.extend(der(DER_SEQUENCE, subject));
+        tbs.extend(der(DER_SEQUENCE, &[0_u8; 16]));
+        let cert = [der(DER_SEQUENCE, &tbs), der(0x03, &[0_u8; 8])].concat();
+        der(DER_SEQUENCE, &cert)
+    }
+
+    #[test]
+    fn test_x509_common_name() {
+        let subject = [
+            rdn(&[0x55, 0x04, 0x06], b"CN"),
+            rdn(OID_COMMON_NAME, b"admin"),
+        ]
+        .concat();
+        let cert = fake_cert(&subject, true);
+        assert_eq!(x509_common_name(&cert), Some("admin".to_string()));
+        let cert = fake_cert(&subject, false);
+        assert_eq!(x509_common_name(&cert), Some("admin".to_string()));
+        let cert = fake_cert(&rdn(&[0x55, 0x04, 0x0a], b"org"), true);
+        assert_eq!(x509_common_name(&cert), None);
+        assert_eq!(x509_common_name(&cert[..cert.len() - 1]), None);
+        assert_eq!(x509_common_name(&[]), None);
+
+        let certs = [Certificate(fake_cert(&subject, true))];
+        let allowed = vec!["ops".to_string(), "admin".to_string()];
+        assert_eq!(
+            check_peer_cn(Some(&certs), &allowed).unwrap(),
+            Some("admin".to_string())
+        );
+        assert_eq!(
+            check_peer_cn(Some(&certs), &[]).unwrap(),
+            Some("admin".to_string())
+        );
+        assert!(check_peer_cn(Some(&certs), &["ops".to_string()]).is_err());
+        assert!(check_peer_cn(None, &allowed).is_err());
+        assert_eq!(check_peer_cn(None, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_qmp_tls_creds() {
+        let mut creds = TlsCredObjConfig {
+            id: "tls0".to_string(),
+            dir: "/etc/pki/qmp".to_string(),
+            cred_type: "x509".to_string(),
+            endpoint: Some("server".to_string()),
+            verifypeer: true,
+        };
+        assert!(check_qmp_tls_creds(Some(&creds), "tls0").is_ok());
+        assert!(check_qmp_tls_creds(None, "tls0").is_err());
+        creds.verifypeer = false;
+        assert!(check_qmp_tls_creds(Some(&creds), "tls0").is_err());
+        creds.verifypeer = true;
+        creds.endpoint = Some("client".to_string());
+        assert!(check_qmp_tls_creds(Some(&creds), "tls0").is_err());
+        creds.endpoint = None;
+        assert!(check_qmp_tls_creds(Some(&creds), "tls0").is_err());
+    }
+}
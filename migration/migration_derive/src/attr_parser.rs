@@ -29,6 +29,12 @@ const COMPAT_VERSION: &str = "compat_version";
 /// identification of field in a structure.
 const FIELD_ATTRIBUTE_NAME: &str = "alias";
 
+/// Attribute `field_version` is used above `field` declaration.
+/// for example: `#[field_version("0.2.0")]` or `#[field_version(2)]`.
+/// It gives the version since which the field has its current meaning, state
+/// of older version won't be copied to this field even if the alias matches.
+const FIELD_VERSION_NAME: &str = "field_version";
+
 /// Parse attribute above a struct.
 /// Version attribute with `current_version` or `compat_version` will be parsed to
 /// two `u32` number.
@@ -88,6 +94,26 @@ pub fn parse_field_attributes(attributes: &[syn::Attribute]) -> Option<String> {
     field_alias
 }
 
+/// Parse version attribute above fields.
+/// Version attribute with `field_version` will be parsed to a `u32` number,
+/// 0 is returned if it is not set.
+pub fn parse_field_version(attributes: &[syn::Attribute]) -> u32 {
+    let mut field_version = 0;
+
+    for attribute in attributes {
+        if attribute.path().is_ident(FIELD_VERSION_NAME) {
+            let lit: Lit = attribute.parse_args().unwrap();
+            field_version = match lit {
+                syn::Lit::Int(lit_int) => lit_int.base10_parse().unwrap(),
+                syn::Lit::Str(lit_str) => version_to_u32(&lit_str.value()),
+                _ => panic!("Unsupported version number."),
+            };
+        }
+    }
+
+    field_version
+}
+
 /// Check current version and compat version.
 ///
 /// # Check rules
@@ -162,4 +188,23 @@ mod test {
         assert_eq!(current_version, 1);
         assert_eq!(compat_version, 256);
     }
+
+    #[test]
+    fn test_parse_field_version() {
+        let input: ItemStruct = parse_quote! {
+            pub struct MyStruct {
+                #[alias(a)]
+                #[field_version("0.2.0")]
+                a: u16,
+                b: u32,
+            }
+        };
+
+        let versions: Vec<u32> = input
+            .fields
+            .iter()
+            .map(|field| parse_field_version(&field.attrs))
+            .collect();
+        assert_eq!(versions, vec![512, 0]);
+    }
 }
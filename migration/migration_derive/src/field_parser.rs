@@ -12,7 +12,7 @@
 
 use quote::{format_ident, quote};
 
-use super::attr_parser::parse_field_attributes;
+use super::attr_parser::{parse_field_attributes, parse_field_version};
 
 /// Parse fields in `DeviceState` structure to `TokenStream`.
 pub fn parse_fields(input: &syn::Fields, ident: &syn::Ident) -> Vec<proc_macro2::TokenStream> {
@@ -43,34 +43,38 @@ fn parse_field(
     let var_name = var_ident.to_string();
     let alias_name =
         parse_field_attributes(&input.value().attrs).unwrap_or_else(|| var_name.clone());
+    let version = parse_field_version(&input.value().attrs);
 
     // parse type of field
     let ty = input.value().ty.clone();
     let (ty_ident, len, is_array) = parse_ty(ty);
+    let elem_name = ty_ident.path.get_ident().unwrap().to_string();
     let type_name = if is_array {
-        format!("[{};{}]", ty_ident.path.get_ident().unwrap(), len)
+        quote! { format!("[{};{}]", #elem_name, #len) }
     } else {
-        ty_ident.path.get_ident().unwrap().to_string()
+        quote! { #elem_name.to_string() }
     };
 
     quote! {
         #struct_ident {
             var_name: #var_name.to_string(),
-            type_name: #type_name.to_string(),
+            type_name: #type_name,
             alias: #alias_name.to_string(),
             offset: util::offset_of!(#ident, #var_ident) as u32,
             size: (std::mem::size_of::<#ty_ident>() * #len) as u32,
+            version: #version,
         }
     }
 }
 
 // Parse syn::Type to TypePath and length of array.
-// Type parser only support path_type and array[path_type] now.
+// Type parser only support path_type and array[path_type] now, the length of array
+// can be an integer literal or a constant.
 //
 // # Output
 //
 // (path_type, length of array(if not an array, len will be 1), is_array)
-fn parse_ty(input: syn::Type) -> (syn::TypePath, usize, bool) {
+fn parse_ty(input: syn::Type) -> (syn::TypePath, proc_macro2::TokenStream, bool) {
     match input {
         syn::Type::Array(array) => {
             let array_type_token = match *array.elem.clone() {
@@ -80,16 +84,14 @@ fn parse_ty(input: syn::Type) -> (syn::TypePath, usize, bool) {
 
             match &array.len {
                 syn::Expr::Lit(expr_lit) => match &expr_lit.lit {
-                    syn::Lit::Int(lit_int) => {
-                        let array_len: usize = lit_int.base10_parse().unwrap();
-                        (array_type_token, array_len, true)
-                    }
+                    syn::Lit::Int(lit_int) => (array_type_token, quote! { #lit_int }, true),
                     _ => panic!("Unsupported array len literal."),
                 },
+                syn::Expr::Path(expr_path) => (array_type_token, quote! { #expr_path }, true),
                 _ => panic!("Unsupported array len."),
             }
         }
-        syn::Type::Path(token) => (token, 1, false),
+        syn::Type::Path(token) => (token, quote! { 1 }, false),
         _ => panic!("Unsupported field type {:?}", input),
     }
 }
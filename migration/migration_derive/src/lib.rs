@@ -14,9 +14,10 @@
 //!
 //! Exports two derives for migration flow:
 //! The `Desc` derive pro macro to generate the DeviceStateDesc structure for
-//! DeviceState struct.It also offers two attributes: one to describe version
-//! and compat version for structure, the other to give struct field an `alias`
-//! name.
+//! DeviceState struct.It also offers three attributes: one to describe version
+//! and compat version for structure, one to give struct field an `alias`
+//! name, and the other to give struct field a `field_version` since which the
+//! field keeps its current meaning.
 //!
 //! ```no_run
 //! #[macro_use]
@@ -37,6 +38,8 @@
 //!     acked_features_select: u32,
 //!     #[alias(status)]
 //!     device_status: u32,
+//!     #[field_version("0.2.0")]
+//!     config_generation: u8,
 //! }
 //!
 //! fn main() {
@@ -59,7 +62,7 @@ use quote::quote;
 use syn::{parse_macro_input, DeriveInput};
 
 /// Define a macro derive `Desc`.
-#[proc_macro_derive(Desc, attributes(desc_version, alias))]
+#[proc_macro_derive(Desc, attributes(desc_version, alias, field_version))]
 pub fn derive_desc(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let ident = input.ident.clone();
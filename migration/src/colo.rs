@@ -0,0 +1,853 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! COLO (COarse-grained LOck-stepping) fault tolerance.
+//!
+//! After live migration, the primary VM and the secondary VM keep running
+//! with the same input, incoming packets of primary VM are mirrored to
+//! secondary VM. Outgoing packets of primary VM are held until the same
+//! packets are sent by secondary VM, and packets of secondary VM are dropped.
+//! As long as they are the same, the secondary VM can take over the service.
+//! Once they diverge, or the comparison or checkpoint delay times out, the
+//! secondary VM is synchronized with the primary VM by a checkpoint, and the
+//! held packets are released. If one side is lost, the other side leaves COLO
+//! mode and runs alone.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::mem::{size_of, take};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::general::Lifecycle;
+use crate::manager::MIGRATION_MANAGER;
+use crate::migration::{decode_pages, Migratable};
+use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
+use crate::{MigrationError, MigrationManager};
+
+/// Interval of mirroring incoming packets and comparing outgoing packets of
+/// primary and secondary VM.
+pub const COLO_COMPARE_PERIOD: Duration = Duration::from_millis(10);
+/// Max time of outgoing packet of primary VM waiting for the packet of
+/// secondary VM, a checkpoint is done if it times out.
+const COLO_COMPARE_TIMEOUT: Duration = Duration::from_millis(3000);
+/// Default max interval between two checkpoints in milliseconds.
+const DEFAULT_CHECKPOINT_DELAY: u64 = 20_000;
+/// Max number of packets of each net device waiting to be compared.
+const MAX_PENDING_PACKETS: usize = 4096;
+
+/// Role of VM in COLO mode.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColoRole {
+    /// Not in COLO mode.
+    #[default]
+    None,
+    /// Primary VM, which provides service.
+    Primary,
+    /// Secondary VM, whose outgoing packets are dropped.
+    Secondary,
+}
+
+impl From<u8> for ColoRole {
+    fn from(role: u8) -> Self {
+        match role {
+            1 => ColoRole::Primary,
+            2 => ColoRole::Secondary,
+            _ => ColoRole::None,
+        }
+    }
+}
+
+impl From<ColoRole> for u8 {
+    fn from(role: ColoRole) -> Self {
+        match role {
+            ColoRole::None => 0,
+            ColoRole::Primary => 1,
+            ColoRole::Secondary => 2,
+        }
+    }
+}
+
+/// State of COLO mode.
+pub struct ColoState {
+    /// Role of VM.
+    pub role: ColoRole,
+    /// Max interval between two checkpoints in milliseconds.
+    pub checkpoint_delay: u64,
+    /// COLO state of net devices.
+    filters: Vec<Arc<ColoNetFilter>>,
+}
+
+impl Default for ColoState {
+    fn default() -> Self {
+        ColoState {
+            role: ColoRole::None,
+            checkpoint_delay: DEFAULT_CHECKPOINT_DELAY,
+            filters: Vec::new(),
+        }
+    }
+}
+
+/// Digest of packet, FNV-1a is used so that it is stable across hosts.
+///
+/// # Arguments
+///
+/// * `iovecs` - The data segments of packet.
+pub fn packet_digest(iovecs: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in iovecs.iter().flat_map(|iov| iov.iter()) {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash
+}
+
+/// Outgoing packet of primary VM waiting for comparison.
+struct HeldPacket {
+    digest: u64,
+    data: Vec<u8>,
+    time: Instant,
+}
+
+/// COLO state of a net device, which is shared by the data path of the device
+/// and the COLO thread. Packets of each device are compared independently.
+pub struct ColoNetFilter {
+    /// Id of the net device.
+    id: String,
+    /// Role of VM, which follows the role in `ColoState`.
+    role: AtomicU8,
+    /// Outgoing packets of primary VM, which are held until compared.
+    primary: Mutex<VecDeque<HeldPacket>>,
+    /// Digests of outgoing packets of secondary VM, which are not compared.
+    secondary: Mutex<VecDeque<u64>>,
+    /// Incoming packets of primary VM, which are not mirrored to secondary VM.
+    mirror: Mutex<Vec<Vec<u8>>>,
+    /// Outgoing packets of primary VM released by comparison, which are sent
+    /// to backend by the device.
+    released: Mutex<VecDeque<Vec<u8>>>,
+    /// Incoming packets mirrored from primary VM, which are received by the
+    /// guest of secondary VM.
+    injected: Mutex<VecDeque<Vec<u8>>>,
+    /// Notify the device that there are released or injected packets.
+    notify_evt: Arc<EventFd>,
+}
+
+impl ColoNetFilter {
+    /// Create COLO state of net device.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the net device, which is the same in both VMs.
+    pub fn new(id: &str) -> Result<Self> {
+        Ok(ColoNetFilter {
+            id: id.to_string(),
+            role: AtomicU8::new(ColoRole::None.into()),
+            primary: Mutex::new(VecDeque::new()),
+            secondary: Mutex::new(VecDeque::new()),
+            mirror: Mutex::new(Vec::new()),
+            released: Mutex::new(VecDeque::new()),
+            injected: Mutex::new(VecDeque::new()),
+            notify_evt: Arc::new(EventFd::new(libc::EFD_NONBLOCK)?),
+        })
+    }
+
+    /// Get the role of VM in COLO mode.
+    pub fn role(&self) -> ColoRole {
+        self.role.load(Ordering::Acquire).into()
+    }
+
+    fn set_role(&self, role: ColoRole) {
+        self.role.store(role.into(), Ordering::Release);
+    }
+
+    /// Eventfd which notifies the device to handle released or injected packets.
+    pub fn notify_evt(&self) -> &Arc<EventFd> {
+        &self.notify_evt
+    }
+
+    /// Filter outgoing packet of the device in COLO mode, packet of primary
+    /// VM is held for comparison and packet of secondary VM is dropped.
+    ///
+    /// Returns whether the packet should be sent to the backend now.
+    ///
+    /// # Arguments
+    ///
+    /// * `iovecs` - The data segments of packet.
+    pub fn filter_tx(&self, iovecs: &[&[u8]]) -> bool {
+        match self.role() {
+            ColoRole::None => true,
+            ColoRole::Primary => {
+                self.primary.lock().unwrap().push_back(HeldPacket {
+                    digest: packet_digest(iovecs),
+                    data: iovecs.concat(),
+                    time: Instant::now(),
+                });
+                false
+            }
+            ColoRole::Secondary => {
+                self.secondary
+                    .lock()
+                    .unwrap()
+                    .push_back(packet_digest(iovecs));
+                false
+            }
+        }
+    }
+
+    /// Record incoming packet of primary VM, which is mirrored to secondary VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - The packet received by guest, including the virtio net header.
+    pub fn mirror_rx(&self, packet: Vec<u8>) {
+        if self.role() == ColoRole::Primary {
+            self.mirror.lock().unwrap().push(packet);
+        }
+    }
+
+    /// Get the next released packet which is sent to the backend.
+    pub fn pop_released(&self) -> Option<Vec<u8>> {
+        self.released.lock().unwrap().pop_front()
+    }
+
+    /// Put back the released packet which the backend can't take now.
+    pub fn unpop_released(&self, packet: Vec<u8>) {
+        self.released.lock().unwrap().push_front(packet);
+    }
+
+    /// Get the next injected packet which is received by guest.
+    pub fn pop_injected(&self) -> Option<Vec<u8>> {
+        self.injected.lock().unwrap().pop_front()
+    }
+
+    /// Put back the injected packet which guest can't take now.
+    pub fn unpop_injected(&self, packet: Vec<u8>) {
+        self.injected.lock().unwrap().push_front(packet);
+    }
+
+    fn notify(&self) {
+        if let Err(e) = self.notify_evt.write(1) {
+            error!("Failed to notify net device {} in COLO: {:?}", self.id, e);
+        }
+    }
+
+    fn release(&self, packets: Vec<Vec<u8>>) {
+        if packets.is_empty() {
+            return;
+        }
+        self.released.lock().unwrap().extend(packets);
+        self.notify();
+    }
+
+    fn take_mirror(&self) -> Vec<Vec<u8>> {
+        take(&mut *self.mirror.lock().unwrap())
+    }
+
+    fn take_secondary(&self) -> Vec<u64> {
+        self.secondary.lock().unwrap().drain(..).collect()
+    }
+
+    fn inject(&self, packets: Vec<Vec<u8>>) {
+        if packets.is_empty() {
+            return;
+        }
+        self.injected.lock().unwrap().extend(packets);
+        self.notify();
+    }
+
+    /// Compare held packets of primary VM with packets of secondary VM in
+    /// order, the same packets are released. Packets not sent by the other VM
+    /// yet are kept for next comparison.
+    ///
+    /// Returns `false` if the packets diverge, too many packets are pending,
+    /// or packet of primary VM waits too long.
+    fn compare(&self, digests: &[u64]) -> bool {
+        let mut secondary = self.secondary.lock().unwrap();
+        secondary.extend(digests);
+        let mut primary = self.primary.lock().unwrap();
+        let mut released = Vec::new();
+        let mut consistent = true;
+        while let (Some(held), Some(digest)) = (primary.front(), secondary.front()) {
+            if held.digest != *digest {
+                consistent = false;
+                break;
+            }
+            released.push(primary.pop_front().unwrap().data);
+            secondary.pop_front();
+        }
+        if primary
+            .front()
+            .map_or(false, |held| held.time.elapsed() > COLO_COMPARE_TIMEOUT)
+        {
+            consistent = false;
+        }
+        consistent &=
+            primary.len() <= MAX_PENDING_PACKETS && secondary.len() <= MAX_PENDING_PACKETS;
+        drop(primary);
+        drop(secondary);
+        self.release(released);
+
+        consistent
+    }
+
+    /// Both VMs are the same after checkpoint, held packets of primary VM are
+    /// released and the others which are covered by the checkpoint are dropped.
+    fn checkpoint(&self) {
+        let held = take(&mut *self.primary.lock().unwrap());
+        self.release(held.into_iter().map(|held| held.data).collect());
+        self.secondary.lock().unwrap().clear();
+        self.mirror.lock().unwrap().clear();
+        self.injected.lock().unwrap().clear();
+    }
+}
+
+/// Encode packets of net devices as: length of id (u16), id, number of packets
+/// (u32), then length (u32) and data of each packet.
+fn encode_packets(devices: &[(String, Vec<Vec<u8>>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (id, packets) in devices {
+        data.extend((id.len() as u16).to_le_bytes());
+        data.extend(id.as_bytes());
+        data.extend((packets.len() as u32).to_le_bytes());
+        for packet in packets {
+            data.extend((packet.len() as u32).to_le_bytes());
+            data.extend(packet);
+        }
+    }
+    data
+}
+
+fn decode_packets(mut data: &[u8]) -> Result<Vec<(String, Vec<Vec<u8>>)>> {
+    fn split<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if data.len() < len {
+            bail!("Incomplete COLO packets");
+        }
+        let (head, tail) = data.split_at(len);
+        *data = tail;
+        Ok(head)
+    }
+
+    let mut devices = Vec::new();
+    while !data.is_empty() {
+        let len = u16::from_le_bytes(split(&mut data, 2)?.try_into().unwrap()) as usize;
+        let id = String::from_utf8(split(&mut data, len)?.to_vec())?;
+        let count = u32::from_le_bytes(split(&mut data, 4)?.try_into().unwrap());
+        let mut packets = Vec::new();
+        for _ in 0..count {
+            let len = u32::from_le_bytes(split(&mut data, 4)?.try_into().unwrap()) as usize;
+            packets.push(split(&mut data, len)?.to_vec());
+        }
+        devices.push((id, packets));
+    }
+    Ok(devices)
+}
+
+fn send_packets<T: Write>(fd: &mut T, devices: &[(String, Vec<Vec<u8>>)]) -> Result<()> {
+    let data = encode_packets(devices);
+    Request::send_msg(fd, TransStatus::ColoCompare, data.len() as u64)?;
+    fd.write_all(&data)?;
+
+    Ok(())
+}
+
+fn recv_packets<T: Read>(fd: &mut T) -> Result<Vec<(String, Vec<Vec<u8>>)>> {
+    let request = expect_request(fd, TransStatus::ColoCompare)?;
+    let mut data = vec![0_u8; request.length as usize];
+    fd.read_exact(&mut data)?;
+
+    decode_packets(&data)
+}
+
+fn recv_digests<T: Read>(fd: &mut T, len: u64) -> Result<Vec<u64>> {
+    let mut data = vec![0_u8; len as usize];
+    fd.read_exact(&mut data)?;
+
+    Ok(data
+        .chunks_exact(size_of::<u64>())
+        .map(|d| u64::from_le_bytes(d.try_into().unwrap()))
+        .collect())
+}
+
+fn send_blocks<T: Write>(fd: &mut T, blocks: &[MemBlock]) -> Result<()> {
+    let data: Vec<u8> = blocks
+        .iter()
+        .flat_map(|block| [block.gpa.to_le_bytes(), block.len.to_le_bytes()])
+        .flatten()
+        .collect();
+    Request::send_msg(fd, TransStatus::Memory, data.len() as u64)?;
+    fd.write_all(&data)?;
+
+    Ok(())
+}
+
+fn recv_blocks<T: Read>(fd: &mut T, len: u64) -> Result<Vec<MemBlock>> {
+    Ok(recv_digests(fd, len)?
+        .chunks_exact(2)
+        .map(|block| MemBlock {
+            gpa: block[0],
+            len: block[1],
+        })
+        .collect())
+}
+
+fn expect_request<T: Read>(fd: &mut T, status: TransStatus) -> Result<Request> {
+    let request = Request::recv_msg(fd)?;
+    if request.status != status {
+        return Err(anyhow!(MigrationError::MigrationStatusErr(
+            (request.status as u16).to_string(),
+            status.to_string(),
+        )));
+    }
+
+    Ok(request)
+}
+
+impl MigrationManager {
+    /// Get the role of VM in COLO mode.
+    pub fn colo_role() -> ColoRole {
+        MIGRATION_MANAGER.colo.lock().unwrap().role
+    }
+
+    /// Set the max interval between two checkpoints.
+    ///
+    /// # Arguments
+    ///
+    /// * `delay` - The interval in milliseconds.
+    pub fn set_colo_checkpoint_delay(delay: u64) {
+        MIGRATION_MANAGER.colo.lock().unwrap().checkpoint_delay = delay;
+    }
+
+    /// Get the max interval between two checkpoints in milliseconds.
+    pub fn colo_checkpoint_delay() -> u64 {
+        MIGRATION_MANAGER.colo.lock().unwrap().checkpoint_delay
+    }
+
+    /// Register COLO state of net device.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - COLO state of the net device.
+    pub fn register_colo_filter(filter: Arc<ColoNetFilter>) {
+        let mut colo = MIGRATION_MANAGER.colo.lock().unwrap();
+        filter.set_role(colo.role);
+        colo.filters.retain(|f| f.id != filter.id);
+        colo.filters.push(filter);
+    }
+
+    /// Unregister COLO state of net device.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Id of the net device.
+    pub fn unregister_colo_filter(id: &str) {
+        let mut colo = MIGRATION_MANAGER.colo.lock().unwrap();
+        colo.filters.retain(|f| f.id != id);
+    }
+
+    fn colo_filters() -> Vec<Arc<ColoNetFilter>> {
+        MIGRATION_MANAGER.colo.lock().unwrap().filters.clone()
+    }
+
+    fn set_colo_role(role: ColoRole) {
+        let mut colo = MIGRATION_MANAGER.colo.lock().unwrap();
+        colo.role = role;
+        for filter in colo.filters.iter() {
+            filter.set_role(role);
+        }
+    }
+
+    /// Leave COLO mode, VM runs alone after failover.
+    pub fn colo_failover() -> Result<()> {
+        let mut colo = MIGRATION_MANAGER.colo.lock().unwrap();
+        if colo.role == ColoRole::None {
+            bail!("VM is not in COLO mode");
+        }
+        info!("COLO failover, {:?} VM runs alone", colo.role);
+        colo.role = ColoRole::None;
+        for filter in colo.filters.iter() {
+            filter.set_role(ColoRole::None);
+            // Held packets of primary VM are released, as there is no other
+            // VM to compare with.
+            filter.checkpoint();
+        }
+        drop(colo);
+
+        Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+        if Self::status() == MigrationStatus::Colo {
+            Self::set_status(MigrationStatus::Completed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Ask destination VM to be secondary VM of COLO mode, it is done before
+    /// sending VM state so that the secondary VM never sends packets out.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    pub(crate) fn colo_setup<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        Request::send_msg(fd, TransStatus::Colo, 0)?;
+        let result = Response::recv_msg(fd)?;
+        if result.is_err() {
+            return Err(anyhow!(MigrationError::ResponseErr));
+        }
+
+        Ok(())
+    }
+
+    /// Be the secondary VM of COLO mode at destination VM.
+    pub(crate) fn colo_setup_secondary<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        let dirty_log_lock = MIGRATION_MANAGER.dirty_log_lock.lock().unwrap();
+        Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
+        drop(dirty_log_lock);
+        Self::set_colo_role(ColoRole::Secondary);
+        Response::send_msg(fd, TransStatus::Ok)?;
+
+        Ok(())
+    }
+
+    /// Run as primary VM of COLO mode after migration until failover.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    pub(crate) fn colo_primary<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        Self::set_status(MigrationStatus::Colo)?;
+        Self::set_colo_role(ColoRole::Primary);
+        Self::recover_from_migration()?;
+        info!("Enter COLO mode as primary VM");
+
+        let mut last_checkpoint = Instant::now();
+        loop {
+            thread::sleep(COLO_COMPARE_PERIOD);
+            if Self::colo_role() != ColoRole::Primary {
+                return Ok(());
+            }
+            if Self::is_canceled() {
+                Request::send_msg(fd, TransStatus::Cancel, 0)?;
+                break;
+            }
+
+            if let Err(e) = Self::colo_primary_round(fd, &mut last_checkpoint) {
+                warn!("Secondary VM is lost: {:?}", e);
+                break;
+            }
+        }
+
+        Self::colo_failover()
+    }
+
+    fn colo_primary_round<T>(fd: &mut T, last_checkpoint: &mut Instant) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        // Mirror incoming packets to secondary VM, which replies digests of its
+        // outgoing packets.
+        let filters = Self::colo_filters();
+        let mirror: Vec<(String, Vec<Vec<u8>>)> = filters
+            .iter()
+            .map(|filter| (filter.id.clone(), filter.take_mirror()))
+            .collect();
+        send_packets(fd, &mirror)?;
+        let mut consistent = true;
+        for (id, digests) in recv_packets(fd)? {
+            let filter = filters
+                .iter()
+                .find(|filter| filter.id == id)
+                .with_context(|| format!("Net device {} is not found in COLO", id))?;
+            let digests: Vec<u64> = digests
+                .iter()
+                .map(|d| d.as_slice().try_into().map(u64::from_le_bytes))
+                .collect::<std::result::Result<_, _>>()
+                .with_context(|| "Invalid digest of COLO packet")?;
+            consistent &= filter.compare(&digests);
+        }
+
+        let delay = Duration::from_millis(Self::colo_checkpoint_delay());
+        if consistent && last_checkpoint.elapsed() < delay {
+            return Ok(());
+        }
+
+        Self::pause()?;
+        let result = Self::send_checkpoint(fd);
+        for filter in filters.iter() {
+            filter.checkpoint();
+        }
+        Self::recover_from_migration()?;
+        *last_checkpoint = Instant::now();
+
+        result
+    }
+
+    /// Send memory dirtied by both VMs and VM state to secondary VM.
+    fn send_checkpoint<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        Request::send_msg(fd, TransStatus::Checkpoint, 0)?;
+
+        // Memory dirtied by secondary VM is also restored to primary one.
+        let request = expect_request(fd, TransStatus::Memory)?;
+        let mut blocks = recv_blocks(fd, request.length)?;
+        blocks.extend(Self::get_dirty_blocks()?);
+        Self::send_checkpoint_memory(fd, blocks)
+            .with_context(|| "Failed to send checkpoint memory")?;
+
+        let mut state = Vec::new();
+        Self::save_vmstate(None, &mut state)?;
+        Request::send_msg(fd, TransStatus::State, state.len() as u64)?;
+        fd.write_all(&state)?;
+        let result = Response::recv_msg(fd)?;
+        if result.is_err() {
+            return Err(anyhow!(MigrationError::ResponseErr));
+        }
+
+        Ok(())
+    }
+
+    /// Run as secondary VM of COLO mode after migration until failover.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    pub fn colo_secondary<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        Self::set_status(MigrationStatus::Colo)?;
+        info!("Enter COLO mode as secondary VM");
+
+        loop {
+            let result = Self::colo_secondary_round(fd);
+            if Self::colo_role() != ColoRole::Secondary {
+                return Ok(());
+            }
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    // Primary VM leaves COLO mode and keeps running alone.
+                    Self::colo_failover()?;
+                    Self::clear_migration()?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("Primary VM is lost: {:?}", e);
+                    return Self::colo_failover();
+                }
+            }
+        }
+    }
+
+    fn colo_secondary_round<T>(fd: &mut T) -> Result<bool>
+    where
+        T: Read + Write,
+    {
+        let request = Request::recv_msg(fd)?;
+        let filters = Self::colo_filters();
+        match request.status {
+            TransStatus::ColoCompare => {
+                let mut data = vec![0_u8; request.length as usize];
+                fd.read_exact(&mut data)?;
+                for (id, packets) in decode_packets(&data)? {
+                    match filters.iter().find(|filter| filter.id == id) {
+                        Some(filter) => filter.inject(packets),
+                        None => warn!("Net device {} is not found in COLO", id),
+                    }
+                }
+                let digests: Vec<(String, Vec<Vec<u8>>)> = filters
+                    .iter()
+                    .map(|filter| {
+                        let digests = filter.take_secondary();
+                        let packets = digests.iter().map(|d| d.to_le_bytes().to_vec());
+                        (filter.id.clone(), packets.collect())
+                    })
+                    .collect();
+                send_packets(fd, &digests)?;
+            }
+            TransStatus::Checkpoint => {
+                Self::pause()?;
+                let result = Self::recv_checkpoint(fd);
+                for filter in filters.iter() {
+                    filter.checkpoint();
+                }
+                Self::recover_from_migration()?;
+                result?;
+            }
+            TransStatus::Cancel => return Ok(false),
+            _ => {
+                return Err(anyhow!(MigrationError::MigrationStatusErr(
+                    (request.status as u16).to_string(),
+                    TransStatus::ColoCompare.to_string(),
+                )))
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Receive checkpoint from primary VM, the checkpoint is applied only
+    /// after it is received completely.
+    fn recv_checkpoint<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        send_blocks(fd, &Self::get_dirty_blocks()?)?;
+
+        let memory = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .memory
+            .clone()
+            .with_context(|| "No memory registered")?;
+        let request = expect_request(fd, TransStatus::Memory)?;
+        let blocks = recv_blocks(fd, request.length)?;
+        let mut pages = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            // Pages are sent without xbzrle, as this VM may have dirtied them.
+            let mut data = Vec::new();
+            decode_pages(fd, None, &block, &mut data)?;
+            pages.push((block, data));
+        }
+        Response::send_msg(fd, TransStatus::Ok)?;
+
+        let request = expect_request(fd, TransStatus::State)?;
+        let mut state = vec![0_u8; request.length as usize];
+        fd.read_exact(&mut state)?;
+
+        for (block, data) in pages {
+            memory.recv_memory(&mut data.as_slice(), block)?;
+        }
+        let mut state = state.as_slice();
+        let header = Self::restore_header(&mut state)?;
+        header.check_header()?;
+        let desc_db = Self::restore_desc_db(&mut state, header.desc_len)
+            .with_context(|| "Failed to load device descriptor db")?;
+        Self::restore_vmstate(desc_db, &mut state)
+            .with_context(|| "Failed to load checkpoint device")?;
+        Self::resume()?;
+        Response::send_msg(fd, TransStatus::Ok)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_compare() {
+        let packet_1 = packet_digest(&[b"stratovirt".as_slice()]);
+        let packet_2 = packet_digest(&[b"strato".as_slice(), b"virt".as_slice()]);
+        let packet_3 = packet_digest(&[b"colo".as_slice()]);
+        assert_eq!(packet_1, packet_2);
+        assert_ne!(packet_1, packet_3);
+
+        let filter = ColoNetFilter::new("net0").unwrap();
+        assert!(filter.filter_tx(&[b"stratovirt".as_slice()]));
+
+        // Packets of primary VM are held until the same packets are sent by
+        // secondary VM.
+        filter.set_role(ColoRole::Primary);
+        assert!(!filter.filter_tx(&[b"strato".as_slice(), b"virt".as_slice()]));
+        assert!(!filter.filter_tx(&[b"colo".as_slice()]));
+        assert!(filter.pop_released().is_none());
+        assert!(filter.compare(&[packet_1]));
+        assert_eq!(filter.pop_released().unwrap(), b"stratovirt".to_vec());
+        assert!(filter.pop_released().is_none());
+
+        // Packet not sent by primary VM is kept for next comparison.
+        assert!(filter.compare(&[packet_3, packet_3]));
+        assert_eq!(filter.pop_released().unwrap(), b"colo".to_vec());
+        assert!(!filter.filter_tx(&[b"stratovirt".as_slice()]));
+        assert!(!filter.compare(&[]));
+        assert!(filter.pop_released().is_none());
+
+        // Held packets are released by checkpoint.
+        filter.checkpoint();
+        assert_eq!(filter.pop_released().unwrap(), b"stratovirt".to_vec());
+        assert!(!filter.compare(&vec![packet_1; MAX_PENDING_PACKETS + 1]));
+
+        // Packets of secondary VM are dropped and their digests are compared.
+        filter.checkpoint();
+        filter.set_role(ColoRole::Secondary);
+        assert!(!filter.filter_tx(&[b"colo".as_slice()]));
+        assert_eq!(filter.take_secondary(), vec![packet_3]);
+    }
+
+    #[test]
+    fn test_packets_mirror() {
+        let filter = ColoNetFilter::new("net0").unwrap();
+        filter.mirror_rx(b"stratovirt".to_vec());
+        assert!(filter.take_mirror().is_empty());
+        filter.set_role(ColoRole::Primary);
+        filter.mirror_rx(b"stratovirt".to_vec());
+        filter.mirror_rx(b"colo".to_vec());
+
+        let devices = vec![
+            ("net0".to_string(), filter.take_mirror()),
+            ("net1".to_string(), Vec::new()),
+        ];
+        let mut data = Vec::new();
+        send_packets(&mut data, &devices).unwrap();
+        let recv = recv_packets(&mut data.as_slice()).unwrap();
+        assert_eq!(recv, devices);
+        assert!(decode_packets(&data[size_of::<Request>()..data.len() - 1]).is_err());
+
+        let filter = ColoNetFilter::new("net0").unwrap();
+        filter.set_role(ColoRole::Secondary);
+        filter.inject(recv[0].1.clone());
+        assert_eq!(filter.notify_evt().read().unwrap(), 1);
+        let packet = filter.pop_injected().unwrap();
+        assert_eq!(packet, b"stratovirt".to_vec());
+        filter.unpop_injected(packet);
+        filter.checkpoint();
+        assert!(filter.pop_injected().is_none());
+    }
+
+    #[test]
+    fn test_blocks_transfer() {
+        let blocks = vec![
+            MemBlock { gpa: 0, len: 4096 },
+            MemBlock {
+                gpa: 0x10_0000,
+                len: 8192,
+            },
+        ];
+        let mut data = Vec::new();
+        send_blocks(&mut data, &blocks).unwrap();
+
+        let mut data = data.as_slice();
+        let request = expect_request(&mut data, TransStatus::Memory).unwrap();
+        let recv = recv_blocks(&mut data, request.length).unwrap();
+        assert_eq!(recv.len(), 2);
+        assert_eq!(recv[1].gpa, 0x10_0000);
+        assert_eq!(recv[1].len, 8192);
+    }
+}
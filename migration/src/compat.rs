@@ -0,0 +1,372 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Compatibility check of source and destination VM before migration.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::MigrationError;
+use hypervisor::kvm::KVM_FDS;
+use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
+
+/// Names of host CPU feature words which are visible to guest.
+#[cfg(target_arch = "x86_64")]
+const CPU_FEATURE_WORDS: [&str; 7] = [
+    "CPUID.01H:ECX",
+    "CPUID.01H:EDX",
+    "CPUID.07H:EBX",
+    "CPUID.07H:ECX",
+    "CPUID.07H:EDX",
+    "CPUID.80000001H:ECX",
+    "CPUID.80000001H:EDX",
+];
+#[cfg(target_arch = "aarch64")]
+const CPU_FEATURE_WORDS: [&str; 2] = ["AT_HWCAP", "AT_HWCAP2"];
+
+/// Bits of `CPU_FEATURE_WORDS` which reflect the state set by host kernel rather than
+/// the capability of host CPU, and are not passed to guest as they are: APIC (CPUID.01H:EDX
+/// bit 9) follows IA32_APIC_BASE, OSXSAVE (CPUID.01H:ECX bit 27) follows CR4.OSXSAVE and
+/// OSPKE (CPUID.07H:ECX bit 4) follows CR4.PKE.
+#[cfg(target_arch = "x86_64")]
+const CPU_FEATURE_OS_BITS: [u64; 7] = [1 << 27, 1 << 9, 0, 1 << 4, 0, 0, 0];
+#[cfg(target_arch = "aarch64")]
+const CPU_FEATURE_OS_BITS: [u64; 2] = [0, 0];
+
+/// Get host CPU feature words, in the order of `CPU_FEATURE_WORDS`.
+#[cfg(target_arch = "x86_64")]
+fn cpu_features() -> Vec<u64> {
+    use core::arch::x86_64::__cpuid_count;
+
+    let (leaf_1, leaf_7, leaf_ext) = (
+        __cpuid_count(0x1, 0),
+        __cpuid_count(0x7, 0),
+        __cpuid_count(0x8000_0001, 0),
+    );
+    [
+        leaf_1.ecx,
+        leaf_1.edx,
+        leaf_7.ebx,
+        leaf_7.ecx,
+        leaf_7.edx,
+        leaf_ext.ecx,
+        leaf_ext.edx,
+    ]
+    .iter()
+    .map(|word| *word as u64)
+    .collect()
+}
+
+/// Get host CPU feature words, in the order of `CPU_FEATURE_WORDS`.
+#[cfg(target_arch = "aarch64")]
+fn cpu_features() -> Vec<u64> {
+    // SAFETY: getauxval has no memory argument.
+    unsafe {
+        vec![
+            libc::getauxval(libc::AT_HWCAP),
+            libc::getauxval(libc::AT_HWCAP2),
+        ]
+    }
+}
+
+/// Information exchanged before migration to check whether source and
+/// destination VM are compatible.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompatInfo {
+    /// VM configuration.
+    pub config: VmConfig,
+    /// Host CPU feature words.
+    pub cpu_features: Vec<u64>,
+    /// Guest physical address and size of memory slots, sorted by address.
+    pub mem_layout: Vec<(u64, u64)>,
+}
+
+impl CompatInfo {
+    /// Collect compatibility information of current VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration of current VM.
+    pub fn new(config: VmConfig) -> Self {
+        let mut mem_layout: Vec<(u64, u64)> = KVM_FDS
+            .load()
+            .get_mem_slots()
+            .lock()
+            .unwrap()
+            .values()
+            .map(|slot| (slot.guest_phys_addr, slot.memory_size))
+            .collect();
+        mem_layout.sort_unstable();
+
+        CompatInfo {
+            config,
+            cpu_features: cpu_features(),
+            mem_layout,
+        }
+    }
+
+    /// Check whether current VM can be the destination of `src`.
+    ///
+    /// Returns all the differences, empty if they are compatible.
+    ///
+    /// # Arguments
+    ///
+    /// * `src` - Compatibility information of source VM.
+    pub fn diff(&self, src: &CompatInfo) -> Vec<String> {
+        let mut diff = Vec::new();
+        let mut check = |item: &str, src: String, dest: String| {
+            if src != dest {
+                diff.push(
+                    MigrationError::MigrationConfigErr(item.to_string(), src, dest).to_string(),
+                );
+            }
+        };
+
+        let (src_machine, dest_machine) = (&src.config.machine_config, &self.config.machine_config);
+        check(
+            "machine type",
+            format!("{:?}", src_machine.mach_type),
+            format!("{:?}", dest_machine.mach_type),
+        );
+        check(
+            "vCPU number",
+            src_machine.nr_cpus.to_string(),
+            dest_machine.nr_cpus.to_string(),
+        );
+        check(
+            "vCPU topology",
+            format!(
+                "sockets={},dies={},clusters={},cores={},threads={},maxcpus={}",
+                src_machine.nr_sockets,
+                src_machine.nr_dies,
+                src_machine.nr_clusters,
+                src_machine.nr_cores,
+                src_machine.nr_threads,
+                src_machine.max_cpus
+            ),
+            format!(
+                "sockets={},dies={},clusters={},cores={},threads={},maxcpus={}",
+                dest_machine.nr_sockets,
+                dest_machine.nr_dies,
+                dest_machine.nr_clusters,
+                dest_machine.nr_cores,
+                dest_machine.nr_threads,
+                dest_machine.max_cpus
+            ),
+        );
+        check(
+            "pmu",
+            format!("{:?}", src_machine.cpu_config.pmu),
+            format!("{:?}", dest_machine.cpu_config.pmu),
+        );
+        check(
+            "tsc-frequency",
+            format!("{:?}", src_machine.cpu_config.tsc_frequency),
+            format!("{:?}", dest_machine.cpu_config.tsc_frequency),
+        );
+        check(
+            "invtsc",
+            src_machine.cpu_config.invtsc.to_string(),
+            dest_machine.cpu_config.invtsc.to_string(),
+        );
+        check(
+            "steal-time",
+            src_machine.cpu_config.steal_time.to_string(),
+            dest_machine.cpu_config.steal_time.to_string(),
+        );
+        check(
+            "memory size",
+            src_machine.mem_config.mem_size.to_string(),
+            dest_machine.mem_config.mem_size.to_string(),
+        );
+        check(
+            "memory layout",
+            format_layout(&src.mem_layout),
+            format_layout(&self.mem_layout),
+        );
+
+        // Guest may use all the features of source host.
+        for (index, name) in CPU_FEATURE_WORDS.iter().enumerate() {
+            let src_word = src.cpu_features.get(index).copied().unwrap_or(0);
+            let dest_word = self.cpu_features.get(index).copied().unwrap_or(0);
+            let missing = src_word & !dest_word & !CPU_FEATURE_OS_BITS[index];
+            if missing != 0 {
+                diff.push(format!(
+                    "Host CPU features {} 0x{:x} of source are missing in destination",
+                    name, missing
+                ));
+            }
+        }
+
+        diff.extend(diff_devices(&src.config, &self.config));
+        diff
+    }
+}
+
+fn format_layout(layout: &[(u64, u64)]) -> String {
+    layout
+        .iter()
+        .map(|(gpa, size)| format!("0x{:x}+0x{:x}", gpa, size))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Compare PCI devices by BDF, and other devices by type.
+fn diff_devices(src_config: &VmConfig, dest_config: &VmConfig) -> Vec<String> {
+    let split = |config: &VmConfig| {
+        let mut pci_devices = HashMap::<PciBdf, String>::new();
+        let mut other_devices = BTreeMap::<String, usize>::new();
+        for (dev_type, dev_info) in config.devices.iter() {
+            match get_pci_bdf(dev_info) {
+                Ok(bdf) => {
+                    pci_devices.insert(bdf, dev_type.to_string());
+                }
+                Err(_) => *other_devices.entry(dev_type.to_string()).or_default() += 1,
+            }
+        }
+        (pci_devices, other_devices)
+    };
+    let (src_pci, src_other) = split(src_config);
+    let (dest_pci, dest_other) = split(dest_config);
+
+    let mut diff = Vec::new();
+    for (bdf, src_type) in src_pci.iter() {
+        let dest_type = dest_pci.get(bdf).map_or("none", |t| t.as_str());
+        if src_type != dest_type {
+            diff.push(
+                MigrationError::MigrationConfigErr(
+                    format!("device {:?}", bdf),
+                    src_type.to_string(),
+                    dest_type.to_string(),
+                )
+                .to_string(),
+            );
+        }
+    }
+    for (bdf, dest_type) in dest_pci.iter() {
+        if !src_pci.contains_key(bdf) {
+            diff.push(
+                MigrationError::MigrationConfigErr(
+                    format!("device {:?}", bdf),
+                    "none".to_string(),
+                    dest_type.to_string(),
+                )
+                .to_string(),
+            );
+        }
+    }
+    if src_other != dest_other {
+        diff.push(
+            MigrationError::MigrationConfigErr(
+                "non-PCI devices".to_string(),
+                format!("{:?}", src_other),
+                format!("{:?}", dest_other),
+            )
+            .to_string(),
+        );
+    }
+    diff.sort();
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compat_info(nr_cpus: u8, devices: &[(&str, &str)]) -> CompatInfo {
+        let mut config = VmConfig::default();
+        config.machine_config.nr_cpus = nr_cpus;
+        config.devices = devices
+            .iter()
+            .map(|(t, i)| (t.to_string(), i.to_string()))
+            .collect();
+        CompatInfo {
+            config,
+            cpu_features: vec![0x3; CPU_FEATURE_WORDS.len()],
+            mem_layout: vec![(0, 0x8000_0000)],
+        }
+    }
+
+    #[test]
+    fn test_compat_diff() {
+        let devices = [
+            (
+                "virtio-blk-pci",
+                "virtio-blk-pci,id=blk0,bus=pcie.0,addr=0x2",
+            ),
+            (
+                "pcie-root-port",
+                "pcie-root-port,id=pcie.1,bus=pcie.0,addr=0x3",
+            ),
+            ("virtio-rng-device", "virtio-rng-device,rng=objrng0"),
+        ];
+        let src = compat_info(4, &devices);
+        let dest = compat_info(4, &devices);
+        assert!(dest.diff(&src).is_empty());
+
+        // Destination host may have more CPU features.
+        let mut dest = compat_info(4, &devices);
+        dest.cpu_features[0] = 0x7;
+        assert!(dest.diff(&src).is_empty());
+
+        // Bits set by host kernel are not compared.
+        let mut src_os = compat_info(4, &devices);
+        for (word, os_bits) in src_os.cpu_features.iter_mut().zip(CPU_FEATURE_OS_BITS) {
+            *word |= os_bits;
+        }
+        assert!(dest.diff(&src_os).is_empty());
+
+        let mut dest = compat_info(2, &devices[..2]);
+        dest.cpu_features[0] = 0x1;
+        dest.mem_layout.push((0x1_0000_0000, 0x4000_0000));
+        let diff = dest.diff(&src);
+        assert_eq!(diff.len(), 4);
+        assert!(diff[0].contains("vCPU number"));
+        assert!(diff[1].contains("memory layout"));
+        assert!(diff[2].contains("0x2 of source are missing"));
+        assert!(diff[3].contains("non-PCI devices"));
+
+        let mut dest = compat_info(4, &devices);
+        dest.config.machine_config.cpu_config.tsc_frequency = Some(2_500_000_000);
+        let diff = dest.diff(&src);
+        assert_eq!(diff.len(), 1);
+        assert!(diff[0].contains("tsc-frequency"));
+
+        let dest = compat_info(
+            4,
+            &[
+                (
+                    "virtio-net-pci",
+                    "virtio-net-pci,id=net0,bus=pcie.0,addr=0x2",
+                ),
+                devices[2],
+                (
+                    "virtio-balloon-pci",
+                    "virtio-balloon-pci,id=bln0,bus=pcie.0,addr=0x4",
+                ),
+            ],
+        );
+        let diff = dest.diff(&src);
+        assert_eq!(diff.len(), 3);
+        assert!(diff
+            .iter()
+            .any(|d| d.contains("source virtio-blk-pci, destination virtio-net-pci")));
+        assert!(diff
+            .iter()
+            .any(|d| d.contains("source pcie-root-port, destination none")));
+        assert!(diff
+            .iter()
+            .any(|d| d.contains("source none, destination virtio-balloon-pci")));
+    }
+}
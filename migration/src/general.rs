@@ -154,10 +154,14 @@ impl MigrationManager {
     ///
     /// * fd - The `Read` trait object.
     /// * snap_desc_db - snap_desc_db - snapshot state descriptor.
+    ///
+    /// # Output
+    ///
+    /// (state data, instance id, device migration version of snapshot)
     pub fn check_vm_state(
         fd: &mut dyn Read,
         desc_db: &HashMap<u64, DeviceStateDesc>,
-    ) -> Result<(Vec<u8>, u64)> {
+    ) -> Result<(Vec<u8>, u64, u32)> {
         let mut instance = Instance::default();
         fd.read_exact(unsafe {
             std::slice::from_raw_parts_mut(
@@ -194,7 +198,7 @@ impl MigrationManager {
             }
         }
 
-        Ok((state_data, instance.name))
+        Ok((state_data, instance.name, snap_desc.current_version))
     }
 
     /// Get `Device`'s alias from device type string.
@@ -253,6 +257,26 @@ pub trait Lifecycle {
             locked_vm.lock().unwrap().pause();
         }
 
+        Self::pause_devices()
+    }
+
+    /// Stop devices which access guest memory out of vCPUs.
+    fn pause_devices() -> Result<()> {
+        let locked_devices = &MIGRATION_MANAGER.vmm.read().unwrap().devices;
+        for (_, device) in locked_devices.iter() {
+            device.lock().unwrap().pause()?;
+        }
+
+        Ok(())
+    }
+
+    /// Restart devices stopped by `pause_devices`.
+    fn restart_devices() -> Result<()> {
+        let locked_devices = &MIGRATION_MANAGER.vmm.read().unwrap().devices;
+        for (_, device) in locked_devices.iter() {
+            device.lock().unwrap().restart()?;
+        }
+
         Ok(())
     }
 
@@ -14,12 +14,16 @@
 //!
 //! Offer snapshot and migration interface for VM.
 
+pub mod colo;
+pub mod compat;
 pub mod error;
 pub mod general;
 pub mod manager;
 pub mod migration;
 pub mod protocol;
 pub mod snapshot;
+pub mod transport;
+pub mod xbzrle;
 
 pub use anyhow::Result;
 
@@ -27,12 +31,17 @@ pub use error::MigrationError;
 pub use manager::{MigrationHook, MigrationManager};
 pub use protocol::{DeviceStateDesc, FieldDesc, MemBlock, MigrationStatus, StateTransfer};
 
+use std::io::{Read, Write};
 use std::time::Duration;
 use std::{net::TcpStream, os::unix::net::UnixStream, thread};
 
+use anyhow::Context;
 use log::error;
 
-use machine_manager::qmp::{qmp_response::Response, qmp_schema};
+use colo::ColoRole;
+use machine_manager::qmp::{qmp_channel::QmpChannel, qmp_response::Response, qmp_schema};
+use transport::{fd_stream, ExecMode, ExecStream};
+use util::seccomp::{apply_thread_filter, spawn_class_thread, ThreadClass};
 
 /// Start to snapshot VM.
 ///
@@ -40,6 +49,10 @@ use machine_manager::qmp::{qmp_response::Response, qmp_schema};
 ///
 /// * `path` - snapshot dir path. If path dir not exists, will create it.
 pub fn snapshot(path: String) -> Response {
+    if MigrationManager::background_snapshot() {
+        return background_snapshot(path);
+    }
+
     if let Err(e) = MigrationManager::save_snapshot(&path) {
         error!("Failed to migrate to path \'{:?}\': {:?}", path, e);
         let _ = MigrationManager::set_status(MigrationStatus::Failed);
@@ -52,13 +65,40 @@ pub fn snapshot(path: String) -> Response {
     Response::create_empty_response()
 }
 
+/// Start to snapshot VM in background, VM keeps running during snapshot.
+///
+/// # Arguments
+///
+/// * `path` - snapshot dir path. If path dir not exists, will create it.
+fn background_snapshot(path: String) -> Response {
+    if let Err(e) = spawn_class_thread(
+        thread::Builder::new().name("background_snapshot".to_string()),
+        move || {
+            if let Err(e) = apply_thread_filter(ThreadClass::Migration)
+                .and_then(|_| MigrationManager::save_background_snapshot(&path))
+            {
+                error!("Failed to snapshot to path \'{:?}\': {:?}", path, e);
+                let _ = MigrationManager::set_status(MigrationStatus::Failed)
+                    .map_err(|e| error!("{:?}", e));
+            }
+        },
+    ) {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    Response::create_empty_response()
+}
+
 /// Start to migrate VM with unix mode.
 ///
 /// # Arguments
 ///
 /// * `path` - Unix socket path, as /tmp/migration.socket.
 pub fn migration_unix_mode(path: String) -> Response {
-    let mut socket = match UnixStream::connect(path) {
+    let socket = match UnixStream::connect(path) {
         Ok(_sock) => {
             // Specify the tcp receiving or send timeout.
             let time_out = Some(Duration::from_secs(30));
@@ -78,24 +118,7 @@ pub fn migration_unix_mode(path: String) -> Response {
         }
     };
 
-    if let Err(e) = thread::Builder::new()
-        .name("unix_migrate".to_string())
-        .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
-                error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
-            }
-        })
-    {
-        return Response::create_error_response(
-            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
-            None,
-        );
-    }
-
-    Response::create_empty_response()
+    spawn_send_migration(socket, "unix_migrate")
 }
 
 /// Start to migrate VM with tcp mode.
@@ -104,7 +127,7 @@ pub fn migration_unix_mode(path: String) -> Response {
 ///
 /// * `path` - Tcp ip and port, as 192.168.1.1:4446.
 pub fn migration_tcp_mode(path: String) -> Response {
-    let mut socket = match TcpStream::connect(path) {
+    let socket = match TcpStream::connect(path) {
         Ok(_sock) => {
             // Specify the tcp receiving or send timeout.
             let time_out = Some(Duration::from_secs(30));
@@ -124,22 +147,127 @@ pub fn migration_tcp_mode(path: String) -> Response {
         }
     };
 
-    if let Err(e) = thread::Builder::new()
-        .name("tcp_migrate".to_string())
+    spawn_send_migration(socket, "tcp_migrate")
+}
+
+/// Start to migrate VM with exec mode.
+///
+/// # Arguments
+///
+/// * `cmd` - Shell command which relays the migration stream, as
+///   `ssh host nc -U /tmp/migration.socket`.
+/// * `one_way` - The command only takes the migration stream, as `gzip -c > /path/to/file`.
+pub fn migration_exec_mode(cmd: String, one_way: bool) -> Response {
+    let mode = if one_way {
+        // Checkpoints of COLO can't be done without the replies of secondary VM.
+        if MigrationManager::colo() {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(
+                    "COLO is not supported by one-way exec migration".to_string(),
+                ),
+                None,
+            );
+        }
+        ExecMode::OneWaySend
+    } else {
+        ExecMode::Duplex
+    };
+    let stream = match ExecStream::spawn(&cmd, mode) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            )
+        }
+    };
+
+    spawn_send_migration(stream, "exec_migrate")
+}
+
+/// Start to migrate VM with fd mode.
+///
+/// # Arguments
+///
+/// * `fd_name` - Name of the fd passed by QMP command `getfd`.
+pub fn migration_fd_mode(fd_name: String) -> Response {
+    let stream = match QmpChannel::get_fd(&fd_name).map(fd_stream) {
+        Some(Ok(stream)) => stream,
+        Some(Err(e)) => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("{:?}", e)),
+                None,
+            )
+        }
+        None => {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(format!("No fd named {} found", fd_name)),
+                None,
+            )
+        }
+    };
+
+    spawn_send_migration(stream, "fd_migrate")
+}
+
+/// Send migration through `stream` in a new thread named `name`.
+fn spawn_send_migration<T>(mut stream: T, name: &str) -> Response
+where
+    T: Read + Write + Send + 'static,
+{
+    if let Err(e) = spawn_class_thread(thread::Builder::new().name(name.to_string()), move || {
+        if let Err(e) = apply_thread_filter(ThreadClass::Migration)
+            .and_then(|_| MigrationManager::send_migration(&mut stream))
+        {
+            error!("Failed to send migration: {:?}", e);
+            let _ = MigrationManager::recover_from_migration();
+            let _ = MigrationManager::set_status(MigrationStatus::Failed)
+                .map_err(|e| error!("{:?}", e));
+        }
+    }) {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+            None,
+        );
+    }
+
+    Response::create_empty_response()
+}
+
+/// Run as secondary VM of COLO mode in a new thread, if it is requested by
+/// source VM during migration.
+///
+/// # Arguments
+///
+/// * `stream` - The migration stream connected to source VM.
+pub fn start_colo_secondary<T>(mut stream: T) -> Result<()>
+where
+    T: Read + Write + Send + 'static,
+{
+    if MigrationManager::colo_role() != ColoRole::Secondary {
+        return Ok(());
+    }
+
+    thread::Builder::new()
+        .name("colo_secondary".to_string())
         .spawn(move || {
-            if let Err(e) = MigrationManager::send_migration(&mut socket) {
-                error!("Failed to send migration: {:?}", e);
-                let _ = MigrationManager::recover_from_migration();
-                let _ = MigrationManager::set_status(MigrationStatus::Failed)
-                    .map_err(|e| error!("{:?}", e));
+            if let Err(e) = MigrationManager::colo_secondary(&mut stream) {
+                error!("Failed to run as COLO secondary VM: {:?}", e);
             }
         })
-    {
+        .with_context(|| "Failed to create COLO secondary thread")?;
+
+    Ok(())
+}
+
+/// Leave COLO mode, VM runs alone after that.
+pub fn colo_lost_heartbeat() -> Response {
+    if let Err(e) = MigrationManager::colo_failover() {
         return Response::create_error_response(
             qmp_schema::QmpErrorClass::GenericError(e.to_string()),
             None,
         );
-    };
+    }
 
     Response::create_empty_response()
 }
@@ -165,3 +293,78 @@ pub fn cancel_migrate() -> Response {
 
     Response::create_empty_response()
 }
+
+/// Set the parameters of migration.
+///
+/// # Arguments
+///
+/// * `args` - The parameters to be set, unset parameter keeps unchanged.
+pub fn set_migrate_parameters(args: qmp_schema::MigrateSetParametersArgument) -> Response {
+    if MigrationManager::is_active() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(
+                "Can't set migration parameters during migration".to_string(),
+            ),
+            None,
+        );
+    }
+
+    MigrationManager::set_migration_limit(args.max_bandwidth, args.downtime_limit);
+    if let Some(size) = args.xbzrle_cache_size {
+        MigrationManager::set_xbzrle_cache_size(size);
+    }
+    if let Some(delay) = args.x_checkpoint_delay {
+        MigrationManager::set_colo_checkpoint_delay(delay);
+    }
+    Response::create_empty_response()
+}
+
+/// Set the capabilities of migration.
+///
+/// # Arguments
+///
+/// * `capabilities` - The capabilities and their states.
+pub fn set_migrate_capabilities(capabilities: Vec<qmp_schema::MigrateCapabilities>) -> Response {
+    if MigrationManager::is_active() {
+        return Response::create_error_response(
+            qmp_schema::QmpErrorClass::GenericError(
+                "Can't set migration capabilities during migration".to_string(),
+            ),
+            None,
+        );
+    }
+
+    for capability in capabilities {
+        if let Err(e) = MigrationManager::set_capability(&capability.capability, capability.state) {
+            return Response::create_error_response(
+                qmp_schema::QmpErrorClass::GenericError(e.to_string()),
+                None,
+            );
+        }
+    }
+
+    Response::create_empty_response()
+}
+
+/// Query the capabilities of migration.
+pub fn query_migrate_capabilities() -> Response {
+    let capabilities: Vec<qmp_schema::MigrateCapabilities> = MigrationManager::capabilities()
+        .into_iter()
+        .map(|(capability, state)| qmp_schema::MigrateCapabilities { state, capability })
+        .collect();
+
+    Response::create_response(serde_json::to_value(capabilities).unwrap(), None)
+}
+
+/// Query the parameters of migration.
+pub fn query_migrate_parameters() -> Response {
+    let (max_bandwidth, downtime_limit) = MigrationManager::migration_limit();
+    let parameters = qmp_schema::MigrationParameters {
+        max_bandwidth,
+        downtime_limit,
+        xbzrle_cache_size: MigrationManager::xbzrle_cache_size(),
+        x_checkpoint_delay: MigrationManager::colo_checkpoint_delay(),
+    };
+
+    Response::create_response(serde_json::to_value(parameters).unwrap(), None)
+}
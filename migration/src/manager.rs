@@ -14,16 +14,19 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::hash::Hash;
 use std::io::{Read, Write};
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::Instant;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::info;
 use once_cell::sync::Lazy;
 
+use crate::colo::ColoState;
 use crate::general::translate_id;
-use crate::migration::DirtyBitmap;
+use crate::migration::{DirtyBitmap, DirtyRingHarvest};
 use crate::protocol::{DeviceStateDesc, MemBlock, MigrationStatus, StateTransfer};
+use crate::xbzrle::XbzrleCache;
 use machine_manager::config::VmConfig;
 use machine_manager::machine::MachineLifecycle;
 use util::byte_code::ByteCode;
@@ -34,7 +37,13 @@ pub(crate) static MIGRATION_MANAGER: Lazy<MigrationManager> = Lazy::new(|| Migra
     desc_db: Arc::new(RwLock::new(HashMap::<String, DeviceStateDesc>::new())),
     status: Arc::new(RwLock::new(MigrationStatus::None)),
     vmm_bitmaps: Arc::new(RwLock::new(HashMap::new())),
+    dirty_log_active: Arc::new(AtomicBool::new(false)),
+    dirty_log_lock: Arc::new(Mutex::new(())),
     limit: Arc::new(RwLock::new(MigrationLimit::default())),
+    xbzrle_cache: Arc::new(Mutex::new(XbzrleCache::default())),
+    capabilities: Arc::new(RwLock::new(MigrationCapabilities::default())),
+    colo: Arc::new(Mutex::new(ColoState::default())),
+    dirty_ring_harvest: Arc::new(Mutex::new(None)),
 });
 
 /// A hook for `Device` to save device state to `Write` object and load device
@@ -99,6 +108,17 @@ pub trait MigrationHook: StateTransfer {
         Ok(())
     }
 
+    /// Save memory state without memory data to `Write` trait, and return the
+    /// host address ranges `(hva, len)` of memory data, which should follow the
+    /// state in order to be the same as `save_memory`.
+    ///
+    /// # Arguments
+    ///
+    /// * _fd - The `Write` trait object to save memory state.
+    fn save_memory_state(&self, _fd: &mut dyn Write) -> Result<Vec<(u64, u64)>> {
+        Ok(Vec::new())
+    }
+
     /// Restore memory state from memory.
     ///
     /// # Arguments
@@ -138,6 +158,39 @@ pub trait MigrationHook: StateTransfer {
     fn resume(&mut self) -> Result<()> {
         Ok(())
     }
+
+    /// Stop the device from accessing guest memory after VM is paused.
+    ///
+    /// # Notes
+    ///
+    /// For device which accesses guest memory out of vCPUs, such as vhost-device,
+    /// it should be stopped before the last dirty memory is sent and its state
+    /// is saved.
+    fn pause(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Restart the device stopped by `pause` if VM keeps running.
+    fn restart(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Start logging the guest memory written by device out of vCPUs.
+    fn start_dirty_log(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Stop logging the guest memory written by device out of vCPUs.
+    fn stop_dirty_log(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Collect and clear the dirty log of device.
+    ///
+    /// # Arguments
+    ///
+    /// * `_mark` - Called with guest address and length of each dirty memory.
+    fn sync_dirty_log(&self, _mark: &mut dyn FnMut(u64, u64)) {}
 }
 
 /// The instance represents a single object in VM.
@@ -181,24 +234,47 @@ pub struct Vmm {
 
 /// Limit of migration.
 pub struct MigrationLimit {
-    /// Start time of each iteration.
-    pub iteration_start_time: Instant,
-    /// Virtual machine downtime.
+    /// Max tolerated virtual machine downtime in milliseconds.
     pub limit_downtime: u64,
     /// Max number of iterations during iteratively sending dirty memory.
     pub max_dirty_iterations: u16,
+    /// Max bandwidth of sending memory in bytes per second, 0 means unlimited.
+    pub max_bandwidth: u64,
+    /// Start time of current bandwidth limiting window.
+    pub window_start_time: Instant,
+    /// Bytes sent in current bandwidth limiting window.
+    pub window_bytes: u64,
+    /// Measured transfer rate of the latest memory sending in bytes per second.
+    pub transfer_rate: u64,
 }
 
 impl Default for MigrationLimit {
     fn default() -> Self {
         Self {
-            iteration_start_time: Instant::now(),
             limit_downtime: 50,
             max_dirty_iterations: 30,
+            max_bandwidth: 0,
+            window_start_time: Instant::now(),
+            window_bytes: 0,
+            transfer_rate: 0,
         }
     }
 }
 
+/// Capabilities of migration which can be switched by user.
+#[derive(Default)]
+pub struct MigrationCapabilities {
+    /// Save snapshot while VM keeps running.
+    pub background_snapshot: bool,
+    /// Enter COLO fault tolerance mode after migration.
+    pub colo: bool,
+}
+
+/// Name of background snapshot capability.
+const CAPABILITY_BACKGROUND_SNAPSHOT: &str = "background-snapshot";
+/// Name of COLO capability.
+const CAPABILITY_COLO: &str = "x-colo";
+
 /// This structure is to manage all resource during migration.
 /// It is also the only way to call on `MIGRATION_MANAGER`.
 pub struct MigrationManager {
@@ -210,8 +286,22 @@ pub struct MigrationManager {
     pub status: Arc<RwLock<MigrationStatus>>,
     /// vmm dirty bitmaps.
     pub vmm_bitmaps: Arc<RwLock<HashMap<u32, DirtyBitmap>>>,
+    /// Whether vmm dirty bitmaps are tracking, it is checked without lock by
+    /// every write to guest memory.
+    pub dirty_log_active: Arc<AtomicBool>,
+    /// Held while dirty log is being started, and during sampling of dirty rate so
+    /// that the sampling never stops the dirty log of migration.
+    pub dirty_log_lock: Arc<Mutex<()>>,
     /// Limiting elements of migration.
     pub limit: Arc<RwLock<MigrationLimit>>,
+    /// Cache of sent pages for xbzrle encoding.
+    pub xbzrle_cache: Arc<Mutex<XbzrleCache>>,
+    /// Capabilities of migration.
+    pub capabilities: Arc<RwLock<MigrationCapabilities>>,
+    /// State of COLO fault tolerance mode.
+    pub colo: Arc<Mutex<ColoState>>,
+    /// Threads harvesting dirty rings of vCPUs.
+    pub dirty_ring_harvest: Arc<Mutex<Option<DirtyRingHarvest>>>,
 }
 
 impl MigrationManager {
@@ -359,6 +449,85 @@ impl MigrationManager {
         locked_vmm.gic_group.insert(translate_id(id), gic);
     }
 
+    /// Set the max bandwidth and max tolerated downtime of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bandwidth` - Max bandwidth in bytes per second, 0 means unlimited.
+    /// * `limit_downtime` - Max tolerated downtime in milliseconds.
+    pub fn set_migration_limit(max_bandwidth: Option<u64>, limit_downtime: Option<u64>) {
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        if let Some(bandwidth) = max_bandwidth {
+            limit.max_bandwidth = bandwidth;
+        }
+        if let Some(downtime) = limit_downtime {
+            limit.limit_downtime = downtime;
+        }
+    }
+
+    /// Get the max bandwidth and max tolerated downtime of migration.
+    pub fn migration_limit() -> (u64, u64) {
+        let limit = MIGRATION_MANAGER.limit.read().unwrap();
+        (limit.max_bandwidth, limit.limit_downtime)
+    }
+
+    /// Set the size of xbzrle cache, 0 means xbzrle encoding is disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of cache in bytes.
+    pub fn set_xbzrle_cache_size(size: u64) {
+        *MIGRATION_MANAGER.xbzrle_cache.lock().unwrap() = XbzrleCache::new(size);
+    }
+
+    /// Get the size of xbzrle cache.
+    pub fn xbzrle_cache_size() -> u64 {
+        MIGRATION_MANAGER.xbzrle_cache.lock().unwrap().size()
+    }
+
+    /// Switch the capability of migration.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of capability.
+    /// * `state` - Enable or disable the capability.
+    pub fn set_capability(name: &str, state: bool) -> Result<()> {
+        let mut capabilities = MIGRATION_MANAGER.capabilities.write().unwrap();
+        match name {
+            CAPABILITY_BACKGROUND_SNAPSHOT => capabilities.background_snapshot = state,
+            CAPABILITY_COLO => capabilities.colo = state,
+            _ => bail!("Unsupported migration capability {}", name),
+        }
+
+        Ok(())
+    }
+
+    /// Whether snapshot is saved in background.
+    pub fn background_snapshot() -> bool {
+        MIGRATION_MANAGER
+            .capabilities
+            .read()
+            .unwrap()
+            .background_snapshot
+    }
+
+    /// Whether to enter COLO mode after migration.
+    pub fn colo() -> bool {
+        MIGRATION_MANAGER.capabilities.read().unwrap().colo
+    }
+
+    /// Get all capabilities of migration with their states.
+    pub fn capabilities() -> Vec<(String, bool)> {
+        let capabilities = MIGRATION_MANAGER.capabilities.read().unwrap();
+        vec![
+            (
+                CAPABILITY_BACKGROUND_SNAPSHOT.to_string(),
+                capabilities.background_snapshot,
+            ),
+            (CAPABILITY_COLO.to_string(), capabilities.colo),
+        ]
+    }
+
     /// Unregister transport instance from vmm.
     ///
     /// # Arguments
@@ -371,6 +540,18 @@ impl MigrationManager {
         locked_vmm.transports.remove(&translate_id(&name));
     }
 
+    /// Unregister cpu instance from vmm.
+    ///
+    /// # Arguments
+    ///
+    /// * `cpu_desc` - The `DeviceStateDesc` of cpu instance.
+    /// * `id` - The id of cpu.
+    pub fn unregister_cpu_instance(cpu_desc: DeviceStateDesc, id: u8) {
+        let name = cpu_desc.name + "/" + &id.to_string();
+        let mut locked_vmm = MIGRATION_MANAGER.vmm.write().unwrap();
+        locked_vmm.cpus.remove(&translate_id(&name));
+    }
+
     /// Unregister device instance from vmm.
     ///
     /// # Arguments
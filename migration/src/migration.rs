@@ -11,23 +11,39 @@
 // See the Mulan PSL v2 for more details.
 
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::mem::size_of;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use kvm_bindings::kvm_userspace_memory_region as MemorySlot;
 use log::{info, warn};
 
+use crate::compat::CompatInfo;
 use crate::general::Lifecycle;
 use crate::manager::MIGRATION_MANAGER;
 use crate::protocol::{MemBlock, MigrationStatus, Request, Response, TransStatus};
-use crate::{MigrationError, MigrationManager};
-use hypervisor::kvm::KVM_FDS;
-use machine_manager::config::{get_pci_bdf, PciBdf, VmConfig};
+use crate::xbzrle::{self, XbzrleCache, PAGE_SIZE};
+use crate::{MigrationError, MigrationHook, MigrationManager};
+use hypervisor::kvm::{DirtyRing, KVM_FDS};
 use util::unix::host_page_size;
 
+/// Max length of memory block sent at a time.
+const MEM_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+/// Time window of limiting the bandwidth of sending memory.
+const BANDWIDTH_WINDOW: Duration = Duration::from_millis(100);
+/// Memory page is sent as raw data.
+const PAGE_RAW: u8 = 0;
+/// Memory page is all zero, no data is sent.
+const PAGE_ZERO: u8 = 1;
+/// Memory page is sent as xbzrle delta against the page sent before.
+const PAGE_XBZRLE: u8 = 2;
+/// Interval of harvesting dirty ring of each vCPU.
+const DIRTY_RING_HARVEST_INTERVAL: Duration = Duration::from_millis(50);
+
 impl MigrationManager {
     /// Start VM live migration at source VM.
     ///
@@ -43,16 +59,27 @@ impl MigrationManager {
         // Activate the migration status of source and destination virtual machine.
         Self::active_migration(fd).with_context(|| "Failed to active migration")?;
 
+        // Reset the transfer statistics of last migration.
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        limit.window_start_time = Instant::now();
+        limit.window_bytes = 0;
+        limit.transfer_rate = 0;
+        drop(limit);
+        MIGRATION_MANAGER.xbzrle_cache.lock().unwrap().clear();
+
         // Send source virtual machine configuration.
         Self::send_vm_config(fd).with_context(|| "Failed to send vm config")?;
 
         // Start logging dirty pages.
+        let dirty_log_lock = MIGRATION_MANAGER.dirty_log_lock.lock().unwrap();
         Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
+        drop(dirty_log_lock);
 
         // Send all memory of virtual machine itself to destination.
         Self::send_vm_memory(fd).with_context(|| "Failed to send VM memory")?;
 
         // Iteratively send virtual machine dirty memory.
+        let mut remaining_blocks = Vec::new();
         let iterations = MIGRATION_MANAGER.limit.read().unwrap().max_dirty_iterations;
         for _ in 0..iterations {
             // Check the migration is active.
@@ -60,7 +87,8 @@ impl MigrationManager {
                 break;
             }
 
-            if !Self::iteration_send(fd)? {
+            if let Some(blocks) = Self::iteration_send(fd)? {
+                remaining_blocks = blocks;
                 break;
             }
         }
@@ -76,10 +104,21 @@ impl MigrationManager {
         Self::pause()?;
 
         // Send remaining virtual machine dirty memory.
+        if !remaining_blocks.is_empty() {
+            Self::send_memory(fd, remaining_blocks)
+                .with_context(|| "Failed to send remaining dirty memory")?;
+        }
         Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
 
-        // Stop logging dirty pages.
-        Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+        // Dirty pages keep being logged for checkpoints in COLO mode.
+        let colo = Self::colo();
+        if colo {
+            Self::colo_setup(fd).with_context(|| "Failed to setup COLO")?;
+        } else {
+            // Stop logging dirty pages.
+            Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+        }
+        MIGRATION_MANAGER.xbzrle_cache.lock().unwrap().clear();
 
         // Get virtual machine state and send it to destination VM.
         Self::send_vmstate(fd).with_context(|| "Failed to send vm state")?;
@@ -87,6 +126,11 @@ impl MigrationManager {
         // Complete the migration.
         Self::complete_migration(fd).with_context(|| "Failed to completing migration")?;
 
+        if colo {
+            // Keep running as primary VM.
+            return Self::colo_primary(fd);
+        }
+
         // Destroy virtual machine.
         Self::clear_migration().with_context(|| "Failed to clear migration")?;
 
@@ -139,6 +183,10 @@ impl MigrationManager {
                     info!("Receive Memory status");
                     Self::recv_vm_memory(fd, request.length)?;
                 }
+                TransStatus::Colo => {
+                    info!("Receive Colo status");
+                    Self::colo_setup_secondary(fd)?;
+                }
                 TransStatus::State => {
                     info!("Receive State status");
                     Self::recv_vmstate(fd)?;
@@ -160,12 +208,13 @@ impl MigrationManager {
         Ok(())
     }
 
-    /// Send Vm configuration from source virtual machine.
+    /// Send Vm configuration and host information from source virtual machine,
+    /// fail with the differences if destination is not compatible.
     fn send_vm_config<T>(fd: &mut T) -> Result<()>
     where
         T: Write + Read,
     {
-        let vm_config = &MIGRATION_MANAGER
+        let vm_config = MIGRATION_MANAGER
             .vmm
             .read()
             .unwrap()
@@ -173,19 +222,66 @@ impl MigrationManager {
             .lock()
             .unwrap()
             .clone();
-        let config_data = serde_json::to_vec(vm_config)?;
+        let config_data = serde_json::to_vec(&CompatInfo::new(vm_config))?;
         Request::send_msg(fd, TransStatus::VmConfig, config_data.len() as u64)?;
         fd.write_all(&config_data)?;
 
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
+        if Self::recv_response(fd)?.is_some_and(|result| result.is_err()) {
+            let request = Request::recv_msg(fd)?;
+            let mut diff = vec![0_u8; request.length as usize];
+            fd.read_exact(&mut diff)?;
+            bail!(
+                "Destination VM is not compatible:\n{}",
+                String::from_utf8_lossy(&diff)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Receive the response of destination VM, `None` if the migration stream
+    /// doesn't relay responses, as a one-way exec command.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` trait object.
+    fn recv_response<T>(fd: &mut T) -> Result<Option<Response>>
+    where
+        T: Read,
+    {
+        match Response::recv_msg(fd) {
+            Ok(response) => Ok(Some(response)),
+            Err(e)
+                if e.chain().any(|cause| {
+                    cause
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|e| e.kind() == ErrorKind::Unsupported)
+                }) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fail if destination VM responds with error.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` trait object.
+    fn check_response<T>(fd: &mut T) -> Result<()>
+    where
+        T: Read,
+    {
+        if Self::recv_response(fd)?.is_some_and(|result| result.is_err()) {
             return Err(anyhow!(MigrationError::ResponseErr));
         }
 
         Ok(())
     }
 
-    /// Check source and destination virtual machine config.
+    /// Check source and destination virtual machine config, all the
+    /// differences are sent back to source if they are not compatible.
     fn check_vm_config<T>(fd: &mut T, len: u64) -> Result<()>
     where
         T: Write + Read,
@@ -194,8 +290,8 @@ impl MigrationManager {
         data.resize_with(len as usize, Default::default);
         fd.read_exact(&mut data)?;
 
-        let src_config: &VmConfig = &serde_json::from_slice(&data)?;
-        let dest_config: &VmConfig = &MIGRATION_MANAGER
+        let src_info: CompatInfo = serde_json::from_slice(&data)?;
+        let dest_config = MIGRATION_MANAGER
             .vmm
             .read()
             .unwrap()
@@ -203,110 +299,92 @@ impl MigrationManager {
             .lock()
             .unwrap()
             .clone();
-        // Check vCPU number.
-        Self::check_vcpu(src_config, dest_config)?;
-        Self::check_memory(src_config, dest_config)?;
-        Self::check_devices(src_config, dest_config)?;
+        let diff = CompatInfo::new(dest_config).diff(&src_info).join("\n");
+        if !diff.is_empty() {
+            Response::send_msg(fd, TransStatus::Error)?;
+            Request::send_msg(fd, TransStatus::VmConfig, diff.len() as u64)?;
+            fd.write_all(diff.as_bytes())?;
+            bail!("Source VM is not compatible:\n{}", diff);
+        }
 
         Response::send_msg(fd, TransStatus::Ok)?;
 
         Ok(())
     }
 
-    /// Check vcpu number config.
-    fn check_vcpu(src_config: &VmConfig, dest_config: &VmConfig) -> Result<()> {
-        let src_cpu = src_config.machine_config.nr_cpus;
-        let dest_cpu = dest_config.machine_config.nr_cpus;
-        if src_cpu != dest_cpu {
-            return Err(anyhow!(MigrationError::MigrationConfigErr(
-                "vCPU number".to_string(),
-                src_cpu.to_string(),
-                dest_cpu.to_string(),
-            )));
+    /// Start to send dirty memory page iteratively. Return `None` if it should
+    /// continue to the next iteration. Otherwise, the expected downtime of sending
+    /// the dirty memory is acceptable, return the dirty memory blocks which should be
+    /// sent after pausing VM.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    fn iteration_send<T>(fd: &mut T) -> Result<Option<Vec<MemBlock>>>
+    where
+        T: Write + Read,
+    {
+        let blocks = Self::get_dirty_blocks()?;
+        let dirty_len: u64 = blocks.iter().map(|block| block.len).sum();
+        if Self::downtime_acceptable(dirty_len) {
+            return Ok(Some(blocks));
         }
 
-        Ok(())
-    }
-
-    /// Check memory size config.
-    fn check_memory(src_config: &VmConfig, dest_config: &VmConfig) -> Result<()> {
-        let src_mem = src_config.machine_config.mem_config.mem_size;
-        let dest_mem = dest_config.machine_config.mem_config.mem_size;
-        if src_mem != dest_mem {
-            return Err(anyhow!(MigrationError::MigrationConfigErr(
-                "memory size".to_string(),
-                src_mem.to_string(),
-                dest_mem.to_string(),
-            )));
-        }
+        Self::send_memory(fd, blocks).with_context(|| "Failed to send dirty memory")?;
 
-        Ok(())
+        Ok(None)
     }
 
-    /// Check devices type and BDF config.
-    fn check_devices(src_config: &VmConfig, dest_config: &VmConfig) -> Result<()> {
-        let mut dest_devices: HashMap<PciBdf, String> = HashMap::new();
-        for (dev_type, dev_info) in dest_config.devices.iter() {
-            if let Ok(dest_bdf) = get_pci_bdf(dev_info) {
-                dest_devices.insert(dest_bdf, dev_type.to_string());
-            }
+    /// Check whether the expected downtime of sending `dirty_len` bytes memory
+    /// under the measured transfer rate is less than the downtime limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `dirty_len` - The length of dirty memory need to be sent.
+    fn downtime_acceptable(dirty_len: u64) -> bool {
+        if dirty_len == 0 {
+            return true;
         }
-        for (src_type, dev_info) in src_config.devices.iter() {
-            if let Ok(src_bdf) = get_pci_bdf(dev_info) {
-                match dest_devices.get(&src_bdf) {
-                    Some(dest_type) => {
-                        if !src_type.eq(dest_type) {
-                            return Err(anyhow!(MigrationError::MigrationConfigErr(
-                                "device type".to_string(),
-                                src_type.to_string(),
-                                dest_type.to_string(),
-                            )));
-                        }
-                    }
-                    None => bail!(
-                        "Failed to get destination device bdf {:?}, type {}",
-                        src_bdf,
-                        src_type
-                    ),
-                }
-            }
+
+        let limit = MIGRATION_MANAGER.limit.read().unwrap();
+        if limit.transfer_rate == 0 {
+            return false;
         }
+        let expected_downtime = dirty_len * 1000 / limit.transfer_rate;
+        info!(
+            "Dirty memory {} bytes, transfer rate {} bytes/s, expected downtime {} ms",
+            dirty_len, limit.transfer_rate, expected_downtime
+        );
 
-        Ok(())
+        expected_downtime <= limit.limit_downtime
     }
 
-    /// Start to send dirty memory page iteratively. Return true if it should
-    /// continue to the next iteration. Otherwise, return false.
+    /// Sleep to keep the rate of sending memory under the max bandwidth.
     ///
     /// # Arguments
     ///
-    /// * `fd` - The fd implements `Read` and `Write` trait object.
-    fn iteration_send<T>(fd: &mut T) -> Result<bool>
-    where
-        T: Write + Read,
-    {
-        let mut state =
-            Self::send_dirty_memory(fd).with_context(|| "Failed to send dirty memory")?;
+    /// * `len` - The length of memory has just been sent.
+    fn limit_bandwidth(len: u64) {
+        let mut limit = MIGRATION_MANAGER.limit.write().unwrap();
+        if limit.max_bandwidth == 0 {
+            return;
+        }
 
-        // Check the virtual machine downtime.
-        if MIGRATION_MANAGER
-            .limit
-            .read()
-            .unwrap()
-            .iteration_start_time
-            .elapsed()
-            < Duration::from_millis(MIGRATION_MANAGER.limit.read().unwrap().limit_downtime)
-        {
-            state = false;
+        limit.window_bytes += len;
+        let expected =
+            Duration::from_secs_f64(limit.window_bytes as f64 / limit.max_bandwidth as f64);
+        let elapsed = limit.window_start_time.elapsed();
+        if elapsed >= BANDWIDTH_WINDOW && elapsed >= expected {
+            // Start a new window, so that idle time will not be taken as credit.
+            limit.window_start_time = Instant::now();
+            limit.window_bytes = 0;
+            return;
         }
-        // Update iteration start time.
-        MIGRATION_MANAGER
-            .limit
-            .write()
-            .unwrap()
-            .iteration_start_time = Instant::now();
+        drop(limit);
 
-        Ok(state)
+        if expected > elapsed {
+            thread::sleep(expected - elapsed);
+        }
     }
 
     /// Receive memory data from source VM.
@@ -329,9 +407,11 @@ impl MigrationManager {
         })?;
 
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
+            let mut data = Vec::new();
             for block in blocks.iter() {
+                decode_pages(fd, Some(locked_memory.as_ref()), block, &mut data)?;
                 locked_memory.recv_memory(
-                    fd,
+                    &mut data.as_slice(),
                     MemBlock {
                         gpa: block.gpa,
                         len: block.len,
@@ -351,31 +431,72 @@ impl MigrationManager {
     ///
     /// * `fd` - The fd implements `Read` and `Write` trait object.
     /// * `blocks` - The memory blocks need to be sent.
-    fn send_memory<T>(fd: &mut T, blocks: Vec<MemBlock>) -> Result<()>
+    pub(crate) fn send_memory<T>(fd: &mut T, blocks: Vec<MemBlock>) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        let mut locked_cache = MIGRATION_MANAGER.xbzrle_cache.lock().unwrap();
+        Self::send_memory_pages(fd, blocks, Some(&mut locked_cache))
+    }
+
+    /// Send memory data of COLO checkpoint to secondary VM. The pages are sent as
+    /// raw or zero pages without xbzrle cache, as the secondary VM may have dirtied
+    /// the old content of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `fd` - The fd implements `Read` and `Write` trait object.
+    /// * `blocks` - The memory blocks need to be sent.
+    pub(crate) fn send_checkpoint_memory<T>(fd: &mut T, blocks: Vec<MemBlock>) -> Result<()>
+    where
+        T: Read + Write,
+    {
+        Self::send_memory_pages(fd, blocks, None)
+    }
+
+    /// Send memory pages of `blocks`, which are encoded as xbzrle delta against
+    /// `cache` if it is given.
+    fn send_memory_pages<T>(
+        fd: &mut T,
+        blocks: Vec<MemBlock>,
+        mut cache: Option<&mut XbzrleCache>,
+    ) -> Result<()>
     where
         T: Read + Write,
     {
+        let blocks = split_mem_blocks(blocks);
         let len = size_of::<MemBlock>() * blocks.len();
         Request::send_msg(fd, TransStatus::Memory, len as u64)?;
         fd.write_all(unsafe {
             std::slice::from_raw_parts(blocks.as_ptr() as *const MemBlock as *const u8, len)
         })?;
 
+        let start_time = Instant::now();
         if let Some(locked_memory) = &MIGRATION_MANAGER.vmm.read().unwrap().memory {
+            let mut data = Vec::new();
+            let mut encoded = Vec::new();
             for block in blocks.iter() {
+                data.clear();
+                encoded.clear();
                 locked_memory.send_memory(
-                    fd,
+                    &mut data,
                     MemBlock {
                         gpa: block.gpa,
                         len: block.len,
                     },
                 )?;
+                encode_pages(block.gpa, &data, cache.as_deref_mut(), &mut encoded);
+                fd.write_all(&encoded)?;
+                Self::limit_bandwidth(encoded.len() as u64);
             }
         }
 
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
+        Self::check_response(fd)?;
+
+        let sent_len: u64 = blocks.iter().map(|block| block.len).sum();
+        let elapsed = start_time.elapsed().as_micros() as u64;
+        if let Some(rate) = (sent_len * 1_000_000).checked_div(elapsed) {
+            MIGRATION_MANAGER.limit.write().unwrap().transfer_rate = rate;
         }
 
         Ok(())
@@ -413,13 +534,7 @@ impl MigrationManager {
     where
         T: Read + Write,
     {
-        let mut blocks: Vec<MemBlock> = Vec::new();
-        let mem_slots = KVM_FDS.load().get_mem_slots();
-        for (_, slot) in mem_slots.lock().unwrap().iter() {
-            let sub_blocks: Vec<MemBlock> = Self::get_dirty_log(slot)?;
-            blocks.extend(sub_blocks);
-        }
-
+        let blocks = Self::get_dirty_blocks()?;
         if blocks.is_empty() {
             return Ok(false);
         }
@@ -429,6 +544,18 @@ impl MigrationManager {
         Ok(true)
     }
 
+    /// Collect and clear dirty memory blocks of all memory slots.
+    pub(crate) fn get_dirty_blocks() -> Result<Vec<MemBlock>> {
+        let mut blocks: Vec<MemBlock> = Vec::new();
+        let mem_slots = KVM_FDS.load().get_mem_slots();
+        for (_, slot) in mem_slots.lock().unwrap().iter() {
+            let sub_blocks: Vec<MemBlock> = Self::get_dirty_log(slot)?;
+            blocks.extend(sub_blocks);
+        }
+
+        Ok(blocks)
+    }
+
     /// Send VM state data to destination VM.
     ///
     /// # Arguments
@@ -441,10 +568,7 @@ impl MigrationManager {
         Request::send_msg(fd, TransStatus::State, 0)?;
         Self::save_vmstate(None, fd)?;
 
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
-        }
+        Self::check_response(fd)?;
 
         Ok(())
     }
@@ -481,10 +605,7 @@ impl MigrationManager {
     {
         Self::set_status(MigrationStatus::Active)?;
         Request::send_msg(fd, TransStatus::Active, 0)?;
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
-        }
+        Self::check_response(fd)?;
 
         Ok(())
     }
@@ -500,10 +621,7 @@ impl MigrationManager {
     {
         Self::set_status(MigrationStatus::Completed)?;
         Request::send_msg(fd, TransStatus::Complete, 0)?;
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
-        }
+        Self::check_response(fd)?;
 
         Ok(())
     }
@@ -546,16 +664,13 @@ impl MigrationManager {
         Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
 
         Request::send_msg(fd, TransStatus::Cancel, 0)?;
-        let result = Response::recv_msg(fd)?;
-        if result.is_err() {
-            return Err(anyhow!(MigrationError::ResponseErr));
-        }
+        Self::check_response(fd)?;
 
         Ok(())
     }
 
     /// Clear live migration environment and shut down VM.
-    fn clear_migration() -> Result<()> {
+    pub(crate) fn clear_migration() -> Result<()> {
         if let Some(locked_vm) = &MIGRATION_MANAGER.vmm.read().unwrap().vm {
             locked_vm.lock().unwrap().destroy();
         }
@@ -565,12 +680,144 @@ impl MigrationManager {
 
     /// Recover the virtual machine if migration is failed.
     pub fn recover_from_migration() -> Result<()> {
+        Self::restart_devices()?;
         if let Some(locked_vm) = &MIGRATION_MANAGER.vmm.read().unwrap().vm {
             locked_vm.lock().unwrap().resume();
         }
 
         Ok(())
     }
+
+    /// Sample the dirty rate of guest memory by logging dirty pages for a period.
+    /// Returns the dirty bytes per second, or None if dirty log is already used by
+    /// migration or COLO.
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - The period to log dirty pages.
+    pub fn sample_dirty_rate(period: Duration) -> Result<Option<u64>> {
+        let _dirty_log_lock = MIGRATION_MANAGER.dirty_log_lock.lock().unwrap();
+        if MIGRATION_MANAGER.dirty_log_active.load(Ordering::Acquire) {
+            return Ok(None);
+        }
+
+        Self::start_dirty_log().with_context(|| "Failed to start logging dirty page")?;
+        let start_time = Instant::now();
+        thread::sleep(period);
+        let mut dirty_bytes = 0;
+        let mem_slots = KVM_FDS.load().get_mem_slots();
+        for (_, slot) in mem_slots.lock().unwrap().iter() {
+            dirty_bytes += Self::get_dirty_log(slot)?
+                .iter()
+                .map(|block| block.len)
+                .sum::<u64>();
+        }
+        let elapsed = start_time.elapsed();
+        Self::stop_dirty_log().with_context(|| "Failed to stop logging dirty page")?;
+
+        Ok(Some(
+            (dirty_bytes as u128 * 1_000_000 / elapsed.as_micros().max(1)) as u64,
+        ))
+    }
+}
+
+/// Split memory blocks into chunks no larger than `MEM_CHUNK_SIZE`, so that the
+/// bandwidth can be limited in a fine-grained way.
+fn split_mem_blocks(blocks: Vec<MemBlock>) -> Vec<MemBlock> {
+    let mut chunks = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let mut offset = 0;
+        while offset < block.len {
+            let len = std::cmp::min(MEM_CHUNK_SIZE, block.len - offset);
+            chunks.push(MemBlock {
+                gpa: block.gpa + offset,
+                len,
+            });
+            offset += len;
+        }
+    }
+
+    chunks
+}
+
+/// Encode memory pages starting from `gpa` to `encoded`. Each page is led by
+/// its type, zero page is sent without data, and page cached in `cache` is
+/// sent as xbzrle delta if the encoded data is shorter than the page.
+fn encode_pages(gpa: u64, data: &[u8], mut cache: Option<&mut XbzrleCache>, encoded: &mut Vec<u8>) {
+    for (index, page) in data.chunks(PAGE_SIZE as usize).enumerate() {
+        let page_gpa = gpa + index as u64 * PAGE_SIZE;
+        if page.iter().all(|byte| *byte == 0) {
+            encoded.push(PAGE_ZERO);
+            if let Some(cache) = cache.as_deref_mut() {
+                if cache.get(page_gpa).is_some() {
+                    cache.insert(page_gpa, page);
+                }
+            }
+            continue;
+        }
+
+        if let Some(cache) = cache.as_deref_mut() {
+            let delta = match cache.get(page_gpa) {
+                Some(old) if old.len() == page.len() => {
+                    xbzrle::encode(old, page, page.len() - size_of::<u16>())
+                }
+                _ => None,
+            };
+            cache.insert(page_gpa, page);
+            if let Some(delta) = delta {
+                encoded.push(PAGE_XBZRLE);
+                encoded.extend_from_slice(&(delta.len() as u16).to_le_bytes());
+                encoded.extend_from_slice(&delta);
+                continue;
+            }
+        }
+
+        encoded.push(PAGE_RAW);
+        encoded.extend_from_slice(page);
+    }
+}
+
+/// Decode memory pages of `block` from `fd` to `data`, the old content of
+/// xbzrle encoded page is read from `memory`, and xbzrle encoded page is
+/// rejected if `memory` is not given.
+pub(crate) fn decode_pages<T: Read>(
+    fd: &mut T,
+    memory: Option<&(dyn MigrationHook + Send + Sync)>,
+    block: &MemBlock,
+    data: &mut Vec<u8>,
+) -> Result<()> {
+    data.clear();
+    data.resize(block.len as usize, 0);
+    for (index, page) in data.chunks_mut(PAGE_SIZE as usize).enumerate() {
+        let mut page_type = [0_u8; 1];
+        fd.read_exact(&mut page_type)?;
+        match page_type[0] {
+            PAGE_RAW => fd.read_exact(page)?,
+            // The page has been zeroed.
+            PAGE_ZERO => {}
+            PAGE_XBZRLE => {
+                let memory = memory.with_context(|| "Unexpected xbzrle encoded page")?;
+                let mut len = [0_u8; size_of::<u16>()];
+                fd.read_exact(&mut len)?;
+                let mut delta = vec![0_u8; u16::from_le_bytes(len) as usize];
+                fd.read_exact(&mut delta)?;
+
+                let mut old = Vec::with_capacity(page.len());
+                memory.send_memory(
+                    &mut old,
+                    MemBlock {
+                        gpa: block.gpa + index as u64 * PAGE_SIZE,
+                        len: page.len() as u64,
+                    },
+                )?;
+                page.copy_from_slice(&old);
+                xbzrle::decode(&delta, page)?;
+            }
+            other => bail!("Invalid type {} of memory page", other),
+        }
+    }
+
+    Ok(())
 }
 
 /// Dirty bitmap information of vmm memory slot.
@@ -639,6 +886,15 @@ impl DirtyBitmap {
         }
     }
 
+    /// Mark dirty page reported by kvm dirty ring.
+    ///
+    /// # Arguments
+    ///
+    /// * `page` - Page offset in memory slot.
+    fn mark_page(&self, page: u64) {
+        self.mark_bitmap(self.gpa + page * self.page_size, self.page_size);
+    }
+
     /// Get and clear dirty bitmap for vmm.
     fn get_and_clear_dirty(&self) -> Vec<u64> {
         self.map
@@ -648,6 +904,110 @@ impl DirtyBitmap {
     }
 }
 
+/// Threads harvesting dirty rings of vCPUs into vmm dirty bitmaps during dirty log.
+pub struct DirtyRingHarvest {
+    /// Notify the threads to exit.
+    stop: Arc<AtomicBool>,
+    /// Handles of harvesting threads.
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl DirtyRingHarvest {
+    /// Spawn one harvesting thread for each vCPU's dirty ring.
+    fn start() -> Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+        for (index, ring) in KVM_FDS.load().get_dirty_rings().into_iter().enumerate() {
+            let thread_stop = stop.clone();
+            let handle = thread::Builder::new()
+                .name(format!("dirty ring {}", index))
+                .spawn(move || {
+                    while !thread_stop.load(Ordering::Acquire) {
+                        if let Err(e) = harvest_dirty_ring(&ring) {
+                            warn!("Failed to harvest dirty ring: {:?}", e);
+                        }
+                        thread::sleep(DIRTY_RING_HARVEST_INTERVAL);
+                    }
+                })
+                .with_context(|| "Failed to spawn dirty ring harvesting thread")?;
+            threads.push(handle);
+        }
+
+        Ok(DirtyRingHarvest { stop, threads })
+    }
+
+    /// Stop the harvesting threads and wait for them to exit.
+    fn stop(self) {
+        self.stop.store(true, Ordering::Release);
+        for handle in self.threads {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Mark the vmm dirty bitmap which covers the host memory range.
+///
+/// # Arguments
+///
+/// * `bitmaps` - Vmm dirty bitmaps of memory slots.
+/// * `addr` - Start host address of dirty memory.
+/// * `len` - Length of dirty memory.
+fn mark_host_range(bitmaps: &HashMap<u32, DirtyBitmap>, addr: u64, len: u64) {
+    for (_, map) in bitmaps.iter() {
+        if (addr >= map.hva) && ((addr + len) <= (map.hva + map.len)) {
+            map.mark_bitmap(addr - map.hva + map.gpa, len);
+        }
+    }
+}
+
+/// Mark the vmm dirty bitmap which covers the guest memory range.
+///
+/// # Arguments
+///
+/// * `bitmaps` - Vmm dirty bitmaps of memory slots.
+/// * `gpa` - Start guest address of dirty memory.
+/// * `len` - Length of dirty memory.
+fn mark_guest_range(bitmaps: &HashMap<u32, DirtyBitmap>, gpa: u64, len: u64) {
+    for (_, map) in bitmaps.iter() {
+        if (gpa >= map.gpa) && ((gpa + len) <= (map.gpa + map.len)) {
+            map.mark_bitmap(gpa, len);
+        }
+    }
+}
+
+/// Collect dirty pages logged by devices out of vCPUs into vmm dirty bitmaps.
+fn sync_device_dirty_log() {
+    let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
+    let locked_devices = &MIGRATION_MANAGER.vmm.read().unwrap().devices;
+    for (_, device) in locked_devices.iter() {
+        device
+            .lock()
+            .unwrap()
+            .sync_dirty_log(&mut |gpa, len| mark_guest_range(&bitmaps, gpa, len));
+    }
+}
+
+/// Collect dirty pages from the dirty ring into vmm dirty bitmaps, and reset
+/// the collected entries in kvm.
+///
+/// # Arguments
+///
+/// * `ring` - Dirty ring of vCPU.
+fn harvest_dirty_ring(ring: &DirtyRing) -> Result<()> {
+    let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
+    let count = ring.harvest(&mut |slot, page| {
+        if let Some(map) = bitmaps.get(&slot) {
+            map.mark_page(page);
+        }
+    });
+    drop(bitmaps);
+
+    if count != 0 {
+        KVM_FDS.load().reset_dirty_rings()?;
+    }
+    Ok(())
+}
+
 pub trait Migratable {
     /// Start the dirty log in the kvm and vmm.
     fn start_dirty_log() -> Result<()> {
@@ -661,18 +1021,65 @@ pub trait Migratable {
         }
         let mut vm_bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
         *vm_bitmaps = bitmaps;
+        drop(vm_bitmaps);
+        MIGRATION_MANAGER
+            .dirty_log_active
+            .store(true, Ordering::Release);
 
         // Start logging dirty memory in kvm.
         KVM_FDS.load().start_dirty_log()?;
 
+        if KVM_FDS.load().dirty_ring_enabled() {
+            let harvest = DirtyRingHarvest::start()?;
+            if let Some(old) = MIGRATION_MANAGER
+                .dirty_ring_harvest
+                .lock()
+                .unwrap()
+                .replace(harvest)
+            {
+                old.stop();
+            }
+        }
+
+        // Start logging dirty memory in devices out of vCPUs.
+        let result = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .devices
+            .iter()
+            .try_for_each(|(_, device)| device.lock().unwrap().start_dirty_log());
+        if let Err(e) = result {
+            let _ = Self::stop_dirty_log();
+            return Err(e);
+        }
+
         Ok(())
     }
 
     /// Stop the dirty log in the kvm and vmm.
     fn stop_dirty_log() -> Result<()> {
+        // Stop logging dirty memory in devices out of vCPUs.
+        for (_, device) in MIGRATION_MANAGER.vmm.read().unwrap().devices.iter() {
+            device.lock().unwrap().stop_dirty_log()?;
+        }
+
+        // Stop harvesting and drain the dirty rings.
+        let harvest = MIGRATION_MANAGER.dirty_ring_harvest.lock().unwrap().take();
+        if let Some(harvest) = harvest {
+            harvest.stop();
+        }
+        for ring in KVM_FDS.load().get_dirty_rings() {
+            harvest_dirty_ring(&ring)?;
+        }
+
         // Clear dirty bitmaps from vmm.
+        MIGRATION_MANAGER
+            .dirty_log_active
+            .store(false, Ordering::Release);
         let mut vm_bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
         *vm_bitmaps = HashMap::new();
+        drop(vm_bitmaps);
 
         // Stop logging dirty memory in kvm.
         KVM_FDS.load().stop_dirty_log()?;
@@ -686,6 +1093,22 @@ pub trait Migratable {
     ///
     /// * `slot` - The memory slot.
     fn get_dirty_log(slot: &MemorySlot) -> Result<Vec<MemBlock>> {
+        sync_device_dirty_log();
+
+        // Dirty pages reported by dirty ring are merged into vmm bitmaps, and
+        // dirty bitmap of kvm is unavailable.
+        if KVM_FDS.load().dirty_ring_enabled() {
+            for ring in KVM_FDS.load().get_dirty_rings() {
+                harvest_dirty_ring(&ring)?;
+            }
+            let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.read().unwrap();
+            let dirty_bitmap = bitmaps
+                .get(&slot.slot)
+                .map(|map| map.get_and_clear_dirty())
+                .unwrap_or_default();
+            return Ok(Self::sync_dirty_bitmap(dirty_bitmap, slot.guest_phys_addr));
+        }
+
         // Get dirty memory from vmm.
         let mut vmm_dirty_bitmap = Vec::new();
         let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
@@ -712,6 +1135,18 @@ pub trait Migratable {
         Ok(Self::sync_dirty_bitmap(dirty_bitmap, slot.guest_phys_addr))
     }
 
+    /// Harvest the dirty ring of vCPU, it is called when the ring is full.
+    ///
+    /// # Arguments
+    ///
+    /// * `vcpu_id` - The id of vCPU.
+    fn harvest_dirty_ring(vcpu_id: u32) -> Result<()> {
+        match KVM_FDS.load().get_dirty_ring(vcpu_id) {
+            Some(ring) => harvest_dirty_ring(&ring),
+            None => bail!("Dirty ring of vcpu {} is not found", vcpu_id),
+        }
+    }
+
     /// mark the dirty log into vmm.
     ///
     /// # Arguments
@@ -719,16 +1154,14 @@ pub trait Migratable {
     /// * `addr` - Start address of dirty memory.
     /// * `len` - Length of dirty memory.
     fn mark_dirty_log(addr: u64, len: u64) {
-        if !MigrationManager::is_active() {
+        // It is called by every write to guest memory from vCPU and iothreads,
+        // so check the lock-free flag first and mark the bitmaps atomically under
+        // the shared lock.
+        if !MIGRATION_MANAGER.dirty_log_active.load(Ordering::Acquire) {
             return;
         }
 
-        let bitmaps = MIGRATION_MANAGER.vmm_bitmaps.write().unwrap();
-        for (_, map) in bitmaps.iter() {
-            if (addr >= map.hva) && ((addr + len) <= (map.hva + map.len)) {
-                map.mark_bitmap(addr - map.hva + map.gpa, len);
-            }
-        }
+        mark_host_range(&MIGRATION_MANAGER.vmm_bitmaps.read().unwrap(), addr, len);
     }
 
     /// sync the dirty log from kvm bitmaps.
@@ -775,3 +1208,174 @@ pub trait Migratable {
 }
 
 impl Migratable for MigrationManager {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::protocol::StateTransfer;
+    use crate::transport::{ExecMode, ExecStream};
+
+    struct TestMemory {
+        data: Mutex<Vec<u8>>,
+    }
+
+    impl StateTransfer for TestMemory {
+        fn get_state_vec(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn get_device_alias(&self) -> u64 {
+            0
+        }
+    }
+
+    impl MigrationHook for TestMemory {
+        fn send_memory(&self, fd: &mut dyn Write, range: MemBlock) -> Result<()> {
+            let data = self.data.lock().unwrap();
+            fd.write_all(&data[range.gpa as usize..(range.gpa + range.len) as usize])?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_split_mem_blocks() {
+        let blocks = vec![
+            MemBlock { gpa: 0, len: 4096 },
+            MemBlock {
+                gpa: 0x1000_0000,
+                len: MEM_CHUNK_SIZE * 2 + 4096,
+            },
+        ];
+        let chunks = split_mem_blocks(blocks);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0].gpa, 0);
+        assert_eq!(chunks[0].len, 4096);
+        assert_eq!(chunks[1].gpa, 0x1000_0000);
+        assert_eq!(chunks[1].len, MEM_CHUNK_SIZE);
+        assert_eq!(chunks[2].gpa, 0x1000_0000 + MEM_CHUNK_SIZE);
+        assert_eq!(chunks[2].len, MEM_CHUNK_SIZE);
+        assert_eq!(chunks[3].gpa, 0x1000_0000 + MEM_CHUNK_SIZE * 2);
+        assert_eq!(chunks[3].len, 4096);
+    }
+
+    #[test]
+    fn test_encode_decode_pages() {
+        let page_size = PAGE_SIZE as usize;
+        let mut cache = XbzrleCache::new(PAGE_SIZE * 4);
+        let mut src = vec![0_u8; page_size * 3];
+        src[page_size..page_size * 2].fill(0x5a);
+        src[page_size * 2..].fill(0xa5);
+
+        // The zero page is sent without data, others are sent as raw data.
+        let mut encoded = Vec::new();
+        encode_pages(0, &src, Some(&mut cache), &mut encoded);
+        assert_eq!(encoded.len(), 1 + (1 + page_size) * 2);
+
+        let memory = TestMemory {
+            data: Mutex::new(vec![0xff_u8; page_size * 3]),
+        };
+        let block = MemBlock {
+            gpa: 0,
+            len: src.len() as u64,
+        };
+        let mut data = Vec::new();
+        decode_pages(&mut encoded.as_slice(), Some(&memory), &block, &mut data).unwrap();
+        assert_eq!(data, src);
+        *memory.data.lock().unwrap() = data;
+
+        // The dirtied cached pages are sent as xbzrle delta.
+        src[page_size + 8] = 1;
+        src[page_size * 2 + 16..page_size * 2 + 32].fill(2);
+        let mut encoded = Vec::new();
+        encode_pages(0, &src, Some(&mut cache), &mut encoded);
+        assert!(encoded.len() < 64);
+
+        let mut data = Vec::new();
+        decode_pages(&mut encoded.as_slice(), Some(&memory), &block, &mut data).unwrap();
+        assert_eq!(data, src);
+    }
+
+    #[test]
+    fn test_checkpoint_pages() {
+        let page_size = PAGE_SIZE as usize;
+        let mut cache = XbzrleCache::new(PAGE_SIZE * 4);
+        let mut src = vec![0x5a_u8; page_size * 2];
+        let mut encoded = Vec::new();
+        encode_pages(0, &src, Some(&mut cache), &mut encoded);
+        let memory = TestMemory {
+            data: Mutex::new(src.clone()),
+        };
+        let block = MemBlock {
+            gpa: 0,
+            len: src.len() as u64,
+        };
+
+        // Secondary VM dirties the first page before a checkpoint, which changes
+        // the same page of primary VM slightly.
+        memory.data.lock().unwrap()[8] = 0xff;
+        src[16] = 1;
+        src[page_size..].fill(0);
+
+        // Checkpoint pages are sent without cache, so that they don't depend on
+        // the content of secondary VM.
+        let mut encoded = Vec::new();
+        encode_pages(0, &src, None, &mut encoded);
+        assert_eq!(encoded.len(), 1 + page_size + 1);
+        let mut data = Vec::new();
+        decode_pages(&mut encoded.as_slice(), None, &block, &mut data).unwrap();
+        assert_eq!(data, src);
+
+        // Xbzrle encoded page is rejected in checkpoint.
+        let mut encoded = Vec::new();
+        encode_pages(0, &src, Some(&mut cache), &mut encoded);
+        assert_eq!(encoded[0], PAGE_XBZRLE);
+        assert!(decode_pages(&mut encoded.as_slice(), None, &block, &mut data).is_err());
+    }
+
+    #[test]
+    fn test_mark_host_range() {
+        // Local bitmaps are used, as MIGRATION_MANAGER is shared by the tests running
+        // in parallel.
+        let page_size = host_page_size();
+        let (gpa, hva, len) = (0x1000_0000, 0x7f00_0000_0000, page_size * 4);
+        let mut bitmaps = HashMap::new();
+        bitmaps.insert(0, DirtyBitmap::new(gpa, hva, len));
+        let dirty = |bitmaps: &HashMap<u32, DirtyBitmap>| bitmaps[&0].get_and_clear_dirty();
+
+        mark_host_range(&bitmaps, hva + page_size, page_size + 1);
+        assert_eq!(dirty(&bitmaps)[0], 0b110);
+        assert!(dirty(&bitmaps).iter().all(|m| *m == 0));
+
+        // Range out of the memory slot is ignored.
+        mark_host_range(&bitmaps, hva + len - 8, 16);
+        mark_host_range(&bitmaps, hva - page_size, 8);
+        assert!(dirty(&bitmaps).iter().all(|m| *m == 0));
+    }
+
+    #[test]
+    fn test_exec_responses() {
+        // Responses relayed by `exec:` command are checked.
+        let mut stream = ExecStream::spawn("cat", ExecMode::Duplex).unwrap();
+        Response::send_msg(&mut stream, TransStatus::Ok).unwrap();
+        assert!(MigrationManager::check_response(&mut stream).is_ok());
+        Response::send_msg(&mut stream, TransStatus::Error).unwrap();
+        assert!(MigrationManager::check_response(&mut stream).is_err());
+        drop(stream);
+
+        // `exec:` command which doesn't relay responses fails the migration, only
+        // `exec-oneway:` command goes on without them.
+        let mut stream = ExecStream::spawn("exec cat > /dev/null", ExecMode::Duplex).unwrap();
+        Request::send_msg(&mut stream, TransStatus::Active, 0).unwrap();
+        assert!(MigrationManager::check_response(&mut stream).is_err());
+        drop(stream);
+
+        let mut stream = ExecStream::spawn("cat > /dev/null", ExecMode::OneWaySend).unwrap();
+        Request::send_msg(&mut stream, TransStatus::Active, 0).unwrap();
+        assert!(MigrationManager::recv_response(&mut stream)
+            .unwrap()
+            .is_none());
+        assert!(MigrationManager::check_response(&mut stream).is_ok());
+    }
+}
@@ -31,6 +31,8 @@ use util::byte_code::ByteCode;
 /// Setup ----------> Active: migration is ready.
 /// Active ---------> Completed: migration is successful.
 /// Completed ------> Active: make migration become ready again.
+/// Completed ------> Colo: enter COLO fault tolerance mode.
+/// Colo -----------> Completed: failover, VM runs alone.
 /// Failed ---------> Setup: reset migration resource.
 /// Any ------------> Failed: something wrong in migration.
 /// Any ------------> Canceled: cancel migration.
@@ -48,6 +50,8 @@ pub enum MigrationStatus {
     Failed,
     /// Migration canceled.
     Canceled,
+    /// Primary and secondary VM run in COLO mode.
+    Colo,
 }
 
 impl std::fmt::Display for MigrationStatus {
@@ -62,6 +66,7 @@ impl std::fmt::Display for MigrationStatus {
                 MigrationStatus::Completed => "completed",
                 MigrationStatus::Failed => "failed",
                 MigrationStatus::Canceled => "canceled",
+                MigrationStatus::Colo => "colo",
             }
         )
     }
@@ -94,7 +99,7 @@ impl MigrationStatus {
                 ))),
             },
             MigrationStatus::Completed => match new_status {
-                MigrationStatus::Active => Ok(new_status),
+                MigrationStatus::Active | MigrationStatus::Colo => Ok(new_status),
                 _ => Err(anyhow!(MigrationError::InvalidStatusTransfer(
                     self, new_status
                 ))),
@@ -106,6 +111,14 @@ impl MigrationStatus {
                 ))),
             },
             MigrationStatus::Canceled => Ok(new_status),
+            MigrationStatus::Colo => match new_status {
+                MigrationStatus::Completed
+                | MigrationStatus::Failed
+                | MigrationStatus::Canceled => Ok(new_status),
+                _ => Err(anyhow!(MigrationError::InvalidStatusTransfer(
+                    self, new_status
+                ))),
+            },
         }
     }
 }
@@ -132,6 +145,13 @@ pub enum TransStatus {
     Error,
     /// Unknown status in migration .
     Unknown,
+    /// Enter COLO mode after migration.
+    Colo,
+    /// Mirror incoming packets and compare outgoing packets of primary and
+    /// secondary VM in COLO mode.
+    ColoCompare,
+    /// Synchronize secondary VM with primary VM in COLO mode.
+    Checkpoint,
 }
 
 impl Default for TransStatus {
@@ -155,6 +175,9 @@ impl std::fmt::Display for TransStatus {
                 TransStatus::Ok => "Ok",
                 TransStatus::Error => "Error",
                 TransStatus::Unknown => "Unknown",
+                TransStatus::Colo => "Colo",
+                TransStatus::ColoCompare => "ColoCompare",
+                TransStatus::Checkpoint => "Checkpoint",
             }
         )
     }
@@ -489,6 +512,19 @@ pub trait StateTransfer {
     /// Downcast some high-version information.
     fn downcast_version(&mut self) {}
 
+    /// Transform `DeviceState` bytes slice saved by an older version to
+    /// current layout. It is called after the fields are padded by alias and
+    /// before the state is set, so the fields not copied from the old state can
+    /// be filled or converted here.
+    ///
+    /// # Arguments
+    ///
+    /// * `_version` - Device migration version of the saved state.
+    /// * `_state` - `DeviceState` bytes slice in current layout.
+    fn upgrade_state(&self, _version: u32, _state: &mut [u8]) -> Result<()> {
+        Ok(())
+    }
+
     /// Get `DeviceState` alias used for `InstanceId`.
     fn get_device_alias(&self) -> u64;
 }
@@ -523,18 +559,16 @@ pub struct FieldDesc {
     pub offset: u32,
     /// Size of this field.
     pub size: u32,
+    /// The minimum device migration version whose field with the same alias
+    /// can be copied to this field, 0 means all versions.
+    #[serde(default)]
+    pub version: u32,
 }
 
 impl DeviceStateDesc {
     /// Check the field is exist in `DeviceState` with a field alias.
     fn contains(&self, alias_name: &str) -> bool {
-        for field in &self.fields {
-            if alias_name == field.alias {
-                return true;
-            }
-        }
-
-        false
+        self.fields.iter().any(|field| alias_name == field.alias)
     }
 
     /// Get a slice index: (start, end) for given field alias.
@@ -555,6 +589,7 @@ impl DeviceStateDesc {
 
     /// Check padding from a device state descriptor to another version device state
     /// descriptor. The padding will be added into current_slice for `DeviceState`.
+    /// Fields whose `version` is higher than the old version are left zero.
     ///
     /// # Arguments
     ///
@@ -565,7 +600,7 @@ impl DeviceStateDesc {
         current_slice.clear();
         current_slice.resize(self.size as usize, 0);
         for field in self.clone().fields {
-            if desc.contains(&field.alias) {
+            if desc.contains(&field.alias) && desc.current_version >= field.version {
                 let (new_start, new_end) = desc.get_slice_index(&field.alias)?;
                 let (start, mut end) = self.get_slice_index(&field.alias)?;
 
@@ -586,7 +621,8 @@ impl DeviceStateDesc {
     /// Check device state version descriptor version message.
     /// If version is same, return enum `Same`.
     /// If version is not same but fit, return enum `Compat`.
-    /// if version is not fit, return enum `Mismatch`.
+    /// if version is not fit, return enum `Mismatch`, it happens when the old
+    /// version is newer than current version or older than compat version.
     ///
     /// # Arguments
     ///
@@ -594,8 +630,10 @@ impl DeviceStateDesc {
     pub fn check_version(&self, desc: &DeviceStateDesc) -> VersionCheck {
         match self.current_version.cmp(&desc.current_version) {
             Ordering::Equal => VersionCheck::Same,
-            Ordering::Greater => VersionCheck::Compat,
-            Ordering::Less => VersionCheck::Mismatch,
+            Ordering::Greater if desc.current_version >= self.compat_version => {
+                VersionCheck::Compat
+            }
+            _ => VersionCheck::Mismatch,
         }
     }
 }
@@ -883,6 +921,46 @@ pub mod tests {
         }
     }
 
+    #[derive(Default)]
+    // A simple device version 6.
+    pub struct DeviceV6 {
+        state: DeviceV6State,
+    }
+
+    #[derive(Copy, Clone, Desc, ByteCode)]
+    #[desc_version(current_version = "6.0.0", compat_version = "4.0.0")]
+    // Statement for DeviceV6, the meaning of `mcr` is changed in this version.
+    pub struct DeviceV6State {
+        #[alias(iir)]
+        rii: u64,
+        #[alias(mcr)]
+        #[field_version("6.0.0")]
+        mcr: u64,
+    }
+
+    impl StateTransfer for DeviceV6 {
+        fn get_state_vec(&self) -> super::Result<Vec<u8>> {
+            Ok(self.state.as_bytes().to_vec())
+        }
+
+        fn set_state_mut(&mut self, state: &[u8]) -> super::Result<()> {
+            self.state = *DeviceV6State::from_bytes(state).unwrap();
+            Ok(())
+        }
+
+        fn upgrade_state(&self, version: u32, state: &mut [u8]) -> super::Result<()> {
+            if version < DeviceV6State::descriptor().current_version {
+                let state = DeviceV6State::from_mut_bytes(state).unwrap();
+                state.mcr = state.rii << 1;
+            }
+            Ok(())
+        }
+
+        fn get_device_alias(&self) -> u64 {
+            0
+        }
+    }
+
     #[test]
     fn test_desc_basic_padding() {
         // This test makes two version of a device. Those devices's difference is appending a new
@@ -1092,6 +1170,56 @@ pub mod tests {
         assert_eq!(device_v5.state.rii, device_v2.state.iir as u64);
     }
 
+    #[test]
+    fn test_desc_field_version_upgrade() {
+        // This test makes two version of a device. The field `mcr` keeps its alias but changes
+        // its meaning, so it isn't copied from old version and is converted by `upgrade_state`.
+        let mut device_v4 = DeviceV4 {
+            state: DeviceV4State::default(),
+        };
+        device_v4.state.rii = 2;
+        device_v4.state.rcm = 255;
+
+        let state_2_desc = DeviceV2State::descriptor();
+        let state_4_desc = DeviceV4State::descriptor();
+        let state_5_desc = DeviceV5State::descriptor();
+        let state_6_desc = DeviceV6State::descriptor();
+
+        assert_eq!(
+            state_6_desc.check_version(&state_4_desc),
+            VersionCheck::Compat
+        );
+        // Older than compat version.
+        assert_eq!(
+            state_6_desc.check_version(&state_2_desc),
+            VersionCheck::Mismatch
+        );
+        // Newer than current version.
+        assert_eq!(
+            state_5_desc.check_version(&state_6_desc),
+            VersionCheck::Mismatch
+        );
+
+        let mut current_slice = device_v4.get_state_vec().unwrap();
+        state_6_desc
+            .add_padding(&state_4_desc, &mut current_slice)
+            .unwrap();
+        let mut device_v6 = DeviceV6::default();
+        device_v6
+            .upgrade_state(state_4_desc.current_version, &mut current_slice)
+            .unwrap();
+        device_v6.set_state_mut(&current_slice).unwrap();
+        assert_eq!(device_v6.state.rii, 2);
+        assert_eq!(device_v6.state.mcr, 4);
+
+        // Field version is compatible with the descriptor without it.
+        let field = serde_json::to_string(&state_4_desc.fields[0])
+            .unwrap()
+            .replace(",\"version\":0", "");
+        let field: FieldDesc = serde_json::from_str(&field).unwrap();
+        assert_eq!(field, state_4_desc.fields[0]);
+    }
+
     #[test]
     fn test_check_header() {
         if !Kvm::new().is_ok() {
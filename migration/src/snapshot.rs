@@ -12,8 +12,13 @@
 
 use std::collections::HashMap;
 use std::fs::{create_dir, File};
-use std::io::{Read, Write};
+use std::io::{Read, Seek, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
 
 use anyhow::{anyhow, bail, Context, Result};
 
@@ -21,7 +26,9 @@ use crate::general::{translate_id, Lifecycle};
 use crate::manager::{MigrationManager, MIGRATION_MANAGER};
 use crate::protocol::{DeviceStateDesc, FileFormat, MigrationStatus, HEADER_LENGTH};
 use crate::MigrationError;
+use util::bitmap::Bitmap;
 use util::unix::host_page_size;
+use util::userfaultfd::Userfaultfd;
 
 pub const SERIAL_SNAPSHOT_ID: &str = "serial";
 pub const KVM_SNAPSHOT_ID: &str = "kvm";
@@ -29,11 +36,16 @@ pub const GICV3_SNAPSHOT_ID: &str = "gicv3";
 pub const GICV3_ITS_SNAPSHOT_ID: &str = "gicv3_its";
 pub const PL011_SNAPSHOT_ID: &str = "pl011";
 pub const PL031_SNAPSHOT_ID: &str = "pl031";
+pub const HPET_SNAPSHOT_ID: &str = "hpet";
 
 /// The suffix used for snapshot memory storage.
 const MEMORY_PATH_SUFFIX: &str = "memory";
 /// The suffix used for snapshot device state storage.
 const DEVICE_PATH_SUFFIX: &str = "state";
+/// Max length of memory saved at a time in background snapshot.
+const WP_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Timeout of polling write protection faults in nanoseconds.
+const WP_POLL_TIMEOUT_NS: i64 = 100_000_000;
 
 impl MigrationManager {
     /// Save snapshot for `VM`.
@@ -51,6 +63,103 @@ impl MigrationManager {
         // Set status to `Active`
         MigrationManager::set_status(MigrationStatus::Active)?;
 
+        // Devices out of vCPUs are stopped while saving, as the VM is.
+        Self::pause_devices()?;
+        let result = Self::save_snapshot_files(path);
+        Self::restart_devices()?;
+        result?;
+
+        // Set status to `Completed`
+        MigrationManager::set_status(MigrationStatus::Completed)?;
+
+        Ok(())
+    }
+
+    /// Save device state and memory data to snapshot dir.
+    fn save_snapshot_files(path: &str) -> Result<()> {
+        // Create snapshot dir and save device state.
+        Self::save_snapshot_state(path)?;
+
+        // Save memory data
+        let mut vm_memory_path = PathBuf::from(path);
+        vm_memory_path.push(MEMORY_PATH_SUFFIX);
+        match File::create(vm_memory_path) {
+            Ok(mut memory_file) => {
+                Self::save_memory(Some(FileFormat::MemoryFull), &mut memory_file)?;
+            }
+            Err(e) => {
+                bail!("Failed to create snapshot memory file: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save snapshot for running `VM` in background.
+    ///
+    /// # Notes
+    ///
+    /// The VM is only paused while saving device state. After that, guest memory is
+    /// write protected by userfaultfd and the VM keeps running. Each page is saved
+    /// before it is modified by VM, so the memory file is consistent with the device
+    /// state file.
+    ///
+    /// # Argument
+    ///
+    /// * `path` - snapshot dir path. If path dir not exists, will create it.
+    pub fn save_background_snapshot(path: &str) -> Result<()> {
+        // Set status to `Active`
+        MigrationManager::set_status(MigrationStatus::Active)?;
+
+        let vm = MIGRATION_MANAGER.vmm.read().unwrap().vm.clone();
+        let paused = vm.as_ref().is_some_and(|vm| vm.lock().unwrap().pause());
+        let result = Self::pause_devices().and_then(|_| Self::start_background_snapshot(path));
+        let restarted = Self::restart_devices();
+        if paused {
+            vm.as_ref().unwrap().lock().unwrap().resume();
+        }
+        let wp_memory = result?;
+        if let Err(e) = restarted {
+            wp_memory.finish()?;
+            return Err(e);
+        }
+
+        let result = wp_memory.save_all();
+        wp_memory.finish()?;
+        result?;
+
+        if !MigrationManager::is_canceled() {
+            // Set status to `Completed`
+            MigrationManager::set_status(MigrationStatus::Completed)?;
+        }
+
+        Ok(())
+    }
+
+    /// Save device state and write protect memory for background snapshot.
+    fn start_background_snapshot(path: &str) -> Result<Arc<WpMemory>> {
+        Self::save_snapshot_state(path)?;
+
+        let mut vm_memory_path = PathBuf::from(path);
+        vm_memory_path.push(MEMORY_PATH_SUFFIX);
+        let mut memory_file = File::create(vm_memory_path)
+            .with_context(|| "Failed to create snapshot memory file")?;
+        Self::save_header(Some(FileFormat::MemoryFull), &mut memory_file)?;
+        let memory = MIGRATION_MANAGER
+            .vmm
+            .read()
+            .unwrap()
+            .memory
+            .clone()
+            .with_context(|| "No memory registered")?;
+        let ranges = memory.save_memory_state(&mut memory_file)?;
+        let offset = memory_file.stream_position()?;
+
+        WpMemory::new(memory_file, offset, ranges)
+    }
+
+    /// Create snapshot dir and save device state file.
+    fn save_snapshot_state(path: &str) -> Result<()> {
         // Create snapshot dir.
         if let Err(e) = create_dir(path) {
             if e.kind() != std::io::ErrorKind::AlreadyExists {
@@ -70,21 +179,6 @@ impl MigrationManager {
             }
         }
 
-        // Save memory data
-        let mut vm_memory_path = PathBuf::from(path);
-        vm_memory_path.push(MEMORY_PATH_SUFFIX);
-        match File::create(vm_memory_path) {
-            Ok(mut memory_file) => {
-                Self::save_memory(Some(FileFormat::MemoryFull), &mut memory_file)?;
-            }
-            Err(e) => {
-                bail!("Failed to create snapshot memory file: {}", e);
-            }
-        }
-
-        // Set status to `Completed`
-        MigrationManager::set_status(MigrationStatus::Completed)?;
-
         Ok(())
     }
 
@@ -249,11 +343,13 @@ impl MigrationManager {
         let locked_vmm = MIGRATION_MANAGER.vmm.read().unwrap();
         // Restore transports state.
         for _ in 0..locked_vmm.transports.len() {
-            let (transport_data, id) = Self::check_vm_state(fd, &snap_desc_db)?;
+            let (mut transport_data, id, version) = Self::check_vm_state(fd, &snap_desc_db)?;
             if let Some(transport) = locked_vmm.transports.get(&id) {
-                transport
-                    .lock()
-                    .unwrap()
+                let mut locked_transport = transport.lock().unwrap();
+                locked_transport
+                    .upgrade_state(version, &mut transport_data)
+                    .with_context(|| "Failed to upgrade transport state")?;
+                locked_transport
                     .restore_mut_device(&transport_data)
                     .with_context(|| "Failed to restore transport state")?;
             }
@@ -261,11 +357,13 @@ impl MigrationManager {
 
         // Restore devices state.
         for _ in 0..locked_vmm.devices.len() {
-            let (device_data, id) = Self::check_vm_state(fd, &snap_desc_db)?;
+            let (mut device_data, id, version) = Self::check_vm_state(fd, &snap_desc_db)?;
             if let Some(device) = locked_vmm.devices.get(&id) {
-                device
-                    .lock()
-                    .unwrap()
+                let mut locked_device = device.lock().unwrap();
+                locked_device
+                    .upgrade_state(version, &mut device_data)
+                    .with_context(|| "Failed to upgrade device state")?;
+                locked_device
                     .restore_mut_device(&device_data)
                     .with_context(|| "Failed to restore device state")?;
             }
@@ -273,8 +371,10 @@ impl MigrationManager {
 
         // Restore CPUs state.
         for _ in 0..locked_vmm.cpus.len() {
-            let (cpu_data, id) = Self::check_vm_state(fd, &snap_desc_db)?;
+            let (mut cpu_data, id, version) = Self::check_vm_state(fd, &snap_desc_db)?;
             if let Some(cpu) = locked_vmm.cpus.get(&id) {
+                cpu.upgrade_state(version, &mut cpu_data)
+                    .with_context(|| "Failed to upgrade cpu state")?;
                 cpu.restore_device(&cpu_data)
                     .with_context(|| "Failed to restore cpu state")?;
             }
@@ -284,7 +384,9 @@ impl MigrationManager {
         {
             // Restore kvm device state.
             if let Some(kvm) = &locked_vmm.kvm {
-                let (kvm_data, _) = Self::check_vm_state(fd, &snap_desc_db)?;
+                let (mut kvm_data, _, version) = Self::check_vm_state(fd, &snap_desc_db)?;
+                kvm.upgrade_state(version, &mut kvm_data)
+                    .with_context(|| "Failed to upgrade kvm state")?;
                 kvm.restore_device(&kvm_data)
                     .with_context(|| "Failed to restore kvm state")?;
             }
@@ -294,8 +396,10 @@ impl MigrationManager {
         {
             // Restore GIC group state.
             for _ in 0..locked_vmm.gic_group.len() {
-                let (gic_data, id) = Self::check_vm_state(fd, &snap_desc_db)?;
+                let (mut gic_data, id, version) = Self::check_vm_state(fd, &snap_desc_db)?;
                 if let Some(gic) = locked_vmm.gic_group.get(&id) {
+                    gic.upgrade_state(version, &mut gic_data)
+                        .with_context(|| "Failed to upgrade gic state")?;
                     gic.restore_device(&gic_data)
                         .with_context(|| "Failed to restore gic state")?;
                 }
@@ -305,3 +409,234 @@ impl MigrationManager {
         Ok(())
     }
 }
+
+/// Memory range saved in background snapshot.
+struct WpRange {
+    /// Host virtual address.
+    hva: u64,
+    /// Length of range.
+    len: u64,
+    /// Offset of range data in memory file.
+    offset: u64,
+}
+
+/// Write protected guest memory of background snapshot.
+struct WpMemory {
+    uffd: Userfaultfd,
+    file: File,
+    ranges: Vec<WpRange>,
+    /// Bitmap of saved pages for each range.
+    saved: Mutex<Vec<Bitmap<u64>>>,
+    page_size: u64,
+    /// Stop flag of fault handler thread.
+    stop: AtomicBool,
+    handler: Mutex<Option<JoinHandle<Result<()>>>>,
+}
+
+impl WpMemory {
+    /// Write protect memory `ranges`, whose data will be saved to `file` starting
+    /// from `offset`, and start the thread to handle write protection faults.
+    fn new(file: File, mut offset: u64, ranges: Vec<(u64, u64)>) -> Result<Arc<Self>> {
+        let page_size = host_page_size();
+        let uffd = Userfaultfd::new()?;
+        let mut wp_ranges = Vec::new();
+        let mut saved = Vec::new();
+        for (hva, len) in ranges {
+            wp_ranges.push(WpRange { hva, len, offset });
+            let pages = (len / page_size) as usize;
+            saved.push(Bitmap::<u64>::new(pages / u64::BITS as usize + 1));
+            offset += len;
+        }
+
+        for range in wp_ranges.iter() {
+            uffd.register_wp(range.hva, range.len)?;
+            uffd.prepare_wp(range.hva, range.len)?;
+            uffd.write_protect(range.hva, range.len, true)?;
+        }
+
+        let wp_memory = Arc::new(WpMemory {
+            uffd,
+            file,
+            ranges: wp_ranges,
+            saved: Mutex::new(saved),
+            page_size,
+            stop: AtomicBool::new(false),
+            handler: Mutex::new(None),
+        });
+        let cloned = wp_memory.clone();
+        let handler = thread::Builder::new()
+            .name("snapshot_wp".to_string())
+            .spawn(move || cloned.handle_faults())?;
+        *wp_memory.handler.lock().unwrap() = Some(handler);
+
+        Ok(wp_memory)
+    }
+
+    /// Save the pages written by VM before they are modified.
+    fn handle_faults(&self) -> Result<()> {
+        while !self.stop.load(Ordering::Acquire) {
+            let mut pollfd = libc::pollfd {
+                fd: self.uffd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = libc::timespec {
+                tv_sec: 0,
+                tv_nsec: WP_POLL_TIMEOUT_NS,
+            };
+            // SAFETY: pollfd and timeout are valid during the call.
+            if unsafe { libc::ppoll(&mut pollfd, 1, &timeout, std::ptr::null()) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e).with_context(|| "Failed to poll userfaultfd");
+            }
+
+            while let Some(addr) = self.uffd.read_wp_fault()? {
+                let index = self
+                    .ranges
+                    .iter()
+                    .position(|range| addr >= range.hva && addr < range.hva + range.len)
+                    .with_context(|| format!("Unexpected write fault at 0x{:x}", addr))?;
+                let page = ((addr - self.ranges[index].hva) / self.page_size) as usize;
+                self.save_pages(index, page, 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save all pages which are not saved yet.
+    fn save_all(&self) -> Result<()> {
+        let chunk_pages = (WP_CHUNK_SIZE / self.page_size) as usize;
+        for (index, range) in self.ranges.iter().enumerate() {
+            let pages = (range.len / self.page_size) as usize;
+            let mut page = 0;
+            while page < pages {
+                if MigrationManager::is_canceled() {
+                    return Ok(());
+                }
+                let nr = std::cmp::min(chunk_pages, pages - page);
+                self.save_pages(index, page, nr)?;
+                page += nr;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save `nr` pages starting from page `start` of range `index` if they are not
+    /// saved, and remove write protection of them.
+    fn save_pages(&self, index: usize, start: usize, nr: usize) -> Result<()> {
+        let range = &self.ranges[index];
+        let mut saved = self.saved.lock().unwrap();
+        let bitmap = &mut saved[index];
+        let mut page = start;
+        while page < start + nr {
+            if bitmap.contain(page)? {
+                page += 1;
+                continue;
+            }
+            let mut end = page + 1;
+            while end < start + nr && !bitmap.contain(end)? {
+                end += 1;
+            }
+
+            let offset = page as u64 * self.page_size;
+            let len = (end - page) as u64 * self.page_size;
+            // SAFETY: the range is guest memory mapped during VM lifetime.
+            let data = unsafe {
+                std::slice::from_raw_parts((range.hva + offset) as *const u8, len as usize)
+            };
+            self.file.write_all_at(data, range.offset + offset)?;
+            self.uffd.write_protect(range.hva + offset, len, false)?;
+            bitmap.set_range(page, end - page)?;
+            page = end;
+        }
+
+        Ok(())
+    }
+
+    /// Stop fault handler thread and remove write protection of all memory.
+    fn finish(&self) -> Result<()> {
+        self.stop.store(true, Ordering::Release);
+        let handler_result = match self.handler.lock().unwrap().take() {
+            Some(handler) => handler
+                .join()
+                .map_err(|_| anyhow!("Snapshot fault handler panicked"))?,
+            None => Ok(()),
+        };
+
+        for range in self.ranges.iter() {
+            self.uffd.write_protect(range.hva, range.len, false)?;
+            self.uffd.unregister(range.hva, range.len)?;
+        }
+
+        handler_result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wp_memory_save() {
+        let page_size = host_page_size();
+        let len = page_size * 4;
+        // SAFETY: the anonymous mapping is checked and unmapped at the end of test.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        let hva = addr as u64;
+        // SAFETY: the mapping is valid and has `len` bytes.
+        let memory = unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, len as usize) };
+        memory.fill(0x5a);
+
+        let path = std::env::temp_dir().join("stratovirt_test_wp_memory");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        // Userfaultfd may be unsupported or forbidden in test environment.
+        let wp_memory = match WpMemory::new(file, 16, vec![(hva, len)]) {
+            Ok(wp_memory) => wp_memory,
+            Err(_) => {
+                // SAFETY: the mapping is created above.
+                unsafe { libc::munmap(addr, len as usize) };
+                let _ = std::fs::remove_file(&path);
+                return;
+            }
+        };
+
+        // The page written by VM is saved before modified.
+        let writer = thread::spawn(move || {
+            // SAFETY: the mapping is valid until the writer is joined.
+            unsafe { *((hva + page_size) as *mut u8) = 1 };
+        });
+        writer.join().unwrap();
+        wp_memory.save_all().unwrap();
+        wp_memory.finish().unwrap();
+
+        let mut data = vec![0_u8; len as usize];
+        wp_memory.file.read_exact_at(&mut data, 16).unwrap();
+        assert!(data.iter().all(|byte| *byte == 0x5a));
+        assert_eq!(memory[page_size as usize], 1);
+
+        // SAFETY: the mapping is created above.
+        unsafe { libc::munmap(addr, len as usize) };
+        std::fs::remove_file(&path).unwrap();
+    }
+}
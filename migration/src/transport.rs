@@ -0,0 +1,183 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::{BorrowedFd, RawFd};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+
+/// Direction of the migration data relayed by the spawned process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecMode {
+    /// The process relays both directions.
+    Duplex,
+    /// The process only takes the migration data from source VM.
+    OneWaySend,
+    /// The process only gives the migration data to destination VM.
+    OneWayRecv,
+}
+
+/// Migration stream over the stdin and stdout of a spawned process.
+///
+/// # Notes
+///
+/// In duplex mode, migration data is written to stdin of the process, and the
+/// response of destination VM is read from its stdout, so the process is expected
+/// to relay both directions, such as `ssh host nc -U /path/to/socket`.
+///
+/// In one-way mode, the process only relays the migration data, such as
+/// `gzip -c > /path/to/file` at source VM and `gzip -dc /path/to/file` at
+/// destination VM. Reading fails with `ErrorKind::Unsupported` at source VM, as
+/// no response of destination VM is relayed, and the destination VM drops its
+/// responses.
+pub struct ExecStream {
+    child: Child,
+    /// Stdin of the process, `None` when receiving in one-way mode or closed.
+    stdin: Option<ChildStdin>,
+    /// Stdout of the process, `None` when sending in one-way mode.
+    stdout: Option<ChildStdout>,
+}
+
+impl ExecStream {
+    /// Spawn `cmd` by shell and connect to its stdin and stdout.
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd` - The shell command line.
+    /// * `mode` - The direction of migration data relayed by the process.
+    pub fn spawn(cmd: &str, mode: ExecMode) -> Result<Self> {
+        let (stdin, stdout) = match mode {
+            ExecMode::Duplex => (Stdio::piped(), Stdio::piped()),
+            ExecMode::OneWaySend => (Stdio::piped(), Stdio::null()),
+            ExecMode::OneWayRecv => (Stdio::null(), Stdio::piped()),
+        };
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(stdin)
+            .stdout(stdout)
+            .spawn()
+            .with_context(|| format!("Failed to spawn migration command \"{}\"", cmd))?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+
+        Ok(ExecStream {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+}
+
+impl Read for ExecStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.stdout.as_mut() {
+            Some(stdout) => stdout.read(buf),
+            None => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "Migration command doesn't relay the responses",
+            )),
+        }
+    }
+}
+
+impl Write for ExecStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            // Responses of destination VM are dropped in one-way mode.
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ExecStream {
+    fn drop(&mut self) {
+        // Close stdin, so that the process exits after relaying all the data.
+        drop(self.stdin.take());
+        match self.child.wait() {
+            Ok(status) if !status.success() => {
+                warn!("Migration command exits with {}", status);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to wait for migration command: {:?}", e),
+        }
+    }
+}
+
+/// Create migration stream from a pre-opened fd. The fd is duplicated, so
+/// the original one is still owned by the caller.
+///
+/// # Arguments
+///
+/// * `fd` - The pre-opened fd, such as a connected socket or a pipe.
+pub fn fd_stream(fd: RawFd) -> Result<File> {
+    if fd < 0 {
+        return Err(anyhow!("Invalid migration fd {}", fd));
+    }
+    // SAFETY: the fd is only borrowed to be duplicated, the validity is
+    // checked by the duplication.
+    let owned = unsafe { BorrowedFd::borrow_raw(fd) }
+        .try_clone_to_owned()
+        .with_context(|| format!("Failed to duplicate migration fd {}", fd))?;
+
+    Ok(File::from(owned))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Response, TransStatus};
+
+    #[test]
+    fn test_exec_stream() {
+        let mut stream = ExecStream::spawn("cat", ExecMode::Duplex).unwrap();
+        stream.write_all(b"stratovirt").unwrap();
+        let mut buf = [0_u8; 10];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"stratovirt");
+    }
+
+    #[test]
+    fn test_exec_stream_one_way() {
+        let mut stream = ExecStream::spawn("cat > /dev/null", ExecMode::OneWaySend).unwrap();
+        stream.write_all(b"stratovirt").unwrap();
+        let mut buf = [0_u8; 4];
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        drop(stream);
+
+        let mut stream = ExecStream::spawn("printf stratovirt", ExecMode::OneWayRecv).unwrap();
+        Response::send_msg(&mut stream, TransStatus::Ok).unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf, b"stratovirt");
+    }
+
+    #[test]
+    fn test_fd_stream() {
+        assert!(fd_stream(-1).is_err());
+
+        let mut file = fd_stream(1).unwrap();
+        assert!(file.flush().is_ok());
+    }
+}
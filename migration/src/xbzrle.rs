@@ -0,0 +1,234 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! XBZRLE (Xor Based Zero Run Length Encoding) of memory pages.
+//!
+//! A page which has been sent before is encoded as the delta against its old
+//! content. The encoded data is a sequence of `(zero run, non-zero run)` pairs,
+//! both lengths are ULEB128 encoded, and each non-zero run is followed by the
+//! new bytes of that run. Trailing zero run is omitted.
+
+use anyhow::{bail, Result};
+
+/// Size of the page unit of memory encoding.
+pub const PAGE_SIZE: u64 = 4096;
+
+fn put_uleb128(buf: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn get_uleb128(buf: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+    loop {
+        if *pos >= buf.len() || shift >= usize::BITS {
+            bail!("Invalid xbzrle encoded length");
+        }
+        let byte = buf[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+/// Encode `new` page as the delta against `old` page.
+///
+/// Returns `None` if the encoded data is not shorter than `limit`.
+///
+/// # Arguments
+///
+/// * `old` - The old content of page.
+/// * `new` - The new content of page, has the same length with `old`.
+/// * `limit` - Max length of encoded data.
+pub fn encode(old: &[u8], new: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let len = new.len();
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < len {
+        let zrun_start = i;
+        while i < len && old[i] == new[i] {
+            i += 1;
+        }
+        if i == len {
+            break;
+        }
+        put_uleb128(&mut encoded, i - zrun_start);
+
+        let nzrun_start = i;
+        while i < len && old[i] != new[i] {
+            i += 1;
+        }
+        put_uleb128(&mut encoded, i - nzrun_start);
+        encoded.extend_from_slice(&new[nzrun_start..i]);
+
+        if encoded.len() >= limit {
+            return None;
+        }
+    }
+
+    Some(encoded)
+}
+
+/// Apply the encoded delta to `page`, which holds the old content.
+///
+/// # Arguments
+///
+/// * `encoded` - The encoded data created by `encode`.
+/// * `page` - The old content of page, will be updated to the new content.
+pub fn decode(encoded: &[u8], page: &mut [u8]) -> Result<()> {
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < encoded.len() {
+        i += get_uleb128(encoded, &mut pos)?;
+        let nzrun = get_uleb128(encoded, &mut pos)?;
+        if i + nzrun > page.len() || pos + nzrun > encoded.len() {
+            bail!("Xbzrle encoded data overflows the page");
+        }
+        page[i..i + nzrun].copy_from_slice(&encoded[pos..pos + nzrun]);
+        i += nzrun;
+        pos += nzrun;
+    }
+
+    Ok(())
+}
+
+/// Cache of the pages that have been sent, it is direct-mapped by page frame
+/// number, so its memory usage is bounded by the cache size.
+#[derive(Default)]
+pub struct XbzrleCache {
+    /// Size of cache in bytes, 0 means xbzrle is disabled.
+    size: u64,
+    /// Cached pages, each entry holds the guest physical address and content.
+    pages: Vec<Option<(u64, Vec<u8>)>>,
+}
+
+impl XbzrleCache {
+    /// Create a cache holding at most `size` bytes pages.
+    pub fn new(size: u64) -> Self {
+        XbzrleCache {
+            size,
+            pages: Vec::new(),
+        }
+    }
+
+    /// Size of the cache in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether xbzrle encoding is enabled.
+    pub fn enabled(&self) -> bool {
+        self.size >= PAGE_SIZE
+    }
+
+    /// Drop all cached pages.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    fn slot(&self, gpa: u64) -> usize {
+        ((gpa / PAGE_SIZE) % (self.size / PAGE_SIZE)) as usize
+    }
+
+    /// Get the cached content of page at `gpa`.
+    pub fn get(&self, gpa: u64) -> Option<&[u8]> {
+        if !self.enabled() {
+            return None;
+        }
+        match self.pages.get(self.slot(gpa)) {
+            Some(Some((addr, data))) if *addr == gpa => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Cache the content of page at `gpa`, replacing the page in the same slot.
+    pub fn insert(&mut self, gpa: u64, data: &[u8]) {
+        if !self.enabled() {
+            return;
+        }
+        if self.pages.is_empty() {
+            self.pages.resize((self.size / PAGE_SIZE) as usize, None);
+        }
+        let slot = self.slot(gpa);
+        match &mut self.pages[slot] {
+            Some((addr, cached)) => {
+                *addr = gpa;
+                cached.clear();
+                cached.extend_from_slice(data);
+            }
+            entry => *entry = Some((gpa, data.to_vec())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xbzrle_encode_decode() {
+        let old = vec![0x5a_u8; PAGE_SIZE as usize];
+        let mut new = old.clone();
+        new[0] = 1;
+        new[100..300].fill(2);
+        new[PAGE_SIZE as usize - 1] = 3;
+
+        let encoded = encode(&old, &new, PAGE_SIZE as usize).unwrap();
+        assert!(encoded.len() < 220);
+        let mut page = old.clone();
+        decode(&encoded, &mut page).unwrap();
+        assert_eq!(page, new);
+
+        // Unchanged page is encoded as empty data.
+        assert!(encode(&old, &old, PAGE_SIZE as usize).unwrap().is_empty());
+
+        // Page changed entirely can't be encoded within the limit.
+        let new = vec![0_u8; PAGE_SIZE as usize];
+        assert!(encode(&old, &new, PAGE_SIZE as usize).is_none());
+
+        // Corrupted data is rejected.
+        let mut page = old.clone();
+        assert!(decode(&[0x80, 0x40, 0x10, 0x1], &mut page).is_err());
+    }
+
+    #[test]
+    fn test_xbzrle_cache() {
+        let mut cache = XbzrleCache::new(0);
+        cache.insert(0, &[1_u8; PAGE_SIZE as usize]);
+        assert!(cache.get(0).is_none());
+
+        let mut cache = XbzrleCache::new(PAGE_SIZE * 2);
+        cache.insert(0, &[1_u8; PAGE_SIZE as usize]);
+        cache.insert(PAGE_SIZE, &[2_u8; PAGE_SIZE as usize]);
+        assert_eq!(cache.get(0).unwrap()[0], 1);
+        assert_eq!(cache.get(PAGE_SIZE).unwrap()[0], 2);
+
+        // Page in the same slot is replaced.
+        cache.insert(PAGE_SIZE * 2, &[3_u8; PAGE_SIZE as usize]);
+        assert!(cache.get(0).is_none());
+        assert_eq!(cache.get(PAGE_SIZE * 2).unwrap()[0], 3);
+
+        cache.clear();
+        assert!(cache.get(PAGE_SIZE).is_none());
+    }
+}
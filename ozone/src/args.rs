@@ -90,6 +90,13 @@ pub fn create_args_parser<'a>() -> ArgParser<'a> {
                 .required(false)
                 .takes_values(true),
         )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .help("launch vhost-user backends, use -backend \"<binary> <args>\" ...")
+                .required(false)
+                .takes_values(true),
+        )
         .arg(
             Arg::with_name("numa")
                 .long("numa")
@@ -0,0 +1,166 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::canonicalize;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+/// Placeholder in the arguments of backend, which is replaced by the socket path.
+const SOCKET_PLACEHOLDER: &str = "{socket}";
+/// Max time to wait for the backend to create its socket.
+const SOCKET_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+const SOCKET_WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Vhost-user backend, such as vhost_user_fs and dpdk, which is launched in the same
+/// namespaces, cgroup and root directory as StratoVirt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendCfg {
+    pub exec_file_path: PathBuf,
+    /// Arguments of the backend, with the placeholder replaced.
+    pub args: Vec<String>,
+    /// Path of the vhost-user socket in the new root directory, which StratoVirt
+    /// connects to.
+    pub socket: String,
+}
+
+/// Parse the command line of backend, in format of `<binary_path> [<arguments>...]`.
+/// The arguments must contain `{socket}`, which is replaced by `/vhost-user-<index>.sock`.
+///
+/// # Arguments
+///
+/// * `cmdline` - Command line of the backend, split by whitespaces.
+/// * `index` - Index of the backend.
+pub fn parse_backend(cmdline: &str, index: usize) -> Result<BackendCfg> {
+    let mut items = cmdline.split_whitespace();
+    let exec_file = items.next().with_context(|| "Empty backend command line")?;
+    let exec_file_path = canonicalize(exec_file)
+        .with_context(|| format!("Failed to parse backend path {:?} to PathBuf", exec_file))?;
+
+    let socket = format!("/vhost-user-{}.sock", index);
+    let items: Vec<&str> = items.collect();
+    if !items.iter().any(|item| item.contains(SOCKET_PLACEHOLDER)) {
+        bail!(
+            "Arguments of backend {} should contain {} as socket path",
+            exec_file,
+            SOCKET_PLACEHOLDER
+        );
+    }
+    let args = items
+        .iter()
+        .map(|item| item.replace(SOCKET_PLACEHOLDER, &socket))
+        .collect();
+
+    Ok(BackendCfg {
+        exec_file_path,
+        args,
+        socket,
+    })
+}
+
+impl BackendCfg {
+    /// Get exec file name of the backend.
+    pub fn exec_file_name(&self) -> Result<String> {
+        match self.exec_file_path.file_name() {
+            Some(file_name) => Ok(file_name.to_string_lossy().into()),
+            None => bail!("Failed to get backend file name"),
+        }
+    }
+
+    /// Spawn the backend in the new root directory, and wait until its socket is created,
+    /// so that StratoVirt can connect to it at startup. The backend is killed when ozone,
+    /// which is replaced by StratoVirt later, exits.
+    ///
+    /// # Arguments
+    ///
+    /// * `uid` - User id of the backend.
+    /// * `gid` - Group id of the backend.
+    pub fn spawn(&self, uid: u32, gid: u32) -> Result<()> {
+        let mut exec_file = PathBuf::from("/");
+        exec_file.push(self.exec_file_name()?);
+        let mut command = Command::new(&exec_file);
+        command
+            .gid(gid)
+            .uid(uid)
+            .stdin(Stdio::null())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .args(&self.args);
+        // SAFETY: prctl is async-signal-safe, and no memory is allocated in the closure.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to spawn backend {:?}", exec_file))?;
+
+        let start = Instant::now();
+        while !Path::new(&self.socket).exists() {
+            if let Some(status) = child.try_wait()? {
+                bail!("Backend {:?} exited with {}", exec_file, status);
+            }
+            if start.elapsed() > SOCKET_WAIT_TIMEOUT {
+                let _ = child.kill();
+                bail!(
+                    "Timeout waiting for backend {:?} to create socket {}",
+                    exec_file,
+                    self.socket
+                );
+            }
+            sleep(SOCKET_WAIT_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backend() {
+        let backend = parse_backend(
+            "/bin/sh -c exec\tvhost_user_fs  -socket-path {socket} -source /share",
+            1,
+        )
+        .unwrap();
+        assert_eq!(backend.exec_file_path, canonicalize("/bin/sh").unwrap());
+        assert_eq!(backend.socket, "/vhost-user-1.sock");
+        assert_eq!(
+            backend.args,
+            vec![
+                "-c",
+                "exec",
+                "vhost_user_fs",
+                "-socket-path",
+                "/vhost-user-1.sock",
+                "-source",
+                "/share"
+            ]
+        );
+        let backend = parse_backend("/bin/sh -path=unix:{socket},server", 0).unwrap();
+        assert_eq!(backend.args, vec!["-path=unix:/vhost-user-0.sock,server"]);
+
+        assert!(parse_backend("", 0).is_err());
+        assert!(parse_backend("/bin/sh -c true", 0).is_err());
+        assert!(parse_backend("/no/such/backend {socket}", 0).is_err());
+    }
+}
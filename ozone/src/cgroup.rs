@@ -12,8 +12,7 @@
 
 use std::{
     collections::HashMap,
-    fs::{self, OpenOptions},
-    io::{BufRead, BufReader},
+    fs,
     path::{Path, PathBuf},
     process,
 };
@@ -23,9 +22,76 @@ use anyhow::{bail, Context, Result};
 use crate::OzoneError;
 
 const MOUNT_DIR: &str = "/proc/mounts";
-const CGROUP_ALLOW_LIST: [&str; 2] = ["cpuset.cpus", "memory.limit_in_bytes"];
+const CGROUP_ALLOW_LIST: [&str; 5] = [
+    "cpuset.cpus",
+    "memory.limit_in_bytes",
+    "memory.max",
+    "cpu.max",
+    "io.max",
+];
+/// Limits in `io.max`, and the corresponding files of blkio controller in cgroup v1.
+const IO_MAX_LIMITS: [(&str, &str); 4] = [
+    ("rbps", "blkio.throttle.read_bps_device"),
+    ("wbps", "blkio.throttle.write_bps_device"),
+    ("riops", "blkio.throttle.read_iops_device"),
+    ("wiops", "blkio.throttle.write_iops_device"),
+];
+/// Default period of `cpu.max` in microseconds.
+const CPU_MAX_DEFAULT_PERIOD: &str = "100000";
 pub type CgroupCfg = HashMap<String, Option<String>>;
 
+/// Cgroup hierarchy which a controller is mounted on.
+#[derive(Debug, PartialEq, Eq)]
+enum Hierarchy {
+    /// Cgroup v1, each controller has its own mount point.
+    V1(PathBuf),
+    /// Cgroup v2, all the controllers share the unified mount point.
+    V2(PathBuf),
+}
+
+impl Hierarchy {
+    /// Detect the hierarchy of controller on host. The controller is used in cgroup v1
+    /// if it's mounted there, which is the case of hybrid hierarchy.
+    ///
+    /// # Arguments
+    ///
+    /// * `controller` - Name of controller in cgroup v2, such as `cpu` and `io`.
+    fn detect(controller: &str) -> Result<Self> {
+        let mounts = fs::read_to_string(MOUNT_DIR)
+            .with_context(|| format!("Failed to read {}", MOUNT_DIR))?;
+        Self::from_mounts(&mounts, controller)
+            .with_context(|| format!("Cgroup controller {} is not mounted", controller))
+    }
+
+    fn from_mounts(mounts: &str, controller: &str) -> Option<Self> {
+        let v1_controller = match controller {
+            "io" => "blkio",
+            _ => controller,
+        };
+        let mut unified = None;
+        for line in mounts.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() < 4 {
+                continue;
+            }
+            match fields[2] {
+                "cgroup" if fields[3].split(',').any(|opt| opt == v1_controller) => {
+                    return Some(Hierarchy::V1(PathBuf::from(fields[1])));
+                }
+                "cgroup2" if unified.is_none() => unified = Some(PathBuf::from(fields[1])),
+                _ => {}
+            }
+        }
+        unified.map(Hierarchy::V2)
+    }
+
+    fn root(self) -> PathBuf {
+        match self {
+            Hierarchy::V1(root) | Hierarchy::V2(root) => root,
+        }
+    }
+}
+
 pub fn init_cgroup() -> CgroupCfg {
     let mut cgroup: CgroupCfg = HashMap::new();
     for item in CGROUP_ALLOW_LIST.iter() {
@@ -35,7 +101,7 @@ pub fn init_cgroup() -> CgroupCfg {
 }
 
 pub fn parse_cgroup(cgroup: &mut CgroupCfg, config: &str) -> Result<()> {
-    let split: Vec<&str> = config.split('=').collect();
+    let split: Vec<&str> = config.splitn(2, '=').collect();
     if split.len() != 2 {
         bail!("Invalid parameter: {:?}", &config);
     }
@@ -43,6 +109,7 @@ pub fn parse_cgroup(cgroup: &mut CgroupCfg, config: &str) -> Result<()> {
         if cgroup.get(split[0]).unwrap().is_some() {
             bail!("{} has been set more than once", &split[0]);
         }
+        check_cgroup_value(cgroup, split[0], split[1])?;
         cgroup.insert(split[0].to_string(), Some(split[1].to_string()));
     } else {
         bail!("Unknown argument: {:?}", &split[0]);
@@ -50,30 +117,166 @@ pub fn parse_cgroup(cgroup: &mut CgroupCfg, config: &str) -> Result<()> {
     Ok(())
 }
 
+fn check_cgroup_value(cgroup: &CgroupCfg, file: &str, value: &str) -> Result<()> {
+    let is_limit = |v: &str| v == "max" || v.parse::<u64>().is_ok();
+    match file {
+        "memory.limit_in_bytes" | "memory.max" => {
+            let other = if file == "memory.max" {
+                "memory.limit_in_bytes"
+            } else {
+                "memory.max"
+            };
+            if cgroup.get(other).is_some_and(Option::is_some) {
+                bail!("memory.limit_in_bytes and memory.max can't be set together");
+            }
+            if !is_limit(value) && value != "-1" {
+                bail!("Invalid memory limit: {:?}", value);
+            }
+        }
+        "cpu.max" => {
+            let fields: Vec<&str> = value.split(' ').collect();
+            if fields.len() > 2
+                || !is_limit(fields[0])
+                || fields.get(1).is_some_and(|p| p.parse::<u64>().is_err())
+            {
+                bail!(
+                    "Invalid cpu.max: {:?}, should be \"<quota>|max [<period>]\"",
+                    value
+                );
+            }
+        }
+        "io.max" => {
+            let mut fields = value.split(' ');
+            let device = fields.next().unwrap_or_default();
+            let valid_device = device.split_once(':').is_some_and(|(major, minor)| {
+                major.parse::<u32>().is_ok() && minor.parse::<u32>().is_ok()
+            });
+            let valid_limits = fields.all(|limit| {
+                limit.split_once('=').is_some_and(|(key, v)| {
+                    IO_MAX_LIMITS.iter().any(|(k, _)| *k == key) && is_limit(v)
+                })
+            });
+            if !valid_device || !valid_limits {
+                bail!(
+                    "Invalid io.max: {:?}, should be \"<major>:<minor> [rbps|wbps|riops|wiops=<limit>|max]...\"",
+                    value
+                );
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Translate the config to files and values of cgroup v1.
+fn v1_settings(file: &str, value: &str) -> Vec<(String, String)> {
+    let limit = |v: &str| if v == "max" { "-1" } else { v }.to_string();
+    match file {
+        "memory.max" | "memory.limit_in_bytes" => {
+            vec![("memory.limit_in_bytes".to_string(), limit(value))]
+        }
+        "cpu.max" => {
+            let mut fields = value.split(' ');
+            let quota = fields.next().unwrap_or_default();
+            let period = fields.next().unwrap_or(CPU_MAX_DEFAULT_PERIOD);
+            vec![
+                ("cpu.cfs_period_us".to_string(), period.to_string()),
+                ("cpu.cfs_quota_us".to_string(), limit(quota)),
+            ]
+        }
+        "io.max" => {
+            let mut fields = value.split(' ');
+            let device = fields.next().unwrap_or_default();
+            fields
+                .filter_map(|limit| limit.split_once('='))
+                .filter_map(|(key, v)| {
+                    let (_, v1_file) = IO_MAX_LIMITS.iter().find(|(k, _)| *k == key)?;
+                    // Zero means no limit in blkio controller.
+                    let v = if v == "max" { "0" } else { v };
+                    Some((v1_file.to_string(), format!("{} {}", device, v)))
+                })
+                .collect()
+        }
+        _ => vec![(file.to_string(), value.to_string())],
+    }
+}
+
+/// Translate the config to file and value of cgroup v2.
+fn v2_setting(file: &str, value: &str) -> (String, String) {
+    match file {
+        "memory.max" | "memory.limit_in_bytes" => {
+            let value = if value == "-1" { "max" } else { value };
+            ("memory.max".to_string(), value.to_string())
+        }
+        _ => (file.to_string(), value.to_string()),
+    }
+}
+
 pub fn realize_cgroup(cmd_parser: &CgroupCfg, exec_file: String, name: String) -> Result<()> {
+    let pid = process::id().to_string();
     for (file, value) in cmd_parser.iter() {
         if let Some(value_to_write) = value {
             let split: Vec<&str> = file.split('.').collect();
-            let base_path = get_base_location(split[0], &exec_file, &name)?;
-            write_cgroup_value(&base_path, file, value_to_write)?;
-            let pid = process::id();
-            write_cgroup_value(&base_path, "tasks", &pid.to_string())?;
+            match Hierarchy::detect(split[0])? {
+                Hierarchy::V1(root) => {
+                    let base_path = root.join(&exec_file).join(&name);
+                    for (file, value) in v1_settings(file, value_to_write) {
+                        write_cgroup_v1_value(&base_path, &file, &value)?;
+                    }
+                    write_cgroup_value(&base_path, "tasks", &pid)?;
+                }
+                Hierarchy::V2(root) => {
+                    let base_path = enable_controller(&root, split[0], &exec_file, &name)?;
+                    let (file, value) = v2_setting(file, value_to_write);
+                    write_cgroup_value(&base_path, &file, &value)?;
+                    write_cgroup_value(&base_path, "cgroup.procs", &pid)?;
+                }
+            }
         }
     }
 
     Ok(())
 }
 
+/// Enable the controller for the cgroup of ozone in cgroup v2, which must be enabled in
+/// all the ancestors. Returns path of the cgroup.
+fn enable_controller(
+    root: &Path,
+    controller: &str,
+    exec_file: &str,
+    name: &str,
+) -> Result<PathBuf> {
+    let controllers = read_file_value(root.join("cgroup.controllers"))?;
+    if !controllers.split(' ').any(|c| c == controller) {
+        bail!(
+            "Cgroup controller {} is not available in cgroup v2",
+            controller
+        );
+    }
+    let exec_path = root.join(exec_file);
+    let base_path = exec_path.join(name);
+    fs::create_dir_all(&base_path)
+        .with_context(|| format!("Failed to create directory: {:?}", base_path))?;
+    for path in [root, exec_path.as_path()] {
+        write_cgroup_value(path, "cgroup.subtree_control", &format!("+{}", controller))?;
+    }
+    Ok(base_path)
+}
+
+fn remove_cgroup_dir(base_path: &Path) -> Result<()> {
+    if base_path.exists() {
+        std::fs::remove_dir(base_path)
+            .with_context(|| format!("Failed to remove cgroup directory {:?}", base_path))?;
+    }
+    Ok(())
+}
+
 pub fn clean_cgroup(cmd_parser: &CgroupCfg, exec_file: String, name: String) -> Result<()> {
     for (file, value) in cmd_parser.iter() {
         if value.is_some() {
             let split: Vec<&str> = file.split('.').collect();
             let base_path = get_base_location(split[0], &exec_file, &name)?;
-            if base_path.exists() {
-                std::fs::remove_dir(&base_path).with_context(|| {
-                    format!("Failed to remove cgroup directory {:?}", &base_path)
-                })?;
-            }
+            remove_cgroup_dir(&base_path)?;
         }
     }
 
@@ -82,41 +285,24 @@ pub fn clean_cgroup(cmd_parser: &CgroupCfg, exec_file: String, name: String) ->
 
 pub fn clean_node(exec_file: String, name: String) -> Result<()> {
     let base_path = get_base_location("cpuset", &exec_file, &name)?;
-    if base_path.exists() {
-        std::fs::remove_dir(&base_path)
-            .with_context(|| format!("Failed to remove cgroup directory {:?}", &base_path))?;
-    }
-
-    Ok(())
+    remove_cgroup_dir(&base_path)
 }
 
 fn get_base_location(controller: &str, exec_file: &str, name: &str) -> Result<PathBuf> {
-    let mut target_path = PathBuf::new();
-    let dir = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(MOUNT_DIR)
-        .with_context(|| "Failed to open '/proc/mounts' ")?;
-    let dir_bufs = BufReader::new(dir);
-    for dir in dir_bufs.lines() {
-        let dir = dir.with_context(|| "Failed to read directory in directory buf")?;
-        if dir.starts_with("cgroup") && dir.contains(controller) {
-            let split: Vec<&str> = dir.split(' ').collect();
-            target_path = PathBuf::from(split[1]);
-            break;
-        }
-    }
-    if target_path.eq(&PathBuf::new()) {
-        bail!("Failed to get base location");
-    }
+    let mut target_path = Hierarchy::detect(controller)
+        .with_context(|| "Failed to get base location")?
+        .root();
     target_path.push(exec_file);
     target_path.push(name);
     Ok(target_path)
 }
 
 pub fn set_numa_node(node: &str, exec_file: &str, name: &str) -> Result<()> {
-    let write_path = get_base_location("cpuset", exec_file, name)?;
-    write_cgroup_value(&write_path, "cpuset.mems", node)
+    let write_path = match Hierarchy::detect("cpuset")? {
+        Hierarchy::V1(root) => root.join(exec_file).join(name),
+        Hierarchy::V2(root) => return set_numa_node_v2(&root, node, exec_file, name),
+    };
+    write_cgroup_v1_value(&write_path, "cpuset.mems", node)
         .with_context(|| OzoneError::WriteError("cpuset.mems".to_string(), node.to_string()))?;
 
     let mut upper_path = write_path.clone();
@@ -141,14 +327,19 @@ pub fn set_numa_node(node: &str, exec_file: &str, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Cpus are inherited from parent in cgroup v2 if not set, so only mems is written.
+fn set_numa_node_v2(root: &Path, node: &str, exec_file: &str, name: &str) -> Result<()> {
+    let write_path = enable_controller(root, "cpuset", exec_file, name)?;
+    write_cgroup_value(&write_path, "cpuset.mems", node)
+        .with_context(|| OzoneError::WriteError("cpuset.mems".to_string(), node.to_string()))?;
+    write_cgroup_value(&write_path, "cgroup.procs", &process::id().to_string())
+        .with_context(|| "Failed to attach pid")
+}
+
 fn write_cgroup_value(path: &Path, file: &str, value: &str) -> Result<()> {
-    if file != "tasks" {
-        if !path.exists() {
-            fs::create_dir_all(path)
-                .with_context(|| format!("Failed to create directory: {:?}", path))?;
-        }
-        inherit_config(path, file)
-            .with_context(|| format!("Failed to inherit configuration for path: {:?}", &path))?;
+    if !path.exists() {
+        fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create directory: {:?}", path))?;
     }
 
     let mut path_to_write = path.to_path_buf();
@@ -163,6 +354,19 @@ fn write_cgroup_value(path: &Path, file: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// Write value to cgroup v1, cpuset inherits configuration from ancestors first.
+fn write_cgroup_v1_value(path: &Path, file: &str, value: &str) -> Result<()> {
+    if file.starts_with("cpuset.") {
+        if !path.exists() {
+            fs::create_dir_all(path)
+                .with_context(|| format!("Failed to create directory: {:?}", path))?;
+        }
+        inherit_config(path, file)
+            .with_context(|| format!("Failed to inherit configuration for path: {:?}", &path))?;
+    }
+    write_cgroup_value(path, file, value)
+}
+
 fn read_file_value(path: PathBuf) -> Result<String> {
     let mut value =
         fs::read_to_string(&path).with_context(|| format!("Failed to read path: {:?}", &path))?;
@@ -244,4 +448,103 @@ mod tests {
         }
         assert!(cgroup.get("memory.limit_in_bytes").unwrap().is_none());
     }
+    #[test]
+    fn test_parse_cgroup_v2() {
+        let mut cgroup = init_cgroup();
+        assert!(parse_cgroup(&mut cgroup, "cpu.max=50000 100000").is_ok());
+        assert!(parse_cgroup(&mut cgroup, "io.max=8:0 rbps=1048576 wiops=max").is_ok());
+        assert!(parse_cgroup(&mut cgroup, "memory.max=max").is_ok());
+        assert!(parse_cgroup(&mut cgroup, "memory.limit_in_bytes=1000000").is_err());
+        assert_eq!(
+            cgroup.get("io.max").unwrap().as_deref(),
+            Some("8:0 rbps=1048576 wiops=max")
+        );
+
+        let mut cgroup = init_cgroup();
+        assert!(parse_cgroup(&mut cgroup, "cpu.max=max").is_ok());
+        assert!(parse_cgroup(&mut cgroup, "memory.max=1g").is_err());
+        for config in [
+            "cpu.max=half",
+            "cpu.max=50000 100000 1",
+            "cpu.max=50000 max",
+            "io.max=8 rbps=1",
+            "io.max=8:0 bps=1",
+            "io.max=8:0 rbps",
+        ] {
+            let mut cgroup = init_cgroup();
+            assert!(parse_cgroup(&mut cgroup, config).is_err(), "{}", config);
+        }
+    }
+
+    #[test]
+    fn test_cgroup_hierarchy() {
+        let v1_mounts = "cgroup2 /sys/fs/cgroup/unified cgroup2 rw,nsdelegate 0 0
+cgroup /sys/fs/cgroup/cpu,cpuacct cgroup rw,nosuid,cpu,cpuacct 0 0
+cgroup /sys/fs/cgroup/blkio cgroup rw,nosuid,blkio 0 0
+cgroup /sys/fs/cgroup/cpuset cgroup rw,nosuid,cpuset 0 0";
+        assert_eq!(
+            Hierarchy::from_mounts(v1_mounts, "cpu"),
+            Some(Hierarchy::V1(PathBuf::from("/sys/fs/cgroup/cpu,cpuacct")))
+        );
+        assert_eq!(
+            Hierarchy::from_mounts(v1_mounts, "io"),
+            Some(Hierarchy::V1(PathBuf::from("/sys/fs/cgroup/blkio")))
+        );
+        // Controllers which aren't used in cgroup v1 are in the unified hierarchy.
+        assert_eq!(
+            Hierarchy::from_mounts(v1_mounts, "memory"),
+            Some(Hierarchy::V2(PathBuf::from("/sys/fs/cgroup/unified")))
+        );
+
+        let v2_mounts = "proc /proc proc rw,nosuid 0 0
+cgroup2 /sys/fs/cgroup cgroup2 rw,nosuid,nsdelegate,memory_recursiveprot 0 0";
+        assert_eq!(
+            Hierarchy::from_mounts(v2_mounts, "memory"),
+            Some(Hierarchy::V2(PathBuf::from("/sys/fs/cgroup")))
+        );
+        assert_eq!(
+            Hierarchy::from_mounts("proc /proc proc rw 0 0", "cpu"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cgroup_settings() {
+        let settings = |v: Vec<(&str, &str)>| -> Vec<(String, String)> {
+            v.iter()
+                .map(|(f, v)| (f.to_string(), v.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            v1_settings("memory.max", "max"),
+            settings(vec![("memory.limit_in_bytes", "-1")])
+        );
+        assert_eq!(
+            v1_settings("cpu.max", "50000"),
+            settings(vec![
+                ("cpu.cfs_period_us", "100000"),
+                ("cpu.cfs_quota_us", "50000")
+            ])
+        );
+        assert_eq!(
+            v1_settings("io.max", "8:0 rbps=1048576 wiops=max"),
+            settings(vec![
+                ("blkio.throttle.read_bps_device", "8:0 1048576"),
+                ("blkio.throttle.write_iops_device", "8:0 0")
+            ])
+        );
+        assert_eq!(
+            v1_settings("cpuset.cpus", "4-5"),
+            settings(vec![("cpuset.cpus", "4-5")])
+        );
+
+        assert_eq!(
+            v2_setting("memory.limit_in_bytes", "-1"),
+            ("memory.max".to_string(), "max".to_string())
+        );
+        assert_eq!(
+            v2_setting("cpu.max", "50000 100000"),
+            ("cpu.max".to_string(), "50000 100000".to_string())
+        );
+    }
 }
@@ -20,6 +20,7 @@ use std::{
 
 use anyhow::{anyhow, bail, Context, Result};
 
+use crate::backend::{parse_backend, BackendCfg};
 use crate::cgroup::{self, init_cgroup, parse_cgroup, CgroupCfg};
 use crate::OzoneError;
 use crate::{capability, namespace, syscall};
@@ -61,6 +62,7 @@ pub struct OzoneHandler {
     exec_file_path: PathBuf,
     chroot_dir: PathBuf,
     source_file_paths: Vec<PathBuf>,
+    backends: Vec<BackendCfg>,
     extra_args: Vec<String>,
 }
 
@@ -123,12 +125,21 @@ impl OzoneHandler {
             }
             handler.cgroup = Some(cgroup_cfg);
         }
+        if let Some(backends) = args.values_of("backend") {
+            for (index, cmdline) in backends.iter().enumerate() {
+                handler.backends.push(
+                    parse_backend(cmdline, index)
+                        .with_context(|| format!("Failed to parse backend {:?}", cmdline))?,
+                );
+            }
+        }
         handler.extra_args = args.extra_args();
         handler.netns_path = args.value_of("network namespace");
         handler.capability = args.value_of("capability");
         handler.chroot_dir = PathBuf::from(BASE_OZONE_PATH);
         handler.chroot_dir.push(handler.exec_file_name()?);
         handler.chroot_dir.push(Path::new(&handler.name));
+        handler.check_backend_names()?;
 
         Ok(handler)
     }
@@ -157,6 +168,34 @@ impl OzoneHandler {
         Ok(())
     }
 
+    /// Backends are copied to chroot directory by file name, which should not conflict.
+    fn check_backend_names(&self) -> Result<()> {
+        let mut names = vec![self.exec_file_name()?];
+        for backend in self.backends.iter() {
+            let name = backend.exec_file_name()?;
+            if names.contains(&name) {
+                bail!("Backend {:?} conflicts with the name of exec file or another backend, please rename it", backend.exec_file_path);
+            }
+            names.push(name);
+        }
+        Ok(())
+    }
+
+    /// Copy binary files of backends to chroot directory.
+    fn copy_backend_files(&self) -> Result<()> {
+        for backend in self.backends.iter() {
+            let mut chroot_file = self.chroot_dir.clone();
+            chroot_file.push(backend.exec_file_name()?);
+            std::fs::copy(&backend.exec_file_path, chroot_file).with_context(|| {
+                format!(
+                    "Failed to copy backend {:?} to new chroot dir",
+                    backend.exec_file_path
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     /// Bind mount 'file_path' into chroot directory.
     ///
     /// # Arguments
@@ -235,6 +274,7 @@ impl OzoneHandler {
 
         self.create_chroot_dir()?;
         self.copy_exec_file()?;
+        self.copy_backend_files()?;
         for source_file_path in self.source_file_paths.iter() {
             self.bind_mount_file(source_file_path)?;
         }
@@ -276,6 +316,11 @@ impl OzoneHandler {
                 .with_context(|| "Failed to clean all capability for ozone.")?;
         }
 
+        // Backends are children of StratoVirt, which share its namespaces and cgroup.
+        for backend in self.backends.iter() {
+            backend.spawn(self.uid, self.gid)?;
+        }
+
         let mut chroot_exec_file = PathBuf::from("/");
         chroot_exec_file.push(self.exec_file_name()?);
         Err(anyhow!(OzoneError::ExecError(
@@ -373,6 +418,7 @@ mod tests {
             netns_path: None,
             chroot_dir,
             source_file_paths,
+            backends: Vec::new(),
             extra_args: Vec::new(),
             capability: None,
             node: None,
@@ -393,4 +439,17 @@ mod tests {
         let exec_file = exec_file.unwrap();
         assert_eq!(exec_file, "stratovirt");
     }
+
+    #[test]
+    fn test_check_backend_names() {
+        let mut handler = create_handler();
+        handler
+            .backends
+            .push(parse_backend("/bin/sh {socket}", 0).unwrap());
+        assert!(handler.check_backend_names().is_ok());
+        handler
+            .backends
+            .push(parse_backend("/bin/sh {socket}", 1).unwrap());
+        assert!(handler.check_backend_names().is_err());
+    }
 }
@@ -13,6 +13,7 @@
 pub mod error;
 
 mod args;
+mod backend;
 mod capability;
 mod cgroup;
 mod handler;
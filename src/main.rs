@@ -17,22 +17,40 @@ use anyhow::{bail, Context, Result};
 use log::{error, info};
 use thiserror::Error;
 
-use machine::{LightMachine, MachineOps, StdMachine};
+use chardev_backend::monitor::ChardevMonitor;
+use machine::{
+    register_crash_dump_devices, start_mem_pressure_monitor, LightMachine, MachineOps, StdMachine,
+};
 use machine_manager::{
     cmdline::{check_api_channel, create_args_parser, create_vmconfig},
-    config::MachineType,
-    config::VmConfig,
+    config::{parse_bench, MachineType, VmConfig},
     event_loop::EventLoop,
+    hang_watchdog::start_hang_watchdog,
+    qmp::qmp_audit::set_qmp_audit,
     qmp::qmp_channel::QmpChannel,
-    qmp::qmp_socket::Socket,
+    qmp::qmp_socket::{lock_config, Socket},
+    qmp::qmp_tls::QmpTlsMonitor,
     signal_handler::{exit_with_code, handle_signal, register_kill_signal, VM_EXIT_GENE_ERR},
     temp_cleaner::TempCleaner,
     test_server::TestSock,
 };
+use util::boot_time::{init_boot_clock, BootStage, BootStageTimer};
+use util::crash_dump::{set_crash_dump_dir, write_crash_dump};
+use util::logger::{LogFormat, LogOutput};
 use util::loop_context::EventNotifierHelper;
+use util::seclabel::set_security_label;
 use util::test_helper::{is_test_enabled, set_test_enabled};
+use util::trace::set_trace_history_size;
+use util::unix::{chroot, drop_privileges};
+use util::vmm_limits::{set_malloc_arena_max, set_rlimit, set_vmm_cgroup, Resource};
 use util::{arg_parser, daemonize::daemonize, logger, set_termi_canon_mode};
 
+/// jemalloc keeps a cache for each thread and spreads threads over arenas, which avoids the lock
+/// contention of allocations in vcpu and iothreads.
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
 #[derive(Error, Debug)]
 enum MainError {
     #[error("Manager")]
@@ -86,7 +104,27 @@ fn main() {
     });
 }
 
+/// Run `stratovirt bench <mode>[,<option>=<value>]...`, which benchmarks virtqueue and
+/// the backends without guest.
+fn run_bench(args: &[String]) -> Result<()> {
+    if args.len() != 1 {
+        bail!("Usage: stratovirt bench <queue|aio|tap>[,<option>=<value>]...");
+    }
+    let config = parse_bench(&args[0])?;
+    logger::init_log(String::new())?;
+    let result = virtio::bench::run_bench(&config).with_context(|| "Failed to run bench")?;
+    println!("{}", result);
+    Ok(())
+}
+
 fn run() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("bench") {
+        return run_bench(&args[2..]);
+    }
+
+    init_boot_clock();
+    let config_parse = BootStageTimer::new(BootStage::ConfigParse, None);
     let cmd_args = create_args_parser().get_matches()?;
 
     if cmd_args.is_present("mod-test") {
@@ -94,17 +132,42 @@ fn run() -> Result<()> {
     }
 
     let logfile_path = cmd_args.value_of("display log").unwrap_or_default();
-    logger::init_log(logfile_path)?;
+    let log_output = match cmd_args.value_of("log-output") {
+        Some(_) if !logfile_path.is_empty() => {
+            bail!("-log-output can't be used with -D <log path> together.")
+        }
+        Some(output) => output.parse::<LogOutput>()?,
+        None if logfile_path.is_empty() => LogOutput::Stderr,
+        None => LogOutput::File(logfile_path),
+    };
+    let log_format = match cmd_args.value_of("log-format") {
+        Some(format) => format.parse::<LogFormat>()?,
+        None => LogFormat::Text,
+    };
+    logger::init_log_output(
+        log_output,
+        log_format,
+        cmd_args.value_of("log-filter").as_deref(),
+        cmd_args.value_of("name").as_deref(),
+    )?;
 
     std::panic::set_hook(Box::new(|panic_msg| {
         set_termi_canon_mode().expect("Failed to set terminal to canonical mode.");
 
         let panic_file = panic_msg.location().map_or("", |loc| loc.file());
         let panic_line = panic_msg.location().map_or(0, |loc| loc.line());
-        if let Some(msg) = panic_msg.payload().downcast_ref::<&str>() {
-            error!("Panic at [{}: {}]: {}.", panic_file, panic_line, msg);
+        let reason = if let Some(msg) = panic_msg.payload().downcast_ref::<&str>() {
+            format!("Panic at [{}: {}]: {}.", panic_file, panic_line, msg)
+        } else if let Some(msg) = panic_msg.payload().downcast_ref::<String>() {
+            format!("Panic at [{}: {}]: {}.", panic_file, panic_line, msg)
         } else {
-            error!("Panic at [{}: {}].", panic_file, panic_line);
+            format!("Panic at [{}: {}].", panic_file, panic_line)
+        };
+        error!("{}", reason);
+        match write_crash_dump(&reason) {
+            Ok(Some(path)) => error!("Crash dump is written to {}", path),
+            Ok(None) => (),
+            Err(e) => error!("Failed to write crash dump: {:?}", e),
         }
 
         // clean temporary file
@@ -113,6 +176,7 @@ fn run() -> Result<()> {
     }));
 
     let mut vm_config: VmConfig = create_vmconfig(&cmd_args)?;
+    drop(config_parse);
     info!("VmConfig is {:?}", vm_config);
 
     match real_main(&cmd_args, &mut vm_config) {
@@ -141,6 +205,30 @@ fn run() -> Result<()> {
 
 fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Result<()> {
     TempCleaner::object_init();
+    // Malloc arenas are created for threads, so the cap must be set before threads start.
+    if let Some(arenas) = vm_config.vmm_limits.malloc_arenas {
+        set_malloc_arena_max(arenas)?;
+    }
+    if let Some(memlock) = vm_config.vmm_limits.memlock {
+        set_rlimit(Resource::RLIMIT_MEMLOCK, memlock)?;
+    }
+    if let Some(nofile) = vm_config.vmm_limits.nofile {
+        set_rlimit(Resource::RLIMIT_NOFILE, nofile)?;
+    }
+    // Resources are labeled when created, so the label must be set first.
+    if let Some(seclabel) = vm_config.seclabel.clone() {
+        set_security_label(seclabel)?;
+    }
+    if let Some(qmp_audit) = &vm_config.qmp_audit {
+        set_qmp_audit(qmp_audit)?;
+    }
+    if vm_config.lock_config {
+        lock_config();
+    }
+    if let Some(crash_dump) = &vm_config.crash_dump {
+        set_crash_dump_dir(&crash_dump.dir)?;
+        set_trace_history_size(crash_dump.trace_events);
+    }
 
     if cmd_args.is_present("daemonize") {
         match daemonize(cmd_args.value_of("pidfile")) {
@@ -159,8 +247,11 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
     QmpChannel::object_init();
     EventLoop::object_init(&vm_config.iothreads)?;
     register_kill_signal();
+    if let Some(hang_watchdog) = &vm_config.hang_watchdog {
+        start_hang_watchdog(hang_watchdog)?;
+    }
 
-    let listeners = check_api_channel(cmd_args, vm_config)?;
+    let (listeners, mut mon_chardev, mut qmp_tls) = check_api_channel(cmd_args, vm_config)?;
     let mut sockets = Vec::new();
     let vm: Arc<Mutex<dyn MachineOps + Send + Sync>> = match vm_config.machine_config.mach_type {
         MachineType::MicroVm => {
@@ -176,6 +267,14 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            if let Some(cfg) = mon_chardev.take() {
+                ChardevMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create monitor on chardev")?;
+            }
+            if let Some(cfg) = qmp_tls.take() {
+                QmpTlsMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create qmp monitor on tls")?;
+            }
             vm
         }
         MachineType::StandardVm => {
@@ -201,6 +300,14 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            if let Some(cfg) = mon_chardev.take() {
+                ChardevMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create monitor on chardev")?;
+            }
+            if let Some(cfg) = qmp_tls.take() {
+                QmpTlsMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create qmp monitor on tls")?;
+            }
             vm
         }
         MachineType::None => {
@@ -215,10 +322,25 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
             for listener in listeners {
                 sockets.push(Socket::from_unix_listener(listener, Some(vm.clone())));
             }
+            if let Some(cfg) = mon_chardev.take() {
+                ChardevMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create monitor on chardev")?;
+            }
+            if let Some(cfg) = qmp_tls.take() {
+                QmpTlsMonitor::realize(cfg, vm.clone())
+                    .with_context(|| "Failed to create qmp monitor on tls")?;
+            }
             vm
         }
     };
 
+    if vm_config.crash_dump.is_some() {
+        register_crash_dump_devices(&vm);
+    }
+    if let Some(mem_pressure) = &vm_config.mem_pressure {
+        start_mem_pressure_monitor(mem_pressure)?;
+    }
+
     for socket in sockets {
         EventLoop::update_event(
             EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(socket))),
@@ -227,13 +349,25 @@ fn real_main(cmd_args: &arg_parser::ArgMatches, vm_config: &mut VmConfig) -> Res
         .with_context(|| "Failed to add api event to MainLoop")?;
     }
 
+    // vCPU threads are started later, they stay in the original cgroup by themselves.
+    if let Some(cgroup) = &vm_config.vmm_limits.cgroup {
+        set_vmm_cgroup(cgroup)?;
+    }
     machine::vm_run(&vm, cmd_args).with_context(|| "Failed to start VM.")?;
 
+    // Devices and sockets have been opened, the privileges are no longer needed.
+    if let Some(dir) = &vm_config.privilege.chroot {
+        chroot(dir)?;
+    }
+    if let Some((uid, gid)) = vm_config.privilege.runas {
+        drop_privileges(uid, gid)?;
+    }
+
     let balloon_switch_on = vm_config.dev_name.get("balloon").is_some();
-    if !cmd_args.is_present("disable-seccomp") {
+    if vm_config.sandbox.enable {
         vm.lock()
             .unwrap()
-            .register_seccomp(balloon_switch_on)
+            .register_seccomp(balloon_switch_on, &vm_config.sandbox)
             .with_context(|| "Failed to register seccomp rules.")?;
     }
 
@@ -18,7 +18,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use log::error;
 use once_cell::sync::Lazy;
 
@@ -551,6 +551,23 @@ pub fn graphic_hardware_ui_info(
     Ok(())
 }
 
+/// Notify the hardware of the display console to change its size, as if the window of
+/// display client is resized.
+///
+/// # Arguments
+///
+/// * `dev_name` - Name of the display console.
+/// * `width` - Width of the display.
+/// * `height` - Height of the display.
+pub fn display_resize(dev_name: &str, width: u32, height: u32) -> Result<()> {
+    let con = CONSOLES
+        .lock()
+        .unwrap()
+        .get_console_by_dev_name(dev_name.to_string())
+        .with_context(|| format!("Display console {} not found", dev_name))?;
+    graphic_hardware_ui_info(con, width, height)
+}
+
 /// Get the weak reference of all active consoles from the console lists.
 pub fn get_active_console() -> Vec<Weak<Mutex<DisplayConsole>>> {
     let mut res: Vec<Weak<Mutex<DisplayConsole>>> = vec![];
@@ -186,3 +186,113 @@ pub const KEYSYM2KEYCODE: [(u16, u16); 173] = [
     (0xFFEC, 0x00DC),
     (0xFFFF, 0x00D3),
 ];
+
+/// Qcodes of qmp, which are named after the keys of US keyboard, and their keycodes.
+pub const QCODE2KEYCODE: [(&str, u16); 105] = [
+    // (Qcode, Keycode)
+    ("esc", 0x0001),
+    ("1", 0x0002),
+    ("2", 0x0003),
+    ("3", 0x0004),
+    ("4", 0x0005),
+    ("5", 0x0006),
+    ("6", 0x0007),
+    ("7", 0x0008),
+    ("8", 0x0009),
+    ("9", 0x000A),
+    ("0", 0x000B),
+    ("minus", 0x000C),
+    ("equal", 0x000D),
+    ("backspace", 0x000E),
+    ("tab", 0x000F),
+    ("q", 0x0010),
+    ("w", 0x0011),
+    ("e", 0x0012),
+    ("r", 0x0013),
+    ("t", 0x0014),
+    ("y", 0x0015),
+    ("u", 0x0016),
+    ("i", 0x0017),
+    ("o", 0x0018),
+    ("p", 0x0019),
+    ("bracket_left", 0x001A),
+    ("bracket_right", 0x001B),
+    ("ret", 0x001C),
+    ("ctrl", 0x001D),
+    ("a", 0x001E),
+    ("s", 0x001F),
+    ("d", 0x0020),
+    ("f", 0x0021),
+    ("g", 0x0022),
+    ("h", 0x0023),
+    ("j", 0x0024),
+    ("k", 0x0025),
+    ("l", 0x0026),
+    ("semicolon", 0x0027),
+    ("apostrophe", 0x0028),
+    ("grave_accent", 0x0029),
+    ("shift", 0x002A),
+    ("backslash", 0x002B),
+    ("z", 0x002C),
+    ("x", 0x002D),
+    ("c", 0x002E),
+    ("v", 0x002F),
+    ("b", 0x0030),
+    ("n", 0x0031),
+    ("m", 0x0032),
+    ("comma", 0x0033),
+    ("dot", 0x0034),
+    ("slash", 0x0035),
+    ("shift_r", 0x0036),
+    ("kp_multiply", 0x0037),
+    ("alt", 0x0038),
+    ("spc", 0x0039),
+    ("caps_lock", 0x003A),
+    ("f1", 0x003B),
+    ("f2", 0x003C),
+    ("f3", 0x003D),
+    ("f4", 0x003E),
+    ("f5", 0x003F),
+    ("f6", 0x0040),
+    ("f7", 0x0041),
+    ("f8", 0x0042),
+    ("f9", 0x0043),
+    ("f10", 0x0044),
+    ("num_lock", 0x0045),
+    ("scroll_lock", 0x0046),
+    ("kp_7", 0x0047),
+    ("kp_8", 0x0048),
+    ("kp_9", 0x0049),
+    ("kp_subtract", 0x004A),
+    ("kp_4", 0x004B),
+    ("kp_5", 0x004C),
+    ("kp_6", 0x004D),
+    ("kp_add", 0x004E),
+    ("kp_1", 0x004F),
+    ("kp_2", 0x0050),
+    ("kp_3", 0x0051),
+    ("kp_0", 0x0052),
+    ("kp_decimal", 0x0053),
+    ("sysrq", 0x0054),
+    ("less", 0x0056),
+    ("f11", 0x0057),
+    ("f12", 0x0058),
+    ("kp_enter", 0x009C),
+    ("ctrl_r", 0x009D),
+    ("kp_divide", 0x00B5),
+    ("print", 0x00B7),
+    ("alt_r", 0x00B8),
+    ("home", 0x00C7),
+    ("up", 0x00C8),
+    ("pgup", 0x00C9),
+    ("left", 0x00CB),
+    ("right", 0x00CD),
+    ("end", 0x00CF),
+    ("down", 0x00D0),
+    ("pgdn", 0x00D1),
+    ("insert", 0x00D2),
+    ("delete", 0x00D3),
+    ("meta_l", 0x00DB),
+    ("meta_r", 0x00DC),
+    ("menu", 0x00DD),
+];
@@ -19,7 +19,7 @@ use anyhow::Result;
 use log::debug;
 use once_cell::sync::Lazy;
 
-use crate::data::keycode::KEYSYM2KEYCODE;
+use crate::data::keycode::{KEYSYM2KEYCODE, QCODE2KEYCODE};
 use util::bitmap::Bitmap;
 
 // Logical window size for mouse.
@@ -59,6 +59,9 @@ const NUM_LOCK_LED: u8 = 0x1;
 const CAPS_LOCK_LED: u8 = 0x2;
 pub const SCROLL_LOCK_LED: u8 = 0x4;
 /// Input button state.
+pub const INPUT_BUTTON_LEFT: u32 = 0x01;
+pub const INPUT_BUTTON_RIGHT: u32 = 0x02;
+pub const INPUT_BUTTON_MIDDLE: u32 = 0x04;
 pub const INPUT_BUTTON_WHEEL_UP: u32 = 0x08;
 pub const INPUT_BUTTON_WHEEL_DOWN: u32 = 0x10;
 pub const INPUT_BUTTON_WHEEL_LEFT: u32 = 0x20;
@@ -212,6 +215,14 @@ struct LedState {
     kbd_led: u8,
 }
 
+/// The latest state of pointer sent to guest.
+#[derive(Default, Clone, Copy)]
+pub struct PointerState {
+    pub button: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
 #[derive(Default)]
 struct Inputs {
     kbd_ids: Vec<String>,
@@ -219,6 +230,7 @@ struct Inputs {
     tablet_ids: Vec<String>,
     tablet_lists: HashMap<String, Arc<Mutex<dyn PointerOpts>>>,
     keyboard_state: KeyBoardState,
+    pointer_state: PointerState,
 }
 
 impl Inputs {
@@ -322,7 +334,10 @@ pub fn press_mouse(button: u32, x: u32, y: u32) -> Result<()> {
 }
 
 pub fn point_event(button: u32, x: u32, y: u32) -> Result<()> {
-    let mouse = INPUTS.lock().unwrap().get_active_mouse();
+    let mut locked_input = INPUTS.lock().unwrap();
+    locked_input.pointer_state = PointerState { button, x, y };
+    let mouse = locked_input.get_active_mouse();
+    drop(locked_input);
     if let Some(m) = mouse {
         m.lock().unwrap().do_point_event(button, x, y)?;
     }
@@ -380,6 +395,19 @@ pub fn release_all_key() -> Result<()> {
     Ok(())
 }
 
+/// Get the latest state of pointer, so that an event changing part of it can be sent.
+pub fn get_pointer_state() -> PointerState {
+    INPUTS.lock().unwrap().pointer_state
+}
+
+/// Get the keycode of qcode, which is the name of key used by qmp, such as `ctrl` and `a`.
+pub fn qcode_to_keycode(qcode: &str) -> Option<u16> {
+    QCODE2KEYCODE
+        .iter()
+        .find(|(name, _)| *name == qcode)
+        .map(|&(_, keycode)| keycode)
+}
+
 pub fn get_kbd_led_state(state: u8) -> bool {
     LED_STATE.lock().unwrap().kbd_led & state == state
 }
@@ -469,5 +497,13 @@ mod tests {
         assert_eq!(test_mouse.lock().unwrap().button, 1);
         assert_eq!(test_mouse.lock().unwrap().x, 54);
         assert_eq!(test_mouse.lock().unwrap().y, 12);
+        let state = get_pointer_state();
+        assert_eq!((state.button, state.x, state.y), (1, 54, 12));
+
+        // Test qcode.
+        assert_eq!(qcode_to_keycode("ctrl"), Some(KEYCODE_CTRL));
+        assert_eq!(qcode_to_keycode("ctrl_r"), Some(KEYCODE_CTRL_R));
+        assert_eq!(qcode_to_keycode("1"), Some(KEYCODE_1));
+        assert_eq!(qcode_to_keycode("unknown"), None);
     }
 }
@@ -0,0 +1,154 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Breakdown of the boot time of VM.
+//!
+//! All the timestamps are relative to the boot clock base, which is set by `init_boot_clock`
+//! at the very beginning of StratoVirt. Stages are no longer recorded once the first vCPU
+//! runs, so that reboot or hotplug doesn't pollute the breakdown.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+static BOOT_CLOCK_BASE: Lazy<Instant> = Lazy::new(Instant::now);
+static BOOT_RECORDS: Mutex<Vec<BootRecord>> = Mutex::new(Vec::new());
+/// Nanoseconds plus one from the boot clock base to the first vCPU run, 0 if no vCPU has run.
+static FIRST_VCPU_RUN: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootStage {
+    ConfigParse,
+    MemorySetup,
+    DeviceRealize,
+    FirmwareLoad,
+}
+
+impl BootStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootStage::ConfigParse => "config-parse",
+            BootStage::MemorySetup => "memory-setup",
+            BootStage::DeviceRealize => "device-realize",
+            BootStage::FirmwareLoad => "firmware-load",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootRecord {
+    pub stage: BootStage,
+    /// Id of the device for `DeviceRealize` stage.
+    pub id: Option<String>,
+    /// Start of the stage relative to the boot clock base.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Timer of a boot stage, the stage is recorded when the timer is dropped.
+pub struct BootStageTimer {
+    stage: BootStage,
+    id: Option<String>,
+    start: Instant,
+}
+
+impl BootStageTimer {
+    pub fn new(stage: BootStage, id: Option<&str>) -> Self {
+        BootStageTimer {
+            stage,
+            id: id.map(String::from),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for BootStageTimer {
+    fn drop(&mut self) {
+        if first_vcpu_run().is_some() {
+            return;
+        }
+        let record = BootRecord {
+            stage: self.stage,
+            id: self.id.take(),
+            start: self.start.saturating_duration_since(*BOOT_CLOCK_BASE),
+            duration: self.start.elapsed(),
+        };
+        BOOT_RECORDS.lock().unwrap().push(record);
+    }
+}
+
+/// Set the boot clock base, which should be called before any other stage starts.
+pub fn init_boot_clock() {
+    Lazy::force(&BOOT_CLOCK_BASE);
+}
+
+/// Record the time when the first vCPU runs, later calls are ignored.
+pub fn record_first_vcpu_run() {
+    if FIRST_VCPU_RUN.load(Ordering::Acquire) != 0 {
+        return;
+    }
+    let ns = BOOT_CLOCK_BASE.elapsed().as_nanos() as u64 + 1;
+    let _ = FIRST_VCPU_RUN.compare_exchange(0, ns, Ordering::AcqRel, Ordering::Acquire);
+}
+
+/// Get the time from the boot clock base to the first vCPU run.
+pub fn first_vcpu_run() -> Option<Duration> {
+    match FIRST_VCPU_RUN.load(Ordering::Acquire) {
+        0 => None,
+        ns => Some(Duration::from_nanos(ns - 1)),
+    }
+}
+
+/// Get the recorded boot stages sorted by their start time.
+pub fn boot_records() -> Vec<BootRecord> {
+    let mut records = BOOT_RECORDS.lock().unwrap().clone();
+    records.sort_by_key(|record| record.start);
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_time() {
+        init_boot_clock();
+        {
+            let _timer = BootStageTimer::new(BootStage::ConfigParse, None);
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        drop(BootStageTimer::new(
+            BootStage::DeviceRealize,
+            Some("drive-0"),
+        ));
+        assert!(first_vcpu_run().is_none());
+
+        let records = boot_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].stage, BootStage::ConfigParse);
+        assert!(records[0].duration >= Duration::from_millis(2));
+        assert_eq!(records[1].id.as_deref(), Some("drive-0"));
+        assert!(records[1].start >= records[0].start + records[0].duration);
+
+        record_first_vcpu_run();
+        let first_run = first_vcpu_run().unwrap();
+        assert!(first_run >= records[1].start);
+        record_first_vcpu_run();
+        assert_eq!(first_vcpu_run(), Some(first_run));
+
+        // Stages after the first vCPU run are ignored.
+        drop(BootStageTimer::new(BootStage::FirmwareLoad, None));
+        assert_eq!(boot_records().len(), 2);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Crash dump bundle written when VMM panics.
+//!
+//! The bundle is a text file in the configured directory, which contains the panic
+//! message, the backtrace of the panicking thread, the recent trace events and the
+//! sections registered by other modules, such as the device list and the registers
+//! of vCPUs. The directory is opened at startup, so the bundle can still be written
+//! after chroot, and only the syscalls allowed by seccomp are used.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::{Lazy, OnceCell};
+
+use crate::time::gettime;
+use crate::trace::trace_history;
+use crate::unix::gettid;
+
+/// Max number of frames in backtrace.
+#[cfg(target_env = "gnu")]
+const MAX_BACKTRACE_FRAMES: usize = 128;
+
+/// Section of crash dump, which returns the content of the section. It's called in
+/// the panic hook, so it must not block on the locks which may be held by the
+/// panicking thread, and should use `try_lock` instead.
+pub type CrashDumpSection = dyn Fn() -> String + Send + Sync;
+
+/// Directory where the bundle is written, with its path.
+static CRASH_DUMP_DIR: OnceCell<(String, File)> = OnceCell::new();
+
+type CrashDumpSections = Vec<(String, Box<CrashDumpSection>)>;
+
+/// Registered sections with their names.
+static CRASH_DUMP_SECTIONS: Lazy<Mutex<CrashDumpSections>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+#[cfg(target_env = "gnu")]
+extern "C" {
+    fn backtrace(buffer: *mut *mut libc::c_void, size: libc::c_int) -> libc::c_int;
+    fn backtrace_symbols_fd(buffer: *const *mut libc::c_void, size: libc::c_int, fd: libc::c_int);
+}
+
+/// Write the backtrace of the current thread to the file.
+#[cfg(target_env = "gnu")]
+fn write_backtrace(file: &mut File) -> Result<()> {
+    let mut frames = [std::ptr::null_mut(); MAX_BACKTRACE_FRAMES];
+    // SAFETY: the buffer is valid and the size is the length of buffer.
+    let nr = unsafe { backtrace(frames.as_mut_ptr(), MAX_BACKTRACE_FRAMES as libc::c_int) };
+    file.flush()?;
+    // SAFETY: the first nr frames are filled by backtrace, and the fd is valid.
+    unsafe { backtrace_symbols_fd(frames.as_ptr(), nr, file.as_raw_fd()) };
+    Ok(())
+}
+
+#[cfg(not(target_env = "gnu"))]
+fn write_backtrace(file: &mut File) -> Result<()> {
+    writeln!(file, "Backtrace is only supported with glibc")?;
+    Ok(())
+}
+
+/// Set the directory where the crash dump bundle is written. It can only be set once.
+///
+/// # Arguments
+///
+/// * `dir` - Path of the directory.
+pub fn set_crash_dump_dir(dir: &str) -> Result<()> {
+    let file =
+        File::open(dir).with_context(|| format!("Failed to open crash dump directory {}", dir))?;
+    if !file.metadata()?.is_dir() {
+        bail!("Crash dump path {} is not a directory", dir);
+    }
+    if CRASH_DUMP_DIR.set((dir.to_string(), file)).is_err() {
+        bail!("Crash dump directory has already been set");
+    }
+
+    // The unwinder library is loaded at the first backtrace, do it now rather than
+    // when crashing, in which files may not be opened anymore.
+    #[cfg(target_env = "gnu")]
+    {
+        let mut frames = [std::ptr::null_mut(); 1];
+        // SAFETY: the buffer is valid and the size is the length of buffer.
+        unsafe { backtrace(frames.as_mut_ptr(), 1) };
+    }
+    Ok(())
+}
+
+/// Register a section of crash dump.
+///
+/// # Arguments
+///
+/// * `name` - Name of the section.
+/// * `section` - Function which returns the content of the section.
+pub fn register_crash_dump_section(name: &str, section: Box<CrashDumpSection>) {
+    CRASH_DUMP_SECTIONS
+        .lock()
+        .unwrap()
+        .push((name.to_string(), section));
+}
+
+fn create_bundle(dir: &File) -> Result<(String, File)> {
+    // SAFETY: getpid has no side effect.
+    let pid = unsafe { libc::getpid() };
+    let name = format!("stratovirt-crash-{}-{}.txt", pid, gettime().0);
+    let c_name = CString::new(name.clone())?;
+    // SAFETY: the dir fd and the name are valid.
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            c_name.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to create crash dump {}", name));
+    }
+    // SAFETY: the fd is just opened and owned by the file.
+    Ok((name, unsafe { File::from_raw_fd(fd) }))
+}
+
+/// Write the crash dump bundle, it returns the path of the bundle, or None if the
+/// directory is not set.
+///
+/// # Arguments
+///
+/// * `reason` - Reason of the crash, such as the panic message.
+pub fn write_crash_dump(reason: &str) -> Result<Option<String>> {
+    let (dir_path, dir) = match CRASH_DUMP_DIR.get() {
+        Some(dir) => dir,
+        None => return Ok(None),
+    };
+    let (name, mut file) = create_bundle(dir)?;
+
+    let thread = std::thread::current();
+    writeln!(file, "=== crash ===")?;
+    writeln!(file, "{}", reason)?;
+    writeln!(
+        file,
+        "thread: {} (tid {})",
+        thread.name().unwrap_or("unnamed"),
+        gettid()
+    )?;
+    writeln!(file, "time: {}", gettime().0)?;
+
+    writeln!(file, "\n=== backtrace ===")?;
+    write_backtrace(&mut file)?;
+
+    writeln!(file, "\n=== trace events ===")?;
+    for event in trace_history() {
+        writeln!(file, "{}", event)?;
+    }
+
+    match CRASH_DUMP_SECTIONS.try_lock() {
+        Ok(sections) => {
+            for (name, section) in sections.iter() {
+                writeln!(file, "\n=== {} ===", name)?;
+                writeln!(file, "{}", section())?;
+            }
+        }
+        Err(_) => writeln!(file, "\nSections are unreachable as they are locked")?,
+    }
+    file.sync_data()?;
+
+    Ok(Some(format!("{}/{}", dir_path.trim_end_matches('/'), name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_write_crash_dump() {
+        let dir = format!("/tmp/stratovirt-crash-dump-test-{}", gettid());
+        fs::create_dir_all(&dir).unwrap();
+        assert!(write_crash_dump("not set").unwrap().is_none());
+        assert!(set_crash_dump_dir("/nonexistent/crash").is_err());
+        assert!(set_crash_dump_dir("/proc/self/status").is_err());
+        set_crash_dump_dir(&dir).unwrap();
+        register_crash_dump_section("devices", Box::new(|| "virtio-blk-pci".to_string()));
+
+        let path = write_crash_dump("Panic at [main.rs: 1]: test")
+            .unwrap()
+            .unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("=== crash ===\nPanic at [main.rs: 1]: test\n"));
+        assert!(content.contains("=== backtrace ===\n"));
+        assert!(content.contains("=== trace events ===\n"));
+        assert!(content.contains("=== devices ===\nvirtio-blk-pci\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -39,6 +39,7 @@ const FDT_LAST_COMP_VERSION: u32 = 16;
 const FDT_BEGIN_NODE: u32 = 0x00000001;
 const FDT_END_NODE: u32 = 0x00000002;
 const FDT_PROP: u32 = 0x00000003;
+const FDT_NOP: u32 = 0x00000004;
 const FDT_END: u32 = 0x00000009;
 // Memory reservation block alignment.
 const MEM_RESERVE_ALIGNMENT: usize = 8;
@@ -289,6 +290,247 @@ impl FdtBuilder {
     }
 }
 
+/// Node of device tree parsed from a flattened device tree blob.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FdtNode {
+    pub name: String,
+    /// Properties of node in order, which are pairs of name and value.
+    pub properties: Vec<(String, Vec<u8>)>,
+    pub children: Vec<FdtNode>,
+}
+
+impl FdtNode {
+    fn new(name: &str) -> Self {
+        FdtNode {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties
+            .iter()
+            .find(|(prop, _)| prop == name)
+            .map(|(_, val)| val.as_slice())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&FdtNode> {
+        self.children.iter().find(|node| node.name == name)
+    }
+
+    /// Get the node by absolute path such as `/soc/uart@9000000`, missing nodes
+    /// in the path are created.
+    fn node_mut_or_create(&mut self, path: &str) -> &mut FdtNode {
+        let mut node = self;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let index = match node.children.iter().position(|child| child.name == name) {
+                Some(index) => index,
+                None => {
+                    node.children.push(FdtNode::new(name));
+                    node.children.len() - 1
+                }
+            };
+            node = &mut node.children[index];
+        }
+        node
+    }
+
+    /// Merge properties and subnodes of `other` into this node, existing
+    /// properties are overridden.
+    pub fn merge(&mut self, other: &FdtNode) {
+        for (name, val) in &other.properties {
+            match self.properties.iter_mut().find(|(prop, _)| prop == name) {
+                Some(prop) => prop.1 = val.clone(),
+                None => self.properties.push((name.clone(), val.clone())),
+            }
+        }
+        for child in &other.children {
+            match self
+                .children
+                .iter_mut()
+                .find(|node| node.name == child.name)
+            {
+                Some(node) => node.merge(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+
+    fn build(&self, fdt: &mut FdtBuilder) -> Result<()> {
+        let node_dep = fdt.begin_node(&self.name)?;
+        for (name, val) in &self.properties {
+            fdt.set_property(name, val)?;
+        }
+        for child in &self.children {
+            child.build(fdt)?;
+        }
+        fdt.end_node(node_dep)
+    }
+}
+
+/// Flattened device tree parsed from blob.
+#[derive(Clone, Debug, Default)]
+pub struct Fdt {
+    pub root: FdtNode,
+    boot_cpuid_phys: u32,
+    mem_reserve: Vec<FdtReserveEntry>,
+}
+
+fn fdt_err(msg: &str) -> anyhow::Error {
+    anyhow!(UtilError::InvalidFdt(msg.to_string()))
+}
+
+fn read_be_u32(blob: &[u8], offset: usize) -> Result<u32> {
+    blob.get(offset..offset + 4)
+        .map(BigEndian::read_u32)
+        .ok_or_else(|| fdt_err("unexpected end of blob"))
+}
+
+fn read_cstr(blob: &[u8], offset: usize) -> Result<&str> {
+    let tail = blob
+        .get(offset..)
+        .ok_or_else(|| fdt_err("string out of blob"))?;
+    let len = tail
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or_else(|| fdt_err("unterminated string"))?;
+    std::str::from_utf8(&tail[..len]).map_err(|_| fdt_err("non-utf8 string"))
+}
+
+fn align_up(offset: usize, alignment: usize) -> usize {
+    (offset + alignment - 1) & !(alignment - 1)
+}
+
+impl Fdt {
+    /// Parse flattened device tree blob, such as the dtb compiled by `dtc`.
+    pub fn from_blob(blob: &[u8]) -> Result<Self> {
+        if read_be_u32(blob, 0)? != FDT_MAGIC {
+            return Err(fdt_err("bad magic"));
+        }
+        if blob.len() < FDT_HEADER_SIZE || read_be_u32(blob, 4)? as usize > blob.len() {
+            return Err(fdt_err("truncated blob"));
+        }
+        if read_be_u32(blob, 24)? > FDT_VERSION {
+            return Err(fdt_err("unsupported version"));
+        }
+        let off_dt_struct = read_be_u32(blob, 8)? as usize;
+        let off_dt_strings = read_be_u32(blob, 12)? as usize;
+        let mut offset = read_be_u32(blob, 16)? as usize;
+        let boot_cpuid_phys = read_be_u32(blob, 28)?;
+
+        let mut mem_reserve = Vec::new();
+        loop {
+            let address = u64::from(read_be_u32(blob, offset)?) << 32
+                | u64::from(read_be_u32(blob, offset + 4)?);
+            let size = u64::from(read_be_u32(blob, offset + 8)?) << 32
+                | u64::from(read_be_u32(blob, offset + 12)?);
+            offset += 16;
+            if address == 0 && size == 0 {
+                break;
+            }
+            mem_reserve.push(FdtReserveEntry { address, size });
+        }
+
+        // Nodes which are not closed yet, the first one is a dummy parent of root.
+        let mut stack = vec![FdtNode::default()];
+        offset = off_dt_struct;
+        loop {
+            let token = read_be_u32(blob, offset)?;
+            offset += 4;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_cstr(blob, offset)?;
+                    offset = align_up(offset + name.len() + 1, STRUCTURE_BLOCK_ALIGNMENT);
+                    stack.push(FdtNode::new(name));
+                }
+                FDT_END_NODE => {
+                    if stack.len() < 2 {
+                        return Err(fdt_err("unbalanced node end"));
+                    }
+                    let node = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(node);
+                }
+                FDT_PROP => {
+                    let len = read_be_u32(blob, offset)? as usize;
+                    let nameoff = read_be_u32(blob, offset + 4)? as usize;
+                    let val = blob
+                        .get(offset + 8..offset + 8 + len)
+                        .ok_or_else(|| fdt_err("property out of blob"))?;
+                    let name = read_cstr(blob, off_dt_strings + nameoff)?;
+                    if stack.len() < 2 {
+                        return Err(fdt_err("property outside node"));
+                    }
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .properties
+                        .push((name.to_string(), val.to_vec()));
+                    offset = align_up(offset + 8 + len, STRUCTURE_BLOCK_ALIGNMENT);
+                }
+                FDT_NOP => {}
+                FDT_END => break,
+                _ => return Err(fdt_err("unknown token")),
+            }
+        }
+
+        let mut dummy = stack.pop().unwrap();
+        if !stack.is_empty() || dummy.children.len() != 1 {
+            return Err(fdt_err("there must be one root node"));
+        }
+        Ok(Fdt {
+            root: dummy.children.pop().unwrap(),
+            boot_cpuid_phys,
+            mem_reserve,
+        })
+    }
+
+    /// Apply overlay to the device tree. The overlay is either a plain device tree
+    /// whose root is merged into root of this tree, or a dtc overlay (`/plugin/`)
+    /// whose fragments are targeted by `target-path`.
+    pub fn apply_overlay(&mut self, overlay: &Fdt) -> Result<()> {
+        if overlay.root.child("__fixups__").is_some() {
+            return Err(fdt_err(
+                "overlay references labels of base tree, which is not supported",
+            ));
+        }
+        let fragments: Vec<&FdtNode> = overlay
+            .root
+            .children
+            .iter()
+            .filter(|node| node.child("__overlay__").is_some())
+            .collect();
+        if fragments.is_empty() {
+            self.root.merge(&overlay.root);
+            return Ok(());
+        }
+
+        for fragment in fragments {
+            let target_path = fragment.property("target-path").ok_or_else(|| {
+                fdt_err(&format!("fragment {} has no target-path", fragment.name))
+            })?;
+            let target_path = std::str::from_utf8(target_path)
+                .map_err(|_| fdt_err("non-utf8 target-path"))?
+                .trim_end_matches('\0');
+            if !target_path.starts_with('/') {
+                return Err(fdt_err(&format!("invalid target-path {}", target_path)));
+            }
+            self.root
+                .node_mut_or_create(target_path)
+                .merge(fragment.child("__overlay__").unwrap());
+        }
+        Ok(())
+    }
+
+    /// Encode the device tree into flattened device tree blob.
+    pub fn to_blob(&self) -> Result<Vec<u8>> {
+        let mut fdt = FdtBuilder::new();
+        fdt.add_mem_reserve(&self.mem_reserve)?;
+        fdt.set_boot_cpuid_phys(self.boot_cpuid_phys);
+        self.root.build(&mut fdt)?;
+        fdt.finish()
+    }
+}
+
 /// Trait for devices to be added to the Flattened Device Tree.
 #[allow(clippy::upper_case_acronyms)]
 pub trait CompileFDT {
@@ -478,6 +720,109 @@ mod tests {
         assert!(fdt_builder.finish().is_err());
     }
 
+    #[test]
+    fn test_parse_fdt() {
+        let mut fdt_builder = FdtBuilder::new();
+        fdt_builder
+            .add_mem_reserve(&[FdtReserveEntry {
+                address: 0x1000,
+                size: 0x100,
+            }])
+            .unwrap();
+        let root_node = fdt_builder.begin_node("").unwrap();
+        fdt_builder
+            .set_property_string("compatible", "linux,dummy-virt")
+            .unwrap();
+        let uart_node = fdt_builder.begin_node("pl011@9000000").unwrap();
+        fdt_builder.set_property_u32("clocks", 1).unwrap();
+        fdt_builder.set_property("dma-coherent", &[]).unwrap();
+        fdt_builder.end_node(uart_node).unwrap();
+        fdt_builder.end_node(root_node).unwrap();
+        fdt_builder.set_boot_cpuid_phys(1);
+        let blob = fdt_builder.finish().unwrap();
+
+        let fdt = Fdt::from_blob(&blob).unwrap();
+        assert_eq!(fdt.root.name, "");
+        assert_eq!(
+            fdt.root.property("compatible"),
+            Some(&b"linux,dummy-virt\0"[..])
+        );
+        let uart = fdt.root.child("pl011@9000000").unwrap();
+        assert_eq!(uart.property("clocks"), Some(&[0, 0, 0, 1][..]));
+        assert_eq!(uart.property("dma-coherent"), Some(&[][..]));
+        assert_eq!(fdt.to_blob().unwrap(), blob);
+
+        assert!(Fdt::from_blob(&blob[..blob.len() - 4]).is_err());
+        assert!(Fdt::from_blob(&[0_u8; 64]).is_err());
+    }
+
+    #[test]
+    fn test_apply_overlay() {
+        let mut fdt_builder = FdtBuilder::new();
+        let root_node = fdt_builder.begin_node("").unwrap();
+        let uart_node = fdt_builder.begin_node("pl011@9000000").unwrap();
+        fdt_builder
+            .set_property_string("status", "disabled")
+            .unwrap();
+        fdt_builder.end_node(uart_node).unwrap();
+        fdt_builder.end_node(root_node).unwrap();
+        let mut fdt = Fdt::from_blob(&fdt_builder.finish().unwrap()).unwrap();
+
+        // Plain fragment is merged into root.
+        let mut fdt_builder = FdtBuilder::new();
+        let root_node = fdt_builder.begin_node("").unwrap();
+        let chosen_node = fdt_builder.begin_node("chosen").unwrap();
+        fdt_builder
+            .set_property_string("bootargs", "console=ttyAMA0")
+            .unwrap();
+        fdt_builder.end_node(chosen_node).unwrap();
+        fdt_builder.end_node(root_node).unwrap();
+        let overlay = Fdt::from_blob(&fdt_builder.finish().unwrap()).unwrap();
+        fdt.apply_overlay(&overlay).unwrap();
+        let chosen = fdt.root.child("chosen").unwrap();
+        assert_eq!(chosen.property("bootargs"), Some(&b"console=ttyAMA0\0"[..]));
+
+        // Fragments of overlay are merged into their target nodes.
+        let mut fdt_builder = FdtBuilder::new();
+        let root_node = fdt_builder.begin_node("").unwrap();
+        let fragment_node = fdt_builder.begin_node("fragment@0").unwrap();
+        fdt_builder
+            .set_property_string("target-path", "/pl011@9000000")
+            .unwrap();
+        let overlay_node = fdt_builder.begin_node("__overlay__").unwrap();
+        fdt_builder.set_property_string("status", "okay").unwrap();
+        fdt_builder.end_node(overlay_node).unwrap();
+        fdt_builder.end_node(fragment_node).unwrap();
+        let fragment_node = fdt_builder.begin_node("fragment@1").unwrap();
+        fdt_builder
+            .set_property_string("target-path", "/soc/gpio")
+            .unwrap();
+        let overlay_node = fdt_builder.begin_node("__overlay__").unwrap();
+        fdt_builder.set_property_u32("ngpios", 8).unwrap();
+        fdt_builder.end_node(overlay_node).unwrap();
+        fdt_builder.end_node(fragment_node).unwrap();
+        fdt_builder.end_node(root_node).unwrap();
+        let overlay = Fdt::from_blob(&fdt_builder.finish().unwrap()).unwrap();
+        fdt.apply_overlay(&overlay).unwrap();
+        let uart = fdt.root.child("pl011@9000000").unwrap();
+        assert_eq!(uart.properties.len(), 1);
+        assert_eq!(uart.property("status"), Some(&b"okay\0"[..]));
+        let gpio = fdt.root.child("soc").unwrap().child("gpio").unwrap();
+        assert_eq!(gpio.property("ngpios"), Some(&[0, 0, 0, 8][..]));
+
+        // Fragment without target-path is rejected.
+        let mut fdt_builder = FdtBuilder::new();
+        let root_node = fdt_builder.begin_node("").unwrap();
+        let fragment_node = fdt_builder.begin_node("fragment@0").unwrap();
+        fdt_builder.set_property_u32("target", 1).unwrap();
+        let overlay_node = fdt_builder.begin_node("__overlay__").unwrap();
+        fdt_builder.end_node(overlay_node).unwrap();
+        fdt_builder.end_node(fragment_node).unwrap();
+        fdt_builder.end_node(root_node).unwrap();
+        let overlay = Fdt::from_blob(&fdt_builder.finish().unwrap()).unwrap();
+        assert!(fdt.apply_overlay(&overlay).is_err());
+    }
+
     #[test]
     fn test_mem_reserve_overlap() {
         let mut fdt_builder = FdtBuilder::new();
@@ -86,4 +86,6 @@ pub enum UtilError {
     MemReserveOverlap,
     #[error("Failed to set {0} property")]
     SetPropertyErr(String),
+    #[error("Invalid flattened device tree: {0}")]
+    InvalidFdt(String),
 }
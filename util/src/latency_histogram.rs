@@ -0,0 +1,273 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+/// Each power of 2 range is divided into 2^SUB_BUCKET_BITS buckets, so the relative
+/// error of recorded value is less than 1/2^SUB_BUCKET_BITS.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Values are in nanoseconds, and the ones not less than 2^41ns (about 36 minutes)
+/// are recorded in the last bucket.
+const MAX_VALUE_BITS: u32 = 41;
+const NUM_BUCKETS: usize = (MAX_VALUE_BITS - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS;
+
+/// Percentiles reported in the snapshot of histogram.
+pub const PERCENTILES: [f64; 5] = [50.0, 90.0, 99.0, 99.9, 99.99];
+
+/// Histograms keyed by device id and operation.
+type HistogramMap = BTreeMap<(String, String), Arc<LatencyHistogram>>;
+
+static LATENCY_HISTOGRAMS: Lazy<Mutex<HistogramMap>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Log-linear histogram of latency in the style of HDR histogram, which can be
+/// recorded concurrently without lock.
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+/// Snapshot of histogram, all the values are in nanoseconds.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub min: u64,
+    pub max: u64,
+    pub mean: u64,
+    /// Values of `PERCENTILES`.
+    pub percentiles: Vec<u64>,
+    /// Non-empty buckets in format of (lowest value, highest value, count).
+    pub buckets: Vec<(u64, u64, u64)>,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        LatencyHistogram {
+            buckets: [ZERO; NUM_BUCKETS],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn bucket_index(value: u64) -> usize {
+        let value = value.min((1 << MAX_VALUE_BITS) - 1);
+        if value < SUB_BUCKETS as u64 {
+            return value as usize;
+        }
+        let magnitude = 63 - value.leading_zeros() - SUB_BUCKET_BITS;
+        magnitude as usize * SUB_BUCKETS + (value >> magnitude) as usize
+    }
+
+    fn bucket_range(index: usize) -> (u64, u64) {
+        if index < 2 * SUB_BUCKETS {
+            return (index as u64, index as u64);
+        }
+        let magnitude = index / SUB_BUCKETS - 1;
+        let lowest = ((index - magnitude * SUB_BUCKETS) as u64) << magnitude;
+        (lowest, lowest + (1 << magnitude) - 1)
+    }
+
+    /// Record the latency.
+    pub fn record(&self, latency: Duration) {
+        let value = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.min.fetch_min(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// Clear the recorded values. Values recorded concurrently may be partly cleared.
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.min.store(u64::MAX, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let buckets: Vec<(u64, u64, u64)> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bucket)| {
+                let count = bucket.load(Ordering::Relaxed);
+                if count == 0 {
+                    return None;
+                }
+                let (lowest, highest) = Self::bucket_range(index);
+                Some((lowest, highest, count))
+            })
+            .collect();
+        // Count the buckets instead of reading `count`, which may be inconsistent
+        // with buckets when recording concurrently.
+        let count: u64 = buckets.iter().map(|b| b.2).sum();
+        if count == 0 {
+            return HistogramSnapshot {
+                percentiles: vec![0; PERCENTILES.len()],
+                ..Default::default()
+            };
+        }
+
+        let min = self.min.load(Ordering::Relaxed);
+        let max = self.max.load(Ordering::Relaxed);
+        let percentiles = PERCENTILES
+            .iter()
+            .map(|p| {
+                let rank = ((p / 100.0 * count as f64).ceil() as u64).max(1);
+                let mut seen = 0;
+                let (_, highest, _) = buckets
+                    .iter()
+                    .find(|b| {
+                        seen += b.2;
+                        seen >= rank
+                    })
+                    .unwrap_or_else(|| buckets.last().unwrap());
+                (*highest).clamp(min, max)
+            })
+            .collect();
+
+        HistogramSnapshot {
+            count,
+            min,
+            max,
+            mean: self.sum.load(Ordering::Relaxed) / count,
+            percentiles,
+            buckets,
+        }
+    }
+}
+
+/// Get the latency histogram of operation of the device, it's created if it doesn't exist.
+///
+/// # Arguments
+///
+/// * `device` - The id of device.
+/// * `op` - The operation of requests, such as `read`.
+pub fn get_latency_histogram(device: &str, op: &str) -> Arc<LatencyHistogram> {
+    LATENCY_HISTOGRAMS
+        .lock()
+        .unwrap()
+        .entry((device.to_string(), op.to_string()))
+        .or_default()
+        .clone()
+}
+
+/// Remove all the latency histograms of the device, it should be called when the device
+/// is unrealized.
+pub fn unregister_latency_histograms(device: &str) {
+    LATENCY_HISTOGRAMS
+        .lock()
+        .unwrap()
+        .retain(|(id, _), _| id != device);
+}
+
+/// Get the latency histograms of the device, or of all the devices if `device` is None.
+/// The result is sorted by device id and operation.
+pub fn latency_histograms(device: Option<&str>) -> Vec<(String, String, Arc<LatencyHistogram>)> {
+    LATENCY_HISTOGRAMS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|((id, _), _)| device.is_none() || device == Some(id.as_str()))
+        .map(|((id, op), histogram)| (id.clone(), op.clone(), histogram.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_index() {
+        for value in [0, 1, 15, 16, 31, 32, 33, 1000, 123_456_789, (1 << 41) - 1] {
+            let (lowest, highest) =
+                LatencyHistogram::bucket_range(LatencyHistogram::bucket_index(value));
+            assert!(lowest <= value && value <= highest, "{}", value);
+            // Relative error is less than 1/16.
+            assert!(
+                (highest - lowest) * SUB_BUCKETS as u64 <= lowest.max(1),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(LatencyHistogram::bucket_index(u64::MAX), NUM_BUCKETS - 1);
+        assert_eq!(
+            LatencyHistogram::bucket_range(NUM_BUCKETS - 1).1,
+            (1 << 41) - 1
+        );
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        for us in 1..=1000 {
+            histogram.record(Duration::from_micros(us));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 1000);
+        assert_eq!(snapshot.min, 1000);
+        assert_eq!(snapshot.max, 1_000_000);
+        assert_eq!(snapshot.mean, 500_500);
+        assert_eq!(snapshot.buckets.iter().map(|b| b.2).sum::<u64>(), 1000);
+        for (p, value) in PERCENTILES.iter().zip(snapshot.percentiles.iter()) {
+            let expected = (p * 10_000.0) as u64;
+            assert!(
+                *value >= expected && *value <= expected + expected / 16,
+                "p{}",
+                p
+            );
+        }
+
+        histogram.reset();
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count, 0);
+        assert!(snapshot.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_latency_histogram_registry() {
+        let read = get_latency_histogram("test-histogram-drive", "read");
+        read.record(Duration::from_micros(10));
+        assert!(Arc::ptr_eq(
+            &read,
+            &get_latency_histogram("test-histogram-drive", "read")
+        ));
+        get_latency_histogram("test-histogram-drive", "write");
+
+        let histograms = latency_histograms(Some("test-histogram-drive"));
+        assert_eq!(histograms.len(), 2);
+        assert_eq!(histograms[0].1, "read");
+        assert_eq!(histograms[0].2.snapshot().count, 1);
+        assert_eq!(histograms[1].1, "write");
+
+        unregister_latency_histograms("test-histogram-drive");
+        assert!(latency_histograms(Some("test-histogram-drive")).is_empty());
+    }
+}
@@ -13,15 +13,18 @@
 pub mod aio;
 pub mod arg_parser;
 pub mod bitmap;
+pub mod boot_time;
 pub mod byte_code;
 pub mod checksum;
 pub mod clock;
+pub mod crash_dump;
 pub mod daemonize;
 #[cfg(target_arch = "aarch64")]
 pub mod device_tree;
 pub mod edid;
 pub mod error;
 pub mod file;
+pub mod latency_histogram;
 pub mod leak_bucket;
 pub mod link_list;
 pub mod logger;
@@ -32,14 +35,18 @@ pub mod offsetof;
 pub mod pixman;
 pub mod reader;
 pub mod seccomp;
+pub mod seclabel;
 pub mod syscall;
 pub mod tap;
 pub mod test_helper;
 pub mod time;
 pub mod trace;
 pub mod unix;
+pub mod userfaultfd;
 #[cfg(feature = "usb_camera_v4l2")]
 pub mod v4l2;
+pub mod vmm_limits;
+pub mod vmm_memory;
 
 pub use anyhow::Result;
 
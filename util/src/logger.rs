@@ -10,16 +10,22 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write as _;
 use std::fs::File;
 use std::io::Write;
 use std::num::Wrapping;
 use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixDatagram;
 use std::path::Path;
-use std::sync::Mutex;
-use std::time::UNIX_EPOCH;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
 
 use crate::time::{get_format_time, gettime};
 use crate::unix::gettid;
@@ -28,6 +34,168 @@ use crate::unix::gettid;
 const LOG_ROTATE_SIZE_MAX: usize = 100 * 1024 * 1024;
 // Logs are retained for seven days.
 const LOG_ROTATE_COUNT_MAX: u32 = 7;
+// Socket of syslog daemon.
+const SYSLOG_SOCKET: &str = "/dev/log";
+// Socket of the native protocol of systemd-journald.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// Identifier of the logs sent to syslog or journald.
+const LOG_IDENTIFIER: &str = "stratovirt";
+// In json format, each call site logs at most LOG_RATE_LIMIT_BURST messages in every
+// LOG_RATE_LIMIT_INTERVAL, the others are suppressed.
+const LOG_RATE_LIMIT_BURST: u32 = 10;
+const LOG_RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(5);
+// Short names of modules which can be used in log filter.
+const MODULE_ALIASES: &[(&str, &str)] = &[("qmp", "machine_manager::qmp")];
+
+/// Filter of log levels, the default level and the levels of modules.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// Levels of modules, sorted by length of module path in descending order, so the
+    /// most specific module matches first.
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    fn level(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                matches!(target.strip_prefix(module.as_str()),
+                    Some(rest) if rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// The most verbose level of all modules, logs above it are discarded by the log
+    /// macros without calling the logger.
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    /// Parse filter in format of `[<level>][,<module>=<level>]...`, such as
+    /// `warn,virtio=debug,qmp=trace`.
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_level = |level: &str| {
+            LevelFilter::from_str(level.trim())
+                .map_err(|_| anyhow!("Invalid log level {}", level.trim()))
+        };
+
+        let mut filter = LogFilter::default();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        bail!("Module of log filter {} is empty", item);
+                    }
+                    let module = MODULE_ALIASES
+                        .iter()
+                        .find(|(alias, _)| *alias == module)
+                        .map_or(module, |(_, path)| *path);
+                    let level = parse_level(level)?;
+                    filter.modules.retain(|(m, _)| m != module);
+                    filter.modules.push((module.to_string(), level));
+                }
+                None => filter.default = parse_level(item)?,
+            }
+        }
+        filter
+            .modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level.as_str().to_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+static LOG_FILTER: Lazy<RwLock<LogFilter>> = Lazy::new(|| RwLock::new(LogFilter::default()));
+
+fn apply_log_filter(filter: LogFilter) {
+    let mut locked_filter = LOG_FILTER.write().unwrap();
+    log::set_max_level(filter.max_level());
+    *locked_filter = filter;
+}
+
+/// Set the log filter at runtime, in format of `[<level>][,<module>=<level>]...`.
+pub fn set_log_filter(filter: &str) -> Result<()> {
+    apply_log_filter(LogFilter::from_str(filter)?);
+    Ok(())
+}
+
+/// Get the current log filter.
+pub fn log_filter() -> String {
+    LOG_FILTER.read().unwrap().to_string()
+}
+
+/// Where the logs are output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogOutput {
+    Stderr,
+    File(String),
+    Syslog,
+    Journald,
+}
+
+impl FromStr for LogOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stderr" => Ok(LogOutput::Stderr),
+            "syslog" => Ok(LogOutput::Syslog),
+            "journald" => Ok(LogOutput::Journald),
+            _ => bail!(
+                "Invalid log output {}, only stderr, syslog and journald are supported",
+                s
+            ),
+        }
+    }
+}
+
+/// Format of the logs written to stderr or log file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("Invalid log format {}, only text and json are supported", s),
+        }
+    }
+}
 
 fn format_now() -> String {
     let (sec, nsec) = gettime();
@@ -45,6 +213,117 @@ fn format_now() -> String {
     )
 }
 
+/// Format current time in UTC like "%year-%mon-%dayT%hour:%min:%sec.%nsecZ".
+fn format_utc_now() -> String {
+    let (sec, nsec) = gettime();
+    let mut ti: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both the time and the result are valid for gmtime_r.
+    unsafe {
+        libc::gmtime_r(&(sec as libc::time_t), &mut ti);
+    }
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:09}Z",
+        ti.tm_year + 1900,
+        ti.tm_mon + 1,
+        ti.tm_mday,
+        ti.tm_hour,
+        ti.tm_min,
+        ti.tm_sec,
+        nsec
+    )
+}
+
+/// Append `s` to `out` as a JSON string.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Format a record as a line of JSON.
+fn json_message(
+    record: &Record,
+    vm_id: Option<&str>,
+    pid: i32,
+    tid: u64,
+    suppressed: u64,
+) -> String {
+    let mut msg = String::from("{\"timestamp\":");
+    push_json_str(&mut msg, &format_utc_now());
+    msg.push_str(",\"level\":");
+    push_json_str(&mut msg, record.level().as_str());
+    msg.push_str(",\"module\":");
+    push_json_str(&mut msg, record.module_path().unwrap_or(record.target()));
+    if let Some(vm_id) = vm_id {
+        msg.push_str(",\"vm-id\":");
+        push_json_str(&mut msg, vm_id);
+    }
+    msg.push_str(",\"message\":");
+    push_json_str(&mut msg, &record.args().to_string());
+    let _ = write!(
+        msg,
+        ",\"fields\":{{\"pid\":{},\"tid\":{},\"file\":",
+        pid, tid
+    );
+    push_json_str(&mut msg, record.file().unwrap_or(""));
+    let _ = write!(msg, ",\"line\":{}", record.line().unwrap_or(0));
+    if suppressed != 0 {
+        let _ = write!(msg, ",\"suppressed\":{}", suppressed);
+    }
+    msg.push_str("}}\n");
+    msg
+}
+
+struct RateLimitState {
+    window_start: Instant,
+    count: u32,
+    suppressed: u64,
+}
+
+/// Rate limiter of the messages logged by each call site.
+#[derive(Default)]
+struct RateLimiter {
+    sites: HashMap<(String, u32), RateLimitState>,
+}
+
+impl RateLimiter {
+    /// Check whether a message of the call site can be logged. Returns the number of
+    /// messages suppressed since the last one logged, or None if it's suppressed.
+    fn check(&mut self, file: &str, line: u32, now: Instant) -> Option<u64> {
+        let state = self
+            .sites
+            .entry((file.to_string(), line))
+            .or_insert(RateLimitState {
+                window_start: now,
+                count: 0,
+                suppressed: 0,
+            });
+        if now.saturating_duration_since(state.window_start) >= LOG_RATE_LIMIT_INTERVAL {
+            state.window_start = now;
+            state.count = 0;
+        }
+        if state.count >= LOG_RATE_LIMIT_BURST {
+            state.suppressed += 1;
+            return None;
+        }
+        state.count += 1;
+        Some(std::mem::take(&mut state.suppressed))
+    }
+}
+
 struct FileRotate {
     handler: Box<dyn Write + Send>,
     path: String,
@@ -98,15 +377,68 @@ impl FileRotate {
     }
 }
 
+fn syslog_priority(level: Level) -> i32 {
+    let severity = match level {
+        Level::Error => libc::LOG_ERR,
+        Level::Warn => libc::LOG_WARNING,
+        Level::Info => libc::LOG_INFO,
+        Level::Debug | Level::Trace => libc::LOG_DEBUG,
+    };
+    libc::LOG_DAEMON | severity
+}
+
+/// Format message of syslog protocol on local socket.
+fn syslog_message(record: &Record, pid: i32, tid: u64) -> String {
+    format!(
+        "<{}>{}[{}]: [{}][{}: {}]: {}",
+        syslog_priority(record.level()),
+        LOG_IDENTIFIER,
+        pid,
+        tid,
+        record.file().unwrap_or(""),
+        record.line().unwrap_or(0),
+        record.args()
+    )
+}
+
+/// Format message of the native protocol of journald. The message is serialized in
+/// binary form, as it may contain newlines.
+fn journald_message(record: &Record, pid: i32, tid: u64) -> Vec<u8> {
+    let mut msg = format!(
+        "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nSYSLOG_PID={}\nTID={}\nCODE_FILE={}\nCODE_LINE={}\n",
+        syslog_priority(record.level()) & 0x7,
+        LOG_IDENTIFIER,
+        pid,
+        tid,
+        record.file().unwrap_or(""),
+        record.line().unwrap_or(0),
+    )
+    .into_bytes();
+    let text = record.args().to_string();
+    msg.extend_from_slice(b"MESSAGE\n");
+    msg.extend_from_slice(&(text.len() as u64).to_le_bytes());
+    msg.extend_from_slice(text.as_bytes());
+    msg.push(b'\n');
+    msg
+}
+
+enum LogSink {
+    File(FileRotate),
+    Syslog(UnixDatagram),
+    Journald(UnixDatagram),
+}
+
 /// Format like "%year-%mon-%dayT%hour:%min:%sec.%nsec
 struct VmLogger {
-    rotate: Mutex<FileRotate>,
-    level: Level,
+    sink: Mutex<LogSink>,
+    format: LogFormat,
+    vm_id: Option<String>,
+    rate_limiter: Mutex<RateLimiter>,
 }
 
 impl Log for VmLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        metadata.level() <= LOG_FILTER.read().unwrap().level(metadata.target())
     }
 
     fn log(&self, record: &Record) {
@@ -116,19 +448,48 @@ impl Log for VmLogger {
 
         let pid = unsafe { libc::getpid() };
         let tid = gettid();
-        let formatmsg = format_args!(
-            "{:<5}: [{}][{}][{}: {}]:{}: {}\n",
-            format_now(),
-            pid,
-            tid,
-            record.file().unwrap_or(""),
-            record.line().unwrap_or(0),
-            record.level(),
-            record.args()
-        )
-        .to_string();
-
-        let mut rotate = self.rotate.lock().unwrap();
+        let mut sink = self.sink.lock().unwrap();
+        let rotate = match &mut *sink {
+            LogSink::File(rotate) => rotate,
+            LogSink::Syslog(sock) => {
+                if let Err(e) = sock.send(syslog_message(record, pid, tid).as_bytes()) {
+                    println!("Failed to log message {:?}", e);
+                }
+                return;
+            }
+            LogSink::Journald(sock) => {
+                if let Err(e) = sock.send(&journald_message(record, pid, tid)) {
+                    println!("Failed to log message {:?}", e);
+                }
+                return;
+            }
+        };
+
+        let formatmsg = match self.format {
+            LogFormat::Text => format_args!(
+                "{:<5}: [{}][{}][{}: {}]:{}: {}\n",
+                format_now(),
+                pid,
+                tid,
+                record.file().unwrap_or(""),
+                record.line().unwrap_or(0),
+                record.level(),
+                record.args()
+            )
+            .to_string(),
+            LogFormat::Json => {
+                let suppressed = match self.rate_limiter.lock().unwrap().check(
+                    record.file().unwrap_or(""),
+                    record.line().unwrap_or(0),
+                    Instant::now(),
+                ) {
+                    Some(suppressed) => suppressed,
+                    None => return,
+                };
+                json_message(record, self.vm_id.as_deref(), pid, tid, suppressed)
+            }
+        };
+
         if let Err(e) = rotate.handler.write_all(formatmsg.as_bytes()) {
             println!("Failed to log message {:?}", e);
             return;
@@ -141,11 +502,7 @@ impl Log for VmLogger {
     fn flush(&self) {}
 }
 
-fn init_vm_logger(
-    level: Level,
-    logfile: Box<dyn Write + Send>,
-    logfile_path: String,
-) -> Result<()> {
+fn file_rotate(logfile: Box<dyn Write + Send>, logfile_path: String) -> Result<FileRotate> {
     let current_size;
     let create_day;
     if logfile_path.is_empty() {
@@ -158,33 +515,19 @@ fn init_vm_logger(
         let sec = mod_time.duration_since(UNIX_EPOCH)?.as_secs();
         create_day = get_format_time(sec as i64)[2];
     };
-    let rotate = Mutex::new(FileRotate {
+    Ok(FileRotate {
         handler: logfile,
         path: logfile_path,
         current_size,
         create_day,
-    });
-
-    let logger = VmLogger { rotate, level };
-    log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(LevelFilter::Trace))?;
-    Ok(())
+    })
 }
 
-fn init_logger_with_env(logfile: Box<dyn Write + Send>, logfile_path: String) -> Result<()> {
-    let level = match std::env::var("STRATOVIRT_LOG_LEVEL") {
-        Ok(l) => match l.to_lowercase().as_str() {
-            "error" => Level::Error,
-            "warn" => Level::Warn,
-            "info" => Level::Info,
-            "debug" => Level::Debug,
-            "trace" => Level::Trace,
-            _ => Level::Info,
-        },
-        _ => Level::Info,
-    };
-
-    init_vm_logger(level, logfile, logfile_path)?;
-    Ok(())
+fn connect_log_socket(path: &str) -> Result<UnixDatagram> {
+    let sock = UnixDatagram::unbound().with_context(|| "Failed to create log socket")?;
+    sock.connect(path)
+        .with_context(|| format!("Failed to connect log socket {}", path))?;
+    Ok(sock)
 }
 
 fn open_log_file(path: &str) -> Result<File> {
@@ -199,11 +542,148 @@ fn open_log_file(path: &str) -> Result<File> {
 }
 
 pub fn init_log(path: String) -> Result<()> {
-    let logfile: Box<dyn Write + Send> = if path.is_empty() {
-        Box::new(std::io::stderr())
+    let output = if path.is_empty() {
+        LogOutput::Stderr
     } else {
-        Box::new(open_log_file(&path)?)
+        LogOutput::File(path)
+    };
+    init_log_output(output, LogFormat::Text, None, None)
+}
+
+/// Init the logger.
+///
+/// # Arguments
+///
+/// * `output` - Where the logs are output.
+/// * `format` - Format of the logs, only text is supported for syslog and journald.
+/// * `filter` - Log filter in format of `[<level>][,<module>=<level>]...`. Env
+///   `STRATOVIRT_LOG_LEVEL` in the same format is used if it's not set.
+/// * `vm_id` - Id of VM which is added to the logs in json format.
+pub fn init_log_output(
+    output: LogOutput,
+    format: LogFormat,
+    filter: Option<&str>,
+    vm_id: Option<&str>,
+) -> Result<()> {
+    if format == LogFormat::Json && matches!(output, LogOutput::Syslog | LogOutput::Journald) {
+        bail!("Json log format is only supported for stderr and log file");
+    }
+
+    let filter = match filter {
+        Some(filter) => LogFilter::from_str(filter)?,
+        None => std::env::var("STRATOVIRT_LOG_LEVEL")
+            .ok()
+            .and_then(|filter| LogFilter::from_str(&filter).ok())
+            .unwrap_or_default(),
+    };
+
+    let sink = match &output {
+        LogOutput::Stderr => {
+            LogSink::File(file_rotate(Box::new(std::io::stderr()), String::new())?)
+        }
+        LogOutput::File(path) => {
+            let logfile = Box::new(open_log_file(path)?);
+            LogSink::File(
+                file_rotate(logfile, path.clone())
+                    .with_context(|| format!("Failed to init logger: {}", path))?,
+            )
+        }
+        LogOutput::Syslog => LogSink::Syslog(connect_log_socket(SYSLOG_SOCKET)?),
+        LogOutput::Journald => LogSink::Journald(connect_log_socket(JOURNALD_SOCKET)?),
+    };
+    let logger = VmLogger {
+        sink: Mutex::new(sink),
+        format,
+        vm_id: vm_id.map(String::from),
+        rate_limiter: Mutex::new(RateLimiter::default()),
     };
-    init_logger_with_env(logfile, path.clone())
-        .with_context(|| format!("Failed to init logger: {}", path))
+    log::set_boxed_logger(Box::new(logger))?;
+    apply_log_filter(filter);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_filter() {
+        let filter = LogFilter::from_str("warn,virtio=debug,qmp=trace,virtio::queue=off").unwrap();
+        assert_eq!(filter.level("machine"), LevelFilter::Warn);
+        assert_eq!(filter.level("virtio"), LevelFilter::Debug);
+        assert_eq!(filter.level("virtio::device::net"), LevelFilter::Debug);
+        assert_eq!(filter.level("virtio::queue::split"), LevelFilter::Off);
+        assert_eq!(filter.level("virtio_gpu"), LevelFilter::Warn);
+        assert_eq!(
+            filter.level("machine_manager::qmp::qmp_socket"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filter.to_string(),
+            "warn,machine_manager::qmp=trace,virtio::queue=off,virtio=debug"
+        );
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        let filter = LogFilter::from_str("vfio=error").unwrap();
+        assert_eq!(filter.level("migration"), LevelFilter::Info);
+        assert_eq!(filter.level("vfio"), LevelFilter::Error);
+        assert_eq!(filter.max_level(), LevelFilter::Info);
+        assert_eq!(LogFilter::from_str("").unwrap(), LogFilter::default());
+
+        assert!(LogFilter::from_str("verbose").is_err());
+        assert!(LogFilter::from_str("=debug").is_err());
+        assert!(LogFilter::from_str("virtio=").is_err());
+    }
+
+    #[test]
+    fn test_log_output() {
+        assert_eq!(LogOutput::from_str("syslog").unwrap(), LogOutput::Syslog);
+        assert_eq!(
+            LogOutput::from_str("journald").unwrap(),
+            LogOutput::Journald
+        );
+        assert!(LogOutput::from_str("/var/log/stratovirt.log").is_err());
+        assert_eq!(LogFormat::from_str("json").unwrap(), LogFormat::Json);
+        assert!(LogFormat::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn test_json_message() {
+        let record = Record::builder()
+            .args(format_args!("queue \"rx\"\tis\nfull\u{1}"))
+            .level(Level::Warn)
+            .target("virtio::queue")
+            .module_path(Some("virtio::queue::split"))
+            .file(Some("virtio/src/queue/split.rs"))
+            .line(Some(42))
+            .build();
+        let msg = json_message(&record, Some("vm-1"), 10, 11, 3);
+        assert!(msg.ends_with("}}\n"));
+        let msg = msg.split_once(",\"level\"").unwrap().1;
+        assert_eq!(
+            msg,
+            ":\"WARN\",\"module\":\"virtio::queue::split\",\"vm-id\":\"vm-1\",\
+             \"message\":\"queue \\\"rx\\\"\\tis\\nfull\\u0001\",\
+             \"fields\":{\"pid\":10,\"tid\":11,\"file\":\"virtio/src/queue/split.rs\",\
+             \"line\":42,\"suppressed\":3}}\n"
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        for _ in 0..LOG_RATE_LIMIT_BURST {
+            assert_eq!(limiter.check("a.rs", 1, start), Some(0));
+        }
+        assert_eq!(limiter.check("a.rs", 1, start), None);
+        assert_eq!(limiter.check("a.rs", 1, start), None);
+        // Other call sites are not limited.
+        assert_eq!(limiter.check("a.rs", 2, start), Some(0));
+        assert_eq!(limiter.check("b.rs", 1, start), Some(0));
+
+        let next = start + LOG_RATE_LIMIT_INTERVAL;
+        assert_eq!(limiter.check("a.rs", 1, next), Some(2));
+        assert_eq!(limiter.check("a.rs", 1, next), Some(0));
+    }
 }
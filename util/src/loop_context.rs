@@ -15,7 +15,7 @@ use std::fmt;
 use std::fmt::Debug;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
@@ -27,14 +27,49 @@ use nix::{
     poll::{ppoll, PollFd, PollFlags},
     sys::time::TimeSpec,
 };
+use once_cell::sync::{Lazy, OnceCell};
 use vmm_sys_util::epoll::{ControlOperation, Epoll, EpollEvent, EventSet};
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::clock::{get_current_time, ClockState};
+use crate::latency_histogram::{HistogramSnapshot, LatencyHistogram};
+use crate::unix::gettid;
 use crate::UtilError;
 
 const READY_EVENT_MAX: usize = 256;
 const AIO_PRFETCH_CYCLE_TIME: usize = 100;
+/// Minimum interval between two reports of slow callbacks of the same event loop.
+const SLOW_CALLBACK_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Budget of a callback in nanoseconds, 0 means no budget.
+static CALLBACK_BUDGET_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Hook called when a callback exceeds the budget, with the name of event loop,
+/// the fd of callback (None for timer), the duration of callback and the budget.
+pub type SlowCallbackHook = dyn Fn(&str, Option<RawFd>, Duration, Duration) + Send + Sync;
+
+static SLOW_CALLBACK_HOOK: OnceCell<Box<SlowCallbackHook>> = OnceCell::new();
+
+/// Base of the busy timestamps of event loops.
+static LOOP_CLOCK_BASE: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Set the budget of callbacks in all event loops, zero disables the check.
+pub fn set_callback_budget(budget: Duration) {
+    let budget = u64::try_from(budget.as_nanos()).unwrap_or(u64::MAX);
+    CALLBACK_BUDGET_NS.store(budget, Ordering::Relaxed);
+}
+
+/// Get the budget of callbacks, zero means no budget.
+pub fn callback_budget() -> Duration {
+    Duration::from_nanos(CALLBACK_BUDGET_NS.load(Ordering::Relaxed))
+}
+
+/// Set the hook to report the callbacks exceeding the budget, it can be set only once.
+pub fn set_slow_callback_hook(hook: Box<SlowCallbackHook>) {
+    if SLOW_CALLBACK_HOOK.set(hook).is_err() {
+        warn!("Hook of slow callback has already been set");
+    }
+}
 
 #[derive(Debug)]
 pub enum NotifierOperation {
@@ -44,7 +79,8 @@ pub enum NotifierOperation {
     /// Try to add a notifier to a file descriptor, when some event
     /// also notice me, the file descriptor must be read.
     AddShared = 2,
-    /// Change the settings associated with a file descriptor.
+    /// Change the settings associated with a file descriptor, the handlers are kept
+    /// if no handler is given.
     Modify = 4,
     /// Delete a file descriptor from the event table, if has one more notifiers,
     /// file descriptor not closed.
@@ -85,6 +121,8 @@ pub struct EventNotifier {
     pub handler_poll: Option<Box<NotifierCallback>>,
     /// Event status
     status: Arc<Mutex<EventStatus>>,
+    /// Number of times the handlers are dispatched.
+    dispatches: AtomicU64,
 }
 
 impl fmt::Debug for EventNotifier {
@@ -96,6 +134,7 @@ impl fmt::Debug for EventNotifier {
             .field("event", &self.event)
             .field("status", &self.status)
             .field("io_poll", &self.handler_poll.is_some())
+            .field("dispatches", &self.dispatches)
             .finish()
     }
 }
@@ -117,6 +156,7 @@ impl EventNotifier {
             handlers,
             handler_poll: None,
             status: Arc::new(Mutex::new(EventStatus::Alive)),
+            dispatches: AtomicU64::new(0),
         }
     }
 }
@@ -186,9 +226,120 @@ impl Timer {
     }
 }
 
+/// Health metrics of event loop.
+#[derive(Default)]
+struct LoopMetrics {
+    /// Number of iterations of the loop.
+    iterations: AtomicU64,
+    /// Time spent on handling events and timers in nanoseconds, not including waiting.
+    busy_ns: AtomicU64,
+    /// Latency of handling events and timers in each iteration.
+    iteration_latency: LatencyHistogram,
+    /// Duration of the longest callback in nanoseconds.
+    longest_callback_ns: AtomicU64,
+    /// Fd of the longest callback, -1 for timer.
+    longest_callback_fd: AtomicI32,
+    /// Number of callbacks exceeding the budget.
+    slow_callbacks: AtomicU64,
+    /// Time of the last report of slow callback.
+    last_report: Mutex<Option<Instant>>,
+    /// Nanoseconds since `LOOP_CLOCK_BASE` plus one when the loop got busy, 0 if
+    /// the loop is waiting for events.
+    busy_since_ns: AtomicU64,
+    /// Thread id of the loop, 0 if the loop has not run.
+    tid: AtomicU64,
+}
+
+impl LoopMetrics {
+    fn mark_busy(&self) {
+        if self.tid.load(Ordering::Relaxed) == 0 {
+            self.tid.store(gettid(), Ordering::Relaxed);
+        }
+        let since = u64::try_from(LOOP_CLOCK_BASE.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.busy_since_ns
+            .store(since.saturating_add(1), Ordering::Relaxed);
+    }
+
+    fn mark_idle(&self) {
+        self.busy_since_ns.store(0, Ordering::Relaxed);
+    }
+
+    fn record_iteration(&self, latency: Duration) {
+        self.iterations.fetch_add(1, Ordering::Relaxed);
+        self.busy_ns.fetch_add(
+            u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        self.iteration_latency.record(latency);
+    }
+
+    fn record_callback(&self, loop_name: &str, fd: Option<RawFd>, duration: Duration) {
+        let duration_ns = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        if duration_ns > self.longest_callback_ns.load(Ordering::Relaxed) {
+            self.longest_callback_ns
+                .store(duration_ns, Ordering::Relaxed);
+            self.longest_callback_fd
+                .store(fd.unwrap_or(-1), Ordering::Relaxed);
+        }
+
+        let budget_ns = CALLBACK_BUDGET_NS.load(Ordering::Relaxed);
+        if budget_ns == 0 || duration_ns <= budget_ns {
+            return;
+        }
+        self.slow_callbacks.fetch_add(1, Ordering::Relaxed);
+
+        // Throttle the reports, as a stalled loop usually has many slow callbacks.
+        let now = Instant::now();
+        let mut last_report = self.last_report.lock().unwrap();
+        if matches!(*last_report, Some(t) if now.duration_since(t) < SLOW_CALLBACK_REPORT_INTERVAL)
+        {
+            return;
+        }
+        *last_report = Some(now);
+        drop(last_report);
+
+        let budget = Duration::from_nanos(budget_ns);
+        match fd {
+            Some(fd) => warn!(
+                "Callback of fd {} in event loop {} took {:?}, exceeding the budget {:?}",
+                fd, loop_name, duration, budget
+            ),
+            None => warn!(
+                "Timer in event loop {} took {:?}, exceeding the budget {:?}",
+                loop_name, duration, budget
+            ),
+        }
+        if let Some(hook) = SLOW_CALLBACK_HOOK.get() {
+            hook(loop_name, fd, duration, budget);
+        }
+    }
+}
+
+/// Snapshot of health metrics of event loop.
+#[derive(Debug, Default, Clone)]
+pub struct EventLoopMetrics {
+    /// Number of iterations of the loop.
+    pub iterations: u64,
+    /// Time spent on handling events and timers, not including waiting.
+    pub busy_time: Duration,
+    /// Latency of handling events and timers in each iteration.
+    pub iteration_latency: HistogramSnapshot,
+    /// Duration of the longest callback.
+    pub longest_callback: Duration,
+    /// Fd of the longest callback, None for timer.
+    pub longest_callback_fd: Option<RawFd>,
+    /// Number of callbacks exceeding the budget.
+    pub slow_callbacks: u64,
+    /// Dispatch counts of the registered fds in format of (fd, count), sorted by
+    /// count in descending order. The fds never dispatched are not included.
+    pub dispatches: Vec<(RawFd, u64)>,
+}
+
 /// Epoll Loop Context
 #[allow(clippy::vec_box)]
 pub struct EventLoopContext {
+    /// Name of the loop, `main` for main loop or id of iothread.
+    name: String,
     /// Epoll file descriptor.
     epoll: Epoll,
     /// Control epoll loop running.
@@ -212,6 +363,8 @@ pub struct EventLoopContext {
     timer_next_id: AtomicU64,
     /// Record VM clock state.
     pub clock_state: Arc<Mutex<ClockState>>,
+    /// Health metrics of the loop.
+    metrics: LoopMetrics,
 }
 
 // SAFETY: The closure in EventNotifier and Timer doesn't impl Send, they're
@@ -222,6 +375,7 @@ impl EventLoopContext {
     /// Constructs a new `EventLoopContext`.
     pub fn new() -> Self {
         let mut ctx = EventLoopContext {
+            name: "main".to_string(),
             epoll: Epoll::new().unwrap(),
             manager: None,
             kick_event: EventFd::new(EFD_NONBLOCK).unwrap(),
@@ -233,11 +387,78 @@ impl EventLoopContext {
             timers: Arc::new(Mutex::new(Vec::new())),
             timer_next_id: AtomicU64::new(0),
             clock_state: Arc::new(Mutex::new(ClockState::default())),
+            metrics: LoopMetrics::default(),
         };
         ctx.init_kick();
         ctx
     }
 
+    /// Set the name of loop which is reported in slow callback.
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Get the thread id of the loop, None if the loop has not run.
+    pub fn thread_id(&self) -> Option<u64> {
+        let tid = self.metrics.tid.load(Ordering::Relaxed);
+        (tid != 0).then_some(tid)
+    }
+
+    /// Get the time since when the loop has been handling events without going back
+    /// to wait, None if the loop is waiting. It's used to detect the stuck loop.
+    pub fn busy_since(&self) -> Option<Instant> {
+        match self.metrics.busy_since_ns.load(Ordering::Relaxed) {
+            0 => None,
+            ns => Some(*LOOP_CLOCK_BASE + Duration::from_nanos(ns - 1)),
+        }
+    }
+
+    /// Get the health metrics of the loop.
+    ///
+    /// # Arguments
+    ///
+    /// * `reset` - Clear the metrics after they are got.
+    pub fn metrics(&self, reset: bool) -> EventLoopMetrics {
+        let metrics = &self.metrics;
+        let mut dispatches: Vec<(RawFd, u64)> = self
+            .events
+            .read()
+            .unwrap()
+            .values()
+            .filter_map(|notifier| {
+                let count = if reset {
+                    notifier.dispatches.swap(0, Ordering::Relaxed)
+                } else {
+                    notifier.dispatches.load(Ordering::Relaxed)
+                };
+                (count != 0).then_some((notifier.raw_fd, count))
+            })
+            .collect();
+        dispatches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let longest_callback_fd = metrics.longest_callback_fd.load(Ordering::Relaxed);
+        let snapshot = EventLoopMetrics {
+            iterations: metrics.iterations.load(Ordering::Relaxed),
+            busy_time: Duration::from_nanos(metrics.busy_ns.load(Ordering::Relaxed)),
+            iteration_latency: metrics.iteration_latency.snapshot(),
+            longest_callback: Duration::from_nanos(
+                metrics.longest_callback_ns.load(Ordering::Relaxed),
+            ),
+            longest_callback_fd: (longest_callback_fd >= 0).then_some(longest_callback_fd),
+            slow_callbacks: metrics.slow_callbacks.load(Ordering::Relaxed),
+            dispatches,
+        };
+        if reset {
+            metrics.iterations.store(0, Ordering::Relaxed);
+            metrics.busy_ns.store(0, Ordering::Relaxed);
+            metrics.iteration_latency.reset();
+            metrics.longest_callback_ns.store(0, Ordering::Relaxed);
+            metrics.longest_callback_fd.store(0, Ordering::Relaxed);
+            metrics.slow_callbacks.store(0, Ordering::Relaxed);
+        }
+        snapshot
+    }
+
     fn init_kick(&mut self) {
         let kick_handler: Rc<NotifierCallback> = Rc::new(|_, fd| {
             read_fd(fd);
@@ -389,8 +610,25 @@ impl EventLoopContext {
         let mut events_map = self.events.write().unwrap();
         match events_map.get_mut(&event.raw_fd) {
             Some(notifier) => {
-                notifier.handlers.clear();
-                notifier.handlers.append(&mut event.handlers);
+                if notifier.event != event.event {
+                    // Status of event is not checked, as it's locked when the handlers are
+                    // dispatched. Parked event is not in epoll, and it's added with the new
+                    // eventset when resumed.
+                    if let Err(e) = self.epoll.ctl(
+                        ControlOperation::Modify,
+                        notifier.raw_fd,
+                        EpollEvent::new(event.event, &**notifier as *const _ as u64),
+                    ) {
+                        if e.raw_os_error() != Some(libc::ENOENT) {
+                            return Err(anyhow!(UtilError::BadSyscall(e)));
+                        }
+                    }
+                    notifier.event = event.event;
+                }
+                if !event.handlers.is_empty() {
+                    notifier.handlers.clear();
+                    notifier.handlers.append(&mut event.handlers);
+                }
             }
             _ => {
                 return Err(anyhow!(UtilError::NoRegisterFd(event.raw_fd)));
@@ -475,9 +713,11 @@ impl EventLoopContext {
 
     /// Executes `epoll.wait()` to wait for events, and call the responding callbacks.
     pub fn run(&mut self) -> Result<bool> {
+        self.metrics.mark_busy();
         if let Some(manager) = &self.manager {
             if manager.lock().unwrap().loop_should_exit() {
                 manager.lock().unwrap().loop_cleanup()?;
+                self.metrics.mark_idle();
                 return Ok(false);
             }
         }
@@ -486,28 +726,40 @@ impl EventLoopContext {
     }
 
     pub fn iothread_run(&mut self) -> Result<bool> {
+        self.metrics.mark_busy();
         if let Some(manager) = &self.manager {
             if manager.lock().unwrap().loop_should_exit() {
                 manager.lock().unwrap().loop_cleanup()?;
+                self.metrics.mark_idle();
                 return Ok(false);
             }
         }
 
-        let min_timeout_ns = self.timers_min_duration();
-        if min_timeout_ns.is_none() {
-            for _i in 0..AIO_PRFETCH_CYCLE_TIME {
-                for notifier in self.events.read().unwrap().values() {
-                    let status_locked = notifier.status.lock().unwrap();
-                    if *status_locked != EventStatus::Alive || notifier.handler_poll.is_none() {
-                        continue;
-                    }
-                    let handler_poll = notifier.handler_poll.as_ref().unwrap();
-                    if handler_poll(EventSet::empty(), notifier.raw_fd).is_some() {
-                        break;
-                    }
+        let mut min_timeout_ns = self.timers_min_duration();
+        // Poll only one round if timers are pending, so that they don't expire late.
+        let poll_cycles = if min_timeout_ns.is_none() {
+            AIO_PRFETCH_CYCLE_TIME
+        } else {
+            1
+        };
+        let mut poll_busy = false;
+        for _i in 0..poll_cycles {
+            for notifier in self.events.read().unwrap().values() {
+                let status_locked = notifier.status.lock().unwrap();
+                if *status_locked != EventStatus::Alive || notifier.handler_poll.is_none() {
+                    continue;
+                }
+                // Poll every handler, those in polling mode are not notified otherwise.
+                let handler_poll = notifier.handler_poll.as_ref().unwrap();
+                if handler_poll(EventSet::empty(), notifier.raw_fd).is_some() {
+                    poll_busy = true;
                 }
             }
         }
+        // Don't sleep if a poll handler is busy, it may not be notified any more.
+        if poll_busy {
+            min_timeout_ns = Some(Duration::ZERO);
+        }
         self.epoll_wait_manager(min_timeout_ns)
     }
 
@@ -580,7 +832,10 @@ impl EventLoopContext {
         let expired_timers: Vec<Box<Timer>> = timers.drain(0..expired_nr).collect();
         drop(timers);
         for timer in expired_timers {
+            let start = Instant::now();
             (timer.func)();
+            self.metrics
+                .record_callback(&self.name, None, start.elapsed());
         }
     }
 
@@ -593,6 +848,7 @@ impl EventLoopContext {
             }
         }
 
+        self.metrics.mark_idle();
         // When time_out greater then zero, use ppoll as a more precise timer.
         if time_out.is_some() && *time_out.as_ref().unwrap() != Duration::ZERO {
             let time_out_spec = Some(TimeSpec::from_duration(*time_out.as_ref().unwrap()));
@@ -619,6 +875,8 @@ impl EventLoopContext {
             self.kick_me.store(false, Ordering::SeqCst);
         }
 
+        self.metrics.mark_busy();
+        let iteration_start = Instant::now();
         for i in 0..ev_count {
             // SAFETY: elements in self.events_map never get released in other functions
             let event = unsafe {
@@ -628,9 +886,14 @@ impl EventLoopContext {
             let mut notifiers = Vec::new();
             let status_locked = event.status.lock().unwrap();
             if *status_locked == EventStatus::Alive {
+                event.dispatches.fetch_add(1, Ordering::Relaxed);
                 for j in 0..event.handlers.len() {
                     let handler = &event.handlers[j];
-                    match handler(self.ready_events[i].event_set(), event.raw_fd) {
+                    let start = Instant::now();
+                    let ret = handler(self.ready_events[i].event_set(), event.raw_fd);
+                    self.metrics
+                        .record_callback(&self.name, Some(event.raw_fd), start.elapsed());
+                    match ret {
                         None => {}
                         Some(mut notifier) => {
                             notifiers.append(&mut notifier);
@@ -646,6 +909,8 @@ impl EventLoopContext {
 
         self.run_timers();
         self.clear_gc();
+        self.metrics.record_iteration(iteration_start.elapsed());
+        self.metrics.mark_idle();
         Ok(true)
     }
 }
@@ -754,6 +1019,35 @@ mod test {
         assert!(mainloop.check_existence(fd1_related.as_raw_fd()).unwrap());
     }
 
+    #[test]
+    fn modify_eventset_test() {
+        let mut mainloop = EventLoopContext::new();
+        let fd1 = EventFd::new(EFD_NONBLOCK).unwrap();
+        let handler: Rc<NotifierCallback> = Rc::new(|_, _| None);
+        let event1 = EventNotifier::new(
+            NotifierOperation::AddShared,
+            fd1.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        );
+        mainloop.update_events(vec![event1]).unwrap();
+
+        // Handlers are kept if not given.
+        let event1_modify = EventNotifier::new(
+            NotifierOperation::Modify,
+            fd1.as_raw_fd(),
+            None,
+            EventSet::IN | EventSet::OUT,
+            Vec::new(),
+        );
+        mainloop.update_events(vec![event1_modify]).unwrap();
+        let events_map = mainloop.events.read().unwrap();
+        let notifier = events_map.get(&fd1.as_raw_fd()).unwrap();
+        assert_eq!(notifier.event, EventSet::IN | EventSet::OUT);
+        assert_eq!(notifier.handlers.len(), 1);
+    }
+
     #[test]
     fn parked_event_test() {
         let mut mainloop = EventLoopContext::new();
@@ -912,6 +1206,48 @@ mod test {
         assert!(mainloop.update_events(vec![event1]).is_ok());
     }
 
+    #[test]
+    fn metrics_test() {
+        let mut mainloop = EventLoopContext::new();
+        let fd1 = EventFd::new(EFD_NONBLOCK).unwrap();
+        let handler: Rc<NotifierCallback> = Rc::new(|_, fd| {
+            read_fd(fd);
+            std::thread::sleep(Duration::from_millis(20));
+            None
+        });
+        let event1 = EventNotifier::new(
+            NotifierOperation::AddShared,
+            fd1.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        );
+        mainloop.update_events(vec![event1]).unwrap();
+
+        assert!(mainloop.thread_id().is_none());
+        set_callback_budget(Duration::from_millis(10));
+        fd1.write(1).unwrap();
+        while mainloop.metrics(false).dispatches.is_empty() {
+            mainloop.run().unwrap();
+        }
+        set_callback_budget(Duration::ZERO);
+        assert_eq!(mainloop.thread_id(), Some(gettid()));
+        assert!(mainloop.busy_since().is_none());
+
+        let metrics = mainloop.metrics(true);
+        assert!(metrics.iterations >= 1);
+        assert!(metrics.busy_time >= Duration::from_millis(20));
+        assert!(metrics.longest_callback >= Duration::from_millis(20));
+        assert_eq!(metrics.longest_callback_fd, Some(fd1.as_raw_fd()));
+        assert_eq!(metrics.slow_callbacks, 1);
+        assert_eq!(metrics.dispatches, vec![(fd1.as_raw_fd(), 1)]);
+
+        let metrics = mainloop.metrics(false);
+        assert_eq!(metrics.iterations, 0);
+        assert_eq!(metrics.slow_callbacks, 0);
+        assert!(metrics.dispatches.is_empty());
+    }
+
     #[test]
     fn fd_released_test() {
         let mut mainloop = EventLoopContext::new();
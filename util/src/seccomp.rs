@@ -78,7 +78,15 @@
 //! ```
 //! This programe will be trapped.
 
-use anyhow::{bail, Result};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::Lazy;
 
 use crate::offset_of;
 
@@ -121,6 +129,7 @@ const SECCOMP_RET_MASK: u32 = 0x0000_ffff;
 const SECCOMP_MODE_FILTER: u32 = 1;
 /// See: https://elixir.bootlin.com/linux/v4.19.123/source/include/uapi/linux/seccomp.h#L21
 const SECCOMP_FILETER_FLAG_TSYNC: u32 = 1;
+const SECCOMP_FILTER_FLAG_NONE: u32 = 0;
 
 /// System call convention as an AUDIT_ARCH_* value
 #[cfg(target_arch = "x86_64")]
@@ -285,7 +294,7 @@ fn handle_process(opt: SeccompOpt) -> Vec<SockFilter> {
 }
 
 /// A wrapper structure of a list of bpf_filters for a syscall's rule.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BpfRule {
     /// The staged rules to avoid jump offset overflow.
     staged_rules: Vec<SockFilter>,
@@ -356,6 +365,11 @@ impl BpfRule {
         }
     }
 
+    /// Get the number of system call this rule applies to.
+    pub fn syscall_num(&self) -> i64 {
+        self.header_rule.k as i64
+    }
+
     /// Change `BpfRules` to a list of `SockFilter`. It will be used when
     /// seccomp taking effect.
     fn as_vec(&self) -> Vec<SockFilter> {
@@ -393,7 +407,7 @@ impl BpfRule {
 }
 
 /// This structure to create, manage, realize a seccomp rule.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SyscallFilter {
     /// A list of Bpf-filter.
     sock_filters: Vec<SockFilter>,
@@ -432,18 +446,33 @@ impl SyscallFilter {
     /// After use this function, all rules in seccomp will take effect whatever
     /// this structure dropped or not. You can only use this function once in
     /// a thread. Otherwise you will get an error.
-    pub fn realize(mut self) -> Result<()> {
-        // Add opt as a bpf_filter to sock_filters.
-        self.sock_filters.append(&mut handle_process(self.opt));
-
-        let sock_bpf_vec = self.sock_filters;
-
+    pub fn realize(self) -> Result<()> {
         // This operation can guarantee seccomp make use for all users and subprocess.
         let ret = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
         if ret != 0 {
             bail!("Seccomp: prctl(2) set no new privs failed.");
         }
 
+        self.install(SECCOMP_FILETER_FLAG_TSYNC)
+    }
+
+    /// Make seccomp take effect on the current thread only, which is stacked on the
+    /// filter installed by `realize`. The syscall is allowed only if all the filters
+    /// allow it.
+    ///
+    /// # Notice
+    /// `realize` must have been called, which sets no new privs for all the threads,
+    /// and `seccomp(2)` must be allowed by its filter.
+    pub fn realize_thread(self) -> Result<()> {
+        self.install(SECCOMP_FILTER_FLAG_NONE)
+    }
+
+    fn install(mut self, flags: u32) -> Result<()> {
+        // Add opt as a bpf_filter to sock_filters.
+        self.sock_filters.append(&mut handle_process(self.opt));
+
+        let sock_bpf_vec = self.sock_filters;
+
         let prog = SockFProg {
             len: sock_bpf_vec.len() as u16,
             sock_filter: sock_bpf_vec.as_ptr(),
@@ -451,14 +480,8 @@ impl SyscallFilter {
         let bpf_prog_ptr = &prog as *const SockFProg;
 
         // Use seccomp(2) to make bpf rules take effect.
-        let ret = unsafe {
-            libc::syscall(
-                libc::SYS_seccomp,
-                SECCOMP_MODE_FILTER,
-                SECCOMP_FILETER_FLAG_TSYNC,
-                bpf_prog_ptr,
-            )
-        };
+        let ret =
+            unsafe { libc::syscall(libc::SYS_seccomp, SECCOMP_MODE_FILTER, flags, bpf_prog_ptr) };
         if ret != 0 {
             bail!("Seccomp: seccomp(2) set seccomp filter mode failed.");
         }
@@ -467,6 +490,159 @@ impl SyscallFilter {
     }
 }
 
+/// Class of threads which share a seccomp profile.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ThreadClass {
+    /// The main thread, which runs the main loop and handles qmp.
+    Main,
+    /// Threads which run vcpus.
+    Vcpu,
+    /// Threads which run iothreads.
+    IoThread,
+    /// Threads which send migration or take background snapshot.
+    Migration,
+}
+
+/// Seccomp filters of thread classes, which are applied by the threads themselves.
+static THREAD_FILTERS: Lazy<Mutex<HashMap<ThreadClass, SyscallFilter>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+/// Whether filters of thread classes have been registered.
+static THREAD_FILTERS_READY: AtomicBool = AtomicBool::new(false);
+
+/// Sender of the jobs spawning class threads to the launcher thread.
+static THREAD_LAUNCHER: Lazy<Mutex<Option<Sender<Box<dyn FnOnce() + Send>>>>> =
+    Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    /// Whether the current thread has applied the filter of its class.
+    static THREAD_FILTER_APPLIED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Register seccomp filters of thread classes, each thread applies the filter of its
+/// class by calling `apply_thread_filter`. The launcher of class threads is started
+/// here, so it must be called before the caller applies its own filter.
+///
+/// # Arguments
+///
+/// * `filters` - Filters of thread classes, which are stacked on the filter realized
+///   for the whole process.
+pub fn register_thread_filters(filters: Vec<(ThreadClass, SyscallFilter)>) -> Result<()> {
+    let (sender, receiver) = channel::<Box<dyn FnOnce() + Send>>();
+    thread::Builder::new()
+        .name("thread_launcher".to_string())
+        .spawn(move || {
+            while let Ok(job) = receiver.recv() {
+                job();
+            }
+        })
+        .with_context(|| "Failed to create thread launcher")?;
+    *THREAD_LAUNCHER.lock().unwrap() = Some(sender);
+
+    THREAD_FILTERS.lock().unwrap().extend(filters);
+    THREAD_FILTERS_READY.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Spawn a thread which applies the filter of its class. Threads inherit the filters
+/// of their creator, so it's spawned by the launcher thread which only has the filter
+/// of the whole process, rather than by the caller which may have applied its own.
+///
+/// # Arguments
+///
+/// * `builder` - Builder of the thread.
+/// * `f` - Function run by the thread.
+pub fn spawn_class_thread<F, T>(builder: thread::Builder, f: F) -> std::io::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let launcher = THREAD_LAUNCHER.lock().unwrap().clone();
+    let launcher = match launcher {
+        Some(launcher) => launcher,
+        None => return builder.spawn(f),
+    };
+
+    let (sender, receiver) = channel();
+    let job = Box::new(move || {
+        let _ = sender.send(builder.spawn(f));
+    });
+    let launcher_err = || std::io::Error::new(std::io::ErrorKind::Other, "No thread launcher");
+    launcher.send(job).map_err(|_| launcher_err())?;
+    receiver.recv().map_err(|_| launcher_err())?
+}
+
+/// Apply the registered seccomp filter of the class to the current thread. It's
+/// called each time the thread is going to run, and takes effect only once.
+///
+/// # Arguments
+///
+/// * `class` - Class of the current thread.
+pub fn apply_thread_filter(class: ThreadClass) -> Result<()> {
+    if !THREAD_FILTERS_READY.load(Ordering::SeqCst) || THREAD_FILTER_APPLIED.with(Cell::get) {
+        return Ok(());
+    }
+    THREAD_FILTER_APPLIED.with(|applied| applied.set(true));
+    let filter = THREAD_FILTERS.lock().unwrap().get(&class).cloned();
+    match filter {
+        Some(filter) => filter.realize_thread(),
+        None => Ok(()),
+    }
+}
+
+macro_rules! syscall_table {
+    ($($nr:ident),* $(,)?) => {
+        &[$((stringify!($nr), libc::$nr)),*]
+    };
+}
+
+/// Syscalls which can be named in seccomp policy.
+const SYSCALL_TABLE: &[(&str, i64)] = syscall_table! {
+    SYS_accept4, SYS_bind, SYS_bpf, SYS_brk, SYS_chroot, SYS_clock_gettime,
+    SYS_clock_nanosleep, SYS_clone, SYS_clone3, SYS_close, SYS_connect, SYS_delete_module,
+    SYS_dup, SYS_epoll_ctl, SYS_epoll_pwait, SYS_eventfd2, SYS_execve, SYS_execveat,
+    SYS_exit, SYS_exit_group, SYS_faccessat, SYS_fallocate, SYS_fchmod, SYS_fchmodat,
+    SYS_fchown, SYS_fcntl, SYS_fdatasync, SYS_finit_module, SYS_flock, SYS_fstat,
+    SYS_fstatfs, SYS_fsync, SYS_ftruncate, SYS_futex, SYS_getcwd, SYS_getdents64,
+    SYS_getegid, SYS_geteuid, SYS_getgid, SYS_getpeername, SYS_getpid, SYS_getppid,
+    SYS_getpriority, SYS_getrandom, SYS_getresgid, SYS_getresuid, SYS_getrlimit,
+    SYS_getsockname, SYS_getsockopt, SYS_gettid, SYS_getuid, SYS_init_module,
+    SYS_io_destroy, SYS_io_getevents, SYS_io_setup, SYS_io_submit, SYS_io_uring_enter,
+    SYS_io_uring_register, SYS_io_uring_setup, SYS_ioctl, SYS_kexec_load, SYS_kill,
+    SYS_listen, SYS_lremovexattr, SYS_lseek, SYS_madvise, SYS_mbind, SYS_memfd_create,
+    SYS_mkdirat, SYS_mlock, SYS_mmap, SYS_mount, SYS_mprotect, SYS_mremap, SYS_msync,
+    SYS_munlock, SYS_munmap, SYS_nanosleep, SYS_newfstatat, SYS_openat, SYS_personality,
+    SYS_pipe2, SYS_pivot_root, SYS_ppoll, SYS_prctl, SYS_pread64, SYS_preadv,
+    SYS_prlimit64, SYS_process_vm_readv, SYS_process_vm_writev, SYS_ptrace, SYS_pwrite64,
+    SYS_pwritev, SYS_read, SYS_readlinkat, SYS_readv, SYS_reboot, SYS_recvfrom,
+    SYS_recvmsg, SYS_renameat, SYS_rseq, SYS_rt_sigaction, SYS_rt_sigprocmask,
+    SYS_rt_sigreturn, SYS_sched_getaffinity, SYS_sched_setaffinity, SYS_sched_setattr,
+    SYS_sched_yield, SYS_seccomp, SYS_sendmmsg, SYS_sendmsg, SYS_sendto,
+    SYS_set_robust_list, SYS_setns, SYS_setpriority, SYS_setrlimit, SYS_setsid,
+    SYS_setsockopt, SYS_shmat, SYS_shmctl, SYS_shmdt, SYS_shmget, SYS_shutdown,
+    SYS_sigaltstack, SYS_socket, SYS_socketpair, SYS_splice, SYS_statx, SYS_swapoff,
+    SYS_swapon, SYS_sync, SYS_syncfs, SYS_sysinfo, SYS_tee, SYS_tgkill, SYS_timerfd_create,
+    SYS_timerfd_gettime, SYS_timerfd_settime, SYS_tkill, SYS_umount2, SYS_uname,
+    SYS_unlinkat, SYS_unshare, SYS_userfaultfd, SYS_write, SYS_writev,
+};
+
+/// Syscalls which only exist on x86_64.
+#[cfg(target_arch = "x86_64")]
+const SYSCALL_TABLE_ARCH: &[(&str, i64)] = syscall_table! {
+    SYS_access, SYS_epoll_wait, SYS_fadvise64, SYS_fork, SYS_mkdir, SYS_open, SYS_poll,
+    SYS_readlink, SYS_stat, SYS_unlink, SYS_vfork,
+};
+#[cfg(target_arch = "aarch64")]
+const SYSCALL_TABLE_ARCH: &[(&str, i64)] = &[];
+
+/// Get the number of system call by its name, such as `openat`.
+pub fn syscall_num(name: &str) -> Option<i64> {
+    SYSCALL_TABLE
+        .iter()
+        .chain(SYSCALL_TABLE_ARCH)
+        .find(|(nr, _)| nr.strip_prefix("SYS_") == Some(name))
+        .map(|(_, num)| *num)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -672,4 +848,16 @@ mod tests {
 
         assert_eq!(seccomp_filter.sock_filters, bpf_vec);
     }
+    #[test]
+    fn test_syscall_num() {
+        assert_eq!(syscall_num("read"), Some(libc::SYS_read));
+        assert_eq!(syscall_num("execve"), Some(libc::SYS_execve));
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(syscall_num("open"), Some(libc::SYS_open));
+        assert_eq!(syscall_num("SYS_read"), None);
+        assert_eq!(syscall_num("unknown"), None);
+
+        let rule = BpfRule::new(libc::SYS_ioctl).add_constraint(SeccompCmpOpt::Eq, 1, 0xAE80);
+        assert_eq!(rule.syscall_num(), libc::SYS_ioctl);
+    }
 }
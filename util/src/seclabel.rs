@@ -0,0 +1,186 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::ffi::CString;
+use std::os::unix::io::RawFd;
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+use crate::seccomp::BpfRule;
+
+const SELINUX_XATTR: &[u8] = b"security.selinux\0";
+/// Attribute of AppArmor, the legacy one is used if LSM stacking isn't supported by host.
+const APPARMOR_CURRENT: [&str; 2] = [
+    "/proc/self/attr/apparmor/current",
+    "/proc/self/attr/current",
+];
+
+/// Security module which the label belongs to.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecurityModel {
+    /// Files, sockets and memfd are labeled by the given context.
+    Selinux,
+    /// AppArmor mediates resources by path, StratoVirt should be confined by the given profile.
+    Apparmor,
+}
+
+/// Security label of the resources created by StratoVirt, which makes per VM isolation
+/// policies like svirt work.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecurityLabel {
+    pub model: SecurityModel,
+    pub label: String,
+}
+
+static SECURITY_LABEL: OnceCell<SecurityLabel> = OnceCell::new();
+
+/// Set the security label, which should be called before any resource is created.
+/// For AppArmor, check that the process is confined by the profile.
+///
+/// # Arguments
+///
+/// * `seclabel` - The security label.
+pub fn set_security_label(seclabel: SecurityLabel) -> Result<()> {
+    if seclabel.model == SecurityModel::Apparmor {
+        let profile = current_apparmor_profile()?;
+        if profile != seclabel.label {
+            bail!(
+                "StratoVirt is confined by AppArmor profile {}, not {}",
+                profile,
+                seclabel.label
+            );
+        }
+    }
+    if SECURITY_LABEL.set(seclabel).is_err() {
+        bail!("Security label has already been set");
+    }
+    Ok(())
+}
+
+fn current_apparmor_profile() -> Result<String> {
+    let content = APPARMOR_CURRENT
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .with_context(|| "Failed to get AppArmor profile, AppArmor may be not enabled")?;
+    Ok(parse_apparmor_profile(&content))
+}
+
+/// The attribute is in format of `<profile> (<mode>)`, or `unconfined`.
+fn parse_apparmor_profile(content: &str) -> String {
+    let content = content.trim_end_matches(['\n', '\0']);
+    match content.rsplit_once(" (") {
+        Some((profile, mode)) if mode.ends_with(')') => profile.to_string(),
+        _ => content.to_string(),
+    }
+}
+
+/// Create syscall bpf rules for labeling resources created after seccomp takes effect,
+/// such as hot plugged chardev sockets.
+pub fn seclabel_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
+    if selinux_label().is_some() {
+        syscall_allow_list.extend(vec![
+            BpfRule::new(libc::SYS_fsetxattr),
+            BpfRule::new(libc::SYS_lsetxattr),
+        ]);
+    }
+}
+
+fn selinux_label() -> Option<CString> {
+    match SECURITY_LABEL.get() {
+        Some(seclabel) if seclabel.model == SecurityModel::Selinux => {
+            // The label is checked to have no nul byte when parsing config.
+            CString::new(seclabel.label.as_str()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Set the security label of the file or socket created by StratoVirt, it's a no-op if
+/// no SELinux label is set.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file or socket.
+pub fn label_path(path: &str) -> Result<()> {
+    let label = match selinux_label() {
+        Some(label) => label,
+        None => return Ok(()),
+    };
+    let cpath = CString::new(path).with_context(|| format!("Invalid path {}", path))?;
+    // SAFETY: all the strings are nul-terminated and the return value is checked.
+    let ret = unsafe {
+        libc::lsetxattr(
+            cpath.as_ptr(),
+            SELINUX_XATTR.as_ptr() as *const libc::c_char,
+            label.as_ptr() as *const libc::c_void,
+            label.as_bytes_with_nul().len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to set security label of {}", path));
+    }
+    Ok(())
+}
+
+/// Set the security label of the file created by StratoVirt, such as memfd which has no
+/// path, it's a no-op if no SELinux label is set.
+///
+/// # Arguments
+///
+/// * `fd` - Fd of the file.
+pub fn label_fd(fd: RawFd) -> Result<()> {
+    let label = match selinux_label() {
+        Some(label) => label,
+        None => return Ok(()),
+    };
+    // SAFETY: all the strings are nul-terminated and the return value is checked.
+    let ret = unsafe {
+        libc::fsetxattr(
+            fd,
+            SELINUX_XATTR.as_ptr() as *const libc::c_char,
+            label.as_ptr() as *const libc::c_void,
+            label.as_bytes_with_nul().len(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to set security label of fd {}", fd));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_apparmor_profile() {
+        assert_eq!(
+            parse_apparmor_profile("libvirt-1234 (enforce)\n"),
+            "libvirt-1234"
+        );
+        assert_eq!(
+            parse_apparmor_profile("/usr/bin/stratovirt (complain)\n\0"),
+            "/usr/bin/stratovirt"
+        );
+        assert_eq!(parse_apparmor_profile("unconfined\n"), "unconfined");
+
+        // No label is set, resources are not labeled.
+        assert!(label_path("/no/such/file").is_ok());
+        assert!(label_fd(-1).is_ok());
+    }
+}
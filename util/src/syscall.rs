@@ -11,7 +11,7 @@
 // See the Mulan PSL v2 for more details.
 
 use anyhow::{bail, Result};
-use libc::{c_void, syscall, SYS_mbind};
+use libc::{c_void, syscall, SYS_mbind, SYS_set_mempolicy};
 
 /// This function set memory policy for host NUMA node memory range.
 ///
@@ -51,3 +51,23 @@ pub fn mbind(
 
     Ok(())
 }
+
+/// This function set memory policy of the calling thread, which applies to the memory
+/// allocated by the thread later.
+///
+/// * Arguments
+///
+/// * `mode` - Memory policy mode.
+/// * `node_mask` - node_mask specifies physical node ID.
+/// * `max_node` - The max node.
+pub fn set_mempolicy(mode: u32, node_mask: Vec<u64>, max_node: u64) -> Result<()> {
+    let res = unsafe { syscall(SYS_set_mempolicy, mode, node_mask.as_ptr(), max_node + 1) };
+    if res < 0 {
+        bail!(
+            "Failed to set thread numa node policy, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+
+    Ok(())
+}
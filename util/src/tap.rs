@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::cmp;
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Result as IoResult, Write};
 use std::os::unix::fs::OpenOptionsExt;
@@ -17,6 +18,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Context, Result};
+use io_uring::{opcode, squeue, types, IoUring};
 use log::error;
 use vmm_sys_util::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
 use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
@@ -37,17 +39,29 @@ const IFF_NO_PI: u16 = 0x1000;
 const IFF_VNET_HDR: u16 = 0x4000;
 const TUNTAP_PATH: &str = "/dev/net/tun";
 const IFNAME_SIZE: usize = 16;
+/// Size of the union in `struct ifreq` after the flags, which is written by TUNGETIFF.
+const IFREQ_PAD_SIZE: usize = 22;
+/// Number of rx buffers registered to io_uring, it bounds the packets read by one submission.
+pub const TAP_URING_RX_BATCH: usize = 32;
+/// Max number of packets written to tap by one submission.
+pub const TAP_URING_TX_BATCH: usize = 32;
+const TAP_URING_ENTRIES: u32 = 64;
+/// Size of each rx buffer, enough for a 64KiB GSO packet with its virtio net header.
+const TAP_URING_BUF_SIZE: usize = 4096 + 65536;
 
 ioctl_iow_nr!(TUNSETIFF, 84, 202, ::std::os::raw::c_int);
 ioctl_ior_nr!(TUNGETFEATURES, 84, 207, ::std::os::raw::c_uint);
+ioctl_ior_nr!(TUNGETIFF, 84, 210, ::std::os::raw::c_uint);
 ioctl_iow_nr!(TUNSETOFFLOAD, 84, 208, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETVNETHDRSZ, 84, 216, ::std::os::raw::c_int);
 ioctl_iow_nr!(TUNSETQUEUE, 84, 217, ::std::os::raw::c_int);
 
 #[repr(C)]
+#[derive(Default)]
 pub struct IfReq {
     ifr_name: [u8; IFNAME_SIZE],
     ifr_flags: u16,
+    ifr_pad: [u8; IFREQ_PAD_SIZE],
 }
 
 #[derive(Clone)]
@@ -72,6 +86,7 @@ impl Tap {
             let mut if_req = IfReq {
                 ifr_name,
                 ifr_flags: IFF_TAP | IFF_NO_PI | IFF_VNET_HDR,
+                ..Default::default()
             };
 
             if queue_pairs > 1 {
@@ -99,6 +114,7 @@ impl Tap {
                 libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
                 File::from_raw_fd(fd)
             };
+            check_tap_fd(&file, queue_pairs).with_context(|| format!("Invalid tap fd {}", fd))?;
         } else {
             return Err(anyhow!(
                 "Open tap failed, unsupported operation, error is {}",
@@ -160,8 +176,8 @@ impl Tap {
             IFF_DETACH_QUEUE
         };
         let mut if_req = IfReq {
-            ifr_name: [0_u8; IFNAME_SIZE],
             ifr_flags,
+            ..Default::default()
         };
 
         let ret = unsafe { ioctl_with_mut_ref(self.file.as_ref(), TUNSETQUEUE(), &mut if_req) };
@@ -189,3 +205,222 @@ impl Tap {
         self.file.as_raw_fd()
     }
 }
+
+/// Tap I/O through io_uring. The tap fd is registered as a fixed file and rx packets are
+/// read into registered buffers, so that a batch of packets costs a single syscall. Multishot
+/// recv is not used because tap is a character device rather than a socket.
+pub struct TapUring {
+    ring: IoUring,
+    // Dropped after `ring`, so the registered buffers outlive the io_uring instance.
+    bufs: Vec<Vec<u8>>,
+}
+
+impl TapUring {
+    pub fn new(tap: &Tap) -> Result<Self> {
+        Self::with_fd(tap.as_raw_fd())
+    }
+
+    fn with_fd(fd: RawFd) -> Result<Self> {
+        let mut ring = IoUring::new(TAP_URING_ENTRIES)
+            .with_context(|| "Failed to create io_uring instance for tap")?;
+        ring.submitter()
+            .register_files(&[fd])
+            .with_context(|| "Failed to register tap fd to io_uring")?;
+        Self::probe_nowait(&mut ring)?;
+
+        let mut bufs = vec![vec![0_u8; TAP_URING_BUF_SIZE]; TAP_URING_RX_BATCH];
+        let iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect();
+        // SAFETY: the buffers are never reallocated and live as long as the ring.
+        unsafe { ring.submitter().register_buffers(&iovecs) }
+            .with_context(|| "Failed to register rx buffers to io_uring")?;
+
+        Ok(TapUring { ring, bufs })
+    }
+
+    /// Reads are issued with `RWF_NOWAIT`, otherwise io_uring arms a poll for a read on an
+    /// empty tap and the request stays pending until a packet arrives, even with O_NONBLOCK.
+    /// Check it's supported with a zero-length read, which doesn't consume a packet.
+    fn probe_nowait(ring: &mut IoUring) -> Result<()> {
+        let entry = opcode::Read::new(types::Fixed(0), std::ptr::null_mut(), 0)
+            .rw_flags(libc::RWF_NOWAIT)
+            .build();
+        // SAFETY: nothing is read into the buffer as the length is zero.
+        unsafe { ring.submission().push(&entry) }
+            .with_context(|| "Failed to push tap probe entry")?;
+        ring.submit_and_wait(1)
+            .with_context(|| "Failed to submit tap probe entry")?;
+        let res = ring
+            .completion()
+            .next()
+            .with_context(|| "No completion of tap probe entry")?
+            .result();
+        if res < 0 && res != -libc::EAGAIN {
+            bail!(
+                "Tap doesn't support nowait read through io_uring: {:?}",
+                std::io::Error::from_raw_os_error(-res)
+            );
+        }
+        Ok(())
+    }
+
+    /// Read at most `count` packets from tap. Return the index of the buffer holding each
+    /// packet and the packet length, in the order of the packets being read.
+    pub fn read_packets(&mut self, count: usize) -> Result<Vec<(usize, usize)>> {
+        let count = cmp::min(count, self.bufs.len());
+        for (index, buf) in self.bufs.iter_mut().take(count).enumerate() {
+            let entry = opcode::ReadFixed::new(
+                types::Fixed(0),
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                index as u16,
+            )
+            .rw_flags(libc::RWF_NOWAIT)
+            .build()
+            .user_data(index as u64);
+            // SAFETY: the buffer is registered and is not accessed until the request completes.
+            unsafe { self.ring.submission().push(&entry) }
+                .with_context(|| "Failed to push tap read entry")?;
+        }
+        // With RWF_NOWAIT, all the reads complete during submission, either with a packet or
+        // with EAGAIN once the tap is drained. No read is left pending on the rx buffers.
+        self.ring
+            .submit_and_wait(count)
+            .with_context(|| "Failed to submit tap read entries")?;
+
+        let mut packets = Vec::with_capacity(count);
+        for cqe in self.ring.completion() {
+            let res = cqe.result();
+            if res > 0 {
+                packets.push((cqe.user_data() as usize, res as usize));
+            } else if res < 0 && res != -libc::EAGAIN {
+                error!(
+                    "Failed to read tap through io_uring: {:?}",
+                    std::io::Error::from_raw_os_error(-res)
+                );
+            }
+        }
+        packets.sort_unstable_by_key(|(index, _)| *index);
+        Ok(packets)
+    }
+
+    /// Get the packet read into the rx buffer `index`.
+    pub fn rx_buf(&self, index: usize, len: usize) -> &[u8] {
+        &self.bufs[index][..len]
+    }
+
+    /// Write packets to tap, each of which is described by a list of iovecs. Return the
+    /// result of each write in order, a negative value is the errno. The writes are linked,
+    /// so that the packets after a failed one are canceled with `ECANCELED` and their order
+    /// is kept when they are sent again.
+    pub fn write_packets(&mut self, packets: &[Vec<libc::iovec>]) -> Result<Vec<i32>> {
+        if packets.len() > TAP_URING_TX_BATCH {
+            bail!(
+                "Too many packets {} in a batch, the max is {}",
+                packets.len(),
+                TAP_URING_TX_BATCH
+            );
+        }
+        for (index, iovecs) in packets.iter().enumerate() {
+            let mut entry =
+                opcode::Writev::new(types::Fixed(0), iovecs.as_ptr(), iovecs.len() as u32)
+                    .build()
+                    .user_data(index as u64);
+            if index + 1 < packets.len() {
+                entry = entry.flags(squeue::Flags::IO_LINK);
+            }
+            // SAFETY: the iovecs and the memory they point to are valid until the request
+            // completes, as it is waited for below.
+            unsafe { self.ring.submission().push(&entry) }
+                .with_context(|| "Failed to push tap write entry")?;
+        }
+        self.ring
+            .submit_and_wait(packets.len())
+            .with_context(|| "Failed to submit tap write entries")?;
+
+        let mut results = vec![0; packets.len()];
+        for cqe in self.ring.completion() {
+            results[cqe.user_data() as usize] = cqe.result();
+        }
+        Ok(results)
+    }
+}
+
+/// Check that the fd passed by management is a tap device which is usable by virtio net,
+/// so that StratoVirt doesn't need CAP_NET_ADMIN to create it.
+fn check_tap_fd(file: &File, queue_pairs: u16) -> Result<()> {
+    let mut if_req = IfReq::default();
+    // SAFETY: file is valid and if_req is as large as `struct ifreq`.
+    let ret = unsafe { ioctl_with_mut_ref(file, TUNGETIFF(), &mut if_req) };
+    if ret < 0 {
+        bail!(
+            "It's not a tap device, error is {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    check_tap_flags(if_req.ifr_flags, queue_pairs)
+}
+
+fn check_tap_flags(flags: u16, queue_pairs: u16) -> Result<()> {
+    if flags & IFF_TAP == 0 {
+        bail!("It's a tun device, but a tap device is required");
+    }
+    if flags & IFF_NO_PI == 0 || flags & IFF_VNET_HDR == 0 {
+        bail!("Tap device should be created with IFF_NO_PI and IFF_VNET_HDR");
+    }
+    if queue_pairs > 1 && flags & IFF_MULTI_QUEUE == 0 {
+        bail!("Tap device should be created with IFF_MULTI_QUEUE for multiqueue");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_tap_fd() {
+        assert_eq!(std::mem::size_of::<IfReq>(), 40);
+        let flags = IFF_TAP | IFF_NO_PI | IFF_VNET_HDR;
+        assert!(check_tap_flags(flags, 1).is_ok());
+        assert!(check_tap_flags(flags | IFF_MULTI_QUEUE, 2).is_ok());
+        assert!(check_tap_flags(flags, 2).is_err());
+        assert!(check_tap_flags(0x01 | IFF_NO_PI | IFF_VNET_HDR, 1).is_err());
+        assert!(check_tap_flags(IFF_TAP | IFF_NO_PI, 1).is_err());
+
+        // A regular file is not a tap device.
+        let file = File::open("/dev/null").unwrap();
+        assert!(check_tap_fd(&file, 1).is_err());
+    }
+
+    #[test]
+    fn test_tap_uring_read_nowait() {
+        let mut fds = [0; 2];
+        // SAFETY: fds is large enough for the pipe.
+        assert_eq!(
+            unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK) },
+            0
+        );
+        // SAFETY: the fds are just created and owned by the files.
+        let (rx, mut tx) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let Ok(mut uring) = TapUring::with_fd(rx.as_raw_fd()) else {
+            // io_uring may be unavailable in the test environment.
+            return;
+        };
+
+        // Reading an empty pipe doesn't wait for data to arrive.
+        assert!(uring.read_packets(TAP_URING_RX_BATCH).unwrap().is_empty());
+
+        tx.write_all(&[0x5a; 64]).unwrap();
+        let packets = uring.read_packets(TAP_URING_RX_BATCH).unwrap();
+        assert_eq!(packets.len(), 1);
+        let (index, len) = packets[0];
+        assert_eq!(uring.rx_buf(index, len), &[0x5a; 64]);
+        assert!(uring.read_packets(TAP_URING_RX_BATCH).unwrap().is_empty());
+    }
+}
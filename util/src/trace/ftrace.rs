@@ -10,20 +10,13 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
-use std::collections::HashSet;
 use std::fs::{File, OpenOptions};
 use std::io::{prelude::Write, BufRead, BufReader};
-use std::ops::Deref;
-use std::sync::Arc;
 
-use anyhow::{Context, Result};
-use arc_swap::ArcSwap;
 use log::error;
 use once_cell::sync::Lazy;
 
 static TRACE_MARKER_FD: Lazy<Option<File>> = Lazy::new(open_trace_marker);
-static TRACE_EVENTS: Lazy<ArcSwap<HashSet<String>>> =
-    Lazy::new(|| ArcSwap::new(Arc::new(HashSet::new())));
 
 fn open_trace_marker() -> Option<File> {
     let file = "/proc/mounts";
@@ -39,6 +32,10 @@ fn open_trace_marker() -> Option<File> {
     loop {
         buf = String::new();
         match reader.read_line(&mut buf) {
+            Ok(0) => {
+                error!("Tracefs is not mounted.");
+                return None;
+            }
             Ok(_) => {
                 if buf.contains("tracefs") {
                     break;
@@ -80,55 +77,15 @@ fn open_trace_marker() -> Option<File> {
     }
 }
 
-pub fn write_trace_marker(event: &str, msg: &str) {
-    if !is_trace_event_enabled(event) {
-        return;
-    }
-
+/// Write the event to trace marker, so that it's in the same ring buffer with the
+/// events of host kernel, e.g. `sched` and `kvm`.
+pub(super) fn write_trace_marker(event: &str, msg: &str) {
+    let fd = match TRACE_MARKER_FD.as_ref() {
+        Some(fd) => fd,
+        None => return,
+    };
     let msg = format!("[{}] {}", event, msg);
-    if let Err(e) = TRACE_MARKER_FD.as_ref().unwrap().write(msg.as_bytes()) {
+    if let Err(e) = (&*fd).write(msg.as_bytes()) {
         error!("Write trace_marker error: {:?}", e);
     }
 }
-
-#[macro_export]
-macro_rules! ftrace {
-    ($func: ident) => {
-        let func = stringify!($func);
-        let msg = String::new();
-        $crate::trace::write_trace_marker(func, &msg);
-    };
-    ($func: ident, $($arg: tt)*) => {
-        let func = stringify!($func);
-        let msg = format!("{}", format_args!($($arg)*));
-        $crate::trace::write_trace_marker(func, &msg);
-    };
-}
-
-pub fn enable_trace_events(file: &str) -> Result<()> {
-    let fd = File::open(file).with_context(|| format!("Failed to open {}.", file))?;
-    let mut reader = BufReader::new(fd);
-
-    loop {
-        let mut buf = String::new();
-        let size = reader
-            .read_line(&mut buf)
-            .with_context(|| format!("Read {} error.", file))?;
-
-        if size == 0 {
-            return Ok(());
-        }
-
-        let mut trace_events = TRACE_EVENTS.load().deref().deref().clone();
-        trace_events.insert(buf.trim().to_string());
-        TRACE_EVENTS.store(Arc::new(trace_events));
-    }
-}
-
-pub fn is_trace_event_enabled(event: &str) -> bool {
-    if TRACE_EVENTS.load().is_empty() {
-        return false;
-    }
-
-    TRACE_EVENTS.load().contains(event)
-}
@@ -0,0 +1,31 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+
+#[link(name = "lttng-ust")]
+extern "C" {
+    /// The function behind `tracef()` of LTTng-UST, which records the formatted
+    /// message as event `lttng_ust_tracef:event`.
+    fn lttng_ust__tracef(fmt: *const c_char, ...);
+}
+
+/// Record the event by LTTng-UST, it's dropped if no LTTng session enables
+/// `lttng_ust_tracef:*` events.
+pub(super) fn write_tracef(event: &str, msg: &str) {
+    // Message can't contain nul byte as a C string.
+    let msg = format!("[{}] {}", event, msg).replace('\0', "\\0");
+    let msg = CString::new(msg).unwrap();
+    // SAFETY: The format string and the message are both valid C strings.
+    unsafe { lttng_ust__tracef(b"%s\0".as_ptr() as *const c_char, msg.as_ptr()) };
+}
@@ -0,0 +1,327 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod ftrace;
+#[cfg(feature = "trace_to_lttng")]
+mod lttng;
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use once_cell::sync::{Lazy, OnceCell};
+
+use crate::time::gettime;
+use crate::unix::gettid;
+
+static TRACE_BACKEND: OnceCell<TraceBackend> = OnceCell::new();
+
+/// Max number of the recent trace events kept in memory, 0 means not to keep them.
+static TRACE_HISTORY_SIZE: AtomicUsize = AtomicUsize::new(0);
+/// Recent trace events, which are dumped when VMM crashes.
+static TRACE_HISTORY: Lazy<Mutex<VecDeque<String>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+
+/// A trace point whose state can be switched at runtime.
+pub struct TraceEvent {
+    name: &'static str,
+    enabled: AtomicBool,
+}
+
+impl TraceEvent {
+    const fn new(name: &'static str) -> Self {
+        TraceEvent {
+            name,
+            enabled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    fn set_enabled(&self, enable: bool) {
+        self.enabled.store(enable, Ordering::Relaxed);
+    }
+}
+
+/// Define trace events. Each event `name(args) => "format";` generates a static
+/// `TraceEvent` and a function `name(args)` which writes the formatted message to
+/// trace marker if the event is enabled, so the message is not formatted otherwise.
+macro_rules! define_trace_events {
+    ($($(#[$attr: meta])* $name: ident($($arg: ident: $ty: ty),*) => $fmt: literal;)*) => {
+        #[allow(non_upper_case_globals)]
+        mod event {
+            use super::TraceEvent;
+
+            $(pub(super) static $name: TraceEvent = TraceEvent::new(stringify!($name));)*
+        }
+
+        static TRACE_EVENT_LIST: &[&TraceEvent] = &[$(&event::$name),*];
+
+        $(
+            $(#[$attr])*
+            #[inline]
+            pub fn $name($($arg: $ty),*) {
+                if event::$name.enabled() {
+                    write_trace_marker(stringify!($name), &format!($fmt, $($arg),*));
+                }
+            }
+        )*
+    };
+}
+
+define_trace_events! {
+    /// Boot config of vCPU.
+    cpu_boot_config(boot_config: &dyn Debug) => "{:#?}";
+    /// CPU topology of micro VM.
+    cpu_topo(cpu_topo: &dyn Debug) => "{:#?}";
+    /// System bus of micro VM.
+    sysbus(sysbus: &dyn Debug) => "{:?}";
+    /// Replaceable devices of micro VM.
+    replaceable_info(replaceable_info: &dyn Debug) => "{:?}";
+    /// State of VM.
+    vm_state(vm_state: &dyn Debug) => "{:#?}";
+    /// Config of replaceable mmio device.
+    mmio_replaceable_config(config: &dyn Debug) => "{:#?}";
+    /// Virtio device is notified by guest.
+    virtio_receive_request(device: &str, behaviour: &str) =>
+        "{} : Request received from Guest {}, ready to start processing.";
+    /// Virtio device finishes processing requests.
+    virtio_send_interrupt(device: &str) =>
+        "{} : stratovirt processing complete, ready to send interrupt to guest.";
+}
+
+/// Backend which trace events are emitted into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceBackend {
+    /// Trace marker of ftrace in host kernel.
+    Ftrace,
+    /// Tracef events of LTTng-UST.
+    Lttng,
+}
+
+impl FromStr for TraceBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ftrace" => Ok(TraceBackend::Ftrace),
+            "lttng" => Ok(TraceBackend::Lttng),
+            _ => bail!("Unknown trace backend {}, expected ftrace or lttng", s),
+        }
+    }
+}
+
+/// Set the backend of trace events, which is ftrace by default. It can only be set
+/// once before any event is emitted.
+pub fn set_trace_backend(backend: TraceBackend) -> Result<()> {
+    #[cfg(not(feature = "trace_to_lttng"))]
+    if backend == TraceBackend::Lttng {
+        bail!("LTTng trace backend is not supported, rebuild with feature trace_to_lttng");
+    }
+    if TRACE_BACKEND.set(backend).is_err() && TRACE_BACKEND.get() != Some(&backend) {
+        bail!(
+            "Trace backend has already been set to {:?}",
+            TRACE_BACKEND.get().unwrap()
+        );
+    }
+    Ok(())
+}
+
+/// Set the max number of the recent trace events kept in memory, 0 means not to keep
+/// them. Only the enabled events are kept.
+pub fn set_trace_history_size(size: usize) {
+    TRACE_HISTORY_SIZE.store(size, Ordering::Relaxed);
+    let mut history = TRACE_HISTORY.lock().unwrap();
+    while history.len() > size {
+        history.pop_front();
+    }
+}
+
+/// Get the recent trace events, the oldest is the first.
+pub fn trace_history() -> Vec<String> {
+    match TRACE_HISTORY.try_lock() {
+        Ok(history) => history.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn record_trace_history(event: &str, msg: &str) {
+    let size = TRACE_HISTORY_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        return;
+    }
+    let (sec, nsec) = gettime();
+    let record = format!("{}.{:09} [{}] {}: {}", sec, nsec, gettid(), event, msg);
+    let mut history = TRACE_HISTORY.lock().unwrap();
+    if history.len() >= size {
+        history.pop_front();
+    }
+    history.push_back(record);
+}
+
+fn write_trace_marker(event: &str, msg: &str) {
+    record_trace_history(event, msg);
+    match TRACE_BACKEND.get_or_init(|| TraceBackend::Ftrace) {
+        TraceBackend::Ftrace => ftrace::write_trace_marker(event, msg),
+        #[cfg(feature = "trace_to_lttng")]
+        TraceBackend::Lttng => lttng::write_tracef(event, msg),
+        #[cfg(not(feature = "trace_to_lttng"))]
+        TraceBackend::Lttng => (),
+    }
+}
+
+/// Match the name of trace event with pattern, in which `*` matches any sequence
+/// of characters.
+fn pattern_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(name) => name,
+                None => return false,
+            };
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| pattern_match(rest, &name[i..]))
+        }
+    }
+}
+
+/// Get the state of trace events whose names match the pattern.
+pub fn get_trace_event_state(pattern: &str) -> Result<Vec<(&'static str, bool)>> {
+    let states: Vec<(&'static str, bool)> = TRACE_EVENT_LIST
+        .iter()
+        .filter(|event| pattern_match(pattern, event.name()))
+        .map(|event| (event.name(), event.enabled()))
+        .collect();
+    if states.is_empty() {
+        bail!("No trace event matches {}", pattern);
+    }
+    Ok(states)
+}
+
+/// Enable or disable trace events whose names match the pattern.
+pub fn set_trace_event_state(pattern: &str, enable: bool) -> Result<()> {
+    let mut matched = false;
+    for event in TRACE_EVENT_LIST
+        .iter()
+        .filter(|event| pattern_match(pattern, event.name()))
+    {
+        event.set_enabled(enable);
+        matched = true;
+    }
+    if !matched {
+        bail!("No trace event matches {}", pattern);
+    }
+    Ok(())
+}
+
+/// Enable trace events listed in file, which contains one pattern per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn enable_trace_events(file: &str) -> Result<()> {
+    let fd = File::open(file).with_context(|| format!("Failed to open {}.", file))?;
+    let reader = BufReader::new(fd);
+
+    for line in reader.lines() {
+        let line = line.with_context(|| format!("Read {} error.", file))?;
+        let pattern = line.trim();
+        if pattern.is_empty() || pattern.starts_with('#') {
+            continue;
+        }
+        set_trace_event_state(pattern, true)?;
+    }
+    Ok(())
+}
+
+pub fn is_trace_event_enabled(event: &str) -> bool {
+    TRACE_EVENT_LIST
+        .iter()
+        .any(|e| e.name() == event && e.enabled())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_match() {
+        assert!(pattern_match(
+            "virtio_send_interrupt",
+            "virtio_send_interrupt"
+        ));
+        assert!(!pattern_match("virtio_send", "virtio_send_interrupt"));
+        assert!(pattern_match("virtio_*", "virtio_send_interrupt"));
+        assert!(pattern_match("*interrupt", "virtio_send_interrupt"));
+        assert!(pattern_match("virtio*send*", "virtio_send_interrupt"));
+        assert!(pattern_match("*", "sysbus"));
+        assert!(!pattern_match("virtio_*", "sysbus"));
+        assert!(!pattern_match("*_config", "sysbus"));
+    }
+
+    #[test]
+    fn test_trace_history() {
+        set_trace_history_size(2);
+        for i in 0..3 {
+            record_trace_history("sysbus", &i.to_string());
+        }
+        let history = trace_history();
+        assert_eq!(history.len(), 2);
+        assert!(history[0].ends_with("sysbus: 1"));
+        assert!(history[1].ends_with("sysbus: 2"));
+
+        set_trace_history_size(0);
+        record_trace_history("sysbus", "3");
+        assert!(trace_history().is_empty());
+    }
+
+    #[test]
+    fn test_trace_event_state() {
+        set_trace_event_state("mmio_replaceable_config", true).unwrap();
+        assert!(is_trace_event_enabled("mmio_replaceable_config"));
+        assert_eq!(
+            get_trace_event_state("mmio_replaceable_*").unwrap(),
+            vec![("mmio_replaceable_config", true)]
+        );
+        set_trace_event_state("mmio_*", false).unwrap();
+        assert!(!is_trace_event_enabled("mmio_replaceable_config"));
+
+        assert!(set_trace_event_state("nonexistent_*", true).is_err());
+        assert!(get_trace_event_state("nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_trace_backend() {
+        assert_eq!(
+            "ftrace".parse::<TraceBackend>().unwrap(),
+            TraceBackend::Ftrace
+        );
+        assert_eq!(
+            "lttng".parse::<TraceBackend>().unwrap(),
+            TraceBackend::Lttng
+        );
+        assert!("perf".parse::<TraceBackend>().is_err());
+
+        set_trace_backend(TraceBackend::Ftrace).unwrap();
+        set_trace_backend(TraceBackend::Ftrace).unwrap();
+        assert!(set_trace_backend(TraceBackend::Lttng).is_err());
+    }
+}
@@ -24,6 +24,7 @@ use libc::{
 };
 use log::error;
 
+use crate::seclabel::label_path;
 use crate::UtilError;
 
 /// This function returns the caller's thread ID(TID).
@@ -31,6 +32,45 @@ pub fn gettid() -> u64 {
     unsafe { libc::syscall(libc::SYS_gettid) as u64 }
 }
 
+/// Pin the thread to the host CPUs.
+///
+/// # Arguments
+///
+/// * `tid` - Thread ID, `0` means the calling thread.
+/// * `cpus` - The host CPUs which the thread is allowed to run on.
+pub fn set_thread_affinity(tid: u64, cpus: &[u64]) -> Result<()> {
+    if cpus.is_empty() {
+        bail!("Host CPU list of affinity is empty");
+    }
+    // SAFETY: cpu_set_t is a bitmap which can be zeroed.
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        if *cpu >= libc::CPU_SETSIZE as u64 {
+            bail!(
+                "Host CPU {} is out of range, should be less than {}",
+                cpu,
+                libc::CPU_SETSIZE
+            );
+        }
+        // SAFETY: The cpu is checked to be in range of cpu_set_t.
+        unsafe { libc::CPU_SET(*cpu as usize, &mut cpu_set) };
+    }
+
+    // SAFETY: cpu_set is a valid cpu_set_t structure with the given size.
+    let ret = unsafe {
+        libc::sched_setaffinity(tid as libc::pid_t, size_of::<libc::cpu_set_t>(), &cpu_set)
+    };
+    if ret < 0 {
+        bail!(
+            "Failed to set affinity of thread {} to {:?}, error is {}",
+            tid,
+            cpus,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
 /// This function used to remove group and others permission using libc::chmod.
 pub fn limit_permission(path: &str) -> Result<()> {
     let file_path = path.as_bytes().to_vec();
@@ -49,6 +89,101 @@ pub fn host_page_size() -> u64 {
     unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
 }
 
+/// Look up the user id and group id of user in the password database.
+///
+/// # Arguments
+///
+/// * `name` - Name of the user.
+pub fn lookup_user(name: &str) -> Result<(u32, u32)> {
+    let cname = std::ffi::CString::new(name).with_context(|| "Invalid user name")?;
+    // SAFETY: passwd is a plain C struct which can be zeroed.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0_u8; 16384];
+    let mut result: *mut libc::passwd = null_mut();
+    // SAFETY: All the pointers are valid, and the buffer is large enough for most entries,
+    // ERANGE is reported otherwise.
+    let ret = unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr() as *mut libc::c_char,
+            buf.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        bail!(
+            "Failed to look up user {}: {}",
+            name,
+            std::io::Error::from_raw_os_error(ret)
+        );
+    }
+    if result.is_null() {
+        bail!("User {} not found", name);
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+/// Change the root directory of process, the working directory is changed to the new
+/// root as well.
+///
+/// # Arguments
+///
+/// * `dir` - Path of the new root directory.
+pub fn chroot(dir: &str) -> Result<()> {
+    std::env::set_current_dir(dir)
+        .with_context(|| format!("Failed to change directory to {}", dir))?;
+    let root = std::ffi::CString::new(".").unwrap();
+    // SAFETY: The path is a valid C string.
+    if unsafe { libc::chroot(root.as_ptr()) } != 0 {
+        bail!(
+            "Failed to chroot to {}: {}",
+            dir,
+            std::io::Error::last_os_error()
+        );
+    }
+    std::env::set_current_dir("/").with_context(|| "Failed to change directory to /")
+}
+
+/// Drop privileges of process by switching to an unprivileged user. The ids of all
+/// threads are changed.
+///
+/// # Arguments
+///
+/// * `uid` - User id to switch to.
+/// * `gid` - Group id to switch to, which is also the only supplementary group.
+pub fn drop_privileges(uid: u32, gid: u32) -> Result<()> {
+    // SAFETY: The group list has one element.
+    if unsafe { libc::setgroups(1, &gid) } != 0 {
+        bail!(
+            "Failed to set supplementary groups to {}: {}",
+            gid,
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: No pointer is passed, and glibc/musl apply it to all the threads.
+    if unsafe { libc::setgid(gid) } != 0 {
+        bail!(
+            "Failed to set gid to {}: {}",
+            gid,
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: Same as above.
+    if unsafe { libc::setuid(uid) } != 0 {
+        bail!(
+            "Failed to set uid to {}: {}",
+            uid,
+            std::io::Error::last_os_error()
+        );
+    }
+    // SAFETY: Same as above. Make sure that the privileges can't be regained.
+    if uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        bail!("Privileges are regained after switching to uid {}", uid);
+    }
+    Ok(())
+}
+
 /// Parse unix uri to unix path.
 ///
 /// # Notions
@@ -174,6 +309,7 @@ impl UnixSock {
         }
         let listener = UnixListener::bind(self.path.as_str())
             .with_context(|| format!("Failed to bind the socket {}", self.path))?;
+        label_path(&self.path)?;
         self.listener = Some(listener);
 
         Ok(())
@@ -420,7 +556,7 @@ mod tests {
 
     use libc::{c_void, iovec};
 
-    use super::{parse_unix_uri, UnixSock};
+    use super::{gettid, lookup_user, parse_unix_uri, set_thread_affinity, UnixSock};
 
     #[test]
     fn test_parse_uri() {
@@ -438,6 +574,47 @@ mod tests {
         assert!(parse_unix_uri(test_uri_03).is_err());
     }
 
+    #[test]
+    fn test_lookup_user() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert!(lookup_user("no_such_user_for_test").is_err());
+        assert!(lookup_user("ro\0ot").is_err());
+    }
+
+    #[test]
+    fn test_set_thread_affinity() {
+        assert!(set_thread_affinity(0, &[]).is_err());
+        assert!(set_thread_affinity(0, &[libc::CPU_SETSIZE as u64]).is_err());
+
+        // SAFETY: cpu_set_t is a bitmap which can be zeroed.
+        let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        // SAFETY: cpu_set is a valid cpu_set_t structure with the given size.
+        let ret = unsafe {
+            libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set)
+        };
+        assert_eq!(ret, 0);
+        // SAFETY: The cpu is in range of cpu_set_t.
+        let cpu = (0..libc::CPU_SETSIZE as usize)
+            .find(|cpu| unsafe { libc::CPU_ISSET(*cpu, &cpu_set) })
+            .unwrap();
+
+        std::thread::spawn(move || {
+            assert!(set_thread_affinity(gettid(), &[cpu as u64]).is_ok());
+            // SAFETY: Same as above.
+            let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+            // SAFETY: Same as above.
+            unsafe {
+                libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut cpu_set)
+            };
+            // SAFETY: Same as above.
+            assert_eq!(unsafe { libc::CPU_COUNT(&cpu_set) }, 1);
+            // SAFETY: Same as above.
+            assert!(unsafe { libc::CPU_ISSET(cpu, &cpu_set) });
+        })
+        .join()
+        .unwrap();
+    }
+
     #[test]
     fn test_create_unix_socket() {
         let path_name = String::from("test_socket1.sock");
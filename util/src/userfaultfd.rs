@@ -0,0 +1,354 @@
+// Copyright (c) 2022 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use anyhow::{bail, Context, Result};
+use vmm_sys_util::ioctl::ioctl_with_mut_ref;
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iowr_nr};
+
+use crate::byte_code::ByteCode;
+
+// See: https://elixir.bootlin.com/linux/v5.10/source/include/uapi/linux/userfaultfd.h
+const UFFDIO: u32 = 0xAA;
+const UFFD_API: u64 = 0xAA;
+const UFFD_EVENT_PAGEFAULT: u8 = 0x12;
+const UFFD_PAGEFAULT_FLAG_WP: u64 = 1 << 1;
+const UFFD_FEATURE_PAGEFAULT_FLAG_WP: u64 = 1 << 0;
+const UFFD_FEATURE_WP_UNPOPULATED: u64 = 1 << 13;
+const UFFDIO_REGISTER_MODE_WP: u64 = 1 << 1;
+const UFFDIO_WRITEPROTECT_MODE_WP: u64 = 1 << 0;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct UffdioApi {
+    api: u64,
+    features: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct UffdioRange {
+    start: u64,
+    len: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct UffdioRegister {
+    range: UffdioRange,
+    mode: u64,
+    ioctls: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct UffdioWriteprotect {
+    range: UffdioRange,
+    mode: u64,
+}
+
+/// Message read from userfaultfd, only the page fault event is parsed.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct UffdMsg {
+    event: u8,
+    reserved1: u8,
+    reserved2: u16,
+    reserved3: u32,
+    flags: u64,
+    address: u64,
+    ptid: u64,
+}
+
+impl ByteCode for UffdMsg {}
+
+ioctl_iowr_nr!(UFFDIO_API, UFFDIO, 0x3F, UffdioApi);
+ioctl_iowr_nr!(UFFDIO_REGISTER, UFFDIO, 0x00, UffdioRegister);
+ioctl_ior_nr!(UFFDIO_UNREGISTER, UFFDIO, 0x01, UffdioRange);
+ioctl_iowr_nr!(UFFDIO_WRITEPROTECT, UFFDIO, 0x06, UffdioWriteprotect);
+
+/// Userfaultfd used to track write access of memory.
+pub struct Userfaultfd {
+    file: File,
+    /// Whether the pages which are not populated yet can be write protected.
+    wp_unpopulated: bool,
+}
+
+impl Userfaultfd {
+    /// Create a non-blocking userfaultfd with write protection feature.
+    pub fn new() -> Result<Self> {
+        // The features can be enabled only once, so probe the supported features
+        // with another userfaultfd.
+        let supported = Self::api(&Self::create()?, 0)?;
+        let mut features = UFFD_FEATURE_PAGEFAULT_FLAG_WP;
+        if supported & UFFD_FEATURE_WP_UNPOPULATED != 0 {
+            features |= UFFD_FEATURE_WP_UNPOPULATED;
+        }
+        Self::with_features(features)
+    }
+
+    fn with_features(features: u64) -> Result<Self> {
+        let file = Self::create()?;
+        Self::api(&file, features)
+            .with_context(|| "Userfaultfd write protection is not supported")?;
+
+        Ok(Userfaultfd {
+            file,
+            wp_unpopulated: features & UFFD_FEATURE_WP_UNPOPULATED != 0,
+        })
+    }
+
+    fn create() -> Result<File> {
+        // SAFETY: the syscall has no memory argument, and the return value is checked.
+        let fd =
+            unsafe { libc::syscall(libc::SYS_userfaultfd, libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| "Failed to create userfaultfd");
+        }
+        // SAFETY: the fd is just created and owned by nobody else.
+        Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+    }
+
+    /// Enable `features` of userfaultfd, and return the supported features.
+    fn api(file: &File, features: u64) -> Result<u64> {
+        let mut api = UffdioApi {
+            api: UFFD_API,
+            features,
+            ioctls: 0,
+        };
+        // SAFETY: the file is a valid userfaultfd and the return value is checked.
+        let ret = unsafe { ioctl_with_mut_ref(file, UFFDIO_API(), &mut api) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| "Failed to enable userfaultfd features");
+        }
+
+        Ok(api.features)
+    }
+
+    /// Prepare memory range `[start, start + len)` for write protection. Write
+    /// protection only works on populated pages in old kernels, so the range is
+    /// populated readable, which maps the untouched anonymous pages to the zero
+    /// page without allocating memory.
+    pub fn prepare_wp(&self, start: u64, len: u64) -> Result<()> {
+        if self.wp_unpopulated {
+            return Ok(());
+        }
+
+        // SAFETY: populating doesn't change the content of memory, and the return
+        // value is checked.
+        let ret = unsafe {
+            libc::madvise(
+                start as *mut libc::c_void,
+                len as libc::size_t,
+                libc::MADV_POPULATE_READ,
+            )
+        };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!(
+                    "Failed to populate memory 0x{:x} len 0x{:x}, write protection of unpopulated memory is not supported",
+                    start, len
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Register memory range `[start, start + len)` to track write access.
+    pub fn register_wp(&self, start: u64, len: u64) -> Result<()> {
+        let mut register = UffdioRegister {
+            range: UffdioRange { start, len },
+            mode: UFFDIO_REGISTER_MODE_WP,
+            ioctls: 0,
+        };
+        // SAFETY: the file is a valid userfaultfd and the return value is checked.
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_REGISTER(), &mut register) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!("Failed to register memory 0x{:x} len 0x{:x}", start, len)
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Unregister memory range `[start, start + len)`.
+    pub fn unregister(&self, start: u64, len: u64) -> Result<()> {
+        let mut range = UffdioRange { start, len };
+        // SAFETY: the file is a valid userfaultfd and the return value is checked.
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_UNREGISTER(), &mut range) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!("Failed to unregister memory 0x{:x} len 0x{:x}", start, len)
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Write protect or unprotect memory range `[start, start + len)`. Unprotecting
+    /// also wakes up the threads blocked by write access to the range.
+    pub fn write_protect(&self, start: u64, len: u64, protect: bool) -> Result<()> {
+        let mut wp = UffdioWriteprotect {
+            range: UffdioRange { start, len },
+            mode: if protect {
+                UFFDIO_WRITEPROTECT_MODE_WP
+            } else {
+                0
+            },
+        };
+        // SAFETY: the file is a valid userfaultfd and the return value is checked.
+        let ret = unsafe { ioctl_with_mut_ref(&self.file, UFFDIO_WRITEPROTECT(), &mut wp) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error()).with_context(|| {
+                format!(
+                    "Failed to set write protection {} of memory 0x{:x} len 0x{:x}",
+                    protect, start, len
+                )
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Read a write protection fault, return the fault address, or `None` if
+    /// there is no pending fault.
+    pub fn read_wp_fault(&self) -> Result<Option<u64>> {
+        let mut msg = UffdMsg::default();
+        loop {
+            match (&self.file).read(msg.as_mut_bytes()) {
+                Ok(len) if len == size_of::<UffdMsg>() => {}
+                Ok(len) => bail!("Invalid userfaultfd message length {}", len),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).with_context(|| "Failed to read userfaultfd"),
+            }
+            if msg.event == UFFD_EVENT_PAGEFAULT && msg.flags & UFFD_PAGEFAULT_FLAG_WP != 0 {
+                return Ok(Some(msg.address));
+            }
+        }
+    }
+}
+
+impl AsRawFd for Userfaultfd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::unix::host_page_size;
+
+    /// Write protect the page at `start`, and check the write access is trapped.
+    fn check_write_protect(uffd: Arc<Userfaultfd>, start: u64, len: u64) {
+        uffd.prepare_wp(start, len).unwrap();
+        uffd.write_protect(start, len, true).unwrap();
+        assert_eq!(uffd.read_wp_fault().unwrap(), None);
+
+        let writer = thread::spawn(move || {
+            // SAFETY: the mapping is valid until the writer is joined.
+            unsafe { *(start as *mut u8) = 1 };
+        });
+        let fault = loop {
+            if let Some(fault) = uffd.read_wp_fault().unwrap() {
+                break fault;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(fault, start);
+        uffd.write_protect(start, len, false).unwrap();
+        writer.join().unwrap();
+
+        // SAFETY: the mapping is valid and written by writer.
+        assert_eq!(unsafe { *(start as *const u8) }, 1);
+        uffd.unregister(start, len).unwrap();
+    }
+
+    #[test]
+    fn test_userfaultfd_write_protect_untouched() {
+        let len = host_page_size();
+        for features in [
+            UFFD_FEATURE_PAGEFAULT_FLAG_WP,
+            UFFD_FEATURE_PAGEFAULT_FLAG_WP | UFFD_FEATURE_WP_UNPOPULATED,
+        ] {
+            // Userfaultfd and the feature may be unsupported or forbidden in test
+            // environment.
+            let uffd = match Userfaultfd::with_features(features) {
+                Ok(uffd) => Arc::new(uffd),
+                Err(_) => continue,
+            };
+            // SAFETY: the anonymous mapping is checked and unmapped at the end of test.
+            let addr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len as usize,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            assert_ne!(addr, libc::MAP_FAILED);
+            let start = addr as u64;
+            if uffd.register_wp(start, len).is_ok() && uffd.prepare_wp(start, len).is_ok() {
+                check_write_protect(uffd, start, len);
+            }
+            // SAFETY: the mapping is created above.
+            unsafe { libc::munmap(addr, len as usize) };
+        }
+    }
+
+    #[test]
+    fn test_userfaultfd_write_protect() {
+        // Userfaultfd may be unsupported or forbidden in test environment.
+        let uffd = match Userfaultfd::new() {
+            Ok(uffd) => Arc::new(uffd),
+            Err(_) => return,
+        };
+        let len = host_page_size();
+        // SAFETY: the anonymous mapping is checked and unmapped at the end of test.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len as usize,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_POPULATE,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(addr, libc::MAP_FAILED);
+        let start = addr as u64;
+        if uffd.register_wp(start, len).is_err() {
+            // SAFETY: the mapping is created above.
+            unsafe { libc::munmap(addr, len as usize) };
+            return;
+        }
+        check_write_protect(uffd, start, len);
+        // SAFETY: the mapping is created above.
+        unsafe { libc::munmap(addr, len as usize) };
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::sys::resource::setrlimit;
+use once_cell::sync::OnceCell;
+
+pub use nix::sys::resource::Resource;
+
+const CGROUP2_MOUNT: &str = "/sys/fs/cgroup";
+const CGROUP_THREADS: &str = "cgroup.threads";
+
+/// The cgroup which StratoVirt is started in, vCPU threads are kept in it after the
+/// threads of VMM are moved to their own cgroup.
+static VCPU_CGROUP: OnceCell<PathBuf> = OnceCell::new();
+
+/// Set both the soft and hard limit of resource of StratoVirt.
+///
+/// # Arguments
+///
+/// * `resource` - The resource to limit.
+/// * `limit` - The limit, `u64::MAX` means unlimited.
+pub fn set_rlimit(resource: Resource, limit: u64) -> Result<()> {
+    setrlimit(resource, limit, limit)
+        .with_context(|| format!("Failed to set {:?} to {}", resource, limit))
+}
+
+/// Set the max number of malloc arenas, which caps the memory held by glibc for threads.
+/// It should be called before threads are created.
+pub fn set_malloc_arena_max(arenas: u32) -> Result<()> {
+    #[cfg(target_env = "gnu")]
+    {
+        // SAFETY: mallopt only changes the parameter of malloc.
+        if unsafe { libc::mallopt(libc::M_ARENA_MAX, arenas as libc::c_int) } == 0 {
+            bail!("Failed to set max number of malloc arenas to {}", arenas);
+        }
+        Ok(())
+    }
+    #[cfg(not(target_env = "gnu"))]
+    bail!(
+        "Capping malloc arenas to {} is only supported with glibc",
+        arenas
+    )
+}
+
+/// Move all the threads of StratoVirt to the threaded cgroup v2, vCPU threads created
+/// later should call `join_vcpu_cgroup` to stay in the original cgroup.
+///
+/// # Arguments
+///
+/// * `cgroup` - Path of the cgroup, which is in the same threaded subtree with the cgroup
+///   of StratoVirt.
+pub fn set_vmm_cgroup(cgroup: &str) -> Result<()> {
+    let threads_file = Path::new(cgroup).join(CGROUP_THREADS);
+    if !threads_file.exists() {
+        bail!("{} is not a cgroup v2 directory", cgroup);
+    }
+    let content = std::fs::read_to_string("/proc/self/cgroup")
+        .with_context(|| "Failed to read cgroup of StratoVirt")?;
+    let current = parse_cgroup2_path(&content)
+        .with_context(|| "StratoVirt is not in a cgroup v2 hierarchy")?;
+    let current = Path::new(CGROUP2_MOUNT).join(current.trim_start_matches('/'));
+    if VCPU_CGROUP.set(current).is_err() {
+        bail!("Cgroup of VMM threads has already been set");
+    }
+
+    let tasks = std::fs::read_dir("/proc/self/task")
+        .with_context(|| "Failed to list threads of StratoVirt")?;
+    for task in tasks {
+        let tid = task?.file_name();
+        let tid = tid.to_string_lossy();
+        // The thread may have exited.
+        if let Err(e) = std::fs::write(&threads_file, tid.as_bytes()) {
+            if Path::new("/proc/self/task").join(tid.as_ref()).exists() {
+                return Err(e).with_context(|| {
+                    format!("Failed to move thread {} to cgroup {}", tid, cgroup)
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move the calling vCPU thread back to the cgroup which StratoVirt is started in, so that
+/// guest time isn't charged to the cgroup of VMM threads. It does nothing if the cgroup of
+/// VMM threads isn't set.
+pub fn join_vcpu_cgroup() -> Result<()> {
+    let cgroup = match VCPU_CGROUP.get() {
+        Some(cgroup) => cgroup,
+        None => return Ok(()),
+    };
+    // SAFETY: gettid has no side effect.
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) };
+    std::fs::write(cgroup.join(CGROUP_THREADS), tid.to_string()).with_context(|| {
+        format!(
+            "Failed to move vCPU thread {} to cgroup {}",
+            tid,
+            cgroup.display()
+        )
+    })
+}
+
+/// The cgroup v2 entry of `/proc/<pid>/cgroup` is in format of `0::<path>`.
+fn parse_cgroup2_path(content: &str) -> Option<&str> {
+    content.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup2_path() {
+        assert_eq!(
+            parse_cgroup2_path("0::/machine.slice/vm1\n"),
+            Some("/machine.slice/vm1")
+        );
+        assert_eq!(
+            parse_cgroup2_path("12:cpu,cpuacct:/vm1\n0::/vm1/vcpus\n"),
+            Some("/vm1/vcpus")
+        );
+        assert_eq!(parse_cgroup2_path("12:cpu,cpuacct:/vm1\n"), None);
+    }
+}
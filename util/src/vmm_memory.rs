@@ -0,0 +1,309 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Memory usage of StratoVirt itself, gathered from procfs.
+//!
+//! The resident memory of each mapping in `/proc/self/smaps` is classified as:
+//! 1. guest RAM, the mappings which overlap the host ranges of guest memory.
+//! 2. thread stacks, the mappings which contain the stack pointer of a thread, and the
+//!    anonymous mappings right above a guard page, which are the stacks of threads
+//!    running in user space.
+//! 3. heap, `[heap]` and the other anonymous mappings, such as malloc arenas.
+//! 4. other, the executable, libraries and other file-backed mappings.
+
+use std::fs;
+
+use anyhow::{Context, Result};
+
+use crate::unix::{gettid, host_page_size};
+
+const SMAPS_PATH: &str = "/proc/self/smaps";
+const TASK_DIR: &str = "/proc/self/task";
+
+/// Resident memory of the stack of a thread in bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ThreadStackUsage {
+    pub tid: u64,
+    pub name: String,
+    pub rss: u64,
+}
+
+/// Breakdown of the resident memory of VMM in bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VmmMemoryUsage {
+    pub total: u64,
+    pub guest_ram: u64,
+    pub heap: u64,
+    pub stacks: u64,
+    pub other: u64,
+    /// Stacks of the threads whose stack pointers are known, sorted by tid.
+    pub thread_stacks: Vec<ThreadStackUsage>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    perms: String,
+    path: String,
+    rss: u64,
+}
+
+impl Mapping {
+    fn is_anonymous(&self) -> bool {
+        self.path.is_empty() || self.path.starts_with("[anon")
+    }
+}
+
+/// Parse the content of smaps. Only the range, permissions, path and Rss of each
+/// mapping are kept.
+fn parse_smaps(content: &str) -> Vec<Mapping> {
+    let mut mappings: Vec<Mapping> = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let first = match fields.next() {
+            Some(first) => first,
+            None => continue,
+        };
+        if first == "Rss:" {
+            if let (Some(mapping), Some(Ok(kb))) =
+                (mappings.last_mut(), fields.next().map(str::parse::<u64>))
+            {
+                mapping.rss = kb * 1024;
+            }
+            continue;
+        }
+        let range = match first.split_once('-') {
+            Some((start, end)) if !first.ends_with(':') => {
+                (u64::from_str_radix(start, 16), u64::from_str_radix(end, 16))
+            }
+            _ => continue,
+        };
+        if let (Ok(start), Ok(end)) = range {
+            let perms = fields.next().unwrap_or_default().to_string();
+            // Skip offset, device and inode, the rest is the path which may contain spaces.
+            let path = fields.skip(3).collect::<Vec<&str>>().join(" ");
+            mappings.push(Mapping {
+                start,
+                end,
+                perms,
+                path,
+                rss: 0,
+            });
+        }
+    }
+    mappings
+}
+
+/// Get the stack pointer of a thread from its current syscall, which is in format of
+/// `<nr> <args>... <sp> <pc>`, or `running` if the thread is not blocked.
+fn parse_stack_pointer(syscall: &str) -> Option<u64> {
+    let fields: Vec<&str> = syscall.split_whitespace().collect();
+    if fields.len() < 3 {
+        return None;
+    }
+    let sp = fields[fields.len() - 2];
+    u64::from_str_radix(sp.trim_start_matches("0x"), 16).ok()
+}
+
+/// Get (tid, name, stack pointer) of the threads of VMM.
+fn thread_stack_pointers() -> Result<Vec<(u64, String, u64)>> {
+    let current = gettid();
+    let mut threads = Vec::new();
+    for entry in fs::read_dir(TASK_DIR).with_context(|| format!("Failed to read {}", TASK_DIR))? {
+        let entry = entry?;
+        let tid = match entry.file_name().to_str().map(str::parse::<u64>) {
+            Some(Ok(tid)) => tid,
+            _ => continue,
+        };
+        let dir = entry.path();
+        let name = fs::read_to_string(dir.join("comm"))
+            .map(|name| name.trim_end().to_string())
+            .unwrap_or_default();
+        let sp = if tid == current {
+            let marker = 0_u8;
+            Some(&marker as *const u8 as u64)
+        } else {
+            fs::read_to_string(dir.join("syscall"))
+                .ok()
+                .and_then(|syscall| parse_stack_pointer(&syscall))
+        };
+        if let Some(sp) = sp {
+            threads.push((tid, name, sp));
+        }
+    }
+    threads.sort_by_key(|(tid, _, _)| *tid);
+    Ok(threads)
+}
+
+fn classify(
+    mappings: &[Mapping],
+    guest_ranges: &[(u64, u64)],
+    threads: &[(u64, String, u64)],
+    page_size: u64,
+) -> VmmMemoryUsage {
+    let mut usage = VmmMemoryUsage {
+        thread_stacks: threads
+            .iter()
+            .map(|(tid, name, _)| ThreadStackUsage {
+                tid: *tid,
+                name: name.clone(),
+                rss: 0,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    for (i, mapping) in mappings.iter().enumerate() {
+        usage.total += mapping.rss;
+        if guest_ranges
+            .iter()
+            .any(|(addr, size)| mapping.start < addr + size && *addr < mapping.end)
+        {
+            usage.guest_ram += mapping.rss;
+            continue;
+        }
+
+        let mut is_stack = mapping.path == "[stack]";
+        for (thread, (_, _, sp)) in threads.iter().enumerate() {
+            if mapping.start <= *sp && *sp < mapping.end {
+                usage.thread_stacks[thread].rss += mapping.rss;
+                is_stack = true;
+            }
+        }
+        // Stacks of threads are guarded by an inaccessible page below them.
+        let guarded = i > 0 && {
+            let prev = &mappings[i - 1];
+            prev.end == mapping.start
+                && prev.end - prev.start == page_size
+                && prev.perms.starts_with("---")
+                && prev.is_anonymous()
+        };
+        if is_stack || (guarded && mapping.is_anonymous() && mapping.perms.starts_with("rw")) {
+            usage.stacks += mapping.rss;
+        } else if mapping.path == "[heap]" || mapping.is_anonymous() {
+            usage.heap += mapping.rss;
+        } else {
+            usage.other += mapping.rss;
+        }
+    }
+    usage
+}
+
+/// Get the breakdown of the resident memory of VMM.
+///
+/// # Arguments
+///
+/// * `guest_ranges` - Host ranges of guest memory in format of (HVA, size).
+pub fn vmm_memory_usage(guest_ranges: &[(u64, u64)]) -> Result<VmmMemoryUsage> {
+    let smaps =
+        fs::read_to_string(SMAPS_PATH).with_context(|| format!("Failed to read {}", SMAPS_PATH))?;
+    let mappings = parse_smaps(&smaps);
+    let threads = thread_stack_pointers()?;
+    Ok(classify(
+        &mappings,
+        guest_ranges,
+        &threads,
+        host_page_size(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMAPS: &str = "\
+55d4a6a00000-55d4a6c00000 r-xp 00000000 fd:01 1234                       /usr/bin/stratovirt
+Size:               2048 kB
+Rss:                1024 kB
+55d4a7000000-55d4a7100000 rw-p 00000000 00:00 0                          [heap]
+Rss:                 512 kB
+7f0000000000-7f0040000000 rw-s 00000000 00:01 5678                       /memfd:stratovirt_ram (deleted)
+Rss:              262144 kB
+7f0050000000-7f0050001000 ---p 00000000 00:00 0
+Rss:                   0 kB
+7f0050001000-7f0050801000 rw-p 00000000 00:00 0
+Rss:                  64 kB
+7f0060000000-7f0060800000 rw-p 00000000 00:00 0
+Rss:                 128 kB
+7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0                          [stack]
+Rss:                  32 kB
+VmFlags: rd wr mr mw me gd ac
+";
+
+    #[test]
+    fn test_parse_smaps() {
+        let mappings = parse_smaps(SMAPS);
+        assert_eq!(mappings.len(), 7);
+        assert_eq!(
+            mappings[2],
+            Mapping {
+                start: 0x7f00_0000_0000,
+                end: 0x7f00_4000_0000,
+                perms: "rw-s".to_string(),
+                path: "/memfd:stratovirt_ram (deleted)".to_string(),
+                rss: 256 << 20,
+            }
+        );
+        assert!(mappings[3].is_anonymous());
+        assert_eq!(mappings[6].path, "[stack]");
+    }
+
+    #[test]
+    fn test_parse_stack_pointer() {
+        assert_eq!(
+            parse_stack_pointer("7 0x7f 0x1 0xffffffff 0x0 0x0 0x0 0x7f0050800e00 0x7f12\n"),
+            Some(0x7f00_5080_0e00)
+        );
+        assert_eq!(
+            parse_stack_pointer("-1 0x7ffc00020000 0x55d4a6a01000"),
+            Some(0x7ffc_0002_0000)
+        );
+        assert_eq!(parse_stack_pointer("running\n"), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        let mappings = parse_smaps(SMAPS);
+        let guest_ranges = [(0x7f00_0000_0000, 0x4000_0000)];
+        let threads = vec![(100, "stratovirt".to_string(), 0x7ffc_0002_0000)];
+        let usage = classify(&mappings, &guest_ranges, &threads, 4096);
+        assert_eq!(usage.total, (1024 + 512 + 262144 + 64 + 128 + 32) * 1024);
+        assert_eq!(usage.guest_ram, 256 << 20);
+        assert_eq!(usage.stacks, (64 + 32) * 1024);
+        assert_eq!(usage.heap, (512 + 128) * 1024);
+        assert_eq!(usage.other, 1024 * 1024);
+        assert_eq!(
+            usage.thread_stacks,
+            vec![ThreadStackUsage {
+                tid: 100,
+                name: "stratovirt".to_string(),
+                rss: 32 * 1024,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vmm_memory_usage() {
+        let usage = vmm_memory_usage(&[]).unwrap();
+        assert!(usage.total > 0);
+        assert_eq!(usage.guest_ram, 0);
+        assert_eq!(
+            usage.total,
+            usage.guest_ram + usage.heap + usage.stacks + usage.other
+        );
+        assert!(usage
+            .thread_stacks
+            .iter()
+            .any(|stack| stack.tid == gettid()));
+    }
+}
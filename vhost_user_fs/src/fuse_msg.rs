@@ -411,7 +411,7 @@ impl FuseBuffer {
             };
 
             let mut iov = Vec::new();
-            let hvas = sys_mem.get_address_map(buf.addr, len as u64)?;
+            let hvas = sys_mem.get_address_map(buf.addr, len as u64, is_read)?;
             for addr in hvas.into_iter() {
                 iov.push(libc::iovec {
                     iov_base: addr.iov_base as *mut libc::c_void,
@@ -0,0 +1,551 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Guest-less benchmark of virtqueue and the backends of virtio devices.
+//!
+//! A synthetic driver builds descriptor chains in memory which doesn't belong to any
+//! guest, and makes them available in batches as a guest driver does. The backend pops
+//! the chains from a split vring, processes them with the benchmarked path and pushes
+//! them to the used ring, then the driver reclaims them for the next batch.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::ErrorKind;
+use std::mem::size_of;
+use std::num::Wrapping;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    Element, QueueConfig, SplitVring, SplitVringDesc, VringOps, VIRTQ_DESC_F_NEXT,
+    VIRTQ_DESC_F_WRITE,
+};
+use address_space::{AddressSpace, GuestAddress, HostMemMapping, Region};
+use machine_manager::config::{BenchConfig, BenchMode};
+use util::aio::{Aio, AioCb, AioEngine, OpCode, WriteZeroesState};
+use util::file::get_file_alignment;
+use util::loop_context::read_fd;
+use util::num_ops::round_up;
+use util::tap::Tap;
+
+/// Alignment of the vring and the buffers, which also meets the alignment of direct IO.
+const BENCH_ALIGN: u64 = 4096;
+/// Max size of the memory of benchmark.
+const MAX_BENCH_MEM_SIZE: u64 = 4 << 30;
+/// Size of the virtio net header with mergeable rx buffers.
+const NET_HDR_SIZE: u32 = 12;
+/// Size of the virtio net header and ethernet header.
+const MIN_PACKET_SIZE: u64 = NET_HDR_SIZE as u64 + 14;
+
+/// Memory layout of the synthetic vring and buffers.
+struct BenchLayout {
+    desc_table: u64,
+    avail_ring: u64,
+    used_ring: u64,
+    buffers: u64,
+    /// Distance between the buffers of two descriptors.
+    buf_stride: u64,
+    size: u64,
+}
+
+impl BenchLayout {
+    fn new(config: &BenchConfig) -> Option<Self> {
+        let queue_size = config.queue_size as u64;
+        let desc_table = 0;
+        let avail_ring = desc_table + queue_size * size_of::<SplitVringDesc>() as u64;
+        // flags, idx, ring and used_event of the available ring.
+        let used_ring = round_up(avail_ring + 6 + 2 * queue_size, BENCH_ALIGN)?;
+        // flags, idx, ring and avail_event of the used ring.
+        let buffers = round_up(used_ring + 6 + 8 * queue_size, BENCH_ALIGN)?;
+        let buf_stride = round_up(config.buf_size as u64, BENCH_ALIGN)?;
+        let size = buffers.checked_add(buf_stride.checked_mul(queue_size)?)?;
+        Some(BenchLayout {
+            desc_table,
+            avail_ring,
+            used_ring,
+            buffers,
+            buf_stride,
+            size,
+        })
+    }
+}
+
+/// The synthetic driver which plays the role of guest.
+struct BenchDriver {
+    mem: Arc<AddressSpace>,
+    layout: BenchLayout,
+    queue_size: u16,
+    avail_idx: Wrapping<u16>,
+    last_used: Wrapping<u16>,
+    /// Heads of the descriptor chains which are not available to device.
+    free_heads: Vec<u16>,
+}
+
+impl BenchDriver {
+    fn new(mem: Arc<AddressSpace>, layout: BenchLayout, config: &BenchConfig) -> Result<Self> {
+        let flags = if config.mode == BenchMode::Aio && !config.write {
+            VIRTQ_DESC_F_WRITE
+        } else {
+            0
+        };
+        let pattern = vec![0x5a_u8; config.buf_size as usize];
+        let mut free_heads = Vec::new();
+        for chain in (0..config.max_inflight()).rev() {
+            let head = chain * config.chain_len;
+            for index in head..head + config.chain_len {
+                let next = index + 1 < head + config.chain_len;
+                let addr = GuestAddress(layout.buffers + index as u64 * layout.buf_stride);
+                let desc = SplitVringDesc {
+                    addr,
+                    len: config.buf_size,
+                    flags: if next {
+                        flags | VIRTQ_DESC_F_NEXT
+                    } else {
+                        flags
+                    },
+                    next: if next { index + 1 } else { 0 },
+                };
+                mem.write_object(
+                    &desc,
+                    GuestAddress(
+                        layout.desc_table + index as u64 * size_of::<SplitVringDesc>() as u64,
+                    ),
+                )?;
+                // Fill the buffers so that the memory is populated before benchmark.
+                mem.write(&mut pattern.as_slice(), addr, pattern.len() as u64)?;
+            }
+            if config.mode == BenchMode::Tap {
+                // Packets start with an empty virtio net header.
+                let addr = GuestAddress(layout.buffers + head as u64 * layout.buf_stride);
+                let hdr = [0_u8; NET_HDR_SIZE as usize];
+                mem.write(&mut hdr.as_slice(), addr, hdr.len() as u64)?;
+            }
+            free_heads.push(head);
+        }
+
+        Ok(BenchDriver {
+            mem,
+            layout,
+            queue_size: config.queue_size,
+            avail_idx: Wrapping(0),
+            last_used: Wrapping(0),
+            free_heads,
+        })
+    }
+
+    /// Make at most `count` descriptor chains available, and return the number of them.
+    fn make_avail(&mut self, count: u64) -> Result<u64> {
+        let mut made = 0;
+        while made < count {
+            let head = match self.free_heads.pop() {
+                Some(head) => head,
+                None => break,
+            };
+            let pos = self.avail_idx.0 % self.queue_size;
+            self.mem.write_object(
+                &head,
+                GuestAddress(self.layout.avail_ring + 4 + 2 * pos as u64),
+            )?;
+            self.avail_idx += Wrapping(1);
+            made += 1;
+        }
+        if made != 0 {
+            self.mem
+                .write_object(&self.avail_idx.0, GuestAddress(self.layout.avail_ring + 2))?;
+        }
+        Ok(made)
+    }
+
+    /// Reclaim the descriptor chains in the used ring, and return the number of them.
+    fn reclaim(&mut self) -> Result<u64> {
+        let used_idx = self
+            .mem
+            .read_object::<u16>(GuestAddress(self.layout.used_ring + 2))?;
+        let mut reclaimed = 0;
+        while self.last_used.0 != used_idx {
+            let pos = self.last_used.0 % self.queue_size;
+            let head = self
+                .mem
+                .read_object::<u32>(GuestAddress(self.layout.used_ring + 4 + 8 * pos as u64))?;
+            self.free_heads.push(head as u16);
+            self.last_used += Wrapping(1);
+            reclaimed += 1;
+        }
+        Ok(reclaimed)
+    }
+}
+
+/// Completion context of an aio request of benchmark.
+#[derive(Clone)]
+struct BenchAioCb {
+    head: u16,
+    completed: Arc<Mutex<Vec<(u16, i64)>>>,
+}
+
+fn bench_aio_complete(aiocb: &AioCb<BenchAioCb>, ret: i64) -> Result<()> {
+    let cb = &aiocb.iocompletecb;
+    cb.completed.lock().unwrap().push((cb.head, ret));
+    Ok(())
+}
+
+/// The benchmarked path which processes the descriptor chains.
+enum BenchBackend {
+    Queue,
+    Aio {
+        aio: Box<Aio<BenchAioCb>>,
+        file: File,
+        file_size: u64,
+        offset: u64,
+        write: bool,
+        direct: bool,
+        req_align: u32,
+        buf_align: u32,
+        completed: Arc<Mutex<Vec<(u16, i64)>>>,
+    },
+    Tap(Tap),
+}
+
+impl BenchBackend {
+    fn new(config: &BenchConfig) -> Result<Self> {
+        let chain_size = config.buf_size as u64 * config.chain_len as u64;
+        match config.mode {
+            BenchMode::Queue => Ok(BenchBackend::Queue),
+            BenchMode::Aio => {
+                let path = config.file.as_ref().unwrap();
+                let mut options = OpenOptions::new();
+                options.read(true).write(config.write);
+                if config.direct {
+                    options.custom_flags(libc::O_DIRECT);
+                }
+                let file = options
+                    .open(path)
+                    .with_context(|| format!("Failed to open bench file {}", path))?;
+                let file_size = file.metadata()?.len();
+                if file_size < chain_size {
+                    bail!(
+                        "Size of bench file {} is smaller than the chain {}",
+                        file_size,
+                        chain_size
+                    );
+                }
+                let (req_align, buf_align) = get_file_alignment(&file, config.direct);
+                let aio = Aio::new(Arc::new(bench_aio_complete), config.aio)?;
+                Ok(BenchBackend::Aio {
+                    aio: Box::new(aio),
+                    file,
+                    file_size,
+                    offset: 0,
+                    write: config.write,
+                    direct: config.direct,
+                    req_align,
+                    buf_align,
+                    completed: Arc::new(Mutex::new(Vec::new())),
+                })
+            }
+            BenchMode::Tap => {
+                if chain_size < MIN_PACKET_SIZE {
+                    bail!(
+                        "Size of the chain {} is smaller than the packet header {}",
+                        chain_size,
+                        MIN_PACKET_SIZE
+                    );
+                }
+                let tap = Tap::new(config.ifname.as_deref(), None, 1)?;
+                tap.set_hdr_size(NET_HDR_SIZE)?;
+                Ok(BenchBackend::Tap(tap))
+            }
+        }
+    }
+
+    /// Process a descriptor chain. Returns the length written to the chain if it's
+    /// completed synchronously.
+    fn process(&mut self, mem: &AddressSpace, elem: &Element) -> Result<Option<u32>> {
+        let mut iovecs = Vec::new();
+        for iov in elem.out_iovec.iter() {
+            iovecs.append(&mut mem.get_address_map(iov.addr, iov.len as u64, false)?);
+        }
+        for iov in elem.in_iovec.iter() {
+            iovecs.append(&mut mem.get_address_map(iov.addr, iov.len as u64, true)?);
+        }
+        match self {
+            BenchBackend::Queue => Ok(Some(0)),
+            BenchBackend::Aio {
+                aio,
+                file,
+                file_size,
+                offset,
+                write,
+                direct,
+                req_align,
+                buf_align,
+                completed,
+            } => {
+                let nbytes = iovecs.iter().map(|iov| iov.iov_len).sum::<u64>();
+                if *offset + nbytes > *file_size {
+                    *offset = 0;
+                }
+                let aiocb = AioCb {
+                    direct: *direct,
+                    req_align: *req_align,
+                    buf_align: *buf_align,
+                    discard: false,
+                    write_zeroes: WriteZeroesState::Off,
+                    file_fd: file.as_raw_fd(),
+                    opcode: if *write {
+                        OpCode::Pwritev
+                    } else {
+                        OpCode::Preadv
+                    },
+                    iovec: iovecs,
+                    offset: *offset as usize,
+                    nbytes,
+                    user_data: 0,
+                    iocompletecb: BenchAioCb {
+                        head: elem.index,
+                        completed: completed.clone(),
+                    },
+                    combine_req: None,
+                };
+                *offset += nbytes;
+                aio.submit_request(aiocb)?;
+                Ok(None)
+            }
+            BenchBackend::Tap(tap) => {
+                let iovecs: Vec<libc::iovec> = iovecs
+                    .iter()
+                    .map(|iov| libc::iovec {
+                        iov_base: iov.iov_base as *mut libc::c_void,
+                        iov_len: iov.iov_len as libc::size_t,
+                    })
+                    .collect();
+                loop {
+                    // SAFETY: the iovecs are translated from the memory of benchmark.
+                    let ret = unsafe {
+                        libc::writev(
+                            tap.as_raw_fd(),
+                            iovecs.as_ptr(),
+                            iovecs.len() as libc::c_int,
+                        )
+                    };
+                    if ret >= 0 {
+                        break;
+                    }
+                    let e = std::io::Error::last_os_error();
+                    if e.raw_os_error() == Some(libc::EIO) {
+                        bail!("Failed to send packet to tap, the tap may be down: {:?}", e);
+                    }
+                    if !matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) {
+                        bail!("Failed to send packet to tap: {:?}", e);
+                    }
+                }
+                Ok(Some(0))
+            }
+        }
+    }
+
+    /// Collect the descriptor chains completed asynchronously, in format of (head, length).
+    /// Wait for one completion at least if `wait` is set and there are requests in flight.
+    fn poll(&mut self, wait: bool) -> Result<Vec<(u16, u32)>> {
+        let (aio, completed) = match self {
+            BenchBackend::Aio { aio, completed, .. } => (aio, completed),
+            _ => return Ok(Vec::new()),
+        };
+        aio.flush_request()?;
+        while wait
+            && aio.get_engine() != AioEngine::Off
+            && completed.lock().unwrap().is_empty()
+            && aio.incomplete_cnt.load(std::sync::atomic::Ordering::SeqCst) != 0
+        {
+            let mut pollfd = libc::pollfd {
+                fd: aio.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pollfd is valid and only one fd is polled.
+            if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != ErrorKind::Interrupted {
+                    bail!("Failed to poll aio: {:?}", e);
+                }
+                continue;
+            }
+            read_fd(aio.fd.as_raw_fd());
+            aio.handle_complete()?;
+        }
+
+        let mut done = Vec::new();
+        for (head, ret) in completed.lock().unwrap().drain(..) {
+            if ret < 0 {
+                bail!("Aio request of bench failed: {}", ret);
+            }
+            done.push((head, ret as u32));
+        }
+        Ok(done)
+    }
+}
+
+/// Result of the benchmark.
+pub struct BenchResult {
+    pub requests: u64,
+    /// Bytes of the buffers processed.
+    pub bytes: u64,
+    /// Number of batches made available by driver.
+    pub kicks: u64,
+    /// Number of interrupts required by vring.
+    pub interrupts: u64,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(f, "requests: {}", self.requests)?;
+        writeln!(f, "elapsed: {:.3} s", secs)?;
+        writeln!(f, "requests/s: {:.0}", self.requests as f64 / secs)?;
+        writeln!(
+            f,
+            "bandwidth: {:.2} MiB/s",
+            self.bytes as f64 / secs / (1 << 20) as f64
+        )?;
+        writeln!(
+            f,
+            "ns/request: {:.0}",
+            self.elapsed.as_nanos() as f64 / self.requests as f64
+        )?;
+        writeln!(f, "kicks: {}", self.kicks)?;
+        write!(f, "interrupts: {}", self.interrupts)
+    }
+}
+
+fn bench_address_space(size: u64) -> Result<Arc<AddressSpace>> {
+    let root = Region::init_container_region(size, "bench");
+    let mem = AddressSpace::new(root, "bench")?;
+    let host_mmap = Arc::new(HostMemMapping::new(
+        GuestAddress(0),
+        None,
+        size,
+        None,
+        false,
+        false,
+        false,
+    )?);
+    mem.root()
+        .add_subregion(Region::init_ram_region(host_mmap, "bench"), 0)?;
+    Ok(mem)
+}
+
+/// Run the guest-less benchmark.
+pub fn run_bench(config: &BenchConfig) -> Result<BenchResult> {
+    let layout = match BenchLayout::new(config) {
+        Some(layout) if layout.size <= MAX_BENCH_MEM_SIZE => layout,
+        _ => bail!(
+            "Memory of bench is larger than {}, decrease queue-size or buf-size",
+            MAX_BENCH_MEM_SIZE
+        ),
+    };
+    let mem = bench_address_space(layout.size)?;
+
+    let mut queue_config = QueueConfig::new(config.queue_size);
+    queue_config.desc_table = GuestAddress(layout.desc_table);
+    queue_config.avail_ring = GuestAddress(layout.avail_ring);
+    queue_config.used_ring = GuestAddress(layout.used_ring);
+    queue_config.addr_cache.desc_table_host =
+        mem.get_host_address(queue_config.desc_table).unwrap();
+    queue_config.addr_cache.avail_ring_host =
+        mem.get_host_address(queue_config.avail_ring).unwrap();
+    queue_config.addr_cache.used_ring_host = mem.get_host_address(queue_config.used_ring).unwrap();
+    queue_config.addr_cache.generation = mem.generation();
+    queue_config.ready = true;
+    let mut vring = SplitVring::new(queue_config);
+
+    let mut backend = BenchBackend::new(config)?;
+    let mut driver = BenchDriver::new(mem.clone(), layout, config)?;
+    let chain_size = config.buf_size as u64 * config.chain_len as u64;
+    let mut result = BenchResult {
+        requests: config.requests,
+        bytes: chain_size * config.requests,
+        kicks: 0,
+        interrupts: 0,
+        elapsed: Duration::ZERO,
+    };
+
+    let start = Instant::now();
+    let mut submitted = 0;
+    let mut completed = 0;
+    while completed < config.requests {
+        let count = std::cmp::min(config.batch as u64, config.requests - submitted);
+        let made = driver.make_avail(count)?;
+        if made != 0 {
+            submitted += made;
+            result.kicks += 1;
+        }
+
+        loop {
+            let elem = vring.pop_avail(&mem, 0)?;
+            if elem.desc_num == 0 {
+                break;
+            }
+            if let Some(len) = backend.process(&mem, &elem)? {
+                vring.add_used(&mem, elem.index, len)?;
+            }
+        }
+        // Wait for the backend if the driver has no chain to make available.
+        let wait = made < config.batch as u64;
+        for (head, len) in backend.poll(wait)? {
+            vring.add_used(&mem, head, len)?;
+        }
+        if vring.should_notify(&mem, 0) {
+            result.interrupts += 1;
+        }
+        completed += driver.reclaim()?;
+    }
+    result.elapsed = start.elapsed();
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_manager::config::parse_bench;
+
+    #[test]
+    fn test_bench_queue() {
+        let config = parse_bench("queue,queue-size=16,chain-len=3,requests=1000,batch=2").unwrap();
+        let result = run_bench(&config).unwrap();
+        assert_eq!(result.requests, 1000);
+        assert_eq!(result.bytes, 1000 * 3 * 4096);
+        assert_eq!(result.kicks, 500);
+    }
+
+    #[test]
+    fn test_bench_aio() {
+        let path = "/tmp/stratovirt_bench_aio";
+        let file = File::create(path).unwrap();
+        file.set_len(1 << 16).unwrap();
+        for rw in ["read", "write"] {
+            let config = parse_bench(&format!(
+                "aio,file={},direct=off,rw={},queue-size=8,chain-len=2,requests=100",
+                path, rw
+            ))
+            .unwrap();
+            let result = run_bench(&config).unwrap();
+            assert_eq!(result.requests, 100);
+        }
+        let config = parse_bench(&format!("aio,file={},buf-size=65536,chain-len=2", path)).unwrap();
+        assert!(run_bench(&config).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
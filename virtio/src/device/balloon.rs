@@ -26,8 +26,8 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd, timerfd::TimerFd};
 
 use crate::{
     error::*, read_config_default, report_virtio_error, virtio_has_feature, Element, Queue,
-    VirtioBase, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BALLOON,
+    VirtioBase, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_VERSION_1,
+    VIRTIO_TYPE_BALLOON,
 };
 use address_space::{
     AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd, RegionType,
@@ -48,6 +48,7 @@ use util::{
     num_ops::round_down,
     offset_of,
     seccomp::BpfRule,
+    trace,
     unix::host_page_size,
 };
 
@@ -608,10 +609,10 @@ impl BalloonIoHandler {
     /// balloon.
     fn process_balloon_queue(&mut self, req_type: bool) -> Result<()> {
         let queue = if req_type {
-            self.trace_request("Balloon".to_string(), "to inflate".to_string());
+            trace::virtio_receive_request("Balloon", "to inflate");
             &self.inf_queue
         } else {
-            self.trace_request("Balloon".to_string(), "to deflate".to_string());
+            trace::virtio_receive_request("Balloon", "to deflate");
             &self.def_queue
         };
         let mut locked_queue = queue.lock().unwrap();
@@ -1176,8 +1177,6 @@ pub fn balloon_allow_list(syscall_allow_list: &mut Vec<BpfRule>) {
     ])
 }
 
-impl VirtioTrace for BalloonIoHandler {}
-
 #[cfg(test)]
 mod tests {
     pub use super::*;
@@ -15,10 +15,10 @@ use std::collections::HashMap;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context, Result};
 use byteorder::{ByteOrder, LittleEndian};
@@ -27,14 +27,14 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use crate::{
     check_config_space_rw, gpa_hva_iovec_map, iov_discard_back, iov_discard_front, iov_to_buf,
-    read_config_default, report_virtio_error, virtio_has_feature, Element, Queue, VirtioBase,
-    VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ, VIRTIO_BLK_F_RO,
-    VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR,
-    VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_T_DISCARD, VIRTIO_BLK_T_FLUSH,
-    VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT, VIRTIO_BLK_T_WRITE_ZEROES,
-    VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
+    read_config_default, register_queue_stats, report_virtio_error, unregister_queue_stats,
+    virtio_has_feature, Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt,
+    VirtioInterruptType, VIRTIO_BLK_F_DISCARD, VIRTIO_BLK_F_FLUSH, VIRTIO_BLK_F_MQ,
+    VIRTIO_BLK_F_RO, VIRTIO_BLK_F_SEG_MAX, VIRTIO_BLK_F_WRITE_ZEROES, VIRTIO_BLK_ID_BYTES,
+    VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP, VIRTIO_BLK_T_DISCARD,
+    VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
+    VIRTIO_BLK_T_WRITE_ZEROES, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP, VIRTIO_F_RING_EVENT_IDX,
+    VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_TYPE_BLOCK,
 };
 use address_space::{AddressSpace, GuestAddress};
 use block_backend::{
@@ -42,7 +42,7 @@ use block_backend::{
     BlockProperty, BlockStatus,
 };
 use machine_manager::config::{BlkDevConfig, ConfigCheck, DriveFile, VmConfig};
-use machine_manager::event_loop::{register_event_helper, unregister_event_helper, EventLoop};
+use machine_manager::event_loop::{unregister_event_helper, EventLoop};
 use migration::{
     migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
     StateTransfer,
@@ -53,11 +53,15 @@ use util::aio::{
     WriteZeroesState,
 };
 use util::byte_code::ByteCode;
+use util::latency_histogram::{
+    get_latency_histogram, unregister_latency_histograms, LatencyHistogram,
+};
 use util::leak_bucket::LeakBucket;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
 use util::offset_of;
+use util::trace;
 
 /// Number of virtqueues.
 const QUEUE_NUM_BLK: usize = 1;
@@ -120,6 +124,91 @@ struct DiscardWriteZeroesSeg {
 
 impl ByteCode for DiscardWriteZeroesSeg {}
 
+/// Latency histograms of block requests, from popping the request to putting it into
+/// used ring.
+struct BlockLatency {
+    read: Arc<LatencyHistogram>,
+    write: Arc<LatencyHistogram>,
+    flush: Arc<LatencyHistogram>,
+}
+
+impl BlockLatency {
+    fn new(id: &str) -> Self {
+        BlockLatency {
+            read: get_latency_histogram(id, "read"),
+            write: get_latency_histogram(id, "write"),
+            flush: get_latency_histogram(id, "flush"),
+        }
+    }
+
+    fn record(&self, req: &Request) {
+        let histogram = match req.out_header.request_type {
+            VIRTIO_BLK_T_IN => &self.read,
+            VIRTIO_BLK_T_OUT => &self.write,
+            VIRTIO_BLK_T_FLUSH => &self.flush,
+            _ => return,
+        };
+        histogram.record(req.start.elapsed());
+    }
+}
+
+/// Caps the number of requests submitted to the block backend and not completed yet, shared by
+/// all the queues of the device.
+struct InflightLimit {
+    max: u32,
+    count: AtomicU32,
+    /// Eventfds of the queues which stopped popping requests because of the limit.
+    stalled: Mutex<Vec<Arc<EventFd>>>,
+}
+
+impl InflightLimit {
+    fn new(max: u32) -> Self {
+        InflightLimit {
+            max,
+            count: AtomicU32::new(0),
+            stalled: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < self.max).then_some(count + 1)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, num: u32) {
+        self.count.fetch_sub(num, Ordering::AcqRel);
+        self.wake_stalled();
+    }
+
+    /// Record the queue as stalled, it's kicked by `queue_evt` once a request completes.
+    fn stall(&self, queue_evt: &Arc<EventFd>) {
+        let mut stalled = self.stalled.lock().unwrap();
+        if !stalled.iter().any(|evt| Arc::ptr_eq(evt, queue_evt)) {
+            stalled.push(queue_evt.clone());
+        }
+        drop(stalled);
+        // Requests may complete before the queue is recorded.
+        self.wake_stalled();
+    }
+
+    fn wake_stalled(&self) {
+        if self.count.load(Ordering::Acquire) >= self.max {
+            return;
+        }
+        for evt in self.stalled.lock().unwrap().drain(..) {
+            if let Err(e) = evt.write(1) {
+                error!(
+                    "Failed to kick the queue stalled by inflight limit: {:?}",
+                    e
+                );
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AioCompleteCb {
     queue: Arc<Mutex<Queue>>,
@@ -128,6 +217,9 @@ pub struct AioCompleteCb {
     req: Arc<Request>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
+    latency: Arc<BlockLatency>,
+    /// The limit which the requests are counted in, None if they are not submitted to backend.
+    inflight: Option<Arc<InflightLimit>>,
 }
 
 impl AioCompleteCb {
@@ -137,6 +229,7 @@ impl AioCompleteCb {
         req: Arc<Request>,
         interrupt_cb: Arc<VirtioInterrupt>,
         driver_features: u64,
+        latency: Arc<BlockLatency>,
     ) -> Self {
         AioCompleteCb {
             queue,
@@ -144,25 +237,44 @@ impl AioCompleteCb {
             req,
             interrupt_cb,
             driver_features,
+            latency,
+            inflight: None,
         }
     }
 
     fn complete_request(&self, status: u8) -> Result<()> {
+        // Hold the queue once for all the merged requests, and notify the guest once.
+        let mut queue_lock = self.queue.lock().unwrap();
         let mut req = Some(self.req.as_ref());
+        let mut req_num = 0;
         while let Some(req_raw) = req {
-            self.complete_one_request(req_raw, status)?;
+            self.complete_one_request(&mut queue_lock, req_raw, status)?;
             req = req_raw.next.as_ref().as_ref();
+            req_num += 1;
+        }
+        if let Some(inflight) = self.inflight.as_ref() {
+            inflight.release(req_num);
+        }
+
+        if queue_lock
+            .vring
+            .should_notify(&self.mem_space, self.driver_features)
+        {
+            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
+                .with_context(|| {
+                    VirtioError::InterruptTrigger("blk io completion", VirtioInterruptType::Vring)
+                })?;
+            trace::virtio_send_interrupt("Block");
         }
         Ok(())
     }
 
-    fn complete_one_request(&self, req: &Request, status: u8) -> Result<()> {
+    fn complete_one_request(&self, queue: &mut Queue, req: &Request, status: u8) -> Result<()> {
         if let Err(ref e) = self.mem_space.write_object(&status, req.in_header) {
             bail!("Failed to write the status (blk io completion) {:?}", e);
         }
 
-        let mut queue_lock = self.queue.lock().unwrap();
-        queue_lock
+        queue
             .vring
             .add_used(&self.mem_space, req.desc_index, req.in_len)
             .with_context(|| {
@@ -171,17 +283,7 @@ impl AioCompleteCb {
                     req.desc_index, req.in_len
                 )
             })?;
-
-        if queue_lock
-            .vring
-            .should_notify(&self.mem_space, self.driver_features)
-        {
-            (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue_lock), false)
-                .with_context(|| {
-                    VirtioError::InterruptTrigger("blk io completion", VirtioInterruptType::Vring)
-                })?;
-            self.trace_send_interrupt("Block".to_string());
-        }
+        self.latency.record(req);
         Ok(())
     }
 }
@@ -194,6 +296,8 @@ struct Request {
     data_len: u64,
     in_len: u32,
     in_header: GuestAddress,
+    /// Time when the request is popped from virtqueue.
+    start: Instant,
     /// Point to the next merged Request.
     next: Box<Option<Request>>,
 }
@@ -241,6 +345,7 @@ impl Request {
             data_len: 0,
             in_len: 0,
             in_header,
+            start: Instant::now(),
             next: Box::new(None),
         };
 
@@ -258,16 +363,21 @@ impl Request {
             | VIRTIO_BLK_T_OUT
             | VIRTIO_BLK_T_DISCARD
             | VIRTIO_BLK_T_WRITE_ZEROES => {
-                let data_iovec = match out_header.request_type {
-                    VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => {
-                        iov_discard_front(&mut elem.out_iovec, size_of::<RequestOutHeader>() as u64)
-                    }
+                let (data_iovec, is_write) = match out_header.request_type {
+                    VIRTIO_BLK_T_OUT | VIRTIO_BLK_T_DISCARD | VIRTIO_BLK_T_WRITE_ZEROES => (
+                        iov_discard_front(
+                            &mut elem.out_iovec,
+                            size_of::<RequestOutHeader>() as u64,
+                        ),
+                        false,
+                    ),
                     // Otherwise discard the last "status" byte.
-                    _ => iov_discard_back(&mut elem.in_iovec, 1),
-                }
-                .with_context(|| "Empty data for block request")?;
+                    _ => (iov_discard_back(&mut elem.in_iovec, 1), true),
+                };
+                let data_iovec = data_iovec.with_context(|| "Empty data for block request")?;
 
-                let (data_len, iovec) = gpa_hva_iovec_map(data_iovec, &handler.mem_space)?;
+                let (data_len, iovec) =
+                    gpa_hva_iovec_map(data_iovec, &handler.mem_space, is_write)?;
                 request.data_len = data_len;
                 request.iovec = iovec;
             }
@@ -503,6 +613,15 @@ struct BlockIoHandler {
     discard: bool,
     /// The write-zeroes state.
     write_zeroes: WriteZeroesState,
+    /// Latency histograms of requests.
+    latency: Arc<BlockLatency>,
+    /// Time that the queue keeps being polled with guest notifications disabled since
+    /// the last request, zero if polling mode is disabled.
+    poll_timeout: Duration,
+    /// When the last request is found in polling mode, None if the queue is notified by guest.
+    poll_last_busy: Option<Instant>,
+    /// Max number of requests in flight in the backend, None if unlimited.
+    inflight: Option<Arc<InflightLimit>>,
 }
 
 impl BlockIoHandler {
@@ -553,14 +672,27 @@ impl BlockIoHandler {
 
     fn process_queue_internal(&mut self) -> Result<bool> {
         let mut req_queue = Vec::new();
+        let mut invalid_reqs = Vec::new();
         let mut done = false;
 
+        // Pop all the available elements with the queue held once, and parse them after the
+        // queue is unlocked, as it will be held when completing requests.
+        let mut elems = Vec::new();
+        let mut queue = self.queue.lock().unwrap();
         loop {
-            let mut queue = self.queue.lock().unwrap();
-            let mut elem = queue
+            if let Some(inflight) = self.inflight.as_ref() {
+                if !inflight.try_acquire() {
+                    inflight.stall(&self.queue_evt);
+                    break;
+                }
+            }
+            let elem = queue
                 .vring
                 .pop_avail(&self.mem_space, self.driver_features)?;
             if elem.desc_num == 0 {
+                if let Some(inflight) = self.inflight.as_ref() {
+                    inflight.release(1);
+                }
                 break;
             }
 
@@ -569,35 +701,49 @@ impl BlockIoHandler {
                 if let Some(ctx) = EventLoop::get_ctx(self.iothread.as_ref()) {
                     if lb.throttled(ctx, 1_u64) {
                         queue.vring.push_back();
+                        if let Some(inflight) = self.inflight.as_ref() {
+                            inflight.release(1);
+                        }
                         break;
                     }
                 };
             }
 
+            // Avoid bogus guest stuck IO thread.
+            if elems.len() >= queue.vring.actual_size() as usize {
+                bail!("The front driver may be damaged, avail requests more than queue size");
+            }
+            elems.push(elem);
+        }
+        drop(queue);
+
+        for mut elem in elems {
             // Init and put valid request into request queue.
             let mut status = VIRTIO_BLK_S_OK;
             let req = Request::new(self, &mut elem, &mut status)?;
             if status != VIRTIO_BLK_S_OK {
-                let aiocompletecb = AioCompleteCb::new(
-                    self.queue.clone(),
-                    self.mem_space.clone(),
-                    Arc::new(req),
-                    self.interrupt_cb.clone(),
-                    self.driver_features,
-                );
-                // unlock queue, because it will be hold below.
-                drop(queue);
-                aiocompletecb.complete_request(status)?;
+                if let Some(inflight) = self.inflight.as_ref() {
+                    inflight.release(1);
+                }
+                invalid_reqs.push((req, status));
                 continue;
             }
-            // Avoid bogus guest stuck IO thread.
-            if req_queue.len() >= queue.vring.actual_size() as usize {
-                bail!("The front driver may be damaged, avail requests more than queue size");
-            }
             req_queue.push(req);
             done = true;
         }
 
+        for (req, status) in invalid_reqs {
+            let aiocompletecb = AioCompleteCb::new(
+                self.queue.clone(),
+                self.mem_space.clone(),
+                Arc::new(req),
+                self.interrupt_cb.clone(),
+                self.driver_features,
+                self.latency.clone(),
+            );
+            aiocompletecb.complete_request(status)?;
+        }
+
         if req_queue.is_empty() {
             return Ok(done);
         }
@@ -605,13 +751,15 @@ impl BlockIoHandler {
         let merge_req_queue = self.merge_req_queue(req_queue);
         for req in merge_req_queue.into_iter() {
             let req_rc = Arc::new(req);
-            let aiocompletecb = AioCompleteCb::new(
+            let mut aiocompletecb = AioCompleteCb::new(
                 self.queue.clone(),
                 self.mem_space.clone(),
                 req_rc.clone(),
                 self.interrupt_cb.clone(),
                 self.driver_features,
+                self.latency.clone(),
             );
+            aiocompletecb.inflight = self.inflight.clone();
             if let Some(block_backend) = self.block_backend.as_ref() {
                 req_rc.execute(self, block_backend.clone(), aiocompletecb)?;
             } else {
@@ -649,14 +797,12 @@ impl BlockIoHandler {
         let mut done = false;
         let start_time = Instant::now();
 
-        while self
-            .queue
-            .lock()
-            .unwrap()
-            .vring
-            .avail_ring_len(&self.mem_space)?
-            != 0
-        {
+        loop {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.vring.avail_ring_len(&self.mem_space)? == 0 {
+                break;
+            }
+
             // Do not stuck IO thread.
             let now = Instant::now();
             if (now - start_time).as_millis() > MAX_MILLIS_TIME_PROCESS_QUEUE as u128 {
@@ -665,19 +811,26 @@ impl BlockIoHandler {
                 break;
             }
 
-            self.queue.lock().unwrap().vring.suppress_queue_notify(
-                &self.mem_space,
-                self.driver_features,
-                true,
-            )?;
+            queue
+                .vring
+                .suppress_queue_notify(&self.mem_space, self.driver_features, true)?;
+            drop(queue);
 
             done = self.process_queue_internal()?;
 
-            self.queue.lock().unwrap().vring.suppress_queue_notify(
-                &self.mem_space,
-                self.driver_features,
-                false,
-            )?;
+            let mut queue = self.queue.lock().unwrap();
+            if self.poll_timeout.is_zero() {
+                queue
+                    .vring
+                    .suppress_queue_notify(&self.mem_space, self.driver_features, false)?;
+            } else {
+                // Keep guest notifications disabled, the iothread polls the queue instead.
+                self.poll_last_busy = Some(Instant::now());
+                queue
+                    .vring
+                    .disable_queue_notify(&self.mem_space, self.driver_features)?;
+            }
+            drop(queue);
 
             // See whether we have been throttled.
             if let Some(lb) = self.leak_bucket.as_mut() {
@@ -692,7 +845,7 @@ impl BlockIoHandler {
     }
 
     fn process_queue(&mut self) -> Result<bool> {
-        self.trace_request("Block".to_string(), "to IO".to_string());
+        trace::virtio_receive_request("Block", "to IO");
         let result = self.process_queue_suppress_notify();
         if result.is_err() {
             report_virtio_error(
@@ -704,6 +857,29 @@ impl BlockIoHandler {
         result
     }
 
+    /// Poll the queue in iothread. Guest notifications stay disabled while requests keep
+    /// arriving within `poll_timeout`, and are enabled again once the queue is idle. The
+    /// next kick from guest resumes polling.
+    fn poll_queue(&mut self) -> Result<bool> {
+        let done = self.process_queue()?;
+        let last_busy = match self.poll_last_busy {
+            Some(last_busy) => last_busy,
+            None => return Ok(done),
+        };
+        if last_busy.elapsed() < self.poll_timeout {
+            return Ok(true);
+        }
+
+        self.poll_last_busy = None;
+        self.queue.lock().unwrap().vring.suppress_queue_notify(
+            &self.mem_space,
+            self.driver_features,
+            false,
+        )?;
+        // Handle the requests added before guest sees the notification enabled.
+        self.process_queue()
+    }
+
     fn complete_func(aiocb: &AioCb<AioCompleteCb>, mut ret: i64) -> Result<()> {
         match aiocb.req_is_completed(ret) {
             AioReqResult::Inflight => return Ok(()),
@@ -812,8 +988,9 @@ impl EventNotifierHelper for BlockIoHandler {
 
         // Register event notifier for queue_evt.
         let h_clone = handler.clone();
+        let queue_stats = handler_raw.queue.lock().unwrap().vring.get_stats().clone();
         let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut h_lock = h_clone.lock().unwrap();
             if h_lock.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -829,7 +1006,7 @@ impl EventNotifierHelper for BlockIoHandler {
             if h_lock.device_broken.load(Ordering::SeqCst) {
                 return None;
             }
-            match h_lock.process_queue() {
+            match h_lock.poll_queue() {
                 Ok(done) => {
                     if done {
                         Some(Vec::new())
@@ -1051,6 +1228,14 @@ impl VirtioDevice for Block {
                 self.blk_cfg.iothread,
             );
         }
+        for iothread in self.blk_cfg.queue_iothreads.iter() {
+            if EventLoop::get_ctx(Some(iothread)).is_none() {
+                bail!(
+                    "IOThread {:?} of Block queue is not configured in params.",
+                    iothread
+                );
+            }
+        }
 
         if !self.blk_cfg.path_on_host.is_empty() {
             let drive_files = self.drive_files.lock().unwrap();
@@ -1117,6 +1302,8 @@ impl VirtioDevice for Block {
         let drive_files = self.drive_files.lock().unwrap();
         let drive_id = VmConfig::get_drive_id(&drive_files, &self.blk_cfg.path_on_host)?;
         remove_block_backend(&drive_id);
+        unregister_latency_histograms(&self.blk_cfg.id);
+        unregister_queue_stats(&self.blk_cfg.id);
         Ok(())
     }
 
@@ -1142,7 +1329,13 @@ impl VirtioDevice for Block {
         queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
         self.interrupt_cb = Some(interrupt_cb.clone());
+        let latency = Arc::new(BlockLatency::new(&self.blk_cfg.id));
+        let inflight = match self.blk_cfg.max_inflight {
+            0 => None,
+            max => Some(Arc::new(InflightLimit::new(max))),
+        };
         let queues = self.base.queues.clone();
+        register_queue_stats(&self.blk_cfg.id, &queues);
         for (index, queue) in queues.iter().enumerate() {
             if !queue.lock().unwrap().is_enabled() {
                 continue;
@@ -1150,6 +1343,7 @@ impl VirtioDevice for Block {
             let (sender, receiver) = channel();
             let update_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK)?);
             let driver_features = self.base.driver_features;
+            let iothread = self.blk_cfg.queue_iothread(index).cloned();
             let handler = BlockIoHandler {
                 queue: queue.clone(),
                 queue_evt: queue_evts[index].clone(),
@@ -1165,20 +1359,24 @@ impl VirtioDevice for Block {
                 update_evt: update_evt.clone(),
                 device_broken: self.base.broken.clone(),
                 interrupt_cb: interrupt_cb.clone(),
-                iothread: self.blk_cfg.iothread.clone(),
+                iothread: iothread.clone(),
                 leak_bucket: match self.blk_cfg.iops {
                     Some(iops) => Some(LeakBucket::new(iops)?),
                     None => None,
                 },
                 discard: self.blk_cfg.discard,
                 write_zeroes: self.blk_cfg.write_zeroes,
+                latency: latency.clone(),
+                poll_timeout: Duration::from_micros(self.blk_cfg.poll_us),
+                poll_last_busy: None,
+                inflight: inflight.clone(),
             };
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
-            register_event_helper(
+            self.base.register_queue_event(
                 notifiers,
+                iothread.as_ref(),
                 self.blk_cfg.iothread.as_ref(),
-                &mut self.base.deactivate_evts,
             )?;
             self.update_evts.push(update_evt);
             self.senders.push(sender);
@@ -1207,6 +1405,7 @@ impl VirtioDevice for Block {
             self.blk_cfg.iothread.as_ref(),
             &mut self.base.deactivate_evts,
         )?;
+        self.base.unregister_queue_events()?;
         if let Some(block_backend) = self.block_backend.as_ref() {
             let mut block_backend = block_backend.lock().unwrap();
             // Must drain requests before unregister.
@@ -1326,9 +1525,6 @@ impl StateTransfer for Block {
 
 impl MigrationHook for Block {}
 
-impl VirtioTrace for BlockIoHandler {}
-impl VirtioTrace for AioCompleteCb {}
-
 #[cfg(test)]
 mod tests {
     use std::sync::atomic::{AtomicU32, Ordering};
@@ -1525,6 +1721,8 @@ mod tests {
         // spawn io thread
         let io_conf = IothreadConfig {
             id: thread_name.clone(),
+            affinity: None,
+            host_nodes: None,
         };
         EventLoop::object_init(&Some(vec![io_conf])).unwrap();
 
@@ -1652,4 +1850,27 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_inflight_limit() {
+        let limit = InflightLimit::new(2);
+        let queue_evt = Arc::new(EventFd::new(libc::EFD_NONBLOCK).unwrap());
+
+        assert!(limit.try_acquire());
+        assert!(limit.try_acquire());
+        assert!(!limit.try_acquire());
+
+        // The stalled queue is kicked once a request completes.
+        limit.stall(&queue_evt);
+        limit.stall(&queue_evt);
+        assert_eq!(limit.stalled.lock().unwrap().len(), 1);
+        assert!(queue_evt.read().is_err());
+        limit.release(1);
+        assert_eq!(queue_evt.read().unwrap(), 1);
+        assert!(limit.stalled.lock().unwrap().is_empty());
+
+        assert!(limit.try_acquire());
+        limit.release(2);
+        assert_eq!(limit.count.load(Ordering::Acquire), 0);
+    }
 }
@@ -41,8 +41,9 @@ use machine_manager::event_loop::{register_event_helper, unregister_event_helper
 use migration_derive::ByteCode;
 use ui::console::{
     console_close, console_init, display_cursor_define, display_graphic_update,
-    display_replace_surface, display_set_major_screen, get_run_stage, set_run_stage, ConsoleType,
-    DisplayConsole, DisplayMouse, DisplaySurface, HardWareOperations, VmRunningStage,
+    display_replace_surface, display_resize, display_set_major_screen, get_run_stage,
+    set_run_stage, ConsoleType, DisplayConsole, DisplayMouse, DisplaySurface, HardWareOperations,
+    VmRunningStage,
 };
 use ui::pixman::unref_pixman_image;
 use util::aio::{iov_from_buf_direct, iov_to_buf_direct, Iovec};
@@ -64,6 +65,8 @@ use util::pixman::{
 const QUEUE_NUM_GPU: usize = 2;
 /// Display changed event
 const VIRTIO_GPU_EVENT_DISPLAY: u32 = 1 << 0;
+/// Max width or height of output, limited by the 12-bit fields of EDID detailed timing.
+const VIRTIO_GPU_MAX_RESOLUTION: u32 = 4095;
 
 /// The flag indicates that the frame buffer only used in windows.
 const VIRTIO_GPU_RES_WIN_FRAMEBUF: u32 = 0x80000000;
@@ -102,6 +105,8 @@ impl Default for GpuResource {
 #[derive(Default, Clone, Copy)]
 struct VirtioGpuOutputState {
     con_id: usize,
+    /// Whether the output is connected, which is reported to guest by display info.
+    enabled: bool,
     width: u32,
     height: u32,
     x_coor: i32,
@@ -318,6 +323,8 @@ impl HardWareOperations for GpuOpts {
         // Update output size.
         for output_state in self.output_states.lock().unwrap().iter_mut() {
             if output_state.con_id == con_id {
+                // Zero size means the output is disconnected.
+                output_state.enabled = width != 0 && height != 0;
                 output_state.width = width;
                 output_state.height = height;
                 break;
@@ -379,8 +386,8 @@ impl VirtioGpuRequest {
             iov_discard_front(&mut elem.out_iovec, size_of::<VirtioGpuCtrlHdr>() as u64)
                 .unwrap_or_default();
 
-        let (out_len, out_iovec) = gpa_hva_iovec_map(data_iovec, mem_space)?;
-        let (in_len, in_iovec) = gpa_hva_iovec_map(&elem.in_iovec, mem_space)?;
+        let (out_len, out_iovec) = gpa_hva_iovec_map(data_iovec, mem_space, false)?;
+        let (in_len, in_iovec) = gpa_hva_iovec_map(&elem.in_iovec, mem_space, true)?;
 
         // Note: in_iov and out_iov total len is no more than 1<<32, and
         // out_iov is more than 1, so in_len and out_len will not overflow.
@@ -459,8 +466,6 @@ struct GpuIoHandler {
     driver_features: u64,
     /// Vector for resources.
     resources_list: Vec<GpuResource>,
-    /// The bit mask of whether scanout is enabled or not.
-    enable_output_bitmask: u32,
     /// The number of scanouts
     num_scanouts: u32,
     /// States of all output_states.
@@ -473,6 +478,36 @@ struct GpuIoHandler {
     used_hostmem: u64,
 }
 
+/// Name of the display console of the output.
+fn output_dev_name(output: u32) -> String {
+    format!("virtio-gpu{}", output)
+}
+
+/// Resize the output of virtio-gpu, which is reported to guest as a display
+/// change event. Zero size disconnects the output.
+///
+/// # Arguments
+///
+/// * `output` - Index of the output.
+/// * `width` - Width of the output.
+/// * `height` - Height of the output.
+pub fn resize_gpu_output(output: u32, width: u32, height: u32) -> Result<()> {
+    if (width == 0) != (height == 0) {
+        bail!("Width and height should be both zero or non-zero");
+    }
+    if width > VIRTIO_GPU_MAX_RESOLUTION || height > VIRTIO_GPU_MAX_RESOLUTION {
+        bail!(
+            "Resolution {}x{} exceeds the max {}x{}",
+            width,
+            height,
+            VIRTIO_GPU_MAX_RESOLUTION,
+            VIRTIO_GPU_MAX_RESOLUTION
+        );
+    }
+    display_resize(&output_dev_name(output), width, height)
+        .with_context(|| format!("Failed to resize virtio-gpu output {}", output))
+}
+
 fn create_surface(
     scanout: &mut GpuScanout,
     info_set_scanout: VirtioGpuSetScanout,
@@ -798,9 +833,8 @@ impl GpuIoHandler {
         display_info.header.hdr_type = VIRTIO_GPU_RESP_OK_DISPLAY_INFO;
 
         let output_states_lock = self.output_states.lock().unwrap();
-        for i in 0..self.num_scanouts {
-            if (self.enable_output_bitmask & (1 << i)) != 0 {
-                let i = i as usize;
+        for i in 0..self.num_scanouts as usize {
+            if output_states_lock[i].enabled {
                 display_info.pmodes[i].enabled = 1;
                 display_info.pmodes[i].rect.width = output_states_lock[i].width;
                 display_info.pmodes[i].rect.height = output_states_lock[i].height;
@@ -1270,7 +1304,7 @@ impl GpuIoHandler {
                 len: ent.length,
             });
         }
-        match gpa_hva_iovec_map(&elemiovec, &self.mem_space) {
+        match gpa_hva_iovec_map(&elemiovec, &self.mem_space, false) {
             Ok((_, iov)) => {
                 res.iov = iov;
                 self.response_nodata(VIRTIO_GPU_RESP_OK_NODATA, req)
@@ -1522,6 +1556,7 @@ impl VirtioDevice for Gpu {
         }
 
         let mut output_states = self.output_states.lock().unwrap();
+        output_states[0].enabled = true;
         output_states[0].width = self.cfg.xres;
         output_states[0].height = self.cfg.yres;
 
@@ -1532,7 +1567,7 @@ impl VirtioDevice for Gpu {
             enable_bar0: self.cfg.enable_bar0,
         });
         for i in 0..self.cfg.max_outputs {
-            let dev_name = format!("virtio-gpu{}", i);
+            let dev_name = output_dev_name(i);
             let con = console_init(dev_name, ConsoleType::Graphic, gpu_opts.clone());
             let con_ref = con.as_ref().unwrap().upgrade().unwrap();
             output_states[i as usize].con_id = con_ref.lock().unwrap().con_id;
@@ -1627,7 +1662,6 @@ impl VirtioDevice for Gpu {
             interrupt_cb,
             driver_features: self.base.driver_features,
             resources_list: Vec::new(),
-            enable_output_bitmask: 1,
             num_scanouts: self.cfg.max_outputs,
             output_states: self.output_states.clone(),
             scanouts,
@@ -18,6 +18,7 @@ use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use std::{cmp, fs, mem};
 
 use anyhow::{bail, Context, Result};
@@ -28,41 +29,49 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use crate::{
     check_config_space_rw, iov_discard_front, iov_to_buf, mem_to_buf, read_config_default,
-    report_virtio_error, virtio_has_feature, ElemIovec, Element, Queue, VirtioBase, VirtioDevice,
-    VirtioError, VirtioInterrupt, VirtioInterruptType, VirtioNetHdr, VirtioTrace,
-    VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC,
-    VIRTIO_NET_CTRL_MAC_ADDR_SET, VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN,
-    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX, VIRTIO_NET_CTRL_RX_ALLMULTI,
-    VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST, VIRTIO_NET_CTRL_RX_NOMULTI,
-    VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC, VIRTIO_NET_CTRL_VLAN,
-    VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM,
-    VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX, VIRTIO_NET_F_CTRL_RX_EXTRA,
-    VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ, VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO,
-    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC,
-    VIRTIO_NET_F_MQ, VIRTIO_NET_OK, VIRTIO_TYPE_NET,
+    register_queue_stats, report_virtio_error, unregister_queue_stats, virtio_has_feature,
+    ElemIovec, Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt,
+    VirtioInterruptType, VirtioNetHdr, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC,
+    VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET,
+    VIRTIO_NET_CTRL_MAC_TABLE_SET, VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MAX,
+    VIRTIO_NET_CTRL_MQ_VQ_PAIRS_MIN, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET, VIRTIO_NET_CTRL_RX,
+    VIRTIO_NET_CTRL_RX_ALLMULTI, VIRTIO_NET_CTRL_RX_ALLUNI, VIRTIO_NET_CTRL_RX_NOBCAST,
+    VIRTIO_NET_CTRL_RX_NOMULTI, VIRTIO_NET_CTRL_RX_NOUNI, VIRTIO_NET_CTRL_RX_PROMISC,
+    VIRTIO_NET_CTRL_VLAN, VIRTIO_NET_CTRL_VLAN_ADD, VIRTIO_NET_CTRL_VLAN_DEL, VIRTIO_NET_ERR,
+    VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_RX,
+    VIRTIO_NET_F_CTRL_RX_EXTRA, VIRTIO_NET_F_CTRL_VLAN, VIRTIO_NET_F_CTRL_VQ,
+    VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_ECN, VIRTIO_NET_F_GUEST_TSO4,
+    VIRTIO_NET_F_GUEST_TSO6, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4,
+    VIRTIO_NET_F_HOST_TSO6, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_F_MQ,
+    VIRTIO_NET_OK, VIRTIO_TYPE_NET,
 };
-use address_space::{AddressSpace, RegionCache};
+use address_space::{AddressSpace, RegionCacheTable};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use machine_manager::{
     config::{ConfigCheck, NetworkInterfaceConfig},
     event_loop::EventLoop,
 };
 use migration::{
-    migration::Migratable, DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager,
-    StateTransfer,
+    colo::{ColoNetFilter, ColoRole},
+    migration::Migratable,
+    DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer,
 };
 use migration_derive::{ByteCode, Desc};
+use util::aio::mem_from_buf;
 use util::byte_code::ByteCode;
+use util::latency_histogram::{
+    get_latency_histogram, unregister_latency_histograms, LatencyHistogram,
+};
 use util::loop_context::gen_delete_notifiers;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
 use util::num_ops::str_to_usize;
 use util::tap::{
-    Tap, IFF_MULTI_QUEUE, TUN_F_CSUM, TUN_F_TSO4, TUN_F_TSO6, TUN_F_TSO_ECN, TUN_F_UFO,
+    Tap, TapUring, IFF_MULTI_QUEUE, TAP_URING_RX_BATCH, TAP_URING_TX_BATCH, TUN_F_CSUM, TUN_F_TSO4,
+    TUN_F_TSO6, TUN_F_TSO_ECN, TUN_F_UFO,
 };
+use util::trace;
 
 /// Number of virtqueues(rx/tx/ctrl).
 const QUEUE_NUM_NET: usize = 3;
@@ -642,8 +651,16 @@ impl EventNotifierHelper for NetCtrlHandler {
 
         let locked_net_io = net_io.lock().unwrap();
         let cloned_net_io = net_io.clone();
+        let queue_stats = locked_net_io
+            .ctrl
+            .queue
+            .lock()
+            .unwrap()
+            .vring
+            .get_stats()
+            .clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut locked_net_io = cloned_net_io.lock().unwrap();
             if locked_net_io.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -672,11 +689,17 @@ impl EventNotifierHelper for NetCtrlHandler {
 struct TxVirtio {
     queue: Arc<Mutex<Queue>>,
     queue_evt: Arc<EventFd>,
+    /// Host iovecs of the packet being sent, reused to avoid allocation per packet.
+    iovecs: Vec<libc::iovec>,
 }
 
 impl TxVirtio {
     fn new(queue: Arc<Mutex<Queue>>, queue_evt: Arc<EventFd>) -> Self {
-        TxVirtio { queue, queue_evt }
+        TxVirtio {
+            queue,
+            queue_evt,
+            iovecs: Vec::new(),
+        }
     }
 }
 
@@ -684,6 +707,8 @@ struct RxVirtio {
     queue_full: bool,
     queue: Arc<Mutex<Queue>>,
     queue_evt: Arc<EventFd>,
+    /// Host iovecs of the buffer being received into, reused to avoid allocation per packet.
+    iovecs: Vec<libc::iovec>,
 }
 
 impl RxVirtio {
@@ -692,6 +717,7 @@ impl RxVirtio {
             queue_full: false,
             queue,
             queue_evt,
+            iovecs: Vec::new(),
         }
     }
 }
@@ -701,6 +727,10 @@ struct NetIoHandler {
     tx: TxVirtio,
     tap: Option<Tap>,
     tap_fd: RawFd,
+    /// Whether to access tap through io_uring.
+    io_uring: bool,
+    /// The io_uring instance bound to the current tap, if `io_uring` is enabled.
+    uring: Option<TapUring>,
     mem_space: Arc<AddressSpace>,
     interrupt_cb: Arc<VirtioInterrupt>,
     driver_features: u64,
@@ -710,6 +740,17 @@ struct NetIoHandler {
     is_listening: bool,
     ctrl_info: Arc<Mutex<CtrlInfo>>,
     queue_size: u16,
+    /// Max number of used entries added before checking whether to notify the guest.
+    notify_batch: u16,
+    /// Latency histograms of packets, from popping the descriptor to putting it into
+    /// used ring.
+    rx_latency: Arc<LatencyHistogram>,
+    tx_latency: Arc<LatencyHistogram>,
+    /// COLO state of the device.
+    colo: Arc<ColoNetFilter>,
+    /// Whether this queue pair sends packets released by COLO comparison, and receives
+    /// packets mirrored from primary VM. Only the first queue pair does it.
+    colo_owner: bool,
 }
 
 impl NetIoHandler {
@@ -747,17 +788,32 @@ impl NetIoHandler {
 
     fn get_libc_iovecs(
         mem_space: &Arc<AddressSpace>,
-        cache: &Option<RegionCache>,
+        cache: &Option<RegionCacheTable>,
         elem_iovecs: &[ElemIovec],
+        is_write: bool,
     ) -> Vec<libc::iovec> {
-        let mut iovecs = Vec::new();
+        let mut iovecs = Vec::with_capacity(elem_iovecs.len());
+        NetIoHandler::fill_libc_iovecs(mem_space, cache, elem_iovecs, is_write, &mut iovecs);
+        iovecs
+    }
+
+    /// Translate `elem_iovecs` into `iovecs`, whose old content is dropped but capacity is kept.
+    /// `is_write` is true for the in iovec of rx queue, which is written by the device.
+    fn fill_libc_iovecs(
+        mem_space: &Arc<AddressSpace>,
+        cache: &Option<RegionCacheTable>,
+        elem_iovecs: &[ElemIovec],
+        is_write: bool,
+        iovecs: &mut Vec<libc::iovec>,
+    ) {
+        iovecs.clear();
         for elem_iov in elem_iovecs.iter() {
             // elem_iov.addr has been checked in pop_avail().
             let mut len = elem_iov.len;
             let mut start = elem_iov.addr;
             loop {
                 let io_vec = mem_space
-                    .get_host_address_from_cache(start, cache)
+                    .get_host_address_from_table(start, is_write, cache)
                     .map(|(hva, fr_len)| libc::iovec {
                         iov_base: hva as *mut libc::c_void,
                         iov_len: std::cmp::min(elem_iov.len, fr_len as u32) as libc::size_t,
@@ -771,17 +827,39 @@ impl NetIoHandler {
                 }
             }
         }
-        iovecs
+    }
+
+    fn create_uring(tap: Option<&Tap>, io_uring: bool) -> Option<TapUring> {
+        if !io_uring {
+            return None;
+        }
+        match TapUring::new(tap?) {
+            Ok(uring) => Some(uring),
+            Err(e) => {
+                warn!(
+                    "Failed to set up io_uring for tap, fall back to readv/writev: {:?}",
+                    e
+                );
+                None
+            }
+        }
     }
 
     fn handle_rx(&mut self) -> Result<()> {
-        self.trace_request("Net".to_string(), "to rx".to_string());
+        trace::virtio_receive_request("Net", "to rx");
+        if self.colo.role() == ColoRole::Secondary {
+            return self.handle_rx_colo();
+        }
         if self.tap.is_none() {
             return Ok(());
         }
+        if self.uring.is_some() {
+            return self.handle_rx_uring();
+        }
 
         let mut queue = self.rx.queue.lock().unwrap();
         let mut rx_packets = 0;
+        let mut pending_used = 0;
         loop {
             let elem = queue
                 .vring
@@ -793,11 +871,15 @@ impl NetIoHandler {
             } else if elem.in_iovec.is_empty() {
                 bail!("The length of in iovec is 0");
             }
-            let iovecs = NetIoHandler::get_libc_iovecs(
+            let start = Instant::now();
+            NetIoHandler::fill_libc_iovecs(
                 &self.mem_space,
                 queue.vring.get_cache(),
                 &elem.in_iovec,
+                true,
+                &mut self.rx.iovecs,
             );
+            let iovecs = &self.rx.iovecs;
 
             if MigrationManager::is_active() {
                 // FIXME: mark dirty page needs to be managed by `AddressSpace` crate.
@@ -808,14 +890,14 @@ impl NetIoHandler {
             }
 
             // Read the data from the tap device.
-            let size = NetIoHandler::read_from_tap(&iovecs, self.tap.as_mut().unwrap());
+            let size = NetIoHandler::read_from_tap(iovecs, self.tap.as_mut().unwrap());
             if size < (NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH) as i32 {
                 queue.vring.push_back();
                 break;
             }
 
-            let mut buf = vec![0_u8; NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH];
-            get_net_header(&iovecs, &mut buf).and_then(|size| {
+            let mut buf = [0_u8; NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH];
+            get_net_header(iovecs, &mut buf).and_then(|size| {
                 if size != buf.len() {
                     bail!(
                         "Invalid header length {}, expected length {}",
@@ -844,19 +926,133 @@ impl NetIoHandler {
                         elem.index, size
                     )
                 })?;
+            self.rx_latency.record(start.elapsed());
+            if self.colo.role() == ColoRole::Primary {
+                let mut packet = vec![0_u8; size as usize];
+                get_net_header(&self.rx.iovecs, &mut packet)?;
+                self.colo.mirror_rx(packet);
+            }
 
-            if queue
+            pending_used += 1;
+            if pending_used >= self.notify_batch {
+                notify_used_ring(
+                    &self.mem_space,
+                    self.driver_features,
+                    &self.interrupt_cb,
+                    &mut queue,
+                )?;
+                pending_used = 0;
+            }
+
+            rx_packets += 1;
+            if rx_packets >= self.queue_size {
+                self.rx
+                    .queue_evt
+                    .write(1)
+                    .with_context(|| "Failed to trigger rx queue event".to_string())?;
+                break;
+            }
+        }
+
+        if pending_used > 0 {
+            notify_used_ring(
+                &self.mem_space,
+                self.driver_features,
+                &self.interrupt_cb,
+                &mut queue,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_rx_uring(&mut self) -> Result<()> {
+        let mut queue = self.rx.queue.lock().unwrap();
+        let uring = self.uring.as_mut().unwrap();
+        let mut rx_packets = 0;
+        let mut pending_used = 0;
+        loop {
+            let avail = queue
                 .vring
-                .should_notify(&self.mem_space, self.driver_features)
-            {
-                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
+                .avail_ring_len(&self.mem_space)
+                .with_context(|| "Failed to get avail ring length for net rx")?
+                as usize;
+            if avail == 0 {
+                self.rx.queue_full = true;
+                break;
+            }
+
+            // Only read as many packets as the guest has buffers for, so that no packet
+            // has to be kept in the rx buffers across calls.
+            let count = cmp::min(avail, TAP_URING_RX_BATCH);
+            let packets = uring.read_packets(count)?;
+            let drained = packets.len() < count;
+            for (buf_index, size) in packets {
+                let buf = uring.rx_buf(buf_index, size);
+                if size < NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH
+                    || self
+                        .ctrl_info
+                        .lock()
+                        .unwrap()
+                        .filter_packets(&buf[NET_HDR_LENGTH..])
+                {
+                    continue;
+                }
+
+                let elem = queue
+                    .vring
+                    .pop_avail(&self.mem_space, self.driver_features)
+                    .with_context(|| "Failed to pop avail ring for net rx")?;
+                if elem.desc_num == 0 {
+                    bail!("No avail ring for net rx while avail ring length is not 0");
+                } else if elem.in_iovec.is_empty() {
+                    bail!("The length of in iovec is 0");
+                }
+                let start = Instant::now();
+                let iovecs = NetIoHandler::get_libc_iovecs(
+                    &self.mem_space,
+                    queue.vring.get_cache(),
+                    &elem.in_iovec,
+                    true,
+                );
+
+                if MigrationManager::is_active() {
+                    for iov in iovecs.iter() {
+                        // Mark vmm dirty page manually if live migration is active.
+                        MigrationManager::mark_dirty_log(iov.iov_base as u64, iov.iov_len as u64);
+                    }
+                }
+
+                let len = copy_to_iovecs(&iovecs, buf)?;
+                queue
+                    .vring
+                    .add_used(&self.mem_space, elem.index, len as u32)
                     .with_context(|| {
-                        VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
+                        format!(
+                            "Failed to add used ring for net rx, index: {}, len: {}",
+                            elem.index, len
+                        )
                     })?;
-                self.trace_send_interrupt("Net".to_string());
+                self.rx_latency.record(start.elapsed());
+                if self.colo.role() == ColoRole::Primary {
+                    self.colo.mirror_rx(buf.to_vec());
+                }
+                rx_packets += 1;
+                pending_used += 1;
+                if pending_used >= self.notify_batch {
+                    notify_used_ring(
+                        &self.mem_space,
+                        self.driver_features,
+                        &self.interrupt_cb,
+                        &mut queue,
+                    )?;
+                    pending_used = 0;
+                }
             }
 
-            rx_packets += 1;
+            if drained {
+                break;
+            }
             if rx_packets >= self.queue_size {
                 self.rx
                     .queue_evt
@@ -866,10 +1062,144 @@ impl NetIoHandler {
             }
         }
 
+        if pending_used > 0 {
+            notify_used_ring(
+                &self.mem_space,
+                self.driver_features,
+                &self.interrupt_cb,
+                &mut queue,
+            )?;
+        }
+
         Ok(())
     }
 
-    fn send_packets(&self, tap_fd: libc::c_int, iovecs: &[libc::iovec]) -> i8 {
+    /// Guest of secondary VM in COLO mode receives the packets mirrored from primary VM,
+    /// and the packets from tap are dropped.
+    fn handle_rx_colo(&mut self) -> Result<()> {
+        if let Some(tap) = self.tap.as_mut() {
+            // Packet is discarded by each read, whatever the length of buffer is.
+            let mut buf = [0_u8; 1024];
+            while matches!(tap.read(&mut buf), Ok(size) if size > 0) {}
+        }
+        if !self.colo_owner {
+            return Ok(());
+        }
+
+        let mut queue = self.rx.queue.lock().unwrap();
+        let mut pending_used = 0;
+        while let Some(packet) = self.colo.pop_injected() {
+            if packet.len() < NET_HDR_LENGTH + ETHERNET_HDR_LENGTH + VLAN_TAG_LENGTH
+                || self
+                    .ctrl_info
+                    .lock()
+                    .unwrap()
+                    .filter_packets(&packet[NET_HDR_LENGTH..])
+            {
+                continue;
+            }
+
+            let elem = queue
+                .vring
+                .pop_avail(&self.mem_space, self.driver_features)
+                .with_context(|| "Failed to pop avail ring for net rx")?;
+            if elem.desc_num == 0 {
+                // Received after guest adds buffers to rx queue.
+                self.colo.unpop_injected(packet);
+                break;
+            } else if elem.in_iovec.is_empty() {
+                bail!("The length of in iovec is 0");
+            }
+            let iovecs = NetIoHandler::get_libc_iovecs(
+                &self.mem_space,
+                queue.vring.get_cache(),
+                &elem.in_iovec,
+                true,
+            );
+            for iov in iovecs.iter() {
+                // Pages written by secondary VM are restored by checkpoint.
+                MigrationManager::mark_dirty_log(iov.iov_base as u64, iov.iov_len as u64);
+            }
+
+            let len = copy_to_iovecs(&iovecs, &packet)?;
+            queue
+                .vring
+                .add_used(&self.mem_space, elem.index, len as u32)
+                .with_context(|| {
+                    format!(
+                        "Failed to add used ring for net rx, index: {}, len: {}",
+                        elem.index, len
+                    )
+                })?;
+            pending_used += 1;
+            if pending_used >= self.notify_batch {
+                notify_used_ring(
+                    &self.mem_space,
+                    self.driver_features,
+                    &self.interrupt_cb,
+                    &mut queue,
+                )?;
+                pending_used = 0;
+            }
+        }
+
+        if pending_used > 0 {
+            notify_used_ring(
+                &self.mem_space,
+                self.driver_features,
+                &self.interrupt_cb,
+                &mut queue,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the packets of primary VM released by COLO comparison to tap, and
+    /// receive the packets mirrored from primary VM in secondary VM.
+    fn handle_colo(&mut self) -> Result<()> {
+        while let Some(packet) = self.colo.pop_released() {
+            if self.tap_fd == -1 {
+                continue;
+            }
+            let iovec = [libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            }];
+            if NetIoHandler::write_tap(self.tap_fd, &iovec) == -1 {
+                self.colo.unpop_released(packet);
+                self.colo.notify_evt().write(1).with_context(|| {
+                    "Failed to trigger COLO event when writev blocked".to_string()
+                })?;
+                break;
+            }
+        }
+
+        if self.colo.role() == ColoRole::Secondary {
+            self.handle_rx_colo()?;
+        }
+
+        Ok(())
+    }
+
+    /// Outgoing packets are filtered in COLO mode, packets of primary VM are held
+    /// for comparison and packets of secondary VM are dropped. Return false if the
+    /// packet should not be sent now.
+    fn colo_filter_packet(colo: &ColoNetFilter, iovecs: &[libc::iovec]) -> bool {
+        if colo.role() == ColoRole::None {
+            return true;
+        }
+        let packet: Vec<&[u8]> = iovecs
+            .iter()
+            // SAFETY: the iovecs are translated from guest memory and are valid.
+            .map(|iov| unsafe {
+                std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len)
+            })
+            .collect();
+        colo.filter_tx(&packet)
+    }
+
+    fn write_tap(tap_fd: libc::c_int, iovecs: &[libc::iovec]) -> i8 {
         loop {
             // SAFETY: the arguments of writev has been checked and is correct.
             let size = unsafe {
@@ -893,11 +1223,22 @@ impl NetIoHandler {
         0_i8
     }
 
+    fn send_packets(&self, tap_fd: libc::c_int, iovecs: &[libc::iovec]) -> i8 {
+        if !NetIoHandler::colo_filter_packet(&self.colo, iovecs) {
+            return 0_i8;
+        }
+        NetIoHandler::write_tap(tap_fd, iovecs)
+    }
+
     fn handle_tx(&mut self) -> Result<()> {
-        self.trace_request("Net".to_string(), "to tx".to_string());
+        trace::virtio_receive_request("Net", "to tx");
+        if self.uring.is_some() {
+            return self.handle_tx_uring();
+        }
         let mut queue = self.tx.queue.lock().unwrap();
 
         let mut tx_packets = 0;
+        let mut pending_used = 0;
         loop {
             let elem = queue
                 .vring
@@ -908,39 +1249,43 @@ impl NetIoHandler {
             } else if elem.out_iovec.is_empty() {
                 bail!("The length of out iovec is 0");
             }
+            let start = Instant::now();
 
-            let iovecs = NetIoHandler::get_libc_iovecs(
+            NetIoHandler::fill_libc_iovecs(
                 &self.mem_space,
                 queue.vring.get_cache(),
                 &elem.out_iovec,
+                false,
+                &mut self.tx.iovecs,
             );
             let tap_fd = if let Some(tap) = self.tap.as_mut() {
                 tap.as_raw_fd() as libc::c_int
             } else {
                 -1_i32
             };
-            if tap_fd != -1 && self.send_packets(tap_fd, &iovecs) == -1 {
+            if tap_fd != -1 && self.send_packets(tap_fd, &self.tx.iovecs) == -1 {
                 queue.vring.push_back();
                 self.tx.queue_evt.write(1).with_context(|| {
                     "Failed to trigger tx queue event when writev blocked".to_string()
                 })?;
-                return Ok(());
+                break;
             }
 
             queue
                 .vring
                 .add_used(&self.mem_space, elem.index, 0)
                 .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
-
-            if queue
-                .vring
-                .should_notify(&self.mem_space, self.driver_features)
-            {
-                (self.interrupt_cb)(&VirtioInterruptType::Vring, Some(&queue), false)
-                    .with_context(|| {
-                        VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring)
-                    })?;
-                self.trace_send_interrupt("Net".to_string());
+            self.tx_latency.record(start.elapsed());
+
+            pending_used += 1;
+            if pending_used >= self.notify_batch {
+                notify_used_ring(
+                    &self.mem_space,
+                    self.driver_features,
+                    &self.interrupt_cb,
+                    &mut queue,
+                )?;
+                pending_used = 0;
             }
             tx_packets += 1;
             if tx_packets >= self.queue_size {
@@ -952,6 +1297,118 @@ impl NetIoHandler {
             }
         }
 
+        if pending_used > 0 {
+            notify_used_ring(
+                &self.mem_space,
+                self.driver_features,
+                &self.interrupt_cb,
+                &mut queue,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_tx_uring(&mut self) -> Result<()> {
+        let mut queue = self.tx.queue.lock().unwrap();
+        let uring = self.uring.as_mut().unwrap();
+        let mut tx_packets = 0;
+        let mut pending_used = 0;
+        loop {
+            let start = Instant::now();
+            let mut elems = Vec::with_capacity(TAP_URING_TX_BATCH);
+            let mut packets = Vec::with_capacity(TAP_URING_TX_BATCH);
+            // Index of the element of each packet, packets dropped in COLO mode are skipped.
+            let mut packet_elems = Vec::with_capacity(TAP_URING_TX_BATCH);
+            while elems.len() < TAP_URING_TX_BATCH {
+                let elem = queue
+                    .vring
+                    .pop_avail(&self.mem_space, self.driver_features)
+                    .with_context(|| "Failed to pop avail ring for net tx")?;
+                if elem.desc_num == 0 {
+                    break;
+                } else if elem.out_iovec.is_empty() {
+                    bail!("The length of out iovec is 0");
+                }
+                let iovecs = NetIoHandler::get_libc_iovecs(
+                    &self.mem_space,
+                    queue.vring.get_cache(),
+                    &elem.out_iovec,
+                    false,
+                );
+                if NetIoHandler::colo_filter_packet(&self.colo, &iovecs) {
+                    packets.push(iovecs);
+                    packet_elems.push(elems.len());
+                }
+                elems.push(elem);
+            }
+            if elems.is_empty() {
+                break;
+            }
+
+            // Stop at the first packet which tap can not take now, the packets after it are
+            // canceled as the writes are linked. Other failed packets are dropped, as the
+            // guest can not be told.
+            let mut sent = elems.len();
+            for (index, res) in uring.write_packets(&packets)?.into_iter().enumerate() {
+                if res == -libc::EAGAIN || res == -libc::ECANCELED {
+                    sent = packet_elems[index];
+                    break;
+                } else if res < 0 {
+                    error!(
+                        "Failed to write tap through io_uring for net handle_tx: {:?}",
+                        std::io::Error::from_raw_os_error(-res)
+                    );
+                }
+            }
+
+            for elem in elems[..sent].iter() {
+                queue
+                    .vring
+                    .add_used(&self.mem_space, elem.index, 0)
+                    .with_context(|| format!("Net tx: Failed to add used ring {}", elem.index))?;
+                self.tx_latency.record(start.elapsed());
+                pending_used += 1;
+                if pending_used >= self.notify_batch {
+                    notify_used_ring(
+                        &self.mem_space,
+                        self.driver_features,
+                        &self.interrupt_cb,
+                        &mut queue,
+                    )?;
+                    pending_used = 0;
+                }
+            }
+
+            if sent < elems.len() {
+                for _ in sent..elems.len() {
+                    queue.vring.push_back();
+                }
+                self.tx.queue_evt.write(1).with_context(|| {
+                    "Failed to trigger tx queue event when writev blocked".to_string()
+                })?;
+                break;
+            }
+
+            tx_packets += elems.len() as u16;
+            if tx_packets >= self.queue_size {
+                self.tx
+                    .queue_evt
+                    .write(1)
+                    .with_context(|| "Failed to trigger tx queue event".to_string())?;
+                break;
+            }
+        }
+
+        if pending_used > 0 {
+            notify_used_ring(
+                &self.mem_space,
+                self.driver_features,
+                &self.interrupt_cb,
+                &mut queue,
+            )?;
+        }
+
         Ok(())
     }
 
@@ -969,6 +1426,10 @@ impl NetIoHandler {
         if let Some(tap) = locked_net_io.tap.as_ref() {
             locked_net_io.tap_fd = tap.as_raw_fd();
         }
+        // The io_uring instance holds the old tap as registered file, so rebuild it.
+        locked_net_io.uring = None;
+        locked_net_io.uring =
+            NetIoHandler::create_uring(locked_net_io.tap.as_ref(), locked_net_io.io_uring);
 
         let mut notifiers_fds = vec![
             locked_net_io.update_evt.as_raw_fd(),
@@ -978,6 +1439,9 @@ impl NetIoHandler {
         if old_tap_fd != -1 {
             notifiers_fds.push(old_tap_fd);
         }
+        if locked_net_io.colo_owner {
+            notifiers_fds.push(locked_net_io.colo.notify_evt().as_raw_fd());
+        }
         let mut notifiers = gen_delete_notifiers(&notifiers_fds);
         drop(locked_net_io);
 
@@ -1004,6 +1468,35 @@ fn get_net_header(iovec: &[libc::iovec], buf: &mut [u8]) -> Result<usize> {
     Ok(end)
 }
 
+/// Notify the guest of the used ring of the queue, if the guest needs it.
+fn notify_used_ring(
+    mem_space: &Arc<AddressSpace>,
+    driver_features: u64,
+    interrupt_cb: &Arc<VirtioInterrupt>,
+    queue: &mut Queue,
+) -> Result<()> {
+    if queue.vring.should_notify(mem_space, driver_features) {
+        interrupt_cb(&VirtioInterruptType::Vring, Some(&*queue), false)
+            .with_context(|| VirtioError::InterruptTrigger("net", VirtioInterruptType::Vring))?;
+        trace::virtio_send_interrupt("Net");
+    }
+    Ok(())
+}
+
+/// Copy the buf to the iovecs and return the copied number of bytes.
+fn copy_to_iovecs(iovecs: &[libc::iovec], buf: &[u8]) -> Result<usize> {
+    let mut start: usize = 0;
+    for iov in iovecs {
+        let end = cmp::min(start + iov.iov_len, buf.len());
+        mem_from_buf(&buf[start..end], iov.iov_base as u64)?;
+        start = end;
+        if start >= buf.len() {
+            break;
+        }
+    }
+    Ok(start)
+}
+
 fn build_event_notifier(
     fd: RawFd,
     handler: Option<Rc<NotifierCallback>>,
@@ -1043,12 +1536,32 @@ impl EventNotifierHelper for NetIoHandler {
 
         // Register event notifier for rx.
         let cloned_net_io = net_io.clone();
+        let queue_stats = locked_net_io
+            .rx
+            .queue
+            .lock()
+            .unwrap()
+            .vring
+            .get_stats()
+            .clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut locked_net_io = cloned_net_io.lock().unwrap();
             if locked_net_io.device_broken.load(Ordering::SeqCst) {
                 return None;
             }
+            // Mirrored packets which guest had no buffer for are received now.
+            if locked_net_io.colo_owner && locked_net_io.colo.role() == ColoRole::Secondary {
+                if let Err(ref e) = locked_net_io.handle_rx_colo() {
+                    error!("Failed to handle rx(rx event) for net in COLO, {:?}", e);
+                    report_virtio_error(
+                        locked_net_io.interrupt_cb.clone(),
+                        locked_net_io.driver_features,
+                        &locked_net_io.device_broken,
+                    );
+                }
+                return None;
+            }
             if let Some(tap) = locked_net_io.tap.as_ref() {
                 if !locked_net_io.is_listening {
                     let notifier = vec![EventNotifier::new(
@@ -1074,8 +1587,16 @@ impl EventNotifierHelper for NetIoHandler {
 
         // Register event notifier for tx.
         let cloned_net_io = net_io.clone();
+        let queue_stats = locked_net_io
+            .tx
+            .queue
+            .lock()
+            .unwrap()
+            .vring
+            .get_stats()
+            .clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut locked_net_io = cloned_net_io.lock().unwrap();
             if locked_net_io.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -1098,6 +1619,33 @@ impl EventNotifierHelper for NetIoHandler {
             EventSet::IN,
         ));
 
+        // Register event notifier for COLO.
+        if locked_net_io.colo_owner {
+            let cloned_net_io = net_io.clone();
+            let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+                read_fd(fd);
+                let mut locked_net_io = cloned_net_io.lock().unwrap();
+                if locked_net_io.device_broken.load(Ordering::SeqCst) {
+                    return None;
+                }
+                if let Err(ref e) = locked_net_io.handle_colo() {
+                    error!("Failed to handle COLO event for net, {:?}", e);
+                    report_virtio_error(
+                        locked_net_io.interrupt_cb.clone(),
+                        locked_net_io.driver_features,
+                        &locked_net_io.device_broken,
+                    );
+                }
+                None
+            });
+            notifiers.push(build_event_notifier(
+                locked_net_io.colo.notify_evt().as_raw_fd(),
+                Some(handler),
+                NotifierOperation::AddShared,
+                EventSet::IN,
+            ));
+        }
+
         // Register event notifier for tap.
         let cloned_net_io = net_io.clone();
         if let Some(tap) = locked_net_io.tap.as_ref() {
@@ -1178,6 +1726,8 @@ pub struct Net {
     update_evts: Vec<Arc<EventFd>>,
     /// The information about control command.
     ctrl_info: Option<Arc<Mutex<CtrlInfo>>>,
+    /// COLO state of the device.
+    colo: Option<Arc<ColoNetFilter>>,
 }
 
 impl Net {
@@ -1374,6 +1924,14 @@ impl VirtioDevice for Net {
                 self.net_cfg.iothread,
             );
         }
+        for iothread in self.net_cfg.queue_iothreads.iter() {
+            if EventLoop::get_ctx(Some(iothread)).is_none() {
+                bail!(
+                    "IOThread {:?} of Net queue is not configured in params.",
+                    iothread
+                );
+            }
+        }
 
         let queue_pairs = self.net_cfg.queues / 2;
         if !self.net_cfg.host_dev_name.is_empty() {
@@ -1399,6 +1957,12 @@ impl VirtioDevice for Net {
 
         self.init_config_features()?;
 
+        if self.colo.is_none() {
+            let colo = Arc::new(ColoNetFilter::new(&self.net_cfg.id)?);
+            MigrationManager::register_colo_filter(colo.clone());
+            self.colo = Some(colo);
+        }
+
         Ok(())
     }
 
@@ -1461,6 +2025,10 @@ impl VirtioDevice for Net {
             VirtioNetState::descriptor(),
             &self.net_cfg.id,
         );
+        unregister_latency_histograms(&self.net_cfg.id);
+        unregister_queue_stats(&self.net_cfg.id);
+        MigrationManager::unregister_colo_filter(&self.net_cfg.id);
+        self.colo = None;
         Ok(())
     }
 
@@ -1492,7 +2060,12 @@ impl VirtioDevice for Net {
         interrupt_cb: Arc<VirtioInterrupt>,
         queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
+        let colo = self
+            .colo
+            .clone()
+            .with_context(|| "Net device is not realized")?;
         let queues = self.base.queues.clone();
+        register_queue_stats(&self.net_cfg.id, &queues);
         let queue_num = queues.len();
         let ctrl_info = Arc::new(Mutex::new(CtrlInfo::new(self.config_space.clone())));
         self.ctrl_info = Some(ctrl_info.clone());
@@ -1545,6 +2118,8 @@ impl VirtioDevice for Net {
                 tx: TxVirtio::new(tx_queue, tx_queue_evt),
                 tap: self.taps.as_ref().map(|t| t[index].clone()),
                 tap_fd: -1,
+                io_uring: self.net_cfg.io_uring,
+                uring: None,
                 mem_space: mem_space.clone(),
                 interrupt_cb: interrupt_cb.clone(),
                 driver_features,
@@ -1554,16 +2129,22 @@ impl VirtioDevice for Net {
                 is_listening: true,
                 ctrl_info: ctrl_info.clone(),
                 queue_size: self.queue_size_max(),
+                notify_batch: self.net_cfg.notify_batch,
+                rx_latency: get_latency_histogram(&self.net_cfg.id, "rx"),
+                tx_latency: get_latency_histogram(&self.net_cfg.id, "tx"),
+                colo: colo.clone(),
+                colo_owner: index == 0,
             };
             if let Some(tap) = &handler.tap {
                 handler.tap_fd = tap.as_raw_fd();
             }
+            handler.uring = NetIoHandler::create_uring(handler.tap.as_ref(), handler.io_uring);
 
             let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
-            register_event_helper(
+            self.base.register_queue_event(
                 notifiers,
+                self.net_cfg.queue_iothread(index),
                 self.net_cfg.iothread.as_ref(),
-                &mut self.base.deactivate_evts,
             )?;
             self.update_evts.push(update_evt);
         }
@@ -1630,6 +2211,7 @@ impl VirtioDevice for Net {
             self.net_cfg.iothread.as_ref(),
             &mut self.base.deactivate_evts,
         )?;
+        self.base.unregister_queue_events()?;
         self.update_evts.clear();
         self.ctrl_info = None;
         Ok(())
@@ -1673,8 +2255,6 @@ impl StateTransfer for Net {
 
 impl MigrationHook for Net {}
 
-impl VirtioTrace for NetIoHandler {}
-
 #[cfg(test)]
 mod tests {
     pub use super::super::*;
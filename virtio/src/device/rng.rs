@@ -25,8 +25,8 @@ use vmm_sys_util::eventfd::EventFd;
 
 use crate::error::VirtioError;
 use crate::{
-    ElemIovec, Queue, VirtioBase, VirtioDevice, VirtioInterrupt, VirtioInterruptType, VirtioTrace,
-    VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
+    register_queue_stats, ElemIovec, Queue, VirtioBase, VirtioDevice, VirtioInterrupt,
+    VirtioInterruptType, VIRTIO_F_VERSION_1, VIRTIO_TYPE_RNG,
 };
 use address_space::AddressSpace;
 use machine_manager::{
@@ -42,6 +42,7 @@ use util::leak_bucket::LeakBucket;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::trace;
 
 const QUEUE_NUM_RNG: usize = 1;
 const RNG_SIZE_MAX: u32 = 1 << 20;
@@ -92,7 +93,7 @@ impl RngHandler {
     }
 
     fn process_queue(&mut self) -> Result<()> {
-        self.trace_request("Rng".to_string(), "to IO".to_string());
+        trace::virtio_receive_request("Rng", "to IO");
         let mut queue_lock = self.queue.lock().unwrap();
         let mut need_interrupt = false;
 
@@ -145,7 +146,7 @@ impl RngHandler {
                 .with_context(|| {
                     VirtioError::InterruptTrigger("rng", VirtioInterruptType::Vring)
                 })?;
-            self.trace_send_interrupt("Rng".to_string());
+            trace::virtio_send_interrupt("Rng");
         }
 
         Ok(())
@@ -158,8 +159,17 @@ impl EventNotifierHelper for RngHandler {
 
         // Register event notifier for queue_evt
         let rng_handler_clone = rng_handler.clone();
+        let queue_stats = rng_handler
+            .lock()
+            .unwrap()
+            .queue
+            .lock()
+            .unwrap()
+            .vring
+            .get_stats()
+            .clone();
         let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             if let Err(ref e) = rng_handler_clone.lock().unwrap().process_queue() {
                 error!("Failed to process queue for virtio rng, err: {:?}", e,);
             }
@@ -295,6 +305,7 @@ impl VirtioDevice for Rng {
         queue_evts: Vec<Arc<EventFd>>,
     ) -> Result<()> {
         let queues = &self.base.queues;
+        register_queue_stats(&self.rng_cfg.id, queues);
         let handler = RngHandler {
             queue: queues[0].clone(),
             queue_evt: queue_evts[0].clone(),
@@ -348,8 +359,6 @@ impl StateTransfer for Rng {
 
 impl MigrationHook for Rng {}
 
-impl VirtioTrace for RngHandler {}
-
 #[cfg(test)]
 mod tests {
     use std::io::Write;
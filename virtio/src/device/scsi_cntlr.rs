@@ -22,9 +22,9 @@ use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
 use crate::{
     check_config_space_rw, gpa_hva_iovec_map, iov_discard_front, iov_to_buf, read_config_default,
-    report_virtio_error, Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt,
-    VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
-    VIRTIO_TYPE_SCSI,
+    register_queue_stats, report_virtio_error, unregister_queue_stats, Element, Queue, VirtioBase,
+    VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType, VIRTIO_F_RING_EVENT_IDX,
+    VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1, VIRTIO_TYPE_SCSI,
 };
 use address_space::{AddressSpace, GuestAddress};
 use block_backend::BlockIoErrorCallback;
@@ -44,15 +44,15 @@ use util::loop_context::{
 };
 
 /// Virtio Scsi Controller has 1 ctrl queue, 1 event queue and at least 1 cmd queue.
-const SCSI_CTRL_QUEUE_NUM: usize = 1;
-const SCSI_EVENT_QUEUE_NUM: usize = 1;
-const SCSI_MIN_QUEUE_NUM: usize = 3;
+pub(crate) const SCSI_CTRL_QUEUE_NUM: usize = 1;
+pub(crate) const SCSI_EVENT_QUEUE_NUM: usize = 1;
+pub(crate) const SCSI_MIN_QUEUE_NUM: usize = 3;
 
 /// Default values of the cdb and sense data size configuration fields. Cannot change cdb size
 /// and sense data size Now.
 /// To do: support Override CDB/sense data size.(Guest controlled)
-const VIRTIO_SCSI_CDB_DEFAULT_SIZE: usize = 32;
-const VIRTIO_SCSI_SENSE_DEFAULT_SIZE: usize = 96;
+pub(crate) const VIRTIO_SCSI_CDB_DEFAULT_SIZE: usize = 32;
+pub(crate) const VIRTIO_SCSI_SENSE_DEFAULT_SIZE: usize = 96;
 
 /// Basic length of fixed format sense data.
 const SCSI_SENSE_LEN: u32 = 18;
@@ -90,17 +90,17 @@ const VIRTIO_SCSI_S_FAILURE: u8 = 9;
 
 #[repr(C, packed)]
 #[derive(Copy, Clone, Debug, Default)]
-struct VirtioScsiConfig {
-    num_queues: u32,
-    seg_max: u32,
-    max_sectors: u32,
-    cmd_per_lun: u32,
-    event_info_size: u32,
-    sense_size: u32,
-    cdb_size: u32,
-    max_channel: u16,
-    max_target: u16,
-    max_lun: u32,
+pub(crate) struct VirtioScsiConfig {
+    pub(crate) num_queues: u32,
+    pub(crate) seg_max: u32,
+    pub(crate) max_sectors: u32,
+    pub(crate) cmd_per_lun: u32,
+    pub(crate) event_info_size: u32,
+    pub(crate) sense_size: u32,
+    pub(crate) cdb_size: u32,
+    pub(crate) max_channel: u16,
+    pub(crate) max_target: u16,
+    pub(crate) max_lun: u32,
 }
 
 impl ByteCode for VirtioScsiConfig {}
@@ -183,6 +183,7 @@ impl VirtioDevice for ScsiCntlr {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        unregister_queue_stats(&self.config.id);
         Ok(())
     }
 
@@ -208,6 +209,7 @@ impl VirtioDevice for ScsiCntlr {
         if queues.len() < SCSI_MIN_QUEUE_NUM {
             bail!("virtio scsi controller queues num can not be less than 3!");
         }
+        register_queue_stats(&self.config.id, &queues);
 
         // Register event notifier for ctrl queue.
         let ctrl_queue = queues[0].clone();
@@ -231,7 +233,7 @@ impl VirtioDevice for ScsiCntlr {
         let event_queue = queues[1].clone();
         let event_queue_evt = queue_evts[1].clone();
         let event_handler = ScsiEventQueueHandler {
-            _queue: event_queue,
+            queue: event_queue,
             queue_evt: event_queue_evt,
             _mem_space: mem_space.clone(),
             _interrupt_cb: interrupt_cb.clone(),
@@ -501,12 +503,12 @@ impl<T: Clone + ByteCode + Default, U: Clone + ByteCode + Default> VirtioScsiReq
         // Get possible dataout buffer from virtqueue Element.
         let mut iovec = elem.out_iovec.clone();
         let elemiov = iov_discard_front(&mut iovec, size_of::<T>() as u64).unwrap_or_default();
-        let (out_len, out_iovec) = gpa_hva_iovec_map(elemiov, mem_space)?;
+        let (out_len, out_iovec) = gpa_hva_iovec_map(elemiov, mem_space, false)?;
 
         // Get possible dataout buffer from virtqueue Element.
         let mut iovec = elem.in_iovec.clone();
         let elemiov = iov_discard_front(&mut iovec, size_of::<U>() as u64).unwrap_or_default();
-        let (in_len, in_iovec) = gpa_hva_iovec_map(elemiov, mem_space)?;
+        let (in_len, in_iovec) = gpa_hva_iovec_map(elemiov, mem_space, true)?;
 
         if out_len > 0 && in_len > 0 {
             warn!("Wrong scsi request! Don't support both datain and dataout buffer");
@@ -660,8 +662,9 @@ impl EventNotifierHelper for ScsiCtrlQueueHandler {
 
         let h_locked = handler.lock().unwrap();
         let h_clone = handler.clone();
+        let queue_stats = h_locked.queue.lock().unwrap().vring.get_stats().clone();
         let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut h_lock = h_clone.lock().unwrap();
             if h_lock.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -679,7 +682,7 @@ impl EventNotifierHelper for ScsiCtrlQueueHandler {
 
 struct ScsiEventQueueHandler {
     /// The Event virtqueue.
-    _queue: Arc<Mutex<Queue>>,
+    queue: Arc<Mutex<Queue>>,
     /// EventFd for the Event virtqueue.
     queue_evt: Arc<EventFd>,
     /// The address space to which the scsi HBA belongs.
@@ -698,8 +701,9 @@ impl EventNotifierHelper for ScsiEventQueueHandler {
 
         let h_locked = handler.lock().unwrap();
         let h_clone = handler.clone();
+        let queue_stats = h_locked.queue.lock().unwrap().vring.get_stats().clone();
         let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut h_lock = h_clone.lock().unwrap();
             if h_lock.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -765,8 +769,9 @@ impl EventNotifierHelper for ScsiCmdQueueHandler {
         // Register event notifier for queue evt.
         let h_locked = handler.lock().unwrap();
         let h_clone = handler.clone();
+        let queue_stats = h_locked.queue.lock().unwrap().vring.get_stats().clone();
         let h: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
-            read_fd(fd);
+            queue_stats.record_kicks(read_fd(fd));
             let mut h_lock = h_clone.lock().unwrap();
             if h_lock.device_broken.load(Ordering::SeqCst) {
                 return None;
@@ -10,6 +10,7 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use std::collections::HashMap;
 use std::mem::size_of;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
@@ -26,8 +27,7 @@ use vmm_sys_util::eventfd::EventFd;
 use crate::{
     gpa_hva_iovec_map, iov_discard_front, iov_to_buf, read_config_default, report_virtio_error,
     Element, Queue, VirtioBase, VirtioDevice, VirtioError, VirtioInterrupt, VirtioInterruptType,
-    VirtioTrace, VIRTIO_CONSOLE_F_MULTIPORT, VIRTIO_CONSOLE_F_SIZE, VIRTIO_F_VERSION_1,
-    VIRTIO_TYPE_CONSOLE,
+    VIRTIO_CONSOLE_F_MULTIPORT, VIRTIO_CONSOLE_F_SIZE, VIRTIO_F_VERSION_1, VIRTIO_TYPE_CONSOLE,
 };
 use address_space::AddressSpace;
 use chardev_backend::chardev::{Chardev, ChardevNotifyDevice, ChardevStatus, InputReceiver};
@@ -43,6 +43,7 @@ use util::byte_code::ByteCode;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::trace;
 
 // Buffer size for chardev backend.
 const BUF_SIZE: usize = 4096;
@@ -137,6 +138,10 @@ pub struct Serial {
     pub max_nr_ports: u32,
     /// Serial port vector for serialport.
     pub ports: Arc<Mutex<Vec<Arc<Mutex<SerialPort>>>>>,
+    /// Handlers of port queues indexed by port number, used by hot-plugged ports.
+    port_handlers: HashMap<u32, Weak<Mutex<SerialPortHandler>>>,
+    /// Handler of control queues, used to notify guest of hot-plugged ports.
+    ctrl_handler: Option<Weak<Mutex<SerialControlHandler>>>,
 }
 
 impl Serial {
@@ -184,11 +189,40 @@ impl Serial {
         for port in self.ports.lock().unwrap().iter_mut() {
             port.lock().unwrap().ctrl_handler = Some(Arc::downgrade(&handler_h.clone()));
         }
+        self.ctrl_handler = Some(Arc::downgrade(&handler_h));
         let notifiers = EventNotifierHelper::internal_notifiers(handler_h);
         register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
 
         Ok(())
     }
+
+    /// Add a port to the virtio serial device. If the device has been activated, the port
+    /// is activated and the guest is notified of it.
+    ///
+    /// # Arguments
+    ///
+    /// * `port` - The port to be added.
+    pub fn add_port(&mut self, port: Arc<Mutex<SerialPort>>) {
+        self.ports.lock().unwrap().push(port.clone());
+        if !self.device_activated() {
+            return;
+        }
+
+        let mut locked_port = port.lock().unwrap();
+        let nr = locked_port.nr;
+        if let Some(handler) = self.port_handlers.get(&nr).and_then(|h| h.upgrade()) {
+            handler.lock().unwrap().port = Some(port.clone());
+            locked_port.activate(&handler);
+        }
+        if let Some(ctrl_handler) = self.ctrl_handler.as_ref().and_then(|h| h.upgrade()) {
+            locked_port.ctrl_handler = Some(Arc::downgrade(&ctrl_handler));
+            drop(locked_port);
+            ctrl_handler
+                .lock()
+                .unwrap()
+                .send_control_event(nr, VIRTIO_CONSOLE_PORT_ADD, 1);
+        }
+    }
 }
 
 pub fn get_max_nr(ports: &Arc<Mutex<Vec<Arc<Mutex<SerialPort>>>>>) -> u32 {
@@ -279,6 +313,8 @@ impl VirtioDevice for Serial {
             let handler_h = Arc::new(Mutex::new(handler));
             let notifiers = EventNotifierHelper::internal_notifiers(handler_h.clone());
             register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+            self.port_handlers
+                .insert(nr as u32, Arc::downgrade(&handler_h));
 
             if let Some(port_h) = port {
                 port_h.lock().unwrap().activate(&handler_h);
@@ -300,6 +336,8 @@ impl VirtioDevice for Serial {
         for port in self.ports.lock().unwrap().iter_mut() {
             port.lock().unwrap().deactivate();
         }
+        self.port_handlers.clear();
+        self.ctrl_handler = None;
         unregister_event_helper(None, &mut self.base.deactivate_evts)?;
 
         Ok(())
@@ -352,12 +390,16 @@ pub struct SerialPort {
 
 impl SerialPort {
     pub fn new(port_cfg: VirtioSerialPort) -> Self {
-        // Console is default host connected. And pty chardev has opened by default in realize()
-        // function.
-        let host_connected = port_cfg.is_console || port_cfg.chardev.backend == ChardevType::Pty;
+        // Console is default host connected. And pty and ringbuf chardev have opened by default in
+        // realize() function.
+        let host_connected = port_cfg.is_console
+            || matches!(
+                port_cfg.chardev.backend,
+                ChardevType::Pty | ChardevType::Ringbuf(_)
+            );
 
         SerialPort {
-            name: Some(port_cfg.id),
+            name: Some(port_cfg.name),
             chardev: Arc::new(Mutex::new(Chardev::new(port_cfg.chardev))),
             nr: port_cfg.nr,
             is_console: port_cfg.is_console,
@@ -417,7 +459,7 @@ struct SerialControlHandler {
 
 impl SerialPortHandler {
     fn output_handle(&mut self) {
-        self.trace_request("Serial".to_string(), "to IO".to_string());
+        trace::virtio_receive_request("Serial", "to IO");
 
         self.output_handle_internal().unwrap_or_else(|e| {
             error!("Port handle output error: {:?}", e);
@@ -491,12 +533,15 @@ impl SerialPortHandler {
 
     fn write_chardev_msg(&self, buffer: &[u8], write_len: usize) {
         let port_locked = self.port.as_ref().unwrap().lock().unwrap();
-        // Discard output buffer if this port's chardev is not connected.
+        let locked_chardev = port_locked.chardev.lock().unwrap();
+        // Discard output buffer if this port's chardev is not connected, but it's still
+        // written to the log file of chardev.
         if !port_locked.host_connected {
+            locked_chardev.write_log(&buffer[..write_len]);
             return;
         }
 
-        if let Some(output) = &mut port_locked.chardev.lock().unwrap().output {
+        if let Some(output) = &locked_chardev.output {
             let mut locked_output = output.lock().unwrap();
             // To do:
             // If the buffer is not fully written to chardev, the incomplete part will be discarded.
@@ -590,8 +635,6 @@ impl SerialPortHandler {
     }
 }
 
-impl VirtioTrace for SerialPortHandler {}
-
 impl EventNotifierHelper for SerialPortHandler {
     fn internal_notifiers(serial_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
         let mut notifiers = Vec::new();
@@ -804,7 +847,7 @@ impl SerialControlHandler {
             return Ok(());
         }
 
-        let (in_size, ctrl_vec) = gpa_hva_iovec_map(&elem.in_iovec, &self.mem_space)?;
+        let (in_size, ctrl_vec) = gpa_hva_iovec_map(&elem.in_iovec, &self.mem_space, true)?;
         let len = size_of::<VirtioConsoleControl>() + extra.len();
         if in_size < len as u64 {
             bail!(
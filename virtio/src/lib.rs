@@ -25,6 +25,7 @@
 //! - `x86_64`
 //! - `aarch64`
 
+pub mod bench;
 pub mod device;
 pub mod error;
 pub mod vhost;
@@ -47,6 +48,7 @@ pub use transport::virtio_mmio::{VirtioMmioDevice, VirtioMmioState};
 pub use transport::virtio_pci::VirtioPciDevice;
 pub use vhost::kernel as VhostKern;
 pub use vhost::user as VhostUser;
+pub use vhost::vdpa as VhostVdpa;
 
 use std::cmp;
 use std::io::Write;
@@ -60,8 +62,10 @@ use vmm_sys_util::eventfd::EventFd;
 
 use address_space::AddressSpace;
 use machine_manager::config::ConfigCheck;
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
 use migration_derive::ByteCode;
 use util::aio::{mem_to_buf, Iovec};
+use util::loop_context::EventNotifier;
 use util::num_ops::{read_u32, write_u32};
 use util::AsAny;
 
@@ -363,6 +367,9 @@ pub struct VirtioBase {
     queues: Vec<Arc<Mutex<Queue>>>,
     /// Eventfd for device deactivate.
     deactivate_evts: Vec<RawFd>,
+    /// Eventfd for device deactivate, which is registered to the iothread of a queue other
+    /// than the iothread of the device.
+    queue_deactivate_evts: Vec<(Option<String>, Vec<RawFd>)>,
     /// Device is broken or not.
     broken: Arc<AtomicBool>,
 }
@@ -483,6 +490,31 @@ impl VirtioBase {
         }
         self.queues = queues;
     }
+
+    /// Register the notifiers of a queue handler to `iothread`, which is the iothread
+    /// assigned to the queue and may differ from `dev_iothread` of the device.
+    fn register_queue_event(
+        &mut self,
+        notifiers: Vec<EventNotifier>,
+        iothread: Option<&String>,
+        dev_iothread: Option<&String>,
+    ) -> Result<()> {
+        if iothread == dev_iothread {
+            return register_event_helper(notifiers, iothread, &mut self.deactivate_evts);
+        }
+        let mut evts = Vec::new();
+        register_event_helper(notifiers, iothread, &mut evts)?;
+        self.queue_deactivate_evts.push((iothread.cloned(), evts));
+        Ok(())
+    }
+
+    /// Unregister the notifiers registered to iothreads other than the device's.
+    fn unregister_queue_events(&mut self) -> Result<()> {
+        for (iothread, mut evts) in self.queue_deactivate_evts.drain(..) {
+            unregister_event_helper(iothread.as_ref(), &mut evts)?;
+        }
+        Ok(())
+    }
 }
 
 /// The trait for virtio device operations.
@@ -768,26 +800,6 @@ fn read_config_default(config: &[u8], offset: u64, mut data: &mut [u8]) -> Resul
     Ok(())
 }
 
-/// The trait for trace descriptions of virtio device interactions
-/// on the front and back ends.
-pub trait VirtioTrace {
-    fn trace_request(&self, device: String, behaviour: String) {
-        util::ftrace!(
-            trace_request,
-            "{} : Request received from Guest {}, ready to start processing.",
-            device,
-            behaviour
-        );
-    }
-    fn trace_send_interrupt(&self, device: String) {
-        util::ftrace!(
-            trace_send_interrupt,
-            "{} : stratovirt processing complete, ready to send interrupt to guest.",
-            device
-        );
-    }
-}
-
 /// The function used to inject interrupt to guest when encounter an virtio error.
 pub fn report_virtio_error(
     interrupt_cb: Arc<VirtioInterrupt>,
@@ -812,7 +824,7 @@ pub fn iov_to_buf(mem_space: &AddressSpace, iovec: &[ElemIovec], buf: &mut [u8])
     let mut end: usize = 0;
 
     for iov in iovec {
-        let addr_map = mem_space.get_address_map(iov.addr, iov.len as u64)?;
+        let addr_map = mem_space.get_address_map(iov.addr, iov.len as u64, false)?;
         for addr in addr_map.into_iter() {
             end = cmp::min(start + addr.iov_len as usize, buf.len());
             mem_to_buf(&mut buf[start..end], addr.iov_base)?;
@@ -853,15 +865,17 @@ pub fn iov_discard_back(iovec: &mut [ElemIovec], mut size: u64) -> Option<&mut [
 
 /// Convert GPA buffer iovec to HVA buffer iovec.
 /// If don't need the entire iovec, use iov_discard_front/iov_discard_back firstly.
+/// `is_write` is true for the in iovec, which is written by the device.
 fn gpa_hva_iovec_map(
     gpa_elemiovec: &[ElemIovec],
     mem_space: &AddressSpace,
+    is_write: bool,
 ) -> Result<(u64, Vec<Iovec>)> {
     let mut iov_size = 0;
     let mut hva_iovec = Vec::new();
 
     for elem in gpa_elemiovec.iter() {
-        let mut hva_vec = mem_space.get_address_map(elem.addr, elem.len as u64)?;
+        let mut hva_vec = mem_space.get_address_map(elem.addr, elem.len as u64, is_write)?;
         hva_iovec.append(&mut hva_vec);
         iov_size += elem.len as u64;
     }
@@ -14,12 +14,16 @@ mod split;
 
 pub use split::*;
 
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
 use vmm_sys_util::eventfd::EventFd;
 
-use address_space::{AddressSpace, GuestAddress, RegionCache};
+use address_space::{AddressSpace, GuestAddress, RegionCacheTable};
+use machine_manager::qmp::qmp_schema::VirtqueueStatsInfo;
 
 /// Split Virtqueue.
 pub const QUEUE_TYPE_SPLIT_VRING: u16 = 1;
@@ -29,18 +33,24 @@ pub const QUEUE_TYPE_PACKED_VRING: u16 = 2;
 pub const INVALID_VECTOR_NUM: u16 = 0xFFFF;
 
 /// This marks a buffer as continuing via the next field.
-const VIRTQ_DESC_F_NEXT: u16 = 0x1;
+pub(crate) const VIRTQ_DESC_F_NEXT: u16 = 0x1;
 /// This marks a buffer as write-only (otherwise read-only).
-const VIRTQ_DESC_F_WRITE: u16 = 0x2;
+pub(crate) const VIRTQ_DESC_F_WRITE: u16 = 0x2;
 /// This means the buffer contains a list of buffer descriptors.
 const VIRTQ_DESC_F_INDIRECT: u16 = 0x4;
 
+/// Statistics of the queues keyed by device id, the index of vector is the queue index.
+type QueueStatsMap = BTreeMap<String, Vec<Arc<QueueStats>>>;
+
+static QUEUE_STATS: Lazy<Mutex<QueueStatsMap>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
 fn checked_offset_mem(
     mmio_space: &Arc<AddressSpace>,
     base: GuestAddress,
     offset: u64,
+    is_write: bool,
 ) -> Result<GuestAddress> {
-    if !mmio_space.address_in_memory(base, offset) {
+    if !mmio_space.range_in_memory(base, offset, is_write) {
         bail!(
             "Invalid Address for queue: base 0x{:X}, size {}",
             base.raw_value(),
@@ -95,6 +105,120 @@ impl Element {
     }
 }
 
+/// Statistics of virtqueue, which are used to tune the notification settings.
+#[derive(Default)]
+pub struct QueueStats {
+    /// Size of the queue.
+    size: u16,
+    /// Number of notifications from guest.
+    kicks: AtomicU64,
+    /// Number of interrupts sent to guest.
+    interrupts: AtomicU64,
+    /// The largest number of descriptor chains in the available ring when popping.
+    avail_high_water: AtomicU16,
+    /// Number of interrupts suppressed by the used event index of guest.
+    event_idx_suppressions: AtomicU64,
+}
+
+impl QueueStats {
+    fn new(size: u16) -> Self {
+        QueueStats {
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// Record the notifications from guest, `kicks` is the value read from the
+    /// eventfd of queue.
+    pub fn record_kicks(&self, kicks: u64) {
+        self.kicks.fetch_add(kicks, Ordering::Relaxed);
+    }
+
+    /// Record an interrupt sent to guest.
+    pub fn record_interrupt(&self) {
+        self.interrupts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_avail_len(&self, len: u16) {
+        self.avail_high_water.fetch_max(len, Ordering::Relaxed);
+    }
+
+    fn record_event_idx_suppression(&self) {
+        self.event_idx_suppressions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, device: &str, queue: usize, reset: bool) -> VirtqueueStatsInfo {
+        let load_u64 = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        let avail_high_water = if reset {
+            self.avail_high_water.swap(0, Ordering::Relaxed)
+        } else {
+            self.avail_high_water.load(Ordering::Relaxed)
+        };
+        VirtqueueStatsInfo {
+            device: device.to_string(),
+            queue: queue as u16,
+            size: self.size,
+            kicks: load_u64(&self.kicks),
+            interrupts: load_u64(&self.interrupts),
+            avail_high_water,
+            event_idx_suppressions: load_u64(&self.event_idx_suppressions),
+        }
+    }
+}
+
+/// Register the statistics of queues of the device, it should be called when the
+/// device is activated. The statistics registered before are replaced.
+///
+/// # Arguments
+///
+/// * `device` - The id of device.
+/// * `queues` - The queues of device.
+pub fn register_queue_stats(device: &str, queues: &[Arc<Mutex<Queue>>]) {
+    let stats = queues
+        .iter()
+        .map(|queue| queue.lock().unwrap().vring.get_stats().clone())
+        .collect();
+    QUEUE_STATS
+        .lock()
+        .unwrap()
+        .insert(device.to_string(), stats);
+}
+
+/// Remove the statistics of queues of the device, it should be called when the device
+/// is unrealized.
+pub fn unregister_queue_stats(device: &str) {
+    QUEUE_STATS.lock().unwrap().remove(device);
+}
+
+/// Query the statistics of queues of the device, or of all the devices if `device` is None.
+/// The result is sorted by device id and queue index.
+///
+/// # Arguments
+///
+/// * `device` - The id of device.
+/// * `reset` - Clear the statistics after they are queried.
+pub fn query_queue_stats(device: Option<&str>, reset: bool) -> Vec<VirtqueueStatsInfo> {
+    QUEUE_STATS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(id, _)| device.is_none() || device == Some(id.as_str()))
+        .flat_map(|(id, stats)| {
+            stats
+                .iter()
+                .enumerate()
+                .map(|(index, queue_stats)| queue_stats.snapshot(id, index, reset))
+                .collect::<Vec<VirtqueueStatsInfo>>()
+        })
+        .collect()
+}
+
 /// Vring operations.
 pub trait VringOps {
     /// Return true if the vring is enable by driver.
@@ -149,6 +273,15 @@ pub trait VringOps {
         suppress: bool,
     ) -> Result<()>;
 
+    /// Keep guest from notifying the virtqueue for the available elements from now on,
+    /// until `suppress_queue_notify` enables notification again.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    /// * `features` - Bit mask of features negotiated by the backend and the frontend.
+    fn disable_queue_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> Result<()>;
+
     /// Get the actual size of the vring.
     fn actual_size(&self) -> u16;
 
@@ -164,8 +297,11 @@ pub trait VringOps {
     /// Get the used index of the vring.
     fn get_used_idx(&self, sys_mem: &Arc<AddressSpace>) -> Result<u16>;
 
-    /// Get the region cache information of the SplitVring.
-    fn get_cache(&self) -> &Option<RegionCache>;
+    /// Get the region cache table of the SplitVring.
+    fn get_cache(&self) -> &Option<RegionCacheTable>;
+
+    /// Get the statistics of the vring.
+    fn get_stats(&self) -> &Arc<QueueStats>;
 }
 
 /// Virtio queue.
@@ -21,13 +21,13 @@ use anyhow::{anyhow, bail, Context, Result};
 use log::{error, warn};
 
 use super::{
-    checked_offset_mem, ElemIovec, Element, VringOps, INVALID_VECTOR_NUM, VIRTQ_DESC_F_INDIRECT,
-    VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+    checked_offset_mem, ElemIovec, Element, QueueStats, VringOps, INVALID_VECTOR_NUM,
+    VIRTQ_DESC_F_INDIRECT, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
 };
 use crate::{
     report_virtio_error, virtio_has_feature, VirtioError, VirtioInterrupt, VIRTIO_F_RING_EVENT_IDX,
 };
-use address_space::{AddressSpace, GuestAddress, RegionCache, RegionType};
+use address_space::{AddressSpace, GuestAddress, RegionCacheTable};
 use util::byte_code::ByteCode;
 
 /// When host consumes a buffer, don't interrupt the guest.
@@ -60,6 +60,8 @@ pub struct VirtioAddrCache {
     pub avail_ring_host: u64,
     /// Host virtual address of the used ring.
     pub used_ring_host: u64,
+    /// Topology generation of memory space when the host addresses are translated.
+    pub generation: u64,
 }
 
 /// The configuration of virtqueue.
@@ -151,6 +153,7 @@ impl QueueConfig {
         features: u64,
         broken: &Arc<AtomicBool>,
     ) {
+        self.addr_cache.generation = mem_space.generation();
         self.addr_cache.desc_table_host =
             if let Some((addr, size)) = mem_space.addr_cache_init(self.desc_table) {
                 if size < self.get_desc_size() {
@@ -176,7 +179,7 @@ impl QueueConfig {
             };
 
         self.addr_cache.used_ring_host =
-            if let Some((addr, size)) = mem_space.addr_cache_init(self.used_ring) {
+            if let Some((addr, size)) = mem_space.get_host_range(self.used_ring, true) {
                 if size < self.get_used_size(features) {
                     report_virtio_error(interrupt_cb.clone(), features, broken);
                     0_u64
@@ -187,6 +190,40 @@ impl QueueConfig {
                 0_u64
             };
     }
+
+    /// Translate the vring addresses again if the memory topology changed since the
+    /// host address cache was built, as the old host addresses may be unmapped.
+    ///
+    /// # Arguments
+    ///
+    /// * `mem_space` - The address space of vring.
+    fn refresh_addr_cache(&mut self, mem_space: &AddressSpace) -> Result<()> {
+        let generation = mem_space.generation();
+        if self.addr_cache.generation == generation {
+            return Ok(());
+        }
+
+        let translate = |addr: GuestAddress, len: u64, is_write: bool| match mem_space
+            .get_host_range(addr, is_write)
+        {
+            Some((host, size)) if size >= len => Ok(host),
+            _ => Err(anyhow!(
+                "Vring memory 0x{:X} with length {} is not mapped",
+                addr.raw_value(),
+                len
+            )),
+        };
+        let desc_table_host = translate(self.desc_table, self.get_desc_size(), false)?;
+        let avail_ring_host = translate(self.avail_ring, self.get_avail_size(0), false)?;
+        let used_ring_host = translate(self.used_ring, self.get_used_size(0), true)?;
+        self.addr_cache = VirtioAddrCache {
+            desc_table_host,
+            avail_ring_host,
+            used_ring_host,
+            generation,
+        };
+        Ok(())
+    }
 }
 
 /// Virtio used element.
@@ -250,7 +287,7 @@ impl SplitVringDesc {
         desc_table_host: u64,
         queue_size: u16,
         index: u16,
-        cache: &mut Option<RegionCache>,
+        cache: &Option<RegionCacheTable>,
     ) -> Result<Self> {
         if index >= queue_size {
             return Err(anyhow!(VirtioError::QueueIndex(index, queue_size)));
@@ -281,37 +318,22 @@ impl SplitVringDesc {
         &self,
         sys_mem: &Arc<AddressSpace>,
         queue_size: u16,
-        cache: &mut Option<RegionCache>,
+        cache: &Option<RegionCacheTable>,
     ) -> bool {
         if self.len == 0 {
             error!("Zero sized buffers are not allowed");
             return false;
         }
-        let mut miss_cached = true;
-        if let Some(reg_cache) = cache {
-            let base = self.addr.0;
-            let offset = self.len as u64;
-            let end = match base.checked_add(offset) {
-                Some(addr) => addr,
-                None => {
-                    error!("The memory of descriptor is invalid, range overflows");
-                    return false;
-                }
-            };
-            if base > reg_cache.start && end < reg_cache.end {
-                miss_cached = false;
-            }
-        } else {
-            let gotten_cache = sys_mem.get_region_cache(self.addr);
-            if let Some(obtained_cache) = gotten_cache {
-                if obtained_cache.reg_type == RegionType::Ram {
-                    *cache = gotten_cache;
-                }
-            }
-        }
+        let miss_cached = !cache
+            .as_ref()
+            .filter(|t| t.generation == sys_mem.generation())
+            .and_then(|t| t.translate(self.addr))
+            .map_or(false, |(_, remain)| u64::from(self.len) <= remain);
 
         if miss_cached {
-            if let Err(ref e) = checked_offset_mem(sys_mem, self.addr, u64::from(self.len)) {
+            if let Err(ref e) =
+                checked_offset_mem(sys_mem, self.addr, u64::from(self.len), self.write_only())
+            {
                 error!("The memory of descriptor is invalid, {:?} ", e);
                 return false;
             }
@@ -339,7 +361,7 @@ impl SplitVringDesc {
         desc_table_host: u64,
         queue_size: u16,
         index: u16,
-        cache: &mut Option<RegionCache>,
+        cache: &Option<RegionCacheTable>,
     ) -> Result<SplitVringDesc> {
         SplitVringDesc::new(sys_mem, desc_table_host, queue_size, index, cache)
             .with_context(|| format!("Failed to find next descriptor {}", index))
@@ -382,7 +404,7 @@ impl SplitVringDesc {
     fn get_element(
         sys_mem: &Arc<AddressSpace>,
         desc_info: &DescInfo,
-        cache: &mut Option<RegionCache>,
+        cache: &Option<RegionCacheTable>,
         elem: &mut Element,
     ) -> Result<()> {
         let mut desc_table_host = desc_info.table_host;
@@ -409,7 +431,7 @@ impl SplitVringDesc {
                     bail!("Found two indirect descriptor elem in one request");
                 }
                 (desc_table_host, _) = sys_mem
-                    .get_host_address_from_cache(desc.addr, cache)
+                    .get_host_address_from_table(desc.addr, false, cache)
                     .with_context(|| "Failed to get descriptor table entry host address")?;
                 queue_size = desc.get_desc_num();
                 desc = Self::next_desc(sys_mem, desc_table_host, queue_size, 0, cache)?;
@@ -455,12 +477,15 @@ impl SplitVringDesc {
 impl ByteCode for SplitVringDesc {}
 
 /// Split vring.
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone)]
 pub struct SplitVring {
-    /// Region cache information.
-    cache: Option<RegionCache>,
+    /// Region caches of guest memory, which translate the descriptors and the buffers
+    /// without walking the flat view.
+    cache: Option<RegionCacheTable>,
     /// The configuration of virtqueue.
     queue_config: QueueConfig,
+    /// Statistics of virtqueue.
+    stats: Arc<QueueStats>,
 }
 
 impl Deref for SplitVring {
@@ -486,6 +511,10 @@ impl SplitVring {
         SplitVring {
             cache: None,
             queue_config,
+            stats: Arc::new(QueueStats::new(min(
+                queue_config.size,
+                queue_config.max_size,
+            ))),
         }
     }
 
@@ -641,24 +670,29 @@ impl SplitVring {
     }
 
     fn is_invalid_memory(&self, sys_mem: &Arc<AddressSpace>, actual_size: u64) -> bool {
-        let desc_table_end =
-            match checked_offset_mem(sys_mem, self.desc_table, DESCRIPTOR_LEN * actual_size) {
-                Ok(addr) => addr,
-                Err(ref e) => {
-                    error!(
-                        "descriptor table is out of bounds: start:0x{:X} size:{} {:?}",
-                        self.desc_table.raw_value(),
-                        DESCRIPTOR_LEN * actual_size,
-                        e
-                    );
-                    return true;
-                }
-            };
+        let desc_table_end = match checked_offset_mem(
+            sys_mem,
+            self.desc_table,
+            DESCRIPTOR_LEN * actual_size,
+            false,
+        ) {
+            Ok(addr) => addr,
+            Err(ref e) => {
+                error!(
+                    "descriptor table is out of bounds: start:0x{:X} size:{} {:?}",
+                    self.desc_table.raw_value(),
+                    DESCRIPTOR_LEN * actual_size,
+                    e
+                );
+                return true;
+            }
+        };
 
         let desc_avail_end = match checked_offset_mem(
             sys_mem,
             self.avail_ring,
             VRING_AVAIL_LEN_EXCEPT_AVAILELEM + AVAILELEM_LEN * actual_size,
+            false,
         ) {
             Ok(addr) => addr,
             Err(ref e) => {
@@ -676,6 +710,7 @@ impl SplitVring {
             sys_mem,
             self.used_ring,
             VRING_USED_LEN_EXCEPT_USEDELEM + USEDELEM_LEN * actual_size,
+            true,
         ) {
             Ok(addr) => addr,
             Err(ref e) => {
@@ -733,6 +768,22 @@ impl SplitVring {
         }
     }
 
+    /// Build the region cache table on the first pop after the vring is activated, and
+    /// again once the memory topology changes.
+    ///
+    /// # Arguments
+    ///
+    /// * `sys_mem` - Address space to which the vring belongs.
+    fn refresh_region_cache(&mut self, sys_mem: &Arc<AddressSpace>) {
+        if self
+            .cache
+            .as_ref()
+            .map_or(true, |t| t.generation != sys_mem.generation())
+        {
+            self.cache = Some(sys_mem.get_region_cache_table());
+        }
+    }
+
     fn get_vring_element(
         &mut self,
         sys_mem: &Arc<AddressSpace>,
@@ -755,7 +806,7 @@ impl SplitVring {
             self.addr_cache.desc_table_host,
             self.actual_size(),
             desc_index,
-            &mut self.cache,
+            &self.cache,
         )?;
 
         // Suppress queue notification related to current processing desc chain.
@@ -770,14 +821,12 @@ impl SplitVring {
             index: desc_index,
             desc,
         };
-        SplitVringDesc::get_element(sys_mem, &desc_info, &mut self.cache, elem).with_context(
-            || {
-                format!(
-                    "Failed to get element from descriptor chain {}, table addr: 0x{:X}, size: {}",
-                    desc_info.index, desc_info.table_host, desc_info.size,
-                )
-            },
-        )?;
+        SplitVringDesc::get_element(sys_mem, &desc_info, &self.cache, elem).with_context(|| {
+            format!(
+                "Failed to get element from descriptor chain {}, table addr: 0x{:X}, size: {}",
+                desc_info.index, desc_info.table_host, desc_info.size,
+            )
+        })?;
         self.next_avail += Wrapping(1);
 
         Ok(())
@@ -808,9 +857,17 @@ impl VringOps for SplitVring {
 
     fn pop_avail(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> Result<Element> {
         let mut element = Element::new(0);
-        if !self.is_enabled() || self.avail_ring_len(sys_mem)? == 0 {
+        if !self.is_enabled() {
+            return Ok(element);
+        }
+        self.refresh_addr_cache(sys_mem)
+            .with_context(|| "Failed to refresh vring address cache")?;
+        self.refresh_region_cache(sys_mem);
+        let avail_len = self.avail_ring_len(sys_mem)?;
+        if avail_len == 0 {
             return Ok(element);
         }
+        self.stats.record_avail_len(avail_len);
 
         // Make sure descriptor read does not bypass avail index read.
         fence(Ordering::Acquire);
@@ -829,6 +886,8 @@ impl VringOps for SplitVring {
         if index >= self.size {
             return Err(anyhow!(VirtioError::QueueIndex(index, self.size)));
         }
+        self.refresh_addr_cache(sys_mem)
+            .with_context(|| "Failed to refresh vring address cache")?;
 
         let next_used = u64::from(self.next_used.0 % self.actual_size());
         let used_elem_addr =
@@ -862,7 +921,11 @@ impl VringOps for SplitVring {
 
     fn should_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> bool {
         if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) {
-            self.used_ring_need_event(sys_mem)
+            let need_event = self.used_ring_need_event(sys_mem);
+            if !need_event {
+                self.stats.record_event_idx_suppression();
+            }
+            need_event
         } else {
             !self.is_avail_ring_no_interrupt(sys_mem)
         }
@@ -882,6 +945,15 @@ impl VringOps for SplitVring {
         Ok(())
     }
 
+    fn disable_queue_notify(&mut self, sys_mem: &Arc<AddressSpace>, features: u64) -> Result<()> {
+        if virtio_has_feature(features, VIRTIO_F_RING_EVENT_IDX) {
+            // The avail event lags behind the consumed elements, so guest never crosses it.
+            self.set_avail_event(sys_mem, (self.next_avail - Wrapping(1)).0)
+        } else {
+            self.set_used_flags(sys_mem, true)
+        }
+    }
+
     fn actual_size(&self) -> u16 {
         self.actual_size()
     }
@@ -907,9 +979,13 @@ impl VringOps for SplitVring {
         SplitVring::get_used_idx(self, sys_mem)
     }
 
-    fn get_cache(&self) -> &Option<RegionCache> {
+    fn get_cache(&self) -> &Option<RegionCacheTable> {
         &self.cache
     }
+
+    fn get_stats(&self) -> &Arc<QueueStats> {
+        &self.stats
+    }
 }
 
 #[cfg(test)]
@@ -1423,6 +1499,10 @@ mod tests {
         assert_eq!(event_idx, 1);
         let avail_idx = vring.get_avail_idx(&sys_space).unwrap();
         assert_eq!(avail_idx, 1);
+
+        let stats = vring.get_stats().snapshot("test", 0, false);
+        assert_eq!(stats.size, QUEUE_SIZE);
+        assert_eq!(stats.avail_high_water, 1);
     }
 
     #[test]
@@ -1986,5 +2066,63 @@ mod tests {
         assert!(vring.set_used_ring_idx(&sys_space, 10).is_ok()); // new
         assert!(vring.set_used_event_idx(&sys_space, 4).is_ok()); // event_idx
         assert_eq!(vring.should_notify(&sys_space, features), false);
+
+        // only the interrupts suppressed by event idx are counted
+        let stats = vring.get_stats().snapshot("test", 0, true);
+        assert_eq!(stats.event_idx_suppressions, 2);
+        let stats = vring.get_stats().snapshot("test", 0, false);
+        assert_eq!(stats.event_idx_suppressions, 0);
+    }
+
+    #[test]
+    fn test_refresh_addr_cache() {
+        let sys_space = address_space_init();
+        let ring_base = 1 << 32;
+        let new_ring_region = || {
+            let host_mmap = Arc::new(
+                HostMemMapping::new(
+                    GuestAddress(ring_base),
+                    None,
+                    0x10000,
+                    None,
+                    false,
+                    false,
+                    false,
+                )
+                .unwrap(),
+            );
+            Region::init_ram_region(host_mmap, "ring")
+        };
+        let used_idx_addr = GuestAddress(ring_base + 0x2000 + VRING_IDX_POSITION);
+        let ring_region = new_ring_region();
+        sys_space
+            .root()
+            .add_subregion(ring_region.clone(), ring_base)
+            .unwrap();
+
+        let mut queue_config = QueueConfig::new(QUEUE_SIZE);
+        queue_config.desc_table = GuestAddress(ring_base);
+        queue_config.avail_ring = GuestAddress(ring_base + 0x1000);
+        queue_config.used_ring = GuestAddress(ring_base + 0x2000);
+        queue_config.ready = true;
+        queue_config.size = QUEUE_SIZE;
+        let mut vring = SplitVring::new(queue_config);
+        vring.add_used(&sys_space, 0, 16).unwrap();
+        assert_eq!(sys_space.read_object::<u16>(used_idx_addr).unwrap(), 1);
+
+        // The memory of vring is replaced, the used ring is updated in the new memory.
+        sys_space.root().delete_subregion(&ring_region).unwrap();
+        let ring_region = new_ring_region();
+        sys_space
+            .root()
+            .add_subregion(ring_region.clone(), ring_base)
+            .unwrap();
+        vring.add_used(&sys_space, 1, 16).unwrap();
+        assert_eq!(sys_space.read_object::<u16>(used_idx_addr).unwrap(), 2);
+
+        // The memory of vring is removed.
+        sys_space.root().delete_subregion(&ring_region).unwrap();
+        assert!(vring.add_used(&sys_space, 2, 16).is_err());
+        assert!(vring.pop_avail(&sys_space, 0).is_err());
     }
 }
@@ -231,6 +231,21 @@ impl VirtioMmioDevice {
         Ok(())
     }
 
+    /// Deactivate the virtio device and reset the virtio states, this function is called
+    /// when frontend virtio driver resets the device by writing 0 to status register,
+    /// or when VM is reset.
+    fn deactivate(&mut self) -> Result<()> {
+        let mut locked_dev = self.device.lock().unwrap();
+        if locked_dev.device_activated() {
+            locked_dev
+                .deactivate()
+                .with_context(|| "Failed to deactivate virtio device")?;
+        }
+        locked_dev.virtio_base_mut().reset();
+
+        Ok(())
+    }
+
     fn assign_interrupt_cb(&mut self) {
         let interrupt_evt = self.base.interrupt_evt.clone();
         let locked_dev = self.device.lock().unwrap();
@@ -240,7 +255,7 @@ impl VirtioMmioDevice {
         let interrupt_status = virtio_base.interrupt_status.clone();
 
         let cb = Arc::new(Box::new(
-            move |int_type: &VirtioInterruptType, _queue: Option<&Queue>, needs_reset: bool| {
+            move |int_type: &VirtioInterruptType, queue: Option<&Queue>, needs_reset: bool| {
                 let status = match int_type {
                     VirtioInterruptType::Config => {
                         if needs_reset {
@@ -254,7 +269,12 @@ impl VirtioMmioDevice {
                         // IO stuck problem by change the device configure.
                         VIRTIO_MMIO_INT_CONFIG | VIRTIO_MMIO_INT_VRING
                     }
-                    VirtioInterruptType::Vring => VIRTIO_MMIO_INT_VRING,
+                    VirtioInterruptType::Vring => {
+                        if let Some(q) = queue {
+                            q.vring.get_stats().record_interrupt();
+                        }
+                        VIRTIO_MMIO_INT_VRING
+                    }
                 };
                 interrupt_status.fetch_or(status, Ordering::SeqCst);
                 let interrupt = interrupt_evt.as_ref().unwrap();
@@ -355,7 +375,14 @@ impl VirtioMmioDevice {
                     isr.fetch_and(!value, Ordering::SeqCst);
                 }
             }
-            STATUS_REG => locked_device.set_device_status(value),
+            STATUS_REG => {
+                let old_status = locked_device.device_status();
+                locked_device.set_device_status(value);
+                if old_status != 0 && value == 0 {
+                    drop(locked_device);
+                    self.deactivate()?;
+                }
+            }
             QUEUE_DESC_LOW_REG => locked_device.queue_config_mut(true).map(|config| {
                 config.desc_table = GuestAddress(config.desc_table.0 | u64::from(value));
             })?,
@@ -531,6 +558,15 @@ impl SysBusDevOps for VirtioMmioDevice {
     fn get_sys_resource(&mut self) -> Option<&mut SysRes> {
         Some(&mut self.base.res)
     }
+
+    fn reset(&mut self) -> Result<()> {
+        self.deactivate()?;
+        self.device
+            .lock()
+            .unwrap()
+            .reset()
+            .with_context(|| "Failed to reset virtio device")
+    }
 }
 
 impl acpi::AmlBuilder for VirtioMmioDevice {
@@ -696,6 +732,11 @@ mod tests {
             self.b_active = true;
             Ok(())
         }
+
+        fn deactivate(&mut self) -> Result<()> {
+            self.b_active = false;
+            Ok(())
+        }
     }
 
     #[test]
@@ -1293,5 +1334,34 @@ mod tests {
                 | CONFIG_STATUS_DRIVER_OK
                 | CONFIG_STATUS_FEATURES_OK
         );
+
+        // Driver resets the device by writing 0 to device status.
+        let mut buf: Vec<u8> = vec![0xff, 0xff, 0xff, 0xff];
+        LittleEndian::write_u32(&mut buf[..], 0);
+        assert!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG));
+        let mut locked_device = virtio_device.lock().unwrap();
+        assert!(!locked_device.device_activated());
+        assert!(!locked_device.b_active);
+        assert_eq!(locked_device.device_status(), 0);
+        locked_device.set_queue_select(0);
+        assert!(!locked_device.queue_config_mut(false).unwrap().ready);
+        drop(locked_device);
+
+        // Reset of VM resets the activated device too.
+        let mut buf: Vec<u8> = vec![0xff, 0xff, 0xff, 0xff];
+        LittleEndian::write_u32(
+            &mut buf[..],
+            CONFIG_STATUS_ACKNOWLEDGE
+                | CONFIG_STATUS_DRIVER
+                | CONFIG_STATUS_DRIVER_OK
+                | CONFIG_STATUS_FEATURES_OK,
+        );
+        assert!(virtio_mmio_device.write(&buf[..], addr, STATUS_REG));
+        assert!(virtio_device.lock().unwrap().b_active);
+        assert!(SysBusDevOps::reset(&mut virtio_mmio_device).is_ok());
+        let locked_device = virtio_device.lock().unwrap();
+        assert!(!locked_device.device_activated());
+        assert!(!locked_device.b_active);
+        assert_eq!(locked_device.device_status(), 0);
     }
 }
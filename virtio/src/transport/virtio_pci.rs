@@ -14,11 +14,13 @@ use std::cmp::{max, min};
 use std::mem::size_of;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, bail, Context};
 use byteorder::{ByteOrder, LittleEndian};
 use log::{debug, error, warn};
-use machine_manager::config::M;
+use machine_manager::config::{VirtioPciMsixConfig, M};
+use machine_manager::event_loop::EventLoop;
 use vmm_sys_util::eventfd::EventFd;
 
 use crate::{
@@ -41,6 +43,7 @@ use devices::pci::config::{
     STATUS_INTERRUPT, SUBSYSTEM_ID, SUBSYSTEM_VENDOR_ID, SUB_CLASS_CODE, VENDOR_ID,
 };
 use devices::pci::msix::{update_dev_id, MsixState};
+use devices::pci::rom::init_rom;
 use devices::pci::{
     config::PciConfig, init_intx, init_msix, init_multifunction, le_write_u16, le_write_u32,
     PciBus, PciDevBase, PciDevOps, PciError, Result as PciResult,
@@ -291,6 +294,82 @@ struct VirtioPciState {
     virtio_base: VirtioBaseState,
 }
 
+/// Interrupt moderation state of one MSI-X vector.
+#[derive(Default)]
+struct VectorModeration {
+    /// Number of queue interrupts not delivered yet.
+    pending: u32,
+    /// Time of the last delivered interrupt.
+    last_fire: Option<Instant>,
+    /// Whether a timer is armed to deliver the pending interrupts.
+    timer_armed: bool,
+}
+
+/// Coalesces the queue interrupts sent to the same MSI-X vector.
+struct IrqModeration {
+    delay: Duration,
+    frames: u32,
+    vectors: Mutex<Vec<VectorModeration>>,
+}
+
+impl IrqModeration {
+    fn new(config: &VirtioPciMsixConfig, nvectors: usize) -> Self {
+        let mut vectors = Vec::with_capacity(nvectors);
+        vectors.resize_with(nvectors, VectorModeration::default);
+        IrqModeration {
+            delay: Duration::from_micros(u64::from(config.coalesce_us)),
+            frames: config.coalesce_frames,
+            vectors: Mutex::new(vectors),
+        }
+    }
+
+    /// Account a queue interrupt on `vector`. Return whether it should be delivered now, otherwise
+    /// the delay left before the pending interrupts must be flushed if no timer is armed yet.
+    fn account(&self, vector: u16) -> (bool, Option<Duration>) {
+        let mut vectors = self.vectors.lock().unwrap();
+        let state = match vectors.get_mut(vector as usize) {
+            Some(state) => state,
+            None => return (true, None),
+        };
+
+        let now = Instant::now();
+        state.pending += 1;
+        let elapsed = state
+            .last_fire
+            .map_or(self.delay, |last| now.saturating_duration_since(last));
+        if elapsed >= self.delay || (self.frames != 0 && state.pending >= self.frames) {
+            state.pending = 0;
+            state.last_fire = Some(now);
+            return (true, None);
+        }
+        if state.timer_armed {
+            return (false, None);
+        }
+        state.timer_armed = true;
+        (false, Some(self.delay - elapsed))
+    }
+
+    /// Called by the flush timer of `vector`, return whether there are pending interrupts.
+    fn flush(&self, vector: u16) -> bool {
+        let mut vectors = self.vectors.lock().unwrap();
+        let state = &mut vectors[vector as usize];
+        state.timer_armed = false;
+        if state.pending == 0 {
+            return false;
+        }
+        state.pending = 0;
+        state.last_fire = Some(Instant::now());
+        true
+    }
+
+    fn reset(&self) {
+        for state in self.vectors.lock().unwrap().iter_mut() {
+            state.pending = 0;
+            state.last_fire = None;
+        }
+    }
+}
+
 /// Virtio-PCI device structure
 #[derive(Clone)]
 pub struct VirtioPciDevice {
@@ -311,6 +390,12 @@ pub struct VirtioPciDevice {
     multi_func: bool,
     /// If the device need to register irqfd to kvm.
     need_irqfd: bool,
+    /// Option ROM image exposed by the expansion ROM BAR.
+    romfile: Option<String>,
+    /// MSI-X vectors number and interrupt moderation settings.
+    msix_config: VirtioPciMsixConfig,
+    /// Queue interrupts moderation, only when `irq-coalesce-us` is set.
+    irq_moderation: Option<Arc<IrqModeration>>,
 }
 
 impl VirtioPciDevice {
@@ -338,6 +423,9 @@ impl VirtioPciDevice {
             interrupt_cb: None,
             multi_func,
             need_irqfd: false,
+            romfile: None,
+            msix_config: VirtioPciMsixConfig::default(),
+            irq_moderation: None,
         }
     }
 
@@ -345,6 +433,14 @@ impl VirtioPciDevice {
         self.need_irqfd = true;
     }
 
+    pub fn set_romfile(&mut self, romfile: &str) {
+        self.romfile = Some(romfile.to_string());
+    }
+
+    pub fn set_msix_config(&mut self, msix_config: &VirtioPciMsixConfig) {
+        self.msix_config = *msix_config;
+    }
+
     fn assign_interrupt_cb(&mut self) {
         let locked_dev = self.device.lock().unwrap();
         let virtio_base = locked_dev.virtio_base();
@@ -356,6 +452,7 @@ impl VirtioPciDevice {
         let cloned_msix = self.base.config.msix.as_ref().unwrap().clone();
         let cloned_intx = self.base.config.intx.as_ref().unwrap().clone();
         let dev_id = self.dev_id.clone();
+        let irq_moderation = self.irq_moderation.clone();
 
         let cb = Arc::new(Box::new(
             move |int_type: &VirtioInterruptType, queue: Option<&Queue>, needs_reset: bool| {
@@ -379,12 +476,46 @@ impl VirtioPciDevice {
                     }
                     VirtioInterruptType::Vring => {
                         interrupt_status.fetch_or(VIRTIO_MMIO_INT_VRING, Ordering::SeqCst);
+                        if let Some(q) = queue {
+                            q.vring.get_stats().record_interrupt();
+                        }
                         queue.map_or(0, |q| q.vring.get_queue_config().vector)
                     }
                 };
 
                 let mut locked_msix = cloned_msix.lock().unwrap();
                 if locked_msix.enabled {
+                    if let (VirtioInterruptType::Vring, Some(moderation)) =
+                        (int_type, irq_moderation.as_ref())
+                    {
+                        let (fire, flush_delay) = moderation.account(vector);
+                        if let Some(delay) = flush_delay {
+                            match EventLoop::get_ctx(None) {
+                                Some(ctx) => {
+                                    let msix = cloned_msix.clone();
+                                    let dev_id = dev_id.clone();
+                                    let moderation = moderation.clone();
+                                    let flush = Box::new(move || {
+                                        // Lock msix before the moderation state, as the
+                                        // interrupt callback does.
+                                        let mut locked_msix = msix.lock().unwrap();
+                                        if moderation.flush(vector) && locked_msix.enabled {
+                                            locked_msix
+                                                .notify(vector, dev_id.load(Ordering::Acquire));
+                                        }
+                                    });
+                                    ctx.timer_add(flush, delay);
+                                }
+                                None => {
+                                    moderation.flush(vector);
+                                    locked_msix.notify(vector, dev_id.load(Ordering::Acquire));
+                                }
+                            }
+                        }
+                        if !fire {
+                            return Ok(());
+                        }
+                    }
                     locked_msix.notify(vector, dev_id.load(Ordering::Acquire));
                 } else {
                     cloned_intx.lock().unwrap().notify(1);
@@ -517,6 +648,9 @@ impl VirtioPciDevice {
         if let Some(msix) = &self.base.config.msix {
             msix.lock().unwrap().clear_pending_vectors();
         }
+        if let Some(moderation) = &self.irq_moderation {
+            moderation.reset();
+        }
 
         true
     }
@@ -1082,7 +1216,15 @@ impl PciDevOps for VirtioPciDevice {
             !0,
         )?;
 
-        let nvectors = self.device.lock().unwrap().queue_num() + 1;
+        // Vectors beyond one per queue plus the config one will never be used.
+        let max_vectors = self.device.lock().unwrap().queue_num() + 1;
+        let nvectors = self
+            .msix_config
+            .vectors
+            .map_or(max_vectors, |vectors| min(vectors as usize, max_vectors));
+        if self.msix_config.coalesce_us != 0 {
+            self.irq_moderation = Some(Arc::new(IrqModeration::new(&self.msix_config, nvectors)));
+        }
         init_msix(
             VIRTIO_PCI_MSIX_BAR_IDX as usize,
             nvectors as u32,
@@ -1106,6 +1248,10 @@ impl PciDevOps for VirtioPciDevice {
             init_gpu_bar0(&mut self.base.config)?;
         }
 
+        if let Some(romfile) = self.romfile.as_ref() {
+            init_rom(romfile, &mut self.base.config, &self.base.base.id)?;
+        }
+
         self.device
             .lock()
             .unwrap()
@@ -1219,6 +1365,15 @@ impl PciDevOps for VirtioPciDevice {
                 dev_path.push_str("/disk@0,0");
                 Some(dev_path)
             }
+            VIRTIO_TYPE_NET => {
+                // Eg: OpenFirmware device path(virtio-net):
+                // /pci@i0cf8/ethernet@3[,1]/ethernet-phy@0
+                let parent_dev_path = self.get_parent_dev_path(parent_bus);
+                let mut dev_path =
+                    self.populate_dev_path(parent_dev_path, self.base.devfn, "/ethernet@");
+                dev_path.push_str("/ethernet-phy@0");
+                Some(dev_path)
+            }
             VIRTIO_TYPE_SCSI => {
                 // The virtio scsi controller can not set boot order, which is set for scsi device.
                 // All the scsi devices in the same scsi controller have the same boot path prefix
@@ -1800,4 +1955,33 @@ mod tests {
             le_read_u16(&virtio_pci.base.config.config, HEADER_TYPE as usize).unwrap();
         assert_eq!(header_type, HEADER_TYPE_MULTIFUNC as u16);
     }
+
+    #[test]
+    fn test_irq_moderation() {
+        let config = VirtioPciMsixConfig {
+            vectors: None,
+            coalesce_us: 100_000,
+            coalesce_frames: 3,
+        };
+        let moderation = IrqModeration::new(&config, 2);
+
+        // The first interrupt is delivered at once, the next ones wait for the flush timer.
+        assert_eq!(moderation.account(0), (true, None));
+        let (fire, delay) = moderation.account(0);
+        assert!(!fire);
+        assert!(delay.unwrap() <= Duration::from_micros(100_000));
+        assert_eq!(moderation.account(0), (false, None));
+        // Vectors are moderated separately.
+        assert_eq!(moderation.account(1), (true, None));
+        // Frames limit reached.
+        assert_eq!(moderation.account(0), (true, None));
+        assert!(!moderation.flush(0));
+
+        let (fire, delay) = moderation.account(0);
+        assert!(!fire && delay.is_some());
+        assert!(moderation.flush(0));
+
+        // Unknown vector is not moderated.
+        assert_eq!(moderation.account(INVALID_VECTOR_NUM), (true, None));
+    }
 }
@@ -13,28 +13,33 @@
 mod net;
 mod vsock;
 
-pub use net::Net;
+pub use net::{Net, VhostNetState};
 pub use vsock::{Vsock, VsockState};
 
 use std::fs::{File, OpenOptions};
-use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use log::debug;
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::{ioctl, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref};
 use vmm_sys_util::{ioctl_io_nr, ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr, ioctl_iowr_nr};
 
 use super::super::QueueConfig;
-use super::VhostOps;
+use super::{VhostDirtyLog, VhostOps};
 use crate::VirtioError;
 use address_space::{
     AddressSpace, FlatRange, GuestAddress, Listener, ListenerReqType, RegionIoEventFd, RegionType,
 };
 use util::byte_code::ByteCode;
 
+/// Misc devices of vhost, whose minor numbers are fixed, used to check the passed fd if
+/// the device node isn't accessible.
+const VHOST_MISC_DEVICES: [(&str, u32); 2] = [("/dev/vhost-net", 238), ("/dev/vhost-vsock", 241)];
+const MISC_MAJOR: u32 = 10;
+
 /// Refer to VHOST_VIRTIO in
 /// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost.h.
 const VHOST: u32 = 0xaf;
@@ -43,12 +48,19 @@ ioctl_iow_nr!(VHOST_SET_FEATURES, VHOST, 0x00, u64);
 ioctl_io_nr!(VHOST_SET_OWNER, VHOST, 0x01);
 ioctl_io_nr!(VHOST_RESET_OWNER, VHOST, 0x02);
 ioctl_iow_nr!(VHOST_SET_MEM_TABLE, VHOST, 0x03, VhostMemory);
+ioctl_iow_nr!(VHOST_SET_LOG_BASE, VHOST, 0x04, u64);
 ioctl_iow_nr!(VHOST_SET_VRING_NUM, VHOST, 0x10, VhostVringState);
 ioctl_iow_nr!(VHOST_SET_VRING_ADDR, VHOST, 0x11, VhostVringAddr);
 ioctl_iow_nr!(VHOST_SET_VRING_BASE, VHOST, 0x12, VhostVringState);
 ioctl_iowr_nr!(VHOST_GET_VRING_BASE, VHOST, 0x12, VhostVringState);
 ioctl_iow_nr!(VHOST_SET_VRING_KICK, VHOST, 0x20, VhostVringFile);
 ioctl_iow_nr!(VHOST_SET_VRING_CALL, VHOST, 0x21, VhostVringFile);
+ioctl_iow_nr!(
+    VHOST_SET_VRING_BUSYLOOP_TIMEOUT,
+    VHOST,
+    0x23,
+    VhostVringState
+);
 ioctl_iow_nr!(VHOST_NET_SET_BACKEND, VHOST, 0x30, VhostVringFile);
 ioctl_iow_nr!(VHOST_VSOCK_SET_GUEST_CID, VHOST, 0x60, u64);
 ioctl_iow_nr!(VHOST_VSOCK_SET_RUNNING, VHOST, 0x61, i32);
@@ -68,30 +80,30 @@ pub struct VhostVringFile {
 /// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost.h.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct VhostVringState {
+pub(crate) struct VhostVringState {
     /// Vring index.
-    index: u32,
+    pub(crate) index: u32,
     /// Vring size.
-    num: u32,
+    pub(crate) num: u32,
 }
 
 /// Refer to vhost_vring_addr in
 /// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost.h.
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct VhostVringAddr {
+pub(crate) struct VhostVringAddr {
     /// Vring index.
-    index: u32,
+    pub(crate) index: u32,
     /// Option flags.
-    flags: u32,
+    pub(crate) flags: u32,
     /// Base address of descriptor table.
-    desc_user_addr: u64,
+    pub(crate) desc_user_addr: u64,
     /// Base address of used vring.
-    used_user_addr: u64,
+    pub(crate) used_user_addr: u64,
     /// Base address of available vring.
-    avail_user_addr: u64,
+    pub(crate) avail_user_addr: u64,
     /// Address where to write logs.
-    log_guest_addr: u64,
+    pub(crate) log_guest_addr: u64,
 }
 
 /// Refer to vhost_memory_region in
@@ -240,7 +252,13 @@ impl VhostBackend {
         rawfd: Option<RawFd>,
     ) -> Result<VhostBackend> {
         let fd = match rawfd {
-            Some(rawfd) => unsafe { File::from_raw_fd(rawfd) },
+            Some(rawfd) => {
+                // SAFETY: the fd is passed by management and owned by the backend.
+                let file = unsafe { File::from_raw_fd(rawfd) };
+                check_vhost_fd(&file, path)
+                    .with_context(|| format!("Invalid vhost fd {} for {}", rawfd, path))?;
+                file
+            }
             None => OpenOptions::new()
                 .read(true)
                 .write(true)
@@ -255,6 +273,27 @@ impl VhostBackend {
     }
 }
 
+/// Check that the fd passed by management is opened from the vhost device at `path`.
+pub(crate) fn check_vhost_fd(file: &File, path: &str) -> Result<()> {
+    let meta = file
+        .metadata()
+        .with_context(|| "Failed to get metadata of fd")?;
+    if !meta.file_type().is_char_device() {
+        bail!("It's not a character device");
+    }
+    let expected = match std::fs::metadata(path) {
+        Ok(dev_meta) => dev_meta.rdev(),
+        Err(_) => match VHOST_MISC_DEVICES.iter().find(|(dev, _)| *dev == path) {
+            Some((_, minor)) => libc::makedev(MISC_MAJOR, *minor),
+            None => return Ok(()),
+        },
+    };
+    if meta.rdev() != expected {
+        bail!("It's not opened from {}", path);
+    }
+    Ok(())
+}
+
 impl AsRawFd for VhostBackend {
     fn as_raw_fd(&self) -> RawFd {
         self.fd.as_raw_fd()
@@ -380,7 +419,7 @@ impl VhostOps for VhostBackend {
             desc_user_addr,
             used_user_addr,
             avail_user_addr,
-            log_guest_addr: 0_u64,
+            log_guest_addr: queue_config.used_ring.raw_value(),
         };
 
         let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_ADDR(), &vring_addr) };
@@ -448,4 +487,17 @@ impl VhostOps for VhostBackend {
         }
         Ok(())
     }
+
+    fn set_log_base(&self, log: &VhostDirtyLog) -> Result<()> {
+        let log_base = log.hva();
+        // SAFETY: self is a valid vhost fd and the log is mapped until it is dropped,
+        // which is after logging is disabled.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_LOG_BASE(), &log_base) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_LOG_BASE".to_string()
+            )));
+        }
+        Ok(())
+    }
 }
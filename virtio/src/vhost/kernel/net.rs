@@ -18,8 +18,13 @@ use anyhow::{anyhow, bail, Context, Result};
 use vmm_sys_util::eventfd::EventFd;
 use vmm_sys_util::ioctl::ioctl_with_ref;
 
-use super::super::{VhostIoHandler, VhostNotify, VhostOps};
-use super::{VhostBackend, VhostVringFile, VHOST_NET_SET_BACKEND};
+use super::super::{
+    VhostDirtyLog, VhostIoHandler, VhostNotify, VhostOps, VHOST_F_LOG_ALL, VHOST_VRING_F_LOG,
+};
+use super::{
+    VhostBackend, VhostVringFile, VhostVringState, VHOST_NET_SET_BACKEND,
+    VHOST_SET_VRING_BUSYLOOP_TIMEOUT,
+};
 use crate::read_config_default;
 use crate::{
     device::net::{build_device_config_space, create_tap, CtrlInfo, MAC_ADDR_LEN},
@@ -31,8 +36,10 @@ use crate::{
     VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MQ, VIRTIO_TYPE_NET,
 };
 use address_space::AddressSpace;
-use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::config::{NetworkInterfaceConfig, MAX_VIRTIO_QUEUE};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 use util::tap::Tap;
@@ -52,6 +59,14 @@ trait VhostNetBackend {
     /// * `queue_index` - Index of the queue to modify.
     /// * `fd` - EventFd that will be signaled from guest.
     fn set_backend(&self, queue_index: usize, fd: RawFd) -> Result<()>;
+
+    /// Set the time that vhost-net busy polls the virtqueue and the tap device before
+    /// sleeping.
+    ///
+    /// # Arguments
+    /// * `queue_index` - Index of the queue to modify.
+    /// * `timeout_us` - Busy polling timeout in microseconds, 0 disables busy polling.
+    fn set_vring_busyloop_timeout(&self, queue_index: usize, timeout_us: u32) -> Result<()>;
 }
 
 impl VhostNetBackend for VhostBackend {
@@ -70,6 +85,39 @@ impl VhostNetBackend for VhostBackend {
         }
         Ok(())
     }
+
+    fn set_vring_busyloop_timeout(&self, queue_index: usize, timeout_us: u32) -> Result<()> {
+        let vring_state = VhostVringState {
+            index: queue_index as u32,
+            num: timeout_us,
+        };
+
+        // SAFETY: self is a valid vhost fd and vring_state is a valid vhost_vring_state.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_BUSYLOOP_TIMEOUT(), &vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_BUSYLOOP_TIMEOUT".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// State of vhost-kernel network device.
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct VhostNetState {
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Virtio net configurations.
+    config_space: VirtioNetConfig,
+    /// Last avail idx of each queue in vhost-net backend.
+    last_avail_idx: [u16; MAX_VIRTIO_QUEUE],
+    /// Device broken status.
+    broken: bool,
 }
 
 /// Network device structure.
@@ -90,6 +138,12 @@ pub struct Net {
     mem_space: Arc<AddressSpace>,
     /// Save irqfd used for vhost-net.
     call_events: Vec<Arc<EventFd>>,
+    /// Last avail idx of each queue in vhost-net backend, restored by migration.
+    last_avail_idx: [u16; MAX_VIRTIO_QUEUE],
+    /// Whether the vrings are stopped by migration.
+    paused: bool,
+    /// Dirty log of guest memory written by vhost-net during migration.
+    dirty_log: Option<VhostDirtyLog>,
 }
 
 impl Net {
@@ -110,7 +164,115 @@ impl Net {
             vhost_features: 0_u64,
             mem_space: mem_space.clone(),
             call_events: Vec::new(),
+            last_avail_idx: [0; MAX_VIRTIO_QUEUE],
+            paused: false,
+            dirty_log: None,
+        }
+    }
+
+    /// Get the vhost features and vring flags, which enable logging dirty memory
+    /// if dirty log is started.
+    fn log_features(&self) -> (u64, u32) {
+        match self.dirty_log {
+            Some(_) => (
+                self.vhost_features | 1 << VHOST_F_LOG_ALL,
+                1 << VHOST_VRING_F_LOG,
+            ),
+            None => (self.vhost_features, 0),
+        }
+    }
+
+    /// Enable or disable logging dirty memory in the running vhost-net backends.
+    fn update_dirty_log(&self) -> Result<()> {
+        let backends = self
+            .backends
+            .as_ref()
+            .with_context(|| "Failed to get backend for vhost net")?;
+        let (features, flags) = self.log_features();
+        let queue_pairs = self.base.queues.len() / 2;
+        for (index, backend) in backends.iter().enumerate().take(queue_pairs) {
+            if let Some(log) = &self.dirty_log {
+                backend
+                    .set_log_base(log)
+                    .with_context(|| "Failed to set log base for vhost net")?;
+            }
+            backend
+                .set_features(features)
+                .with_context(|| "Failed to set features for vhost net")?;
+            for queue_index in 0..2 {
+                let queue_config = self.base.queues[index * 2 + queue_index]
+                    .lock()
+                    .unwrap()
+                    .vring
+                    .get_queue_config();
+                backend
+                    .set_vring_addr(&queue_config, queue_index, flags)
+                    .with_context(|| {
+                        format!(
+                            "Failed to set vring addr for vhost net, index: {}",
+                            index * 2 + queue_index,
+                        )
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Attach or detach the tap devices of all vrings. Detaching the tap device makes
+    /// vhost-net stop the vring and finish all in-flight descriptors.
+    fn set_backends_running(&self, running: bool) -> Result<()> {
+        let backends = self
+            .backends
+            .as_ref()
+            .with_context(|| "Failed to get backend for vhost net")?;
+        let taps = self
+            .taps
+            .as_ref()
+            .with_context(|| "Failed to get tap for vhost net")?;
+        let queue_pairs = self.base.queues.len() / 2;
+        for (index, backend) in backends.iter().enumerate().take(queue_pairs) {
+            let fd = if running {
+                taps[index].file.as_raw_fd()
+            } else {
+                -1
+            };
+            // 2 queues: rx and tx.
+            for queue_index in 0..2 {
+                backend.set_backend(queue_index, fd).with_context(|| {
+                    format!(
+                        "Failed to set tap device for vhost net, index: {}",
+                        index * 2 + queue_index,
+                    )
+                })?;
+            }
         }
+
+        Ok(())
+    }
+
+    /// Get the last avail idx of each vring from vhost-net backend. The vrings
+    /// should be stopped before calling this function.
+    fn get_last_avail_idx(&self) -> Result<[u16; MAX_VIRTIO_QUEUE]> {
+        let mut last_avail_idx = [0_u16; MAX_VIRTIO_QUEUE];
+        let backends = self
+            .backends
+            .as_ref()
+            .with_context(|| "Failed to get backend for vhost net")?;
+        let queue_pairs = self.base.queues.len() / 2;
+        for (index, backend) in backends.iter().enumerate().take(queue_pairs) {
+            for queue_index in 0..2 {
+                last_avail_idx[index * 2 + queue_index] =
+                    backend.get_vring_base(queue_index).with_context(|| {
+                        format!(
+                            "Failed to get vring base for vhost net, index: {}",
+                            index * 2 + queue_index,
+                        )
+                    })?;
+            }
+        }
+
+        Ok(last_avail_idx)
     }
 }
 
@@ -161,6 +323,8 @@ impl VirtioDevice for Net {
             .with_context(|| "Failed to get features for vhost net")?;
         vhost_features &= !(1_u64 << VHOST_NET_F_VIRTIO_NET_HDR);
         vhost_features &= !(1_u64 << VIRTIO_F_ACCESS_PLATFORM);
+        // Dirty memory is only logged during migration.
+        vhost_features &= !(1_u64 << VHOST_F_LOG_ALL);
 
         let mut device_features = vhost_features;
         device_features |= 1 << VIRTIO_F_VERSION_1
@@ -194,6 +358,7 @@ impl VirtioDevice for Net {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(VhostNetState::descriptor(), &self.net_cfg.id);
         Ok(())
     }
 
@@ -270,8 +435,14 @@ impl VirtioDevice for Net {
                     .with_context(|| format!("Failed to get index {} vhost backend", index))?,
             };
 
+            let (features, vring_flags) = self.log_features();
+            if let Some(log) = &self.dirty_log {
+                backend
+                    .set_log_base(log)
+                    .with_context(|| "Failed to set log base for vhost net")?;
+            }
             backend
-                .set_features(self.vhost_features)
+                .set_features(features)
                 .with_context(|| "Failed to set features for vhost net")?;
             backend
                 .set_mem_table()
@@ -292,19 +463,21 @@ impl VirtioDevice for Net {
                         )
                     })?;
                 backend
-                    .set_vring_addr(&queue_config, queue_index, 0)
+                    .set_vring_addr(&queue_config, queue_index, vring_flags)
                     .with_context(|| {
                         format!(
                             "Failed to set vring addr for vhost net, index: {}",
                             queue_index,
                         )
                     })?;
-                backend.set_vring_base(queue_index, 0).with_context(|| {
-                    format!(
-                        "Failed to set vring base for vhost net, index: {}",
-                        queue_index,
-                    )
-                })?;
+                backend
+                    .set_vring_base(queue_index, self.last_avail_idx[index * 2 + queue_index])
+                    .with_context(|| {
+                        format!(
+                            "Failed to set vring base for vhost net, index: {}",
+                            queue_index,
+                        )
+                    })?;
                 backend
                     .set_vring_kick(queue_index, queue_evts[index * 2 + queue_index].clone())
                     .with_context(|| {
@@ -313,6 +486,16 @@ impl VirtioDevice for Net {
                             index * 2 + queue_index,
                         )
                     })?;
+                if self.net_cfg.poll_us != 0 {
+                    backend
+                        .set_vring_busyloop_timeout(queue_index, self.net_cfg.poll_us)
+                        .with_context(|| {
+                            format!(
+                                "Failed to set vring busyloop timeout for vhost net, index: {}",
+                                index * 2 + queue_index,
+                            )
+                        })?;
+                }
 
                 drop(queue);
 
@@ -398,18 +581,112 @@ impl VirtioDevice for Net {
                 backend.set_backend(queue_index, -1)?;
             }
         }
+        self.last_avail_idx = [0; MAX_VIRTIO_QUEUE];
+        self.paused = false;
 
         Ok(())
     }
 }
 
+impl StateTransfer for Net {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        // The ring indexes are synced when vhost-net is stopped by `pause`.
+        if self.device_activated() && !self.paused {
+            bail!("Vhost net {} is still running", self.net_cfg.id);
+        }
+
+        let state = VhostNetState {
+            device_features: self.base.device_features,
+            driver_features: self.base.driver_features,
+            config_space: *self.config_space.lock().unwrap(),
+            last_avail_idx: self.last_avail_idx,
+            broken: self.base.broken.load(Ordering::SeqCst),
+        };
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = VhostNetState::from_bytes(state)
+            .with_context(|| migration::error::MigrationError::FromBytesError("VHOST_NET"))?;
+        self.base.device_features = state.device_features;
+        self.base.driver_features = state.driver_features;
+        self.base.broken.store(state.broken, Ordering::SeqCst);
+        *self.config_space.lock().unwrap() = state.config_space;
+        self.last_avail_idx = state.last_avail_idx;
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&VhostNetState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Net {
+    fn pause(&mut self) -> migration::Result<()> {
+        if !self.device_activated() || self.paused {
+            return Ok(());
+        }
+
+        self.set_backends_running(false)
+            .with_context(|| "Failed to stop vhost net backend")?;
+        self.last_avail_idx = self.get_last_avail_idx()?;
+        self.paused = true;
+
+        Ok(())
+    }
+
+    fn restart(&mut self) -> migration::Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+
+        self.set_backends_running(true)
+            .with_context(|| "Failed to restart vhost net backend")?;
+        self.paused = false;
+
+        Ok(())
+    }
+
+    fn start_dirty_log(&mut self) -> migration::Result<()> {
+        self.dirty_log = Some(VhostDirtyLog::new(&self.mem_space)?);
+        if self.device_activated() {
+            self.update_dirty_log()
+                .with_context(|| "Failed to start dirty log of vhost net")?;
+        }
+
+        Ok(())
+    }
+
+    fn stop_dirty_log(&mut self) -> migration::Result<()> {
+        if self.dirty_log.is_none() {
+            return Ok(());
+        }
+
+        let log = self.dirty_log.take();
+        if self.device_activated() {
+            self.update_dirty_log()
+                .with_context(|| "Failed to stop dirty log of vhost net")?;
+        }
+        drop(log);
+
+        Ok(())
+    }
+
+    fn sync_dirty_log(&self, mark: &mut dyn FnMut(u64, u64)) {
+        if let Some(log) = &self.dirty_log {
+            log.sync(mark);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
 
     use super::*;
     use address_space::*;
-    use machine_manager::config::DEFAULT_VIRTQUEUE_SIZE;
+    use machine_manager::config::{DEFAULT_NOTIFY_BATCH, DEFAULT_VIRTQUEUE_SIZE};
 
     const SYSTEM_SPACE_SIZE: u64 = (1024 * 1024) as u64;
 
@@ -448,10 +725,16 @@ mod tests {
             tap_fds: Some(vec![4]),
             vhost_fds: Some(vec![5]),
             iothread: None,
+            queue_iothreads: Vec::new(),
             queues: 2,
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            boot_index: None,
+            romfile: None,
+            io_uring: false,
+            poll_us: 0,
+            notify_batch: DEFAULT_NOTIFY_BATCH,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -470,10 +753,16 @@ mod tests {
             tap_fds: None,
             vhost_fds: None,
             iothread: None,
+            queue_iothreads: Vec::new(),
             queues: 2,
             mq: false,
             socket_path: None,
             queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            boot_index: None,
+            romfile: None,
+            io_uring: false,
+            poll_us: 0,
+            notify_batch: DEFAULT_NOTIFY_BATCH,
         };
         let conf = vec![net1];
         let confs = Some(conf);
@@ -528,4 +817,73 @@ mod tests {
         let mut read_data: Vec<u8> = vec![0; len as usize];
         assert_eq!(vhost_net.read_config(offset, &mut read_data).is_ok(), false);
     }
+    #[test]
+    fn test_vhost_net_state() {
+        let net_cfg = NetworkInterfaceConfig {
+            id: "eth0".to_string(),
+            host_dev_name: "".to_string(),
+            mac: Some("1A:2B:3C:4D:5E:6F".to_string()),
+            vhost_type: Some("vhost-kernel".to_string()),
+            tap_fds: None,
+            vhost_fds: None,
+            iothread: None,
+            queue_iothreads: Vec::new(),
+            queues: 2,
+            mq: false,
+            socket_path: None,
+            queue_size: DEFAULT_VIRTQUEUE_SIZE,
+            boot_index: None,
+            romfile: None,
+            io_uring: false,
+            poll_us: 0,
+            notify_batch: DEFAULT_NOTIFY_BATCH,
+        };
+        let vhost_net_space = vhost_address_space_init();
+        let mut src_net = Net::new(&net_cfg, &vhost_net_space);
+        src_net.base.device_features = 0x1234;
+        src_net.base.driver_features = 0x34;
+        src_net.last_avail_idx[0] = 10;
+        src_net.last_avail_idx[1] = 20;
+
+        // The device is not activated, ring indexes are taken from the device itself.
+        let state = src_net.get_state_vec().unwrap();
+        let mut dst_net = Net::new(&net_cfg, &vhost_net_space);
+        assert!(dst_net.set_state_mut(&state).is_ok());
+        assert_eq!(dst_net.base.device_features, 0x1234);
+        assert_eq!(dst_net.base.driver_features, 0x34);
+        assert_eq!(dst_net.last_avail_idx[0], 10);
+        assert_eq!(dst_net.last_avail_idx[1], 20);
+
+        assert!(dst_net.set_state_mut(&state[..state.len() - 1]).is_err());
+
+        // Pausing the device which is not activated changes nothing.
+        assert!(src_net.pause().is_ok());
+        assert!(!src_net.paused);
+        assert!(src_net.restart().is_ok());
+    }
+
+    #[test]
+    fn test_vhost_dirty_log() {
+        let vhost_net_space = vhost_address_space_init();
+        let log = VhostDirtyLog::new(&vhost_net_space).unwrap();
+        // One bit for each 4K page.
+        assert_eq!(log.size(), (SYSTEM_SPACE_SIZE / 0x1000 / 64 + 1) * 8);
+
+        // SAFETY: the log is mapped with its size, which is larger than 2 words.
+        let words = unsafe { std::slice::from_raw_parts_mut(log.hva() as *mut u64, 2) };
+        words[0] = 1 << 3;
+        words[1] = 1 << 0 | 1 << 63;
+
+        let mut dirty = Vec::new();
+        log.sync(&mut |gpa, len| dirty.push((gpa, len)));
+        assert_eq!(
+            dirty,
+            vec![(0x3000, 0x1000), (0x40000, 0x1000), (0x7f000, 0x1000)]
+        );
+
+        // The log is cleared after sync.
+        dirty.clear();
+        log.sync(&mut |gpa, len| dirty.push((gpa, len)));
+        assert!(dirty.is_empty());
+    }
 }
@@ -12,21 +12,32 @@
 
 pub mod kernel;
 pub mod user;
+pub mod vdpa;
 
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::fs::File;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::rc::Rc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use log::error;
 use vmm_sys_util::epoll::EventSet;
 use vmm_sys_util::eventfd::EventFd;
 
 use super::{Queue, QueueConfig, VirtioInterrupt, VirtioInterruptType};
+use address_space::AddressSpace;
 use util::loop_context::{
     read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
 };
+use util::unix::do_mmap;
+
+/// Vhost feature to log all writes to guest memory into the dirty log.
+pub const VHOST_F_LOG_ALL: u32 = 26;
+/// Vring flag to log writes to the used ring into the dirty log.
+pub const VHOST_VRING_F_LOG: u32 = 0;
+/// Size of guest memory that one bit of vhost dirty log covers.
+const VHOST_LOG_PAGE: u64 = 0x1000;
 
 /// Vhost vring call notify structure.
 pub struct VhostNotify {
@@ -36,6 +47,90 @@ pub struct VhostNotify {
     pub queue: Arc<Mutex<Queue>>,
 }
 
+/// Dirty log shared with vhost backends, which sets one bit for each page of guest
+/// memory it writes. It is backed by memfd so that vhost-user backends can map it.
+pub struct VhostDirtyLog {
+    /// The memfd which backs the log.
+    file: File,
+    /// Host address where the log is mapped.
+    hva: u64,
+    /// Size of the log in bytes.
+    size: u64,
+}
+
+impl VhostDirtyLog {
+    /// Create a dirty log which covers all Ram regions of `mem_space`.
+    pub fn new(mem_space: &AddressSpace) -> Result<Self> {
+        let pages = mem_space.memory_end_address().raw_value() / VHOST_LOG_PAGE;
+        let size = (pages / 64 + 1) * std::mem::size_of::<u64>() as u64;
+
+        let name = b"stratovirt_vhost_log\0";
+        // SAFETY: the name is a nul-terminated string and the return value is checked.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                name.as_ptr() as *const libc::c_char,
+                libc::MFD_CLOEXEC,
+            )
+        } as RawFd;
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| "Failed to create memfd for vhost dirty log");
+        }
+        // SAFETY: fd is created above and owned by the file.
+        let file = unsafe { File::from_raw_fd(fd) };
+        file.set_len(size)
+            .with_context(|| "Failed to set the length of vhost dirty log")?;
+        let hva = do_mmap(&Some(&file), size, 0, false, true, false)
+            .with_context(|| "Failed to map vhost dirty log")?;
+
+        Ok(VhostDirtyLog { file, hva, size })
+    }
+
+    /// Host address of the log, which is used by vhost-kernel backends.
+    pub fn hva(&self) -> u64 {
+        self.hva
+    }
+
+    /// Size of the log in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Collect and clear the dirty pages, `mark` is called with the guest address
+    /// and length of each dirty page.
+    pub fn sync(&self, mark: &mut dyn FnMut(u64, u64)) {
+        let words = (self.size / std::mem::size_of::<u64>() as u64) as usize;
+        // SAFETY: the log is mapped with the size, and the backends only set bits of it
+        // atomically.
+        let log = unsafe { std::slice::from_raw_parts(self.hva as *const AtomicU64, words) };
+        for (index, word) in log.iter().enumerate() {
+            if word.load(Ordering::Relaxed) == 0 {
+                continue;
+            }
+            let mut bits = word.swap(0, Ordering::AcqRel);
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as u64;
+                mark((index as u64 * 64 + bit) * VHOST_LOG_PAGE, VHOST_LOG_PAGE);
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+impl AsRawFd for VhostDirtyLog {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for VhostDirtyLog {
+    fn drop(&mut self) {
+        // SAFETY: the log is mapped by `new` with the size.
+        unsafe { libc::munmap(self.hva as *mut libc::c_void, self.size as libc::size_t) };
+    }
+}
+
 pub trait VhostOps {
     /// Set the current process as the (exclusive) owner of file descriptor
     /// of the vhost backend. This must be run before any other vhost commands.
@@ -107,6 +202,15 @@ pub trait VhostOps {
     fn set_vring_enable(&self, _queue_idx: usize, _status: bool) -> Result<()> {
         Ok(())
     }
+
+    /// Set the dirty log where the backend logs writes to guest memory once
+    /// `VHOST_F_LOG_ALL` is set.
+    ///
+    /// # Arguments
+    /// * `_log` - The dirty log.
+    fn set_log_base(&self, _log: &VhostDirtyLog) -> Result<()> {
+        bail!("Dirty log is not supported by the vhost backend")
+    }
 }
 
 pub struct VhostIoHandler {
@@ -22,7 +22,7 @@ use anyhow::{bail, Context, Result};
 use log::{error, info, warn};
 use vmm_sys_util::{epoll::EventSet, eventfd::EventFd};
 
-use super::super::VhostOps;
+use super::super::{VhostDirtyLog, VhostOps, VHOST_F_LOG_ALL, VHOST_VRING_F_LOG};
 use super::message::{
     RegionMemInfo, VhostUserHdrFlag, VhostUserMemContext, VhostUserMemHdr, VhostUserMsgHdr,
     VhostUserMsgReq, VhostUserVringAddr, VhostUserVringState,
@@ -42,6 +42,8 @@ use util::unix::do_mmap;
 
 /// Vhost supports multiple queue
 pub const VHOST_USER_PROTOCOL_F_MQ: u8 = 0;
+/// Vhost supports logging dirty memory to the log shared by `VHOST_USER_SET_LOG_BASE` msg.
+pub const VHOST_USER_PROTOCOL_F_LOG_SHMFD: u8 = 1;
 /// Vhost supports `VHOST_USER_SET_CONFIG` and `VHOST_USER_GET_CONFIG` msg.
 pub const VHOST_USER_PROTOCOL_F_CONFIG: u8 = 9;
 /// Vhost supports `VHOST_USER_SET_INFLIGHT_FD` and `VHOST_USER_GET_INFLIGHT_FD` msg.
@@ -346,6 +348,16 @@ pub struct VhostUserInflight {
     pub queue_size: u16,
 }
 
+/// Struct for set log base request, field is defined by dpdk.
+#[repr(C)]
+#[derive(Debug, Default, Clone)]
+pub struct VhostUserLog {
+    // The size of dirty log.
+    pub mmap_size: u64,
+    // The offset from the start of the supplied file descriptor.
+    pub mmap_offset: u64,
+}
+
 /// Struct for saving inflight info, create this struct to save inflight info when
 /// vhost client start, use this struct to set inflight fd when vhost client reconnect.
 #[derive(Debug)]
@@ -362,6 +374,7 @@ pub enum VhostBackendType {
     TypeNet,
     TypeBlock,
     TypeFs,
+    TypeScsi,
 }
 
 impl ToString for VhostBackendType {
@@ -370,6 +383,7 @@ impl ToString for VhostBackendType {
             VhostBackendType::TypeNet => String::from("net"),
             VhostBackendType::TypeBlock => String::from("block"),
             VhostBackendType::TypeFs => String::from("fs"),
+            VhostBackendType::TypeScsi => String::from("scsi"),
         }
     }
 }
@@ -388,6 +402,11 @@ pub struct VhostUserClient {
     inflight: Option<VhostInflight>,
     backend_type: VhostBackendType,
     pub protocol_features: u64,
+    /// Dirty log of guest memory written by the backend during migration.
+    pub dirty_log: Option<Arc<VhostDirtyLog>>,
+    /// Last avail index of each queue restored by migration, which is used instead
+    /// of the used index at the next activation.
+    pub last_avail_idx: Option<Vec<u16>>,
 }
 
 impl VhostUserClient {
@@ -424,6 +443,8 @@ impl VhostUserClient {
             inflight: None,
             backend_type,
             protocol_features: 0_u64,
+            dirty_log: None,
+            last_avail_idx: None,
         })
     }
 
@@ -484,8 +505,10 @@ impl VhostUserClient {
             let inflight = self.inflight.as_ref().unwrap();
             self.set_inflight_fd(inflight.inner.clone(), inflight.file.as_raw_fd())?;
         } else {
-            bail!(
-                "Failed to get inflight fd, spdk doesn't support, spdk protocol feature: {:#b}",
+            // The device still works without inflight fd, only the in-flight requests can not be
+            // resubmitted after the backend restarts.
+            warn!(
+                "Backend doesn't support inflight fd, protocol feature: {:#b}",
                 protocol_feature
             );
         }
@@ -497,12 +520,18 @@ impl VhostUserClient {
         self.set_owner()
             .with_context(|| "Failed to set owner for vhost-user")?;
 
-        self.set_features(self.features)
+        let (features, vring_flags) = self.log_features();
+        self.set_features(features)
             .with_context(|| "Failed to set features for vhost-user")?;
 
         self.set_mem_table()
             .with_context(|| "Failed to set mem table for vhost-user")?;
 
+        if let Some(log) = &self.dirty_log {
+            self.set_log_base(log)
+                .with_context(|| "Failed to set log base for vhost-user")?;
+        }
+
         let queue_size = self
             .queues
             .first()
@@ -525,6 +554,7 @@ impl VhostUserClient {
                 })?;
         }
 
+        let last_avail_idx = self.last_avail_idx.take();
         for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
             let queue = queue_mutex.lock().unwrap();
             if !queue.vring.is_enabled() {
@@ -533,7 +563,7 @@ impl VhostUserClient {
             }
 
             let queue_config = queue.vring.get_queue_config();
-            self.set_vring_addr(&queue_config, queue_index, 0)
+            self.set_vring_addr(&queue_config, queue_index, vring_flags)
                 .with_context(|| {
                     format!(
                         "Failed to set vring addr for vhost-user, index: {}",
@@ -542,7 +572,10 @@ impl VhostUserClient {
                 })?;
             // When spdk/ovs has been killed, stratovirt can not get the last avail
             // index in spdk/ovs, it can only use used index as last avail index.
-            let last_avail_idx = queue.vring.get_used_idx(&self.mem_space)?;
+            let last_avail_idx = match &last_avail_idx {
+                Some(idx) => idx[queue_index],
+                None => queue.vring.get_used_idx(&self.mem_space)?,
+            };
             self.set_vring_base(queue_index, last_avail_idx)
                 .with_context(|| {
                     format!(
@@ -566,10 +599,9 @@ impl VhostUserClient {
                 })?;
         }
 
-        if self.backend_type == VhostBackendType::TypeBlock {
+        if self.protocol_features_negotiated() {
             // If VHOST_USER_F_PROTOCOL_FEATURES has been negotiated, it should call
             // set_vring_enable to enable vring. Otherwise, the ring is enabled by default.
-            // Currently, only vhost-user-blk device support negotiate VHOST_USER_F_PROTOCOL_FEATURES.
             for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
                 if !queue_mutex.lock().unwrap().is_enabled() {
                     continue;
@@ -586,6 +618,51 @@ impl VhostUserClient {
         Ok(())
     }
 
+    /// Get the features and vring flags, which enable logging dirty memory if dirty
+    /// log is started.
+    fn log_features(&self) -> (u64, u32) {
+        match self.dirty_log {
+            Some(_) => (self.features | 1 << VHOST_F_LOG_ALL, 1 << VHOST_VRING_F_LOG),
+            None => (self.features, 0),
+        }
+    }
+
+    /// Enable or disable logging dirty memory in the running backend according to
+    /// `dirty_log`.
+    pub fn update_dirty_log(&self) -> Result<()> {
+        if let Some(log) = &self.dirty_log {
+            self.set_log_base(log)
+                .with_context(|| "Failed to set log base for vhost-user")?;
+        }
+        let (features, vring_flags) = self.log_features();
+        self.set_features(features)
+            .with_context(|| "Failed to set features for vhost-user")?;
+        for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
+            let queue = queue_mutex.lock().unwrap();
+            if !queue.vring.is_enabled() {
+                continue;
+            }
+            self.set_vring_addr(&queue.vring.get_queue_config(), queue_index, vring_flags)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring addr for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Currently, only vhost-user-blk and vhost-user-scsi devices support negotiating
+    /// VHOST_USER_F_PROTOCOL_FEATURES.
+    fn protocol_features_negotiated(&self) -> bool {
+        matches!(
+            self.backend_type,
+            VhostBackendType::TypeBlock | VhostBackendType::TypeScsi
+        )
+    }
+
     pub fn reset_vhost_user(&mut self) -> Result<()> {
         for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
             if !queue_mutex.lock().unwrap().vring.is_enabled() {
@@ -604,6 +681,63 @@ impl VhostUserClient {
         Ok(())
     }
 
+    /// Stop all enabled vrings and get their last avail index. The vhost-user backend
+    /// replies `GET_VRING_BASE` only after all in-flight descriptors are completed.
+    pub fn stop_vrings(&self) -> Result<Vec<u16>> {
+        let mut last_avail_idx = Vec::with_capacity(self.queues.len());
+        for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
+            if !queue_mutex.lock().unwrap().vring.is_enabled() {
+                last_avail_idx.push(0);
+                continue;
+            }
+            if self.protocol_features_negotiated() {
+                self.set_vring_enable(queue_index, false).with_context(|| {
+                    format!("Failed to set vring disable, index: {}", queue_index)
+                })?;
+            }
+            let idx = self
+                .get_vring_base(queue_index)
+                .with_context(|| format!("Failed to get vring base, index: {}", queue_index))?;
+            last_avail_idx.push(idx);
+        }
+
+        Ok(last_avail_idx)
+    }
+
+    /// Restart the vrings stopped by `stop_vrings` from the given last avail index.
+    pub fn restart_vrings(&self, last_avail_idx: &[u16]) -> Result<()> {
+        for (queue_index, queue_mutex) in self.queues.iter().enumerate() {
+            if !queue_mutex.lock().unwrap().vring.is_enabled() {
+                continue;
+            }
+            self.set_vring_base(queue_index, last_avail_idx[queue_index])
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring base for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            // The vring is started once the backend receives the kick fd.
+            self.set_vring_kick(queue_index, self.queue_evts[queue_index].clone())
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring kick for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            if self.protocol_features_negotiated() {
+                self.set_vring_enable(queue_index, true).with_context(|| {
+                    format!(
+                        "Failed to set vring enable for vhost-user, index: {}",
+                        queue_index,
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn add_event(client: &Arc<Mutex<Self>>) -> Result<()> {
         let notifiers = EventNotifierHelper::internal_notifiers(client.clone());
         register_event_helper(notifiers, None, &mut client.lock().unwrap().delete_evts)
@@ -803,7 +937,7 @@ impl VhostOps for VhostUserClient {
     fn set_mem_table(&self) -> Result<()> {
         let mem_regions = self.mem_info.regions.lock().unwrap();
         if mem_regions.is_empty() {
-            bail!("Failed to initial vhost user memory map, consider using command mem-share=on");
+            bail!("Failed to initial vhost user memory map, consider using command mem-share=on or share=on of memory backend");
         }
 
         let num_region = mem_regions.len();
@@ -898,7 +1032,7 @@ impl VhostOps for VhostUserClient {
             desc_user_addr,
             used_user_addr,
             avail_user_addr,
-            log_guest_addr: 0_u64,
+            log_guest_addr: queue.used_ring.raw_value(),
         };
         self.client
             .lock()
@@ -1012,6 +1146,53 @@ impl VhostOps for VhostUserClient {
         bail!("Does not support for resetting owner")
     }
 
+    fn set_log_base(&self, log: &VhostDirtyLog) -> Result<()> {
+        if !virtio_has_feature(
+            self.protocol_features,
+            VHOST_USER_PROTOCOL_F_LOG_SHMFD as u32,
+        ) {
+            bail!("Backend doesn't support sharing dirty log by fd");
+        }
+
+        let request = VhostUserMsgReq::SetLogBase as u32;
+        let hdr = VhostUserMsgHdr::new(
+            request,
+            VhostUserHdrFlag::NeedReply as u32,
+            size_of::<VhostUserLog>() as u32,
+        );
+        let log_info = VhostUserLog {
+            mmap_size: log.size(),
+            mmap_offset: 0,
+        };
+        let payload_opt: Option<&[u8]> = None;
+        let client = self.client.lock().unwrap();
+        client
+            .sock
+            .send_msg(Some(&hdr), Some(&log_info), payload_opt, &[log.as_raw_fd()])
+            .with_context(|| "Failed to send msg for setting log base")?;
+
+        // The backend replies after it maps the log. The reply carries no payload in
+        // dpdk, and a u64 in libvhost-user.
+        let mut reply = VhostUserMsgHdr::default();
+        let mut body = 0_u64;
+        let payload_opt: Option<&mut [u8]> = None;
+        let (recv_len, _) = client
+            .sock
+            .recv_msg(Some(&mut reply), Some(&mut body), payload_opt, &mut [])
+            .with_context(|| "Failed to recv ack msg for setting log base")?;
+        if reply.request != request || !reply.is_reply() || recv_len < size_of::<VhostUserMsgHdr>()
+        {
+            bail!(
+                "The ack msg is invalid, request: {}, header request: {}, reply type: {}",
+                request,
+                reply.request,
+                reply.is_reply()
+            );
+        }
+
+        Ok(())
+    }
+
     fn get_vring_base(&self, queue_idx: usize) -> Result<u16> {
         let request = VhostUserMsgReq::GetVringBase as u32;
         let hdr = VhostUserMsgHdr::new(
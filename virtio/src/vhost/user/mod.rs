@@ -16,6 +16,7 @@ mod block;
 mod client;
 mod message;
 mod net;
+mod scsi;
 mod sock;
 
 pub use self::client::*;
@@ -23,7 +24,8 @@ pub use self::fs::*;
 pub use self::message::*;
 pub use self::sock::*;
 pub use block::Block;
-pub use net::Net;
+pub use net::{Net, VhostUserNetState};
+pub use scsi::Scsi;
 
 use std::sync::{Arc, Mutex};
 
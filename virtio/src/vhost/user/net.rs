@@ -13,11 +13,14 @@
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use vmm_sys_util::eventfd::EventFd;
 
-use super::super::VhostOps;
-use super::{listen_guest_notifier, VhostBackendType, VhostUserClient};
+use super::super::{VhostDirtyLog, VhostOps, VHOST_F_LOG_ALL};
+use super::message::VHOST_USER_F_PROTOCOL_FEATURES;
+use super::{
+    listen_guest_notifier, VhostBackendType, VhostUserClient, VHOST_USER_PROTOCOL_F_LOG_SHMFD,
+};
 use crate::{
     device::net::{build_device_config_space, CtrlInfo, MAC_ADDR_LEN},
     read_config_default, virtio_has_feature, CtrlVirtio, NetCtrlHandler, VirtioBase, VirtioDevice,
@@ -28,14 +31,33 @@ use crate::{
     VIRTIO_NET_F_MRG_RXBUF, VIRTIO_TYPE_NET,
 };
 use address_space::AddressSpace;
-use machine_manager::config::NetworkInterfaceConfig;
+use machine_manager::config::{NetworkInterfaceConfig, MAX_VIRTIO_QUEUE};
 use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use migration::{DeviceStateDesc, FieldDesc, MigrationHook, MigrationManager, StateTransfer};
+use migration_derive::{ByteCode, Desc};
 use util::byte_code::ByteCode;
 use util::loop_context::EventNotifierHelper;
 
 /// Number of virtqueues.
 const QUEUE_NUM_NET: usize = 2;
 
+/// State of vhost-user network device.
+#[repr(C)]
+#[derive(Clone, Copy, Desc, ByteCode)]
+#[desc_version(compat_version = "0.1.0")]
+pub struct VhostUserNetState {
+    /// Bit mask of features supported by the backend.
+    device_features: u64,
+    /// Bit mask of features negotiated by the backend and the frontend.
+    driver_features: u64,
+    /// Virtio net configurations.
+    config_space: VirtioNetConfig,
+    /// Last avail idx of each queue in vhost-user backend.
+    last_avail_idx: [u16; MAX_VIRTIO_QUEUE],
+    /// Device broken status.
+    broken: bool,
+}
+
 /// Network device structure.
 pub struct Net {
     /// Virtio device base property.
@@ -50,6 +72,14 @@ pub struct Net {
     client: Option<Arc<Mutex<VhostUserClient>>>,
     /// Whether irqfd can be used.
     enable_irqfd: bool,
+    /// Whether the backend supports logging dirty memory.
+    log_supported: bool,
+    /// Last avail idx of each queue, which is synced by `pause` or restored by migration.
+    last_avail_idx: Option<[u16; MAX_VIRTIO_QUEUE]>,
+    /// Whether the vrings are stopped by migration.
+    paused: bool,
+    /// Dirty log of guest memory written by vhost-user backend during migration.
+    dirty_log: Option<Arc<VhostDirtyLog>>,
 }
 
 impl Net {
@@ -69,9 +99,28 @@ impl Net {
             mem_space: mem_space.clone(),
             client: None,
             enable_irqfd: false,
+            log_supported: false,
+            last_avail_idx: None,
+            paused: false,
+            dirty_log: None,
         }
     }
 
+    /// Pass the dirty log to the client, and update it in the running backend.
+    fn update_dirty_log(&self) -> Result<()> {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let mut locked_client = client.lock().unwrap();
+        locked_client.dirty_log = self.dirty_log.clone();
+        if self.device_activated() {
+            locked_client.update_dirty_log()?;
+        }
+
+        Ok(())
+    }
+
     fn delete_event(&mut self) -> Result<()> {
         match &self.client {
             Some(client) => {
@@ -100,6 +149,8 @@ impl Net {
         self.base.broken.store(false, Ordering::SeqCst);
         self.config_space = Default::default();
         self.client = None;
+        self.last_avail_idx = None;
+        self.paused = false;
 
         Ok(())
     }
@@ -139,13 +190,28 @@ impl VirtioDevice for Net {
     }
 
     fn init_config_features(&mut self) -> Result<()> {
-        let client = self.client.as_ref().unwrap();
-        self.base.device_features = client
-            .lock()
-            .unwrap()
+        let mut locked_client = self.client.as_ref().unwrap().lock().unwrap();
+        self.base.device_features = locked_client
             .get_features()
             .with_context(|| "Failed to get features for vhost-user net")?;
 
+        if virtio_has_feature(self.base.device_features, VHOST_USER_F_PROTOCOL_FEATURES) {
+            let protocol_features = locked_client
+                .get_protocol_features()
+                .with_context(|| "Failed to get protocol features for vhost-user net")?;
+            locked_client.protocol_features =
+                protocol_features & 1 << VHOST_USER_PROTOCOL_F_LOG_SHMFD;
+            locked_client
+                .set_protocol_features(locked_client.protocol_features)
+                .with_context(|| "Failed to set protocol features for vhost-user net")?;
+        }
+        self.log_supported = virtio_has_feature(self.base.device_features, VHOST_F_LOG_ALL)
+            && virtio_has_feature(
+                locked_client.protocol_features,
+                VHOST_USER_PROTOCOL_F_LOG_SHMFD as u32,
+            );
+        drop(locked_client);
+
         let features = 1 << VIRTIO_F_VERSION_1
             | 1 << VIRTIO_NET_F_GUEST_CSUM
             | 1 << VIRTIO_NET_F_GUEST_TSO4
@@ -245,6 +311,8 @@ impl VirtioDevice for Net {
             client.set_queue_evts(&queue_evts);
         }
         client.features = driver_features & !(1 << VIRTIO_NET_F_MAC);
+        client.dirty_log = self.dirty_log.clone();
+        client.last_avail_idx = self.last_avail_idx.take().map(|idx| idx.to_vec());
 
         if !self.enable_irqfd {
             listen_guest_notifier(
@@ -282,6 +350,10 @@ impl VirtioDevice for Net {
     }
 
     fn unrealize(&mut self) -> Result<()> {
+        MigrationManager::unregister_device_instance(
+            VhostUserNetState::descriptor(),
+            &self.net_cfg.id,
+        );
         self.delete_event()?;
         self.client = None;
 
@@ -292,3 +364,112 @@ impl VirtioDevice for Net {
         virtio_has_feature(self.base.device_features, VIRTIO_NET_F_CTRL_VQ)
     }
 }
+
+impl StateTransfer for Net {
+    fn get_state_vec(&self) -> migration::Result<Vec<u8>> {
+        // The ring indexes are synced when vhost-user backend is stopped by `pause`.
+        if self.device_activated() && !self.paused {
+            bail!("Vhost-user net {} is still running", self.net_cfg.id);
+        }
+
+        let state = VhostUserNetState {
+            device_features: self.base.device_features,
+            driver_features: self.base.driver_features,
+            config_space: *self.config_space.lock().unwrap(),
+            last_avail_idx: self.last_avail_idx.unwrap_or([0; MAX_VIRTIO_QUEUE]),
+            broken: self.base.broken.load(Ordering::SeqCst),
+        };
+
+        Ok(state.as_bytes().to_vec())
+    }
+
+    fn set_state_mut(&mut self, state: &[u8]) -> migration::Result<()> {
+        let state = VhostUserNetState::from_bytes(state)
+            .with_context(|| migration::error::MigrationError::FromBytesError("VHOST_USER_NET"))?;
+        self.base.device_features = state.device_features;
+        self.base.driver_features = state.driver_features;
+        self.base.broken.store(state.broken, Ordering::SeqCst);
+        *self.config_space.lock().unwrap() = state.config_space;
+        self.last_avail_idx = Some(state.last_avail_idx);
+        Ok(())
+    }
+
+    fn get_device_alias(&self) -> u64 {
+        MigrationManager::get_desc_alias(&VhostUserNetState::descriptor().name).unwrap_or(!0)
+    }
+}
+
+impl MigrationHook for Net {
+    fn pause(&mut self) -> migration::Result<()> {
+        if !self.device_activated() || self.paused {
+            return Ok(());
+        }
+
+        // The backend replies the last avail index after all in-flight descriptors
+        // are completed.
+        let client = self
+            .client
+            .as_ref()
+            .with_context(|| "Failed to get client for vhost-user net")?;
+        let queue_idx = client
+            .lock()
+            .unwrap()
+            .stop_vrings()
+            .with_context(|| "Failed to stop vhost-user net backend")?;
+        let mut last_avail_idx = [0_u16; MAX_VIRTIO_QUEUE];
+        for (index, idx) in queue_idx.iter().enumerate().take(MAX_VIRTIO_QUEUE) {
+            last_avail_idx[index] = *idx;
+        }
+        self.last_avail_idx = Some(last_avail_idx);
+        self.paused = true;
+
+        Ok(())
+    }
+
+    fn restart(&mut self) -> migration::Result<()> {
+        if !self.paused {
+            return Ok(());
+        }
+
+        let last_avail_idx = self.last_avail_idx.take().unwrap_or([0; MAX_VIRTIO_QUEUE]);
+        self.client
+            .as_ref()
+            .with_context(|| "Failed to get client for vhost-user net")?
+            .lock()
+            .unwrap()
+            .restart_vrings(&last_avail_idx)
+            .with_context(|| "Failed to restart vhost-user net backend")?;
+        self.paused = false;
+
+        Ok(())
+    }
+
+    fn start_dirty_log(&mut self) -> migration::Result<()> {
+        if !self.log_supported {
+            bail!(
+                "Vhost-user net {} backend doesn't support logging dirty memory",
+                self.net_cfg.id
+            );
+        }
+
+        self.dirty_log = Some(Arc::new(VhostDirtyLog::new(&self.mem_space)?));
+        self.update_dirty_log()
+            .with_context(|| "Failed to start dirty log of vhost-user net")
+    }
+
+    fn stop_dirty_log(&mut self) -> migration::Result<()> {
+        if self.dirty_log.is_none() {
+            return Ok(());
+        }
+
+        self.dirty_log = None;
+        self.update_dirty_log()
+            .with_context(|| "Failed to stop dirty log of vhost-user net")
+    }
+
+    fn sync_dirty_log(&self, mark: &mut dyn FnMut(u64, u64)) {
+        if let Some(log) = &self.dirty_log {
+            log.sync(mark);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use vmm_sys_util::eventfd::EventFd;
+
+use super::client::VhostUserClient;
+use crate::vhost::VhostOps;
+use crate::ScsiCntlr::{
+    VirtioScsiConfig, SCSI_CTRL_QUEUE_NUM, SCSI_EVENT_QUEUE_NUM, VIRTIO_SCSI_CDB_DEFAULT_SIZE,
+    VIRTIO_SCSI_SENSE_DEFAULT_SIZE,
+};
+use crate::VhostUser::client::{VhostBackendType, VHOST_USER_PROTOCOL_F_MQ};
+use crate::VhostUser::listen_guest_notifier;
+use crate::VhostUser::message::VHOST_USER_F_PROTOCOL_FEATURES;
+use crate::{
+    check_config_space_rw, read_config_default, virtio_has_feature, VirtioBase, VirtioDevice,
+    VirtioInterrupt, VIRTIO_F_RING_EVENT_IDX, VIRTIO_F_RING_INDIRECT_DESC, VIRTIO_F_VERSION_1,
+    VIRTIO_SCSI_F_CHANGE, VIRTIO_SCSI_F_HOTPLUG, VIRTIO_TYPE_SCSI,
+};
+use address_space::AddressSpace;
+use machine_manager::config::{ScsiCntlrConfig, VIRTIO_SCSI_MAX_LUN, VIRTIO_SCSI_MAX_TARGET};
+use machine_manager::event_loop::unregister_event_helper;
+use util::byte_code::ByteCode;
+
+/// Size of `struct virtio_scsi_event` filled in the event queue.
+const VIRTIO_SCSI_EVENT_INFO_SIZE: u32 = 16;
+
+/// Vhost-user-scsi device. All the virtqueues, including the ctrl queue and the event queue, are
+/// handled by the backend, so the hotplug and parameter change events of LUNs are reported to
+/// the guest by the backend directly.
+pub struct Scsi {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// Configuration of the scsi controller.
+    config: ScsiCntlrConfig,
+    /// Config space of the scsi controller.
+    config_space: VirtioScsiConfig,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// Vhost user client.
+    client: Option<Arc<Mutex<VhostUserClient>>>,
+    /// Whether irqfd can be used.
+    pub enable_irqfd: bool,
+    /// Vhost user protocol features.
+    protocol_features: u64,
+}
+
+impl Scsi {
+    pub fn new(config: &ScsiCntlrConfig, mem_space: &Arc<AddressSpace>) -> Self {
+        let queue_num = config.queues as usize + SCSI_CTRL_QUEUE_NUM + SCSI_EVENT_QUEUE_NUM;
+        let queue_size = config.queue_size;
+
+        Scsi {
+            base: VirtioBase::new(VIRTIO_TYPE_SCSI, queue_num, queue_size),
+            config: config.clone(),
+            config_space: Default::default(),
+            mem_space: mem_space.clone(),
+            client: None,
+            enable_irqfd: false,
+            protocol_features: 0_u64,
+        }
+    }
+
+    /// Connect with the backend and register update event.
+    fn init_client(&mut self) -> Result<()> {
+        let socket_path = self
+            .config
+            .socket_path
+            .as_ref()
+            .map(|path| path.to_string())
+            .with_context(|| "vhost-user: socket path is not found")?;
+        let client = VhostUserClient::new(
+            &self.mem_space,
+            &socket_path,
+            self.queue_num() as u64,
+            VhostBackendType::TypeScsi,
+        )
+        .with_context(|| {
+            "Failed to create the client which communicates with the server for vhost-user scsi"
+        })?;
+        let client = Arc::new(Mutex::new(client));
+        VhostUserClient::add_event(&client)?;
+        self.client = Some(client);
+        Ok(())
+    }
+}
+
+impl VirtioDevice for Scsi {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.init_client()?;
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        let locked_client = self.client.as_ref().unwrap().lock().unwrap();
+        let features = locked_client
+            .get_features()
+            .with_context(|| "Failed to get features for vhost-user scsi")?;
+
+        if virtio_has_feature(features, VHOST_USER_F_PROTOCOL_FEATURES) {
+            let protocol_features = locked_client
+                .get_protocol_features()
+                .with_context(|| "Failed to get protocol features for vhost-user scsi")?;
+            self.protocol_features = protocol_features & (1 << VHOST_USER_PROTOCOL_F_MQ);
+            locked_client
+                .set_protocol_features(self.protocol_features)
+                .with_context(|| "Failed to set protocol features for vhost-user scsi")?;
+
+            if virtio_has_feature(protocol_features, VHOST_USER_PROTOCOL_F_MQ as u32) {
+                let max_queue_num = locked_client
+                    .get_max_queue_num()
+                    .with_context(|| "Failed to get queue num for vhost-user scsi")?;
+                if self.queue_num() > max_queue_num as usize {
+                    bail!(
+                        "Exceed the max queue num that backend supported ({} queues)",
+                        max_queue_num
+                    );
+                }
+            } else if self.config.queues > 1 {
+                bail!(
+                    "Backend doesn't support multi queue, protocol features: {:#b}",
+                    protocol_features
+                );
+            }
+        } else {
+            bail!("Bad vhost-user scsi backend feature: {:#b}", features);
+        }
+        drop(locked_client);
+
+        self.config_space.num_queues = self.config.queues;
+        self.config_space.seg_max = self.queue_size_max() as u32 - 2;
+        self.config_space.max_sectors = 0xFFFF_u32;
+        self.config_space.cmd_per_lun = 128;
+        self.config_space.event_info_size = VIRTIO_SCSI_EVENT_INFO_SIZE;
+        self.config_space.sense_size = VIRTIO_SCSI_SENSE_DEFAULT_SIZE as u32;
+        self.config_space.cdb_size = VIRTIO_SCSI_CDB_DEFAULT_SIZE as u32;
+        self.config_space.max_target = VIRTIO_SCSI_MAX_TARGET;
+        self.config_space.max_lun = VIRTIO_SCSI_MAX_LUN as u32;
+
+        self.base.device_features = 1_u64 << VIRTIO_F_VERSION_1
+            | 1_u64 << VIRTIO_F_RING_EVENT_IDX
+            | 1_u64 << VIRTIO_F_RING_INDIRECT_DESC
+            | 1_u64 << VIRTIO_SCSI_F_HOTPLUG
+            | 1_u64 << VIRTIO_SCSI_F_CHANGE;
+        self.base.device_features &= features;
+
+        Ok(())
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        read_config_default(self.config_space.as_bytes(), offset, data)
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        check_config_space_rw(self.config_space.as_bytes(), offset, data)?;
+
+        let mut config_space = self.config_space;
+        let offset = offset as usize;
+        let end = offset + data.len();
+        config_space.as_mut_bytes()[offset..end].copy_from_slice(data);
+        // Guest can only set sense_size and cdb_size, which are handled by the backend and
+        // can not be changed.
+        if config_space.sense_size != self.config_space.sense_size
+            || config_space.cdb_size != self.config_space.cdb_size
+        {
+            bail!("Vhost-user scsi doesn't support changing the sense data and cdb size");
+        }
+
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        _mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let mut client = match &self.client {
+            Some(client) => client.lock().unwrap(),
+            None => return Err(anyhow!("Failed to get client for vhost-user scsi")),
+        };
+        client.features = self.base.driver_features;
+        client.protocol_features = self.protocol_features;
+        client.set_queues(&self.base.queues);
+        client.set_queue_evts(&queue_evts);
+
+        if !self.enable_irqfd {
+            let queue_num = self.base.queues.len();
+            listen_guest_notifier(&mut self.base, &mut client, None, queue_num, interrupt_cb)?;
+        }
+
+        client.activate_vhost_user()?;
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        self.client
+            .as_ref()
+            .with_context(|| "Failed to get client when deactivating device")?
+            .lock()
+            .unwrap()
+            .reset_vhost_user()?;
+        if !self.base.deactivate_evts.is_empty() {
+            unregister_event_helper(None, &mut self.base.deactivate_evts)?;
+        }
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        self.client
+            .as_ref()
+            .with_context(|| "Failed to get client when stopping event")?
+            .lock()
+            .unwrap()
+            .delete_event()
+            .with_context(|| "Failed to delete vhost-user scsi event")?;
+        self.client = None;
+        Ok(())
+    }
+
+    fn set_guest_notifiers(&mut self, queue_evts: &[Arc<EventFd>]) -> Result<()> {
+        self.enable_irqfd = true;
+        match &self.client {
+            Some(client) => client.lock().unwrap().set_call_events(queue_evts),
+            None => return Err(anyhow!("Failed to get client for vhost-user scsi")),
+        };
+
+        Ok(())
+    }
+}
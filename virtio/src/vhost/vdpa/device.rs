@@ -0,0 +1,335 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use log::error;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use super::super::{VhostIoHandler, VhostNotify, VhostOps};
+use super::VdpaBackend;
+use crate::{
+    error::VirtioError, VirtioBase, VirtioDevice, VirtioInterrupt, VirtioInterruptType,
+    CONFIG_STATUS_ACKNOWLEDGE, CONFIG_STATUS_DRIVER, CONFIG_STATUS_DRIVER_OK,
+    CONFIG_STATUS_FEATURES_OK, VIRTIO_F_RING_PACKED,
+};
+use address_space::AddressSpace;
+use machine_manager::config::{VdpaConfig, MAX_VIRTIO_QUEUE};
+use machine_manager::event_loop::{register_event_helper, unregister_event_helper};
+use util::loop_context::{
+    read_fd, EventNotifier, EventNotifierHelper, NotifierCallback, NotifierOperation,
+};
+
+/// Injects the config change interrupt when the vdpa device signals it.
+struct VdpaConfigHandler {
+    config_evt: Arc<EventFd>,
+    interrupt_cb: Arc<VirtioInterrupt>,
+    device_broken: Arc<AtomicBool>,
+}
+
+impl EventNotifierHelper for VdpaConfigHandler {
+    fn internal_notifiers(config_handler: Arc<Mutex<Self>>) -> Vec<EventNotifier> {
+        let handler_clone = config_handler.clone();
+        let handler: Rc<NotifierCallback> = Rc::new(move |_, fd: RawFd| {
+            read_fd(fd);
+            let locked_handler = handler_clone.lock().unwrap();
+            if locked_handler.device_broken.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Err(e) = (locked_handler.interrupt_cb)(&VirtioInterruptType::Config, None, false)
+            {
+                error!(
+                    "Failed to trigger config interrupt for vhost-vdpa device, error is {:?}",
+                    e
+                );
+            }
+            None
+        });
+
+        vec![EventNotifier::new(
+            NotifierOperation::AddShared,
+            config_handler.lock().unwrap().config_evt.as_raw_fd(),
+            None,
+            EventSet::IN,
+            vec![handler],
+        )]
+    }
+}
+
+/// Vhost-vdpa device, whose virtqueues are served by the vdpa device (usually a hardware
+/// offload device) directly. The guest kicks the vdpa device by the ioeventfds, and the vdpa
+/// device notifies the guest by the irqfds.
+pub struct Device {
+    /// Virtio device base property.
+    base: VirtioBase,
+    /// The vhost-vdpa backend.
+    backend: VdpaBackend,
+    /// Size of config space of the vdpa device.
+    config_size: u64,
+    /// System address space.
+    mem_space: Arc<AddressSpace>,
+    /// Save irqfd used for vhost-vdpa.
+    call_events: Vec<Arc<EventFd>>,
+}
+
+impl Device {
+    /// Open the vdpa device and get its queues, which are needed before realizing.
+    ///
+    /// # Arguments
+    ///
+    /// * `cfg` - Configuration of the vhost-vdpa device.
+    /// * `device_type` - Expected virtio device type of the vdpa device.
+    /// * `mem_space` - System address space.
+    pub fn new(cfg: &VdpaConfig, device_type: u32, mem_space: &Arc<AddressSpace>) -> Result<Self> {
+        let backend = VdpaBackend::new(&cfg.vhostdev, cfg.vhostfd)
+            .with_context(|| "Failed to create backend for vhost-vdpa")?;
+        backend
+            .set_owner()
+            .with_context(|| "Failed to set owner for vhost-vdpa")?;
+
+        let device_id = backend.get_device_id()?;
+        if device_id != device_type {
+            bail!(
+                "Virtio device type of {} is {}, expected {}",
+                cfg.vhostdev,
+                device_id,
+                device_type
+            );
+        }
+        let queue_num = backend.get_vqs_count()? as usize;
+        if queue_num == 0 || queue_num > MAX_VIRTIO_QUEUE {
+            bail!(
+                "Invalid queue num {} of {}, should be in [1, {}]",
+                queue_num,
+                cfg.vhostdev,
+                MAX_VIRTIO_QUEUE
+            );
+        }
+        let queue_size = std::cmp::min(cfg.queue_size, backend.get_vring_num()?);
+
+        Ok(Device {
+            base: VirtioBase::new(device_type, queue_num, queue_size),
+            backend,
+            config_size: 0,
+            mem_space: mem_space.clone(),
+            call_events: Vec::new(),
+        })
+    }
+
+    fn check_config_space_rw(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let data_len = data.len() as u64;
+        offset
+            .checked_add(data_len)
+            .filter(|&end| end <= self.config_size)
+            .with_context(|| VirtioError::DevConfigOverflow(offset, data_len, self.config_size))?;
+        Ok(())
+    }
+}
+
+impl VirtioDevice for Device {
+    fn virtio_base(&self) -> &VirtioBase {
+        &self.base
+    }
+
+    fn virtio_base_mut(&mut self) -> &mut VirtioBase {
+        &mut self.base
+    }
+
+    fn realize(&mut self) -> Result<()> {
+        self.backend
+            .negotiate_iotlb_msg_v2()
+            .with_context(|| "Failed to set backend features for vhost-vdpa")?;
+        self.config_size = self.backend.get_config_size()? as u64;
+
+        self.backend.set_status(0)?;
+        self.backend
+            .add_status((CONFIG_STATUS_ACKNOWLEDGE | CONFIG_STATUS_DRIVER) as u8)?;
+        self.backend.register_mem_listener(&self.mem_space)?;
+
+        self.init_config_features()?;
+        Ok(())
+    }
+
+    fn init_config_features(&mut self) -> Result<()> {
+        let features = self
+            .backend
+            .get_features()
+            .with_context(|| "Failed to get features for vhost-vdpa")?;
+        // The vring base is restored by the last avail idx, which is not enough for packed ring.
+        self.base.device_features = features & !(1_u64 << VIRTIO_F_RING_PACKED);
+        Ok(())
+    }
+
+    fn unrealize(&mut self) -> Result<()> {
+        self.backend.set_status(0)?;
+        self.backend.unregister_mem_listener(&self.mem_space)
+    }
+
+    fn read_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        self.check_config_space_rw(offset, data)?;
+        self.backend
+            .get_config(offset, data)
+            .with_context(|| "Failed to get config for vhost-vdpa")
+    }
+
+    fn write_config(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.check_config_space_rw(offset, data)?;
+        self.backend
+            .set_config(offset, data)
+            .with_context(|| "Failed to set config for vhost-vdpa")
+    }
+
+    fn set_guest_notifiers(&mut self, queue_evts: &[Arc<EventFd>]) -> Result<()> {
+        for fd in queue_evts.iter() {
+            self.call_events.push(fd.clone());
+        }
+
+        Ok(())
+    }
+
+    fn activate(
+        &mut self,
+        _mem_space: Arc<AddressSpace>,
+        interrupt_cb: Arc<VirtioInterrupt>,
+        queue_evts: Vec<Arc<EventFd>>,
+    ) -> Result<()> {
+        let backend = &self.backend;
+        backend
+            .set_features(self.base.driver_features)
+            .with_context(|| "Failed to set features for vhost-vdpa")?;
+        backend.add_status(CONFIG_STATUS_FEATURES_OK as u8)?;
+
+        let mut host_notifies = Vec::new();
+        let mut enabled_queues = Vec::new();
+        for (queue_index, queue_mutex) in self.base.queues.iter().enumerate() {
+            let queue = queue_mutex.lock().unwrap();
+            if !queue.vring.is_enabled() {
+                continue;
+            }
+            let actual_size = queue.vring.actual_size();
+            let queue_config = queue.vring.get_queue_config();
+            drop(queue);
+
+            backend
+                .set_vring_num(queue_index, actual_size)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring num for vhost-vdpa, index: {} size: {}",
+                        queue_index, actual_size,
+                    )
+                })?;
+            backend
+                .set_vring_addr(&queue_config, queue_index, 0)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring addr for vhost-vdpa, index: {}",
+                        queue_index,
+                    )
+                })?;
+            backend.set_vring_base(queue_index, 0).with_context(|| {
+                format!(
+                    "Failed to set vring base for vhost-vdpa, index: {}",
+                    queue_index,
+                )
+            })?;
+            backend
+                .set_vring_kick(queue_index, queue_evts[queue_index].clone())
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring kick for vhost-vdpa, index: {}",
+                        queue_index,
+                    )
+                })?;
+
+            let event = if self.call_events.is_empty() {
+                let host_notify = VhostNotify {
+                    notify_evt: Arc::new(
+                        EventFd::new(libc::EFD_NONBLOCK)
+                            .with_context(|| VirtioError::EventFdCreate)?,
+                    ),
+                    queue: queue_mutex.clone(),
+                };
+                let event = host_notify.notify_evt.clone();
+                host_notifies.push(host_notify);
+                event
+            } else {
+                self.call_events[queue_index].clone()
+            };
+            backend
+                .set_vring_call(queue_index, event)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring call for vhost-vdpa, index: {}",
+                        queue_index,
+                    )
+                })?;
+            enabled_queues.push(queue_index);
+        }
+
+        if self.call_events.is_empty() {
+            let handler = VhostIoHandler {
+                interrupt_cb: interrupt_cb.clone(),
+                host_notifies,
+                device_broken: self.base.broken.clone(),
+            };
+            let notifiers = EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(handler)));
+            register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+        }
+
+        let config_evt =
+            Arc::new(EventFd::new(libc::EFD_NONBLOCK).with_context(|| VirtioError::EventFdCreate)?);
+        backend
+            .set_config_call(&config_evt)
+            .with_context(|| "Failed to set config call for vhost-vdpa")?;
+        let config_handler = VdpaConfigHandler {
+            config_evt,
+            interrupt_cb,
+            device_broken: self.base.broken.clone(),
+        };
+        let notifiers =
+            EventNotifierHelper::internal_notifiers(Arc::new(Mutex::new(config_handler)));
+        register_event_helper(notifiers, None, &mut self.base.deactivate_evts)?;
+
+        for queue_index in enabled_queues {
+            backend
+                .set_vring_enable(queue_index, true)
+                .with_context(|| {
+                    format!(
+                        "Failed to set vring enable for vhost-vdpa, index: {}",
+                        queue_index,
+                    )
+                })?;
+        }
+        backend
+            .add_status(CONFIG_STATUS_DRIVER_OK as u8)
+            .with_context(|| "Failed to start vhost-vdpa")?;
+        self.base.broken.store(false, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    fn deactivate(&mut self) -> Result<()> {
+        // Reset the vdpa device to stop the vrings, and get it ready for the next activation.
+        self.backend.set_status(0)?;
+        self.backend
+            .add_status((CONFIG_STATUS_ACKNOWLEDGE | CONFIG_STATUS_DRIVER) as u8)?;
+        unregister_event_helper(None, &mut self.base.deactivate_evts)?;
+        self.call_events.clear();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,665 @@
+// Copyright (c) 2023 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// StratoVirt is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+mod device;
+
+pub use device::Device;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::mem::size_of;
+use std::os::raw::c_ulong;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::warn;
+use vmm_sys_util::eventfd::EventFd;
+use vmm_sys_util::ioctl::{
+    ioctl, ioctl_with_mut_ptr, ioctl_with_mut_ref, ioctl_with_ptr, ioctl_with_ref,
+};
+use vmm_sys_util::{ioctl_ioc_nr, ioctl_ior_nr, ioctl_iow_nr};
+
+use super::kernel::{
+    check_vhost_fd, VhostVringAddr, VhostVringFile, VhostVringState, VHOST_GET_FEATURES,
+    VHOST_GET_VRING_BASE, VHOST_RESET_OWNER, VHOST_SET_FEATURES, VHOST_SET_OWNER,
+    VHOST_SET_VRING_ADDR, VHOST_SET_VRING_BASE, VHOST_SET_VRING_CALL, VHOST_SET_VRING_KICK,
+    VHOST_SET_VRING_NUM,
+};
+use super::VhostOps;
+use crate::{QueueConfig, VirtioError};
+use address_space::{AddressSpace, FlatRange, Listener, ListenerReqType, RegionIoEventFd};
+use util::byte_code::ByteCode;
+
+/// Refer to VHOST_VDPA ioctls in
+/// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost.h.
+const VHOST: u32 = 0xaf;
+ioctl_iow_nr!(VHOST_SET_BACKEND_FEATURES, VHOST, 0x25, u64);
+ioctl_ior_nr!(VHOST_GET_BACKEND_FEATURES, VHOST, 0x26, u64);
+ioctl_ior_nr!(VHOST_VDPA_GET_DEVICE_ID, VHOST, 0x70, u32);
+ioctl_ior_nr!(VHOST_VDPA_GET_STATUS, VHOST, 0x71, u8);
+ioctl_iow_nr!(VHOST_VDPA_SET_STATUS, VHOST, 0x72, u8);
+ioctl_ior_nr!(VHOST_VDPA_GET_CONFIG, VHOST, 0x73, VhostVdpaConfig);
+ioctl_iow_nr!(VHOST_VDPA_SET_CONFIG, VHOST, 0x74, VhostVdpaConfig);
+ioctl_iow_nr!(VHOST_VDPA_SET_VRING_ENABLE, VHOST, 0x75, VhostVringState);
+ioctl_ior_nr!(VHOST_VDPA_GET_VRING_NUM, VHOST, 0x76, u16);
+ioctl_iow_nr!(VHOST_VDPA_SET_CONFIG_CALL, VHOST, 0x77, i32);
+ioctl_ior_nr!(VHOST_VDPA_GET_IOVA_RANGE, VHOST, 0x78, VhostVdpaIovaRange);
+ioctl_ior_nr!(VHOST_VDPA_GET_CONFIG_SIZE, VHOST, 0x79, u32);
+ioctl_ior_nr!(VHOST_VDPA_GET_VQS_COUNT, VHOST, 0x80, u32);
+
+/// The IOTLB messages are in the format of `vhost_msg_v2`.
+const VHOST_BACKEND_F_IOTLB_MSG_V2: u64 = 0x1;
+/// Type of `vhost_msg_v2`.
+const VHOST_IOTLB_MSG_V2: u32 = 0x2;
+/// Types of `vhost_iotlb_msg`.
+const VHOST_IOTLB_UPDATE: u8 = 2;
+const VHOST_IOTLB_INVALIDATE: u8 = 3;
+/// Access permission of the IOVA mapping.
+const VHOST_ACCESS_RW: u8 = 0x3;
+
+/// Refer to vhost_vdpa_config in
+/// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost_types.h.
+/// It's followed by the config data of `len` bytes.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct VhostVdpaConfig {
+    off: u32,
+    len: u32,
+}
+
+impl ByteCode for VhostVdpaConfig {}
+
+/// Refer to vhost_vdpa_iova_range in
+/// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost_types.h.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct VhostVdpaIovaRange {
+    /// First address that can be mapped.
+    first: u64,
+    /// Last address that can be mapped.
+    last: u64,
+}
+
+/// Refer to vhost_iotlb_msg in
+/// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost_types.h.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct VhostIotlbMsg {
+    iova: u64,
+    size: u64,
+    uaddr: u64,
+    perm: u8,
+    msg_type: u8,
+    padding: [u8; 6],
+}
+
+/// Refer to vhost_msg_v2 in
+/// https://github.com/torvalds/linux/blob/master/include/uapi/linux/vhost_types.h.
+/// The union of `vhost_iotlb_msg` is padded to 64 bytes.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct VhostMsgV2 {
+    msg_type: u32,
+    asid: u32,
+    iotlb: VhostIotlbMsg,
+    padding: [u8; 32],
+}
+
+impl ByteCode for VhostMsgV2 {}
+
+/// Maps the guest RAM into the IOVA space of the vdpa device, the IOVA is the GPA.
+struct VdpaMemInfo {
+    fd: Arc<File>,
+    iova_range: VhostVdpaIovaRange,
+    enabled: bool,
+}
+
+impl VdpaMemInfo {
+    fn send_iotlb_msg(&self, iotlb: VhostIotlbMsg) -> Result<()> {
+        let msg = VhostMsgV2 {
+            msg_type: VHOST_IOTLB_MSG_V2,
+            iotlb,
+            ..Default::default()
+        };
+        self.fd
+            .as_ref()
+            .write_all(msg.as_bytes())
+            .with_context(|| "Failed to write iotlb msg to vhost-vdpa")
+    }
+
+    fn dma_map(&self, iova: u64, size: u64, uaddr: u64) -> Result<()> {
+        if iova < self.iova_range.first || iova + size - 1 > self.iova_range.last {
+            bail!(
+                "IOVA 0x{:x} size 0x{:x} is out of the range [0x{:x}, 0x{:x}] of vdpa device",
+                iova,
+                size,
+                self.iova_range.first,
+                self.iova_range.last
+            );
+        }
+        self.send_iotlb_msg(VhostIotlbMsg {
+            iova,
+            size,
+            uaddr,
+            perm: VHOST_ACCESS_RW,
+            msg_type: VHOST_IOTLB_UPDATE,
+            ..Default::default()
+        })
+    }
+
+    fn dma_unmap(&self, iova: u64, size: u64) -> Result<()> {
+        self.send_iotlb_msg(VhostIotlbMsg {
+            iova,
+            size,
+            msg_type: VHOST_IOTLB_INVALIDATE,
+            ..Default::default()
+        })
+    }
+
+    fn add_listener_region(&self, fr: &FlatRange) -> Result<()> {
+        if fr.owner.region_type() != address_space::RegionType::Ram {
+            return Ok(());
+        }
+
+        let guest_phys_addr = fr.addr_range.base.raw_value();
+        let memory_size = fr.addr_range.size;
+        let hva = match fr.owner.get_host_address() {
+            Some(addr) => addr,
+            None => bail!("Failed to get host address"),
+        };
+        let userspace_addr = hva + fr.offset_in_region;
+        self.dma_map(guest_phys_addr, memory_size, userspace_addr)
+            .with_context(|| {
+                format!(
+                    "Failed to do dma map: gpa 0x{:x}, size 0x{:x}, hva 0x{:x}",
+                    guest_phys_addr, memory_size, userspace_addr
+                )
+            })
+    }
+
+    fn del_listener_region(&self, fr: &FlatRange) -> Result<()> {
+        if fr.owner.region_type() != address_space::RegionType::Ram {
+            return Ok(());
+        }
+
+        let guest_phys_addr = fr.addr_range.base.raw_value();
+        let size = fr.addr_range.size;
+        self.dma_unmap(guest_phys_addr, size).with_context(|| {
+            format!(
+                "Failed to do dma unmap: gpa 0x{:x}, size 0x{:x}",
+                guest_phys_addr, size
+            )
+        })
+    }
+}
+
+impl Listener for VdpaMemInfo {
+    fn priority(&self) -> i32 {
+        0
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        self.enabled = false;
+    }
+
+    fn handle_request(
+        &self,
+        range: Option<&FlatRange>,
+        _evtfd: Option<&RegionIoEventFd>,
+        req_type: ListenerReqType,
+    ) -> Result<()> {
+        match req_type {
+            ListenerReqType::AddRegion => {
+                self.add_listener_region(range.unwrap())?;
+            }
+            ListenerReqType::DeleteRegion => {
+                self.del_listener_region(range.unwrap())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Vhost-vdpa backend, which is opened from `/dev/vhost-vdpa-N`. The virtqueues are served
+/// by the vdpa device directly, which accesses the guest memory by the IOVA mappings.
+pub struct VdpaBackend {
+    fd: Arc<File>,
+    mem_info: Arc<Mutex<VdpaMemInfo>>,
+}
+
+impl VdpaBackend {
+    pub fn new(path: &str, rawfd: Option<RawFd>) -> Result<VdpaBackend> {
+        let fd = match rawfd {
+            Some(rawfd) => {
+                // SAFETY: the fd is passed by management and owned by the backend.
+                let file = unsafe { File::from_raw_fd(rawfd) };
+                check_vhost_fd(&file, path)
+                    .with_context(|| format!("Invalid vhost-vdpa fd {} for {}", rawfd, path))?;
+                file
+            }
+            None => OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_CLOEXEC)
+                .open(path)
+                .with_context(|| format!("Failed to open {} for vhost-vdpa backend.", path))?,
+        };
+        let fd = Arc::new(fd);
+
+        let mut iova_range = VhostVdpaIovaRange::default();
+        // SAFETY: fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe {
+            ioctl_with_mut_ref(fd.as_ref(), VHOST_VDPA_GET_IOVA_RANGE(), &mut iova_range)
+        };
+        if ret < 0 {
+            warn!("Failed to get iova range of {}, use the whole range", path);
+            iova_range.last = u64::MAX;
+        }
+        let mem_info = Arc::new(Mutex::new(VdpaMemInfo {
+            fd: fd.clone(),
+            iova_range,
+            enabled: false,
+        }));
+
+        Ok(VdpaBackend { fd, mem_info })
+    }
+
+    /// Map the guest memory to the vdpa device, and keep the mappings updated.
+    pub fn register_mem_listener(&self, mem_space: &Arc<AddressSpace>) -> Result<()> {
+        mem_space
+            .register_listener(self.mem_info.clone())
+            .with_context(|| "Failed to map guest memory for vhost-vdpa")
+    }
+
+    /// Unmap the guest memory from the vdpa device.
+    pub fn unregister_mem_listener(&self, mem_space: &Arc<AddressSpace>) -> Result<()> {
+        mem_space
+            .unregister_listener(self.mem_info.clone())
+            .with_context(|| "Failed to unmap guest memory for vhost-vdpa")
+    }
+
+    /// Get the features of vhost backend, such as the format of IOTLB messages.
+    pub fn get_backend_features(&self) -> Result<u64> {
+        let mut features: u64 = 0;
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, VHOST_GET_BACKEND_FEATURES(), &mut features) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_GET_BACKEND_FEATURES".to_string()
+            )));
+        }
+        Ok(features)
+    }
+
+    pub fn set_backend_features(&self, features: u64) -> Result<()> {
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_BACKEND_FEATURES(), &features) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_BACKEND_FEATURES".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Negotiate `vhost_msg_v2` format of IOTLB messages, which is required to do DMA map.
+    pub fn negotiate_iotlb_msg_v2(&self) -> Result<()> {
+        let features = self.get_backend_features()?;
+        if features & VHOST_BACKEND_F_IOTLB_MSG_V2 == 0 {
+            bail!(
+                "Vhost-vdpa backend doesn't support iotlb msg v2, backend features: {:#x}",
+                features
+            );
+        }
+        self.set_backend_features(VHOST_BACKEND_F_IOTLB_MSG_V2)
+    }
+
+    /// Get the virtio device type of the vdpa device.
+    pub fn get_device_id(&self) -> Result<u32> {
+        self.get_u32(VHOST_VDPA_GET_DEVICE_ID(), "VHOST_VDPA_GET_DEVICE_ID")
+    }
+
+    /// Get the number of virtqueues supported by the vdpa device.
+    pub fn get_vqs_count(&self) -> Result<u32> {
+        self.get_u32(VHOST_VDPA_GET_VQS_COUNT(), "VHOST_VDPA_GET_VQS_COUNT")
+    }
+
+    /// Get the size of config space of the vdpa device.
+    pub fn get_config_size(&self) -> Result<u32> {
+        self.get_u32(VHOST_VDPA_GET_CONFIG_SIZE(), "VHOST_VDPA_GET_CONFIG_SIZE")
+    }
+
+    fn get_u32(&self, req: c_ulong, name: &str) -> Result<u32> {
+        let mut value: u32 = 0;
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, req, &mut value) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(name.to_string())));
+        }
+        Ok(value)
+    }
+
+    /// Get the max size of virtqueues supported by the vdpa device.
+    pub fn get_vring_num(&self) -> Result<u16> {
+        let mut num: u16 = 0;
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, VHOST_VDPA_GET_VRING_NUM(), &mut num) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_GET_VRING_NUM".to_string()
+            )));
+        }
+        Ok(num)
+    }
+
+    pub fn get_status(&self) -> Result<u8> {
+        let mut status: u8 = 0;
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, VHOST_VDPA_GET_STATUS(), &mut status) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_GET_STATUS".to_string()
+            )));
+        }
+        Ok(status)
+    }
+
+    /// Set the virtio device status of the vdpa device, 0 resets the device.
+    pub fn set_status(&self, status: u8) -> Result<()> {
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_VDPA_SET_STATUS(), &status) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_SET_STATUS".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Add bits to the virtio device status, and check that the vdpa device accepts them.
+    pub fn add_status(&self, status: u8) -> Result<()> {
+        let new_status = self.get_status()? | status;
+        self.set_status(new_status)?;
+        if self.get_status()? & status != status {
+            bail!("Vdpa device doesn't accept status {:#x}", status);
+        }
+        Ok(())
+    }
+
+    /// Read the config space of the vdpa device.
+    pub fn get_config(&self, offset: u64, data: &mut [u8]) -> Result<()> {
+        let hdr_len = size_of::<VhostVdpaConfig>();
+        let mut buf = vec![0_u8; hdr_len + data.len()];
+        buf[..hdr_len].copy_from_slice(
+            VhostVdpaConfig {
+                off: offset as u32,
+                len: data.len() as u32,
+            }
+            .as_bytes(),
+        );
+        // SAFETY: buf is large enough for the header and config data, and we check the return.
+        let ret = unsafe { ioctl_with_mut_ptr(self, VHOST_VDPA_GET_CONFIG(), buf.as_mut_ptr()) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_GET_CONFIG".to_string()
+            )));
+        }
+        data.copy_from_slice(&buf[hdr_len..]);
+        Ok(())
+    }
+
+    /// Write the config space of the vdpa device.
+    pub fn set_config(&self, offset: u64, data: &[u8]) -> Result<()> {
+        let hdr_len = size_of::<VhostVdpaConfig>();
+        let mut buf = Vec::with_capacity(hdr_len + data.len());
+        buf.extend_from_slice(
+            VhostVdpaConfig {
+                off: offset as u32,
+                len: data.len() as u32,
+            }
+            .as_bytes(),
+        );
+        buf.extend_from_slice(data);
+        // SAFETY: buf contains the header and config data, and we check the return.
+        let ret = unsafe { ioctl_with_ptr(self, VHOST_VDPA_SET_CONFIG(), buf.as_ptr()) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_SET_CONFIG".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Set eventfd to signal when the config space of the vdpa device is changed.
+    pub fn set_config_call(&self, fd: &EventFd) -> Result<()> {
+        let fd = fd.as_raw_fd();
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_VDPA_SET_CONFIG_CALL(), &fd) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_SET_CONFIG_CALL".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for VdpaBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl VhostOps for VdpaBackend {
+    fn set_owner(&self) -> Result<()> {
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl(self, VHOST_SET_OWNER()) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_OWNER".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn reset_owner(&self) -> Result<()> {
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl(self, VHOST_RESET_OWNER()) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_RESET_OWNER".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn get_features(&self) -> Result<u64> {
+        let mut avail_features: u64 = 0;
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, VHOST_GET_FEATURES(), &mut avail_features) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_GET_FEATURES".to_string()
+            )));
+        }
+        Ok(avail_features)
+    }
+
+    fn set_features(&self, features: u64) -> Result<()> {
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_FEATURES(), &features) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_FEATURES".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_mem_table(&self) -> Result<()> {
+        // The guest memory is mapped by IOTLB messages of the memory listener.
+        Ok(())
+    }
+
+    fn set_vring_num(&self, queue_idx: usize, num: u16) -> Result<()> {
+        let vring_state = VhostVringState {
+            index: queue_idx as u32,
+            num: u32::from(num),
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_NUM(), &vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_NUM".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_vring_addr(&self, queue_config: &QueueConfig, index: usize, flags: u32) -> Result<()> {
+        // The vdpa device accesses the vring by IOVA, which is the GPA.
+        let vring_addr = VhostVringAddr {
+            index: index as u32,
+            flags,
+            desc_user_addr: queue_config.desc_table.raw_value(),
+            used_user_addr: queue_config.used_ring.raw_value(),
+            avail_user_addr: queue_config.avail_ring.raw_value(),
+            log_guest_addr: 0_u64,
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_ADDR(), &vring_addr) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_ADDR".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_vring_base(&self, queue_idx: usize, num: u16) -> Result<()> {
+        let vring_state = VhostVringState {
+            index: queue_idx as u32,
+            num: u32::from(num),
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_BASE(), &vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_BASE".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn get_vring_base(&self, queue_idx: usize) -> Result<u16> {
+        let mut vring_state = VhostVringState {
+            index: queue_idx as u32,
+            num: 0,
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_mut_ref(self, VHOST_GET_VRING_BASE(), &mut vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_GET_VRING_BASE".to_string()
+            )));
+        }
+        Ok(vring_state.num as u16)
+    }
+
+    fn set_vring_call(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<()> {
+        let vring_file = VhostVringFile {
+            index: queue_idx as u32,
+            fd: fd.as_raw_fd(),
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_CALL(), &vring_file) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_CALL".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_vring_kick(&self, queue_idx: usize, fd: Arc<EventFd>) -> Result<()> {
+        let vring_file = VhostVringFile {
+            index: queue_idx as u32,
+            fd: fd.as_raw_fd(),
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_SET_VRING_KICK(), &vring_file) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_SET_VRING_KICK".to_string()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_vring_enable(&self, queue_idx: usize, status: bool) -> Result<()> {
+        let vring_state = VhostVringState {
+            index: queue_idx as u32,
+            num: status as u32,
+        };
+        // SAFETY: self.fd is the vhost-vdpa device and we check the return.
+        let ret = unsafe { ioctl_with_ref(self, VHOST_VDPA_SET_VRING_ENABLE(), &vring_state) };
+        if ret < 0 {
+            return Err(anyhow!(VirtioError::VhostIoctl(
+                "VHOST_VDPA_SET_VRING_ENABLE".to_string()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vhost_msg_v2_layout() {
+        // Same as the layout of `struct vhost_msg_v2` in the kernel.
+        assert_eq!(size_of::<VhostIotlbMsg>(), 32);
+        assert_eq!(size_of::<VhostMsgV2>(), 72);
+        assert_eq!(size_of::<VhostVdpaConfig>(), 8);
+
+        let msg = VhostMsgV2 {
+            msg_type: VHOST_IOTLB_MSG_V2,
+            iotlb: VhostIotlbMsg {
+                iova: 0x1000,
+                size: 0x2000,
+                uaddr: 0x3000,
+                perm: VHOST_ACCESS_RW,
+                msg_type: VHOST_IOTLB_UPDATE,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let bytes = msg.as_bytes();
+        assert_eq!(bytes[0..4], VHOST_IOTLB_MSG_V2.to_ne_bytes());
+        assert_eq!(bytes[8..16], 0x1000_u64.to_ne_bytes());
+        assert_eq!(bytes[24..32], 0x3000_u64.to_ne_bytes());
+        assert_eq!(bytes[32], VHOST_ACCESS_RW);
+        assert_eq!(bytes[33], VHOST_IOTLB_UPDATE);
+    }
+}